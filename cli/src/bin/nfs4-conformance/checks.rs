@@ -0,0 +1,725 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! The checks, grouped by what they are about. Each returns why it failed, if it did.
+
+use crate::raw::{RawSession, Result};
+use nfs4::{
+    ArgOp, CloseArgs, Cookie, CreateArgs, CreateType, ExistingLockOwner, FileHandle, LockArgs,
+    LockStatusError, LockTArgs, LockType, LockUArgs, Locker, LookUpArgs, OpenArgs, OpenClaim,
+    OpenFlag, OpenToLockOwner, PutFhArgs, ReadArgs, ReadDirArgs, ReadDirRes, RemoveArgs,
+    SequenceId, SessionId, ShareAccess, ShareDeny, SlotId, StableHow, StateId, StateOwner,
+    StatusError, StatusResult, TestStateIdArgs, Verifier, WriteArgs,
+};
+use nfs4_client::{Client, CompoundBuilder, CompoundReply, NfsUrl, OpIndex, OpenOptions};
+use std::collections::BTreeSet;
+use std::net::TcpStream;
+
+pub struct Check {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub run: fn(&mut Context) -> Result<()>,
+}
+
+macro_rules! checks {
+    ($($name:literal $run:ident: $description:literal,)*) => {
+        pub const CHECKS: &[Check] = &[$(Check {
+            name: $name,
+            description: $description,
+            run: $run,
+        }),*];
+    };
+}
+
+checks! {
+    "stateid-bad" bad_stateid: "READ with a state ID never given out is BAD_STATEID",
+    "stateid-future-seqid" future_seqid: "READ with a seqid ahead of the open's is BAD_STATEID",
+    "stateid-old-seqid" old_seqid: "READ with what an open was before an upgrade is OLD_STATEID",
+    "stateid-current-seqid" current_seqid: "a seqid of 0 means whatever the open's is now",
+    "stateid-anonymous" anonymous_stateid: "READ with the anonymous state ID works",
+    "stateid-closed" closed_stateid: "an open's state ID is BAD_STATEID once it is closed",
+    "stateid-open-mode" open_mode: "WRITE with an open for reading only is OPENMODE",
+    "stateid-test" test_stateid: "TEST_STATEID tells good state IDs from bad ones",
+    "session-misordered" misordered: "skipping a sequence ID of a slot is SEQ_MISORDERED",
+    "session-bad-slot" bad_slot: "a slot above the highest one is BADSLOT",
+    "session-replay" replay: "sending a request again gets the same reply, or RETRY_UNCACHED_REP",
+    "session-sequence-pos" sequence_pos: "SEQUENCE anywhere but first is SEQUENCE_POS",
+    "session-missing-sequence" missing_sequence: "a COMPOUND without SEQUENCE is OP_NOT_IN_SESSION",
+    "session-bad-session" bad_session: "SEQUENCE for a session which doesn't exist is BADSESSION",
+    "session-minor-version" minor_version: "an unknown minor version is MINOR_VERS_MISMATCH",
+    "lock-conflict" lock_conflict: "a write lock over another owner's is DENIED, saying by whom",
+    "lock-shared" lock_shared: "read locks of different owners overlap",
+    "lock-test" lock_test: "LOCKT finds another owner's lock but not our own",
+    "lock-unlock" lock_unlock: "a range can be locked by someone else after LOCKU",
+    "lock-zero-length" lock_zero_length: "a lock of length 0 is INVAL",
+    "share-deny" share_deny: "opening to write what another client denies writing is SHARE_DENIED",
+    "readdir-empty" readdir_empty: "an empty directory has no entries, not even . and ..",
+    "readdir-reserved-cookie" readdir_reserved_cookie: "the cookies 1 and 2 are BAD_COOKIE",
+    "readdir-too-small" readdir_too_small: "a READDIR reply too small for an entry is TOOSMALL",
+    "readdir-not-dir" readdir_not_dir: "READDIR of a file is NOTDIR",
+    "readdir-paging" readdir_paging: "reading a directory a page at a time finds every entry once",
+    "name-dots" name_dots: "LOOKUP of . or .. is BADNAME or NOENT",
+    "name-empty" name_empty: "LOOKUP of an empty name is INVAL",
+    "name-not-dir" name_not_dir: "LOOKUP in a file is NOTDIR",
+    "name-not-empty" name_not_empty: "REMOVE of a directory with something in it is NOTEMPTY",
+    "name-exists" name_exists: "CREATE of a name already taken is EXIST",
+}
+
+/// What the checks share, mostly the scratch directory they make their files in.
+pub struct Context {
+    client: Client<TcpStream>,
+    url: NfsUrl,
+    dir: FileHandle,
+    counter: u64,
+}
+
+impl Context {
+    pub fn new(client: Client<TcpStream>, url: NfsUrl, dir: FileHandle) -> Self {
+        Self {
+            client,
+            url,
+            dir,
+            counter: 0,
+        }
+    }
+
+    pub fn into_client(self) -> Client<TcpStream> {
+        self.client
+    }
+
+    fn name(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{prefix}-{}", self.counter)
+    }
+
+    /// An owner for opens or locks, different from all the others.
+    fn owner(&mut self) -> StateOwner {
+        StateOwner {
+            client_id: self.client.client_id(),
+            opaque: self.name("owner").into_bytes(),
+        }
+    }
+
+    fn send(&mut self, builder: CompoundBuilder) -> Result<CompoundReply> {
+        self.client
+            .send_compound(builder)
+            .map_err(|e| format!("sending COMPOUND: {e:?}"))
+    }
+
+    /// Sends a single operation on the given file handle.
+    fn send_on<Op: nfs4_client::Operation>(
+        &mut self,
+        handle: FileHandle,
+        op: Op,
+    ) -> Result<(CompoundReply, OpIndex<Op>)> {
+        let mut builder = CompoundBuilder::new();
+        builder.push(PutFhArgs { object: handle });
+        let index = builder.push(op);
+        Ok((self.send(builder)?, index))
+    }
+
+    /// Creates a file with the given contents in the scratch directory.
+    fn file(&mut self, contents: &[u8]) -> Result<(String, FileHandle)> {
+        let name = self.name("file");
+        let file = self
+            .client
+            .create_file(self.dir.clone(), &name)
+            .map_err(|e| format!("creating {name}: {e:?}"))?;
+        let handle = file.handle.clone();
+        self.client
+            .write_at(handle.clone(), 0, contents)
+            .map_err(|e| format!("writing {name}: {e:?}"))?;
+        self.client
+            .close(file)
+            .map_err(|e| format!("closing {name}: {e:?}"))?;
+        Ok((name, handle))
+    }
+
+    fn directory(&mut self) -> Result<(String, FileHandle)> {
+        let name = self.name("dir");
+        let handle = self
+            .client
+            .create_directory(self.dir.clone(), &name, Default::default())
+            .map_err(|e| format!("creating {name}: {e:?}"))?;
+        Ok((name, handle))
+    }
+
+    /// Opens the given file of the scratch directory as the given owner, bypassing the opens
+    /// `Client` keeps track of.
+    fn open(&mut self, name: &str, owner: &StateOwner, access: ShareAccess) -> Result<StateId> {
+        let (reply, open) = self.send_on(
+            self.dir.clone(),
+            OpenArgs {
+                sequence_id: SequenceId(0),
+                share_access: access,
+                share_deny: ShareDeny::NONE,
+                owner: owner.clone(),
+                open_how: OpenFlag::OpenNoCreate,
+                claim: OpenClaim::Null { file: name.into() },
+            },
+        )?;
+        Ok(ok(reply.get(open), "OPEN")?.state_id)
+    }
+
+    fn read(&mut self, handle: FileHandle, state_id: StateId) -> Result<CompoundReply> {
+        let (reply, _) = self.send_on(
+            handle,
+            ReadArgs {
+                state_id,
+                offset: 0,
+                count: 16,
+            },
+        )?;
+        Ok(reply)
+    }
+
+    /// A file opened for reading, by an owner of its own.
+    fn opened_file(&mut self) -> Result<(FileHandle, StateId)> {
+        let (name, handle) = self.file(b"some data")?;
+        let owner = self.owner();
+        let state_id = self.open(&name, &owner, ShareAccess::READ)?;
+        Ok((handle, state_id))
+    }
+
+    fn lock(&mut self, handle: FileHandle, args: LockArgs) -> Result<CompoundReply> {
+        Ok(self.send_on(handle, args)?.0)
+    }
+}
+
+fn status_name(status: Option<StatusError>) -> String {
+    match status {
+        None => "OK".into(),
+        Some(e) => format!("{e:?}"),
+    }
+}
+
+/// Checks the COMPOUND ended with one of the given statuses, `None` meaning it succeeded.
+fn expect(reply: &CompoundReply, expected: &[Option<StatusError>]) -> Result<()> {
+    if expected.contains(&reply.status()) {
+        return Ok(());
+    }
+    let expected: Vec<_> = expected.iter().map(|s| status_name(*s)).collect();
+    Err(format!(
+        "expected {}, got {}",
+        expected.join(" or "),
+        status_name(reply.status())
+    ))
+}
+
+fn ok<T>(result: Option<nfs4_client::Result<T>>, what: &str) -> Result<T> {
+    match result {
+        Some(Ok(res)) => Ok(res),
+        Some(Err(e)) => Err(format!("{what} failed: {e:?}")),
+        None => Err(format!("{what} wasn't reached")),
+    }
+}
+
+/// Asking for a new lock owner, which holds nothing yet.
+fn new_lock(
+    open_state_id: StateId,
+    lock_owner: &StateOwner,
+    lock_type: LockType,
+    offset: u64,
+    length: u64,
+) -> LockArgs {
+    LockArgs {
+        lock_type,
+        reclaim: false,
+        offset,
+        length,
+        locker: Locker::NewLockOwner(OpenToLockOwner {
+            open_sequence_id: SequenceId(0),
+            open_state_id,
+            lock_sequence_id: SequenceId(0),
+            lock_owner: lock_owner.clone(),
+        }),
+    }
+}
+
+fn bad_stateid(context: &mut Context) -> Result<()> {
+    let (handle, _) = context.opened_file()?;
+    let made_up = StateId {
+        sequence_id: 1,
+        other: [0xab; 12],
+    };
+    expect(
+        &context.read(handle, made_up)?,
+        &[Some(StatusError::BadStateId)],
+    )
+}
+
+fn future_seqid(context: &mut Context) -> Result<()> {
+    let (handle, mut state_id) = context.opened_file()?;
+    state_id.sequence_id += 1;
+    expect(
+        &context.read(handle, state_id)?,
+        &[Some(StatusError::BadStateId)],
+    )
+}
+
+/// An open for reading, and its state ID from before it was upgraded to reading and writing.
+fn upgraded_open(context: &mut Context) -> Result<(FileHandle, StateId)> {
+    let (name, handle) = context.file(b"some data")?;
+    let owner = context.owner();
+    let old = context.open(&name, &owner, ShareAccess::READ)?;
+    let new = context.open(&name, &owner, ShareAccess::BOTH)?;
+    if new.other != old.other || new.sequence_id <= old.sequence_id {
+        return Err(format!("upgrading the open gave {new:?}, after {old:?}"));
+    }
+    Ok((handle, old))
+}
+
+fn old_seqid(context: &mut Context) -> Result<()> {
+    let (handle, old) = upgraded_open(context)?;
+    expect(
+        &context.read(handle, old)?,
+        &[Some(StatusError::OldStateId)],
+    )
+}
+
+fn current_seqid(context: &mut Context) -> Result<()> {
+    let (handle, mut old) = upgraded_open(context)?;
+    old.sequence_id = 0;
+    expect(&context.read(handle, old)?, &[None])
+}
+
+fn anonymous_stateid(context: &mut Context) -> Result<()> {
+    let (_, handle) = context.file(b"some data")?;
+    expect(&context.read(handle, StateId::anonymous())?, &[None])
+}
+
+fn closed_stateid(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let close = CloseArgs {
+        sequence_id: SequenceId(0),
+        open_stateid: state_id,
+    };
+    let (reply, index) = context.send_on(handle.clone(), close.clone())?;
+    ok(reply.get(index), "CLOSE")?;
+
+    expect(
+        &context.read(handle.clone(), state_id)?,
+        &[Some(StatusError::BadStateId)],
+    )?;
+    let (reply, _) = context.send_on(handle, close)?;
+    expect(&reply, &[Some(StatusError::BadStateId)]).map_err(|e| format!("closing again: {e}"))
+}
+
+fn open_mode(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let (reply, _) = context.send_on(
+        handle,
+        WriteArgs {
+            state_id,
+            offset: 0,
+            stable: StableHow::FileSync,
+            data: b"more data".to_vec().into(),
+        },
+    )?;
+    expect(&reply, &[Some(StatusError::OpenMode)])
+}
+
+fn test_stateid(context: &mut Context) -> Result<()> {
+    let (_, state_id) = context.opened_file()?;
+    let made_up = StateId {
+        sequence_id: 1,
+        other: [0xab; 12],
+    };
+    let mut builder = CompoundBuilder::new();
+    let test = builder.push(TestStateIdArgs {
+        state_ids: vec![state_id, made_up],
+    });
+    let reply = context.send(builder)?;
+    let codes = ok(reply.get(test), "TEST_STATEID")?.status_codes;
+    let expected = [
+        StatusResult::Ok(()),
+        StatusResult::Err(StatusError::BadStateId),
+    ];
+    if codes != expected {
+        return Err(format!("expected {expected:?}, got {codes:?}"));
+    }
+    Ok(())
+}
+
+fn raw_session(context: &Context) -> Result<RawSession> {
+    RawSession::connect(&context.url).map_err(|e| format!("creating a session: {e}"))
+}
+
+fn misordered(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let mut skipped = session.next_sequence_id();
+    skipped.incr();
+    let sequence = session.sequence_in(SlotId(0), skipped);
+    expect_raw(
+        session.compound(vec![sequence])?,
+        StatusError::SeqMisordered,
+    )
+}
+
+fn bad_slot(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let slot_id = SlotId(session.highest_slot_id.0 + 1);
+    let sequence = session.sequence_in(slot_id, SequenceId(1));
+    expect_raw(session.compound(vec![sequence])?, StatusError::BadSlot)
+}
+
+fn replay(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let sequence = session.sequence();
+    let first = session.compound(vec![sequence.clone(), ArgOp::PutRootFh])?;
+    if first.status != StatusResult::Ok(()) {
+        return Err(format!("the first request failed: {:?}", first.status));
+    }
+    let again = session.compound(vec![sequence, ArgOp::PutRootFh])?;
+    match again.status {
+        StatusResult::Err(StatusError::RetryUncachedRep) => Ok(()),
+        _ if again == first => Ok(()),
+        _ => Err(format!("expected {first:?}, got {again:?}")),
+    }
+}
+
+fn sequence_pos(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let sequence = session.sequence();
+    let second = session.sequence_in(SlotId(0), session.next_sequence_id());
+    expect_raw(
+        session.compound(vec![sequence, second])?,
+        StatusError::SequencePos,
+    )
+}
+
+fn missing_sequence(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    expect_raw(
+        session.compound(vec![ArgOp::PutRootFh])?,
+        StatusError::OpNotInSession,
+    )
+}
+
+fn bad_session(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let mut sequence = session.sequence();
+    if let ArgOp::Sequence(args) = &mut sequence {
+        args.session_id = SessionId([0xff; 16]);
+    }
+    expect_raw(session.compound(vec![sequence])?, StatusError::BadSession)
+}
+
+fn minor_version(context: &mut Context) -> Result<()> {
+    let mut session = raw_session(context)?;
+    let sequence = session.sequence();
+    let res = session.compound_with_minor_version(99, vec![sequence])?;
+    if res.status != StatusResult::Err(StatusError::MinorVersMismatch) {
+        return Err(format!("expected MinorVersMismatch, got {:?}", res.status));
+    }
+    if !res.res_array.is_empty() {
+        return Err(format!("expected no results, got {:?}", res.res_array));
+    }
+    Ok(())
+}
+
+fn expect_raw(res: nfs4::CompoundRes, expected: StatusError) -> Result<()> {
+    if res.status != StatusResult::Err(expected) {
+        return Err(format!("expected {expected:?}, got {:?}", res.status));
+    }
+    Ok(())
+}
+
+fn lock_conflict(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let (first, second) = (context.owner(), context.owner());
+    let reply = context.lock(
+        handle.clone(),
+        new_lock(state_id, &first, LockType::Write, 0, 100),
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("the first lock: {e}"))?;
+
+    let (reply, lock) =
+        context.send_on(handle, new_lock(state_id, &second, LockType::Write, 50, 10))?;
+    match reply.get(lock) {
+        Some(Err(nfs4_client::Error::Lock(LockStatusError {
+            error: StatusError::Denied,
+            denied: Some(denied),
+        }))) => {
+            if denied.owner != first || (denied.offset, denied.length) != (0, 100) {
+                return Err(format!("denied by the wrong lock: {denied:?}"));
+            }
+            Ok(())
+        }
+        other => Err(format!("expected Denied, got {other:?}")),
+    }
+}
+
+fn lock_shared(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let (first, second) = (context.owner(), context.owner());
+    let reply = context.lock(
+        handle.clone(),
+        new_lock(state_id, &first, LockType::Read, 0, 100),
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("the first lock: {e}"))?;
+    let reply = context.lock(handle, new_lock(state_id, &second, LockType::Read, 50, 100))?;
+    expect(&reply, &[None])
+}
+
+fn lock_test(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let (first, second) = (context.owner(), context.owner());
+    let reply = context.lock(
+        handle.clone(),
+        new_lock(state_id, &first, LockType::Write, 0, 100),
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("the lock: {e}"))?;
+
+    let test = |owner: &StateOwner| LockTArgs {
+        lock_type: LockType::Read,
+        offset: 10,
+        length: 10,
+        owner: owner.clone(),
+    };
+    let (reply, _) = context.send_on(handle.clone(), test(&second))?;
+    expect(&reply, &[Some(StatusError::Denied)]).map_err(|e| format!("another owner: {e}"))?;
+    let (reply, _) = context.send_on(handle, test(&first))?;
+    expect(&reply, &[None]).map_err(|e| format!("the same owner: {e}"))
+}
+
+fn lock_unlock(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let (first, second) = (context.owner(), context.owner());
+    let (reply, lock) = context.send_on(
+        handle.clone(),
+        new_lock(state_id, &first, LockType::Write, 0, 100),
+    )?;
+    let lock_state_id = ok(reply.get(lock), "LOCK")?.lock_state_id;
+
+    let (reply, _) = context.send_on(
+        handle.clone(),
+        LockUArgs {
+            lock_type: LockType::Write,
+            sequence_id: SequenceId(0),
+            lock_state_id,
+            offset: 0,
+            length: 100,
+        },
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("unlocking: {e}"))?;
+    let reply = context.lock(
+        handle.clone(),
+        new_lock(state_id, &second, LockType::Write, 0, 100),
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("locking again: {e}"))?;
+
+    // The first owner still has its lock state, it just doesn't lock anything
+    let (reply, _) = context.send_on(
+        handle,
+        LockArgs {
+            lock_type: LockType::Read,
+            reclaim: false,
+            offset: 200,
+            length: 10,
+            locker: Locker::ExistingLockOwner(ExistingLockOwner {
+                lock_state_id,
+                lock_sequence_id: SequenceId(0),
+            }),
+        },
+    )?;
+    expect(&reply, &[None]).map_err(|e| format!("locking with the old lock state: {e}"))
+}
+
+fn lock_zero_length(context: &mut Context) -> Result<()> {
+    let (handle, state_id) = context.opened_file()?;
+    let owner = context.owner();
+    let reply = context.lock(handle, new_lock(state_id, &owner, LockType::Read, 0, 0))?;
+    expect(&reply, &[Some(StatusError::Inval)])
+}
+
+fn share_deny(context: &mut Context) -> Result<()> {
+    let (name, _) = context.file(b"some data")?;
+    let owner = context.owner();
+    let (reply, open) = context.send_on(
+        context.dir.clone(),
+        OpenArgs {
+            sequence_id: SequenceId(0),
+            share_access: ShareAccess::READ,
+            share_deny: ShareDeny::WRITE,
+            owner,
+            open_how: OpenFlag::OpenNoCreate,
+            claim: OpenClaim::Null { file: name.clone() },
+        },
+    )?;
+    ok(reply.get(open), "OPEN")?;
+
+    let mut other = context
+        .url
+        .connect()
+        .map_err(|e| format!("connecting another client: {e:?}"))?;
+    let options = OpenOptions::new().access(ShareAccess::WRITE);
+    match other.open(context.dir.clone(), &name, &options) {
+        Err(e) if e.status() == Some(StatusError::ShareDenied) => Ok(()),
+        Err(e) => Err(format!("expected ShareDenied, got {e:?}")),
+        Ok(_) => Err("expected ShareDenied, got OK".into()),
+    }
+}
+
+fn read_dir_args(cookie: Cookie, cookie_verifier: Verifier, max_count: u32) -> ReadDirArgs {
+    ReadDirArgs {
+        cookie,
+        cookie_verifier,
+        directory_count: max_count,
+        max_count,
+        attr_request: Default::default(),
+    }
+}
+
+fn read_dir_page(
+    context: &mut Context,
+    handle: FileHandle,
+    args: ReadDirArgs,
+) -> Result<ReadDirRes> {
+    let (reply, read_dir) = context.send_on(handle, args)?;
+    ok(reply.get(read_dir), "READDIR")
+}
+
+fn readdir_empty(context: &mut Context) -> Result<()> {
+    let (_, handle) = context.directory()?;
+    let res = read_dir_page(
+        context,
+        handle,
+        read_dir_args(Cookie::initial(), Verifier(0), 4096),
+    )?;
+    if !res.reply.entries.is_empty() || !res.reply.eof {
+        return Err(format!("expected nothing, got {:?}", res.reply));
+    }
+    Ok(())
+}
+
+fn readdir_reserved_cookie(context: &mut Context) -> Result<()> {
+    let (_, handle) = context.directory()?;
+    for cookie in [1, 2] {
+        let (reply, _) = context.send_on(
+            handle.clone(),
+            read_dir_args(Cookie(cookie), Verifier(0), 4096),
+        )?;
+        expect(&reply, &[Some(StatusError::BadCookie)])
+            .map_err(|e| format!("cookie {cookie}: {e}"))?;
+    }
+    Ok(())
+}
+
+fn readdir_too_small(context: &mut Context) -> Result<()> {
+    context.file(b"")?;
+    let (reply, _) = context.send_on(
+        context.dir.clone(),
+        read_dir_args(Cookie::initial(), Verifier(0), 1),
+    )?;
+    expect(&reply, &[Some(StatusError::TooSmall)])
+}
+
+fn readdir_not_dir(context: &mut Context) -> Result<()> {
+    let (_, handle) = context.file(b"")?;
+    let (reply, _) =
+        context.send_on(handle, read_dir_args(Cookie::initial(), Verifier(0), 4096))?;
+    expect(&reply, &[Some(StatusError::NotDir)])
+}
+
+fn readdir_paging(context: &mut Context) -> Result<()> {
+    let (dir_name, handle) = context.directory()?;
+    let mut expected = BTreeSet::new();
+    for i in 0..50 {
+        let name = format!("entry-with-a-longish-name-{i}");
+        let file = context
+            .client
+            .create_file(handle.clone(), &name)
+            .map_err(|e| format!("creating {dir_name}/{name}: {e:?}"))?;
+        context
+            .client
+            .close(file)
+            .map_err(|e| format!("closing {dir_name}/{name}: {e:?}"))?;
+        expected.insert(name);
+    }
+
+    let mut found = BTreeSet::new();
+    let (mut cookie, mut cookie_verifier) = (Cookie::initial(), Verifier(0));
+    loop {
+        let res = read_dir_page(
+            context,
+            handle.clone(),
+            read_dir_args(cookie, cookie_verifier, 512),
+        )?;
+        for entry in &res.reply.entries {
+            if !found.insert(entry.name.clone()) {
+                return Err(format!("{} came up twice", entry.name));
+            }
+        }
+        if res.reply.eof {
+            break;
+        }
+        let Some(last) = res.reply.entries.last() else {
+            return Err("a page without entries which isn't the last".into());
+        };
+        cookie = last.cookie;
+        cookie_verifier = res.cookie_verifier;
+    }
+    if found != expected {
+        let missing: Vec<_> = expected.difference(&found).collect();
+        let extra: Vec<_> = found.difference(&expected).collect();
+        return Err(format!("missing {missing:?}, extra {extra:?}"));
+    }
+    Ok(())
+}
+
+fn look_up(context: &mut Context, handle: FileHandle, name: &str) -> Result<CompoundReply> {
+    Ok(context
+        .send_on(
+            handle,
+            LookUpArgs {
+                object_name: name.into(),
+            },
+        )?
+        .0)
+}
+
+fn name_dots(context: &mut Context) -> Result<()> {
+    for name in [".", ".."] {
+        let reply = look_up(context, context.dir.clone(), name)?;
+        expect(
+            &reply,
+            &[Some(StatusError::BadName), Some(StatusError::NoEnt)],
+        )
+        .map_err(|e| format!("{name}: {e}"))?;
+    }
+    Ok(())
+}
+
+fn name_empty(context: &mut Context) -> Result<()> {
+    let reply = look_up(context, context.dir.clone(), "")?;
+    expect(&reply, &[Some(StatusError::Inval)])
+}
+
+fn name_not_dir(context: &mut Context) -> Result<()> {
+    let (_, handle) = context.file(b"")?;
+    let reply = look_up(context, handle, "entry")?;
+    expect(&reply, &[Some(StatusError::NotDir)])
+}
+
+fn name_not_empty(context: &mut Context) -> Result<()> {
+    let (name, handle) = context.directory()?;
+    context
+        .client
+        .create_directory(handle, "entry", Default::default())
+        .map_err(|e| format!("creating {name}/entry: {e:?}"))?;
+    let (reply, _) = context.send_on(context.dir.clone(), RemoveArgs { target: name })?;
+    expect(&reply, &[Some(StatusError::NotEmpty)])
+}
+
+fn name_exists(context: &mut Context) -> Result<()> {
+    let (name, _) = context.directory()?;
+    let (reply, _) = context.send_on(
+        context.dir.clone(),
+        CreateArgs {
+            object_type: CreateType::Directory,
+            object_name: name,
+            create_attrs: Default::default(),
+        },
+    )?;
+    expect(&reply, &[Some(StatusError::Exist)])
+}
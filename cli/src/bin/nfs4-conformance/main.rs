@@ -0,0 +1,117 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Runs a battery of protocol checks against an NFSv4.1 server and reports which it passes, in
+//! the spirit of pynfs. Each check does something a server has to get right, like refusing state
+//! IDs it never gave out, or telling two lock owners apart, and compares what it replies with what
+//! RFC 8881 says it must. Everything happens in a scratch directory which is removed at the end.
+
+use checks::{Context, CHECKS};
+use clap::Parser;
+use nfs4::FileHandle;
+use nfs4_client::{Client, NfsUrl};
+use std::io;
+use std::net::TcpStream;
+use std::process::ExitCode;
+
+mod checks;
+mod raw;
+
+#[derive(Parser)]
+struct Options {
+    /// The server, as a host name or an `nfs://` URL. The scratch directory is made in the path of
+    /// the URL, or the root.
+    server: String,
+    #[clap(long)]
+    port: Option<u16>,
+    /// Only runs the checks whose names contain one of these
+    #[clap(long = "only")]
+    only: Vec<String>,
+    /// Lists the checks instead of running them
+    #[clap(long)]
+    list: bool,
+    /// Keeps the scratch directory, to look at what was left behind
+    #[clap(long)]
+    keep: bool,
+}
+
+/// Removes everything in the directory, then the directory itself.
+fn remove_all(
+    client: &mut Client<TcpStream>,
+    parent: FileHandle,
+    name: &str,
+) -> nfs4_client::Result<()> {
+    let dir = client.look_up_from(parent.clone(), name)?;
+    let entries: Vec<_> = client
+        .read_dir(dir.clone(), Default::default())
+        .map(|entry| Ok(entry?.name))
+        .collect::<nfs4_client::Result<_>>()?;
+    for entry in entries {
+        match client.remove(dir.clone(), &entry) {
+            Err(e) if e.is_directory_not_empty() => remove_all(client, dir.clone(), &entry)?,
+            result => {
+                result?;
+            }
+        }
+    }
+    client.remove(parent, name)?;
+    Ok(())
+}
+
+fn main() -> nfs4_client::Result<ExitCode> {
+    let opts = Options::parse();
+    if opts.list {
+        for check in CHECKS {
+            println!("{:<28} {}", check.name, check.description);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut url: NfsUrl = match opts.server.starts_with("nfs://") {
+        true => opts.server.parse()?,
+        false => NfsUrl::new(&opts.server, nfs4_client::NFS_PORT),
+    };
+    if let Some(port) = opts.port {
+        url.port = port;
+    }
+    let mut client = url.connect()?;
+    if client.minor_version() < 1 {
+        return Err(
+            io::Error::new(io::ErrorKind::Unsupported, "the server doesn't do NFSv4.1").into(),
+        );
+    }
+
+    let name = format!("nfs4-conformance-{:016x}", rand::random::<u64>());
+    let parent = client.look_up(&url.path)?;
+    let dir = client.create_directory(parent.clone(), &name, Default::default())?;
+    let mut context = Context::new(client, url.clone(), dir);
+
+    let checks = CHECKS.iter().filter(|check| {
+        opts.only.is_empty() || opts.only.iter().any(|o| check.name.contains(o.as_str()))
+    });
+    let (mut passed, mut failed) = (0, 0);
+    for check in checks {
+        match (check.run)(&mut context) {
+            Ok(()) => {
+                passed += 1;
+                println!("PASS {}", check.name);
+            }
+            Err(reason) => {
+                failed += 1;
+                println!("FAIL {}: {reason}", check.name);
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+
+    let mut client = context.into_client();
+    if opts.keep {
+        println!("left {} behind", url.path.join(&name));
+    } else {
+        remove_all(&mut client, parent, &name)?;
+    }
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
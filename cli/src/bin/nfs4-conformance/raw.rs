@@ -0,0 +1,159 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! A session of our own, without `Client` managing it, for sending what `Client` never would,
+//! like requests out of order in a slot.
+
+use nfs4::{
+    ArgOp, CallbackSecurityParameters, ChannelAttrs, ClientOwner, CompoundArgs, CompoundRes,
+    CreateSessionArgs, CreateSessionFlags, DestroyClientIdArgs, DestroySessionArgs, ExchangeIdArgs,
+    ExchangeIdFlags, ResOp, SequenceArgs, SequenceId, SessionId, SlotId, StateProtect,
+    StatusResult, Verifier,
+};
+use nfs4_client::NfsUrl;
+use std::net::TcpStream;
+use sun_rpc_client::RpcClient;
+
+const NFS: u32 = 100003;
+const COMPOUND_PROCEDURE: u32 = 1;
+
+/// What went wrong, for the report.
+pub type Result<T> = std::result::Result<T, String>;
+
+fn channel_attrs(max_requests: u32) -> ChannelAttrs {
+    ChannelAttrs {
+        header_pad_size: 0,
+        max_request_size: 65536,
+        max_response_size: 65536,
+        max_response_size_cached: 4096,
+        max_operations: 16,
+        max_requests,
+        rdma_ird: None,
+    }
+}
+
+pub struct RawSession {
+    rpc_client: RpcClient<TcpStream>,
+    client_id: nfs4::ClientId,
+    pub session_id: SessionId,
+    /// The highest slot the server gave us
+    pub highest_slot_id: SlotId,
+    // What to send next in slot 0
+    sequence_id: SequenceId,
+}
+
+impl RawSession {
+    pub fn connect(url: &NfsUrl) -> Result<Self> {
+        let transport = TcpStream::connect((url.host.as_str(), url.port))
+            .map_err(|e| format!("connecting: {e}"))?;
+        let mut rpc_client = RpcClient::new(transport, NFS);
+
+        let res = compound(
+            &mut rpc_client,
+            vec![ArgOp::ExchangeId(ExchangeIdArgs {
+                client_owner: ClientOwner {
+                    verifier: Verifier(0),
+                    owner_id: rand::random::<u64>().to_be_bytes().into(),
+                },
+                flags: ExchangeIdFlags::empty(),
+                state_protect: StateProtect::None,
+                client_impl_id: None,
+            })],
+        )?;
+        let Some(ResOp::ExchangeId(StatusResult::Ok(exchange_id))) = res.res_array.first() else {
+            return Err(format!("EXCHANGE_ID failed: {:?}", res.status));
+        };
+        let client_id = exchange_id.client_id;
+
+        let res = compound(
+            &mut rpc_client,
+            vec![ArgOp::CreateSession(CreateSessionArgs {
+                client_id,
+                sequence_id: exchange_id.sequence_id,
+                flags: CreateSessionFlags::empty(),
+                fore_channel_attrs: channel_attrs(8),
+                back_channel_attrs: channel_attrs(1),
+                program: 0x40000000,
+                security_parameters: vec![CallbackSecurityParameters::None],
+            })],
+        )?;
+        let Some(ResOp::CreateSession(StatusResult::Ok(session))) = res.res_array.first() else {
+            return Err(format!("CREATE_SESSION failed: {:?}", res.status));
+        };
+        Ok(Self {
+            rpc_client,
+            client_id,
+            session_id: session.session_id,
+            highest_slot_id: SlotId(session.fore_channel_attrs.max_requests.max(1) - 1),
+            sequence_id: SequenceId(1),
+        })
+    }
+
+    /// A SEQUENCE with whatever is given.
+    pub fn sequence_in(&self, slot_id: SlotId, sequence_id: SequenceId) -> ArgOp {
+        ArgOp::Sequence(SequenceArgs {
+            session_id: self.session_id,
+            sequence_id,
+            slot_id,
+            highest_slot_id: self.highest_slot_id,
+            cache_this: false,
+        })
+    }
+
+    /// The SEQUENCE for the next request in slot 0, assuming it is sent.
+    pub fn sequence(&mut self) -> ArgOp {
+        let op = self.sequence_in(SlotId(0), self.sequence_id);
+        self.sequence_id.incr();
+        op
+    }
+
+    /// What slot 0 is expecting next.
+    pub fn next_sequence_id(&self) -> SequenceId {
+        self.sequence_id
+    }
+
+    pub fn compound(&mut self, arg_array: Vec<ArgOp>) -> Result<CompoundRes> {
+        compound(&mut self.rpc_client, arg_array)
+    }
+
+    /// Sends a COMPOUND with the given minor version, instead of 1.
+    pub fn compound_with_minor_version(
+        &mut self,
+        minor_version: u32,
+        arg_array: Vec<ArgOp>,
+    ) -> Result<CompoundRes> {
+        send(&mut self.rpc_client, minor_version, arg_array)
+    }
+}
+
+impl Drop for RawSession {
+    fn drop(&mut self) {
+        let session_id = self.session_id;
+        let _ = self.compound(vec![ArgOp::DestroySession(DestroySessionArgs {
+            session_id,
+        })]);
+        let client_id = self.client_id;
+        let _ = self.compound(vec![ArgOp::DestroyClientId(DestroyClientIdArgs {
+            client_id,
+        })]);
+    }
+}
+
+fn send(
+    rpc_client: &mut RpcClient<TcpStream>,
+    minor_version: u32,
+    arg_array: Vec<ArgOp>,
+) -> Result<CompoundRes> {
+    let args = CompoundArgs {
+        tag: "".into(),
+        minor_version,
+        arg_array,
+    };
+    rpc_client
+        .send_request(COMPOUND_PROCEDURE, args)
+        .and_then(|_| rpc_client.receive_reply())
+        .map_err(|e| format!("sending COMPOUND: {e:?}"))
+}
+
+fn compound(rpc_client: &mut RpcClient<TcpStream>, arg_array: Vec<ArgOp>) -> Result<CompoundRes> {
+    send(rpc_client, 1, arg_array)
+}
@@ -0,0 +1,406 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Measuring how fast a server is. Each mode does one kind of operation over and over for a set
+//! time, with as many of them going at once as the queue depth, and then prints how many it got
+//! through and how long they took. The files it works on are made in a directory of its own and
+//! removed at the end.
+
+use super::{json, Cli, Output};
+use clap::{Args, ValueEnum};
+use indicatif::BinaryBytes;
+use nfs4::{FileAttribute, FileHandle};
+use nfs4_client::{Client, RemotePathBuf, Result};
+use rand::Rng as _;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Mode {
+    /// Read a file from start to end, each job going through its own part of it
+    SeqRead,
+    /// Write a file of its own for each job from start to end, over again once it gets to the
+    /// size
+    SeqWrite,
+    /// Read blocks of a file at random offsets
+    RandRead,
+    /// Write blocks of a file of its own for each job at random offsets
+    RandWrite,
+    /// List a directory with `--files` entries in it
+    Readdir,
+    /// Create empty files and remove them again
+    Metadata,
+}
+
+fn seconds(s: &str) -> std::result::Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("invalid number of seconds `{s}`"))
+}
+
+fn block_size(s: &str) -> std::result::Result<u64, String> {
+    match super::dd::number(s)? {
+        0 => Err("the block size can't be 0".into()),
+        n => Ok(n),
+    }
+}
+
+#[derive(Args)]
+pub struct BenchOptions {
+    #[arg(value_enum)]
+    pub mode: Mode,
+    /// The directory to make the files in
+    #[arg(long, default_value = "/")]
+    pub dir: RemotePathBuf,
+    /// How much each READ or WRITE is for, in bytes or with a unit of K, M or G
+    #[arg(long, default_value = "1M", value_parser = block_size)]
+    pub bs: u64,
+    /// How many operations to have going at once, each with a connection of its own
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub depth: u16,
+    /// How long to keep going for, in seconds
+    #[arg(long, default_value = "10", value_parser = seconds)]
+    pub duration: Duration,
+    /// How big the files read and written are
+    #[arg(long, default_value = "64M", value_parser = super::dd::number)]
+    pub size: u64,
+    /// How many entries the directory listed by readdir has
+    #[arg(long, default_value_t = 1000)]
+    pub files: u32,
+}
+
+/// How long each operation of one kind took, and how much they did.
+#[derive(Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    entries: u64,
+}
+
+impl Samples {
+    fn merge(&mut self, other: Self) {
+        self.latencies.extend(other.latencies);
+        self.bytes += other.bytes;
+        self.entries += other.entries;
+    }
+
+    fn print(&mut self, operation: &str, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let ops = self.latencies.len();
+        print!("{operation}: {ops} ops, {:.1} ops/s", ops as f64 / secs);
+        if self.bytes > 0 {
+            print!(
+                ", {}, {}/s",
+                BinaryBytes(self.bytes),
+                BinaryBytes((self.bytes as f64 / secs) as u64)
+            );
+        }
+        if self.entries > 0 {
+            print!(", {:.1} entries/s", self.entries as f64 / secs);
+        }
+        println!();
+
+        let Some([min, p50, p90, p99, p99_9, max]) = self.percentiles() else {
+            return;
+        };
+        println!(
+            "  latency min {min:.2?}, p50 {p50:.2?}, p90 {p90:.2?}, p99 {p99:.2?}, p99.9 {p99_9:.2?}, \
+             max {max:.2?}"
+        );
+    }
+
+    /// The minimum latency, the 50th, 90th, 99th and 99.9th percentiles and the maximum.
+    fn percentiles(&mut self) -> Option<[Duration; 6]> {
+        self.latencies.sort();
+        let max = *self.latencies.last()?;
+        let percentile = |p: f64| {
+            let i = ((self.latencies.len() - 1) as f64 * p / 100.0).round() as usize;
+            self.latencies[i]
+        };
+        Some([
+            self.latencies[0],
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            percentile(99.9),
+            max,
+        ])
+    }
+
+    fn summary(&mut self, elapsed: Duration) -> json::BenchResult {
+        let secs = elapsed.as_secs_f64();
+        let micros = |time: Duration| time.as_micros() as u64;
+        json::BenchResult {
+            ops: self.latencies.len(),
+            ops_per_sec: self.latencies.len() as f64 / secs,
+            bytes: self.bytes,
+            bytes_per_sec: self.bytes as f64 / secs,
+            entries: self.entries,
+            entries_per_sec: self.entries as f64 / secs,
+            latency_us: self
+                .percentiles()
+                .map(|[min, p50, p90, p99, p99_9, max]| json::Latency {
+                    min: micros(min),
+                    p50: micros(p50),
+                    p90: micros(p90),
+                    p99: micros(p99),
+                    p99_9: micros(p99_9),
+                    max: micros(max),
+                }),
+        }
+    }
+}
+
+/// What one job got through, by operation.
+type Results = BTreeMap<&'static str, Samples>;
+
+/// Runs the operation and records how long it took.
+fn timed<T>(
+    results: &mut Results,
+    operation: &'static str,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let start = Instant::now();
+    let value = f()?;
+    results
+        .entry(operation)
+        .or_default()
+        .latencies
+        .push(start.elapsed());
+    Ok(value)
+}
+
+/// Reads the block at the offset, with as many READs as it takes to get all of it, and returns
+/// how much there was.
+fn read_block(
+    client: &mut Client<TcpStream>,
+    handle: &FileHandle,
+    offset: u64,
+    len: u64,
+) -> Result<u64> {
+    let mut read = 0;
+    while read < len {
+        let res = client.read_at(handle.clone(), offset + read, (len - read) as usize)?;
+        read += res.data.len() as u64;
+        if res.eof || res.data.is_empty() {
+            break;
+        }
+    }
+    Ok(read)
+}
+
+fn write_block(
+    client: &mut Client<TcpStream>,
+    handle: &FileHandle,
+    offset: u64,
+    block: &[u8],
+) -> Result<u64> {
+    let mut written = 0;
+    while written < block.len() {
+        written += client.write_at(handle.clone(), offset + written as u64, &block[written..])?;
+    }
+    Ok(written as u64)
+}
+
+/// What the jobs work on, made before they start.
+enum Target {
+    /// One file all of them read
+    Shared(FileHandle),
+    /// A file for each job
+    PerJob(Vec<FileHandle>),
+    /// The directory the files are made in
+    Directory(FileHandle),
+}
+
+impl Cli {
+    /// Makes a file of the given size, written out if `fill` is set and sparse otherwise.
+    fn bench_file(
+        &mut self,
+        dir: FileHandle,
+        name: &str,
+        size: u64,
+        fill: bool,
+    ) -> Result<FileHandle> {
+        let handle = self.client.create_file(dir, name)?.handle.clone();
+        match fill {
+            true => self
+                .client
+                .write_all(handle.clone(), io::Read::take(io::repeat(0xa5), size))?,
+            false => {
+                let size = [FileAttribute::Size(size)].into_iter().collect();
+                self.client.set_attr(handle.clone(), size)?;
+            }
+        }
+        Ok(handle)
+    }
+
+    /// Removes the directory the benchmark made, and everything in it.
+    fn remove_bench_dir(&mut self, parent: FileHandle, name: &str) -> Result<()> {
+        let dir = self.client.look_up_from(parent.clone(), name)?;
+        let names = self
+            .client
+            .read_dir(dir.clone(), Default::default())
+            .map(|entry| Ok(entry?.name))
+            .collect::<Result<Vec<_>>>()?;
+        for entry in names {
+            self.client.remove(dir.clone(), &entry)?;
+        }
+        self.client.remove(parent, name)?;
+        Ok(())
+    }
+
+    fn bench_target(&mut self, options: &BenchOptions, dir: FileHandle) -> Result<Target> {
+        let jobs = u64::from(options.depth);
+        Ok(match options.mode {
+            Mode::SeqRead | Mode::RandRead => {
+                eprintln!("writing {} to read", BinaryBytes(options.size));
+                Target::Shared(self.bench_file(dir, "data", options.size, true)?)
+            }
+            Mode::SeqWrite => Target::PerJob(
+                (0..jobs)
+                    .map(|i| self.bench_file(dir.clone(), &format!("data-{i}"), 0, false))
+                    .collect::<Result<_>>()?,
+            ),
+            // Sparse, so that the writes aren't all extending it
+            Mode::RandWrite => Target::PerJob(
+                (0..jobs)
+                    .map(|i| {
+                        self.bench_file(dir.clone(), &format!("data-{i}"), options.size, false)
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Mode::Readdir => {
+                eprintln!("creating {} files to list", options.files);
+                for i in 0..options.files {
+                    let file = self.client.create_file(dir.clone(), &format!("{i:08}"))?;
+                    self.client.close(file)?;
+                }
+                Target::Directory(dir)
+            }
+            Mode::Metadata => Target::Directory(dir),
+        })
+    }
+
+    /// Does the operations of the mode over and over until the time is up.
+    fn bench_job(
+        client: &mut Client<TcpStream>,
+        options: &BenchOptions,
+        target: &Target,
+        job: u64,
+    ) -> Result<Results> {
+        let mut results = Results::new();
+        let mut rng = rand::thread_rng();
+        let blocks = (options.size / options.bs).max(1);
+        let block: Vec<u8> = (0..options.bs).map(|_| rng.gen()).collect();
+        // Each job starts its sequential reads at a different place, so they aren't all reading
+        // the same blocks
+        let mut next = blocks * job / u64::from(options.depth);
+        let start = Instant::now();
+        while start.elapsed() < options.duration {
+            let index = match options.mode {
+                Mode::SeqRead | Mode::SeqWrite => {
+                    let index = next % blocks;
+                    next += 1;
+                    index
+                }
+                _ => rng.gen_range(0, blocks),
+            };
+            let offset = index * options.bs;
+            match (options.mode, target) {
+                (Mode::SeqRead | Mode::RandRead, Target::Shared(handle)) => {
+                    let read = timed(&mut results, "read", || {
+                        read_block(client, handle, offset, options.bs)
+                    })?;
+                    results.get_mut("read").unwrap().bytes += read;
+                }
+                (Mode::SeqWrite | Mode::RandWrite, Target::PerJob(handles)) => {
+                    let handle = &handles[job as usize];
+                    let written = timed(&mut results, "write", || {
+                        write_block(client, handle, offset, &block)
+                    })?;
+                    results.get_mut("write").unwrap().bytes += written;
+                }
+                (Mode::Readdir, Target::Directory(dir)) => {
+                    let entries = timed(&mut results, "readdir", || {
+                        client
+                            .read_dir(dir.clone(), Default::default())
+                            .try_fold(0, |n, entry| entry.map(|_| n + 1))
+                    })?;
+                    results.get_mut("readdir").unwrap().entries += entries;
+                }
+                (Mode::Metadata, Target::Directory(dir)) => {
+                    let name = format!("{job}-{next}");
+                    next += 1;
+                    timed(&mut results, "create", || {
+                        let file = client.create_file(dir.clone(), &name)?;
+                        client.close(file)
+                    })?;
+                    timed(&mut results, "remove", || client.remove(dir.clone(), &name))?;
+                }
+                _ => unreachable!(),
+            }
+        }
+        Ok(results)
+    }
+
+    /// Runs the benchmark and prints what it measured. The files it made are removed even if it
+    /// fails.
+    pub fn bench(&mut self, options: BenchOptions) -> Result<()> {
+        let parent = self.client.look_up(self.path(options.dir.clone()))?;
+        let name = format!("nfs4-bench-{}", std::process::id());
+        let dir = self
+            .client
+            .create_directory(parent.clone(), &name, Default::default())?;
+
+        let res = self.bench_target(&options, dir).and_then(|target| {
+            let mut others = (1..options.depth)
+                .map(|_| (self.new_client)())
+                .collect::<Result<Vec<_>>>()?;
+            eprintln!(
+                "running for {:.1?} with a queue depth of {}",
+                options.duration, options.depth
+            );
+            let start = Instant::now();
+            let per_job = std::thread::scope(|scope| {
+                let threads: Vec<_> = std::iter::once(&mut self.client)
+                    .chain(&mut others)
+                    .zip(0..)
+                    .map(|(client, job)| {
+                        let (options, target) = (&options, &target);
+                        scope.spawn(move || Self::bench_job(client, options, target, job))
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|t| t.join().unwrap())
+                    .collect::<Result<Vec<_>>>()
+            })?;
+            Ok((per_job, start.elapsed()))
+        });
+        let cleaned_up = self.remove_bench_dir(parent, &name);
+        let (per_job, elapsed) = res?;
+        cleaned_up?;
+
+        let mut results = Results::new();
+        for job in per_job {
+            for (operation, samples) in job {
+                results.entry(operation).or_default().merge(samples);
+            }
+        }
+        if self.output == Output::Json {
+            let results: BTreeMap<_, _> = results
+                .iter_mut()
+                .map(|(operation, samples)| (*operation, samples.summary(elapsed)))
+                .collect();
+            json::print(&results);
+            return Ok(());
+        }
+        for (operation, samples) in &mut results {
+            samples.print(operation, elapsed);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Hashes of remote files, worked out by reading them through here, and checking with them that
+//! copies came out the same as what they were made from.
+
+use super::{json, Cli, Output};
+use clap::ValueEnum;
+use nfs4::FileHandle;
+use nfs4_client::{Client, RemotePath, RemotePathBuf, Result};
+use sha2::digest::DynDigest;
+use sha2::{Digest as _, Sha256, Sha512};
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+/// Hashes what is written to it.
+struct Hasher(Box<dyn DynDigest>);
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        Self(match algorithm {
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+            Algorithm::Sha512 => Box::new(Sha512::new()),
+        })
+    }
+
+    fn finish(self) -> String {
+        hex::encode(self.0.finalize())
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The hash of the remote file in hex.
+pub fn remote_checksum(
+    client: &mut Client<TcpStream>,
+    handle: FileHandle,
+    algorithm: Algorithm,
+) -> Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    client.read_all(handle, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// The hash of the local file in hex.
+pub fn local_checksum(path: &Path, algorithm: Algorithm) -> Result<String> {
+    let mut hasher = Hasher::new(algorithm);
+    io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Checks that the local and remote files have the same contents by reading both of them again
+/// and comparing their SHA-256 hashes.
+pub fn verify(
+    client: &mut Client<TcpStream>,
+    handle: FileHandle,
+    local: &Path,
+    remote: &RemotePath,
+) -> Result<()> {
+    let remote_sum = remote_checksum(client, handle, Algorithm::Sha256)?;
+    if local_checksum(local, Algorithm::Sha256)? != remote_sum {
+        let message = format!(
+            "{remote}: the copy at {} doesn't match, its checksum is different",
+            local.display()
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+    }
+    Ok(())
+}
+
+impl Cli {
+    /// Prints the hash of each remote file the way `sha256sum` does.
+    pub fn checksum(&mut self, paths: Vec<RemotePathBuf>, algorithm: Algorithm) -> Result<()> {
+        let mut sums = vec![];
+        for path in paths {
+            let handle = self.client.look_up(&path)?;
+            let sum = remote_checksum(&mut self.client, handle, algorithm)?;
+            match self.output {
+                Output::Text => println!("{sum}  {path}"),
+                Output::Json => sums.push(json::Checksum {
+                    path: path.into_string(),
+                    algorithm: algorithm.to_possible_value().unwrap().get_name().into(),
+                    checksum: sum,
+                }),
+            }
+        }
+        if self.output == Output::Json {
+            json::print(&sums);
+        }
+        Ok(())
+    }
+}
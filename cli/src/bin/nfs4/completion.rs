@@ -0,0 +1,207 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Shell completion, done by clap_complete from the commands and options clap knows about. The
+//! shell runs us to complete each word, see `clap_complete::CompleteEnv`. Remote paths are
+//! completed by running `nfs4 <server> __complete-path <prefix>`, which lists the remote
+//! directory.
+
+use super::{Cli, Location, Options};
+use clap::{Arg, CommandFactory as _, Parser, ValueEnum};
+use clap_complete::env::{Bash, EnvCompleter, Fish, Zsh};
+use clap_complete::{ArgValueCompleter, CompleteEnv, CompletionCandidate};
+use nfs4::{FileAttributeId, FileType};
+use nfs4_client::{RemotePathBuf, Result};
+use std::any::TypeId;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::process::{Command, Stdio};
+
+/// What the shell sets to have us complete a word rather than run a command.
+const COMPLETE_VAR: &str = "COMPLETE";
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// `nfs4 completions <SHELL>`, which doesn't take a server like the other commands do.
+#[derive(Parser)]
+#[command(name = "nfs4 completions")]
+struct CompletionsOptions {
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+fn is_remote(arg: &Arg) -> bool {
+    let type_id = arg.get_value_parser().type_id();
+    type_id == TypeId::of::<RemotePathBuf>() || type_id == TypeId::of::<Location>()
+}
+
+/// The words of the command line being completed which come before the command: the server, the
+/// port if there is one, and the options. `None` if there is no command yet.
+fn server_words(words: &[OsString]) -> Option<&[OsString]> {
+    let command = Options::command();
+    let value_options: Vec<String> = command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && arg.get_action().takes_values())
+        .flat_map(|arg| {
+            let longs = arg.get_long_and_visible_aliases().unwrap_or_default();
+            let shorts = arg.get_short_and_visible_aliases().unwrap_or_default();
+            longs
+                .into_iter()
+                .map(|l| format!("--{l}"))
+                .chain(shorts.into_iter().map(|s| format!("-{s}")))
+        })
+        .collect();
+
+    let mut positionals = 0;
+    let mut i = 1;
+    while i < words.len() {
+        let word = words[i].to_string_lossy();
+        if value_options.iter().any(|option| *option == word) {
+            i += 1;
+        } else if !word.starts_with('-') {
+            let is_port = positionals == 1 && word.parse::<u16>().is_ok();
+            if positionals > 0 && !is_port {
+                return Some(&words[1..i]);
+            }
+            positionals += 1;
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Completes a remote path with what `__complete-path` lists for it, given the server from the
+/// command line being completed, which comes after `--` in our arguments.
+fn remote_paths(current: &OsStr) -> Vec<CompletionCandidate> {
+    let words: Vec<OsString> = std::env::args_os()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect();
+    let Some(server) = server_words(&words) else {
+        return vec![];
+    };
+    let Ok(program) = std::env::current_exe() else {
+        return vec![];
+    };
+    let output = Command::new(program)
+        .args(server)
+        .arg("__complete-path")
+        .arg(current)
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(CompletionCandidate::new)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// The command line as clap knows it, with the arguments which are remote paths completed by
+/// `remote_paths`.
+fn command() -> clap::Command {
+    let mut command = Options::command();
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_owned())
+        .collect();
+    for name in names {
+        command = command.mut_subcommand(name, |sub| {
+            let remote: Vec<_> = sub
+                .get_arguments()
+                .filter(|arg| is_remote(arg))
+                .map(|arg| arg.get_id().clone())
+                .collect();
+            remote.into_iter().fold(sub, |sub, id| {
+                sub.mut_arg(id, |arg| arg.add(ArgValueCompleter::new(remote_paths)))
+            })
+        });
+    }
+    command
+}
+
+/// Completes the command line given in the arguments and exits, if the shell ran us for that.
+pub fn complete() {
+    CompleteEnv::with_factory(command)
+        .var(COMPLETE_VAR)
+        .bin("nfs4")
+        .complete();
+}
+
+/// Prints the script which makes the shell given in the arguments, which start with
+/// `completions`, run us to complete the command line.
+pub fn print_script(args: impl IntoIterator<Item = OsString>) -> Result<()> {
+    let options = CompletionsOptions::parse_from(args);
+    let shell: &dyn EnvCompleter = match options.shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+    };
+    let completer = std::env::current_exe()?;
+    shell.write_registration(
+        COMPLETE_VAR,
+        "nfs4",
+        "nfs4",
+        &completer.to_string_lossy(),
+        &mut io::stdout(),
+    )?;
+    Ok(())
+}
+
+impl Cli {
+    /// Prints the remote paths which start with the given one, one per line and with a `/` after
+    /// directories, for completing it in a shell. Names starting with `.` are only given when it
+    /// does too.
+    pub fn complete_path(&mut self, prefix: &str) -> Result<()> {
+        let (dir, partial) = match prefix.rfind('/') {
+            Some(i) => prefix.split_at(i + 1),
+            None => ("", prefix),
+        };
+        let handle = self.client.look_up(self.path(dir.into()))?;
+        let attr_request = [FileAttributeId::Type].into_iter().collect();
+        let mut matches = vec![];
+        for entry in self.client.read_dir(handle, attr_request) {
+            let entry = entry?;
+            if !entry.name.starts_with(partial)
+                || (entry.name.starts_with('.') && partial.is_empty())
+            {
+                continue;
+            }
+            let is_dir = entry.attrs.get_as(FileAttributeId::Type) == Some(&FileType::Directory);
+            matches.push(format!(
+                "{dir}{}{}",
+                entry.name,
+                if is_dir { "/" } else { "" }
+            ));
+        }
+        matches.sort();
+        for path in matches {
+            println!("{path}");
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn words_before_command() {
+    let words = |line: &str| line.split(' ').map(OsString::from).collect::<Vec<_>>();
+    let line = words("nfs4 --output json nfs.example.com 2050 cp /a /b");
+    assert_eq!(server_words(&line), Some(&line[1..5]));
+    let line = words("nfs4 nfs.example.com ls 2050");
+    assert_eq!(server_words(&line), Some(&line[1..2]));
+    assert_eq!(server_words(&words("nfs4 nfs.example.com 2050")), None);
+}
+
+#[test]
+fn remote_paths_completed() {
+    let command = command();
+    let cat = command.find_subcommand("cat").unwrap();
+    let path = cat.get_arguments().find(|arg| arg.is_positional()).unwrap();
+    assert!(path.get::<ArgValueCompleter>().is_some());
+    command.debug_assert();
+}
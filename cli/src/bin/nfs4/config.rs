@@ -0,0 +1,233 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! The config file, `~/.config/nfs4/config.toml`, which gives names to servers so they can be
+//! given as `@name` instead of repeating how to reach them every time. Each is a table under
+//! `hosts`, like
+//!
+//! ```toml
+//! [hosts.prod]
+//! host = "nfs.example.com"
+//! port = 2049
+//! path = "/data"
+//! sec = "sys"
+//! uid = 1000
+//! gid = 1000
+//! read_chunk_size = 1048576
+//! bwlimit = "10M"
+//! ```
+//!
+//! where everything but `host` can be left out. What is given on the command line wins over it.
+
+use nfs4::SecurityInfo;
+use nfs4_client::{ClientBuilder, NfsUrl, RateLimiter, RemotePathBuf, Result};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sun_rpc_client::{AuthSysParameters, Gid, OpaqueAuth, Uid};
+use toml_edit::{Document, Item};
+
+/// Where the config file is, under `$XDG_CONFIG_HOME` if it is set and `~/.config` otherwise.
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".config")))?;
+    Some(config_dir.join("nfs4/config.toml"))
+}
+
+/// How to reach a server, and how to talk to it, from the config file.
+#[derive(Default)]
+pub struct HostConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    /// The path on the server paths given to commands are under, and the export with v3
+    pub path: Option<RemotePathBuf>,
+    pub minor_version: Option<u32>,
+    pub security: Option<SecurityInfo>,
+    /// The user and group to send with AUTH_SYS, the group being the same as the user if not given
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub id_domain: Option<String>,
+    /// In seconds
+    pub timeout: Option<u64>,
+    pub read_chunk_size: Option<u32>,
+    pub write_chunk_size: Option<u32>,
+    pub read_pipeline_depth: Option<usize>,
+    pub write_pipeline_depth: Option<usize>,
+    /// In bytes per second
+    pub bwlimit: Option<u64>,
+}
+
+fn invalid(config: &Path, message: impl std::fmt::Display) -> nfs4_client::Error {
+    let message = format!("{}: {message}", config.display());
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+/// The value as a number of the given type, if it is one which fits in it.
+fn number<T: TryFrom<i64>>(value: &Item) -> Option<T> {
+    value.as_integer()?.try_into().ok()
+}
+
+impl HostConfig {
+    /// Reads the settings of the host with the given name from the config file.
+    pub fn load(config: &Path, name: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(config).map_err(|e| invalid(config, e))?;
+        let document: Document = text.parse().map_err(|e| invalid(config, e))?;
+        let table = document
+            .get("hosts")
+            .and_then(|hosts| hosts.get(name))
+            .and_then(Item::as_table_like)
+            .ok_or_else(|| invalid(config, format!("no host named `{name}`")))?;
+
+        let mut host = Self::default();
+        for (key, value) in table.iter() {
+            let wrong_type =
+                |expected| invalid(config, format!("`{key}` of `{name}` should be {expected}"));
+            let string = || value.as_str().ok_or_else(|| wrong_type("a string"));
+            let not_number = || wrong_type("a number which isn't negative or too big");
+            match key {
+                "host" => host.host = string()?.into(),
+                "port" => host.port = Some(number(value).ok_or_else(not_number)?),
+                "path" => host.path = Some(string()?.into()),
+                "minor_version" => host.minor_version = Some(number(value).ok_or_else(not_number)?),
+                "sec" => {
+                    host.security = Some(match string()? {
+                        "sys" => SecurityInfo::Sys,
+                        "none" => SecurityInfo::None,
+                        _ => return Err(wrong_type("\"sys\" or \"none\"")),
+                    })
+                }
+                "uid" => host.uid = Some(number(value).ok_or_else(not_number)?),
+                "gid" => host.gid = Some(number(value).ok_or_else(not_number)?),
+                "id_domain" => host.id_domain = Some(string()?.into()),
+                "timeout" => host.timeout = Some(number(value).ok_or_else(not_number)?),
+                "read_chunk_size" => {
+                    host.read_chunk_size = Some(number(value).ok_or_else(not_number)?)
+                }
+                "write_chunk_size" => {
+                    host.write_chunk_size = Some(number(value).ok_or_else(not_number)?)
+                }
+                "read_pipeline_depth" => {
+                    host.read_pipeline_depth = Some(number(value).ok_or_else(not_number)?)
+                }
+                "write_pipeline_depth" => {
+                    host.write_pipeline_depth = Some(number(value).ok_or_else(not_number)?)
+                }
+                "bwlimit" => {
+                    let rate = super::bandwidth(string()?).map_err(|e| invalid(config, e))?;
+                    host.bwlimit = Some(rate);
+                }
+                _ => {
+                    let message = format!("unknown setting `{key}` of `{name}`");
+                    return Err(invalid(config, message));
+                }
+            }
+        }
+        if host.host.is_empty() {
+            return Err(invalid(config, format!("`{name}` has no `host`")));
+        }
+        if host.gid.is_some() && host.uid.is_none() {
+            return Err(invalid(
+                config,
+                format!("`{name}` has a `gid` but no `uid`"),
+            ));
+        }
+        if host.security == Some(SecurityInfo::None) && host.uid.is_some() {
+            let message = format!("`{name}` has a `uid` but `sec` is \"none\"");
+            return Err(invalid(config, message));
+        }
+        Ok(host)
+    }
+
+    pub fn url(&self) -> NfsUrl {
+        let mut url = NfsUrl::new(&self.host, self.port.unwrap_or(nfs4_client::NFS_PORT));
+        if let Some(path) = &self.path {
+            url.path = path.clone();
+        }
+        url.minor_version = self.minor_version;
+        url.security = self.security.clone();
+        url
+    }
+
+    /// Applies the settings which aren't part of the URL to the builder.
+    pub fn configure(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        if let Some(uid) = self.uid {
+            let gid = self.gid.unwrap_or(uid);
+            builder = builder.credential(OpaqueAuth::auth_sys(AuthSysParameters {
+                stamp: 0,
+                machine_name: "nfs4".into(),
+                uid: Uid(uid),
+                gid: Gid(gid),
+                gids: vec![Gid(gid)],
+            }));
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+        if let Some(size) = self.read_chunk_size {
+            builder = builder.read_chunk_size(size);
+        }
+        if let Some(size) = self.write_chunk_size {
+            builder = builder.write_chunk_size(size);
+        }
+        if let Some(depth) = self.read_pipeline_depth {
+            builder = builder.read_pipeline_depth(depth);
+        }
+        if let Some(depth) = self.write_pipeline_depth {
+            builder = builder.write_pipeline_depth(depth);
+        }
+        if let Some(bwlimit) = self.bwlimit {
+            builder = builder.bandwidth_limit(RateLimiter::new(bwlimit));
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+fn load(text: &str) -> Result<HostConfig> {
+    let dir = tempdir::TempDir::new("nfs4_config").unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, text).unwrap();
+    HostConfig::load(&path, "prod")
+}
+
+#[test]
+fn host_config() {
+    let host = load(
+        r#"
+        [hosts.prod]
+        host = "nfs.example.com"
+        port = 2050
+        path = "/data"
+        sec = "sys"
+        uid = 1000
+        read_chunk_size = 1048576
+        bwlimit = "10M"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(host.host, "nfs.example.com");
+    assert_eq!(host.port, Some(2050));
+    assert_eq!(host.security, Some(SecurityInfo::Sys));
+    assert_eq!((host.uid, host.gid), (Some(1000), None));
+    assert_eq!(host.read_chunk_size, Some(1 << 20));
+    assert_eq!(host.bwlimit, Some(10 << 20));
+    let url = host.url();
+    assert_eq!(url.path.as_str(), "/data");
+    assert_eq!(url.port, 2050);
+}
+
+#[test]
+fn invalid_host_config() {
+    let error = |text: &str| format!("{:?}", load(text).err().unwrap());
+    assert!(error("[hosts.dev]\nhost = \"a\"").contains("no host named `prod`"));
+    assert!(error("[hosts.prod]\nport = 1").contains("has no `host`"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\nport = -1").contains("`port` of `prod`"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\nport = 70000").contains("`port` of `prod`"));
+    assert!(error("[hosts.prod]\nhost = 1").contains("should be a string"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\nsec = \"krb5\"").contains("`sec` of `prod`"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\ngid = 1").contains("a `gid` but no `uid`"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\nsec = \"none\"\nuid = 1").contains("but `sec` is"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\nbwlimit = \"fast\"").contains("invalid rate"));
+    assert!(error("[hosts.prod]\nhost = \"a\"\ncolour = 1").contains("unknown setting"));
+    assert!(error("[hosts.prod\n").contains("config.toml"));
+}
@@ -0,0 +1,286 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Copying blocks of remote files at given offsets like `dd`, with READs and WRITEs of the given
+//! block size. `if` and `of` are remote paths, and leaving one out or giving `-` means stdin or
+//! stdout, so local files go through redirection.
+
+use super::Cli;
+use indicatif::BinaryBytes;
+use nfs4::{FileAttribute, FileHandle};
+use nfs4_client::{OpenOptions, RemotePathBuf, Result};
+use std::io::{self, Read as _, Write as _};
+use std::time::Instant;
+
+/// One `key=value` operand.
+#[derive(Clone, Debug)]
+pub enum Operand {
+    Input(RemotePathBuf),
+    Output(RemotePathBuf),
+    BlockSize(u64),
+    Count(u64),
+    Skip(u64),
+    Seek(u64),
+    NoTruncate,
+}
+
+/// A number with an optional unit after it, `c` for bytes, `w` for two, `b` for 512 and `K`, `M`
+/// or `G` for powers of 1024.
+pub fn number(s: &str) -> std::result::Result<u64, String> {
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'c')) => (&s[..i], 1),
+        Some((i, 'w')) => (&s[..i], 2),
+        Some((i, 'b')) => (&s[..i], 512),
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid number `{s}`"))
+}
+
+pub fn operand(s: &str) -> std::result::Result<Operand, String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `key=value`, got `{s}`"))?;
+    Ok(match key {
+        "if" => Operand::Input(value.into()),
+        "of" => Operand::Output(value.into()),
+        "bs" => match number(value)? {
+            0 => return Err("the block size can't be 0".into()),
+            n => Operand::BlockSize(n),
+        },
+        "count" => Operand::Count(number(value)?),
+        "skip" => Operand::Skip(number(value)?),
+        "seek" => Operand::Seek(number(value)?),
+        "conv" if value == "notrunc" => Operand::NoTruncate,
+        "conv" => return Err(format!("unsupported conversion `{value}`, only notrunc is")),
+        _ => return Err(format!("unknown operand `{key}`")),
+    })
+}
+
+/// What the operands add up to, the way `dd` takes them.
+pub struct DdOptions {
+    input: Option<RemotePathBuf>,
+    output: Option<RemotePathBuf>,
+    block_size: u64,
+    /// How many blocks to copy, all of them if not given
+    count: Option<u64>,
+    /// How many blocks of the input to pass over first
+    skip: u64,
+    /// How many blocks of the output to pass over first
+    seek: u64,
+    truncate: bool,
+}
+
+impl DdOptions {
+    pub fn new(operands: Vec<Operand>) -> Self {
+        let mut options = Self {
+            input: None,
+            output: None,
+            block_size: 512,
+            count: None,
+            skip: 0,
+            seek: 0,
+            truncate: true,
+        };
+        let stdio = |path: RemotePathBuf| (path.as_str() != "-").then_some(path);
+        for operand in operands {
+            match operand {
+                Operand::Input(path) => options.input = stdio(path),
+                Operand::Output(path) => options.output = stdio(path),
+                Operand::BlockSize(n) => options.block_size = n,
+                Operand::Count(n) => options.count = Some(n),
+                Operand::Skip(n) => options.skip = n,
+                Operand::Seek(n) => options.seek = n,
+                Operand::NoTruncate => options.truncate = false,
+            }
+        }
+        options
+    }
+}
+
+/// Full and partial blocks, which `dd` counts separately.
+#[derive(Default)]
+struct Records {
+    full: u64,
+    partial: u64,
+}
+
+impl Records {
+    fn add(&mut self, len: usize, block_size: u64) {
+        match len as u64 == block_size {
+            true => self.full += 1,
+            false => self.partial += 1,
+        }
+    }
+}
+
+/// Reads from stdin until the buffer is full or it ends, returning how much was read.
+fn read_stdin(buf: &mut [u8]) -> io::Result<usize> {
+    let mut stdin = io::stdin().lock();
+    let mut len = 0;
+    while len < buf.len() {
+        match stdin.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+impl Cli {
+    /// Opens the remote file for `of`, creating it if it doesn't exist.
+    fn open_output(&mut self, path: &RemotePathBuf) -> Result<FileHandle> {
+        let (Some(parent_dir), Some(name)) = (path.parent(), path.file_name()) else {
+            let message = format!("`{path}` isn't a file");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        };
+        let parent = self.client.look_up(parent_dir)?;
+        let options = OpenOptions::new().access(nfs4::ShareAccess::WRITE).create();
+        let file = self.client.open(parent, name, &options)?;
+        let handle = file.handle.clone();
+        self.client.close(file)?;
+        Ok(handle)
+    }
+
+    /// Copies blocks from the input to the output, and prints how many it did along with how
+    /// long it took to stderr, like `dd` does.
+    pub fn dd(&mut self, options: DdOptions) -> Result<()> {
+        let block_size = options.block_size;
+        let input = match &options.input {
+            Some(path) => Some(self.client.look_up(self.path(path.clone()))?),
+            None => None,
+        };
+        let output = match &options.output {
+            Some(path) => Some(self.open_output(&self.path(path.clone()))?),
+            None if options.seek > 0 => {
+                let message = "seek needs `of` to be a remote file";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+            }
+            None => None,
+        };
+        let overflow = || io::Error::new(io::ErrorKind::InvalidInput, "offset is too big");
+        let mut in_offset = options.skip.checked_mul(block_size).ok_or_else(overflow)?;
+        let mut out_offset = options.seek.checked_mul(block_size).ok_or_else(overflow)?;
+        if let (Some(handle), true) = (&output, options.truncate) {
+            let size = [FileAttribute::Size(out_offset)].into_iter().collect();
+            self.client.set_attr(handle.clone(), size)?;
+        }
+
+        let start = Instant::now();
+        let mut buf = vec![0; block_size as usize];
+        if input.is_none() {
+            // Stdin can't be seeked, so the skipped blocks are read and thrown away
+            for _ in 0..options.skip {
+                if read_stdin(&mut buf)? == 0 {
+                    break;
+                }
+            }
+        }
+
+        let (mut records_in, mut records_out) = (Records::default(), Records::default());
+        let mut copied = 0;
+        while options
+            .count
+            .is_none_or(|c| records_in.full + records_in.partial < c)
+        {
+            let block = match &input {
+                Some(handle) => self.read_range(handle, in_offset, in_offset + block_size)?,
+                None => {
+                    let len = read_stdin(&mut buf)?;
+                    buf[..len].to_vec()
+                }
+            };
+            if block.is_empty() {
+                break;
+            }
+            records_in.add(block.len(), block_size);
+            in_offset += block.len() as u64;
+
+            match &output {
+                Some(handle) => {
+                    let mut written = 0;
+                    while written < block.len() {
+                        let offset = out_offset + written as u64;
+                        written +=
+                            self.client
+                                .write_at(handle.clone(), offset, &block[written..])?;
+                    }
+                }
+                None => io::stdout().write_all(&block)?,
+            }
+            records_out.add(block.len(), block_size);
+            out_offset += block.len() as u64;
+            copied += block.len() as u64;
+        }
+        io::stdout().flush()?;
+
+        let elapsed = start.elapsed().as_secs_f64();
+        eprintln!("{}+{} records in", records_in.full, records_in.partial);
+        eprintln!("{}+{} records out", records_out.full, records_out.partial);
+        eprintln!(
+            "{copied} bytes ({}) copied, {elapsed:.3} s, {}/s",
+            BinaryBytes(copied),
+            BinaryBytes((copied as f64 / elapsed.max(f64::EPSILON)) as u64)
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn numbers() {
+    assert_eq!(number("100"), Ok(100));
+    assert_eq!(number("3c"), Ok(3));
+    assert_eq!(number("3w"), Ok(6));
+    assert_eq!(number("2b"), Ok(1024));
+    assert_eq!(number("4k"), Ok(4096));
+    assert_eq!(number("4K"), Ok(4096));
+    assert_eq!(number("1M"), Ok(1 << 20));
+    assert_eq!(number("1G"), Ok(1 << 30));
+    assert!(number("").is_err());
+    assert!(number("K").is_err());
+    assert!(number("-1").is_err());
+    assert!(number("1T").is_err());
+    assert!(number("18446744073709551615K").is_err());
+}
+
+#[test]
+fn operands() {
+    let operands = [
+        "if=/a",
+        "of=-",
+        "bs=1M",
+        "count=3",
+        "skip=1",
+        "seek=2",
+        "conv=notrunc",
+    ];
+    let operands = operands.map(|s| operand(s).unwrap());
+    let options = DdOptions::new(operands.into());
+    assert_eq!(options.input, Some("/a".into()));
+    assert_eq!(options.output, None);
+    assert_eq!(options.block_size, 1 << 20);
+    assert_eq!(options.count, Some(3));
+    assert_eq!(options.skip, 1);
+    assert_eq!(options.seek, 2);
+    assert!(!options.truncate);
+
+    let options = DdOptions::new(vec![]);
+    assert_eq!((options.input, options.output), (None, None));
+    assert_eq!(options.block_size, 512);
+    assert_eq!(options.count, None);
+    assert!(options.truncate);
+}
+
+#[test]
+fn invalid_operands() {
+    assert!(operand("if").is_err());
+    assert!(operand("bs=0").is_err());
+    assert!(operand("conv=sync").is_err());
+    assert!(operand("ibs=1").is_err());
+    assert!(operand("count=x").is_err());
+}
@@ -0,0 +1,108 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Editing a remote file with a local editor. The file is copied to a temporary directory, and
+//! only written back if it was changed there and hasn't been changed on the server in the
+//! meantime, which is told by its change attribute.
+
+use super::Cli;
+use nfs4::{FileAttribute, FileAttributeId, FileHandle, StatusError};
+use nfs4_client::{RemotePathBuf, Result};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs `$VISUAL` or `$EDITOR` on the given file, or `vi` if neither is set. The variable can
+/// have arguments in it, like `code --wait`.
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let args = shlex::split(&editor).filter(|a| !a.is_empty());
+    let Some([program, args @ ..]) = args.as_deref() else {
+        let message = format!("can't run the editor `{editor}`");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+    };
+    let status = Command::new(program).args(args).arg(path).status()?;
+    if !status.success() {
+        let message = format!("the editor `{editor}` failed with {status}");
+        return Err(io::Error::other(message).into());
+    }
+    Ok(())
+}
+
+impl Cli {
+    /// Downloads the file, runs an editor on it, and uploads it again if it was changed. The
+    /// local copy is kept and where it is printed if it can't be uploaded, so the edits aren't
+    /// lost.
+    pub fn edit(&mut self, path: RemotePathBuf) -> Result<()> {
+        let attr_request = [FileAttributeId::Change].into_iter().collect();
+        let (handle, mut attrs) = self.client.look_up_with_attrs(&path, attr_request)?;
+        let change = attrs.remove(FileAttributeId::Change);
+        let mut original = vec![];
+        self.client.read_all(handle.clone(), &mut original)?;
+
+        // In a directory of its own so that the file can keep its name, which editors go by. It
+        // gets a name nobody else can have picked beforehand, and only we can get into it.
+        let dir = tempfile::Builder::new().prefix("nfs4-edit-").tempdir()?;
+        let name = path.file_name().unwrap_or("file");
+        let local = dir.path().join(name);
+        std::fs::write(&local, &original)?;
+
+        run_editor(&local)?;
+        let edited = std::fs::read(&local)?;
+        if edited == original {
+            eprintln!("{path}: not changed");
+            return Ok(());
+        }
+
+        if let Err(error) = self.write_back(&path, handle, change, &edited) {
+            let dir = dir.keep();
+            eprintln!("the edited file is left at {}", dir.join(name).display());
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Writes the edited file over the remote one, as long as the remote one still has the given
+    /// change attribute. Servers which don't have a change attribute don't get checked.
+    fn write_back(
+        &mut self,
+        path: &RemotePathBuf,
+        handle: FileHandle,
+        change: Option<FileAttribute>,
+        edited: &[u8],
+    ) -> Result<()> {
+        // Only the first write is made conditional, the rest go once it went through
+        let expected = change.into_iter().collect();
+        let first = edited.len().min(self.client.max_write() as usize);
+        let written = match self.client.write_if_unchanged(
+            handle.clone(),
+            expected,
+            0,
+            edited[..first].to_vec(),
+        ) {
+            Err(e) if e.status() == Some(StatusError::NotSame) => {
+                let message = format!("{path} was changed on the server while it was being edited");
+                return Err(io::Error::other(message).into());
+            }
+            res => res?.count as usize,
+        };
+        // The server can take less than all of it, the rest goes like the chunks after it
+        let mut offset = written.min(first);
+        while offset < edited.len() {
+            let count = self
+                .client
+                .write_at(handle.clone(), offset as u64, &edited[offset..])?;
+            if count == 0 {
+                let message = format!("{path}: the server took none of a WRITE at {offset}");
+                return Err(io::Error::new(io::ErrorKind::WriteZero, message).into());
+            }
+            offset += count;
+        }
+        let size = [FileAttribute::Size(edited.len() as u64)]
+            .into_iter()
+            .collect();
+        self.client.set_attr(handle, size)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Going through everything under a remote directory: drawing it like `tree`, adding up the space
+//! it takes like `du` and picking entries out of it like `find`.
+
+use super::{json, Cli, Output};
+use indicatif::BinaryBytes;
+use nfs4::{EnumSet, FileAttributeId, FileAttributes, FileId, FileType, FsId, Time};
+use nfs4_client::{RemotePath, RemotePathBuf, Result, WalkEntry};
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How a number given to `find` is compared, written `+N` for more than N, `-N` for less than N
+/// and just `N` for exactly N.
+#[derive(Clone, Copy, Debug)]
+pub enum Comparison {
+    Less(u64),
+    Exactly(u64),
+    More(u64),
+}
+
+impl Comparison {
+    fn matches(self, n: u64) -> bool {
+        match self {
+            Self::Less(m) => n < m,
+            Self::Exactly(m) => n == m,
+            Self::More(m) => n > m,
+        }
+    }
+}
+
+fn comparison(
+    s: &str,
+    parse: impl FnOnce(&str) -> Option<u64>,
+) -> std::result::Result<Comparison, String> {
+    let (make, number): (fn(u64) -> Comparison, _) = match s.as_bytes().first() {
+        Some(b'+') => (Comparison::More, &s[1..]),
+        Some(b'-') => (Comparison::Less, &s[1..]),
+        _ => (Comparison::Exactly, s),
+    };
+    parse(number)
+        .map(make)
+        .ok_or_else(|| format!("invalid number `{s}`"))
+}
+
+/// A size in bytes, or with a K, M or G after it in those units.
+pub fn size_comparison(s: &str) -> std::result::Result<Comparison, String> {
+    comparison(s, |number| {
+        let (number, multiplier) = match number.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&number[..i], 1 << 10),
+            Some((i, 'M')) => (&number[..i], 1 << 20),
+            Some((i, 'G')) => (&number[..i], 1 << 30),
+            _ => (number, 1),
+        };
+        number.parse::<u64>().ok()?.checked_mul(multiplier)
+    })
+}
+
+/// A number of days.
+pub fn days_comparison(s: &str) -> std::result::Result<Comparison, String> {
+    comparison(s, |number| number.parse().ok())
+}
+
+/// The type the way `find -type` takes it, like `f` for a regular file.
+pub fn type_letter(s: &str) -> std::result::Result<FileType, String> {
+    Ok(match s {
+        "f" => FileType::Regular,
+        "d" => FileType::Directory,
+        "l" => FileType::Link,
+        "b" => FileType::Block,
+        "c" => FileType::Character,
+        "p" => FileType::Fifo,
+        "s" => FileType::Socket,
+        _ => {
+            return Err(format!(
+                "unknown type `{s}`, expected one of f, d, l, b, c, p or s"
+            ))
+        }
+    })
+}
+
+/// What an entry has to be like to be printed by `find`. Ones left as `None` match anything.
+pub struct FindFilters {
+    pub name: Option<String>,
+    pub file_type: Option<FileType>,
+    pub size: Option<Comparison>,
+    pub mtime: Option<Comparison>,
+}
+
+impl FindFilters {
+    fn matches(&self, name: &str, attrs: &FileAttributes, now: i64) -> bool {
+        if let Some(pattern) = &self.name {
+            if !super::glob::matches_including_dot(pattern, name) {
+                return false;
+            }
+        }
+        if let Some(file_type) = &self.file_type {
+            if attrs.get_as(FileAttributeId::Type) != Some(file_type) {
+                return false;
+            }
+        }
+        if let Some(size) = self.size {
+            match attrs.get_as::<u64>(FileAttributeId::Size) {
+                Some(s) if size.matches(*s) => {}
+                _ => return false,
+            }
+        }
+        if let Some(mtime) = self.mtime {
+            // Like `find`, how many whole days ago it was modified
+            match attrs.get_as::<Time>(FileAttributeId::TimeModify) {
+                Some(t) if mtime.matches((now - t.seconds).max(0) as u64 / (24 * 60 * 60)) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// The entries of each directory in name order, keyed by the path of the directory. The root of
+/// the walk is the empty path.
+fn children_by_parent(entries: &[WalkEntry]) -> HashMap<&RemotePath, Vec<&WalkEntry>> {
+    let mut children: HashMap<_, Vec<_>> = HashMap::new();
+    for entry in entries {
+        let parent = entry.path.parent().unwrap_or(RemotePath::new(""));
+        children.entry(parent).or_default().push(entry);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    children
+}
+
+fn is_directory(attrs: &FileAttributes) -> bool {
+    attrs.get_as(FileAttributeId::Type) == Some(&FileType::Directory)
+}
+
+impl Cli {
+    /// Everything under the given path along with its attributes, those asked for and the ones the
+    /// walk needs. Entries that can't be read are reported and left out.
+    fn walk_all(
+        &mut self,
+        path: &RemotePath,
+        attr_request: impl IntoIterator<Item = FileAttributeId>,
+    ) -> Result<(FileAttributes, Vec<WalkEntry>)> {
+        let attr_request: EnumSet<_> = attr_request
+            .into_iter()
+            .chain([FileAttributeId::Type])
+            .collect();
+        let (root, attrs) = self.client.look_up_with_attrs(path, attr_request.clone())?;
+        let mut entries = vec![];
+        if is_directory(&attrs) {
+            for entry in self.client.walk(root, attr_request) {
+                match entry {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => eprintln!("{}: {:?}", path.join(e.path), e.error),
+                }
+            }
+        }
+        Ok((attrs, entries))
+    }
+
+    fn print_tree(
+        &mut self,
+        children: &HashMap<&RemotePath, Vec<&WalkEntry>>,
+        dir: &RemotePath,
+        indent: &str,
+    ) -> Result<()> {
+        let Some(entries) = children.get(dir) else {
+            return Ok(());
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            let name = entry.path.file_name().unwrap_or_default();
+            let branch = if last { "└── " } else { "├── " };
+            if entry.file_type() == Some(&FileType::Link) {
+                let target = self.client.read_link(entry.handle.clone())?;
+                println!("{indent}{branch}{name} -> {target}");
+            } else {
+                println!("{indent}{branch}{name}");
+            }
+            let indent = format!("{indent}{}", if last { "    " } else { "│   " });
+            self.print_tree(children, &entry.path, &indent)?;
+        }
+        Ok(())
+    }
+
+    fn tree_entries(
+        &mut self,
+        children: &HashMap<&RemotePath, Vec<&WalkEntry>>,
+        dir: &RemotePath,
+    ) -> Result<Vec<json::TreeEntry>> {
+        let mut contents = vec![];
+        for entry in children.get(dir).into_iter().flatten() {
+            let target = match entry.file_type() {
+                Some(FileType::Link) => Some(self.client.read_link(entry.handle.clone())?),
+                _ => None,
+            };
+            contents.push(json::TreeEntry {
+                name: entry.path.file_name().unwrap_or_default().into(),
+                file_type: entry.file_type().cloned(),
+                target,
+                contents: self.tree_entries(children, &entry.path)?,
+            });
+        }
+        Ok(contents)
+    }
+
+    /// Prints everything under the path as a tree, with the entries of each directory in name
+    /// order, and then how many directories and files there were.
+    pub fn tree(&mut self, path: RemotePathBuf) -> Result<()> {
+        let (_, entries) = self.walk_all(&path, [])?;
+        let children = children_by_parent(&entries);
+        let directories = entries.iter().filter(|e| is_directory(&e.attrs)).count();
+        let files = entries.len() - directories;
+        if self.output == Output::Json {
+            let contents = self.tree_entries(&children, RemotePath::new(""))?;
+            json::print(&json::Tree {
+                path: path.into_string(),
+                contents,
+                directories,
+                files,
+            });
+            return Ok(());
+        }
+
+        println!("{path}");
+        self.print_tree(&children, RemotePath::new(""), "")?;
+        let plural =
+            |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+        println!(
+            "\n{}, {}",
+            plural(directories, "directory", "directories"),
+            plural(files, "file", "files")
+        );
+        Ok(())
+    }
+
+    /// Prints how much space each directory under the path takes along with everything in it,
+    /// after the directories in it, and last the total for the path itself. With `summarize` only
+    /// the total is printed. A file with several links is only counted the first time it is seen.
+    pub fn du(&mut self, path: RemotePathBuf, summarize: bool) -> Result<()> {
+        let (root_attrs, entries) = self.walk_all(&path, [FileAttributeId::SpaceUsed])?;
+        let space_used = |attrs: &FileAttributes| {
+            attrs
+                .get_as::<u64>(FileAttributeId::SpaceUsed)
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let root = RemotePath::new("");
+        let mut totals = HashMap::from([(root, space_used(&root_attrs))]);
+        let mut seen = HashSet::new();
+        for entry in &entries {
+            if !is_directory(&entry.attrs) {
+                let fs_id = entry.attrs.get_as::<FsId>(FileAttributeId::FsId);
+                let file_id = entry.attrs.get_as::<FileId>(FileAttributeId::FileId);
+                if let (Some(fs_id), Some(file_id)) = (fs_id, file_id) {
+                    if !seen.insert((fs_id.major, fs_id.minor, file_id.0)) {
+                        continue;
+                    }
+                }
+            }
+            let space = space_used(&entry.attrs);
+            let mut dir = Some(&*entry.path);
+            while let Some(d) = dir {
+                *totals.entry(d).or_default() += space;
+                dir = d.parent();
+            }
+        }
+
+        // The directories in the order they are printed, after the ones in them
+        fn dir_totals(
+            children: &HashMap<&RemotePath, Vec<&WalkEntry>>,
+            totals: &HashMap<&RemotePath, u64>,
+            path: &RemotePath,
+            dir: &RemotePath,
+            rows: &mut Vec<(RemotePathBuf, u64)>,
+        ) {
+            for entry in children.get(dir).into_iter().flatten() {
+                if is_directory(&entry.attrs) {
+                    dir_totals(children, totals, path, &entry.path, rows);
+                    rows.push((path.join(&entry.path), totals[&*entry.path]));
+                }
+            }
+        }
+        let mut rows = vec![];
+        if !summarize {
+            dir_totals(
+                &children_by_parent(&entries),
+                &totals,
+                &path,
+                root,
+                &mut rows,
+            );
+        }
+        rows.push((path.clone(), totals[root]));
+
+        if self.output == Output::Json {
+            let rows: Vec<_> = rows
+                .into_iter()
+                .map(|(path, size)| json::DiskUsage {
+                    path: path.into_string(),
+                    size,
+                })
+                .collect();
+            json::print(&rows);
+            return Ok(());
+        }
+        for (path, total) in rows {
+            println!("{}\t{path}", BinaryBytes(total));
+        }
+        Ok(())
+    }
+
+    /// Prints the path and everything under it which matches the filters, in the order they are
+    /// found.
+    pub fn find(&mut self, path: RemotePathBuf, filters: &FindFilters) -> Result<()> {
+        let attr_request = [FileAttributeId::Size, FileAttributeId::TimeModify];
+        let (root_attrs, entries) = self.walk_all(&path, attr_request)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+
+        let mut found = vec![];
+        let root_name = path.file_name().unwrap_or("/");
+        if filters.matches(root_name, &root_attrs, now) {
+            found.push(path.clone());
+        }
+        for entry in &entries {
+            let name = entry.path.file_name().unwrap_or_default();
+            if filters.matches(name, &entry.attrs, now) {
+                found.push(path.join(&entry.path));
+            }
+        }
+
+        if self.output == Output::Json {
+            let found: Vec<_> = found.into_iter().map(RemotePathBuf::into_string).collect();
+            json::print(&found);
+            return Ok(());
+        }
+        for path in found {
+            println!("{path}");
+        }
+        Ok(())
+    }
+}
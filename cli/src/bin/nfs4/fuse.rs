@@ -0,0 +1,617 @@
+// Copyright 2023 Remi Bernotavicius
+
+use fuser::{
+    FileAttr, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use nfs4::{FileAttribute, FileAttributeId, FileAttributes, FileHandle, FileId, SetTime};
+use nfs4_client::{Client, Error, RemotePath, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::net::TcpStream;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use sun_rpc_client::Transport;
+
+const TTL: Duration = Duration::from_secs(1);
+const ROOT_INODE: u64 = 1;
+
+fn errno(error: &Error) -> libc::c_int {
+    use nfs4::StatusError::*;
+
+    match error.status() {
+        Some(status) => match status {
+            Perm => libc::EPERM,
+            NoEnt => libc::ENOENT,
+            NxIo => libc::ENXIO,
+            Access => libc::EACCES,
+            Exist => libc::EEXIST,
+            XDev => libc::EXDEV,
+            NotDir => libc::ENOTDIR,
+            Isdir => libc::EISDIR,
+            Inval => libc::EINVAL,
+            FBig => libc::EFBIG,
+            NoSpc => libc::ENOSPC,
+            RoFs => libc::EROFS,
+            MLink => libc::EMLINK,
+            NameTooLong => libc::ENAMETOOLONG,
+            NotEmpty => libc::ENOTEMPTY,
+            DQuot => libc::EDQUOT,
+            Stale | BadHandle => libc::ESTALE,
+            NotSupported => libc::ENOTSUP,
+            _ => libc::EIO,
+        },
+        None => libc::EIO,
+    }
+}
+
+fn file_type(file_type: Option<&nfs4::FileType>) -> fuser::FileType {
+    match file_type {
+        Some(nfs4::FileType::Directory) => fuser::FileType::Directory,
+        Some(nfs4::FileType::Link) => fuser::FileType::Symlink,
+        Some(nfs4::FileType::Block) => fuser::FileType::BlockDevice,
+        Some(nfs4::FileType::Character) => fuser::FileType::CharDevice,
+        Some(nfs4::FileType::Socket) => fuser::FileType::Socket,
+        Some(nfs4::FileType::Fifo) => fuser::FileType::NamedPipe,
+        _ => fuser::FileType::RegularFile,
+    }
+}
+
+/// The name as the server takes it. Names which aren't UTF-8 can't be sent, so they are invalid.
+fn utf8(name: &OsStr) -> Result<&str> {
+    name.to_str().ok_or(nfs4::StatusError::Inval.into())
+}
+
+fn set_time(time: TimeOrNow) -> SetTime {
+    match time {
+        TimeOrNow::Now => SetTime::SetToServerTime,
+        TimeOrNow::SpecificTime(time) => SetTime::SetToClientTime(time.into()),
+    }
+}
+
+/// An entry of an open directory, as given to the kernel.
+struct DirEntry {
+    inode: u64,
+    kind: fuser::FileType,
+    name: String,
+}
+
+/// A FUSE filesystem which forwards everything to an NFS server. Inode numbers are handed out as
+/// files are looked up, and remembered for as long as the filesystem is mounted. Directories are
+/// listed once when opened, and the kernel reads the listing from there a bit at a time.
+struct NfsFilesystem<TransportT: Transport> {
+    client: Client<TransportT>,
+    handles: HashMap<u64, FileHandle>,
+    inodes: HashMap<u64, u64>,
+    next_inode: u64,
+    // The listings of the open directories, by the file handle the kernel has for them
+    dirs: HashMap<u64, Vec<DirEntry>>,
+    next_dir: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl<TransportT: Transport> NfsFilesystem<TransportT> {
+    /// Serves the given directory as the root. Files whose owner can't be mapped to an ID appear
+    /// to be owned by the given user and group.
+    fn new(mut client: Client<TransportT>, root: FileHandle, uid: u32, gid: u32) -> Result<Self> {
+        let root_attrs = client.get_attr(root.clone())?.object_attributes;
+        let inodes = root_attrs
+            .get_as::<FileId>(FileAttributeId::FileId)
+            .map(|id| (id.0, ROOT_INODE))
+            .into_iter()
+            .collect();
+        Ok(Self {
+            client,
+            handles: HashMap::from([(ROOT_INODE, root)]),
+            inodes,
+            next_inode: ROOT_INODE + 1,
+            dirs: HashMap::new(),
+            next_dir: 1,
+            uid,
+            gid,
+        })
+    }
+
+    fn handle(&self, inode: u64) -> Result<FileHandle> {
+        self.handles
+            .get(&inode)
+            .cloned()
+            .ok_or(nfs4::StatusError::Stale.into())
+    }
+
+    fn inode_for(&mut self, handle: FileHandle, attrs: &FileAttributes) -> u64 {
+        let Some(file_id) = attrs.get_as::<FileId>(FileAttributeId::FileId) else {
+            let inode = self.next_inode;
+            self.next_inode += 1;
+            self.handles.insert(inode, handle);
+            return inode;
+        };
+        let inode = *self.inodes.entry(file_id.0).or_insert_with(|| {
+            self.next_inode += 1;
+            self.next_inode - 1
+        });
+        self.handles.insert(inode, handle);
+        inode
+    }
+
+    fn file_attr(&self, inode: u64, attrs: &FileAttributes) -> FileAttr {
+        let time = |id| {
+            attrs
+                .get_as(id)
+                .and_then(nfs4::Time::to_system_time)
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        };
+        let size = *attrs.get_as(FileAttributeId::Size).unwrap_or(&0);
+        let id_mapper = self.client.id_mapper();
+        let uid = attrs
+            .get_as::<String>(FileAttributeId::Owner)
+            .and_then(|owner| id_mapper.uid(owner))
+            .unwrap_or(self.uid);
+        let gid = attrs
+            .get_as::<String>(FileAttributeId::OwnerGroup)
+            .and_then(|group| id_mapper.gid(group))
+            .unwrap_or(self.gid);
+        FileAttr {
+            ino: inode,
+            size,
+            blocks: attrs
+                .get_as::<u64>(FileAttributeId::SpaceUsed)
+                .map_or(size.div_ceil(512), |used| used.div_ceil(512)),
+            atime: time(FileAttributeId::TimeAccess),
+            mtime: time(FileAttributeId::TimeModify),
+            ctime: time(FileAttributeId::TimeMetadata),
+            crtime: time(FileAttributeId::TimeCreate),
+            kind: file_type(attrs.get_as(FileAttributeId::Type)),
+            perm: attrs
+                .get_as::<nfs4::Mode>(FileAttributeId::Mode)
+                .map_or(0o644, |m| (m.0 & 0o7777) as u16),
+            nlink: *attrs.get_as(FileAttributeId::NumLinks).unwrap_or(&1),
+            uid,
+            gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    fn get_attr(&mut self, inode: u64) -> Result<FileAttr> {
+        let handle = self.handle(inode)?;
+        let attrs = self.client.get_attr(handle)?.object_attributes;
+        Ok(self.file_attr(inode, &attrs))
+    }
+
+    fn entry(&mut self, handle: FileHandle) -> Result<FileAttr> {
+        let attrs = self.client.get_attr(handle.clone())?.object_attributes;
+        let inode = self.inode_for(handle, &attrs);
+        Ok(self.file_attr(inode, &attrs))
+    }
+
+    fn look_up(&mut self, parent: u64, name: &OsStr) -> Result<FileAttr> {
+        let parent = self.handle(parent)?;
+        let handle = self.client.look_up_from(parent, utf8(name)?)?;
+        self.entry(handle)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_attr(
+        &mut self,
+        inode: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+    ) -> Result<FileAttr> {
+        let handle = self.handle(inode)?;
+        let attrs: FileAttributes = [
+            mode.map(|m| FileAttribute::Mode(nfs4::Mode(m & 0o7777))),
+            uid.map(|u| FileAttribute::Owner(self.client.id_mapper().owner(u))),
+            gid.map(|g| FileAttribute::OwnerGroup(self.client.id_mapper().group(g))),
+            size.map(FileAttribute::Size),
+            atime.map(|t| FileAttribute::TimeAccessSet(set_time(t))),
+            mtime.map(|t| FileAttribute::TimeModifySet(set_time(t))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        self.client.set_attr(handle, attrs)?;
+        self.get_attr(inode)
+    }
+
+    /// Lists the directory, returning the file handle the kernel reads the listing with.
+    fn open_dir(&mut self, inode: u64) -> Result<u64> {
+        let handle = self.handle(inode)?;
+        let attr_request = [
+            FileAttributeId::FileHandle,
+            FileAttributeId::FileId,
+            FileAttributeId::Type,
+        ]
+        .into_iter()
+        .collect();
+        let entries: Vec<_> = self
+            .client
+            .read_dir(handle, attr_request)
+            .collect::<Result<_>>()?;
+
+        let mut listing = vec![];
+        for mut entry in entries {
+            let kind = file_type(entry.attrs.get_as(FileAttributeId::Type));
+            let entry_inode = match entry.attrs.remove_as(FileAttributeId::FileHandle) {
+                Some(handle) => self.inode_for(handle, &entry.attrs),
+                None => self.look_up(inode, entry.name.as_ref())?.ino,
+            };
+            listing.push(DirEntry {
+                inode: entry_inode,
+                kind,
+                name: entry.name,
+            });
+        }
+        let fh = self.next_dir;
+        self.next_dir += 1;
+        self.dirs.insert(fh, listing);
+        Ok(fh)
+    }
+
+    fn read(&mut self, inode: u64, offset: i64, size: u32) -> Result<Vec<u8>> {
+        let handle = self.handle(inode)?;
+        let mut data = vec![];
+        while data.len() < size as usize {
+            let count = (size as usize - data.len()).min(self.client.max_read() as usize);
+            let res = self.client.read(
+                handle.clone(),
+                offset as u64 + data.len() as u64,
+                count as u32,
+            )?;
+            let eof = res.eof || res.data.is_empty();
+            data.extend(res.data);
+            if eof {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    fn write(&mut self, inode: u64, offset: i64, data: &[u8]) -> Result<u32> {
+        let handle = self.handle(inode)?;
+        let mut written = 0;
+        while written < data.len() {
+            let end = data.len().min(written + self.client.max_write() as usize);
+            let res = self.client.write(
+                handle.clone(),
+                offset as u64 + written as u64,
+                data[written..end].to_vec(),
+            )?;
+            if res.count == 0 {
+                break;
+            }
+            written += res.count as usize;
+        }
+        Ok(written as u32)
+    }
+
+    fn create(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr> {
+        let parent = self.handle(parent)?;
+        let handle = self.client.create_file(parent, utf8(name)?)?.handle.clone();
+        let attrs = [FileAttribute::Mode(nfs4::Mode(mode & 0o7777))]
+            .into_iter()
+            .collect();
+        self.client.set_attr(handle.clone(), attrs)?;
+        self.entry(handle)
+    }
+
+    fn make_dir(&mut self, parent: u64, name: &OsStr, mode: u32) -> Result<FileAttr> {
+        let parent = self.handle(parent)?;
+        let attrs = [FileAttribute::Mode(nfs4::Mode(mode & 0o7777))]
+            .into_iter()
+            .collect();
+        let handle = self.client.create_directory(parent, utf8(name)?, attrs)?;
+        self.entry(handle)
+    }
+
+    fn remove(&mut self, parent: u64, name: &OsStr) -> Result<()> {
+        let parent = self.handle(parent)?;
+        self.client.remove(parent, utf8(name)?)?;
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+    ) -> Result<()> {
+        let parent = self.handle(parent)?;
+        let new_parent = self.handle(new_parent)?;
+        self.client
+            .rename(parent, new_parent, utf8(name)?, utf8(new_name)?)?;
+        Ok(())
+    }
+}
+
+impl<TransportT: Transport> Filesystem for NfsFilesystem<TransportT> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self.look_up(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.get_attr(ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        match self.set_attr(ino, mode, uid, gid, size, atime, mtime) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        match self.open_dir(ino) {
+            Ok(fh) => reply.opened(fh, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(listing) = self.dirs.get(&fh) else {
+            reply.error(libc::EBADF);
+            return;
+        };
+        // The offset of each entry is where the one after it is
+        let start = usize::try_from(offset).unwrap_or(0);
+        for (i, entry) in listing.iter().enumerate().skip(start) {
+            if reply.add(entry.inode, i as i64 + 1, entry.kind, &entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.dirs.remove(&fh);
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match NfsFilesystem::read(self, ino, offset, size) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match NfsFilesystem::write(self, ino, offset, data) {
+            Ok(written) => reply.written(written),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match NfsFilesystem::create(self, parent, name, mode) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match self.make_dir(parent, name, mode) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        match NfsFilesystem::rename(self, parent, name, newparent, newname) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+}
+
+pub fn mount(mut client: Client<TcpStream>, path: &RemotePath, mountpoint: &Path) -> Result<()> {
+    client.start_default_lease_renewal();
+    let root = client.look_up(path)?;
+
+    // Files whose owner isn't numeric appear to be owned by whoever owns the mountpoint
+    let metadata = std::fs::metadata(mountpoint)?;
+    let fs = NfsFilesystem::new(client, root, metadata.uid(), metadata.gid())?;
+    let options = [
+        MountOption::FSName(format!("nfs4:{path}")),
+        MountOption::Subtype("nfs4".into()),
+    ];
+    fuser::mount2(fs, mountpoint, &options)?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn in_memory_fs(files: nfs4_server::memory::MemoryFs) -> NfsFilesystem<nfs4_server::pipe::Pipe> {
+    let server = nfs4_server::Server::with_file_system(files);
+    let mut client = Client::new(server.connect_in_process()).unwrap();
+    let root = client.look_up("/").unwrap();
+    NfsFilesystem::new(client, root, 1000, 1000).unwrap()
+}
+
+#[test]
+fn look_up() {
+    let mut files = nfs4_server::memory::MemoryFs::new();
+    files.make_dir("dir").unwrap();
+    files.write_file("dir/file", "hello").unwrap();
+    let mut fs = in_memory_fs(files);
+
+    let dir = fs.look_up(ROOT_INODE, "dir".as_ref()).unwrap();
+    assert_eq!(dir.kind, fuser::FileType::Directory);
+    let file = fs.look_up(dir.ino, "file".as_ref()).unwrap();
+    assert_eq!(file.kind, fuser::FileType::RegularFile);
+    assert_eq!(file.size, 5);
+    // The same file gets the same inode
+    assert_eq!(fs.look_up(dir.ino, "file".as_ref()).unwrap().ino, file.ino);
+
+    let error = fs.look_up(dir.ino, "missing".as_ref()).unwrap_err();
+    assert_eq!(errno(&error), libc::ENOENT);
+}
+
+#[test]
+fn names_not_utf8() {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let mut fs = in_memory_fs(nfs4_server::memory::MemoryFs::new());
+    let name = OsStr::from_bytes(b"\xff");
+    let error = fs.look_up(ROOT_INODE, name).unwrap_err();
+    assert_eq!(errno(&error), libc::EINVAL);
+    let error = fs.create(ROOT_INODE, name, 0o644).unwrap_err();
+    assert_eq!(errno(&error), libc::EINVAL);
+    let error = fs
+        .rename(ROOT_INODE, "a".as_ref(), ROOT_INODE, name)
+        .unwrap_err();
+    assert_eq!(errno(&error), libc::EINVAL);
+}
+
+#[test]
+fn read_dir() {
+    let mut files = nfs4_server::memory::MemoryFs::new();
+    files.make_dir("dir").unwrap();
+    for i in 0..3 {
+        files.write_file(&format!("dir/file{i}"), "").unwrap();
+    }
+    let mut fs = in_memory_fs(files);
+
+    let dir = fs.look_up(ROOT_INODE, "dir".as_ref()).unwrap();
+    let fh = fs.open_dir(dir.ino).unwrap();
+    let mut entries: Vec<_> = fs.dirs[&fh]
+        .iter()
+        .map(|e| (e.name.clone(), e.inode, e.kind))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    let names: Vec<_> = entries.iter().map(|(name, ..)| name.as_str()).collect();
+    assert_eq!(names, ["file0", "file1", "file2"]);
+    for (name, inode, kind) in entries {
+        assert_eq!(kind, fuser::FileType::RegularFile);
+        assert_eq!(fs.look_up(dir.ino, name.as_ref()).unwrap().ino, inode);
+    }
+
+    // Each open of the directory has a listing of its own
+    let other_fh = fs.open_dir(dir.ino).unwrap();
+    assert_ne!(other_fh, fh);
+}
+
+#[test]
+fn read_and_write() {
+    let mut files = nfs4_server::memory::MemoryFs::new();
+    files.write_file("file", "hello").unwrap();
+    let mut fs = in_memory_fs(files);
+
+    let file = fs.look_up(ROOT_INODE, "file".as_ref()).unwrap();
+    assert_eq!(fs.read(file.ino, 1, 3).unwrap(), b"ell");
+    // Reading past the end gives what is there
+    assert_eq!(fs.read(file.ino, 3, 100).unwrap(), b"lo");
+
+    assert_eq!(fs.write(file.ino, 5, b" world").unwrap(), 6);
+    assert_eq!(fs.read(file.ino, 0, 100).unwrap(), b"hello world");
+    assert_eq!(fs.get_attr(file.ino).unwrap().size, 11);
+
+    let created = fs.create(ROOT_INODE, "new".as_ref(), 0o600).unwrap();
+    assert_eq!(created.perm, 0o600);
+    assert_eq!(fs.write(created.ino, 0, b"data").unwrap(), 4);
+    assert_eq!(fs.read(created.ino, 0, 4).unwrap(), b"data");
+}
@@ -0,0 +1,142 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Wildcards in remote paths, like the shell expands them in local ones. `*` matches any number
+//! of characters, `?` any one and `[...]` any one of those in the brackets, with ranges like
+//! `a-z` and `!` or `^` first to negate it. Neither matches a leading `.` of a name.
+
+pub fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Matches the characters in brackets against the given one, returning whether it did and the
+/// rest of the pattern after the closing bracket. A `[` without a closing bracket is taken as it
+/// is.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut class) = match pattern {
+        ['!' | '^', rest @ ..] => (true, rest),
+        rest => (false, rest),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match class {
+            // A `]` first is one of the characters rather than the end
+            [']', rest @ ..] if !first => return Some((matched != negated, rest)),
+            [low, '-', high, rest @ ..] if *high != ']' => {
+                matched |= (*low..=*high).contains(&c);
+                class = rest;
+            }
+            [ch, rest @ ..] => {
+                matched |= *ch == c;
+                class = rest;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
+
+/// Matches one character of the name against the start of the pattern, which isn't a `*`,
+/// returning how much of the pattern it took if it matched.
+fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern {
+        ['?', ..] => Some(1),
+        ['[', class @ ..] => match match_class(class, c) {
+            Some((matched, rest)) => matched.then_some(pattern.len() - rest.len()),
+            None => (c == '[').then_some(1),
+        },
+        [p, ..] => (*p == c).then_some(1),
+        [] => None,
+    }
+}
+
+/// Only the last `*` is ever gone back to, taking one more character of the name each time, since
+/// whatever an earlier one would take instead the last one can take too. So this takes at most the
+/// length of the pattern times that of the name, where trying every way would be exponential in the
+/// number of `*`s.
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // The pattern after the last `*`, and the name from where it stopped taking characters
+    let mut backtrack = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            backtrack = Some((p, n));
+        } else if let Some(taken) = match_one(&pattern[p..], name[n]) {
+            p += taken;
+            n += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p;
+            n = star_n + 1;
+            backtrack = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|p| *p == '*')
+}
+
+/// Whether the name matches the pattern, which is for one component of a path.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    matches_including_dot(pattern, name)
+}
+
+/// Like `matches`, but a leading `.` can be matched by wildcards too, like `find -name` does.
+pub fn matches_including_dot(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_chars(&pattern, &name)
+}
+
+#[test]
+fn wildcards() {
+    assert!(matches("*.txt", "notes.txt"));
+    assert!(!matches("*.txt", "notes.txt.bak"));
+    assert!(matches("a?c", "abc"));
+    assert!(!matches("a?c", "ac"));
+    assert!(matches("*", ""));
+    assert!(matches("a*b*c", "aXbYbZc"));
+    assert!(!matches("a*b*c", "aXbYbZ"));
+    assert!(matches("**a", "ba"));
+    assert!(matches("ü*", "über"));
+}
+
+#[test]
+fn classes() {
+    assert!(matches("[abc]x", "bx"));
+    assert!(!matches("[abc]x", "dx"));
+    assert!(matches("[a-c][0-9]", "c7"));
+    assert!(matches("[!a-c]", "d"));
+    assert!(!matches("[^a-c]", "b"));
+    assert!(matches("[]]", "]"));
+    assert!(matches("[a-]", "-"));
+    // Without a closing bracket it is taken as it is
+    assert!(matches("[ab", "[ab"));
+    assert!(!matches("[ab", "a"));
+}
+
+#[test]
+fn leading_dot() {
+    assert!(!matches("*", ".hidden"));
+    assert!(!matches("?hidden", ".hidden"));
+    assert!(matches(".*", ".hidden"));
+    assert!(matches_including_dot("*", ".hidden"));
+}
+
+#[test]
+fn many_stars() {
+    // Trying every way of splitting the name between the `*`s would take forever
+    let name = "a".repeat(100);
+    assert!(!matches("*a*a*a*a*a*a*a*a*a*a*a*a*b", &name));
+    assert!(matches("*a*a*a*a*a*a*a*a*a*a*a*a", &name));
+}
+
+#[test]
+fn finds_wildcards() {
+    assert!(has_wildcards("*.rs"));
+    assert!(has_wildcards("file[12]"));
+    assert!(!has_wildcards("plain.txt"));
+}
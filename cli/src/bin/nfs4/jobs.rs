@@ -0,0 +1,68 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Transferring many files at once. The calls of one client take turns, so every job but the
+//! first gets a client, and with it a connection and session, of its own.
+
+use super::Cli;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use nfs4_client::{Client, Result};
+use std::net::TcpStream;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Cli {
+    /// Calls `work` for each item, with up to `jobs` of them going at once. Each job gets a line
+    /// of the given progress display, for showing the file it is on. Once a job fails the others
+    /// finish what they are doing and the first error is returned.
+    pub fn run_jobs<ItemT: Send>(
+        &mut self,
+        jobs: usize,
+        items: Vec<ItemT>,
+        progress: &MultiProgress,
+        work: impl Fn(&mut Client<TcpStream>, ItemT, &ProgressBar) -> Result<()> + Sync,
+    ) -> Result<()> {
+        let jobs = jobs.clamp(1, items.len().max(1));
+        let mut others = (1..jobs)
+            .map(|_| (self.new_client)())
+            .collect::<Result<Vec<_>>>()?;
+        // They all share one limit
+        for client in &mut others {
+            client.set_bandwidth_limit(self.client.bandwidth_limit().cloned());
+        }
+        let queue = Mutex::new(items.into_iter());
+        let style = ProgressStyle::with_template(
+            "{msg:40!} {bar:30} {binary_bytes:>10}/{binary_total_bytes:<10}",
+        )
+        .unwrap();
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = std::iter::once(&mut self.client)
+                .chain(&mut others)
+                .map(|client| {
+                    let (queue, work) = (&queue, &work);
+                    let bar = progress.add(ProgressBar::new(0).with_style(style.clone()));
+                    scope.spawn(move || {
+                        let mut res = Ok(());
+                        loop {
+                            // Not in the condition of a `while let`, which would keep it locked
+                            let item = lock(queue).next();
+                            let Some(item) = item else { break };
+                            res = work(client, item, &bar);
+                            if res.is_err() {
+                                // Leave nothing for the other jobs to start on
+                                lock(queue).by_ref().for_each(drop);
+                                break;
+                            }
+                        }
+                        bar.finish_and_clear();
+                        res
+                    })
+                })
+                .collect();
+            threads.into_iter().try_for_each(|t| t.join().unwrap())
+        })
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Output for `--output json`. The `Serialize` impls of the `nfs4` types give their XDR encoding,
+//! so what gets printed is built here instead, in a shape meant for scripts to read, with the
+//! `nfs4` types in it given in their `nfs4::readable` form.
+
+use nfs4::{EnumSet, FileAttributeId, FileAttributes, FileHandle, FileType};
+use serde::Serialize;
+
+/// One entry of a directory listing.
+#[derive(Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(
+        rename = "type",
+        with = "nfs4::readable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub file_type: Option<FileType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbolic_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<String>,
+    #[serde(with = "nfs4::readable", skip_serializing_if = "Option::is_none")]
+    pub handle: Option<FileHandle>,
+}
+
+#[derive(Serialize)]
+pub struct Usage {
+    pub path: String,
+    pub size: u64,
+    pub used: u64,
+    pub avail: u64,
+    pub files: u64,
+    pub files_free: u64,
+}
+
+#[derive(Serialize)]
+pub struct Quota {
+    pub path: String,
+    pub used: Option<u64>,
+    pub soft_avail: Option<u64>,
+    pub hard_avail: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ServerInfo {
+    #[serde(with = "nfs4::readable")]
+    pub supported_attrs: EnumSet<FileAttributeId>,
+    #[serde(with = "nfs4::readable")]
+    pub supported_attrs_exclusive_create: Option<EnumSet<FileAttributeId>>,
+    pub max_read: u64,
+    pub max_write: u64,
+    pub lease_time: u64,
+    pub fh_expire_type: Vec<String>,
+    pub acl_support: Option<Vec<String>>,
+}
+
+/// How much space a directory takes along with everything in it, in bytes.
+#[derive(Serialize)]
+pub struct DiskUsage {
+    pub path: String,
+    pub size: u64,
+}
+
+/// One entry of `tree`, with the entries in it if it is a directory.
+#[derive(Serialize)]
+pub struct TreeEntry {
+    pub name: String,
+    #[serde(
+        rename = "type",
+        with = "nfs4::readable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub file_type: Option<FileType>,
+    /// Where a symbolic link points to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contents: Vec<TreeEntry>,
+}
+
+#[derive(Serialize)]
+pub struct Tree {
+    pub path: String,
+    pub contents: Vec<TreeEntry>,
+    pub directories: usize,
+    pub files: usize,
+}
+
+#[derive(Serialize)]
+pub struct Checksum {
+    pub path: String,
+    pub algorithm: String,
+    pub checksum: String,
+}
+
+/// How long calls took, in microseconds, in the order they were made.
+#[derive(Serialize)]
+pub struct RoundTrips {
+    pub times_us: Vec<u64>,
+    #[serde(flatten)]
+    pub summary: Option<RoundTripSummary>,
+}
+
+#[derive(Serialize)]
+pub struct RoundTripSummary {
+    pub min_us: u64,
+    pub avg_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Serialize)]
+pub struct Ping {
+    pub nfs: RoundTrips,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_mapper: Option<RoundTrips>,
+    /// Why the port mapper couldn't be pinged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port_mapper_error: Option<String>,
+    /// The minor versions of NFSv4 the server speaks, with `--probe`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minor_versions: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_flavors: Option<Vec<String>>,
+}
+
+/// Latency percentiles, in microseconds.
+#[derive(Serialize)]
+pub struct Latency {
+    pub min: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p99_9: u64,
+    pub max: u64,
+}
+
+/// What `bench` measured for one kind of operation.
+#[derive(Serialize)]
+pub struct BenchResult {
+    pub ops: usize,
+    pub ops_per_sec: f64,
+    pub bytes: u64,
+    pub bytes_per_sec: f64,
+    pub entries: u64,
+    pub entries_per_sec: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_us: Option<Latency>,
+}
+
+pub fn print(value: &impl Serialize) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// The attributes as an object keyed by their names, like `time_modify`.
+pub fn print_attrs(attrs: &FileAttributes) {
+    print(&nfs4::readable::serialize(attrs, serde_json::value::Serializer).unwrap());
+}
@@ -0,0 +1,196 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Printing directory listings the way `ls` does.
+
+use super::{json, mode::symbolic_mode};
+use chrono::{offset::TimeZone as _, Local};
+use clap::{Args, ValueEnum};
+use indicatif::BinaryBytes;
+use nfs4::{EnumSet, FileAttributeId, FileType};
+use nfs4_client::{DirEntryInfo, IdMapper};
+use std::io::IsTerminal as _;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorWhen {
+    Always,
+    #[default]
+    Auto,
+    Never,
+}
+
+#[derive(Args, Clone, Default)]
+pub struct ListOptions {
+    /// Print the mode, number of links, owner, group, size and modify time of each entry
+    #[arg(short, long)]
+    pub long: bool,
+    /// Print sizes in powers of 1024, like 1.50 KiB
+    #[arg(short = 'h', long)]
+    pub human_readable: bool,
+    /// Include entries whose names start with `.`
+    #[arg(short, long)]
+    pub all: bool,
+    /// Sort by modify time, newest first
+    #[arg(short = 't', conflicts_with = "sort_size")]
+    pub sort_time: bool,
+    /// Sort by size, largest first
+    #[arg(short = 'S')]
+    pub sort_size: bool,
+    /// Print the file handle of each entry before its name
+    #[arg(short = 'i', long)]
+    pub handles: bool,
+    /// Color names by file type
+    #[arg(long, value_enum, default_value_t = ColorWhen::Auto)]
+    pub color: ColorWhen,
+    /// Print help, which is only a long option since -h is taken
+    #[arg(long, action = clap::ArgAction::Help)]
+    help: Option<bool>,
+}
+
+impl ListOptions {
+    pub fn long() -> Self {
+        Self {
+            long: true,
+            ..Self::default()
+        }
+    }
+
+    /// The attributes a listing with these options needs.
+    pub fn attr_request(&self) -> EnumSet<FileAttributeId> {
+        let long = [
+            FileAttributeId::NumLinks,
+            FileAttributeId::Owner,
+            FileAttributeId::OwnerGroup,
+        ];
+        [
+            FileAttributeId::Type,
+            FileAttributeId::Mode,
+            FileAttributeId::Size,
+            FileAttributeId::TimeModify,
+        ]
+        .into_iter()
+        .chain(long.into_iter().filter(|_| self.long))
+        .chain(self.handles.then_some(FileAttributeId::FileHandle))
+        .collect()
+    }
+}
+
+/// The local ID the owner or group maps to, or the name the server gave if it maps to none.
+fn local_id(name: Option<&str>, map: impl Fn(&str) -> Option<u32>) -> String {
+    match name {
+        Some(name) => map(name).map_or(name.into(), |id| id.to_string()),
+        None => "-".into(),
+    }
+}
+
+fn modify_time(entry: &DirEntryInfo) -> Option<(i64, u32)> {
+    let time = entry.modify_time.as_ref()?;
+    Some((time.seconds, time.nseconds))
+}
+
+fn size(entry: &DirEntryInfo) -> u64 {
+    entry.size.unwrap_or(0)
+}
+
+/// The name with the color `ls` gives its file type.
+fn colored_name(entry: &DirEntryInfo) -> String {
+    let executable = entry.mode.is_some_and(|m| m & 0o111 != 0);
+    let color = match entry.file_type {
+        Some(FileType::Directory) => "01;34",
+        Some(FileType::Link) => "01;36",
+        Some(FileType::Block | FileType::Character) => "01;33",
+        Some(FileType::Socket | FileType::Fifo) => "01;35",
+        Some(FileType::Regular) if executable => "01;32",
+        _ => return entry.name.clone(),
+    };
+    format!("\x1b[{color}m{}\x1b[0m", entry.name)
+}
+
+fn modify_time_str(entry: &DirEntryInfo) -> String {
+    let Some(time) = entry.modify_time.as_ref().and_then(|t| t.to_date_time()) else {
+        return "-".into();
+    };
+    Local
+        .from_utc_datetime(&time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Drops hidden entries unless `-a` was given and puts the rest in the order asked for.
+fn filter_and_sort(entries: &mut Vec<DirEntryInfo>, options: &ListOptions) {
+    if !options.all {
+        entries.retain(|e| !e.name.starts_with('.'));
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    if options.sort_time {
+        entries.sort_by_key(|e| std::cmp::Reverse(modify_time(e)));
+    } else if options.sort_size {
+        entries.sort_by_key(|e| std::cmp::Reverse(size(e)));
+    }
+}
+
+pub fn print_listing(
+    mut entries: Vec<DirEntryInfo>,
+    id_mapper: &dyn IdMapper,
+    options: &ListOptions,
+) {
+    filter_and_sort(&mut entries, options);
+
+    let color = match options.color {
+        ColorWhen::Always => true,
+        ColorWhen::Auto => std::io::stdout().is_terminal(),
+        ColorWhen::Never => false,
+    };
+    for e in &entries {
+        let mut line = String::new();
+        if options.handles {
+            let handle = e.handle.as_ref().map_or("-".into(), |h| h.to_string());
+            line += &format!("{handle} ");
+        }
+        if options.long {
+            let mode = symbolic_mode(e.file_type.as_ref(), e.mode.unwrap_or(0));
+            let num_links = e.num_links.unwrap_or(0);
+            let owner = local_id(e.owner.as_deref(), |o| id_mapper.uid(o));
+            let group = local_id(e.owner_group.as_deref(), |g| id_mapper.gid(g));
+            let size = match options.human_readable {
+                true => BinaryBytes(size(e)).to_string(),
+                false => size(e).to_string(),
+            };
+            let modify = modify_time_str(e);
+            line += &format!("{mode} {num_links:3} {owner:8} {group:8} {size:>10} {modify} ");
+        }
+        if color {
+            line += &colored_name(e);
+        } else {
+            line += &e.name;
+        }
+        println!("{line}");
+    }
+}
+
+/// Prints the listing as a JSON array, with whatever attributes of each entry were fetched.
+pub fn print_json_listing(
+    mut entries: Vec<DirEntryInfo>,
+    id_mapper: &dyn IdMapper,
+    options: &ListOptions,
+) {
+    filter_and_sort(&mut entries, options);
+
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|e| json::Entry {
+            file_type: e.file_type.clone(),
+            mode: e.mode,
+            symbolic_mode: e.mode.map(|m| symbolic_mode(e.file_type.as_ref(), m)),
+            size: e.size,
+            links: e.num_links,
+            uid: e.owner.as_deref().and_then(|o| id_mapper.uid(o)),
+            gid: e.owner_group.as_deref().and_then(|g| id_mapper.gid(g)),
+            owner: e.owner,
+            group: e.owner_group,
+            mtime: e.modify_time.map(|t| t.to_string()),
+            handle: e.handle,
+            name: e.name,
+        })
+        .collect();
+    json::print(&entries);
+}
@@ -0,0 +1,1853 @@
+// Copyright 2023 Remi Bernotavicius
+
+use clap::{Parser, Subcommand, ValueEnum};
+use indicatif::{BinaryBytes, MultiProgress, ProgressBar, ProgressStyle};
+use ls::ListOptions;
+use mode::ModeChange;
+use nfs4::{
+    Access, FileAttribute, FileAttributeId, FileAttributes, FileHandle, FileType, OperationId,
+    StatusError, ToId as _,
+};
+use nfs4_client::{
+    DirEntryInfo, DirEvent, IdMap, NfsUrl, RateLimiter, RemotePath, RemotePathBuf, Result,
+};
+use std::collections::HashMap;
+use std::fs::FileTimes;
+use std::io;
+use std::io::Seek as _;
+use std::net::TcpStream;
+use std::os::unix::fs::{FileExt as _, MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use sun_rpc_client::mount::{MountClient, MOUNT, MOUNT_VERSION};
+use sun_rpc_client::portmap::{Binding, PortMapper, Protocol, RpcBind, RPCBIND_VERSION_4};
+use sun_rpc_client::{Transport, UdpTransport};
+
+mod bench;
+mod checksum;
+mod completion;
+mod config;
+mod dd;
+mod edit;
+mod find;
+#[cfg(feature = "fuse")]
+mod fuse;
+mod glob;
+mod jobs;
+mod json;
+mod ls;
+mod mode;
+mod ping;
+mod selftest;
+mod shell;
+mod sync;
+mod tail;
+mod v3;
+
+/// Attributes to set, given to `set-attr`. The mode is kept apart since a symbolic one depends on
+/// the current mode.
+#[derive(Clone, Debug)]
+struct AttrChanges {
+    attrs: FileAttributes,
+    mode: Option<ModeChange>,
+}
+
+const ATTR_KEYS: &str = "size, owner, owner_group, mode, mtime, atime, hidden, system, archive";
+
+// How much of the end of a partial copy is compared with the other copy before resuming
+const RESUME_CHECK_LEN: u64 = 64 * 1024;
+
+/// A time given as RFC 3339, like `2023-06-01T12:00:00Z`, or `now` for the server's time.
+fn set_time(s: &str) -> std::result::Result<nfs4::SetTime, String> {
+    if s == "now" {
+        return Ok(nfs4::SetTime::SetToServerTime);
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(s)
+        .map_err(|e| format!("invalid time `{s}`, expected RFC 3339 or `now`: {e}"))?;
+    Ok(nfs4::SetTime::SetToClientTime(nfs_time(
+        time.timestamp(),
+        time.timestamp_subsec_nanos().into(),
+    )))
+}
+
+/// Comma-separated `key=value` pairs. Symbolic modes have commas of their own, so a part like
+/// `go-w` right after `mode=` carries on the mode.
+fn file_attrs(s: &str) -> std::result::Result<AttrChanges, String> {
+    let mut pairs: Vec<String> = vec![];
+    for part in s.split(',') {
+        let who = part.trim_start_matches(['u', 'g', 'o', 'a']);
+        match pairs.last_mut() {
+            Some(mode) if mode.starts_with("mode=") && who.starts_with(['+', '-', '=']) => {
+                mode.push(',');
+                mode.push_str(part);
+            }
+            _ => pairs.push(part.into()),
+        }
+    }
+
+    let mut changes = AttrChanges {
+        attrs: FileAttributes::default(),
+        mode: None,
+    };
+    for pair in &pairs {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("missing `=` in `{pair}`"))?;
+        let flag = || {
+            value
+                .parse::<bool>()
+                .map_err(|_| format!("invalid {key} `{value}`, expected true or false"))
+        };
+        changes.attrs.insert(match key {
+            "size" => FileAttribute::Size(
+                value
+                    .parse()
+                    .map_err(|e| format!("invalid size `{value}`: {e}"))?,
+            ),
+            "owner" => FileAttribute::Owner(value.into()),
+            "owner_group" => FileAttribute::OwnerGroup(value.into()),
+            "mode" => {
+                changes.mode = Some(mode::mode_change(value)?);
+                continue;
+            }
+            "mtime" => FileAttribute::TimeModifySet(set_time(value)?),
+            "atime" => FileAttribute::TimeAccessSet(set_time(value)?),
+            "hidden" => FileAttribute::Hidden(flag()?),
+            "system" => FileAttribute::System(flag()?),
+            "archive" => FileAttribute::Archive(flag()?),
+            other => {
+                return Err(format!(
+                    "unsupported attribute `{other}`, expected one of {ATTR_KEYS}"
+                ))
+            }
+        });
+    }
+    Ok(changes)
+}
+
+/// A local user and group given as `USER[:GROUP]` or `:GROUP`, like `chown` takes them. Each is
+/// a name or an ID.
+fn owner_names(s: &str) -> std::result::Result<(Option<String>, Option<String>), String> {
+    let (user, group) = match s.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (s, None),
+    };
+    let user = (!user.is_empty()).then(|| user.to_owned());
+    let group = group.filter(|g| !g.is_empty()).map(str::to_owned);
+    if user.is_none() && group.is_none() {
+        return Err("missing user and group".into());
+    }
+    Ok((user, group))
+}
+
+/// A rate in bytes per second, with an optional K, M or G suffix for powers of 1024, like `500K`
+/// or `10M`. A trailing `B`, `iB` or `/s` is allowed too.
+fn bandwidth(s: &str) -> std::result::Result<u64, String> {
+    let rate = s.trim_end_matches("/s");
+    let rate = rate
+        .strip_suffix("iB")
+        .or_else(|| rate.strip_suffix('B'))
+        .unwrap_or(rate);
+    let (number, multiplier) = match rate.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&rate[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&rate[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&rate[..i], 1 << 30),
+        _ => (rate, 1),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok((n * multiplier as f64) as u64),
+        _ => Err(format!(
+            "invalid rate `{s}`, expected something like 500K or 10M"
+        )),
+    }
+}
+
+/// A path on some server, given as an `nfs://` URL, or just a path on the server we are connected
+/// to.
+#[derive(Clone, Debug)]
+struct Location {
+    server: Option<NfsUrl>,
+    path: RemotePathBuf,
+}
+
+fn location(s: &str) -> std::result::Result<Location, String> {
+    if !s.starts_with("nfs://") {
+        return Ok(Location {
+            server: None,
+            path: s.into(),
+        });
+    }
+    let url: NfsUrl = s.parse().map_err(|e: io::Error| e.to_string())?;
+    Ok(Location {
+        path: url.path.clone(),
+        server: Some(url),
+    })
+}
+
+#[derive(Subcommand)]
+enum Command {
+    GetAttr {
+        path: RemotePathBuf,
+    },
+    /// Set attributes of the remote path, given as `key=value` pairs separated by commas. The
+    /// keys are size, owner, owner_group, mode (octal or symbolic, like chmod takes it), mtime and
+    /// atime (RFC 3339 or `now`), and hidden, system and archive (true or false)
+    SetAttr {
+        path: RemotePathBuf,
+        #[arg(value_parser = file_attrs)]
+        attrs: AttrChanges,
+    },
+    /// List the remote directory in long format, like `ls -l`
+    #[command(disable_help_flag = true)]
+    ReadDir {
+        path: RemotePathBuf,
+        #[command(flatten)]
+        options: ListOptions,
+    },
+    /// Change the owner and group of the remote path to local ones, given as `USER[:GROUP]` with
+    /// names or IDs. Names are only known with --id-domain
+    Chown {
+        #[arg(value_parser = owner_names)]
+        owner: (Option<String>, Option<String>),
+        path: RemotePathBuf,
+    },
+    /// Change the mode of the remote path, given in octal like `755` or symbolically like
+    /// `u+x,go-w`
+    Chmod {
+        #[arg(value_parser = mode::mode_change)]
+        mode: ModeChange,
+        path: RemotePathBuf,
+    },
+    /// Set the access and modify times of the remote file to now, creating it if it doesn't exist
+    Touch {
+        path: RemotePathBuf,
+    },
+    /// Print the security label of the remote path, for labeled NFS
+    GetLabel {
+        path: RemotePathBuf,
+    },
+    /// Set the security label of the remote path, like an SELinux context
+    SetLabel {
+        path: RemotePathBuf,
+        label: String,
+        /// The label format specifier, which tells what kind of label it is
+        #[arg(long, default_value_t = 0)]
+        format: u32,
+        /// Which policy of the format the label is for
+        #[arg(long, default_value_t = 0)]
+        policy: u32,
+    },
+    #[command(alias = "rm")]
+    Remove {
+        path: RemotePathBuf,
+    },
+    Download {
+        /// Download a directory and everything in it
+        #[arg(short, long)]
+        recursive: bool,
+        /// How many files of a recursive download to download at once, each over a connection of
+        /// its own
+        #[arg(short, long, default_value_t = 1, requires = "recursive")]
+        jobs: usize,
+        /// Continue from the end of the local file left by an earlier download, if its last part
+        /// matches the remote file
+        #[arg(long, conflicts_with = "recursive")]
+        resume: bool,
+        /// Read each file again once it is downloaded and check that the SHA-256 hashes of the
+        /// local and remote copies match
+        #[arg(long)]
+        verify: bool,
+        /// Move no more than this many bytes per second, like 500K or 10M
+        #[arg(long, value_parser = bandwidth)]
+        bwlimit: Option<u64>,
+        remote: RemotePathBuf,
+        local: PathBuf,
+    },
+    #[command(alias = "put")]
+    Upload {
+        /// Upload a directory and everything in it
+        #[arg(short, long)]
+        recursive: bool,
+        /// How many files of a recursive upload to upload at once, each over a connection of its
+        /// own
+        #[arg(short, long, default_value_t = 1, requires = "recursive")]
+        jobs: usize,
+        /// Continue from the end of the remote file left by an earlier upload, if its last part
+        /// matches the local file
+        #[arg(long, conflicts_with = "recursive")]
+        resume: bool,
+        /// Move no more than this many bytes per second, like 500K or 10M
+        #[arg(long, value_parser = bandwidth)]
+        bwlimit: Option<u64>,
+        /// The local file or directory, or `-` to upload what comes in on stdin
+        local: PathBuf,
+        remote: RemotePathBuf,
+    },
+    /// Make the remote directory match the local one, only copying what changed
+    Sync {
+        /// Make the local directory match the remote one instead
+        #[arg(long)]
+        reverse: bool,
+        /// Also compare the contents of files whose size and modify time match, and check the
+        /// SHA-256 hashes of both copies of each file after copying it
+        #[arg(short, long)]
+        checksum: bool,
+        /// Delete things in the destination which aren't in the source
+        #[arg(long)]
+        delete: bool,
+        /// Only print what would be done
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// How many files to copy at once, each over a connection of its own
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+        /// Move no more than this many bytes per second, like 500K or 10M
+        #[arg(long, value_parser = bandwidth)]
+        bwlimit: Option<u64>,
+        local: PathBuf,
+        remote: RemotePathBuf,
+    },
+    /// Mount the remote path at the given local directory using FUSE
+    #[cfg(feature = "fuse")]
+    Mount {
+        path: RemotePathBuf,
+        mountpoint: PathBuf,
+    },
+    /// Interactive prompt for running commands over one connection
+    Shell,
+    /// List the RPC programs registered with the server's port mapper
+    RpcInfo,
+    /// Time round trips to the server with calls which do nothing, of the NFS program and of the
+    /// port mapper
+    Ping {
+        /// How many calls to make to each
+        #[arg(short, long, default_value_t = 10)]
+        count: u32,
+        /// Also find out which minor versions of NFSv4 and security flavors the server supports
+        #[arg(long)]
+        probe: bool,
+    },
+    /// List what the server exports by browsing its NFSv4 pseudo file system, or using the MOUNT
+    /// protocol like `showmount -e` with v3
+    Exports,
+    /// Show how much space is used and available on the file system, like `df -h`
+    Df {
+        #[clap(default_value = "/")]
+        path: RemotePathBuf,
+    },
+    /// Show how much space we are using and may use before running into our quota
+    Quota {
+        #[clap(default_value = "/")]
+        path: RemotePathBuf,
+    },
+    /// Show what the server supports: which attributes, how much a READ or WRITE may be, how long
+    /// its lease is, whether its file handles expire, and which kinds of ACE it takes
+    ServerInfo,
+    /// Print the last lines of the remote file, and with -f what is appended to it after
+    Tail {
+        /// How many lines to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Keep checking the file for more and print it as it comes
+        #[arg(short, long)]
+        follow: bool,
+        /// How many seconds to wait between checks with -f
+        #[arg(short, long, default_value_t = 1.0)]
+        sleep_interval: f64,
+        path: RemotePathBuf,
+    },
+    /// Print everything under the remote directory as a tree
+    Tree {
+        #[clap(default_value = "/")]
+        path: RemotePathBuf,
+    },
+    /// Show how much space each directory under the remote path takes, like `du -h`
+    Du {
+        /// Only show the total for the path itself
+        #[arg(short, long)]
+        summarize: bool,
+        #[clap(default_value = "/")]
+        path: RemotePathBuf,
+    },
+    /// Print the remote path and everything under it which matches all the given filters
+    Find {
+        #[clap(default_value = "/")]
+        path: RemotePathBuf,
+        /// Only entries with names matching this wildcard pattern
+        #[arg(long)]
+        name: Option<String>,
+        /// Only entries of this type: f, d, l, b, c, p or s
+        #[arg(long = "type", value_parser = find::type_letter)]
+        file_type: Option<FileType>,
+        /// Only files of this size in bytes, or K, M or G with one of those after it. With a + in
+        /// front ones bigger than it, with a - ones smaller.
+        #[arg(long, value_parser = find::size_comparison, allow_hyphen_values = true)]
+        size: Option<find::Comparison>,
+        /// Only entries last modified this many days ago. With a + in front ones modified longer
+        /// ago, with a - ones modified since.
+        #[arg(long, value_parser = find::days_comparison, allow_hyphen_values = true)]
+        mtime: Option<find::Comparison>,
+    },
+    /// Print entries as they are created, removed or renamed in the remote directory
+    Watch {
+        path: RemotePathBuf,
+    },
+    /// List the remote directory
+    #[command(disable_help_flag = true)]
+    Ls {
+        path: RemotePathBuf,
+        #[command(flatten)]
+        options: ListOptions,
+    },
+    LsFh {
+        fh: FileHandle,
+    },
+    /// Print the contents of the remote file
+    Cat {
+        #[arg(required_unless_present = "fh")]
+        path: Option<RemotePathBuf>,
+        /// Print the file with this handle instead, given in hex
+        #[arg(long, conflicts_with = "path")]
+        fh: Option<FileHandle>,
+    },
+    /// Print the first lines of the remote file
+    Head {
+        /// How many lines to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Print this many bytes instead of lines
+        #[arg(short = 'c', long)]
+        bytes: Option<u64>,
+        path: RemotePathBuf,
+    },
+    /// Open the remote file in `$VISUAL` or `$EDITOR`, and write it back if it was changed, as
+    /// long as it wasn't changed on the server in the meantime
+    Edit {
+        path: RemotePathBuf,
+    },
+    /// Print the hash of the remote files, like `sha256sum`
+    Checksum {
+        #[arg(long, value_enum, default_value_t = checksum::Algorithm::Sha256)]
+        algo: checksum::Algorithm,
+        #[arg(required = true)]
+        paths: Vec<RemotePathBuf>,
+    },
+    /// List the remote paths starting with the given one, for shell completion
+    #[command(name = "__complete-path", hide = true)]
+    CompletePath {
+        #[arg(allow_hyphen_values = true)]
+        prefix: String,
+    },
+    /// Measure how fast the server is at one kind of operation, printing the throughput and
+    /// latency percentiles
+    Bench {
+        #[command(flatten)]
+        options: bench::BenchOptions,
+    },
+    /// Check that the server does what this client needs of it, by going through what the
+    /// integration tests do in a directory made for it and removed again, printing which steps
+    /// failed
+    Selftest {
+        /// The directory to make it in
+        #[arg(long, default_value = "/")]
+        dir: RemotePathBuf,
+    },
+    /// Copy blocks between remote files, or stdin and stdout, like `dd`. Takes `if=PATH`,
+    /// `of=PATH`, `bs=N`, `count=N`, `skip=N`, `seek=N` and `conv=notrunc`, with sizes in bytes or
+    /// with a unit of c, w, b, K, M or G
+    Dd {
+        #[arg(value_parser = dd::operand)]
+        operands: Vec<dd::Operand>,
+    },
+    /// Copy a file without the data going through here. Either path can be an `nfs://` URL, in
+    /// which case the destination server reads the file straight from the source one.
+    Cp {
+        #[arg(value_parser = location)]
+        source: Location,
+        #[arg(value_parser = location)]
+        destination: Location,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Proto {
+    V3,
+    V4,
+}
+
+#[derive(Parser)]
+#[command(
+    after_help = "Run `nfs4 completions bash|zsh|fish` for a script which completes commands \
+    and remote paths in the shell"
+)]
+struct Options {
+    /// The server, or an `nfs://host[:port]/path?minorversion=N&sec=sys|none` URL for it. The
+    /// paths given to commands are then taken to be under the path of the URL, and with v3 it is
+    /// the export to mount. `@name` is the server with that name in the config file
+    host: String,
+    /// The port of the server, 2049 if not given here, in the URL or in the config file
+    port: Option<u16>,
+    /// Where to read servers given as `@name` from, instead of `~/.config/nfs4/config.toml`
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Which version of NFS to speak, only some commands work with v3
+    #[arg(long, value_enum, default_value_t = Proto::V4)]
+    proto: Proto,
+    /// Speak only this minor version of NFSv4, rather than the newest one the server speaks
+    #[arg(long, value_name = "N")]
+    minor_version: Option<u32>,
+    /// Ask the server to only take operations which change our state, like CLOSE, from the
+    /// credential the client was made with (SP4_MACH_CRED), which some servers insist on
+    #[arg(long)]
+    protect_state: bool,
+    /// The export to mount when using v3
+    #[arg(long, default_value = "/")]
+    export: String,
+    /// Read and write files straight from the data servers when the server hands out pNFS layouts
+    #[arg(long)]
+    pnfs: bool,
+    /// Reach the port mapper, MOUNT and NFSv3 over UDP, for servers which don't take TCP
+    #[arg(long)]
+    udp: bool,
+    /// Also send reads and writes over a connection to this address of the server, can be given
+    /// more than once
+    #[arg(long = "trunk", value_name = "HOST[:PORT]")]
+    trunks: Vec<String>,
+    /// Map owners and groups in this NFSv4 domain to the users and groups of this machine. Without
+    /// it only IDs the server sends as numbers are mapped
+    #[arg(long)]
+    id_domain: Option<String>,
+    /// Give up on the server if it doesn't answer a request in this many seconds, instead of
+    /// waiting forever
+    #[arg(long)]
+    timeout: Option<u64>,
+    /// Remember attributes, look-ups and listings for this many seconds instead of asking the
+    /// server again, which makes sync and mount much quicker over a slow link. Changes made by
+    /// others are only seen once what is remembered expires
+    #[arg(long, value_name = "SECONDS")]
+    attr_cache: Option<u64>,
+    /// Remember the handle each name leads to for this many seconds, so that looking up deep
+    /// paths only looks up the part of them not seen before. Names removed or renamed by others
+    /// can still be found until they expire
+    #[arg(long, value_name = "SECONDS")]
+    name_cache: Option<u64>,
+    /// Print to stderr how many of each operation were sent, how much was read and written, the
+    /// errors and the latency once the command is done. The shell has a `stats` command instead
+    #[arg(long)]
+    stats: bool,
+    /// Log every COMPOUND sent with how each of its operations went, the sizes and the latency.
+    /// Given twice, also print every request and reply in full
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Print the output of get-attr, ls, read-dir, df, quota, exports, server-info, tree, du,
+    /// find, checksum, ping and bench as JSON, for scripts
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    /// Take `*`, `?` and `[` in remote paths as they are, rather than as wildcards matching the
+    /// names in the remote directory. Quote paths with wildcards so the local shell leaves them
+    #[arg(long, global = true)]
+    no_glob: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+fn set_local_attrs(file: &std::fs::File, attrs: &FileAttributes) -> Result<()> {
+    if let Some(mode) = attrs.get_as::<nfs4::Mode>(FileAttributeId::Mode) {
+        file.set_permissions(std::fs::Permissions::from_mode(mode.0 & 0o7777))?;
+    }
+
+    let mut times = FileTimes::new();
+    let time = |id| attrs.get_as(id).and_then(nfs4::Time::to_system_time);
+    if let Some(access) = time(FileAttributeId::TimeAccess) {
+        times = times.set_accessed(access);
+    }
+    if let Some(modify) = time(FileAttributeId::TimeModify) {
+        times = times.set_modified(modify);
+    }
+    file.set_times(times)?;
+    Ok(())
+}
+
+fn nfs_time(seconds: i64, nseconds: i64) -> nfs4::Time {
+    nfs4::Time {
+        seconds,
+        nseconds: nseconds as u32,
+    }
+}
+
+fn remote_attrs(metadata: &std::fs::Metadata) -> FileAttributes {
+    [
+        FileAttribute::Mode(nfs4::Mode(metadata.mode() & 0o7777)),
+        FileAttribute::TimeAccessSet(nfs4::SetTime::SetToClientTime(nfs_time(
+            metadata.atime(),
+            metadata.atime_nsec(),
+        ))),
+        FileAttribute::TimeModifySet(nfs4::SetTime::SetToClientTime(nfs_time(
+            metadata.mtime(),
+            metadata.mtime_nsec(),
+        ))),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// Collects everything under the given directory depth-first, in name order, with paths relative
+/// to the directory.
+fn walk_local(
+    root: &Path,
+    relative: PathBuf,
+    entries: &mut Vec<(PathBuf, std::fs::Metadata)>,
+) -> Result<()> {
+    let mut dir_entries =
+        std::fs::read_dir(root.join(&relative))?.collect::<io::Result<Vec<_>>>()?;
+    dir_entries.sort_by_key(|e| e.file_name());
+
+    for entry in dir_entries {
+        let path = relative.join(entry.file_name());
+        let metadata = std::fs::symlink_metadata(root.join(&path))?;
+        let is_dir = metadata.is_dir();
+        entries.push((path.clone(), metadata));
+        if is_dir {
+            walk_local(root, path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+struct Cli {
+    client: nfs4_client::Client<TcpStream>,
+    // Where paths are taken to be from, the path of the URL we were given
+    root: RemotePathBuf,
+    output: Output,
+    no_glob: bool,
+    // Connects another client to the same server, for transferring files in parallel
+    new_client: Box<dyn Fn() -> Result<nfs4_client::Client<TcpStream>>>,
+}
+
+impl Cli {
+    /// The given path under the root.
+    fn path(&self, path: RemotePathBuf) -> RemotePathBuf {
+        if self.root.as_str() == "/" {
+            return path;
+        }
+        // Pushing the path as it is keeps a trailing slash, which matters to `upload`
+        self.root.join(path.as_str().trim_start_matches('/'))
+    }
+
+    /// The paths the wildcards in the given path match, in name order. It is an error if it
+    /// matches nothing.
+    fn expand(&mut self, path: RemotePathBuf) -> Result<Vec<RemotePathBuf>> {
+        if self.no_glob || !glob::has_wildcards(path.as_str()) {
+            return Ok(vec![path]);
+        }
+        let components: Vec<&str> = path.components().collect();
+        let mut matches = vec![RemotePathBuf::from("/")];
+        for (i, component) in components.iter().enumerate() {
+            if !glob::has_wildcards(component) {
+                matches.iter_mut().for_each(|m| m.push(component));
+                continue;
+            }
+            // Only directories can have the components after this one
+            let last = i == components.len() - 1;
+            let mut next = vec![];
+            for dir in matches {
+                let handle = self.client.look_up(&dir)?;
+                let attr_request = [FileAttributeId::Type].into_iter().collect();
+                for entry in self.client.read_dir(handle, attr_request) {
+                    let entry = entry?;
+                    let file_type = entry.attrs.get_as(FileAttributeId::Type);
+                    if glob::matches(component, &entry.name)
+                        && (last || file_type == Some(&FileType::Directory))
+                    {
+                        next.push(dir.join(&entry.name));
+                    }
+                }
+            }
+            next.sort();
+            matches = next;
+        }
+        if matches.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no matches for `{path}`, use --no-glob to take it as it is"),
+            )
+            .into());
+        }
+        Ok(matches)
+    }
+
+    /// Checks with the server that we have the given access to the given object, so that a
+    /// transfer fails up front rather than part way through.
+    fn check_access(
+        &mut self,
+        handle: FileHandle,
+        path: &RemotePath,
+        access: Access,
+    ) -> Result<()> {
+        let res = self.client.access(handle, access)?;
+        if (res.supported - res.access).intersects(access) {
+            return Err(nfs4_client::Error::Protocol(nfs4_client::NfsError {
+                status: StatusError::Access,
+                op: Some(OperationId::Access),
+                path: Some(path.to_owned()),
+            }));
+        }
+        Ok(())
+    }
+
+    fn get_attr(&mut self, path: RemotePathBuf) -> Result<()> {
+        let reply = self.client.stat(&path)?;
+        match self.output {
+            Output::Text => println!("{reply:#?}"),
+            Output::Json => json::print_attrs(&reply.object_attributes),
+        }
+        Ok(())
+    }
+
+    fn chown(
+        &mut self,
+        path: RemotePathBuf,
+        user: Option<String>,
+        group: Option<String>,
+    ) -> Result<()> {
+        let id_mapper = self.client.id_mapper();
+        let unknown = |kind: &str, name: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown {kind} `{name}`"),
+            )
+        };
+        let uid = user
+            .map(|u| id_mapper.uid(&u).ok_or_else(|| unknown("user", &u)))
+            .transpose()?;
+        let gid = group
+            .map(|g| id_mapper.gid(&g).ok_or_else(|| unknown("group", &g)))
+            .transpose()?;
+
+        let handle = self.client.look_up(&path)?;
+        self.client.set_owner(handle, uid, gid)?;
+        Ok(())
+    }
+
+    /// Looks up the path along with the mode the change gives it.
+    fn new_mode(
+        &mut self,
+        path: &RemotePath,
+        change: &ModeChange,
+    ) -> Result<(FileHandle, nfs4::Mode)> {
+        let (handle, mode) = if change.is_relative() {
+            let attr_request = [FileAttributeId::Mode, FileAttributeId::Type]
+                .into_iter()
+                .collect();
+            let (handle, attrs) = self.client.look_up_with_attrs(path, attr_request)?;
+            let mode = attrs
+                .get_as::<nfs4::Mode>(FileAttributeId::Mode)
+                .map_or(0, |m| m.0);
+            let is_dir = attrs.get_as(FileAttributeId::Type) == Some(&FileType::Directory);
+            (handle, change.apply(mode & 0o7777, is_dir))
+        } else {
+            (self.client.look_up(path)?, change.apply(0, false))
+        };
+        Ok((handle, nfs4::Mode(mode)))
+    }
+
+    fn chmod(&mut self, path: RemotePathBuf, change: ModeChange) -> Result<()> {
+        let (handle, mode) = self.new_mode(&path, &change)?;
+        let attrs = [FileAttribute::Mode(mode)].into_iter().collect();
+        self.client.set_attr(handle, attrs)?;
+        Ok(())
+    }
+
+    fn touch(&mut self, path: RemotePathBuf) -> Result<()> {
+        let handle = match self.client.look_up(&path) {
+            Err(e) if e.is_not_found() => {
+                let (parent_dir, name) = (path.parent().unwrap(), path.file_name().unwrap());
+                let parent = self.client.look_up(parent_dir)?;
+                let file = self.client.create_file(parent, name)?;
+                let handle = file.handle.clone();
+                self.client.close(file)?;
+                handle
+            }
+            res => res?,
+        };
+        let attrs = nfs4::FileAttributesBuilder::new()
+            .atime_now()
+            .mtime_now()
+            .build();
+        self.client.set_attr(handle, attrs)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, path: RemotePathBuf) -> Result<()> {
+        let (parent_dir, name) = (path.parent().unwrap(), path.file_name().unwrap());
+        let parent = self.client.look_up(parent_dir)?;
+        self.client.remove(parent, name)?;
+        Ok(())
+    }
+
+    /// Whether the given part of the local file is the same as that of the remote one, read from
+    /// the server in case it is the remote file that has it.
+    fn tail_matches(
+        &mut self,
+        handle: FileHandle,
+        local: &std::fs::File,
+        end: u64,
+    ) -> Result<bool> {
+        let start = end.saturating_sub(RESUME_CHECK_LEN);
+        let mut local_tail = vec![0; (end - start) as usize];
+        local.read_exact_at(&mut local_tail, start)?;
+        let mut remote_tail = vec![];
+        while remote_tail.len() < local_tail.len() {
+            let offset = start + remote_tail.len() as u64;
+            let len = local_tail.len() - remote_tail.len();
+            let res = self.client.read_at(handle.clone(), offset, len)?;
+            if res.data.is_empty() {
+                break;
+            }
+            remote_tail.extend(res.data);
+        }
+        Ok(local_tail == remote_tail)
+    }
+
+    /// Where to continue a transfer which got as far as `partial_len` of `len`, which is from the
+    /// start if what is there doesn't look like the start of the file.
+    fn resume_offset(
+        &mut self,
+        handle: FileHandle,
+        local: &std::fs::File,
+        partial_len: u64,
+        len: u64,
+    ) -> Result<u64> {
+        if partial_len > len || !self.tail_matches(handle, local, partial_len)? {
+            eprintln!("the partial copy doesn't match, starting over");
+            return Ok(0);
+        }
+        Ok(partial_len)
+    }
+
+    fn download(
+        &mut self,
+        remote: RemotePathBuf,
+        local: PathBuf,
+        resume: bool,
+        verify: bool,
+    ) -> Result<()> {
+        let local_file = if local.to_string_lossy().ends_with('/') {
+            local.join(remote.file_name().unwrap())
+        } else {
+            local
+        };
+
+        let (handle, mut remote_attrs) = self
+            .client
+            .look_up_with_attrs(&remote, [FileAttributeId::Size].into_iter().collect())?;
+        self.check_access(handle.clone(), &remote, Access::READ)?;
+        let size = remote_attrs.remove_as(FileAttributeId::Size).unwrap();
+
+        let progress = ProgressBar::new(size).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        );
+        if resume {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&local_file)?;
+            let partial_len = file.metadata()?.len();
+            let offset = self.resume_offset(handle.clone(), &file, partial_len, size)?;
+            file.set_len(offset)?;
+            file.seek(io::SeekFrom::Start(offset))?;
+            progress.set_position(offset);
+            self.client
+                .read_all_from(handle.clone(), offset, progress.wrap_write(file))?;
+        } else {
+            let file = std::fs::File::create(&local_file)?;
+            self.client
+                .read_all(handle.clone(), progress.wrap_write(file))?;
+        }
+
+        if verify {
+            checksum::verify(&mut self.client, handle, &local_file, &remote)?;
+        }
+        Ok(())
+    }
+
+    fn download_recursive(
+        &mut self,
+        remote: RemotePathBuf,
+        local: PathBuf,
+        jobs: usize,
+        verify: bool,
+    ) -> Result<()> {
+        let root = self.client.look_up(&remote)?;
+        self.check_access(root.clone(), &remote, Access::READ | Access::LOOKUP)?;
+        let attr_request = [
+            FileAttributeId::Mode,
+            FileAttributeId::Size,
+            FileAttributeId::TimeAccess,
+            FileAttributeId::TimeModify,
+        ]
+        .into_iter()
+        .collect();
+
+        let mut entries = vec![];
+        for entry in self.client.walk(root, attr_request) {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => eprintln!("{}: {:?}", remote.join(e.path), e.error),
+            }
+        }
+
+        let total_size = entries
+            .iter()
+            .filter(|e| e.file_type() == Some(&FileType::Regular))
+            .filter_map(|e| e.attrs.get_as::<u64>(FileAttributeId::Size))
+            .sum();
+        let multi_progress = MultiProgress::new();
+        let progress = multi_progress.add(ProgressBar::new(total_size).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        ));
+
+        std::fs::create_dir_all(&local)?;
+        let mut directories = vec![];
+        let mut files = vec![];
+        for entry in &entries {
+            let local_path = local.join(entry.path.to_local());
+            match entry.file_type() {
+                Some(FileType::Directory) => {
+                    std::fs::create_dir_all(&local_path)?;
+                    directories.push(entry);
+                }
+                Some(FileType::Regular) => files.push(entry),
+                Some(FileType::Link) => {
+                    let target = self.client.read_link(entry.handle.clone())?;
+                    std::os::unix::fs::symlink(target, &local_path)?;
+                }
+                other => multi_progress.println(format!(
+                    "skipping {} of type {other:?}",
+                    remote.join(&entry.path)
+                ))?,
+            }
+        }
+
+        self.run_jobs(jobs, files, &multi_progress, |client, entry, bar| {
+            let size = entry.attrs.get_as(FileAttributeId::Size).copied();
+            bar.reset();
+            bar.set_length(size.unwrap_or(0));
+            bar.set_message(entry.path.to_string());
+            let local_path = local.join(entry.path.to_local());
+            let file = std::fs::File::create(&local_path)?;
+            let sink = progress.wrap_write(bar.wrap_write(&file));
+            client.read_all(entry.handle.clone(), sink)?;
+            set_local_attrs(&file, &entry.attrs)?;
+            if verify {
+                let remote = remote.join(&entry.path);
+                checksum::verify(client, entry.handle.clone(), &local_path, &remote)?;
+            }
+            Ok(())
+        })?;
+        progress.finish();
+
+        // Directories get their attributes last, and deepest first, since creating things in
+        // them changes their modify time.
+        for entry in directories.into_iter().rev() {
+            let dir = std::fs::File::open(local.join(entry.path.to_local()))?;
+            set_local_attrs(&dir, &entry.attrs)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_attr(&mut self, path: RemotePathBuf, changes: AttrChanges) -> Result<()> {
+        let mut attrs = changes.attrs;
+        let handle = match &changes.mode {
+            Some(change) => {
+                let (handle, mode) = self.new_mode(&path, change)?;
+                attrs.insert(FileAttribute::Mode(mode));
+                handle
+            }
+            None => self.client.look_up(&path)?,
+        };
+        let requested: Vec<_> = attrs.iter().map(|attr| attr.to_id()).collect();
+        let set = self.client.set_attr(handle, attrs)?;
+        let not_set: Vec<_> = requested
+            .into_iter()
+            .filter(|id| !set.contains(*id))
+            .collect();
+        if !not_set.is_empty() {
+            eprintln!("the server didn't set {not_set:?}");
+        }
+        Ok(())
+    }
+
+    fn upload(&mut self, local: PathBuf, remote: RemotePathBuf, resume: bool) -> Result<()> {
+        if local.as_os_str() == "-" {
+            return self.upload_stdin(remote, resume);
+        }
+        let (parent_dir, name) = if remote.as_str().ends_with('/') {
+            (&*remote, local.file_name().unwrap().to_str().unwrap())
+        } else {
+            (remote.parent().unwrap(), remote.file_name().unwrap())
+        };
+
+        let parent = self.client.look_up(parent_dir)?;
+        self.check_access(parent.clone(), parent_dir, Access::EXTEND)?;
+
+        let mut file = std::fs::File::open(&local)?;
+        let len = file.metadata()?.len();
+        let progress = ProgressBar::new(len).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        );
+        let size_request = [FileAttributeId::Size].into_iter().collect();
+        let existing = match resume {
+            true => match self
+                .client
+                .look_up_with_attrs(parent_dir.join(name), size_request)
+            {
+                Err(e) if e.is_not_found() => None,
+                res => Some(res?),
+            },
+            false => None,
+        };
+        let Some((handle, mut attrs)) = existing else {
+            let handle = self.client.create_file(parent, name)?.handle.clone();
+            self.client.write_all(handle, progress.wrap_read(file))?;
+            return Ok(());
+        };
+
+        let partial_len = attrs.remove_as(FileAttributeId::Size).unwrap_or(0);
+        let offset = self.resume_offset(handle.clone(), &file, partial_len, len)?;
+        if offset < partial_len {
+            let attrs = [FileAttribute::Size(offset)].into_iter().collect();
+            self.client.set_attr(handle.clone(), attrs)?;
+        }
+        file.seek(io::SeekFrom::Start(offset))?;
+        progress.set_position(offset);
+        self.client
+            .write_all_at(handle, offset, progress.wrap_read(file))?;
+        Ok(())
+    }
+
+    /// Uploads everything that comes in on stdin until it is closed, for which the remote path has
+    /// to name the file.
+    fn upload_stdin(&mut self, remote: RemotePathBuf, resume: bool) -> Result<()> {
+        if resume {
+            let message = "an upload from stdin can't be resumed";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        let parent_dir = remote.parent().filter(|_| !remote.as_str().ends_with('/'));
+        let Some(parent_dir) = parent_dir else {
+            let message = "give the remote file a name to upload stdin to";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        };
+
+        let parent = self.client.look_up(parent_dir)?;
+        self.check_access(parent.clone(), parent_dir, Access::EXTEND)?;
+        let handle = Self::truncate(&mut self.client, &remote)?;
+        // How much there is to come isn't known, so there is only a count of what was sent so far
+        let progress = ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {binary_bytes} {binary_bytes_per_sec}")
+                .unwrap(),
+        );
+        self.client
+            .write_all(handle, progress.wrap_read(io::stdin().lock()))?;
+        progress.finish();
+        Ok(())
+    }
+
+    fn upload_recursive(
+        &mut self,
+        local: PathBuf,
+        remote: RemotePathBuf,
+        jobs: usize,
+    ) -> Result<()> {
+        let mut entries = vec![];
+        walk_local(&local, PathBuf::new(), &mut entries)?;
+
+        let total_size = entries
+            .iter()
+            .filter(|(_, m)| m.is_file())
+            .map(|(_, m)| m.len())
+            .sum();
+        let multi_progress = MultiProgress::new();
+        let progress = multi_progress.add(ProgressBar::new(total_size).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        ));
+
+        let root = self.look_up_or_create_dir(&remote)?;
+        self.check_access(root.clone(), &remote, Access::EXTEND)?;
+        let mut handles = HashMap::from([(PathBuf::new(), root.clone())]);
+        let mut directories = vec![(root, std::fs::metadata(&local)?)];
+        let mut files = vec![];
+
+        for (path, metadata) in entries {
+            let parent = handles[path.parent().unwrap()].clone();
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let local_path = local.join(&path);
+
+            if metadata.is_dir() {
+                let handle = self.create_or_open_dir(parent, name)?;
+                handles.insert(path, handle.clone());
+                directories.push((handle, metadata));
+            } else if metadata.is_file() {
+                files.push((parent, path, metadata));
+            } else if metadata.is_symlink() {
+                let target = std::fs::read_link(&local_path)?;
+                let attrs = FileAttributes::default();
+                self.client
+                    .create_symlink(parent, name, target.to_str().unwrap(), attrs)?;
+            } else {
+                multi_progress.println(format!("skipping {}", local_path.display()))?;
+            }
+        }
+
+        self.run_jobs(
+            jobs,
+            files,
+            &multi_progress,
+            |client, (parent, path, metadata), bar| {
+                bar.reset();
+                bar.set_length(metadata.len());
+                bar.set_message(path.display().to_string());
+                let name = path.file_name().unwrap().to_str().unwrap();
+                let handle = client.create_file(parent, name)?.handle.clone();
+                let file = std::fs::File::open(local.join(&path))?;
+                client.write_all(handle.clone(), progress.wrap_read(bar.wrap_read(file)))?;
+                client.set_attr(handle, remote_attrs(&metadata))?;
+                Ok(())
+            },
+        )?;
+        progress.finish();
+
+        // Directories get their attributes last, and deepest first, since creating things in
+        // them changes their modify time.
+        for (handle, metadata) in directories.into_iter().rev() {
+            self.client.set_attr(handle, remote_attrs(&metadata))?;
+        }
+
+        Ok(())
+    }
+
+    fn create_or_open_dir(&mut self, parent: FileHandle, name: &str) -> Result<FileHandle> {
+        match self
+            .client
+            .create_directory(parent.clone(), name, Default::default())
+        {
+            Err(e) if e.is_already_exists() => self.client.look_up_from(parent, name),
+            res => res,
+        }
+    }
+
+    fn look_up_or_create_dir(&mut self, path: &RemotePath) -> Result<FileHandle> {
+        let mut handle = self.client.look_up("/")?;
+        for name in path.components() {
+            handle = self.create_or_open_dir(handle, name)?;
+        }
+        Ok(handle)
+    }
+
+    fn ls(&mut self, path: RemotePathBuf, options: &ListOptions) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        // JSON always has the attributes of a long listing, there is no width to keep within
+        let mut request_options = options.clone();
+        request_options.long |= self.output == Output::Json;
+        let attr_request = request_options.attr_request();
+        let entries = self
+            .client
+            .read_dir(handle, attr_request)
+            .map(|e| e.map(DirEntryInfo::from))
+            .collect::<Result<Vec<_>>>()?;
+        let id_mapper = self.client.id_mapper();
+        match self.output {
+            Output::Text => ls::print_listing(entries, id_mapper, options),
+            Output::Json => ls::print_json_listing(entries, id_mapper, options),
+        }
+        Ok(())
+    }
+
+    fn df(&mut self, path: RemotePathBuf) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let stat = self.client.statfs(handle)?;
+
+        let used = stat.space_total.saturating_sub(stat.space_free);
+        if self.output == Output::Json {
+            json::print(&json::Usage {
+                path: path.into_string(),
+                size: stat.space_total,
+                used,
+                avail: stat.space_avail,
+                files: stat.files_total,
+                files_free: stat.files_free,
+            });
+            return Ok(());
+        }
+        let use_percent = (used * 100).checked_div(stat.space_total).unwrap_or(0);
+        println!(
+            "{:>12} {:>12} {:>12} {:>4} {:>12} {:>12} path",
+            "size", "used", "avail", "use%", "files", "files free"
+        );
+        println!(
+            "{:>12} {:>12} {:>12} {:>3}% {:>12} {:>12} {}",
+            BinaryBytes(stat.space_total).to_string(),
+            BinaryBytes(used).to_string(),
+            BinaryBytes(stat.space_avail).to_string(),
+            use_percent,
+            stat.files_total,
+            stat.files_free,
+            path
+        );
+        Ok(())
+    }
+
+    fn get_label(&mut self, path: RemotePathBuf) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let label = self.client.get_label(handle)?;
+        println!("{}", String::from_utf8_lossy(&label.data));
+        Ok(())
+    }
+
+    fn set_label(&mut self, path: RemotePathBuf, label: nfs4::SecLabel) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        self.client.set_label(handle, label)?;
+        Ok(())
+    }
+
+    fn quota(&mut self, path: RemotePathBuf) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let quota = self.client.quota(handle)?;
+        if self.output == Output::Json {
+            json::print(&json::Quota {
+                path: path.into_string(),
+                used: quota.used,
+                soft_avail: quota.avail_soft,
+                hard_avail: quota.avail_hard,
+            });
+            return Ok(());
+        }
+
+        let bytes = |b: Option<u64>| b.map_or("-".into(), |b| BinaryBytes(b).to_string());
+        println!(
+            "{:>12} {:>12} {:>12} path",
+            "used", "soft avail", "hard avail"
+        );
+        println!(
+            "{:>12} {:>12} {:>12} {}",
+            bytes(quota.used),
+            bytes(quota.avail_soft),
+            bytes(quota.avail_hard),
+            path
+        );
+        Ok(())
+    }
+
+    fn server_info(&mut self) -> Result<()> {
+        let capabilities = self.client.server_capabilities()?;
+        let fh_expire_type = capabilities
+            .fh_expire_type
+            .iter_names()
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        let acl_support = capabilities.acl_support.map(|support| {
+            support
+                .iter_names()
+                .map(|(name, _)| name.to_lowercase())
+                .collect()
+        });
+        let info = json::ServerInfo {
+            supported_attrs: capabilities.supported_attrs,
+            supported_attrs_exclusive_create: capabilities.supported_attrs_exclusive_create,
+            max_read: capabilities.max_read,
+            max_write: capabilities.max_write,
+            lease_time: capabilities.lease_time.as_secs(),
+            fh_expire_type,
+            acl_support,
+        };
+        if self.output == Output::Json {
+            json::print(&info);
+            return Ok(());
+        }
+
+        let list = |names: &[String]| {
+            if names.is_empty() {
+                "-".into()
+            } else {
+                names.join(", ")
+            }
+        };
+        let attr_names = |ids: &nfs4::EnumSet<FileAttributeId>| -> Vec<String> {
+            ids.iter().map(nfs4::readable::attribute_name).collect()
+        };
+        println!(
+            "supported attributes: {}",
+            list(&attr_names(&info.supported_attrs))
+        );
+        println!(
+            "exclusive create attributes: {}",
+            list(&attr_names(
+                &info.supported_attrs_exclusive_create.unwrap_or_default()
+            ))
+        );
+        println!("max read: {}", BinaryBytes(info.max_read));
+        println!("max write: {}", BinaryBytes(info.max_write));
+        println!("lease time: {}s", info.lease_time);
+        if info.fh_expire_type.is_empty() {
+            println!("file handles: persistent");
+        } else {
+            println!("file handles: {}", info.fh_expire_type.join(", "));
+        }
+        println!(
+            "ACL support: {}",
+            list(info.acl_support.as_deref().unwrap_or(&[]))
+        );
+        Ok(())
+    }
+
+    fn print_stats(&self) {
+        let stats = self.client.stats();
+        let latency = stats.average_latency().unwrap_or_default();
+        eprintln!("{} COMPOUNDs, {latency:?} on average", stats.compounds);
+        eprintln!(
+            "{} read, {} written, {} retransmits",
+            BinaryBytes(stats.bytes_read),
+            BinaryBytes(stats.bytes_written),
+            stats.retransmits
+        );
+        for (op, count) in &stats.ops {
+            eprintln!("{count:>10} {op:?}");
+        }
+        for (status, count) in &stats.errors {
+            eprintln!("{count:>10} {status:?}");
+        }
+    }
+
+    fn watch(&mut self, path: RemotePathBuf) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let watcher = self.client.watch_dir(handle)?;
+        for event in watcher.events() {
+            match event {
+                DirEvent::Created(name) => println!("created {name}"),
+                DirEvent::Removed(name) => println!("removed {name}"),
+                DirEvent::Renamed { from, to } => println!("renamed {from} -> {to}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the given file if it doesn't exist yet and empties it.
+    fn truncate(
+        client: &mut nfs4_client::Client<TcpStream>,
+        path: &RemotePath,
+    ) -> Result<FileHandle> {
+        let (parent_dir, name) = (path.parent().unwrap(), path.file_name().unwrap());
+        let parent = client.look_up(parent_dir)?;
+        let options = nfs4_client::OpenOptions::new()
+            .access(nfs4::ShareAccess::WRITE)
+            .create();
+        let file = client.open(parent, name, &options)?;
+        let handle = file.handle.clone();
+        client.set_attr(
+            handle.clone(),
+            [FileAttribute::Size(0)].into_iter().collect(),
+        )?;
+        client.close(file)?;
+        Ok(handle)
+    }
+
+    fn cp(&mut self, server: (String, u16), source: Location, destination: Location) -> Result<()> {
+        let address = |url: &NfsUrl| (url.host.clone(), url.port);
+        let source_server = source.server.as_ref().map_or(server.clone(), address);
+        let destination_server = destination.server.as_ref().map_or(server.clone(), address);
+
+        let mut destination_client = None;
+        let client = match &destination.server {
+            Some(url) if destination_server != server => destination_client.insert(url.connect()?),
+            _ => &mut self.client,
+        };
+        let destination_handle = Self::truncate(client, &destination.path)?;
+
+        let copied = if source_server == destination_server {
+            let source_handle = client.look_up(&source.path)?;
+            client.copy(source_handle, destination_handle)?
+        } else {
+            let mut source_client = source.server.unwrap().connect()?;
+            let source_handle = source_client.look_up(&source.path)?;
+            let this_server = nfs4::NetLoc::Name(destination_server.0);
+            client.copy_from_remote(
+                &mut source_client,
+                source_handle,
+                this_server,
+                destination_handle,
+            )?
+        };
+        println!("copied {}", BinaryBytes(copied));
+        Ok(())
+    }
+
+    fn list_exports(&mut self) -> Result<()> {
+        let exports = self.client.list_exports()?;
+        if self.output == Output::Json {
+            let exports: Vec<_> = exports.into_iter().map(|e| e.into_string()).collect();
+            json::print(&exports);
+            return Ok(());
+        }
+        for export in exports {
+            println!("{export}");
+        }
+        Ok(())
+    }
+
+    fn lsfh(&mut self, fh: FileHandle) -> Result<()> {
+        let attr_request = [FileAttributeId::FileHandle].into_iter().collect();
+        for e in self.client.read_dir(fh, attr_request) {
+            let e = e?;
+            let name = &e.name;
+            let fh: &FileHandle = e.attrs.get_as(FileAttributeId::FileHandle).unwrap();
+            println!("{fh} {name}");
+        }
+
+        Ok(())
+    }
+
+    fn cat(&mut self, fh: FileHandle) -> Result<()> {
+        self.client.read_all(fh, std::io::stdout())?;
+        Ok(())
+    }
+}
+
+fn rpc_service_name(program: u32) -> &'static str {
+    match program {
+        100000 => "portmapper",
+        100003 => "nfs",
+        100005 => "mountd",
+        100021 => "nlockmgr",
+        100024 => "status",
+        100227 => "nfs_acl",
+        _ => "",
+    }
+}
+
+/// Lists what the server has registered with rpcbind, falling back to the older port mapper
+/// protocol for servers which don't speak rpcbind.
+/// Connects to the given port of the host over UDP or TCP, for the programs which take either.
+fn connect_rpc(host: &str, port: u16, udp: bool) -> io::Result<Box<dyn Transport + Send>> {
+    Ok(if udp {
+        Box::new(UdpTransport::connect((host, port))?)
+    } else {
+        Box::new(TcpStream::connect((host, port))?)
+    })
+}
+
+fn rpc_protocol(udp: bool) -> Protocol {
+    if udp {
+        Protocol::Udp
+    } else {
+        Protocol::Tcp
+    }
+}
+
+fn rpc_info(host: &str, udp: bool) -> Result<()> {
+    let mut transport = connect_rpc(host, sun_rpc_client::PORT_MAPPER_PORT, udp)?;
+    let bindings = match RpcBind::new(&mut transport, RPCBIND_VERSION_4).dump() {
+        Ok(bindings) => bindings,
+        Err(sun_rpc_client::Error::ProgramMismatch) => PortMapper::new(&mut transport)
+            .dump()?
+            .into_iter()
+            .map(|m| Binding {
+                program: m.program,
+                version: m.version,
+                netid: m.protocol.netid().into(),
+                address: m.port.to_string(),
+                owner: String::new(),
+            })
+            .collect(),
+        Err(e) => return Err(e.into()),
+    };
+
+    println!(
+        "{:>10} {:>4} {:6} {:24} service",
+        "program", "vers", "netid", "address"
+    );
+    for b in bindings {
+        println!(
+            "{:>10} {:>4} {:6} {:24} {}",
+            b.program,
+            b.version,
+            b.netid,
+            b.address,
+            rpc_service_name(b.program)
+        );
+    }
+    Ok(())
+}
+
+fn exports(host: &str, udp: bool) -> Result<()> {
+    let port_mapper = connect_rpc(host, sun_rpc_client::PORT_MAPPER_PORT, udp)?;
+    let port = PortMapper::new(port_mapper)
+        .get_port(MOUNT, MOUNT_VERSION, rpc_protocol(udp))?
+        .ok_or(sun_rpc_client::Error::ProgramUnavailable)?;
+
+    let transport = connect_rpc(host, port, udp)?;
+    let exports = MountClient::new(transport).exports()?;
+
+    println!("Export list for {host}:");
+    for export in exports {
+        let groups = if export.groups.is_empty() {
+            "(everyone)".into()
+        } else {
+            export.groups.join(",")
+        };
+        println!("{:31} {groups}", export.directory);
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    completion::complete();
+    // This one doesn't take a server, so it can't go through `Options`
+    if std::env::args_os()
+        .nth(1)
+        .is_some_and(|a| a == "completions")
+    {
+        return completion::print_script(std::env::args_os().skip(1));
+    }
+    let opts = Options::parse();
+    if opts.verbose > 0 {
+        let level = if opts.verbose > 1 {
+            tracing::Level::TRACE
+        } else {
+            tracing::Level::DEBUG
+        };
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    let host_config = match opts.host.strip_prefix('@') {
+        Some(name) => {
+            let path = opts.config.or_else(config::default_path).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "can't find the config file")
+            })?;
+            Some(config::HostConfig::load(&path, name)?)
+        }
+        None => None,
+    };
+    let from_url = opts.host.starts_with("nfs://");
+    let mut url = match &host_config {
+        Some(config) => config.url(),
+        None if from_url => opts.host.parse()?,
+        None => NfsUrl::new(opts.host, nfs4_client::NFS_PORT),
+    };
+    if let Some(port) = opts.port.filter(|_| !from_url) {
+        url.port = port;
+    }
+
+    // These don't talk to NFS
+    match opts.command {
+        Command::RpcInfo => return rpc_info(&url.host, opts.udp),
+        Command::Ping { count, probe } => {
+            return ping::ping(&url, opts.udp, count, probe, opts.output)
+        }
+        Command::Exports if opts.proto == Proto::V3 => return exports(&url.host, opts.udp),
+        _ => {}
+    }
+
+    if opts.proto == Proto::V3 {
+        let path_given = from_url || host_config.is_some_and(|c| c.path.is_some());
+        let export = match path_given {
+            true => url.path.into_string(),
+            false => opts.export,
+        };
+        return v3::run(&url.host, url.port, &export, opts.udp, opts.command);
+    }
+
+    let server = (url.host.clone(), url.port);
+    let mut builder = url.client_builder();
+    let mut id_domain = opts.id_domain;
+    if let Some(config) = host_config {
+        builder = config.configure(builder);
+        id_domain = id_domain.or(config.id_domain);
+    }
+    if let Some(minor_version) = opts.minor_version {
+        builder = builder.minor_version(minor_version);
+    }
+    if opts.protect_state {
+        builder = builder.protect_state(true);
+    }
+    if let Some(domain) = id_domain {
+        builder = builder.id_mapper(Arc::new(IdMap::from_system(domain)?));
+    }
+    if let Some(timeout) = opts.timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(ttl) = opts.attr_cache {
+        builder = builder.attr_cache(Duration::from_secs(ttl));
+    }
+    if let Some(ttl) = opts.name_cache {
+        builder = builder.name_cache(Duration::from_secs(ttl));
+    }
+    let connect_to = server.clone();
+    let connect = move || -> Result<nfs4_client::Client<TcpStream>> {
+        let mut client = builder.connect_tcp(connect_to.clone())?;
+        client.follow_referrals(TcpStream::connect);
+        let reconnect_to = connect_to.clone();
+        client.reconnect_with(move || TcpStream::connect(&reconnect_to));
+        Ok(client)
+    };
+    let mut client = connect()?;
+    for trunk in &opts.trunks {
+        let (host, port) = match trunk.rsplit_once(':').map(|(h, p)| (h, p.parse())) {
+            Some((host, Ok(port))) => (host, port),
+            _ => (trunk.as_str(), nfs4_client::NFS_PORT),
+        };
+        client.add_connection(TcpStream::connect((host, port))?)?;
+    }
+    if opts.pnfs {
+        client.enable_pnfs(TcpStream::connect);
+    }
+
+    let mut cli = Cli {
+        client,
+        root: url.path,
+        output: opts.output,
+        no_glob: opts.no_glob,
+        new_client: Box::new(connect),
+    };
+    // Leaving on Ctrl-C shouldn't leave the server holding on to our state either
+    let shutdown = cli.client.shutdown_handle();
+    ctrlc::set_handler(move || {
+        let _ = shutdown.shutdown();
+        std::process::exit(130);
+    })
+    .map_err(io::Error::other)?;
+
+    match opts.command {
+        #[cfg(feature = "fuse")]
+        Command::Mount { path, mountpoint } => {
+            let path = cli.path(path);
+            fuse::mount(cli.client, &path, &mountpoint)
+        }
+        Command::Shell => shell::run(cli),
+        command => {
+            let result = run(&mut cli, command, server);
+            if opts.stats && result.is_ok() {
+                cli.print_stats();
+            }
+            let shut_down = cli.client.shutdown();
+            result.and(shut_down)
+        }
+    }
+}
+
+/// Does one of the commands which use the client, other than the ones which take it over.
+fn run(cli: &mut Cli, command: Command, server: (String, u16)) -> Result<()> {
+    match command {
+        Command::GetAttr { path } => {
+            for path in cli.expand(cli.path(path))? {
+                cli.get_attr(path)?
+            }
+        }
+        Command::ReadDir { path, mut options } => {
+            options.long = true;
+            cli.ls(cli.path(path), &options)?
+        }
+        Command::Chown {
+            owner: (user, group),
+            path,
+        } => {
+            for path in cli.expand(cli.path(path))? {
+                cli.chown(path, user.clone(), group.clone())?
+            }
+        }
+        Command::Chmod { mode, path } => {
+            for path in cli.expand(cli.path(path))? {
+                cli.chmod(path, mode.clone())?
+            }
+        }
+        Command::Touch { path } => cli.touch(cli.path(path))?,
+        Command::GetLabel { path } => cli.get_label(cli.path(path))?,
+        Command::SetLabel {
+            path,
+            label,
+            format,
+            policy,
+        } => cli.set_label(
+            cli.path(path),
+            nfs4::SecLabel {
+                format: nfs4::LabelFormat { format, policy },
+                data: label.into_bytes(),
+            },
+        )?,
+        Command::Remove { path } => {
+            for path in cli.expand(cli.path(path))? {
+                cli.remove(path)?
+            }
+        }
+        Command::Download {
+            recursive,
+            jobs,
+            resume,
+            verify,
+            bwlimit,
+            remote,
+            local,
+        } => {
+            if let Some(bwlimit) = bwlimit {
+                cli.client
+                    .set_bandwidth_limit(Some(RateLimiter::new(bwlimit)));
+            }
+            let remote = cli.path(remote);
+            let remotes = cli.expand(remote.clone())?;
+            // What wildcards match goes into the local directory under its own name, like `cp`
+            let into_dir = remotes != [remote];
+            for remote in remotes {
+                let local = match into_dir {
+                    true => local.join(remote.file_name().unwrap()),
+                    false => local.clone(),
+                };
+                match recursive {
+                    true => cli.download_recursive(remote, local, jobs, verify)?,
+                    false => cli.download(remote, local, resume, verify)?,
+                }
+            }
+        }
+        Command::SetAttr { path, attrs } => cli.set_attr(cli.path(path), attrs)?,
+        Command::Upload {
+            recursive,
+            jobs,
+            resume,
+            bwlimit,
+            local,
+            remote,
+        } => {
+            if let Some(bwlimit) = bwlimit {
+                cli.client
+                    .set_bandwidth_limit(Some(RateLimiter::new(bwlimit)));
+            }
+            match recursive {
+                true => cli.upload_recursive(local, cli.path(remote), jobs)?,
+                false => cli.upload(local, cli.path(remote), resume)?,
+            }
+        }
+        Command::Sync {
+            reverse,
+            checksum,
+            delete,
+            dry_run,
+            jobs,
+            bwlimit,
+            local,
+            remote,
+        } => {
+            if let Some(bwlimit) = bwlimit {
+                cli.client
+                    .set_bandwidth_limit(Some(RateLimiter::new(bwlimit)));
+            }
+            let options = sync::SyncOptions {
+                checksum,
+                delete,
+                dry_run,
+                jobs,
+            };
+            let remote = cli.path(remote);
+            if reverse {
+                cli.sync_from_remote(remote, local, options)?
+            } else {
+                cli.sync_to_remote(local, remote, options)?
+            }
+        }
+        #[cfg(feature = "fuse")]
+        Command::Mount { .. } => unreachable!(),
+        Command::Shell | Command::RpcInfo | Command::Ping { .. } => unreachable!(),
+        Command::Exports => cli.list_exports()?,
+        Command::Ls { path, options } => cli.ls(cli.path(path), &options)?,
+        Command::LsFh { fh } => cli.lsfh(fh)?,
+        Command::Cat { path, fh } => {
+            let fh = match fh {
+                Some(fh) => fh,
+                None => cli.client.look_up(cli.path(path.unwrap()))?,
+            };
+            cli.cat(fh)?
+        }
+        Command::Head { lines, bytes, path } => cli.head(cli.path(path), lines, bytes)?,
+        Command::Df { path } => cli.df(cli.path(path))?,
+        Command::Quota { path } => cli.quota(cli.path(path))?,
+        Command::ServerInfo => cli.server_info()?,
+        Command::Tail {
+            lines,
+            follow,
+            sleep_interval,
+            path,
+        } => {
+            let interval = Duration::from_secs_f64(sleep_interval.max(0.0));
+            cli.tail(cli.path(path), lines, follow, interval)?
+        }
+        Command::Tree { path } => cli.tree(cli.path(path))?,
+        Command::Du { summarize, path } => cli.du(cli.path(path), summarize)?,
+        Command::Find {
+            path,
+            name,
+            file_type,
+            size,
+            mtime,
+        } => {
+            let filters = find::FindFilters {
+                name,
+                file_type,
+                size,
+                mtime,
+            };
+            cli.find(cli.path(path), &filters)?
+        }
+        Command::Edit { path } => cli.edit(cli.path(path))?,
+        Command::CompletePath { prefix } => cli.complete_path(&prefix)?,
+        Command::Bench { options } => cli.bench(options)?,
+        Command::Selftest { dir } => cli.selftest(cli.path(dir))?,
+        Command::Dd { operands } => cli.dd(dd::DdOptions::new(operands))?,
+        Command::Checksum { algo, paths } => {
+            let mut expanded = vec![];
+            for path in paths {
+                expanded.extend(cli.expand(cli.path(path))?);
+            }
+            cli.checksum(expanded, algo)?
+        }
+        Command::Watch { path } => cli.watch(cli.path(path))?,
+        Command::Cp {
+            mut source,
+            mut destination,
+        } => {
+            for location in [&mut source, &mut destination] {
+                if location.server.is_none() {
+                    location.path = cli.path(location.path.clone());
+                }
+            }
+            cli.cp(server, source, destination)?
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn bandwidths() {
+    assert_eq!(bandwidth("100"), Ok(100));
+    assert_eq!(bandwidth("500K"), Ok(500 << 10));
+    assert_eq!(bandwidth("10M"), Ok(10 << 20));
+    assert_eq!(bandwidth("10m"), Ok(10 << 20));
+    assert_eq!(bandwidth("1G"), Ok(1 << 30));
+    assert_eq!(bandwidth("1.5M"), Ok(3 << 19));
+    assert_eq!(bandwidth("10MB"), Ok(10 << 20));
+    assert_eq!(bandwidth("10MiB/s"), Ok(10 << 20));
+    assert_eq!(bandwidth("100B/s"), Ok(100));
+    assert!(bandwidth("0").is_err());
+    assert!(bandwidth("-1M").is_err());
+    assert!(bandwidth("fast").is_err());
+    assert!(bandwidth("").is_err());
+}
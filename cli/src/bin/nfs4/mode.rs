@@ -0,0 +1,172 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Modes given the way `chmod` takes them, either in octal like `644` or as symbolic changes to
+//! the current mode like `u+x,go-w`.
+
+use nfs4::{FileType, Mode};
+
+// The bits each of `u`, `g` and `o` can change, special bits included
+const USER: u32 = 0o4700;
+const GROUP: u32 = 0o2070;
+const OTHER: u32 = 0o1007;
+
+#[derive(Clone, Debug)]
+pub enum ModeChange {
+    Octal(u32),
+    Symbolic(Vec<Clause>),
+}
+
+/// One comma-separated part of a symbolic mode, like `go-w` or `u=rw+s`.
+#[derive(Clone, Debug)]
+pub struct Clause {
+    // The bits of the users it is for
+    who: u32,
+    actions: Vec<(char, String)>,
+}
+
+impl ModeChange {
+    /// Whether the new mode depends on the current one, which then has to be fetched first.
+    pub fn is_relative(&self) -> bool {
+        matches!(self, Self::Symbolic(_))
+    }
+
+    /// The mode the change turns the given one into. `X` only adds execute permission to
+    /// directories and to files someone can already execute.
+    pub fn apply(&self, mut mode: u32, is_dir: bool) -> u32 {
+        let clauses = match self {
+            Self::Octal(mode) => return *mode,
+            Self::Symbolic(clauses) => clauses,
+        };
+        for clause in clauses {
+            for (op, perms) in &clause.actions {
+                let executable = is_dir || mode & 0o111 != 0;
+                let bits = perms
+                    .chars()
+                    .map(|p| match p {
+                        'r' => 0o444,
+                        'w' => 0o222,
+                        'x' => 0o111,
+                        'X' if executable => 0o111,
+                        's' => 0o6000,
+                        't' => 0o1000,
+                        _ => 0,
+                    })
+                    .fold(0, |bits, b| bits | b)
+                    & clause.who;
+                mode = match op {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    _ => (mode & !clause.who) | bits,
+                };
+            }
+        }
+        mode
+    }
+}
+
+fn clause(s: &str) -> Result<Clause, String> {
+    let start = s
+        .find(['+', '-', '='])
+        .ok_or(format!("missing `+`, `-` or `=` in `{s}`"))?;
+    let mut who = 0;
+    for c in s[..start].chars() {
+        who |= match c {
+            'u' => USER,
+            'g' => GROUP,
+            'o' => OTHER,
+            'a' => USER | GROUP | OTHER,
+            _ => return Err(format!("invalid user `{c}` in `{s}`")),
+        };
+    }
+    if who == 0 {
+        who = USER | GROUP | OTHER;
+    }
+
+    let mut actions: Vec<(char, String)> = vec![];
+    for c in s[start..].chars() {
+        match c {
+            '+' | '-' | '=' => actions.push((c, String::new())),
+            'r' | 'w' | 'x' | 'X' | 's' | 't' => actions.last_mut().unwrap().1.push(c),
+            _ => return Err(format!("invalid permission `{c}` in `{s}`")),
+        }
+    }
+    Ok(Clause { who, actions })
+}
+
+/// Parses a mode for clap, as octal digits or comma-separated symbolic clauses.
+pub fn mode_change(s: &str) -> Result<ModeChange, String> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        return match u32::from_str_radix(s, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(ModeChange::Octal(mode)),
+            _ => Err(format!("invalid octal mode `{s}`")),
+        };
+    }
+    Ok(ModeChange::Symbolic(
+        s.split(',').map(clause).collect::<Result<_, _>>()?,
+    ))
+}
+
+/// The mode the way `ls -l` shows it, like `drwxr-xr-x`.
+pub fn symbolic_mode(file_type: Option<&FileType>, mode: u32) -> String {
+    let kind = match file_type {
+        Some(FileType::Directory | FileType::AttrDir) => 'd',
+        Some(FileType::Link) => 'l',
+        Some(FileType::Block) => 'b',
+        Some(FileType::Character) => 'c',
+        Some(FileType::Socket) => 's',
+        Some(FileType::Fifo) => 'p',
+        Some(FileType::Regular | FileType::NamedAttr) | None => '-',
+    };
+    format!("{kind}{}", Mode(mode))
+}
+
+#[test]
+fn octal() {
+    let change = mode_change("644").unwrap();
+    assert!(!change.is_relative());
+    assert_eq!(change.apply(0o777, false), 0o644);
+    assert_eq!(mode_change("4755").unwrap().apply(0, false), 0o4755);
+    assert!(mode_change("10000").is_err());
+    assert!(mode_change("8").is_err());
+}
+
+#[test]
+fn symbolic() {
+    let change = mode_change("u+x,go-w").unwrap();
+    assert!(change.is_relative());
+    assert_eq!(change.apply(0o666, false), 0o744);
+    assert_eq!(mode_change("a=r").unwrap().apply(0o777, false), 0o444);
+    // Without users it is for all of them
+    assert_eq!(mode_change("+x").unwrap().apply(0o644, false), 0o755);
+    assert_eq!(mode_change("o=").unwrap().apply(0o777, false), 0o770);
+    assert_eq!(mode_change("u=rw+x").unwrap().apply(0o000, false), 0o700);
+    assert_eq!(mode_change("u+s,g+s").unwrap().apply(0o755, false), 0o6755);
+    assert_eq!(mode_change("+t").unwrap().apply(0o777, true), 0o1777);
+    assert_eq!(mode_change("g-s").unwrap().apply(0o2775, true), 0o775);
+}
+
+#[test]
+fn conditional_execute() {
+    let change = mode_change("a+X").unwrap();
+    assert_eq!(change.apply(0o644, true), 0o755);
+    assert_eq!(change.apply(0o644, false), 0o644);
+    assert_eq!(change.apply(0o744, false), 0o755);
+}
+
+#[test]
+fn invalid_symbolic() {
+    assert!(mode_change("").is_err());
+    assert!(mode_change("u").is_err());
+    assert!(mode_change("z+x").is_err());
+    assert!(mode_change("u+q").is_err());
+    assert!(mode_change("u+x,").is_err());
+}
+
+#[test]
+fn ls_mode() {
+    assert_eq!(
+        symbolic_mode(Some(&FileType::Directory), 0o755),
+        "drwxr-xr-x"
+    );
+    assert_eq!(symbolic_mode(None, 0o644), "-rw-r--r--");
+}
@@ -0,0 +1,145 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Finding out whether a server is there and how far away it is, with calls of the NULL
+//! procedure, which servers answer without doing anything. If those are quick but everything else
+//! is slow, it is the server and not the network.
+
+use super::{json, Output};
+use nfs4::{RpcGssService, SecurityInfo};
+use nfs4_client::{NfsUrl, Probe, Result};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use sun_rpc_client::portmap::PORT_MAPPER_VERSION;
+use sun_rpc_client::{RpcClient, PORT_MAPPER, PORT_MAPPER_PORT};
+
+fn micros(time: Duration) -> u64 {
+    time.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// Makes the given number of calls, printing how long each one took like `ping` does and then
+/// the minimum, average and 99th percentile of them. With JSON output nothing is printed, the
+/// times are returned instead.
+fn round_trips(
+    name: &str,
+    count: u32,
+    output: Output,
+    mut call: impl FnMut() -> Result<()>,
+) -> Result<json::RoundTrips> {
+    let mut times = vec![];
+    for seq in 0..count {
+        let start = Instant::now();
+        call()?;
+        let time = start.elapsed();
+        if output == Output::Text {
+            println!("{name}: seq={seq} time={time:.2?}");
+        }
+        times.push(time);
+    }
+    let times_us = times.iter().copied().map(micros).collect();
+    times.sort();
+    let mut summary = None;
+    if let Some(max) = times.last() {
+        let avg = times.iter().sum::<Duration>() / times.len() as u32;
+        let p99 = times[((times.len() - 1) as f64 * 0.99).round() as usize];
+        if output == Output::Text {
+            println!(
+                "{name}: {count} calls, min {:.2?}, avg {avg:.2?}, p99 {p99:.2?}, max {max:.2?}",
+                times[0]
+            );
+        }
+        summary = Some(json::RoundTripSummary {
+            min_us: micros(times[0]),
+            avg_us: micros(avg),
+            p99_us: micros(p99),
+            max_us: micros(*max),
+        });
+    }
+    Ok(json::RoundTrips { times_us, summary })
+}
+
+fn flavor_name(flavor: &SecurityInfo) -> &'static str {
+    match flavor {
+        SecurityInfo::None => "none",
+        SecurityInfo::Sys => "sys",
+        SecurityInfo::RpcSecGss { flavor_info } => match flavor_info.service {
+            RpcGssService::None => "RPCSEC_GSS with authentication",
+            RpcGssService::Integrity => "RPCSEC_GSS with integrity",
+            RpcGssService::Privacy => "RPCSEC_GSS with privacy",
+        },
+    }
+}
+
+/// Prints which minor versions of NFSv4 the server speaks, and which security flavors it takes for
+/// the path of the URL, and returns them.
+fn probe(url: &NfsUrl, output: Output) -> Result<(Vec<u32>, Option<Vec<String>>)> {
+    let mut probe = Probe::new(TcpStream::connect((url.host.as_str(), url.port))?);
+    let mut minor_versions = vec![];
+    for minor_version in 0..=2 {
+        let supported = probe.supports_minor_version(minor_version)?;
+        if output == Output::Text {
+            let answer = if supported { "yes" } else { "no" };
+            println!("NFSv4.{minor_version}: {answer}");
+        }
+        if supported {
+            minor_versions.push(minor_version);
+        }
+    }
+    // Finding the security flavors needs a session, which NFSv4.0 doesn't have
+    let mut names = None;
+    if minor_versions.iter().any(|v| *v > 0) {
+        let mut client = url.connect()?;
+        let handle = client.look_up(&url.path)?;
+        let flavors = client.sec_info_no_name(handle)?;
+        let flavors: Vec<_> = flavors.iter().map(|f| flavor_name(f).to_owned()).collect();
+        if output == Output::Text {
+            println!("security flavors of {}: {}", url.path, flavors.join(", "));
+        }
+        names = Some(flavors);
+    }
+    Ok((minor_versions, names))
+}
+
+/// Pings the NFS program, and the port mapper if the server runs one, which isn't needed for
+/// NFSv4, so not being able to reach it is only printed.
+pub fn ping(url: &NfsUrl, udp: bool, count: u32, probe_server: bool, output: Output) -> Result<()> {
+    let nfs = format!("nfs {}:{}", url.host, url.port);
+    let mut nfs_probe = Probe::new(TcpStream::connect((url.host.as_str(), url.port))?);
+    let nfs = round_trips(&nfs, count, output, || nfs_probe.null())?;
+
+    let port_mapper = format!("portmapper {}:{PORT_MAPPER_PORT}", url.host);
+    let res = super::connect_rpc(&url.host, PORT_MAPPER_PORT, udp)
+        .map_err(nfs4_client::Error::from)
+        .and_then(|transport| {
+            let mut client = RpcClient::with_version(transport, PORT_MAPPER, PORT_MAPPER_VERSION);
+            round_trips(&port_mapper, count, output, || {
+                Ok(client.call(sun_rpc_client::NULL_PROCEDURE, ())?)
+            })
+        });
+    let mut ping = json::Ping {
+        nfs,
+        port_mapper: None,
+        port_mapper_error: None,
+        minor_versions: None,
+        security_flavors: None,
+    };
+    match res {
+        Ok(round_trips) => ping.port_mapper = Some(round_trips),
+        Err(e) => {
+            let error = std::io::Error::from(e).to_string();
+            if output == Output::Text {
+                println!("{port_mapper}: {error}");
+            }
+            ping.port_mapper_error = Some(error);
+        }
+    }
+
+    if probe_server {
+        let (minor_versions, security_flavors) = probe(url, output)?;
+        ping.minor_versions = Some(minor_versions);
+        ping.security_flavors = security_flavors;
+    }
+    if output == Output::Json {
+        json::print(&ping);
+    }
+    Ok(())
+}
@@ -0,0 +1,253 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Checking that a server does what we need of it, by using it the way the integration tests do
+//! but without a VM to run it in. Each step works in a directory of its own, made in one which is
+//! removed at the end, and a step failing doesn't stop the others.
+
+use super::Cli;
+use nfs4::{
+    FileAttribute, FileAttributeId, FileHandle, LookUpArgs, PutFhArgs, ShareAccess, ShareDeny,
+};
+use nfs4_client::{CompoundBuilder, OpenOptions, RemotePathBuf, Result};
+use std::collections::BTreeSet;
+use std::io;
+
+type Step = fn(&mut Cli, FileHandle) -> Result<()>;
+
+const STEPS: &[(&str, Step)] = &[
+    ("write and read a file", write_read),
+    ("write and read at offsets", write_read_at),
+    ("set the size", set_size),
+    ("rename", rename),
+    ("remove", remove),
+    ("list a directory", read_dir),
+    ("share reservations", share_reservations),
+    ("write if unchanged", write_if_unchanged),
+    ("see writes of another client", other_client),
+    ("compound stopping at a failure", compound),
+];
+
+fn ensure(condition: bool, message: impl Into<String>) -> Result<()> {
+    match condition {
+        true => Ok(()),
+        false => Err(io::Error::other(message.into()).into()),
+    }
+}
+
+fn test_contents(len: usize) -> Vec<u8> {
+    (0..len).map(|v| (v % 251) as u8).collect()
+}
+
+impl Cli {
+    fn file_size(&mut self, handle: FileHandle) -> Result<u64> {
+        let attrs = self.client.get_attr(handle)?.object_attributes;
+        Ok(attrs
+            .get_as::<u64>(FileAttributeId::Size)
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Removes the directory and everything in it.
+    fn remove_tree(&mut self, parent: FileHandle, name: &str) -> Result<()> {
+        let dir = self.client.look_up_from(parent.clone(), name)?;
+        let names = self
+            .client
+            .read_dir(dir.clone(), Default::default())
+            .map(|entry| Ok(entry?.name))
+            .collect::<Result<Vec<_>>>()?;
+        for entry in names {
+            match self.client.remove(dir.clone(), &entry) {
+                Err(e) if e.is_directory_not_empty() => self.remove_tree(dir.clone(), &entry)?,
+                res => {
+                    res?;
+                }
+            }
+        }
+        self.client.remove(parent, name)?;
+        Ok(())
+    }
+
+    pub fn selftest(&mut self, dir: RemotePathBuf) -> Result<()> {
+        let parent = self.client.look_up(&dir)?;
+        let name = format!("nfs4-selftest-{}", std::process::id());
+        let scratch = self
+            .client
+            .create_directory(parent.clone(), &name, Default::default())?;
+
+        let mut failed = 0;
+        for (i, (step_name, step)) in STEPS.iter().enumerate() {
+            let res = self
+                .client
+                .create_directory(scratch.clone(), &format!("step-{i}"), Default::default())
+                .and_then(|step_dir| step(self, step_dir));
+            match res {
+                Ok(()) => println!("ok   {step_name}"),
+                Err(e) => {
+                    failed += 1;
+                    println!("FAIL {step_name}: {e:?}");
+                }
+            }
+        }
+        self.remove_tree(parent, &name)?;
+
+        let message = format!("{failed} of {} steps failed", STEPS.len());
+        ensure(failed == 0, message)
+    }
+}
+
+fn write_read(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let handle = cli.client.create_file(dir, "a_file")?.handle.clone();
+    let contents = test_contents(100_000);
+    cli.client.write_all(handle.clone(), &contents[..])?;
+
+    let mut read_data = vec![];
+    cli.client.read_all(handle.clone(), &mut read_data)?;
+    ensure(
+        read_data == contents,
+        "read something other than was written",
+    )?;
+    let size = cli.file_size(handle)?;
+    ensure(
+        size == 100_000,
+        format!("the size is {size} instead of 100000"),
+    )
+}
+
+fn write_read_at(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let handle = cli.client.create_file(dir, "a_file")?.handle.clone();
+    cli.client.write_at(handle.clone(), 10, b"hello")?;
+    cli.client.write_at(handle.clone(), 0, b"abc")?;
+
+    let reply = cli.client.read_at(handle.clone(), 8, 4)?;
+    ensure(&reply.data[..] == b"\0\0he", "the hole isn't zeros")?;
+    let reply = cli.client.read_at(handle, 12, 100)?;
+    ensure(&reply.data[..] == b"llo", "read the wrong data at the end")?;
+    ensure(reply.eof, "reading to the end isn't the end of the file")
+}
+
+fn set_size(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let handle = cli.client.create_file(dir, "a_file")?.handle.clone();
+    let attrs = [FileAttribute::Size(100)].into_iter().collect();
+    cli.client.set_attr(handle.clone(), attrs)?;
+    let size = cli.file_size(handle)?;
+    ensure(size == 100, format!("the size is {size} instead of 100"))
+}
+
+fn rename(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    cli.client.create_file(dir.clone(), "a_file")?;
+    cli.client
+        .rename(dir.clone(), dir.clone(), "a_file", "b_file")?;
+    let old = cli.client.look_up_from(dir.clone(), "a_file");
+    ensure(
+        old.is_err_and(|e| e.is_not_found()),
+        "the old name is still there",
+    )?;
+    cli.client.look_up_from(dir, "b_file")?;
+    Ok(())
+}
+
+fn remove(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    cli.client.create_file(dir.clone(), "a_file")?;
+    let subdir = cli
+        .client
+        .create_directory(dir.clone(), "a_dir", Default::default())?;
+    cli.client.create_file(subdir, "a_file")?;
+
+    let res = cli.client.remove(dir.clone(), "a_dir");
+    ensure(
+        res.is_err_and(|e| e.is_directory_not_empty()),
+        "removed a directory with something in it",
+    )?;
+    cli.client.remove(dir.clone(), "a_file")?;
+    let res = cli.client.look_up_from(dir, "a_file");
+    ensure(
+        res.is_err_and(|e| e.is_not_found()),
+        "the file is still there",
+    )
+}
+
+fn read_dir(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let mut expected = BTreeSet::new();
+    for i in 0..100 {
+        let name = format!("a_file{i}");
+        cli.client.create_file(dir.clone(), &name)?;
+        expected.insert(name);
+    }
+    let actual = cli
+        .client
+        .read_dir(dir, Default::default())
+        .map(|entry| Ok(entry?.name))
+        .collect::<Result<BTreeSet<_>>>()?;
+    ensure(
+        actual == expected,
+        format!("listed {} entries instead of 100", actual.len()),
+    )
+}
+
+fn share_reservations(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let options = OpenOptions::new()
+        .access(ShareAccess::BOTH)
+        .deny(ShareDeny::WRITE)
+        .create_new();
+    let mut file = cli.client.open(dir.clone(), "a_file", &options)?;
+
+    let mut other = (cli.new_client)()?;
+    let options = OpenOptions::new().access(ShareAccess::WRITE);
+    let res = other.open(dir.clone(), "a_file", &options);
+    ensure(
+        res.is_err(),
+        "another client opened for writing what we deny",
+    )?;
+
+    cli.client
+        .open_downgrade(&mut file, ShareAccess::READ, ShareDeny::NONE)?;
+    let other_file = other.open(dir, "a_file", &options)?;
+    other.close(other_file)?;
+    cli.client.close(file)
+}
+
+fn write_if_unchanged(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let handle = cli.client.create_file(dir, "a_file")?.handle.clone();
+    let size = |size| [FileAttribute::Size(size)].into_iter().collect();
+    ensure(
+        cli.client.verify(handle.clone(), size(0))?,
+        "VERIFY of the right size fails",
+    )?;
+    cli.client
+        .write_if_unchanged(handle.clone(), size(0), 0, b"hello".to_vec())?;
+    let res = cli
+        .client
+        .write_if_unchanged(handle, size(0), 5, b"hello".to_vec());
+    ensure(res.is_err(), "wrote even though the size changed")
+}
+
+fn other_client(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let handle = cli.client.create_file(dir, "a_file")?.handle.clone();
+    let contents = test_contents(10_000);
+    let mut other = (cli.new_client)()?;
+    other.write_all(handle.clone(), &contents[..])?;
+
+    let mut read_data = vec![];
+    cli.client.read_all(handle, &mut read_data)?;
+    ensure(
+        read_data == contents,
+        "read something other than was written",
+    )
+}
+
+fn compound(cli: &mut Cli, dir: FileHandle) -> Result<()> {
+    let mut compound = CompoundBuilder::new();
+    compound.push(PutFhArgs { object: dir });
+    let look_up = compound.push(LookUpArgs {
+        object_name: "not_there".into(),
+    });
+    let reply = cli.client.send_compound(compound)?;
+    ensure(
+        reply.failed_index() == Some(look_up.index()),
+        format!(
+            "failed at {:?} with {:?}",
+            reply.failed_index(),
+            reply.status()
+        ),
+    )
+}
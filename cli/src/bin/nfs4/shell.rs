@@ -0,0 +1,268 @@
+// Copyright 2023 Remi Bernotavicius
+
+use super::{ls::ListOptions, mode::mode_change, owner_names, Cli};
+use nfs4::{FileAttributeId, FileType};
+use nfs4_client::{RemotePath, RemotePathBuf, Result};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+const HELP: &str = "\
+cd <dir>               change the remote directory
+pwd                    print the remote directory
+ls [dir]               list a remote directory
+get <remote> [local]   download a file, or each file a pattern like *.gz matches
+put <local> [remote]   upload a file
+rm <path>              remove a remote file or empty directory, or what a pattern matches
+mkdir <dir>            create a remote directory
+chown <user[:group]> <path>
+                       change the owner and group of a remote path
+chmod <mode> <path>    change the mode of a remote path, like 644 or u+x
+touch <path>           update the times of a remote file, creating it if needed
+df                     show how much space is left
+quota                  show how much space we may still use
+stats                  show what has been sent to the server so far
+help                   print this message
+exit                   leave the shell";
+
+/// Joins the given path onto the current directory, resolving `..` ourselves since the server
+/// doesn't.
+fn resolve(cwd: &RemotePath, path: &str) -> RemotePathBuf {
+    let mut resolved = if path.starts_with('/') {
+        RemotePathBuf::from("/")
+    } else {
+        cwd.to_owned()
+    };
+    for name in RemotePath::new(path).components() {
+        if name == ".." {
+            resolved.pop();
+        } else {
+            resolved.push(name);
+        }
+    }
+    resolved
+}
+
+fn readline_error(error: ReadlineError) -> nfs4_client::Error {
+    match error {
+        ReadlineError::Io(e) => e.into(),
+        e => io::Error::other(e.to_string()).into(),
+    }
+}
+
+struct ShellHelper {
+    cli: Rc<RefCell<Cli>>,
+    cwd: Rc<RefCell<RemotePathBuf>>,
+    local: FilenameCompleter,
+}
+
+impl ShellHelper {
+    fn complete_remote(&self, word: &str) -> Result<Vec<Pair>> {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let dir_path = resolve(&self.cwd.borrow(), dir);
+
+        let mut cli = self.cli.borrow_mut();
+        let handle = cli.client.look_up(dir_path)?;
+        let attr_request = [FileAttributeId::Type].into_iter().collect();
+        let mut candidates = vec![];
+        for entry in cli.client.read_dir(handle, attr_request) {
+            let entry = entry?;
+            if !entry.name.starts_with(prefix) {
+                continue;
+            }
+            let is_dir = entry.attrs.get_as(FileAttributeId::Type) == Some(&FileType::Directory);
+            let suffix = if is_dir { "/" } else { "" };
+            candidates.push(Pair {
+                display: format!("{}{suffix}", entry.name),
+                replacement: format!("{dir}{}{suffix}", entry.name),
+            });
+        }
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        Ok(candidates)
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(' ').map_or(0, |i| i + 1);
+        let previous_words: Vec<_> = line[..start].split_whitespace().collect();
+
+        // The first argument of `put` is a local path, everything else is remote
+        if previous_words.len() == 1 && previous_words[0] == "put" {
+            return self.local.complete(line, pos, ctx);
+        }
+        if previous_words.is_empty() {
+            let commands = [
+                "cd", "exit", "get", "help", "ls", "mkdir", "put", "pwd", "rm",
+            ];
+            let candidates = commands
+                .iter()
+                .filter(|c| c.starts_with(&line[..pos]))
+                .map(|c| Pair {
+                    display: c.to_string(),
+                    replacement: format!("{c} "),
+                })
+                .collect();
+            return Ok((0, candidates));
+        }
+
+        Ok((
+            start,
+            self.complete_remote(&line[start..pos]).unwrap_or_default(),
+        ))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+struct Shell {
+    cli: Rc<RefCell<Cli>>,
+    cwd: Rc<RefCell<RemotePathBuf>>,
+}
+
+impl Shell {
+    fn cd(&self, path: &str) -> Result<()> {
+        let path = resolve(&self.cwd.borrow(), path);
+        let mut cli = self.cli.borrow_mut();
+        let (_, mut attrs) = cli
+            .client
+            .look_up_with_attrs(&path, [FileAttributeId::Type].into_iter().collect())?;
+        if attrs.remove_as(FileAttributeId::Type) != Some(FileType::Directory) {
+            return Err(nfs4::StatusError::NotDir.into());
+        }
+        *self.cwd.borrow_mut() = path;
+        Ok(())
+    }
+
+    fn run_command(&self, args: &[String]) -> Result<bool> {
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        match args[..] {
+            ["cd"] => return self.cd("/").map(|()| true),
+            ["cd", path] => return self.cd(path).map(|()| true),
+            _ => {}
+        }
+
+        let cwd = self.cwd.borrow().clone();
+        let mut cli = self.cli.borrow_mut();
+        match args[..] {
+            ["pwd"] => println!("{cwd}"),
+            ["ls"] => cli.ls(cwd, &ListOptions::long())?,
+            ["ls", path] => cli.ls(resolve(&cwd, path), &ListOptions::long())?,
+            ["get", remote] => {
+                for remote in cli.expand(resolve(&cwd, remote))? {
+                    cli.download(remote, "./".into(), false, false)?
+                }
+            }
+            ["get", remote, local] => {
+                cli.download(resolve(&cwd, remote), local.into(), false, false)?
+            }
+            ["put", local] => cli.upload(local.into(), format!("{cwd}/").into(), false)?,
+            ["put", local, remote] => {
+                let mut remote_path = resolve(&cwd, remote).into_string();
+                if remote.ends_with('/') {
+                    remote_path.push('/');
+                }
+                cli.upload(local.into(), remote_path.into(), false)?
+            }
+            ["rm", path] => {
+                for path in cli.expand(resolve(&cwd, path))? {
+                    cli.remove(path)?
+                }
+            }
+            ["mkdir", path] => {
+                let path = resolve(&cwd, path);
+                let parent = cli.client.look_up(path.parent().unwrap())?;
+                let name = path.file_name().unwrap();
+                cli.client
+                    .create_directory(parent, name, Default::default())?;
+            }
+            ["chown", owner, path] => match owner_names(owner) {
+                Ok((user, group)) => cli.chown(resolve(&cwd, path), user, group)?,
+                Err(e) => println!("{e}"),
+            },
+            ["chmod", mode, path] => match mode_change(mode) {
+                Ok(change) => cli.chmod(resolve(&cwd, path), change)?,
+                Err(e) => println!("{e}"),
+            },
+            ["touch", path] => cli.touch(resolve(&cwd, path))?,
+            ["df"] => cli.df(cwd)?,
+            ["quota"] => cli.quota(cwd)?,
+            ["stats"] => cli.print_stats(),
+            ["help"] => println!("{HELP}"),
+            ["exit"] | ["quit"] => return Ok(false),
+            [] => {}
+            _ => println!("unknown command, try `help`"),
+        }
+        Ok(true)
+    }
+}
+
+pub fn run(mut cli: Cli) -> Result<()> {
+    // The shell can sit idle at the prompt for a long time
+    cli.client.start_default_lease_renewal();
+
+    let cwd = cli.root.clone();
+    let shell = Shell {
+        cli: Rc::new(RefCell::new(cli)),
+        cwd: Rc::new(RefCell::new(cwd)),
+    };
+
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(ShellHelper {
+        cli: shell.cli.clone(),
+        cwd: shell.cwd.clone(),
+        local: FilenameCompleter::new(),
+    }));
+
+    loop {
+        let prompt = format!("nfs4:{}> ", shell.cwd.borrow());
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e)),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        let Some(args) = shlex::split(&line) else {
+            println!("unbalanced quotes");
+            continue;
+        };
+        match shell.run_command(&args) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("error: {e:?}"),
+        }
+    }
+
+    drop(editor);
+    match Rc::into_inner(shell.cli) {
+        Some(cli) => cli.into_inner().client.shutdown(),
+        None => Ok(()),
+    }
+}
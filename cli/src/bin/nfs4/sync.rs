@@ -0,0 +1,637 @@
+// Copyright 2023 Remi Bernotavicius
+
+use super::{checksum, remote_attrs, set_local_attrs, walk_local, Cli};
+use indicatif::MultiProgress;
+use nfs4::{FileAttribute, FileAttributeId, FileHandle, FileType};
+use nfs4_client::{RemotePath, RemotePathBuf, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read as _};
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum EntryKind {
+    Directory,
+    File,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug)]
+struct EntryInfo {
+    kind: EntryKind,
+    size: u64,
+    modified: i64,
+    link_target: Option<String>,
+}
+
+impl EntryInfo {
+    /// `None` for a symlink whose target isn't UTF-8, which can't be made on the server.
+    fn from_local(path: &Path, metadata: &std::fs::Metadata) -> Result<Option<Self>> {
+        let kind = if metadata.is_dir() {
+            EntryKind::Directory
+        } else if metadata.is_file() {
+            EntryKind::File
+        } else if metadata.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other
+        };
+        let link_target = if kind == EntryKind::Symlink {
+            match std::fs::read_link(path)?.into_os_string().into_string() {
+                Ok(target) => Some(target),
+                Err(_) => return Ok(None),
+            }
+        } else {
+            None
+        };
+        Ok(Some(Self {
+            kind,
+            size: metadata.len(),
+            modified: metadata.mtime(),
+            link_target,
+        }))
+    }
+
+    fn from_remote(entry: &nfs4_client::WalkEntry, link_target: Option<String>) -> Self {
+        let kind = match entry.file_type() {
+            Some(FileType::Directory) => EntryKind::Directory,
+            Some(FileType::Regular) => EntryKind::File,
+            Some(FileType::Link) => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let modified = entry
+            .attrs
+            .get_as::<nfs4::Time>(FileAttributeId::TimeModify)
+            .map_or(0, |t| t.seconds);
+        Self {
+            kind,
+            size: *entry.attrs.get_as(FileAttributeId::Size).unwrap_or(&0),
+            modified,
+            link_target,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Delete(RemotePathBuf),
+    CreateDirectory(RemotePathBuf),
+    Copy(RemotePathBuf),
+    Link(RemotePathBuf),
+}
+
+#[derive(Clone, Copy)]
+pub struct SyncOptions {
+    pub checksum: bool,
+    pub delete: bool,
+    pub dry_run: bool,
+    /// How many files to copy at once
+    pub jobs: usize,
+}
+
+/// Works out what needs to happen to make the destination look like the source. Deletions come
+/// first, deepest first, followed by everything else in depth-first order. `same_contents` is
+/// asked about files which look the same based on size and modify time, when checking contents.
+fn plan(
+    source: &BTreeMap<RemotePathBuf, EntryInfo>,
+    destination: &BTreeMap<RemotePathBuf, EntryInfo>,
+    options: SyncOptions,
+    mut same_contents: impl FnMut(&RemotePath) -> Result<bool>,
+) -> Result<Vec<Action>> {
+    let mut deletes = vec![];
+    let mut actions = vec![];
+
+    for (path, dest) in destination {
+        let replaced = source.get(path).is_some_and(|s| s.kind != dest.kind);
+        if replaced || (options.delete && !source.contains_key(path)) {
+            deletes.push(Action::Delete(path.clone()));
+        }
+    }
+
+    for (path, src) in source {
+        let dest = destination.get(path).filter(|d| d.kind == src.kind);
+        match src.kind {
+            EntryKind::Directory if dest.is_none() => {
+                actions.push(Action::CreateDirectory(path.clone()))
+            }
+            EntryKind::File => {
+                let changed = match dest {
+                    None => true,
+                    Some(dest) if dest.size != src.size || dest.modified != src.modified => true,
+                    Some(_) => options.checksum && !same_contents(path)?,
+                };
+                if changed {
+                    actions.push(Action::Copy(path.clone()));
+                }
+            }
+            EntryKind::Symlink if dest.is_none_or(|d| d.link_target != src.link_target) => {
+                if dest.is_some() {
+                    deletes.push(Action::Delete(path.clone()));
+                }
+                actions.push(Action::Link(path.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    deletes.sort_by(|a, b| match (a, b) {
+        (Action::Delete(a), Action::Delete(b)) => b.cmp(a),
+        _ => unreachable!(),
+    });
+    deletes.extend(actions);
+    Ok(deletes)
+}
+
+fn print_action(action: &Action) {
+    match action {
+        Action::Delete(path) => println!("delete {path}"),
+        Action::CreateDirectory(path) => println!("mkdir {path}"),
+        Action::Copy(path) => println!("copy {path}"),
+        Action::Link(path) => println!("link {path}"),
+    }
+}
+
+/// What is under the given local directory. Entries with names or symlink targets which aren't
+/// UTF-8 can't be on the server, so they are left out with a warning.
+fn local_entries(root: &Path) -> Result<BTreeMap<RemotePathBuf, EntryInfo>> {
+    let mut entries = vec![];
+    if root.exists() {
+        walk_local(root, PathBuf::new(), &mut entries)?;
+    }
+    let mut local = BTreeMap::new();
+    for (path, metadata) in entries {
+        let local_path = root.join(&path);
+        let Some(remote_path) = path.iter().map(|c| c.to_str()).collect() else {
+            eprintln!("{}: skipped, the name isn't UTF-8", local_path.display());
+            continue;
+        };
+        let Some(info) = EntryInfo::from_local(&local_path, &metadata)? else {
+            eprintln!("{}: skipped, the target isn't UTF-8", local_path.display());
+            continue;
+        };
+        local.insert(remote_path, info);
+    }
+    Ok(local)
+}
+
+/// Compares what the reader produces to what is written to it, without keeping either around.
+struct CompareWriter<R> {
+    reader: R,
+    same: bool,
+}
+
+impl<R: io::Read> io::Write for CompareWriter<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut expected = vec![0; buf.len()];
+        let n = self.reader.read(&mut expected)?;
+        if n == 0 || expected[..n] != buf[..n] {
+            self.same = false;
+            return Ok(buf.len());
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Cli {
+    fn remote_entries(
+        &mut self,
+        root: FileHandle,
+    ) -> Result<(
+        BTreeMap<RemotePathBuf, EntryInfo>,
+        HashMap<RemotePathBuf, FileHandle>,
+    )> {
+        let attr_request = [FileAttributeId::Size, FileAttributeId::TimeModify]
+            .into_iter()
+            .collect();
+        let mut walk_entries = vec![];
+        for entry in self.client.walk(root.clone(), attr_request) {
+            match entry {
+                Ok(entry) => walk_entries.push(entry),
+                Err(e) => return Err(e.error),
+            }
+        }
+
+        let mut entries = BTreeMap::new();
+        let mut handles = HashMap::from([(RemotePathBuf::new(), root)]);
+        for entry in walk_entries {
+            let link_target = if entry.file_type() == Some(&FileType::Link) {
+                Some(self.client.read_link(entry.handle.clone())?)
+            } else {
+                None
+            };
+            entries.insert(
+                entry.path.clone(),
+                EntryInfo::from_remote(&entry, link_target),
+            );
+            handles.insert(entry.path, entry.handle);
+        }
+        Ok((entries, handles))
+    }
+
+    fn same_contents(&mut self, local: &Path, remote: FileHandle) -> Result<bool> {
+        let mut compare = CompareWriter {
+            reader: std::fs::File::open(local)?,
+            same: true,
+        };
+        self.client.read_all(remote, &mut compare)?;
+        Ok(compare.same && compare.reader.read(&mut [0])? == 0)
+    }
+
+    fn remove_remote(&mut self, parent: FileHandle, name: &str, handle: FileHandle) -> Result<()> {
+        match self.client.remove(parent.clone(), name) {
+            Err(e) if e.is_directory_not_empty() => {
+                let children: Vec<_> = self
+                    .client
+                    .read_dir(
+                        handle.clone(),
+                        [FileAttributeId::FileHandle].into_iter().collect(),
+                    )
+                    .collect::<Result<_>>()?;
+                for child in children {
+                    let child_handle = child.attrs.get_as(FileAttributeId::FileHandle).cloned();
+                    let child_handle = match child_handle {
+                        Some(h) => h,
+                        None => self.client.look_up_from(handle.clone(), &child.name)?,
+                    };
+                    self.remove_remote(handle.clone(), &child.name, child_handle)?;
+                }
+                self.client.remove(parent, name)?;
+            }
+            res => {
+                res?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn sync_to_remote(
+        &mut self,
+        local: PathBuf,
+        remote: RemotePathBuf,
+        options: SyncOptions,
+    ) -> Result<()> {
+        let source = local_entries(&local)?;
+        let root = if options.dry_run {
+            self.client.look_up(&remote).ok()
+        } else {
+            Some(self.look_up_or_create_dir(&remote)?)
+        };
+        let (destination, mut handles) = match root {
+            Some(root) => self.remote_entries(root)?,
+            None => Default::default(),
+        };
+
+        let actions = plan(&source, &destination, options, |path| {
+            let handle = handles[path].clone();
+            self.same_contents(&local.join(path.to_local()), handle)
+        })?;
+
+        // Files are copied once everything else is done, so that several can go at once
+        let mut copies = vec![];
+        for action in &actions {
+            print_action(action);
+            if options.dry_run {
+                continue;
+            }
+
+            match action {
+                Action::Delete(path) => {
+                    let parent = handles[path.parent().unwrap()].clone();
+                    let name = path.file_name().unwrap();
+                    if let Some(handle) = handles.remove(path) {
+                        self.remove_remote(parent, name, handle)?;
+                    }
+                }
+                Action::CreateDirectory(path) => {
+                    let parent = handles[path.parent().unwrap()].clone();
+                    let name = path.file_name().unwrap();
+                    let handle = self.create_or_open_dir(parent, name)?;
+                    handles.insert(path.clone(), handle);
+                }
+                Action::Copy(path) => {
+                    let parent = handles[path.parent().unwrap()].clone();
+                    copies.push((path, parent, handles.get(path).cloned()));
+                }
+                Action::Link(path) => {
+                    let parent = handles[path.parent().unwrap()].clone();
+                    let name = path.file_name().unwrap();
+                    let target = source[path].link_target.as_ref().unwrap();
+                    let handle =
+                        self.client
+                            .create_symlink(parent, name, target, Default::default())?;
+                    handles.insert(path.clone(), handle);
+                }
+            }
+        }
+
+        let progress = MultiProgress::new();
+        self.run_jobs(
+            options.jobs,
+            copies,
+            &progress,
+            |client, (path, parent, handle), bar| {
+                bar.reset();
+                bar.set_length(source[path].size);
+                bar.set_message(path.to_string());
+                let handle = match handle {
+                    Some(handle) => {
+                        let size = [FileAttribute::Size(0)].into_iter().collect();
+                        client.set_attr(handle.clone(), size)?;
+                        handle
+                    }
+                    None => {
+                        let name = path.file_name().unwrap();
+                        client.create_file(parent, name)?.handle.clone()
+                    }
+                };
+                let local_path = local.join(path.to_local());
+                let file = std::fs::File::open(&local_path)?;
+                let metadata = file.metadata()?;
+                client.write_all(handle.clone(), bar.wrap_read(file))?;
+                client.set_attr(handle.clone(), remote_attrs(&metadata))?;
+                if options.checksum {
+                    checksum::verify(client, handle, &local_path, &remote.join(path))?;
+                }
+                Ok(())
+            },
+        )?;
+
+        if !options.dry_run {
+            // Directories get their attributes last, and deepest first, since creating things in
+            // them changes their modify time.
+            let mut directories: Vec<_> = source
+                .iter()
+                .filter(|(_, info)| info.kind == EntryKind::Directory)
+                .map(|(path, _)| path.clone())
+                .collect();
+            directories.insert(0, RemotePathBuf::new());
+            for path in directories.into_iter().rev() {
+                let metadata = std::fs::metadata(local.join(path.to_local()))?;
+                self.client
+                    .set_attr(handles[&path].clone(), remote_attrs(&metadata))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn sync_from_remote(
+        &mut self,
+        remote: RemotePathBuf,
+        local: PathBuf,
+        options: SyncOptions,
+    ) -> Result<()> {
+        let root = self.client.look_up(&remote)?;
+        let (source, handles) = self.remote_entries(root.clone())?;
+        let destination = local_entries(&local)?;
+
+        let actions = plan(&source, &destination, options, |path| {
+            let handle = handles[path].clone();
+            self.same_contents(&local.join(path.to_local()), handle)
+        })?;
+
+        if !options.dry_run {
+            std::fs::create_dir_all(&local)?;
+        }
+
+        // Files are copied once everything else is done, so that several can go at once
+        let mut copies = vec![];
+        for action in &actions {
+            print_action(action);
+            if options.dry_run {
+                continue;
+            }
+
+            match action {
+                Action::Delete(path) => {
+                    let local_path = local.join(path.to_local());
+                    match std::fs::symlink_metadata(&local_path) {
+                        Ok(m) if m.is_dir() => std::fs::remove_dir_all(&local_path)?,
+                        Ok(_) => std::fs::remove_file(&local_path)?,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Action::CreateDirectory(path) => {
+                    std::fs::create_dir_all(local.join(path.to_local()))?
+                }
+                Action::Copy(path) => copies.push((path, handles[path].clone())),
+                Action::Link(path) => {
+                    let target = source[path].link_target.as_ref().unwrap();
+                    std::os::unix::fs::symlink(target, local.join(path.to_local()))?;
+                }
+            }
+        }
+
+        let progress = MultiProgress::new();
+        self.run_jobs(
+            options.jobs,
+            copies,
+            &progress,
+            |client, (path, handle), bar| {
+                bar.reset();
+                bar.set_length(source[path].size);
+                bar.set_message(path.to_string());
+                let local_path = local.join(path.to_local());
+                let file = std::fs::File::create(&local_path)?;
+                client.read_all(handle.clone(), bar.wrap_write(&file))?;
+                let attrs = client.get_attr(handle.clone())?;
+                set_local_attrs(&file, &attrs.object_attributes)?;
+                if options.checksum {
+                    checksum::verify(client, handle, &local_path, &remote.join(path))?;
+                }
+                Ok(())
+            },
+        )?;
+
+        if !options.dry_run {
+            let mut directories: Vec<_> = source
+                .iter()
+                .filter(|(_, info)| info.kind == EntryKind::Directory)
+                .map(|(path, _)| path.clone())
+                .collect();
+            directories.insert(0, RemotePathBuf::new());
+            for path in directories.into_iter().rev() {
+                let attrs = self.client.get_attr(handles[&path].clone())?;
+                let dir = std::fs::File::open(local.join(path.to_local()))?;
+                set_local_attrs(&dir, &attrs.object_attributes)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn entries(list: &[(&str, EntryKind, u64, i64)]) -> BTreeMap<RemotePathBuf, EntryInfo> {
+    list.iter()
+        .map(|&(path, kind, size, modified)| {
+            let info = EntryInfo {
+                kind,
+                size,
+                modified,
+                link_target: None,
+            };
+            (path.into(), info)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+const OPTIONS: SyncOptions = SyncOptions {
+    checksum: false,
+    delete: false,
+    dry_run: false,
+    jobs: 1,
+};
+
+#[test]
+fn plan_copies_what_changed() {
+    use EntryKind::*;
+
+    let source = entries(&[
+        ("dir", Directory, 0, 1),
+        ("dir/bigger", File, 10, 1),
+        ("dir/newer", File, 5, 2),
+        ("dir/same", File, 5, 1),
+        ("new_dir", Directory, 0, 1),
+        ("new_file", File, 5, 1),
+    ]);
+    let destination = entries(&[
+        ("dir", Directory, 0, 1),
+        ("dir/bigger", File, 5, 1),
+        ("dir/newer", File, 5, 1),
+        ("dir/same", File, 5, 1),
+        ("old", File, 5, 1),
+    ]);
+
+    let actions = plan(&source, &destination, OPTIONS, |_| unreachable!()).unwrap();
+    assert_eq!(
+        actions,
+        [
+            Action::Copy("dir/bigger".into()),
+            Action::Copy("dir/newer".into()),
+            Action::CreateDirectory("new_dir".into()),
+            Action::Copy("new_file".into()),
+        ]
+    );
+
+    // Only files which look the same have their contents checked
+    let options = SyncOptions {
+        checksum: true,
+        ..OPTIONS
+    };
+    let mut checked = vec![];
+    let actions = plan(&source, &destination, options, |path| {
+        checked.push(path.to_owned());
+        Ok(false)
+    })
+    .unwrap();
+    assert_eq!(checked, [RemotePathBuf::from("dir/same")]);
+    assert!(actions.contains(&Action::Copy("dir/same".into())));
+}
+
+#[test]
+fn plan_deletes_only_with_delete() {
+    use EntryKind::*;
+
+    let source = entries(&[("file", File, 5, 1), ("replaced", Directory, 0, 1)]);
+    let destination = entries(&[
+        ("file", File, 5, 1),
+        ("old", Directory, 0, 1),
+        ("old/file", File, 5, 1),
+        ("replaced", File, 5, 1),
+    ]);
+
+    // What is replaced by something of another kind is deleted either way
+    let actions = plan(&source, &destination, OPTIONS, |_| Ok(true)).unwrap();
+    assert_eq!(
+        actions,
+        [
+            Action::Delete("replaced".into()),
+            Action::CreateDirectory("replaced".into()),
+        ]
+    );
+
+    let options = SyncOptions {
+        delete: true,
+        ..OPTIONS
+    };
+    let actions = plan(&source, &destination, options, |_| Ok(true)).unwrap();
+    assert_eq!(
+        actions,
+        [
+            Action::Delete("replaced".into()),
+            Action::Delete("old/file".into()),
+            Action::Delete("old".into()),
+            Action::CreateDirectory("replaced".into()),
+        ]
+    );
+}
+
+#[test]
+fn local_names_not_utf8() {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let local = tempdir::TempDir::new("sync").unwrap();
+    let not_utf8 = std::ffi::OsStr::from_bytes(b"\xff");
+    std::fs::write(local.path().join("file"), "hello").unwrap();
+    std::fs::write(local.path().join(not_utf8), "hello").unwrap();
+    std::os::unix::fs::symlink(not_utf8, local.path().join("link")).unwrap();
+
+    let entries = local_entries(local.path()).unwrap();
+    assert_eq!(
+        entries.keys().collect::<Vec<_>>(),
+        [&RemotePathBuf::from("file")]
+    );
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    use nfs4_server::{memory::MemoryFs, Server};
+    use std::net::{TcpListener, TcpStream};
+
+    let mut files = MemoryFs::new();
+    files.make_dir("remote").unwrap();
+    files.write_file("remote/old", "bye").unwrap();
+    let server = Server::with_file_system(files);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let serving = server.clone();
+    std::thread::spawn(move || serving.serve(listener));
+    let connect = move || -> Result<_> { nfs4_client::Client::new(TcpStream::connect(address)?) };
+    let mut cli = Cli {
+        client: connect().unwrap(),
+        root: "/".into(),
+        output: super::Output::Text,
+        no_glob: false,
+        new_client: Box::new(connect),
+    };
+
+    let local = tempdir::TempDir::new("sync").unwrap();
+    std::fs::write(local.path().join("new"), "hello").unwrap();
+    let options = SyncOptions {
+        delete: true,
+        dry_run: true,
+        ..OPTIONS
+    };
+    cli.sync_to_remote(local.path().into(), "/remote".into(), options)
+        .unwrap();
+    let read = |path| server.update_file_system(|files| files.read_file(path));
+    assert_eq!(read("remote/old").unwrap(), b"bye");
+    assert!(read("remote/new").is_err());
+
+    let options = SyncOptions {
+        dry_run: false,
+        ..options
+    };
+    cli.sync_to_remote(local.path().into(), "/remote".into(), options)
+        .unwrap();
+    assert_eq!(read("remote/new").unwrap(), b"hello");
+    assert!(read("remote/old").is_err());
+}
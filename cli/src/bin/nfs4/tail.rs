@@ -0,0 +1,132 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Printing the start of a remote file like `head`, or the end of it like `tail` along with what
+//! gets appended to it after with `-f`.
+
+use super::Cli;
+use nfs4::{Change, FileAttributeId, FileHandle};
+use nfs4_client::{RemotePathBuf, Result};
+use std::io::{self, Write as _};
+use std::time::Duration;
+
+// How much is read at a time going back from the end looking for the start of the last lines
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Where the last `lines` lines of the data start, if it has that many. A newline at the very end
+/// doesn't start another line.
+fn start_of_last_lines(data: &[u8], lines: usize) -> Option<usize> {
+    if lines == 0 {
+        return Some(data.len());
+    }
+    let body = data.strip_suffix(b"\n").unwrap_or(data);
+    body.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(lines - 1)
+        .map(|(i, _)| i + 1)
+}
+
+impl Cli {
+    /// Reads the given range of the file, less of it if the file ends first.
+    pub fn read_range(&mut self, handle: &FileHandle, start: u64, end: u64) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        while start + (data.len() as u64) < end {
+            let offset = start + data.len() as u64;
+            let res = self
+                .client
+                .read_at(handle.clone(), offset, (end - offset) as usize)?;
+            data.extend(&res.data);
+            if res.eof || res.data.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Prints the first `lines` lines of the file, or the first `bytes` bytes if given.
+    pub fn head(&mut self, path: RemotePathBuf, lines: usize, bytes: Option<u64>) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let mut stdout = io::stdout().lock();
+        let mut offset = 0;
+        let mut lines_left = lines;
+        loop {
+            let end = bytes.map_or(offset + CHUNK_SIZE, |b| b.min(offset + CHUNK_SIZE));
+            if offset >= end || (bytes.is_none() && lines_left == 0) {
+                break;
+            }
+            let data = self.read_range(&handle, offset, end)?;
+            if data.is_empty() {
+                break;
+            }
+            let mut len = data.len();
+            if bytes.is_none() {
+                let newlines: Vec<usize> = (0..data.len()).filter(|i| data[*i] == b'\n').collect();
+                if newlines.len() >= lines_left {
+                    len = newlines[lines_left - 1] + 1;
+                }
+                lines_left -= newlines.len().min(lines_left);
+            }
+            stdout.write_all(&data[..len])?;
+            offset += data.len() as u64;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn size_and_change(&mut self, handle: &FileHandle) -> Result<(u64, Option<Change>)> {
+        let mut attrs = self.client.get_attr(handle.clone())?.object_attributes;
+        let size = attrs.remove_as(FileAttributeId::Size).unwrap_or(0);
+        Ok((size, attrs.remove_as(FileAttributeId::Change)))
+    }
+
+    /// Prints the last `lines` lines of the file. With `follow` it then keeps checking the file
+    /// every `interval` and prints what was added to it, starting over if it gets shorter.
+    pub fn tail(
+        &mut self,
+        path: RemotePathBuf,
+        lines: usize,
+        follow: bool,
+        interval: Duration,
+    ) -> Result<()> {
+        let handle = self.client.look_up(&path)?;
+        let (size, mut change) = self.size_and_change(&handle)?;
+
+        let mut start = size;
+        let mut data = vec![];
+        while start > 0 && start_of_last_lines(&data, lines).is_none() {
+            let chunk_start = start.saturating_sub(CHUNK_SIZE);
+            let mut chunk = self.read_range(&handle, chunk_start, start)?;
+            chunk.append(&mut data);
+            data = chunk;
+            start = chunk_start;
+        }
+        let from = start_of_last_lines(&data, lines).unwrap_or(0);
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&data[from..])?;
+        stdout.flush()?;
+
+        if !follow {
+            return Ok(());
+        }
+        let mut offset = size;
+        loop {
+            std::thread::sleep(interval);
+            let (size, new_change) = self.size_and_change(&handle)?;
+            if new_change.is_some() && new_change == change {
+                continue;
+            }
+            change = new_change;
+            if size < offset {
+                eprintln!("{path}: file truncated");
+                offset = 0;
+            }
+            if size > offset {
+                let data = self.read_range(&handle, offset, size)?;
+                offset += data.len() as u64;
+                stdout.write_all(&data)?;
+                stdout.flush()?;
+            }
+        }
+    }
+}
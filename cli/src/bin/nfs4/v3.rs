@@ -0,0 +1,177 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! The commands which also work against NFSv3 servers.
+
+use super::{connect_rpc, rpc_protocol, Command};
+use chrono::{offset::TimeZone as _, Local};
+use hex::ToHex as _;
+use indicatif::{ProgressBar, ProgressStyle};
+use nfs3::{FileHandle, StatusError};
+use nfs3_client::Client;
+use nfs4_client::{RemotePathBuf, Result};
+use std::io;
+use std::path::PathBuf;
+use sun_rpc_client::mount::{MountClient, MountResult, MOUNT, MOUNT_VERSION};
+use sun_rpc_client::portmap::PortMapper;
+use sun_rpc_client::Transport;
+
+fn nfs3_error(error: nfs3_client::Error) -> nfs4_client::Error {
+    match error {
+        nfs3_client::Error::Io(e) => e.into(),
+        nfs3_client::Error::SunRpc(e) => e.into(),
+        e => io::Error::other(format!("{e:?}")).into(),
+    }
+}
+
+/// Gets the root handle of the export from the server's MOUNT service.
+fn mount(host: &str, export: &str, udp: bool) -> Result<FileHandle> {
+    let port_mapper = connect_rpc(host, sun_rpc_client::PORT_MAPPER_PORT, udp)?;
+    let port = PortMapper::new(port_mapper)
+        .get_port(MOUNT, MOUNT_VERSION, rpc_protocol(udp))?
+        .ok_or(sun_rpc_client::Error::ProgramUnavailable)?;
+
+    let transport = connect_rpc(host, port, udp)?;
+    match MountClient::new(transport).mount(export)? {
+        MountResult::Ok(ok) => Ok(FileHandle(ok.handle)),
+        e => Err(io::Error::other(format!("mounting {export} failed: {e:?}")).into()),
+    }
+}
+
+struct Cli {
+    client: Client<Box<dyn Transport + Send>>,
+}
+
+impl Cli {
+    fn get_attr(&mut self, path: RemotePathBuf) -> nfs3_client::Result<()> {
+        let handle = self.client.look_up(path.as_str())?;
+        let reply = self.client.get_attr(handle)?;
+        println!("{reply:#?}");
+        Ok(())
+    }
+
+    fn read_dir(&mut self, path: RemotePathBuf) -> nfs3_client::Result<()> {
+        let handle = self.client.look_up(path.as_str())?;
+        let entries = self
+            .client
+            .read_dir(handle)
+            .collect::<nfs3_client::Result<Vec<_>>>()?;
+        for e in entries {
+            let Some(attrs) = &e.attributes else {
+                println!("{}", e.name);
+                continue;
+            };
+            let modify = Local
+                .timestamp_opt(attrs.time_modify.seconds.into(), attrs.time_modify.nseconds)
+                .unwrap()
+                .to_rfc2822();
+            println!(
+                "{:o} {:3} {:5} {:10} {modify:31} {}",
+                attrs.mode, attrs.num_links, attrs.uid, attrs.size, e.name
+            );
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, path: RemotePathBuf) -> nfs3_client::Result<()> {
+        let (parent_dir, name) = (path.parent().unwrap(), path.file_name().unwrap());
+        let parent = self.client.look_up(parent_dir.as_str())?;
+        match self.client.remove(parent.clone(), name) {
+            // Directories have their own procedure
+            Err(e) if e.status() == Some(StatusError::IsDir) => {
+                self.client.remove_directory(parent, name)
+            }
+            res => res,
+        }
+    }
+
+    fn download(&mut self, remote: RemotePathBuf, local: PathBuf) -> nfs3_client::Result<()> {
+        let local_file = if local.to_string_lossy().ends_with('/') {
+            local.join(remote.file_name().unwrap())
+        } else {
+            local
+        };
+
+        let handle = self.client.look_up(remote.as_str())?;
+        let size = self.client.get_attr(handle.clone())?.size;
+
+        let progress = ProgressBar::new(size).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        );
+        let file = std::fs::File::create(local_file)?;
+        self.client.read_all(handle, progress.wrap_write(file))?;
+        Ok(())
+    }
+
+    fn upload(&mut self, local: PathBuf, remote: RemotePathBuf) -> nfs3_client::Result<()> {
+        let (parent_dir, name) = if remote.as_str().ends_with('/') {
+            (&*remote, local.file_name().unwrap().to_str().unwrap())
+        } else {
+            (remote.parent().unwrap(), remote.file_name().unwrap())
+        };
+
+        let parent = self.client.look_up(parent_dir.as_str())?;
+        let handle = self.client.create_file(parent, name)?;
+
+        let file = std::fs::File::open(local)?;
+        let progress = ProgressBar::new(file.metadata()?.len()).with_style(
+            ProgressStyle::with_template("{wide_bar} {percent}% {binary_bytes_per_sec}").unwrap(),
+        );
+        self.client.write_all(handle, progress.wrap_read(file))?;
+        Ok(())
+    }
+
+    fn lsfh(&mut self, fh: FileHandle) -> nfs3_client::Result<()> {
+        for e in self.client.read_dir(fh) {
+            let e = e?;
+            let fhstr: String = e.handle.map(|fh| fh.0.encode_hex()).unwrap_or_default();
+            println!("{fhstr} {}", e.name);
+        }
+        Ok(())
+    }
+
+    fn cat(&mut self, fh: FileHandle) -> nfs3_client::Result<()> {
+        self.client.read_all(fh, std::io::stdout())
+    }
+}
+
+pub fn run(host: &str, port: u16, export: &str, udp: bool, command: Command) -> Result<()> {
+    let root = mount(host, export, udp)?;
+    let transport = connect_rpc(host, port, udp)?;
+    let client = Client::new(transport, root).map_err(nfs3_error)?;
+
+    let mut cli = Cli { client };
+    let res = match command {
+        Command::GetAttr { path } => cli.get_attr(path),
+        Command::ReadDir { path, .. } => cli.read_dir(path),
+        Command::Remove { path } => cli.remove(path),
+        Command::Download {
+            recursive: false,
+            resume: false,
+            verify: false,
+            remote,
+            local,
+            ..
+        } => cli.download(remote, local),
+        Command::Upload {
+            recursive: false,
+            resume: false,
+            local,
+            remote,
+            ..
+        } => cli.upload(local, remote),
+        Command::Ls { path, .. } => {
+            let handle = cli.client.look_up(path.as_str()).map_err(nfs3_error)?;
+            cli.lsfh(handle)
+        }
+        Command::LsFh { fh } => cli.lsfh(FileHandle(fh.0.to_vec())),
+        Command::Cat { fh: Some(fh), .. } => cli.cat(FileHandle(fh.0.to_vec())),
+        Command::Cat {
+            path: Some(path), ..
+        } => {
+            let handle = cli.client.look_up(path.as_str()).map_err(nfs3_error)?;
+            cli.cat(handle)
+        }
+        _ => return Err(io::Error::other("command not supported over NFSv3").into()),
+    };
+    res.map_err(nfs3_error)
+}
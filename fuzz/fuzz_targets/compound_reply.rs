@@ -0,0 +1,14 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Decodes arbitrary bytes as an RPC message carrying a COMPOUND reply, like a record received
+//! from the server. Anything is fine but panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nfs4::CompoundRes;
+use sun_rpc::Message;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_xdr::from_bytes::<_, Message<CompoundRes>>(data);
+});
@@ -0,0 +1,18 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Decodes arbitrary bytes as file attributes. What decodes has to encode to something which
+//! decodes to the same attributes again, unknown ones included.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nfs4::FileAttributes;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(attrs) = serde_xdr::from_bytes::<_, FileAttributes>(data) else {
+        return;
+    };
+    let encoded = serde_xdr::to_bytes(&attrs).unwrap();
+    let decoded: FileAttributes = serde_xdr::from_bytes(&encoded).unwrap();
+    assert_eq!(decoded, attrs);
+});
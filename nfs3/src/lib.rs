@@ -0,0 +1,513 @@
+// Copyright 2023 Remi Bernotavicius
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{
+    de::Deserializer,
+    ser::{SerializeStruct as _, Serializer},
+    Deserialize, Serialize,
+};
+use xdr_extras::{DeserializeWithDiscriminant, SerializeWithDiscriminant};
+
+pub const NFS: u32 = 100003;
+pub const NFS_VERSION: u32 = 3;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug, IntoPrimitive)]
+#[repr(u32)]
+pub enum Procedure {
+    Null = 0,
+    GetAttr = 1,
+    SetAttr = 2,
+    LookUp = 3,
+    Access = 4,
+    ReadLink = 5,
+    Read = 6,
+    Write = 7,
+    Create = 8,
+    MkDir = 9,
+    SymLink = 10,
+    MkNod = 11,
+    Remove = 12,
+    RmDir = 13,
+    Rename = 14,
+    Link = 15,
+    ReadDir = 16,
+    ReadDirPlus = 17,
+    FsStat = 18,
+    FsInfo = 19,
+    PathConf = 20,
+    Commit = 21,
+}
+
+#[derive(
+    SerializeWithDiscriminant,
+    DeserializeWithDiscriminant,
+    PartialEq,
+    Eq,
+    Copy,
+    Clone,
+    Debug,
+    TryFromPrimitive,
+)]
+#[repr(u32)]
+pub enum StatusError {
+    Perm = 1,
+    NoEnt = 2,
+    Io = 5,
+    NxIo = 6,
+    Access = 13,
+    Exist = 17,
+    XDev = 18,
+    NoDev = 19,
+    NotDir = 20,
+    IsDir = 21,
+    Inval = 22,
+    FBig = 27,
+    NoSpc = 28,
+    RoFs = 30,
+    MLink = 31,
+    NameTooLong = 63,
+    NotEmpty = 66,
+    DQuot = 69,
+    Stale = 70,
+    Remote = 71,
+    BadHandle = 10001,
+    NotSync = 10002,
+    BadCookie = 10003,
+    NotSupported = 10004,
+    TooSmall = 10005,
+    ServerFault = 10006,
+    BadType = 10007,
+    JukeBox = 10008,
+}
+
+/// The result of every procedure, which on failure often still carries some attributes.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum StatusResult<T, F = ()> {
+    Ok(T),
+    Err(StatusError, F),
+}
+
+impl<T, F> Serialize for StatusResult<T, F>
+where
+    T: Serialize,
+    F: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("StatusResult", 2)?;
+        match self {
+            Self::Ok(v) => {
+                state.serialize_field("discriminant", &0u32)?;
+                state.serialize_field("ok", v)?;
+            }
+            Self::Err(e, f) => {
+                state.serialize_field("error", e)?;
+                state.serialize_field("fail", f)?;
+            }
+        }
+        state.end()
+    }
+}
+
+impl<'de, T, F> Deserialize<'de> for StatusResult<T, F>
+where
+    T: Deserialize<'de>,
+    F: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor<T, F>(std::marker::PhantomData<(T, F)>);
+
+        impl<'de, T, F> serde::de::Visitor<'de> for Visitor<T, F>
+        where
+            T: Deserialize<'de>,
+            F: Deserialize<'de>,
+        {
+            type Value = StatusResult<T, F>;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("StatusResult")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let disc: u32 = seq
+                    .next_element()?
+                    .ok_or(serde::de::Error::custom("expected discriminant"))?;
+                if disc == 0 {
+                    Ok(StatusResult::Ok(
+                        seq.next_element()?
+                            .ok_or(serde::de::Error::custom("expected value"))?,
+                    ))
+                } else {
+                    let error: StatusError = disc.try_into().map_err(|_| {
+                        serde::de::Error::custom(format!(
+                            "unexpected value {disc:?} for StatusError"
+                        ))
+                    })?;
+                    let fail = seq
+                        .next_element()?
+                        .ok_or(serde::de::Error::custom("expected value"))?;
+                    Ok(StatusResult::Err(error, fail))
+                }
+            }
+        }
+
+        deserializer.deserialize_struct(
+            "StatusResult",
+            &["disc", "value"],
+            Visitor(std::marker::PhantomData),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
+pub struct FileHandle(#[serde(with = "serde_bytes")] pub Vec<u8>);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Verifier(pub u64);
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct CookieVerifier(pub u64);
+
+#[derive(
+    SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Copy, Clone, Debug,
+)]
+#[repr(u32)]
+pub enum FileType {
+    Regular = 1,
+    Directory = 2,
+    Block = 3,
+    Character = 4,
+    Link = 5,
+    Socket = 6,
+    Fifo = 7,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Time {
+    pub seconds: u32,
+    pub nseconds: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct SpecData {
+    pub major: u32,
+    pub minor: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FileAttributes {
+    pub file_type: FileType,
+    pub mode: u32,
+    pub num_links: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub used: u64,
+    pub rdev: SpecData,
+    pub fsid: u64,
+    pub file_id: u64,
+    pub time_access: Time,
+    pub time_modify: Time,
+    pub time_change: Time,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct WccAttributes {
+    pub size: u64,
+    pub time_modify: Time,
+    pub time_change: Time,
+}
+
+/// The attributes of an object before and after it was modified.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct WccData {
+    pub before: Option<WccAttributes>,
+    pub after: Option<FileAttributes>,
+}
+
+#[derive(
+    SerializeWithDiscriminant,
+    DeserializeWithDiscriminant,
+    PartialEq,
+    Eq,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+)]
+#[repr(u32)]
+pub enum SetTime {
+    #[default]
+    DontChange = 0,
+    ServerTime = 1,
+    ClientTime(Time) = 2,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct SetAttributes {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub time_access: SetTime,
+    pub time_modify: SetTime,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DirOpArgs {
+    pub dir: FileHandle,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct GetAttrArgs {
+    pub object: FileHandle,
+}
+
+pub type GetAttrRes = StatusResult<FileAttributes>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SetAttrArgs {
+    pub object: FileHandle,
+    pub new_attributes: SetAttributes,
+    /// Only apply the change if the object's change time still matches this.
+    pub guard: Option<Time>,
+}
+
+pub type SetAttrRes = StatusResult<WccData, WccData>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct LookUpArgs {
+    pub what: DirOpArgs,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct LookUpOk {
+    pub object: FileHandle,
+    pub object_attributes: Option<FileAttributes>,
+    pub dir_attributes: Option<FileAttributes>,
+}
+
+pub type LookUpRes = StatusResult<LookUpOk, Option<FileAttributes>>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadLinkArgs {
+    pub symlink: FileHandle,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadLinkOk {
+    pub symlink_attributes: Option<FileAttributes>,
+    pub data: String,
+}
+
+pub type ReadLinkRes = StatusResult<ReadLinkOk, Option<FileAttributes>>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadArgs {
+    pub file: FileHandle,
+    pub offset: u64,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadOk {
+    pub file_attributes: Option<FileAttributes>,
+    pub count: u32,
+    pub eof: bool,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+pub type ReadRes = StatusResult<ReadOk, Option<FileAttributes>>;
+
+#[derive(
+    SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Copy, Clone, Debug,
+)]
+#[repr(u32)]
+pub enum StableHow {
+    Unstable = 0,
+    DataSync = 1,
+    FileSync = 2,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct WriteArgs {
+    pub file: FileHandle,
+    pub offset: u64,
+    pub count: u32,
+    pub stable: StableHow,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct WriteOk {
+    pub file_wcc: WccData,
+    pub count: u32,
+    pub committed: StableHow,
+    pub verifier: Verifier,
+}
+
+pub type WriteRes = StatusResult<WriteOk, WccData>;
+
+#[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
+#[repr(u32)]
+pub enum CreateHow {
+    Unchecked(SetAttributes) = 0,
+    Guarded(SetAttributes) = 1,
+    Exclusive(Verifier) = 2,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CreateArgs {
+    pub location: DirOpArgs,
+    pub how: CreateHow,
+}
+
+/// The reply to the procedures which create something in a directory.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CreateOk {
+    pub object: Option<FileHandle>,
+    pub object_attributes: Option<FileAttributes>,
+    pub dir_wcc: WccData,
+}
+
+pub type CreateRes = StatusResult<CreateOk, WccData>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct MkDirArgs {
+    pub location: DirOpArgs,
+    pub attributes: SetAttributes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SymLinkArgs {
+    pub location: DirOpArgs,
+    pub attributes: SetAttributes,
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct RemoveArgs {
+    pub object: DirOpArgs,
+}
+
+pub type RemoveRes = StatusResult<WccData, WccData>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct RenameArgs {
+    pub from: DirOpArgs,
+    pub to: DirOpArgs,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct RenameWcc {
+    pub from_dir_wcc: WccData,
+    pub to_dir_wcc: WccData,
+}
+
+pub type RenameRes = StatusResult<RenameWcc, RenameWcc>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadDirPlusArgs {
+    pub dir: FileHandle,
+    pub cookie: u64,
+    pub cookie_verifier: CookieVerifier,
+    pub dir_count: u32,
+    pub max_count: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DirectoryEntry {
+    pub file_id: u64,
+    pub name: String,
+    pub cookie: u64,
+    pub attributes: Option<FileAttributes>,
+    pub handle: Option<FileHandle>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DirectoryList {
+    #[serde(with = "xdr_extras::list")]
+    pub entries: Vec<DirectoryEntry>,
+    pub eof: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReadDirPlusOk {
+    pub dir_attributes: Option<FileAttributes>,
+    pub cookie_verifier: CookieVerifier,
+    pub reply: DirectoryList,
+}
+
+pub type ReadDirPlusRes = StatusResult<ReadDirPlusOk, Option<FileAttributes>>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FsInfoArgs {
+    pub root: FileHandle,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FsInfoOk {
+    pub root_attributes: Option<FileAttributes>,
+    pub read_max: u32,
+    pub read_preferred: u32,
+    pub read_multiple: u32,
+    pub write_max: u32,
+    pub write_preferred: u32,
+    pub write_multiple: u32,
+    pub dir_preferred: u32,
+    pub max_file_size: u64,
+    pub time_delta: Time,
+    pub properties: u32,
+}
+
+pub type FsInfoRes = StatusResult<FsInfoOk, Option<FileAttributes>>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CommitArgs {
+    pub file: FileHandle,
+    pub offset: u64,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CommitOk {
+    pub file_wcc: WccData,
+    pub verifier: Verifier,
+}
+
+pub type CommitRes = StatusResult<CommitOk, WccData>;
+
+#[test]
+fn deserialize_status_result() {
+    let bytes = [0, 0, 0, 2, 0, 0, 0, 0];
+    let res: LookUpRes = serde_xdr::from_bytes(bytes).unwrap();
+    assert_eq!(res, StatusResult::Err(StatusError::NoEnt, None));
+
+    #[rustfmt::skip]
+    let bytes = [
+        0, 0, 0, 0,
+        0, 0, 0, 2, 0xab, 0xcd, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+    ];
+    let res: LookUpRes = serde_xdr::from_bytes(bytes).unwrap();
+    assert_eq!(
+        res,
+        StatusResult::Ok(LookUpOk {
+            object: FileHandle(vec![0xab, 0xcd]),
+            object_attributes: None,
+            dir_attributes: None,
+        })
+    );
+}
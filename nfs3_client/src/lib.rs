@@ -0,0 +1,405 @@
+// Copyright 2023 Remi Bernotavicius
+
+use derive_more::From;
+use nfs3::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read as _};
+use std::path::{Component, Path};
+use sun_rpc_client::{OpaqueAuth, RpcClient, Transport};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, From)]
+pub enum Error {
+    SunRpc(sun_rpc_client::Error),
+    Protocol(StatusError),
+    Io(io::Error),
+}
+
+impl Error {
+    /// The status the server returned, if this error came from the server.
+    pub fn status(&self) -> Option<StatusError> {
+        match self {
+            Self::Protocol(e) => Some(*e),
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(StatusError::NoEnt)
+    }
+
+    pub fn is_already_exists(&self) -> bool {
+        self.status() == Some(StatusError::Exist)
+    }
+}
+
+pub const NFS_PORT: u16 = 2049;
+
+// How much of a directory listing to ask for at once
+const READ_DIR_COUNT: u32 = 8192;
+const READ_DIR_MAX_COUNT: u32 = 32768;
+
+fn into_result<T, F>(res: StatusResult<T, F>) -> Result<T> {
+    match res {
+        StatusResult::Ok(v) => Ok(v),
+        StatusResult::Err(e, _) => Err(e.into()),
+    }
+}
+
+pub struct Client<TransportT> {
+    rpc_client: RpcClient<TransportT>,
+    root: FileHandle,
+    read_chunk_size: u32,
+    write_chunk_size: u32,
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Creates a client for the file system with the given root handle, which is what the MOUNT
+    /// protocol hands out for an export.
+    pub fn new(transport: TransportT, root: FileHandle) -> Result<Self> {
+        let mut client = Self {
+            rpc_client: RpcClient::with_version(transport, NFS, NFS_VERSION),
+            root,
+            read_chunk_size: 0,
+            write_chunk_size: 0,
+        };
+
+        let fs_info = client.fs_info()?;
+        client.read_chunk_size = fs_info.read_preferred.clamp(1, fs_info.read_max.max(1));
+        client.write_chunk_size = fs_info.write_preferred.clamp(1, fs_info.write_max.max(1));
+
+        Ok(client)
+    }
+
+    fn call<Args, Res>(&mut self, procedure: Procedure, args: Args) -> Result<Res>
+    where
+        Args: Serialize,
+        Res: DeserializeOwned + fmt::Debug,
+    {
+        Ok(self.rpc_client.call(procedure.into(), args)?)
+    }
+
+    /// Sets the credential sent with every request from now on.
+    pub fn set_credential(&mut self, credential: OpaqueAuth) {
+        self.rpc_client.set_credential(credential);
+    }
+
+    pub fn root(&self) -> FileHandle {
+        self.root.clone()
+    }
+
+    /// Sets how many bytes are asked for in each READ, which starts as what the server prefers.
+    pub fn set_read_chunk_size(&mut self, chunk_size: u32) {
+        self.read_chunk_size = chunk_size.max(1);
+    }
+
+    pub fn read_chunk_size(&self) -> u32 {
+        self.read_chunk_size
+    }
+
+    /// Sets how many bytes are sent in each WRITE, which starts as what the server prefers.
+    pub fn set_write_chunk_size(&mut self, chunk_size: u32) {
+        self.write_chunk_size = chunk_size.max(1);
+    }
+
+    pub fn write_chunk_size(&self) -> u32 {
+        self.write_chunk_size
+    }
+
+    pub fn fs_info(&mut self) -> Result<FsInfoOk> {
+        let root = self.root();
+        let res: FsInfoRes = self.call(Procedure::FsInfo, FsInfoArgs { root })?;
+        into_result(res)
+    }
+
+    pub fn get_attr(&mut self, handle: FileHandle) -> Result<FileAttributes> {
+        let res: GetAttrRes = self.call(Procedure::GetAttr, GetAttrArgs { object: handle })?;
+        into_result(res)
+    }
+
+    pub fn set_attr(&mut self, handle: FileHandle, attrs: SetAttributes) -> Result<()> {
+        let args = SetAttrArgs {
+            object: handle,
+            new_attributes: attrs,
+            guard: None,
+        };
+        let res: SetAttrRes = self.call(Procedure::SetAttr, args)?;
+        into_result(res)?;
+        Ok(())
+    }
+
+    pub fn look_up(&mut self, path: impl AsRef<Path>) -> Result<FileHandle> {
+        let root = self.root();
+        self.look_up_from(root, path)
+    }
+
+    /// Like `look_up`, but with the path relative to the given directory. Unlike NFSv4 there is no
+    /// compound, so this is a LOOKUP per path component.
+    pub fn look_up_from(&mut self, dir: FileHandle, path: impl AsRef<Path>) -> Result<FileHandle> {
+        let mut handle = dir;
+        for c in path.as_ref().components() {
+            let name = match c {
+                Component::Normal(name) => name.to_str().unwrap(),
+                Component::ParentDir => "..",
+                _ => continue,
+            };
+            let args = LookUpArgs {
+                what: DirOpArgs {
+                    dir: handle,
+                    name: name.into(),
+                },
+            };
+            let res: LookUpRes = self.call(Procedure::LookUp, args)?;
+            handle = into_result(res)?.object;
+        }
+        Ok(handle)
+    }
+
+    pub fn read_link(&mut self, handle: FileHandle) -> Result<String> {
+        let res: ReadLinkRes = self.call(Procedure::ReadLink, ReadLinkArgs { symlink: handle })?;
+        Ok(into_result(res)?.data)
+    }
+
+    pub fn read(&mut self, handle: FileHandle, offset: u64, count: u32) -> Result<ReadOk> {
+        let args = ReadArgs {
+            file: handle,
+            offset,
+            count,
+        };
+        let res: ReadRes = self.call(Procedure::Read, args)?;
+        into_result(res)
+    }
+
+    pub fn read_all(&mut self, handle: FileHandle, mut sink: impl io::Write) -> Result<()> {
+        let mut offset = 0;
+        loop {
+            let reply = self.read(handle.clone(), offset, self.read_chunk_size)?;
+            sink.write_all(&reply.data)?;
+            offset += reply.data.len() as u64;
+            if reply.eof || reply.data.is_empty() {
+                break Ok(());
+            }
+        }
+    }
+
+    pub fn write(&mut self, handle: FileHandle, offset: u64, data: Vec<u8>) -> Result<WriteOk> {
+        let args = WriteArgs {
+            file: handle,
+            offset,
+            count: data.len() as u32,
+            stable: StableHow::FileSync,
+            data,
+        };
+        let res: WriteRes = self.call(Procedure::Write, args)?;
+        into_result(res)
+    }
+
+    pub fn write_all(&mut self, handle: FileHandle, mut source: impl io::Read) -> Result<()> {
+        let mut offset = 0;
+        loop {
+            let mut data = Vec::with_capacity(self.write_chunk_size as usize);
+            (&mut source)
+                .take(self.write_chunk_size.into())
+                .read_to_end(&mut data)?;
+            if data.is_empty() {
+                break Ok(());
+            }
+
+            while !data.is_empty() {
+                let reply = self.write(handle.clone(), offset, data.clone())?;
+                if reply.count == 0 {
+                    return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+                }
+                let count = (reply.count as usize).min(data.len());
+                data.drain(..count);
+                offset += count as u64;
+            }
+        }
+    }
+
+    pub fn commit(&mut self, handle: FileHandle, offset: u64, count: u32) -> Result<CommitOk> {
+        let args = CommitArgs {
+            file: handle,
+            offset,
+            count,
+        };
+        let res: CommitRes = self.call(Procedure::Commit, args)?;
+        into_result(res)
+    }
+
+    /// Servers may leave the handle of what was created out of the reply, in which case we have
+    /// to look it up.
+    fn created_handle(
+        &mut self,
+        parent: FileHandle,
+        name: &str,
+        res: CreateRes,
+    ) -> Result<FileHandle> {
+        match into_result(res)?.object {
+            Some(handle) => Ok(handle),
+            None => self.look_up_from(parent, name),
+        }
+    }
+
+    pub fn create_file(&mut self, parent: FileHandle, name: &str) -> Result<FileHandle> {
+        let args = CreateArgs {
+            location: DirOpArgs {
+                dir: parent.clone(),
+                name: name.into(),
+            },
+            how: CreateHow::Guarded(SetAttributes::default()),
+        };
+        let res: CreateRes = self.call(Procedure::Create, args)?;
+        self.created_handle(parent, name, res)
+    }
+
+    pub fn create_directory(
+        &mut self,
+        parent_dir: FileHandle,
+        name: &str,
+        attrs: SetAttributes,
+    ) -> Result<FileHandle> {
+        let args = MkDirArgs {
+            location: DirOpArgs {
+                dir: parent_dir.clone(),
+                name: name.into(),
+            },
+            attributes: attrs,
+        };
+        let res: CreateRes = self.call(Procedure::MkDir, args)?;
+        self.created_handle(parent_dir, name, res)
+    }
+
+    pub fn create_symlink(
+        &mut self,
+        parent_dir: FileHandle,
+        name: &str,
+        target: &str,
+        attrs: SetAttributes,
+    ) -> Result<FileHandle> {
+        let args = SymLinkArgs {
+            location: DirOpArgs {
+                dir: parent_dir.clone(),
+                name: name.into(),
+            },
+            attributes: attrs,
+            data: target.into(),
+        };
+        let res: CreateRes = self.call(Procedure::SymLink, args)?;
+        self.created_handle(parent_dir, name, res)
+    }
+
+    pub fn remove(&mut self, parent_dir: FileHandle, name: &str) -> Result<()> {
+        let args = RemoveArgs {
+            object: DirOpArgs {
+                dir: parent_dir,
+                name: name.into(),
+            },
+        };
+        let res: RemoveRes = self.call(Procedure::Remove, args)?;
+        into_result(res)?;
+        Ok(())
+    }
+
+    /// Like `remove`, but for empty directories.
+    pub fn remove_directory(&mut self, parent_dir: FileHandle, name: &str) -> Result<()> {
+        let args = RemoveArgs {
+            object: DirOpArgs {
+                dir: parent_dir,
+                name: name.into(),
+            },
+        };
+        let res: RemoveRes = self.call(Procedure::RmDir, args)?;
+        into_result(res)?;
+        Ok(())
+    }
+
+    pub fn rename(
+        &mut self,
+        src_dir: FileHandle,
+        target_dir: FileHandle,
+        src_entry: &str,
+        target_entry: &str,
+    ) -> Result<()> {
+        let args = RenameArgs {
+            from: DirOpArgs {
+                dir: src_dir,
+                name: src_entry.into(),
+            },
+            to: DirOpArgs {
+                dir: target_dir,
+                name: target_entry.into(),
+            },
+        };
+        let res: RenameRes = self.call(Procedure::Rename, args)?;
+        into_result(res)?;
+        Ok(())
+    }
+
+    /// Lists the entries of the given directory along with their attributes and handles, fetching
+    /// them from the server a page at a time as the iterator is advanced.
+    pub fn read_dir(&mut self, handle: FileHandle) -> ReadDirIter<'_, TransportT> {
+        ReadDirIter {
+            client: self,
+            handle,
+            cookie: 0,
+            cookie_verifier: CookieVerifier::default(),
+            page: VecDeque::new(),
+            eof: false,
+        }
+    }
+}
+
+pub struct ReadDirIter<'a, TransportT> {
+    client: &'a mut Client<TransportT>,
+    handle: FileHandle,
+    cookie: u64,
+    cookie_verifier: CookieVerifier,
+    page: VecDeque<DirectoryEntry>,
+    eof: bool,
+}
+
+impl<TransportT: Transport> ReadDirIter<'_, TransportT> {
+    fn fetch_page(&mut self) -> Result<()> {
+        let args = ReadDirPlusArgs {
+            dir: self.handle.clone(),
+            cookie: self.cookie,
+            cookie_verifier: self.cookie_verifier,
+            dir_count: READ_DIR_COUNT,
+            max_count: READ_DIR_MAX_COUNT,
+        };
+        let res: ReadDirPlusRes = self.client.call(Procedure::ReadDirPlus, args)?;
+        let res = into_result(res)?;
+
+        self.cookie_verifier = res.cookie_verifier;
+        self.eof = res.reply.eof;
+        if let Some(last) = res.reply.entries.last() {
+            self.cookie = last.cookie;
+        }
+        // Unlike NFSv4, the listing includes these
+        self.page.extend(
+            res.reply
+                .entries
+                .into_iter()
+                .filter(|e| e.name != "." && e.name != ".."),
+        );
+        Ok(())
+    }
+}
+
+impl<TransportT: Transport> Iterator for ReadDirIter<'_, TransportT> {
+    type Item = Result<DirectoryEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.page.is_empty() && !self.eof {
+            if let Err(e) = self.fetch_page() {
+                self.eof = true;
+                return Some(Err(e));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! How long decoding the reply to a large READ takes, with the data copied out of the received
+//! message the way serde-xdr and `xdr_extras::from_bytes` do, and shared with it the way
+//! `xdr_extras::from_shared_bytes` does.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nfs4::{Bytes, CompoundRes, ReadRes, ResOp, StatusResult};
+use xdr_extras::Limits;
+
+fn read_reply(size: usize) -> Bytes {
+    let res = CompoundRes {
+        status: StatusResult::Ok(()),
+        tag: String::new(),
+        res_array: vec![ResOp::Read(StatusResult::Ok(ReadRes {
+            eof: false,
+            data: vec![0xab; size].into(),
+        }))],
+    };
+    xdr_extras::to_bytes(&res).unwrap().into()
+}
+
+fn decode_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_read");
+    for size in [64 << 10, 1 << 20] {
+        let reply = read_reply(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("serde_xdr", size), &reply, |b, reply| {
+            b.iter(|| serde_xdr::from_bytes::<_, CompoundRes>(&reply[..]).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("copied", size), &reply, |b, reply| {
+            b.iter(|| xdr_extras::from_bytes::<CompoundRes>(reply).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("shared", size), &reply, |b, reply| {
+            b.iter(|| {
+                xdr_extras::from_shared_bytes::<CompoundRes>(reply, Limits::default()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode_read);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Putting together the attributes to set on an object.
+
+use super::{Acl, FileAttribute, FileAttributes, Mode, SetTime, Time};
+use alloc::string::String;
+
+/// Builds the `FileAttributes` to give SETATTR, CREATE or OPEN, like
+/// `FileAttributesBuilder::new().size(100).mode(0o644).mtime_now().build()`. Setting the same
+/// attribute twice keeps the last value.
+#[derive(Clone, Debug, Default)]
+pub struct FileAttributesBuilder {
+    attrs: FileAttributes,
+}
+
+impl FileAttributesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Any attribute, including ones there is no method for. See `FileAttributeId::is_settable`
+    /// for the ones which can be set.
+    pub fn attr(mut self, attr: FileAttribute) -> Self {
+        self.attrs.insert(attr);
+        self
+    }
+
+    /// Truncates or extends the file to the given size.
+    pub fn size(self, size: u64) -> Self {
+        self.attr(FileAttribute::Size(size))
+    }
+
+    /// The permission bits, along with the set-user-ID, set-group-ID and sticky bits.
+    pub fn mode(self, mode: u32) -> Self {
+        self.attr(FileAttribute::Mode(Mode(mode)))
+    }
+
+    /// The owner as the server names it, like `alice@example.com` or a numeric ID.
+    pub fn owner(self, owner: impl Into<String>) -> Self {
+        self.attr(FileAttribute::Owner(owner.into()))
+    }
+
+    pub fn owner_group(self, group: impl Into<String>) -> Self {
+        self.attr(FileAttribute::OwnerGroup(group.into()))
+    }
+
+    pub fn acl(self, acl: Acl) -> Self {
+        self.attr(FileAttribute::Acl(acl))
+    }
+
+    pub fn atime(self, time: Time) -> Self {
+        self.attr(FileAttribute::TimeAccessSet(SetTime::SetToClientTime(time)))
+    }
+
+    /// Sets the access time to the server's current time, so it doesn't matter whether the
+    /// clocks agree.
+    pub fn atime_now(self) -> Self {
+        self.attr(FileAttribute::TimeAccessSet(SetTime::SetToServerTime))
+    }
+
+    pub fn mtime(self, time: Time) -> Self {
+        self.attr(FileAttribute::TimeModifySet(SetTime::SetToClientTime(time)))
+    }
+
+    /// Sets the modify time to the server's current time, see `atime_now`.
+    pub fn mtime_now(self) -> Self {
+        self.attr(FileAttribute::TimeModifySet(SetTime::SetToServerTime))
+    }
+
+    pub fn build(self) -> FileAttributes {
+        self.attrs
+    }
+}
+
+impl From<FileAttributesBuilder> for FileAttributes {
+    fn from(builder: FileAttributesBuilder) -> Self {
+        builder.build()
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Text forms of the types people read and type in, for logs, JSON output and command lines. They
+//! parse back from what they display as.
+
+#[cfg(feature = "chrono")]
+use super::Time;
+use super::{FileHandle, Mode, StateId};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Something which isn't the text form of the type it was parsed as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    what: &'static str,
+    text: String,
+}
+
+impl ParseError {
+    fn new(what: &'static str, text: &str) -> Self {
+        Self {
+            what,
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} `{}`", self.what, self.text)
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Bytes displayed in lower case hex.
+pub(crate) struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+pub(crate) fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// In hex, like `01000601a2b3c4d5`.
+impl fmt::Display for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Hex(&self.0).fmt(f)
+    }
+}
+
+impl FromStr for FileHandle {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        parse_hex(s)
+            .map(|handle| Self(handle.into()))
+            .ok_or_else(|| ParseError::new("file handle", s))
+    }
+}
+
+/// The sequence ID and the rest in hex, like `1:0123456789abcdef01234567`, the way Linux logs
+/// them.
+impl fmt::Display for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.sequence_id, Hex(&self.other))
+    }
+}
+
+impl FromStr for StateId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let error = || ParseError::new("state ID", s);
+        let (sequence_id, other) = s.split_once(':').ok_or_else(error)?;
+        Ok(Self {
+            sequence_id: sequence_id.parse().map_err(|_| error())?,
+            other: parse_hex(other)
+                .and_then(|o| o.try_into().ok())
+                .ok_or_else(error)?,
+        })
+    }
+}
+
+// Each of user, group and other, with the special bit shown in place of its execute permission
+const MODE_CLASSES: [(u32, char); 3] = [(0o4000, 's'), (0o2000, 's'), (0o1000, 't')];
+
+/// The permissions the way `ls -l` shows them without the file type, like `rwxr-xr-x`. Set-ID
+/// and sticky bits show in place of execute, in upper case when execute isn't set.
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (special_bit, special_char)) in MODE_CLASSES.into_iter().enumerate() {
+            let bits = self.0 >> (6 - 3 * i);
+            let execute = match (bits & 0o1 != 0, self.0 & special_bit != 0) {
+                (true, true) => special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            };
+            let read = if bits & 0o4 != 0 { 'r' } else { '-' };
+            let write = if bits & 0o2 != 0 { 'w' } else { '-' };
+            write!(f, "{read}{write}{execute}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Either what it displays as, or octal digits like `755`.
+impl FromStr for Mode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let error = || ParseError::new("mode", s);
+        if !s.is_empty() && s.chars().all(|c| c.is_digit(8)) {
+            return match u32::from_str_radix(s, 8) {
+                Ok(mode) if mode <= 0o7777 => Ok(Self(mode)),
+                _ => Err(error()),
+            };
+        }
+
+        let chars: Vec<char> = s.chars().collect();
+        if chars.len() != 9 {
+            return Err(error());
+        }
+        let mut mode = 0;
+        for (i, (class, (special_bit, special_char))) in
+            chars.chunks(3).zip(MODE_CLASSES).enumerate()
+        {
+            let shift = 6 - 3 * i;
+            mode |= match class[0] {
+                'r' => 0o4 << shift,
+                '-' => 0,
+                _ => return Err(error()),
+            };
+            mode |= match class[1] {
+                'w' => 0o2 << shift,
+                '-' => 0,
+                _ => return Err(error()),
+            };
+            mode |= match class[2] {
+                'x' => 0o1 << shift,
+                '-' => 0,
+                c if c == special_char => special_bit | (0o1 << shift),
+                c if c == special_char.to_ascii_uppercase() => special_bit,
+                _ => return Err(error()),
+            };
+        }
+        Ok(Self(mode))
+    }
+}
+
+/// In RFC 3339 in UTC, like `2023-04-01T12:30:00.5Z`. Times too far off for that are shown as
+/// seconds since the epoch.
+#[cfg(feature = "chrono")]
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_date_time() {
+            Some(time) => {
+                let text = time
+                    .and_utc()
+                    .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true);
+                f.write_str(&text)
+            }
+            None => write!(f, "{}.{:09}", self.seconds, self.nseconds),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl FromStr for Time {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let error = || ParseError::new("time", s);
+        if let Ok(time) = chrono::DateTime::parse_from_rfc3339(s) {
+            return Ok(Self {
+                seconds: time.timestamp(),
+                nseconds: time.timestamp_subsec_nanos(),
+            });
+        }
+        let (seconds, nseconds) = s.split_once('.').ok_or_else(error)?;
+        Ok(Self {
+            seconds: seconds.parse().map_err(|_| error())?,
+            nseconds: nseconds
+                .parse()
+                .ok()
+                .filter(|n| *n < 1_000_000_000 && nseconds.len() == 9)
+                .ok_or_else(error)?,
+        })
+    }
+}
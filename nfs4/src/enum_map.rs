@@ -1,13 +1,13 @@
 // Copyright 2023 Remi Bernotavicius
 
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{format, vec, vec::Vec};
 use derive_more::From;
 use serde::{
     de::{DeserializeOwned, Deserializer},
     ser::Serializer,
     Deserialize, Serialize,
 };
-use std::collections::{BTreeMap, BTreeSet};
-use std::io::Read as _;
 
 pub trait ToId<Id>
 where
@@ -16,6 +16,8 @@ where
     fn to_id(&self) -> Id;
 }
 
+/// A set of enum values, encoded as a bitmap of their discriminants. Bits for values we don't
+/// know are left out when decoding, the other end may know of more than we do.
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct EnumSet<K>(BTreeSet<K>);
 
@@ -27,7 +29,7 @@ impl<K> Default for EnumSet<K> {
 
 impl<K> IntoIterator for EnumSet<K> {
     type Item = K;
-    type IntoIter = std::collections::btree_set::IntoIter<K>;
+    type IntoIter = alloc::collections::btree_set::IntoIter<K>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
@@ -46,12 +48,25 @@ where
     }
 }
 
+/// Values of an enum keyed by their discriminants, encoded as a bitmap of the keys followed by the
+/// values, like the attributes of a file.
+///
+/// The values aren't delimited, so once there is a key we don't know, we can't tell where its
+/// value or any of the ones after it end. Those are kept as they are by their bit number instead,
+/// the first one having the data of all of them and the rest none, so that the map encodes back
+/// to what it was decoded from.
 #[derive(PartialEq, Eq, Clone, Debug)]
-pub struct EnumMap<K, V>(BTreeMap<K, V>);
+pub struct EnumMap<K, V> {
+    map: BTreeMap<K, V>,
+    unknown: BTreeMap<u32, Vec<u8>>,
+}
 
 impl<K, V> Default for EnumMap<K, V> {
     fn default() -> Self {
-        Self(Default::default())
+        Self {
+            map: Default::default(),
+            unknown: Default::default(),
+        }
     }
 }
 
@@ -64,9 +79,10 @@ where
     where
         T: IntoIterator<Item = V>,
     {
-        Self(BTreeMap::from_iter(
-            iter.into_iter().map(|v| (v.to_id(), v)),
-        ))
+        Self {
+            map: BTreeMap::from_iter(iter.into_iter().map(|v| (v.to_id(), v))),
+            unknown: BTreeMap::new(),
+        }
     }
 }
 
@@ -77,7 +93,7 @@ where
 {
     pub fn insert(&mut self, value: V) {
         let key = value.to_id();
-        self.0.insert(key, value);
+        self.map.insert(key, value);
     }
 }
 
@@ -106,11 +122,20 @@ where
     K: Ord + Copy,
 {
     pub fn get(&self, key: K) -> Option<&V> {
-        self.0.get(&key)
+        self.map.get(&key)
     }
 
     pub fn remove(&mut self, key: K) -> Option<V> {
-        self.0.remove(&key)
+        self.map.remove(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        self.map.values()
+    }
+
+    /// The raw data of the entries whose keys we don't know, by their bit number.
+    pub fn unknown(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.unknown.iter().map(|(bit, data)| (*bit, &data[..]))
     }
 }
 
@@ -125,6 +150,10 @@ where
     pub fn contains(&self, key: K) -> bool {
         self.0.contains(&key)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = K> + '_ {
+        self.0.iter().copied()
+    }
 }
 
 impl<K, V> EnumMap<K, V>
@@ -133,16 +162,24 @@ where
     V: Serialize,
 {
     fn to_raw(&self) -> EnumMapRaw {
-        let keys: EnumSet<K> = self.0.keys().cloned().collect();
-        let map = keys.to_raw();
-
-        let mut body = vec![];
-        for v in self.0.values() {
-            let mut serialized = vec![];
-            serde_xdr::to_writer(&mut serialized, v).unwrap();
-            body.extend(serialized.into_iter().skip(4));
+        let mut values: BTreeMap<u32, Vec<u8>> = self.unknown.clone();
+        for (k, v) in &self.map {
+            let mut serialized = xdr_extras::to_bytes(v).unwrap();
+            values.insert((*k).into(), serialized.split_off(4));
+        }
+
+        let mut map = vec![];
+        for bit in values.keys() {
+            let index = (bit / 32) as usize;
+            if index >= map.len() {
+                map.resize(index + 1, 0);
+            }
+            map[index] |= 1 << (bit % 32);
+        }
+        EnumMapRaw {
+            map: EnumSetRaw { map },
+            body: values.into_values().flatten().collect(),
         }
-        EnumMapRaw { map, body }
     }
 }
 
@@ -154,7 +191,7 @@ where
     where
         &'a T: TryFrom<&'a V>,
     {
-        let v = self.0.get(&key)?;
+        let v = self.map.get(&key)?;
         v.try_into().ok()
     }
 
@@ -162,7 +199,7 @@ where
     where
         T: TryFrom<V>,
     {
-        let v = self.0.remove(&key)?;
+        let v = self.map.remove(&key)?;
         v.try_into().ok()
     }
 }
@@ -170,27 +207,15 @@ where
 #[allow(dead_code)]
 #[derive(Debug, From)]
 pub enum EnumMapDeserializationError {
-    UnknownKeyValue(u32),
-    BadValueXdr(serde_xdr::CompatDeserializationError),
+    BadValueXdr(xdr_extras::Error),
 }
 
 impl<K> EnumSet<K>
 where
     K: TryFrom<u32> + Ord + Serialize,
 {
-    fn try_from_raw(raw: EnumSetRaw) -> Result<Self, EnumMapDeserializationError> {
-        let mut set = BTreeSet::new();
-        for b in 0..(u32::try_from(raw.map.len()).unwrap() * 32) {
-            let index = (b / 32) as usize;
-            let chunk = &raw.map[index];
-            if chunk & 1 << (b % 32) != 0 {
-                let key: K = b
-                    .try_into()
-                    .map_err(|_| EnumMapDeserializationError::UnknownKeyValue(b))?;
-                set.insert(key);
-            }
-        }
-        Ok(Self(set))
+    fn from_raw(raw: EnumSetRaw) -> Self {
+        Self(raw.bits().filter_map(|b| b.try_into().ok()).collect())
     }
 }
 
@@ -200,19 +225,35 @@ where
     V: DeserializeOwned,
 {
     fn try_from_raw(raw: EnumMapRaw) -> Result<Self, EnumMapDeserializationError> {
-        let mut body_cursor = &raw.body[..];
-        let mut map = BTreeMap::new();
-
-        let key_set = EnumSet::try_from_raw(raw.map)?;
+        // Each value is decoded from the encoding of its key followed by the rest of the body.
+        // Rather than copying the rest for each one, the key is written over the end of what was
+        // already decoded, with room left at the front for the first one. Keys are discriminants,
+        // encoded in four bytes.
+        const KEY_LEN: usize = 4;
+        let mut buffer = vec![0; KEY_LEN];
+        buffer.extend_from_slice(&raw.body);
+        let mut position = KEY_LEN;
 
-        for key in key_set {
-            let serialized_key = serde_xdr::to_bytes(&key).unwrap();
-            let mut combined_input = (&serialized_key[..]).chain(&mut body_cursor);
-            let value: V = serde_xdr::from_reader(&mut combined_input)?;
+        let mut map = BTreeMap::new();
+        let mut unknown = BTreeMap::new();
+
+        for b in raw.map.bits() {
+            let key = match K::try_from(b) {
+                Ok(key) if unknown.is_empty() => key,
+                _ => {
+                    unknown.insert(b, buffer.split_off(position));
+                    continue;
+                }
+            };
+            let start = position - KEY_LEN;
+            buffer[start..position].copy_from_slice(&xdr_extras::to_bytes(&key)?);
+            let mut deserializer = xdr_extras::Deserializer::new(&buffer[start..]);
+            let value = V::deserialize(&mut deserializer)?;
+            position = buffer.len() - deserializer.remaining().len();
             map.insert(key, value);
         }
 
-        Ok(Self(map))
+        Ok(Self { map, unknown })
     }
 }
 
@@ -221,6 +262,22 @@ struct EnumSetRaw {
     map: Vec<u32>,
 }
 
+impl EnumSetRaw {
+    /// Skips over empty words rather than checking every bit, so a long bitmap with little set
+    /// doesn't take long. Words past where bit numbers still fit a `u32` are ignored.
+    fn bits(&self) -> impl Iterator<Item = u32> + '_ {
+        self.map
+            .iter()
+            .zip(0..=u32::MAX / 32)
+            .filter(|(word, _)| **word != 0)
+            .flat_map(|(&word, index)| {
+                (0..32)
+                    .filter(move |b| word & 1 << b != 0)
+                    .map(move |b| index * 32 + b)
+            })
+    }
+}
+
 impl<K> Serialize for EnumSet<K>
 where
     K: Into<u32> + Copy + Ord,
@@ -242,8 +299,7 @@ where
     where
         D: Deserializer<'de>,
     {
-        Self::try_from_raw(EnumSetRaw::deserialize(deserializer)?)
-            .map_err(|e| serde::de::Error::custom(format!("Failed to deserialize EnumSet: {e:?}")))
+        Ok(Self::from_raw(EnumSetRaw::deserialize(deserializer)?))
     }
 }
 
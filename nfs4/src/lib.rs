@@ -1,8 +1,17 @@
 // Copyright 2023 Remi Bernotavicius
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
 use bitflags::bitflags;
-use bitflags_serde_shim::impl_serde_for_bitflags;
+pub use builder::FileAttributesBuilder;
+pub use bytes::Bytes;
+use core::fmt;
+use core::time::Duration;
 use derive_more::{From, TryInto};
+pub use display::ParseError;
 use enum_as_inner::EnumAsInner;
 pub use enum_map::{EnumMap, EnumSet, ToId};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
@@ -11,12 +20,16 @@ use serde::{
     ser::{SerializeStruct as _, Serializer},
     Deserialize, Serialize,
 };
-use serde_xdr::opaque_data::fixed_length;
-use std::fmt;
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
 use sun_rpc::{AuthFlavor, AuthSysParameters};
-use xdr_extras::{DeserializeWithDiscriminant, SerializeWithDiscriminant};
+use xdr_extras::{fixed_length, DeserializeWithDiscriminant, SerializeWithDiscriminant};
 
+mod builder;
+mod display;
 mod enum_map;
+#[cfg(feature = "serde")]
+pub mod readable;
 
 pub type FileAttributes = EnumMap<FileAttributeId, FileAttribute>;
 
@@ -34,6 +47,24 @@ pub struct CompoundRes {
     pub res_array: Vec<ResOp>,
 }
 
+/// Encodes the flags as their bits. Bits this side doesn't know of are kept rather than refused,
+/// since a peer with a newer minor version may set them.
+macro_rules! serde_for_bitflags {
+    ($name:ident) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.bits().serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok(Self::from_bits_retain(u32::deserialize(deserializer)?))
+            }
+        }
+    };
+}
+
 bitflags! {
     #[derive(PartialEq, Eq, Copy, Clone, Debug)]
     pub struct Access: u32 {
@@ -46,7 +77,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(Access);
+serde_for_bitflags!(Access);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct AccessArgs {
@@ -126,6 +157,7 @@ pub enum FileType {
     Socket = 6,
     Fifo = 7,
     AttrDir = 8,
+    NamedAttr = 9,
 }
 
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
@@ -179,6 +211,7 @@ pub enum FileAttributeId {
     FilesFree = 22,
     FilesTotal = 23,
     FsLocations = 24,
+    Hidden = 25,
     Homogeneous = 26,
     MaxFileSize = 27,
     MaxLink = 28,
@@ -230,6 +263,44 @@ pub enum FileAttributeId {
     ModeSetMasked = 74,
     SupportedAttrsExclusiveCreate = 75,
     FsCharsetCap = 76,
+    CloneBlksize = 77,
+    SpaceFreed = 78,
+    ChangeAttrType = 79,
+    SecLabel = 80,
+    ModeUmask = 81,
+    XattrSupport = 82,
+}
+
+impl FileAttributeId {
+    /// Whether SETATTR can change the attribute. The rest are read-only, asking to set them is
+    /// `StatusError::Inval`.
+    pub fn is_settable(&self) -> bool {
+        matches!(
+            self,
+            Self::Size
+                | Self::Acl
+                | Self::Archive
+                | Self::Hidden
+                | Self::MimeType
+                | Self::Mode
+                | Self::Owner
+                | Self::OwnerGroup
+                | Self::System
+                | Self::TimeAccessSet
+                | Self::TimeBackup
+                | Self::TimeCreate
+                | Self::TimeModifySet
+                | Self::Dacl
+                | Self::Sacl
+                | Self::LayoutHint
+                | Self::RetentionSet
+                | Self::RetentevtSet
+                | Self::RetentionHold
+                | Self::ModeSetMasked
+                | Self::SecLabel
+                | Self::ModeUmask
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
@@ -238,8 +309,8 @@ pub struct FsId {
     pub minor: u64,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
-pub struct FileHandle(#[serde(with = "serde_bytes")] pub Vec<u8>);
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
+pub struct FileHandle(#[serde(with = "xdr_extras::shared_bytes")] pub Bytes);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct Identity(pub String);
@@ -269,7 +340,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(AceFlags);
+serde_for_bitflags!(AceFlags);
 
 bitflags! {
     #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -297,7 +368,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(AceMask);
+serde_for_bitflags!(AceMask);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct Ace {
@@ -321,22 +392,146 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(AclFlags);
+serde_for_bitflags!(AclFlags);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct AclWithFlags {
     pub flags: AclFlags,
+    pub aces: Vec<Ace>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
-pub struct ChangePolicy(u32);
+bitflags! {
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct AclSupport: u32 {
+        const ALLOW_ACL = 0x00000001;
+        const DENY_ACL  = 0x00000002;
+        const AUDIT_ACL = 0x00000004;
+        const ALARM_ACL = 0x00000008;
+    }
+}
+
+serde_for_bitflags!(AclSupport);
+
+bitflags! {
+    /// When file handles can stop working. Empty means they are persistent.
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct FhExpireType: u32 {
+        const NOEXPIRE_WITH_OPEN = 0x00000001;
+        const VOLATILE_ANY       = 0x00000002;
+        const VOL_MIGRATION      = 0x00000004;
+        const VOL_RENAME         = 0x00000008;
+    }
+}
+
+serde_for_bitflags!(FhExpireType);
+
+bitflags! {
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct FsCharsetCap: u32 {
+        const CONTAINS_NON_UTF8 = 0x00000001;
+        const ALLOWS_ONLY_UTF8  = 0x00000002;
+    }
+}
+
+serde_for_bitflags!(FsCharsetCap);
+
+/// Changes whenever the policy the file system is under does, like when it migrates.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ChangePolicy {
+    pub major: u64,
+    pub minor: u64,
+}
+
+/// How the change attribute changes, see RFC 7862 section 12.2.3.
+#[derive(
+    SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Copy, Clone, Debug,
+)]
+#[repr(u32)]
+pub enum ChangeAttrType {
+    MonotonicIncrement = 0,
+    VersionCounter = 1,
+    VersionCounterNoPnfs = 2,
+    TimeMetadata = 3,
+    Undefined = 4,
+}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct LabelFormat {
+    pub format: u32,
+    pub policy: u32,
+}
+
+/// A security label for labeled NFS, see RFC 7862 section 12.2.2.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SecLabel {
+    pub format: LabelFormat,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
 pub struct Time {
     pub seconds: i64,
     pub nseconds: u32,
 }
 
+impl Time {
+    /// The time since the epoch, or `None` for times before it.
+    pub fn to_duration(&self) -> Option<Duration> {
+        let seconds = u64::try_from(self.seconds).ok()?;
+        Some(Duration::new(seconds, self.nseconds))
+    }
+}
+
+/// Taken as the time since the epoch. Durations longer than the seconds fit are cut short.
+impl From<Duration> for Time {
+    fn from(duration: Duration) -> Self {
+        Self {
+            seconds: duration.as_secs().try_into().unwrap_or(i64::MAX),
+            nseconds: duration.subsec_nanos(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Time {
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// `None` when the time is too far off for the system to represent.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let seconds = Duration::from_secs(self.seconds.unsigned_abs());
+        let time = if self.seconds >= 0 {
+            UNIX_EPOCH.checked_add(seconds)?
+        } else {
+            UNIX_EPOCH.checked_sub(seconds)?
+        };
+        time.checked_add(Duration::from_nanos(self.nseconds.into()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SystemTime> for Time {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.into(),
+            Err(error) => {
+                // Before the epoch the seconds count back from it, but the nanoseconds still
+                // count forward from there
+                let before_epoch = error.duration();
+                let mut seconds = -i64::try_from(before_epoch.as_secs()).unwrap_or(i64::MAX);
+                let mut nseconds = before_epoch.subsec_nanos();
+                if nseconds > 0 {
+                    seconds -= 1;
+                    nseconds = 1_000_000_000 - nseconds;
+                }
+                Self { seconds, nseconds }
+            }
+        }
+    }
+}
+
 #[cfg(feature = "chrono")]
 impl Time {
     pub fn to_date_time(&self) -> Option<chrono::NaiveDateTime> {
@@ -352,6 +547,7 @@ pub enum LayoutType {
     NfsV41Files = 1,
     Osd2Objects = 2,
     BlockVolume = 3,
+    FlexFiles = 4,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
@@ -367,8 +563,11 @@ pub struct LockDenied {
     DeserializeWithDiscriminant,
     PartialEq,
     Eq,
+    Copy,
     Clone,
     Debug,
+    PartialOrd,
+    Ord,
     TryFromPrimitive,
 )]
 #[repr(u32)]
@@ -475,13 +674,51 @@ pub enum StatusError {
     RejectDeleg = 10085,
     ReturnConflict = 10086,
     DelegRevoked = 10087,
+    PartnerNotSupp = 10088,
+    PartnerNoAuth = 10089,
+    UnionNotSupp = 10090,
+    OffloadDenied = 10091,
+    WrongLfs = 10092,
+    BadLabel = 10093,
+    OffloadNoReqs = 10094,
+}
+
+impl StatusError {
+    pub fn is_not_found(&self) -> bool {
+        *self == Self::NoEnt
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self, Self::Perm | Self::Access)
+    }
+
+    pub fn is_already_exists(&self) -> bool {
+        *self == Self::Exist
+    }
+
+    pub fn is_not_a_directory(&self) -> bool {
+        *self == Self::NotDir
+    }
+
+    pub fn is_a_directory(&self) -> bool {
+        *self == Self::Isdir
+    }
+
+    pub fn is_directory_not_empty(&self) -> bool {
+        *self == Self::NotEmpty
+    }
+
+    /// Whether the file handle used no longer refers to a file.
+    pub fn is_stale(&self) -> bool {
+        matches!(self, Self::Stale | Self::FhExpired)
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
-pub struct Component(String);
+pub struct Component(pub String);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
-pub struct PathName(Vec<Component>);
+pub struct PathName(pub Vec<Component>);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct FsLocation {
@@ -502,7 +739,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(FsLocationsInfoFlags);
+serde_for_bitflags!(FsLocationsInfoFlags);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct FsLocationsServer {
@@ -583,6 +820,17 @@ pub enum ThresholdAttribute {
     WriteIoSize(u32) = ThresholdAttributeId::WriteIoSize as u32,
 }
 
+impl ToId<ThresholdAttributeId> for ThresholdAttribute {
+    fn to_id(&self) -> ThresholdAttributeId {
+        match self {
+            Self::ReadSize(..) => ThresholdAttributeId::ReadSize,
+            Self::WriteSize(..) => ThresholdAttributeId::WriteSize,
+            Self::ReadIoSize(..) => ThresholdAttributeId::ReadIoSize,
+            Self::WriteIoSize(..) => ThresholdAttributeId::WriteIoSize,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct ThresholdItem {
     pub layout_type: LayoutType,
@@ -591,7 +839,7 @@ pub struct ThresholdItem {
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct MdsThreshold {
-    hints: Vec<ThresholdItem>,
+    pub hints: Vec<ThresholdItem>,
 }
 
 struct OctalFmt<T>(T);
@@ -614,13 +862,19 @@ impl fmt::Debug for Mode {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone)]
-pub struct ModeMasked(pub u32);
+/// Sets only the bits of the mode which aren't in the mask.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ModeMasked {
+    pub value: Mode,
+    pub mask: Mode,
+}
 
-impl fmt::Debug for ModeMasked {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_tuple("Mode").field(&OctalFmt(self.0)).finish()
-    }
+/// The mode to create an object with, along with the umask of the process creating it for the
+/// server to apply when the directory has no default ACL, see RFC 8275.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ModeUmask {
+    pub mode: Mode,
+    pub umask: Mode,
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -669,7 +923,7 @@ where
     where
         D: Deserializer<'de>,
     {
-        struct Visitor<T>(std::marker::PhantomData<T>);
+        struct Visitor<T>(core::marker::PhantomData<T>);
 
         impl<'de, T> serde::de::Visitor<'de> for Visitor<T>
         where
@@ -677,7 +931,7 @@ where
         {
             type Value = StatusResult<T>;
 
-            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> core::fmt::Result {
                 formatter.write_str("StatusResult")
             }
 
@@ -707,7 +961,7 @@ where
         deserializer.deserialize_struct(
             "StatusResult",
             &["disc", "value"],
-            Visitor(std::marker::PhantomData),
+            Visitor(core::marker::PhantomData),
         )
     }
 }
@@ -747,7 +1001,7 @@ where
     where
         D: Deserializer<'de>,
     {
-        struct Visitor<T>(std::marker::PhantomData<T>);
+        struct Visitor<T>(core::marker::PhantomData<T>);
 
         impl<'de, T> serde::de::Visitor<'de> for Visitor<T>
         where
@@ -755,7 +1009,7 @@ where
         {
             type Value = LockStatusResult<T>;
 
-            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> core::fmt::Result {
                 formatter.write_str("LockStatusResult")
             }
 
@@ -792,7 +1046,7 @@ where
         deserializer.deserialize_struct(
             "LockStatusResult",
             &["disc", "value", "denied"],
-            Visitor(std::marker::PhantomData),
+            Visitor(core::marker::PhantomData),
         )
     }
 }
@@ -821,7 +1075,7 @@ pub struct FileId(pub u64);
 pub enum FileAttribute {
     SupportedAttrs(EnumSet<FileAttributeId>) = FileAttributeId::SupportedAttrs as u32,
     Type(FileType) = FileAttributeId::Type as u32,
-    FhExpireType(u32) = FileAttributeId::FhExpireType as u32,
+    FhExpireType(FhExpireType) = FileAttributeId::FhExpireType as u32,
     Change(Change) = FileAttributeId::Change as u32,
     Size(u64) = FileAttributeId::Size as u32,
     LinkSupport(bool) = FileAttributeId::LinkSupport as u32,
@@ -832,7 +1086,7 @@ pub enum FileAttribute {
     LeaseTime(Lease) = FileAttributeId::LeaseTime as u32,
     ReadDirAttrError(StatusResult<()>) = FileAttributeId::ReadDirAttrError as u32,
     Acl(Acl) = FileAttributeId::Acl as u32,
-    AclSupport(u32) = FileAttributeId::AclSupport as u32,
+    AclSupport(AclSupport) = FileAttributeId::AclSupport as u32,
     Archive(bool) = FileAttributeId::Archive as u32,
     CanSetTime(bool) = FileAttributeId::CanSetTime as u32,
     CaseInsensitive(bool) = FileAttributeId::CaseInsensitive as u32,
@@ -844,6 +1098,7 @@ pub enum FileAttribute {
     FilesFree(u64) = FileAttributeId::FilesFree as u32,
     FilesTotal(u64) = FileAttributeId::FilesTotal as u32,
     FsLocations(FsLocations) = FileAttributeId::FsLocations as u32,
+    Hidden(bool) = FileAttributeId::Hidden as u32,
     Homogeneous(bool) = FileAttributeId::Homogeneous as u32,
     MaxFileSize(u64) = FileAttributeId::MaxFileSize as u32,
     MaxLink(u32) = FileAttributeId::MaxLink as u32,
@@ -895,7 +1150,13 @@ pub enum FileAttribute {
     ModeSetMasked(ModeMasked) = FileAttributeId::ModeSetMasked as u32,
     SupportedAttrsExclusiveCreate(EnumSet<FileAttributeId>) =
         FileAttributeId::SupportedAttrsExclusiveCreate as u32,
-    FsCharsetCap(u32) = FileAttributeId::FsCharsetCap as u32,
+    FsCharsetCap(FsCharsetCap) = FileAttributeId::FsCharsetCap as u32,
+    CloneBlksize(u32) = FileAttributeId::CloneBlksize as u32,
+    SpaceFreed(u64) = FileAttributeId::SpaceFreed as u32,
+    ChangeAttrType(ChangeAttrType) = FileAttributeId::ChangeAttrType as u32,
+    SecLabel(SecLabel) = FileAttributeId::SecLabel as u32,
+    ModeUmask(ModeUmask) = FileAttributeId::ModeUmask as u32,
+    XattrSupport(bool) = FileAttributeId::XattrSupport as u32,
 }
 
 impl ToId<FileAttributeId> for FileAttribute {
@@ -926,6 +1187,7 @@ impl ToId<FileAttributeId> for FileAttribute {
             Self::FilesFree(..) => FileAttributeId::FilesFree,
             Self::FilesTotal(..) => FileAttributeId::FilesTotal,
             Self::FsLocations(..) => FileAttributeId::FsLocations,
+            Self::Hidden(..) => FileAttributeId::Hidden,
             Self::Homogeneous(..) => FileAttributeId::Homogeneous,
             Self::MaxFileSize(..) => FileAttributeId::MaxFileSize,
             Self::MaxLink(..) => FileAttributeId::MaxLink,
@@ -979,6 +1241,12 @@ impl ToId<FileAttributeId> for FileAttribute {
                 FileAttributeId::SupportedAttrsExclusiveCreate
             }
             Self::FsCharsetCap(..) => FileAttributeId::FsCharsetCap,
+            Self::CloneBlksize(..) => FileAttributeId::CloneBlksize,
+            Self::SpaceFreed(..) => FileAttributeId::SpaceFreed,
+            Self::ChangeAttrType(..) => FileAttributeId::ChangeAttrType,
+            Self::SecLabel(..) => FileAttributeId::SecLabel,
+            Self::ModeUmask(..) => FileAttributeId::ModeUmask,
+            Self::XattrSupport(..) => FileAttributeId::XattrSupport,
         }
     }
 }
@@ -1024,16 +1292,16 @@ pub enum LockType {
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct OpenToLockOwner {
-    open_sequence_id: SequenceId,
-    open_state_id: StateId,
-    lock_sequence_id: SequenceId,
-    lock_owner: StateOwner,
+    pub open_sequence_id: SequenceId,
+    pub open_state_id: StateId,
+    pub lock_sequence_id: SequenceId,
+    pub lock_owner: StateOwner,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct ExistingLockOwner {
-    lock_state_id: StateId,
-    lock_sequence_id: SequenceId,
+    pub lock_state_id: StateId,
+    pub lock_sequence_id: SequenceId,
 }
 
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
@@ -1104,7 +1372,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(ShareAccess);
+serde_for_bitflags!(ShareAccess);
 
 bitflags! {
     #[derive(PartialEq, Eq, Copy, Clone, Debug)]
@@ -1116,7 +1384,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(ShareDeny);
+serde_for_bitflags!(ShareDeny);
 
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
 #[repr(u32)]
@@ -1137,7 +1405,9 @@ pub enum OpenDelegationType {
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
 #[repr(u32)]
 pub enum CreateHow {
-    Unchecked = 0,
+    Unchecked {
+        create_attrs: FileAttributes,
+    } = 0,
     Guarded {
         create_attrs: FileAttributes,
     } = 1,
@@ -1193,6 +1463,12 @@ pub struct OpenAttrArgs {
     pub create_dir: bool,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OpenConfirmArgs {
+    pub open_state_id: StateId,
+    pub sequence_id: SequenceId,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct OpenDowngradeArgs {
     pub open_state_id: StateId,
@@ -1245,6 +1521,11 @@ pub struct RenameArgs {
     pub new_name: String,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct RenewArgs {
+    pub client_id: ClientId,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct SecInfoArgs {
     pub name: String,
@@ -1256,6 +1537,31 @@ pub struct SetAttrArgs {
     pub object_attributes: FileAttributes,
 }
 
+/// Where the server calls back a client of minor version 0, which has no backchannel.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CallbackClient {
+    pub program: u32,
+    pub location: NetAddr,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SetClientIdArgs {
+    pub client: ClientOwner,
+    pub callback: CallbackClient,
+    pub callback_ident: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SetClientIdConfirmArgs {
+    pub client_id: ClientId,
+    pub confirm: Verifier,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReleaseLockOwnerArgs {
+    pub lock_owner: StateOwner,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct VerifyArgs {
     pub object_attributes: FileAttributes,
@@ -1276,8 +1582,8 @@ pub struct WriteArgs {
     pub state_id: StateId,
     pub offset: u64,
     pub stable: StableHow,
-    #[serde(with = "serde_bytes")]
-    pub data: Vec<u8>,
+    #[serde(with = "xdr_extras::shared_bytes")]
+    pub data: Bytes,
 }
 
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
@@ -1293,9 +1599,9 @@ pub struct GssHandle(#[serde(with = "serde_bytes")] pub Vec<u8>);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct GssCallbackHandles {
-    service: RpcGssService,
-    handle_from_server: GssHandle,
-    handle_from_client: GssHandle,
+    pub service: RpcGssService,
+    pub handle_from_server: GssHandle,
+    pub handle_from_client: GssHandle,
 }
 
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
@@ -1352,7 +1658,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(ExchangeIdFlags);
+serde_for_bitflags!(ExchangeIdFlags);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct StateProtectOps {
@@ -1425,7 +1731,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(CreateSessionFlags);
+serde_for_bitflags!(CreateSessionFlags);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct CreateSessionArgs {
@@ -1484,16 +1790,12 @@ pub struct GetDirDelegationArgs {
 #[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
 pub struct DeviceId(#[serde(with = "fixed_length")] pub [u8; 16]);
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
-pub struct Util(pub u32);
-
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct GetDeviceInfoArgs {
     pub device_id: DeviceId,
-    pub util: Util,
-    pub first_stripe_index: u32,
-    pub pattern_offset: u64,
-    pub fh_list: Vec<FileHandle>,
+    pub layout_type: LayoutType,
+    pub max_count: u32,
+    pub notify_types: EnumSet<NotifyDeviceIdType>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
@@ -1575,6 +1877,78 @@ pub struct LayoutReturnArgs {
     pub layout_return: LayoutReturn,
 }
 
+/// An error a data server returned to us.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct DeviceError {
+    pub device_id: DeviceId,
+    pub status: StatusError,
+    pub op: OperationId,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct LayoutErrorArgs {
+    pub offset: u64,
+    pub length: u64,
+    pub state_id: StateId,
+    pub errors: Vec<DeviceError>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct IoInfo {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct LayoutStatsArgs {
+    pub offset: u64,
+    pub length: u64,
+    pub state_id: StateId,
+    pub read: IoInfo,
+    pub write: IoInfo,
+    pub device_id: DeviceId,
+    pub layout_update: LayoutUpdate,
+}
+
+/// How to reach a server, for copies between servers.
+#[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
+#[repr(u32)]
+pub enum NetLoc {
+    Name(String) = 1,
+    Url(String) = 2,
+    NetAddr(NetAddr) = 3,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CopyArgs {
+    pub src_state_id: StateId,
+    pub dst_state_id: StateId,
+    pub src_offset: u64,
+    pub dst_offset: u64,
+    /// Zero copies up to the end of the source file
+    pub count: u64,
+    pub consecutive: bool,
+    pub synchronous: bool,
+    /// Empty when copying within the server, otherwise where the source file is
+    pub source_server: Vec<NetLoc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CopyNotifyArgs {
+    pub src_state_id: StateId,
+    pub destination_server: NetLoc,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OffloadCancelArgs {
+    pub state_id: StateId,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OffloadStatusArgs {
+    pub state_id: StateId,
+}
+
 #[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
 #[repr(u32)]
 pub enum SecInfoStyle {
@@ -1672,6 +2046,7 @@ pub enum OperationId {
     NVerify = 17,
     Open = 18,
     OpenAttr = 19,
+    OpenConfirm = 20,
     OpenDowngrade = 21,
     PutFh = 22,
     PutPubFh = 23,
@@ -1681,12 +2056,16 @@ pub enum OperationId {
     ReadLink = 27,
     Remove = 28,
     Rename = 29,
+    Renew = 30,
     RestoreFh = 31,
     SaveFh = 32,
     SecInfo = 33,
     SetAttr = 34,
+    SetClientId = 35,
+    SetClientIdConfirm = 36,
     Verify = 37,
     Write = 38,
+    ReleaseLockOwner = 39,
     BackchannelCtl = 40,
     BindConnToSession = 41,
     ExchangeId = 42,
@@ -1706,6 +2085,23 @@ pub enum OperationId {
     WantDelegation = 56,
     DestroyClientId = 57,
     ReclaimComplete = 58,
+    Copy = 60,
+    CopyNotify = 61,
+    LayoutError = 64,
+    LayoutStats = 65,
+    OffloadCancel = 66,
+    OffloadStatus = 67,
+}
+
+impl OperationId {
+    /// The minor version of NFSv4 which added the operation.
+    pub fn minor_version(self) -> u32 {
+        match self as u32 {
+            ..=39 => 0,
+            40..=58 => 1,
+            _ => 2,
+        }
+    }
 }
 
 #[derive(
@@ -1730,6 +2126,7 @@ pub enum ArgOp {
     NVerify(NVerifyArgs) = OperationId::NVerify as u32,
     Open(OpenArgs) = OperationId::Open as u32,
     OpenAttr(OpenAttrArgs) = OperationId::OpenAttr as u32,
+    OpenConfirm(OpenConfirmArgs) = OperationId::OpenConfirm as u32,
     OpenDowngrade(OpenDowngradeArgs) = OperationId::OpenDowngrade as u32,
     PutFh(PutFhArgs) = OperationId::PutFh as u32,
     PutPubFh = OperationId::PutPubFh as u32,
@@ -1739,12 +2136,16 @@ pub enum ArgOp {
     ReadLink = OperationId::ReadLink as u32,
     Remove(RemoveArgs) = OperationId::Remove as u32,
     Rename(RenameArgs) = OperationId::Rename as u32,
+    Renew(RenewArgs) = OperationId::Renew as u32,
     RestoreFh = OperationId::RestoreFh as u32,
     SaveFh = OperationId::SaveFh as u32,
     SecInfo(SecInfoArgs) = OperationId::SecInfo as u32,
     SetAttr(SetAttrArgs) = OperationId::SetAttr as u32,
+    SetClientId(SetClientIdArgs) = OperationId::SetClientId as u32,
+    SetClientIdConfirm(SetClientIdConfirmArgs) = OperationId::SetClientIdConfirm as u32,
     Verify(VerifyArgs) = OperationId::Verify as u32,
     Write(WriteArgs) = OperationId::Write as u32,
+    ReleaseLockOwner(ReleaseLockOwnerArgs) = OperationId::ReleaseLockOwner as u32,
     BackchannelCtl(BackchannelCtlArgs) = OperationId::BackchannelCtl as u32,
     BindConnToSession(BindConnToSessionArgs) = OperationId::BindConnToSession as u32,
     ExchangeId(ExchangeIdArgs) = OperationId::ExchangeId as u32,
@@ -1764,6 +2165,81 @@ pub enum ArgOp {
     WantDelegation(WantDelegationArgs) = OperationId::WantDelegation as u32,
     DestroyClientId(DestroyClientIdArgs) = OperationId::DestroyClientId as u32,
     ReclaimComplete(ReclaimCompleteArgs) = OperationId::ReclaimComplete as u32,
+    Copy(CopyArgs) = OperationId::Copy as u32,
+    CopyNotify(CopyNotifyArgs) = OperationId::CopyNotify as u32,
+    LayoutError(LayoutErrorArgs) = OperationId::LayoutError as u32,
+    LayoutStats(LayoutStatsArgs) = OperationId::LayoutStats as u32,
+    OffloadCancel(OffloadCancelArgs) = OperationId::OffloadCancel as u32,
+    OffloadStatus(OffloadStatusArgs) = OperationId::OffloadStatus as u32,
+}
+
+impl ToId<OperationId> for ArgOp {
+    fn to_id(&self) -> OperationId {
+        match self {
+            Self::Access(..) => OperationId::Access,
+            Self::Close(..) => OperationId::Close,
+            Self::Commit(..) => OperationId::Commit,
+            Self::Create(..) => OperationId::Create,
+            Self::DelegPurge(..) => OperationId::DelegPurge,
+            Self::DelegReturn(..) => OperationId::DelegReturn,
+            Self::GetAttr(..) => OperationId::GetAttr,
+            Self::GetFh => OperationId::GetFh,
+            Self::Link(..) => OperationId::Link,
+            Self::Lock(..) => OperationId::Lock,
+            Self::LockT(..) => OperationId::LockT,
+            Self::LockU(..) => OperationId::LockU,
+            Self::LookUp(..) => OperationId::LookUp,
+            Self::LookUpP => OperationId::LookUpP,
+            Self::NVerify(..) => OperationId::NVerify,
+            Self::Open(..) => OperationId::Open,
+            Self::OpenAttr(..) => OperationId::OpenAttr,
+            Self::OpenConfirm(..) => OperationId::OpenConfirm,
+            Self::OpenDowngrade(..) => OperationId::OpenDowngrade,
+            Self::PutFh(..) => OperationId::PutFh,
+            Self::PutPubFh => OperationId::PutPubFh,
+            Self::PutRootFh => OperationId::PutRootFh,
+            Self::Read(..) => OperationId::Read,
+            Self::ReadDir(..) => OperationId::ReadDir,
+            Self::ReadLink => OperationId::ReadLink,
+            Self::Remove(..) => OperationId::Remove,
+            Self::Rename(..) => OperationId::Rename,
+            Self::Renew(..) => OperationId::Renew,
+            Self::RestoreFh => OperationId::RestoreFh,
+            Self::SaveFh => OperationId::SaveFh,
+            Self::SecInfo(..) => OperationId::SecInfo,
+            Self::SetAttr(..) => OperationId::SetAttr,
+            Self::SetClientId(..) => OperationId::SetClientId,
+            Self::SetClientIdConfirm(..) => OperationId::SetClientIdConfirm,
+            Self::Verify(..) => OperationId::Verify,
+            Self::Write(..) => OperationId::Write,
+            Self::ReleaseLockOwner(..) => OperationId::ReleaseLockOwner,
+            Self::BackchannelCtl(..) => OperationId::BackchannelCtl,
+            Self::BindConnToSession(..) => OperationId::BindConnToSession,
+            Self::ExchangeId(..) => OperationId::ExchangeId,
+            Self::CreateSession(..) => OperationId::CreateSession,
+            Self::DestroySession(..) => OperationId::DestroySession,
+            Self::FreeStateid(..) => OperationId::FreeStateid,
+            Self::GetDirDelegation(..) => OperationId::GetDirDelegation,
+            Self::GetDeviceInfo(..) => OperationId::GetDeviceInfo,
+            Self::GetDeviceList(..) => OperationId::GetDeviceList,
+            Self::LayoutCommit(..) => OperationId::LayoutCommit,
+            Self::LayoutGet(..) => OperationId::LayoutGet,
+            Self::LayoutReturn(..) => OperationId::LayoutReturn,
+            Self::SecInfoNoName(..) => OperationId::SecInfoNoName,
+            Self::Sequence(..) => OperationId::Sequence,
+            Self::SetSsv(..) => OperationId::SetSsv,
+            Self::TestStateId(..) => OperationId::TestStateId,
+            Self::WantDelegation(..) => OperationId::WantDelegation,
+            Self::DestroyClientId(..) => OperationId::DestroyClientId,
+            Self::ReclaimComplete(..) => OperationId::ReclaimComplete,
+            Self::Copy(..) => OperationId::Copy,
+            Self::CopyNotify(..) => OperationId::CopyNotify,
+            Self::LayoutError(..) => OperationId::LayoutError,
+            Self::LayoutStats(..) => OperationId::LayoutStats,
+            Self::OffloadCancel(..) => OperationId::OffloadCancel,
+            Self::OffloadStatus(..) => OperationId::OffloadStatus,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
@@ -1797,7 +2273,7 @@ pub struct CommitRes {
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
-pub struct ChangeId(u64);
+pub struct ChangeId(pub u64);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct ChangeInfo {
@@ -1847,7 +2323,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(OpenResult);
+serde_for_bitflags!(OpenResult);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct OpenReadDelegation {
@@ -1909,6 +2385,11 @@ pub struct OpenRes {
     pub delegation: OpenDelegation,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OpenConfirmRes {
+    pub open_state_id: StateId,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct OpenDowngradeRes {
     pub open_state_id: StateId,
@@ -1917,8 +2398,8 @@ pub struct OpenDowngradeRes {
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct ReadRes {
     pub eof: bool,
-    #[serde(with = "serde_bytes")]
-    pub data: Vec<u8>,
+    #[serde(with = "xdr_extras::shared_bytes")]
+    pub data: Bytes,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
@@ -1944,7 +2425,7 @@ pub struct RenameRes {
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
-pub struct Qop(u32);
+pub struct Qop(pub u32);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct RpcSecGssInfo {
@@ -1971,6 +2452,14 @@ pub struct SetAttrRes {
     pub attr_set: EnumSet<FileAttributeId>,
 }
 
+// A server answering with `ClidInUse` also tells which address the client ID is in use from,
+// which isn't kept here.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SetClientIdRes {
+    pub client_id: ClientId,
+    pub confirm: Verifier,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct SetAttrStatusResult {
     pub status: StatusResult<()>,
@@ -2022,14 +2511,23 @@ pub struct CreateSessionRes {
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
-pub struct GetDirDelegationRes {
+pub struct GetDirDelegationResOk {
     pub cookie_verifier: Verifier,
     pub state_id: StateId,
-    pub notification: EnumSet<FileAttributeId>,
+    pub notification: EnumSet<NotifyType>,
     pub child_attributes: EnumSet<FileAttributeId>,
     pub dir_attributes: EnumSet<FileAttributeId>,
 }
 
+#[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
+#[repr(u32)]
+pub enum GetDirDelegationRes {
+    Ok(GetDirDelegationResOk) = 0,
+    Unavailable {
+        will_signal_delegation_available: bool,
+    } = 1,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct DeviceAddr {
     pub layout_type: LayoutType,
@@ -2037,6 +2535,22 @@ pub struct DeviceAddr {
     pub body: Vec<u8>,
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NetAddr {
+    pub netid: String,
+    /// The universal address, like `192.168.0.1.8.1` where the last two numbers are the port.
+    pub addr: String,
+}
+
+/// The body of a `DeviceAddr` for `LayoutType::NfsV41Files`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FilesDeviceAddr {
+    /// Which entry of `multipath_ds_list` each stripe goes to
+    pub stripe_indices: Vec<u32>,
+    /// For each data server, the addresses it can be reached at
+    pub multipath_ds_list: Vec<Vec<NetAddr>>,
+}
+
 #[derive(
     SerializeWithDiscriminant,
     DeserializeWithDiscriminant,
@@ -2090,6 +2604,185 @@ pub struct Layout {
     pub content: LayoutContent,
 }
 
+/// How a files layout stripes the file across data servers, along with some flags.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Util(pub u32);
+
+impl Util {
+    const DENSE: u32 = 0x1;
+    const COMMIT_THROUGH_MDS: u32 = 0x2;
+    const STRIPE_UNIT_SIZE_MASK: u32 = 0xFFFFFFC0;
+
+    /// Whether the data servers store only their own stripe units, back to back, rather than
+    /// each unit at its offset in the file.
+    pub fn is_dense(&self) -> bool {
+        self.0 & Self::DENSE != 0
+    }
+
+    pub fn commit_through_mds(&self) -> bool {
+        self.0 & Self::COMMIT_THROUGH_MDS != 0
+    }
+
+    pub fn stripe_unit_size(&self) -> u32 {
+        self.0 & Self::STRIPE_UNIT_SIZE_MASK
+    }
+}
+
+/// The body of a `LayoutContent` for `LayoutType::NfsV41Files`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FilesLayout {
+    pub device_id: DeviceId,
+    pub util: Util,
+    pub first_stripe_index: u32,
+    pub pattern_offset: u64,
+    pub fh_list: Vec<FileHandle>,
+}
+
+bitflags! {
+    #[derive(PartialEq, Eq, Copy, Clone, Debug)]
+    pub struct FlexFilesFlags: u32 {
+        const NO_LAYOUTCOMMIT   = 0x00000001;
+        const NO_IO_THRU_MDS    = 0x00000002;
+        const NO_READ_IO        = 0x00000004;
+        const WRITE_ONE_MIRROR  = 0x00000008;
+    }
+}
+
+serde_for_bitflags!(FlexFilesFlags);
+
+/// Where a copy of some of the file is kept, and how to get at it.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesDataServer {
+    pub device_id: DeviceId,
+    pub efficiency: u32,
+    pub state_id: StateId,
+    /// The handle to use for each version of NFS the data server speaks
+    pub fh_versions: Vec<FileHandle>,
+    /// The AUTH_SYS uid and gid to access the file with, as numbers
+    pub user: String,
+    pub group: String,
+}
+
+/// A complete copy of the file, striped across its data servers.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesMirror {
+    pub data_servers: Vec<FlexFilesDataServer>,
+}
+
+/// The body of a `LayoutContent` for `LayoutType::FlexFiles`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesLayout {
+    /// Zero when each mirror has a single data server
+    pub stripe_unit: u64,
+    pub mirrors: Vec<FlexFilesMirror>,
+    pub flags: FlexFilesFlags,
+    /// How often the server would like LAYOUTSTATS, in seconds
+    pub stats_collect_hint: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesDeviceVersion {
+    pub version: u32,
+    pub minor_version: u32,
+    pub read_size: u32,
+    pub write_size: u32,
+    /// Whether the data server checks state IDs with the metadata server
+    pub tightly_coupled: bool,
+}
+
+/// The body of a `DeviceAddr` for `LayoutType::FlexFiles`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesDeviceAddr {
+    pub netaddrs: Vec<NetAddr>,
+    pub versions: Vec<FlexFilesDeviceVersion>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct FlexFilesIoLatency {
+    pub ops_requested: u64,
+    pub bytes_requested: u64,
+    pub ops_completed: u64,
+    pub bytes_completed: u64,
+    pub bytes_not_delivered: u64,
+    pub total_busy_time: Time,
+    pub aggregate_completion_time: Time,
+}
+
+/// The body of the `LayoutUpdate` in `LayoutStatsArgs` for `LayoutType::FlexFiles`.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesLayoutUpdate {
+    pub addr: NetAddr,
+    pub fh: FileHandle,
+    pub read: FlexFilesIoLatency,
+    pub write: FlexFilesIoLatency,
+    pub duration: Time,
+    pub local: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesIoError {
+    pub offset: u64,
+    pub length: u64,
+    pub state_id: StateId,
+    pub errors: Vec<DeviceError>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct FlexFilesIoStats {
+    pub offset: u64,
+    pub length: u64,
+    pub state_id: StateId,
+    pub read: IoInfo,
+    pub write: IoInfo,
+    pub device_id: DeviceId,
+    pub layout_update: FlexFilesLayoutUpdate,
+}
+
+/// The body of a `LayoutReturnFile` for `LayoutType::FlexFiles`, for reporting errors and
+/// statistics to servers which don't take LAYOUTERROR and LAYOUTSTATS.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct FlexFilesLayoutReturn {
+    pub io_errors: Vec<FlexFilesIoError>,
+    pub io_stats: Vec<FlexFilesIoStats>,
+}
+
+/// How much of a copy was done, and whether it goes on in the background.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct WriteResponse {
+    /// Set when the server carries on with the copy after replying, identifying it
+    pub callback_id: Option<StateId>,
+    pub count: u64,
+    pub committed: StableHow,
+    pub write_verifier: Verifier,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CopyRequirements {
+    pub consecutive: bool,
+    pub synchronous: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CopyRes {
+    pub response: WriteResponse,
+    pub requirements: CopyRequirements,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CopyNotifyRes {
+    /// How long the destination server has to start the copy
+    pub lease_time: Time,
+    pub state_id: StateId,
+    pub source_server: Vec<NetLoc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct OffloadStatusRes {
+    pub count: u64,
+    /// Set once the copy is done
+    pub complete: Option<StatusResult<()>>,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct LayoutGetRes {
     pub return_on_close: bool,
@@ -2121,7 +2814,7 @@ bitflags! {
     }
 }
 
-impl_serde_for_bitflags!(SequenceStatusFlags);
+serde_for_bitflags!(SequenceStatusFlags);
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct SequenceRes {
@@ -2171,6 +2864,7 @@ pub enum ResOp {
     NVerify(StatusResult<()>) = OperationId::NVerify as u32,
     Open(StatusResult<OpenRes>) = OperationId::Open as u32,
     OpenAttr(StatusResult<()>) = OperationId::OpenAttr as u32,
+    OpenConfirm(StatusResult<OpenConfirmRes>) = OperationId::OpenConfirm as u32,
     OpenDowngrade(StatusResult<OpenDowngradeRes>) = OperationId::OpenDowngrade as u32,
     PutFh(StatusResult<()>) = OperationId::PutFh as u32,
     PutPubFh(StatusResult<()>) = OperationId::PutPubFh as u32,
@@ -2180,12 +2874,16 @@ pub enum ResOp {
     ReadLink(StatusResult<ReadLinkRes>) = OperationId::ReadLink as u32,
     Remove(StatusResult<RemoveRes>) = OperationId::Remove as u32,
     Rename(StatusResult<RenameRes>) = OperationId::Rename as u32,
+    Renew(StatusResult<()>) = OperationId::Renew as u32,
     RestoreFh(StatusResult<()>) = OperationId::RestoreFh as u32,
     SaveFh(StatusResult<()>) = OperationId::SaveFh as u32,
     SecInfo(StatusResult<SecInfoRes>) = OperationId::SecInfo as u32,
     SetAttr(SetAttrStatusResult) = OperationId::SetAttr as u32,
+    SetClientId(StatusResult<SetClientIdRes>) = OperationId::SetClientId as u32,
+    SetClientIdConfirm(StatusResult<()>) = OperationId::SetClientIdConfirm as u32,
     Verify(StatusResult<()>) = OperationId::Verify as u32,
     Write(StatusResult<WriteRes>) = OperationId::Write as u32,
+    ReleaseLockOwner(StatusResult<()>) = OperationId::ReleaseLockOwner as u32,
     BackchannelCtl(StatusResult<()>) = OperationId::BackchannelCtl as u32,
     BindConnToSession(StatusResult<BindConnToSessionRes>) = OperationId::BindConnToSession as u32,
     ExchangeId(StatusResult<ExchangeIdRes>) = OperationId::ExchangeId as u32,
@@ -2205,4 +2903,276 @@ pub enum ResOp {
     WantDelegation(StatusResult<WantDelegationRes>) = OperationId::WantDelegation as u32,
     DestroyClientId(StatusResult<()>) = OperationId::DestroyClientId as u32,
     ReclaimComplete(StatusResult<()>) = OperationId::ReclaimComplete as u32,
+    Copy(StatusResult<CopyRes>) = OperationId::Copy as u32,
+    CopyNotify(StatusResult<CopyNotifyRes>) = OperationId::CopyNotify as u32,
+    LayoutError(StatusResult<()>) = OperationId::LayoutError as u32,
+    LayoutStats(StatusResult<()>) = OperationId::LayoutStats as u32,
+    OffloadCancel(StatusResult<()>) = OperationId::OffloadCancel as u32,
+    OffloadStatus(StatusResult<OffloadStatusRes>) = OperationId::OffloadStatus as u32,
+}
+
+// The callback program, which the server calls to recall delegations and send notifications
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbCompoundArgs {
+    pub tag: String,
+    pub minor_version: u32,
+    pub callback_ident: u32,
+    pub arg_array: Vec<CbArgOp>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbCompoundRes {
+    pub status: StatusResult<()>,
+    pub tag: String,
+    pub res_array: Vec<CbResOp>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReferringCall {
+    pub sequence_id: SequenceId,
+    pub slot_id: SlotId,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct ReferringCallList {
+    pub session_id: SessionId,
+    pub referring_calls: Vec<ReferringCall>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbSequenceArgs {
+    pub session_id: SessionId,
+    pub sequence_id: SequenceId,
+    pub slot_id: SlotId,
+    pub highest_slot_id: SlotId,
+    pub cache_this: bool,
+    pub referring_call_lists: Vec<ReferringCallList>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbRecallArgs {
+    pub state_id: StateId,
+    pub truncate: bool,
+    pub fh: FileHandle,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct Notify {
+    pub mask: EnumSet<NotifyType>,
+    /// The XDR encoded details of each type of change in `mask`, in order. See `NotifyAdd` and
+    /// friends.
+    #[serde(with = "serde_bytes")]
+    pub values: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyEntry {
+    pub file: String,
+    pub attrs: FileAttributes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct PrevEntry {
+    pub prev_entry: NotifyEntry,
+    pub prev_entry_cookie: Cookie,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyRemove {
+    pub old_entry: NotifyEntry,
+    pub old_entry_cookie: Cookie,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyAdd {
+    pub old_entry: Option<NotifyRemove>,
+    pub new_entry: NotifyEntry,
+    pub new_entry_cookie: Option<Cookie>,
+    pub prev_entry: Option<PrevEntry>,
+    pub last_entry: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyAttr {
+    pub changed_entry: NotifyEntry,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyRename {
+    pub old_entry: NotifyRemove,
+    pub new_entry: NotifyAdd,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct NotifyVerifier {
+    pub old_cookie_verifier: Verifier,
+    pub new_cookie_verifier: Verifier,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbNotifyArgs {
+    pub state_id: StateId,
+    pub fh: FileHandle,
+    pub changes: Vec<Notify>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbRecallAnyArgs {
+    pub objects_to_keep: u32,
+    pub type_mask: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbRecallSlotArgs {
+    pub target_highest_slot_id: SlotId,
+}
+
+/// How a copy the server did in the background turned out.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum OffloadInfo {
+    Ok(WriteResponse),
+    Err {
+        status: StatusError,
+        bytes_copied: u64,
+    },
+}
+
+impl Serialize for OffloadInfo {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("OffloadInfo", 2)?;
+        match self {
+            Self::Ok(response) => {
+                state.serialize_field("discriminant", &0u32)?;
+                state.serialize_field("ok", response)?;
+            }
+            Self::Err {
+                status,
+                bytes_copied,
+            } => {
+                state.serialize_field("status", status)?;
+                state.serialize_field("bytes_copied", bytes_copied)?;
+            }
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for OffloadInfo {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = OffloadInfo;
+
+            fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> core::fmt::Result {
+                formatter.write_str("OffloadInfo")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let disc: u32 = seq
+                    .next_element()?
+                    .ok_or(serde::de::Error::custom("expected discriminant"))?;
+                if disc == 0 {
+                    return Ok(OffloadInfo::Ok(
+                        seq.next_element()?
+                            .ok_or(serde::de::Error::custom("expected value"))?,
+                    ));
+                }
+                let status: StatusError = disc.try_into().map_err(|_| {
+                    serde::de::Error::custom(format!("unexpected value {disc:?} for StatusError"))
+                })?;
+                let bytes_copied = seq
+                    .next_element()?
+                    .ok_or(serde::de::Error::custom("expected bytes copied"))?;
+                Ok(OffloadInfo::Err {
+                    status,
+                    bytes_copied,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("OffloadInfo", &["disc", "value"], Visitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbOffloadArgs {
+    pub fh: FileHandle,
+    pub state_id: StateId,
+    pub info: OffloadInfo,
+}
+
+#[derive(
+    SerializeWithDiscriminant,
+    DeserializeWithDiscriminant,
+    PartialEq,
+    Eq,
+    Copy,
+    Clone,
+    Debug,
+    PartialOrd,
+    Ord,
+    TryFromPrimitive,
+    IntoPrimitive,
+)]
+#[repr(u32)]
+pub enum CbOperationId {
+    GetAttr = 3,
+    Recall = 4,
+    LayoutRecall = 5,
+    Notify = 6,
+    PushDeleg = 7,
+    RecallAny = 8,
+    RecallableObjAvail = 9,
+    RecallSlot = 10,
+    Sequence = 11,
+    WantsCancelled = 12,
+    NotifyLock = 13,
+    NotifyDeviceId = 14,
+    Offload = 15,
+    Illegal = 10044,
+}
+
+#[derive(
+    SerializeWithDiscriminant, DeserializeWithDiscriminant, From, PartialEq, Eq, Clone, Debug,
+)]
+#[repr(u32)]
+pub enum CbArgOp {
+    Recall(CbRecallArgs) = CbOperationId::Recall as u32,
+    Notify(CbNotifyArgs) = CbOperationId::Notify as u32,
+    RecallAny(CbRecallAnyArgs) = CbOperationId::RecallAny as u32,
+    RecallSlot(CbRecallSlotArgs) = CbOperationId::RecallSlot as u32,
+    Sequence(CbSequenceArgs) = CbOperationId::Sequence as u32,
+    Offload(CbOffloadArgs) = CbOperationId::Offload as u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct CbSequenceRes {
+    pub session_id: SessionId,
+    pub sequence_id: SequenceId,
+    pub slot_id: SlotId,
+    pub highest_slot_id: SlotId,
+    pub target_highest_slot_id: SlotId,
+}
+
+#[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, PartialEq, Eq, Clone, Debug)]
+#[repr(u32)]
+pub enum CbResOp {
+    Recall(StatusResult<()>) = CbOperationId::Recall as u32,
+    Notify(StatusResult<()>) = CbOperationId::Notify as u32,
+    RecallAny(StatusResult<()>) = CbOperationId::RecallAny as u32,
+    RecallSlot(StatusResult<()>) = CbOperationId::RecallSlot as u32,
+    Sequence(StatusResult<CbSequenceRes>) = CbOperationId::Sequence as u32,
+    Offload(StatusResult<()>) = CbOperationId::Offload as u32,
+    Illegal(StatusResult<()>) = CbOperationId::Illegal as u32,
 }
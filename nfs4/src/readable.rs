@@ -0,0 +1,370 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! A self-describing serde form of the attribute types, for JSON and the like, as opposed to the
+//! XDR encoding their `Serialize` impls give. Use it on fields with
+//! `#[serde(with = "nfs4::readable")]`.
+//!
+//! Attributes are keyed by their name in snake case, like `time_modify`. Numbers, flags, names
+//! and times are given as they are, the type by its name, sets of attributes like
+//! `supported_attrs` as a list of their names and file handles in hex. The rest, like
+//! ACLs and layout hints, are given as the hex of their XDR encoding. Attributes we don't know
+//! are left out.
+
+use super::display::{parse_hex, Hex};
+use super::{
+    Cookie, DirectoryEntry, EnumSet, FileAttribute, FileAttributeId, FileAttributes, FileHandle,
+    FileType, ToId as _,
+};
+use alloc::format;
+use alloc::string::{String, ToString as _};
+use alloc::vec::Vec;
+use core::fmt;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::ser::{SerializeMap as _, Serializer};
+use serde::{Deserialize, Serialize};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// The types with a readable form.
+pub trait Readable: Sized + sealed::Sealed {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+pub fn serialize<T: Readable, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    value.serialize_readable(serializer)
+}
+
+pub fn deserialize<'de, T: Readable, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    T::deserialize_readable(deserializer)
+}
+
+struct Ref<'a, T>(&'a T);
+
+impl<T: Readable> Serialize for Ref<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_readable(serializer)
+    }
+}
+
+struct Owned<T>(T);
+
+impl<'de, T: Readable> Deserialize<'de> for Owned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize_readable(deserializer).map(Self)
+    }
+}
+
+impl<T: Readable> sealed::Sealed for Option<T> {}
+
+impl<T: Readable> Readable for Option<T> {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Some(value) => serializer.serialize_some(&Ref(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<Owned<T>>::deserialize(deserializer)?.map(|v| v.0))
+    }
+}
+
+impl<T: Readable> sealed::Sealed for Vec<T> {}
+
+impl<T: Readable> Readable for Vec<T> {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(Ref))
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values = Vec::<Owned<T>>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|v| v.0).collect())
+    }
+}
+
+impl sealed::Sealed for FileHandle {}
+
+impl Readable for FileHandle {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+const FILE_TYPES: [FileType; 9] = [
+    FileType::Regular,
+    FileType::Directory,
+    FileType::Block,
+    FileType::Character,
+    FileType::Link,
+    FileType::Socket,
+    FileType::Fifo,
+    FileType::AttrDir,
+    FileType::NamedAttr,
+];
+
+/// A name like `TimeModify` in snake case, like `time_modify`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && !snake.is_empty() {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+impl sealed::Sealed for FileType {}
+
+/// By name, like `directory`.
+impl Readable for FileType {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&snake_case(&format!("{self:?}")))
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        FILE_TYPES
+            .into_iter()
+            .find(|t| snake_case(&format!("{t:?}")) == name)
+            .ok_or_else(|| de::Error::custom(format!("unknown file type `{name}`")))
+    }
+}
+
+/// The name an attribute is keyed by, like `time_modify`.
+pub fn attribute_name(id: FileAttributeId) -> String {
+    snake_case(&format!("{id:?}"))
+}
+
+fn attribute_id(name: &str) -> Option<FileAttributeId> {
+    (0..=u32::from(FileAttributeId::XattrSupport))
+        .filter_map(|n| FileAttributeId::try_from(n).ok())
+        .find(|id| attribute_name(*id) == name)
+}
+
+impl sealed::Sealed for EnumSet<FileAttributeId> {}
+
+/// As a list of the attributes' names.
+impl Readable for EnumSet<FileAttributeId> {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(attribute_name))
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|name| {
+                attribute_id(&name)
+                    .ok_or_else(|| de::Error::custom(format!("unknown file attribute `{name}`")))
+            })
+            .collect()
+    }
+}
+
+/// The XDR encoding of the attribute's value, without the discriminant.
+fn attribute_xdr(attr: &FileAttribute) -> Vec<u8> {
+    let mut bytes = xdr_extras::to_bytes(attr).unwrap();
+    bytes.drain(..4);
+    bytes
+}
+
+fn attribute_from_xdr(id: FileAttributeId, value: &[u8]) -> Option<FileAttribute> {
+    let mut bytes = xdr_extras::to_bytes(&id).unwrap();
+    bytes.extend_from_slice(value);
+    xdr_extras::from_bytes(&bytes).ok()
+}
+
+// The attributes whose values are given by their own `Serialize` impls, which for these come out
+// the same in any format.
+macro_rules! readable_attributes {
+    ($($name:ident),* $(,)?) => {
+        fn serialize_attribute<M: serde::ser::SerializeMap>(
+            map: &mut M,
+            attr: &FileAttribute,
+        ) -> Result<(), M::Error> {
+            let name = attribute_name(attr.to_id());
+            match attr {
+                $(FileAttribute::$name(value) => map.serialize_entry(&name, value),)*
+                FileAttribute::SupportedAttrs(ids)
+                | FileAttribute::SupportedAttrsExclusiveCreate(ids) => {
+                    map.serialize_entry(&name, &Ref(ids))
+                }
+                FileAttribute::Type(file_type) => map.serialize_entry(&name, &Ref(file_type)),
+                FileAttribute::FileHandle(handle) => map.serialize_entry(&name, &Ref(handle)),
+                other => {
+                    map.serialize_entry(&name, &Hex(&attribute_xdr(other)).to_string())
+                }
+            }
+        }
+
+        fn deserialize_attribute<'de, M: MapAccess<'de>>(
+            map: &mut M,
+            id: FileAttributeId,
+        ) -> Result<FileAttribute, M::Error> {
+            Ok(match id {
+                $(FileAttributeId::$name => FileAttribute::$name(map.next_value()?),)*
+                FileAttributeId::SupportedAttrs => {
+                    FileAttribute::SupportedAttrs(map.next_value::<Owned<_>>()?.0)
+                }
+                FileAttributeId::SupportedAttrsExclusiveCreate => {
+                    FileAttribute::SupportedAttrsExclusiveCreate(map.next_value::<Owned<_>>()?.0)
+                }
+                FileAttributeId::Type => FileAttribute::Type(map.next_value::<Owned<_>>()?.0),
+                FileAttributeId::FileHandle => {
+                    FileAttribute::FileHandle(map.next_value::<Owned<_>>()?.0)
+                }
+                other => {
+                    let text: String = map.next_value()?;
+                    parse_hex(&text)
+                        .and_then(|value| attribute_from_xdr(other, &value))
+                        .ok_or_else(|| {
+                            de::Error::custom(format!(
+                                "invalid value for {}: `{text}`",
+                                attribute_name(other)
+                            ))
+                        })?
+                }
+            })
+        }
+    };
+}
+
+readable_attributes! {
+    Change,
+    Size,
+    LinkSupport,
+    SymlinkSupport,
+    NamedAttr,
+    FsId,
+    UniqueHandles,
+    LeaseTime,
+    Archive,
+    CanSetTime,
+    CaseInsensitive,
+    CasePreserving,
+    ChownRestricted,
+    FileId,
+    FilesAvail,
+    FilesFree,
+    FilesTotal,
+    Hidden,
+    Homogeneous,
+    MaxFileSize,
+    MaxLink,
+    MaxName,
+    MaxRead,
+    MaxWrite,
+    MimeType,
+    Mode,
+    NoTrunc,
+    NumLinks,
+    Owner,
+    OwnerGroup,
+    QuotaAvailHard,
+    QuotaAvailSoft,
+    QuotaUsed,
+    SpaceAvail,
+    SpaceFree,
+    SpaceTotal,
+    SpaceUsed,
+    System,
+    TimeAccess,
+    TimeBackup,
+    TimeCreate,
+    TimeDelta,
+    TimeMetadata,
+    TimeModify,
+    MountedOnFileid,
+    DirNotifDelay,
+    DirentNotifDelay,
+    LayoutBlksize,
+    LayoutAlignment,
+    RetentionHold,
+    CloneBlksize,
+    SpaceFreed,
+    XattrSupport,
+}
+
+impl sealed::Sealed for FileAttributes {}
+
+impl Readable for FileAttributes {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for attr in self.iter() {
+            serialize_attribute(&mut map, attr)?;
+        }
+        map.end()
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AttributesVisitor;
+
+        impl<'de> Visitor<'de> for AttributesVisitor {
+            type Value = FileAttributes;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a map of file attributes by name")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<FileAttributes, M::Error> {
+                let mut attrs = FileAttributes::default();
+                while let Some(name) = map.next_key::<String>()? {
+                    let id = attribute_id(&name).ok_or_else(|| {
+                        de::Error::custom(format!("unknown file attribute `{name}`"))
+                    })?;
+                    attrs.insert(deserialize_attribute(&mut map, id)?);
+                }
+                Ok(attrs)
+            }
+        }
+
+        deserializer.deserialize_map(AttributesVisitor)
+    }
+}
+
+#[derive(Serialize)]
+struct DirectoryEntryRef<'a> {
+    cookie: u64,
+    name: &'a str,
+    attrs: Ref<'a, FileAttributes>,
+}
+
+#[derive(Deserialize)]
+struct ReadableDirectoryEntry {
+    cookie: u64,
+    name: String,
+    #[serde(with = "self")]
+    attrs: FileAttributes,
+}
+
+impl sealed::Sealed for DirectoryEntry {}
+
+impl Readable for DirectoryEntry {
+    fn serialize_readable<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DirectoryEntryRef {
+            cookie: self.cookie.0,
+            name: &self.name,
+            attrs: Ref(&self.attrs),
+        }
+        .serialize(serializer)
+    }
+
+    fn deserialize_readable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entry = ReadableDirectoryEntry::deserialize(deserializer)?;
+        Ok(Self {
+            cookie: Cookie(entry.cookie),
+            name: entry.name,
+            attrs: entry.attrs,
+        })
+    }
+}
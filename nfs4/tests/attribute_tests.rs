@@ -0,0 +1,201 @@
+// copyright 2023 Remi Bernotavicius
+
+use nfs4::{
+    Ace, AceFlags, AceMask, AceType, Acl, AclFlags, AclSupport, AclWithFlags, ChangeAttrType,
+    ChangePolicy, EnumSet, FhExpireType, FileAttribute, FileAttributeId, FileAttributes,
+    FileAttributesBuilder, FileId, FsCharsetCap, Identity, LabelFormat, LayoutHint, LayoutType,
+    MdsThreshold, Mode, ModeMasked, ModeUmask, RetentionGet, RetentionSet, SecLabel, SetTime,
+    ThresholdAttribute, ThresholdItem, Time, ToId as _,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+fn round_trip<T>(value: &T) -> Vec<u8>
+where
+    T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+{
+    let bytes = xdr_extras::to_bytes(value).unwrap();
+    assert_eq!(&xdr_extras::from_bytes::<T>(&bytes).unwrap(), value);
+    bytes
+}
+
+#[test]
+fn every_attribute_id() {
+    let all: EnumSet<FileAttributeId> = (0..=FileAttributeId::XattrSupport as u32)
+        .map(|id| FileAttributeId::try_from(id).unwrap())
+        .collect();
+    round_trip(&all);
+}
+
+#[test]
+fn attributes_round_trip() {
+    let ace = Ace {
+        type_: AceType::AccessAllowed,
+        flags: AceFlags::IDENTIFIER_GROUP,
+        access_mask: AceMask::READ_DATA | AceMask::EXECUTE,
+        who: Identity("GROUP@".into()),
+    };
+    let attrs: FileAttributes = [
+        FileAttribute::FhExpireType(FhExpireType::VOLATILE_ANY | FhExpireType::VOL_RENAME),
+        FileAttribute::Acl(Acl {
+            aces: vec![ace.clone()],
+        }),
+        FileAttribute::AclSupport(AclSupport::ALLOW_ACL | AclSupport::DENY_ACL),
+        FileAttribute::Archive(true),
+        FileAttribute::Hidden(false),
+        FileAttribute::QuotaAvailHard(1 << 40),
+        FileAttribute::QuotaUsed(4096),
+        FileAttribute::System(true),
+        FileAttribute::TimeBackup(Time {
+            seconds: 1_700_000_000,
+            nseconds: 5,
+        }),
+        FileAttribute::MountedOnFileid(FileId(2)),
+        FileAttribute::Dacl(AclWithFlags {
+            flags: AclFlags::AUTO_INHERIT,
+            aces: vec![ace],
+        }),
+        FileAttribute::ChangePolicy(ChangePolicy { major: 7, minor: 1 }),
+        FileAttribute::LayoutHint(LayoutHint {
+            type_: LayoutType::NfsV41Files,
+            body: vec![1, 2, 3],
+        }),
+        FileAttribute::LayoutBlksize(65536),
+        FileAttribute::MdsThreshold(MdsThreshold {
+            hints: vec![ThresholdItem {
+                layout_type: LayoutType::FlexFiles,
+                hintset: [ThresholdAttribute::ReadSize(4096)].into_iter().collect(),
+            }],
+        }),
+        FileAttribute::RetentionGet(RetentionGet {
+            duration: 60,
+            begin_time: None,
+        }),
+        FileAttribute::RetentionSet(RetentionSet {
+            enable: true,
+            duration: Some(60),
+        }),
+        FileAttribute::ModeSetMasked(ModeMasked {
+            value: Mode(0o644),
+            mask: Mode(0o022),
+        }),
+        FileAttribute::FsCharsetCap(FsCharsetCap::ALLOWS_ONLY_UTF8),
+        FileAttribute::CloneBlksize(4096),
+        FileAttribute::SpaceFreed(8192),
+        FileAttribute::ChangeAttrType(ChangeAttrType::VersionCounter),
+        FileAttribute::SecLabel(SecLabel {
+            format: LabelFormat {
+                format: 1,
+                policy: 2,
+            },
+            data: b"system_u:object_r:nfs_t:s0".to_vec(),
+        }),
+        FileAttribute::ModeUmask(ModeUmask {
+            mode: Mode(0o777),
+            umask: Mode(0o022),
+        }),
+        FileAttribute::XattrSupport(true),
+    ]
+    .into_iter()
+    .collect();
+    round_trip(&attrs);
+}
+
+#[test]
+fn attributes_encoding() {
+    let attrs: FileAttributes = [
+        FileAttribute::ChangePolicy(ChangePolicy { major: 1, minor: 2 }),
+        FileAttribute::ModeUmask(ModeUmask {
+            mode: Mode(0o666),
+            umask: Mode(0o022),
+        }),
+    ]
+    .into_iter()
+    .collect();
+    let words: [u32; 10] = [
+        // The bitmap, change_policy is bit 60 and mode_umask bit 81
+        3,
+        0,
+        1 << (60 - 32),
+        1 << (81 - 64),
+        // The values, in the order of their IDs
+        24,
+        0,
+        1,
+        0,
+        2,
+        0o666,
+    ];
+    let mut expected: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    expected.extend(0o022u32.to_be_bytes());
+    assert_eq!(round_trip(&attrs), expected);
+}
+
+#[test]
+fn unknown_attributes() {
+    let words: [u32; 9] = [
+        // The bitmap, with size and two attributes from the future
+        3,
+        1 << 4,
+        0,
+        1 << (90 - 64) | 1 << (95 - 64),
+        // The values, the size and then whatever the others are
+        16,
+        0,
+        5,
+        0xdead,
+        0xbeef,
+    ];
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+
+    let attrs: FileAttributes = xdr_extras::from_bytes(&bytes).unwrap();
+    assert_eq!(attrs.get_as(FileAttributeId::Size), Some(&5u64));
+    let data = [0, 0, 0xde, 0xad, 0, 0, 0xbe, 0xef];
+    assert_eq!(
+        attrs.unknown().collect::<Vec<_>>(),
+        [(90, &data[..]), (95, &[][..])]
+    );
+    assert_eq!(xdr_extras::to_bytes(&attrs).unwrap(), bytes);
+
+    let supported: EnumSet<FileAttributeId> = xdr_extras::from_bytes(&bytes[..16]).unwrap();
+    assert_eq!(
+        supported.iter().collect::<Vec<_>>(),
+        [FileAttributeId::Size]
+    );
+}
+
+#[test]
+fn long_bitmap() {
+    // Size, then a million empty words and one from the far future
+    let mut words: Vec<u32> = vec![1_000_002, 1 << 4];
+    words.resize(1_000_002, 0);
+    words.push(1);
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+
+    let set: EnumSet<FileAttributeId> = xdr_extras::from_bytes(&bytes).unwrap();
+    assert_eq!(set.iter().collect::<Vec<_>>(), [FileAttributeId::Size]);
+}
+
+#[test]
+fn builder() {
+    let attrs = FileAttributesBuilder::new()
+        .size(100)
+        .mode(0o600)
+        .mode(0o644)
+        .owner("alice")
+        .mtime_now()
+        .build();
+    assert_eq!(
+        attrs,
+        [
+            FileAttribute::Size(100),
+            FileAttribute::Mode(Mode(0o644)),
+            FileAttribute::Owner("alice".into()),
+            FileAttribute::TimeModifySet(SetTime::SetToServerTime),
+        ]
+        .into_iter()
+        .collect()
+    );
+    assert!(attrs.iter().all(|attr| attr.to_id().is_settable()));
+    assert!(!FileAttributeId::TimeModify.is_settable());
+}
@@ -0,0 +1,515 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Replaying packet captures of NFS traffic, to check that every message in them decodes, and
+//! encodes back to the same bytes. Reads pcap and pcapng files, puts the TCP streams to and from
+//! the NFS port back together and splits them into RPC messages. COMPOUND calls and replies are
+//! decoded as our types, as are the callbacks servers send back over the same connection.
+//!
+//! Messages protected by RPCSEC_GSS can't be decoded, nor can replies to calls the capture
+//! doesn't have, so those are counted as skipped. Streams which start part way through a message
+//! are decoded from the first thing which looks like the start of one, and a stream stops being
+//! decoded where the capture is missing some of it.
+
+use nfs4::{CbCompoundArgs, CbCompoundRes, CompoundArgs, CompoundRes};
+use serde::de::{Deserialize as _, DeserializeOwned};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use sun_rpc::{AcceptedReplyBody, AuthFlavor, Message, MessageBody, ReplyBody};
+
+pub const NFS_PORT: u16 = 2049;
+
+const NFS: u32 = 100003;
+const NULL_PROCEDURE: u32 = 0;
+const COMPOUND_PROCEDURE: u32 = 1;
+
+/// The largest RPC message we believe, used when finding where messages start.
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded {
+    Compound(CompoundArgs),
+    CompoundReply(CompoundRes),
+    Callback(CbCompoundArgs),
+    CallbackReply(CbCompoundRes),
+    Null,
+}
+
+#[derive(Debug, Default)]
+pub struct Replay {
+    /// What the client of each connection sent followed by what the server sent, with
+    /// connections in the order they were first seen
+    pub decoded: Vec<Decoded>,
+    /// Messages we can't decode, see the module documentation
+    pub skipped: usize,
+    /// Streams which stop being decoded part way through, because the capture is missing some
+    pub incomplete_streams: usize,
+    /// What was wrong with each message which failed to decode
+    pub failures: Vec<String>,
+}
+
+pub fn replay_file(path: impl AsRef<Path>) -> Result<Replay, String> {
+    let path = path.as_ref();
+    let capture = std::fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    replay(&capture).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Replays the given contents of a pcap or pcapng file.
+pub fn replay(capture: &[u8]) -> Result<Replay, String> {
+    let mut connections = Connections::default();
+    for (link_type, frame) in frames(capture)? {
+        if let Some(segment) = tcp_segment(link_type, frame) {
+            connections.add(segment);
+        }
+    }
+    Ok(connections.replay())
+}
+
+/// Reads integers in the byte order of the capture file.
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8], at: usize) -> Option<u16> {
+        let bytes = bytes.get(at..at + 2)?.try_into().unwrap();
+        Some(if self.big {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(self, bytes: &[u8], at: usize) -> Option<u32> {
+        let bytes = bytes.get(at..at + 4)?.try_into().unwrap();
+        Some(if self.big {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+const BIG: Endian = Endian { big: true };
+
+const PCAP_MAGIC: u32 = 0xa1b2c3d4;
+const PCAP_NANOSECOND_MAGIC: u32 = 0xa1b23c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+/// The link type and contents of each frame in the capture.
+fn frames(capture: &[u8]) -> Result<Vec<(u32, &[u8])>, String> {
+    let magic = BIG.u32(capture, 0).ok_or("too short to be a capture")?;
+    if magic == PCAPNG_SECTION_HEADER {
+        return pcapng_frames(capture);
+    }
+    let endian = if [PCAP_MAGIC, PCAP_NANOSECOND_MAGIC].contains(&magic) {
+        BIG
+    } else if [PCAP_MAGIC, PCAP_NANOSECOND_MAGIC].contains(&magic.swap_bytes()) {
+        Endian { big: false }
+    } else {
+        return Err(format!("not a pcap or pcapng file, magic {magic:#010x}"));
+    };
+    let link_type = endian.u32(capture, 20).ok_or("truncated pcap header")?;
+
+    let mut frames = vec![];
+    let mut at = 24;
+    while at < capture.len() {
+        let length = endian
+            .u32(capture, at + 8)
+            .ok_or("truncated packet header")? as usize;
+        let frame = capture
+            .get(at + 16..at + 16 + length)
+            .ok_or("truncated packet")?;
+        frames.push((link_type, frame));
+        at += 16 + length;
+    }
+    Ok(frames)
+}
+
+fn pcapng_frames(capture: &[u8]) -> Result<Vec<(u32, &[u8])>, String> {
+    let mut frames = vec![];
+    let mut endian = BIG;
+    let mut link_types = vec![];
+    let mut at = 0;
+    while at < capture.len() {
+        if BIG.u32(capture, at) == Some(PCAPNG_SECTION_HEADER) {
+            let byte_order = BIG.u32(capture, at + 8).ok_or("truncated section header")?;
+            endian = Endian {
+                big: byte_order == PCAPNG_BYTE_ORDER_MAGIC,
+            };
+            // Interfaces are numbered from the start of each section
+            link_types.clear();
+        }
+        let block_type = endian.u32(capture, at).ok_or("truncated block")?;
+        let length = endian.u32(capture, at + 4).ok_or("truncated block")? as usize;
+        if length < 12 {
+            return Err(format!("block of {length} bytes"));
+        }
+        let block = capture
+            .get(at + 8..at + length - 4)
+            .ok_or("truncated block")?;
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                link_types.push(endian.u16(block, 0).ok_or("truncated interface")?.into());
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = endian.u32(block, 0).ok_or("truncated packet")? as usize;
+                let captured = endian.u32(block, 12).ok_or("truncated packet")? as usize;
+                let link_type = *link_types.get(interface).ok_or("unknown interface")?;
+                let frame = block.get(20..20 + captured).ok_or("truncated packet")?;
+                frames.push((link_type, frame));
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let original = endian.u32(block, 0).ok_or("truncated packet")? as usize;
+                let link_type = *link_types.first().ok_or("unknown interface")?;
+                let frame = &block[4..];
+                frames.push((link_type, &frame[..original.min(frame.len())]));
+            }
+            _ => {}
+        }
+        at += length;
+    }
+    Ok(frames)
+}
+
+const LINK_TYPE_NULL: u32 = 0;
+const LINK_TYPE_ETHERNET: u32 = 1;
+const LINK_TYPE_RAW: u32 = 101;
+const LINK_TYPE_LINUX_SLL: u32 = 113;
+const LINK_TYPE_IPV4: u32 = 228;
+const LINK_TYPE_IPV6: u32 = 229;
+const LINK_TYPE_LINUX_SLL2: u32 = 276;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const ETHER_TYPE_VLAN: u16 = 0x8100;
+const ETHER_TYPE_QINQ: u16 = 0x88a8;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const TCP_SYN: u8 = 0x02;
+
+struct Segment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    syn: bool,
+    data: &'a [u8],
+}
+
+/// The TCP segment in the frame, if it is one to or from the NFS port.
+fn tcp_segment(link_type: u32, frame: &[u8]) -> Option<Segment<'_>> {
+    let packet = match link_type {
+        LINK_TYPE_ETHERNET => {
+            let mut at = 12;
+            loop {
+                match BIG.u16(frame, at)? {
+                    ETHER_TYPE_VLAN | ETHER_TYPE_QINQ => at += 4,
+                    ETHER_TYPE_IPV4 | ETHER_TYPE_IPV6 => break,
+                    _ => return None,
+                }
+            }
+            frame.get(at + 2..)?
+        }
+        // The address family is in the byte order of the machine that captured it, but the
+        // IP header says which version it is anyway
+        LINK_TYPE_NULL => frame.get(4..)?,
+        LINK_TYPE_LINUX_SLL => frame.get(16..)?,
+        LINK_TYPE_LINUX_SLL2 => frame.get(20..)?,
+        LINK_TYPE_RAW | LINK_TYPE_IPV4 | LINK_TYPE_IPV6 => frame,
+        _ => return None,
+    };
+
+    let (source, destination, tcp) = match packet.first()? >> 4 {
+        4 => {
+            let header_length = usize::from(packet[0] & 0xf) * 4;
+            let total_length = usize::from(BIG.u16(packet, 2)?);
+            let fragment = BIG.u16(packet, 6)? & 0x3fff;
+            if *packet.get(9)? != IP_PROTOCOL_TCP || fragment != 0 {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().unwrap();
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().unwrap();
+            // Anything past the total length is padding of the frame
+            let end = total_length.min(packet.len());
+            (
+                IpAddr::from(Ipv4Addr::from(source)),
+                IpAddr::from(Ipv4Addr::from(destination)),
+                packet.get(header_length..end)?,
+            )
+        }
+        6 => {
+            let payload_length = usize::from(BIG.u16(packet, 4)?);
+            if *packet.get(6)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let source: [u8; 16] = packet.get(8..24)?.try_into().unwrap();
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().unwrap();
+            let end = (40 + payload_length).min(packet.len());
+            (
+                IpAddr::from(Ipv6Addr::from(source)),
+                IpAddr::from(Ipv6Addr::from(destination)),
+                packet.get(40..end)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = BIG.u16(tcp, 0)?;
+    let destination_port = BIG.u16(tcp, 2)?;
+    if source_port != NFS_PORT && destination_port != NFS_PORT {
+        return None;
+    }
+    let header_length = usize::from(tcp.get(12)? >> 4) * 4;
+    Some(Segment {
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        sequence: BIG.u32(tcp, 4)?,
+        syn: tcp.get(13)? & TCP_SYN != 0,
+        data: tcp.get(header_length..)?,
+    })
+}
+
+/// One direction of a TCP connection.
+#[derive(Default)]
+struct Stream {
+    // Where the stream starts, if we saw the SYN
+    start: Option<u32>,
+    segments: Vec<(u32, Vec<u8>)>,
+}
+
+impl Stream {
+    /// The data of the stream up to the first of it missing from the capture, and whether
+    /// anything is missing.
+    fn assemble(mut self) -> (Vec<u8>, bool) {
+        let Some(start) = self.start.or(self.segments.first().map(|(s, _)| *s)) else {
+            return (vec![], false);
+        };
+        // Offsets which are "negative" are retransmissions of what came before the capture
+        let mut segments: Vec<(usize, Vec<u8>)> = std::mem::take(&mut self.segments)
+            .into_iter()
+            .map(|(sequence, data)| (sequence.wrapping_sub(start), data))
+            .filter(|(offset, _)| *offset < 1 << 31)
+            .map(|(offset, data)| (offset as usize, data))
+            .collect();
+        segments.sort_by_key(|(offset, _)| *offset);
+
+        let mut stream = vec![];
+        for (offset, data) in segments {
+            if offset > stream.len() {
+                return (stream, true);
+            }
+            if offset + data.len() > stream.len() {
+                stream.extend_from_slice(&data[stream.len() - offset..]);
+            }
+        }
+        (stream, false)
+    }
+}
+
+/// The source and destination of a stream, or the client and server of a connection.
+type Addresses = (SocketAddr, SocketAddr);
+
+#[derive(Default)]
+struct Connections {
+    // By source and destination, in the order they first appear
+    streams: Vec<(Addresses, Stream)>,
+    index: HashMap<Addresses, usize>,
+}
+
+impl Connections {
+    fn add(&mut self, segment: Segment<'_>) {
+        let key = (segment.source, segment.destination);
+        let index = *self.index.entry(key).or_insert_with(|| {
+            self.streams.push((key, Stream::default()));
+            self.streams.len() - 1
+        });
+        let stream = &mut self.streams[index].1;
+        if segment.syn {
+            // The SYN takes up a sequence number of its own
+            stream.start = Some(segment.sequence.wrapping_add(1));
+        }
+        if !segment.data.is_empty() {
+            stream
+                .segments
+                .push((segment.sequence, segment.data.to_vec()));
+        }
+    }
+
+    fn replay(self) -> Replay {
+        let mut replay = Replay::default();
+        let mut connections: Vec<(Addresses, Vec<Vec<u8>>)> = vec![];
+        let mut connection_index = HashMap::new();
+        for ((source, destination), stream) in self.streams {
+            let start_known = stream.start.is_some();
+            let (data, incomplete) = stream.assemble();
+            if incomplete {
+                replay.incomplete_streams += 1;
+            }
+            let messages = split_records(&data, start_known);
+
+            // Both directions of a connection are replayed together, with the client first
+            let client_to_server = destination.port() == NFS_PORT;
+            let key = if client_to_server {
+                (source, destination)
+            } else {
+                (destination, source)
+            };
+            let index = *connection_index.entry(key).or_insert_with(|| {
+                connections.push((key, vec![]));
+                connections.len() - 1
+            });
+            connections[index].1.extend(messages);
+        }
+
+        for ((client, server), messages) in connections {
+            replay_connection(&mut replay, &format!("{client} -> {server}"), messages);
+        }
+        replay
+    }
+}
+
+fn looks_like_start_of_message(data: &[u8]) -> bool {
+    let Some(header) = BIG.u32(data, 0) else {
+        return false;
+    };
+    let length = (header & 0x7fff_ffff) as usize;
+    if !(12..MAX_MESSAGE_SIZE).contains(&length) {
+        return false;
+    }
+    match (BIG.u32(data, 8), BIG.u32(data, 12), BIG.u32(data, 16)) {
+        // A call of RPC version 2
+        (Some(0), Some(2), _) => true,
+        // An accepted reply with a verifier of a flavor there is
+        (Some(1), Some(0), Some(flavor)) => flavor <= AuthFlavor::RpcSecGss as u32,
+        _ => false,
+    }
+}
+
+/// Splits the stream into RPC messages, undoing the record marking. Unless we know where the
+/// stream starts, it is from the first thing that looks like the start of a message.
+fn split_records(data: &[u8], start_known: bool) -> Vec<Vec<u8>> {
+    let mut at = 0;
+    if !start_known {
+        while at + 20 <= data.len() && !looks_like_start_of_message(&data[at..]) {
+            at += 4;
+        }
+    }
+
+    let mut messages = vec![];
+    let mut message = vec![];
+    while let Some(header) = BIG.u32(data, at) {
+        let length = (header & 0x7fff_ffff) as usize;
+        let Some(fragment) = data.get(at + 4..at + 4 + length) else {
+            break;
+        };
+        message.extend_from_slice(fragment);
+        if header & 0x8000_0000 != 0 {
+            messages.push(std::mem::take(&mut message));
+        }
+        at += 4 + length;
+    }
+    messages
+}
+
+/// What the reply to a call is, and so what is in the call itself.
+#[derive(Clone, Copy)]
+enum Expected {
+    Null,
+    CompoundReply,
+    CallbackReply,
+    Undecodable,
+}
+
+/// Decodes the message body, which must also be exactly what encoding what was decoded gives, so
+/// the captures are golden vectors for the encoding as well as the decoding.
+fn decode<T: DeserializeOwned + Serialize + fmt::Debug>(body: &[u8]) -> Result<T, String> {
+    let mut deserializer = xdr_extras::Deserializer::new(body);
+    let decoded = T::deserialize(&mut deserializer).map_err(|e| format!("{e:?}"))?;
+    let rest = deserializer.remaining();
+    if !rest.is_empty() {
+        return Err(format!(
+            "{} bytes left over after decoding {decoded:?}",
+            rest.len()
+        ));
+    }
+    let encoded = xdr_extras::to_bytes(&decoded).map_err(|e| format!("{e:?}"))?;
+    if encoded != body {
+        return Err(format!(
+            "{decoded:?} encodes differently from how it was received"
+        ));
+    }
+    Ok(decoded)
+}
+
+fn replay_connection(replay: &mut Replay, name: &str, messages: Vec<Vec<u8>>) {
+    let mut headers = vec![];
+    for message in &messages {
+        let mut deserializer = xdr_extras::Deserializer::new(message);
+        match Message::<()>::deserialize(&mut deserializer) {
+            Ok(header) => headers.push((header, deserializer.remaining())),
+            Err(error) => replay
+                .failures
+                .push(format!("{name}: bad RPC header: {error:?}")),
+        }
+    }
+
+    // Callbacks are answered in the other direction, so we need all the calls first
+    let mut calls = BTreeMap::new();
+    for (header, _) in &headers {
+        if let MessageBody::Call(call) = &header.body {
+            let expected = match (call.program, call.procedure) {
+                _ if call.credential.flavor == AuthFlavor::RpcSecGss => Expected::Undecodable,
+                (_, NULL_PROCEDURE) => Expected::Null,
+                (NFS, COMPOUND_PROCEDURE) => Expected::CompoundReply,
+                // Servers call back whatever program number the client gave them
+                (_, COMPOUND_PROCEDURE) => Expected::CallbackReply,
+                _ => Expected::Undecodable,
+            };
+            calls.insert(header.xid.0, expected);
+        }
+    }
+
+    for (header, body) in headers {
+        let xid = header.xid.0;
+        let decoded = match header.body {
+            MessageBody::Call(_) => match calls[&xid] {
+                Expected::Null if body.is_empty() => Ok(Decoded::Null),
+                Expected::Null => Err(format!("NULL call with {} bytes", body.len())),
+                Expected::CompoundReply => decode(body).map(Decoded::Compound),
+                Expected::CallbackReply => decode(body).map(Decoded::Callback),
+                Expected::Undecodable => {
+                    replay.skipped += 1;
+                    continue;
+                }
+            },
+            MessageBody::Reply(ReplyBody::Accepted(accepted)) => {
+                // Only successful replies have anything in them to decode
+                if accepted.body != AcceptedReplyBody::Success(()) {
+                    continue;
+                }
+                match calls.get(&xid) {
+                    Some(Expected::Null) if body.is_empty() => Ok(Decoded::Null),
+                    Some(Expected::Null) => Err(format!("NULL reply with {} bytes", body.len())),
+                    Some(Expected::CompoundReply) => decode(body).map(Decoded::CompoundReply),
+                    Some(Expected::CallbackReply) => decode(body).map(Decoded::CallbackReply),
+                    Some(Expected::Undecodable) | None => {
+                        replay.skipped += 1;
+                        continue;
+                    }
+                }
+            }
+            MessageBody::Reply(ReplyBody::Denied(_)) => continue,
+        };
+        match decoded {
+            Ok(decoded) => replay.decoded.push(decoded),
+            Err(error) => replay.failures.push(format!("{name}: {xid:#x}: {error}")),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2023 Remi Bernotavicius
+
+mod capture;
+
+use capture::{Decoded, Replay, NFS_PORT};
+use nfs4::{
+    ArgOp, CbCompoundArgs, CbCompoundRes, CompoundArgs, CompoundRes, FileHandle, GetFhRes, ResOp,
+    StatusResult,
+};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use sun_rpc::{
+    AcceptedReply, AcceptedReplyBody, AuthFlavor, CallBody, Message, MessageBody, OpaqueAuth,
+    ReplyBody, Xid,
+};
+
+/// Every capture in `tests/captures` decodes, see the README there for where they come from.
+#[test]
+fn captures_decode() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/captures");
+    let mut failures = vec![];
+    let mut captures = 0;
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if !matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("pcap" | "pcapng")
+        ) {
+            continue;
+        }
+        let replay = capture::replay_file(&path).unwrap();
+        println!(
+            "{}: {} decoded, {} skipped, {} incomplete streams",
+            path.display(),
+            replay.decoded.len(),
+            replay.skipped,
+            replay.incomplete_streams
+        );
+        if replay.decoded.is_empty() {
+            failures.push(format!("{}: nothing decoded", path.display()));
+        }
+        failures.extend(replay.failures);
+        captures += 1;
+    }
+    assert!(captures > 0, "no captures in {dir}");
+    assert!(failures.is_empty(), "{failures:#?}");
+}
+
+fn call<T>(xid: u32, program: u32, procedure: u32, args: T) -> Message<T> {
+    Message {
+        xid: Xid(xid),
+        body: MessageBody::Call(CallBody {
+            rpc_version: 2,
+            program,
+            version: 4,
+            procedure,
+            credential: OpaqueAuth::none(),
+            verifier: OpaqueAuth::none(),
+            call_args: args,
+        }),
+    }
+}
+
+fn reply<T>(xid: u32, res: T) -> Message<T> {
+    Message {
+        xid: Xid(xid),
+        body: MessageBody::Reply(ReplyBody::Accepted(AcceptedReply {
+            verifier: OpaqueAuth::none(),
+            body: AcceptedReplyBody::Success(res),
+        })),
+    }
+}
+
+/// The message with record marking, split in to fragments of at most the given size.
+fn record(message: &impl Serialize, max_fragment_size: usize) -> Vec<u8> {
+    let message = xdr_extras::to_bytes(message).unwrap();
+    let mut record = vec![];
+    let fragments: Vec<&[u8]> = message.chunks(max_fragment_size).collect();
+    for (i, fragment) in fragments.iter().enumerate() {
+        let last = if i == fragments.len() - 1 { 1 << 31 } else { 0 };
+        record.extend((fragment.len() as u32 | last).to_be_bytes());
+        record.extend(*fragment);
+    }
+    record
+}
+
+fn tcp(source: SocketAddr, destination: SocketAddr, sequence: u32, syn: bool) -> Vec<u8> {
+    let mut tcp = vec![];
+    tcp.extend(source.port().to_be_bytes());
+    tcp.extend(destination.port().to_be_bytes());
+    tcp.extend(sequence.to_be_bytes());
+    tcp.extend([0; 4]);
+    tcp.extend([5 << 4, if syn { 0x02 } else { 0x18 }]);
+    tcp.extend([0; 6]);
+    tcp
+}
+
+/// An Ethernet frame with the TCP segment in it.
+fn ethernet_frame(
+    source: SocketAddr,
+    destination: SocketAddr,
+    sequence: u32,
+    syn: bool,
+    data: &[u8],
+) -> Vec<u8> {
+    let (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) = (source.ip(), destination.ip())
+    else {
+        unreachable!()
+    };
+    let mut frame = vec![0; 12];
+    frame.extend(0x0800u16.to_be_bytes());
+    frame.extend([0x45, 0]);
+    frame.extend((20u16 + 20 + data.len() as u16).to_be_bytes());
+    frame.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+    frame.extend(source_ip.octets());
+    frame.extend(destination_ip.octets());
+    frame.extend(tcp(source, destination, sequence, syn));
+    frame.extend(data);
+    // Ethernet frames are padded to the minimum size
+    frame.resize(frame.len().max(60), 0);
+    frame
+}
+
+/// An IPv6 packet with the TCP segment in it.
+fn ipv6_packet(source: SocketAddr, destination: SocketAddr, sequence: u32, data: &[u8]) -> Vec<u8> {
+    let (IpAddr::V6(source_ip), IpAddr::V6(destination_ip)) = (source.ip(), destination.ip())
+    else {
+        unreachable!()
+    };
+    let mut packet = vec![0x60, 0, 0, 0];
+    packet.extend((20 + data.len() as u16).to_be_bytes());
+    packet.extend([6, 64]);
+    packet.extend(source_ip.octets());
+    packet.extend(destination_ip.octets());
+    packet.extend(tcp(source, destination, sequence, false));
+    packet.extend(data);
+    packet
+}
+
+/// A little-endian pcap file of Ethernet frames.
+fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut pcap = vec![];
+    pcap.extend(0xa1b2c3d4u32.to_le_bytes());
+    pcap.extend([2, 0, 4, 0]);
+    pcap.extend([0; 8]);
+    pcap.extend(65535u32.to_le_bytes());
+    pcap.extend(1u32.to_le_bytes());
+    for frame in frames {
+        pcap.extend([0; 8]);
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend((frame.len() as u32).to_le_bytes());
+        pcap.extend(frame);
+    }
+    pcap
+}
+
+fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let length = (12 + body.len().next_multiple_of(4)) as u32;
+    let mut block = vec![];
+    block.extend(block_type.to_be_bytes());
+    block.extend(length.to_be_bytes());
+    block.extend(body);
+    block.resize(length as usize - 4, 0);
+    block.extend(length.to_be_bytes());
+    block
+}
+
+/// A big-endian pcapng file of raw IPv6 packets.
+fn pcapng(packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut section_header = vec![];
+    section_header.extend(0x1a2b3c4du32.to_be_bytes());
+    section_header.extend([0, 1, 0, 0]);
+    section_header.extend(u64::MAX.to_be_bytes());
+    let mut pcapng = pcapng_block(0x0a0d0d0a, &section_header);
+
+    let mut interface = vec![];
+    interface.extend(229u16.to_be_bytes());
+    interface.extend([0; 6]);
+    pcapng.extend(pcapng_block(1, &interface));
+
+    for packet in packets {
+        let mut enhanced_packet = vec![0; 12];
+        enhanced_packet.extend((packet.len() as u32).to_be_bytes());
+        enhanced_packet.extend((packet.len() as u32).to_be_bytes());
+        enhanced_packet.extend(packet);
+        pcapng.extend(pcapng_block(6, &enhanced_packet));
+    }
+    pcapng
+}
+
+fn compound() -> CompoundArgs {
+    CompoundArgs {
+        tag: "".into(),
+        minor_version: 1,
+        arg_array: vec![ArgOp::PutRootFh, ArgOp::GetFh],
+    }
+}
+
+fn compound_res() -> CompoundRes {
+    CompoundRes {
+        status: StatusResult::Ok(()),
+        tag: "".into(),
+        res_array: vec![
+            ResOp::PutRootFh(StatusResult::Ok(())),
+            ResOp::GetFh(StatusResult::Ok(GetFhRes {
+                object: FileHandle(vec![1, 2, 3].into()),
+            })),
+        ],
+    }
+}
+
+#[test]
+fn replay_pcap() {
+    let client = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 700);
+    let server = SocketAddr::new(Ipv4Addr::new(10, 0, 0, 2).into(), NFS_PORT);
+
+    let mut gss_call = call(3, 100003, 1, compound());
+    if let MessageBody::Call(call) = &mut gss_call.body {
+        call.credential.flavor = AuthFlavor::RpcSecGss;
+    }
+    let callback_args = CbCompoundArgs {
+        tag: "".into(),
+        minor_version: 1,
+        callback_ident: 0,
+        arg_array: vec![],
+    };
+    let callback_res = CbCompoundRes {
+        status: StatusResult::Ok(()),
+        tag: "".into(),
+        res_array: vec![],
+    };
+
+    let from_client = [
+        record(&call(1, 100003, 0, ()), 1024),
+        record(&call(2, 100003, 1, compound()), 1024),
+        record(&gss_call, 1024),
+        record(&reply(7, callback_res.clone()), 1024),
+    ]
+    .concat();
+    let from_server = [
+        record(&reply(1, ()), 1024),
+        record(&reply(2, compound_res()), 1024),
+        record(&reply(3, compound_res()), 1024),
+        record(&call(7, 0x40000000, 1, callback_args.clone()), 1024),
+    ]
+    .concat();
+
+    let mut frames = vec![
+        ethernet_frame(client, server, 1000, true, &[]),
+        ethernet_frame(server, client, 5000, true, &[]),
+    ];
+    // What the client sent arrives in pieces, out of order and some of it twice
+    let pieces: Vec<&[u8]> = from_client.chunks(30).collect();
+    for i in [1, 0, 2, 2, 3].into_iter().chain(4..pieces.len()) {
+        let sequence = 1001 + i as u32 * 30;
+        frames.push(ethernet_frame(client, server, sequence, false, pieces[i]));
+    }
+    frames.push(ethernet_frame(server, client, 5001, false, &from_server));
+
+    let replay: Replay = capture::replay(&pcap(&frames)).unwrap();
+    assert_eq!(replay.failures, Vec::<String>::new());
+    assert_eq!(
+        replay.decoded,
+        [
+            Decoded::Null,
+            Decoded::Compound(compound()),
+            Decoded::CallbackReply(callback_res),
+            Decoded::Null,
+            Decoded::CompoundReply(compound_res()),
+            Decoded::Callback(callback_args),
+        ]
+    );
+    assert_eq!(replay.skipped, 2);
+    assert_eq!(replay.incomplete_streams, 0);
+}
+
+#[test]
+fn replay_pcapng_from_part_way_through() {
+    let client = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 700);
+    let server = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), NFS_PORT);
+
+    // The capture starts with the end of a call, and the next is sent in small fragments
+    let earlier = record(&call(1, 100003, 1, compound()), 1024);
+    let fragmented = record(&call(2, 100003, 1, compound()), 16);
+    let from_client = [
+        &earlier[earlier.len() - 12..],
+        &fragmented[..],
+        &record(&call(3, 100003, 1, compound()), 1024)[..],
+        &record(&call(4, 100003, 1, compound()), 1024)[..],
+    ]
+    .concat();
+    let earlier = record(&reply(1, compound_res()), 1024);
+    let from_server = [
+        &earlier[earlier.len() - 20..],
+        &record(&reply(2, compound_res()), 1024)[..],
+    ]
+    .concat();
+
+    // Part of the last two calls is missing from the capture
+    let missing_from = 12 + fragmented.len() + 10;
+    let missing_to = missing_from + 100;
+    let packets = [
+        ipv6_packet(client, server, 70000, &from_client[..missing_from]),
+        ipv6_packet(
+            client,
+            server,
+            70000 + missing_to as u32,
+            &from_client[missing_to..],
+        ),
+        ipv6_packet(server, client, u32::MAX - 10, &from_server),
+    ];
+    let replay = capture::replay(&pcapng(&packets)).unwrap();
+    assert_eq!(replay.failures, Vec::<String>::new());
+    assert_eq!(
+        replay.decoded,
+        [
+            Decoded::Compound(compound()),
+            Decoded::CompoundReply(compound_res())
+        ]
+    );
+    assert_eq!(replay.incomplete_streams, 1);
+}
@@ -0,0 +1,91 @@
+// Copyright 2023 Remi Bernotavicius
+
+#[cfg(feature = "chrono")]
+use nfs4::Time;
+use nfs4::{FileHandle, Mode, StateId};
+use std::fmt;
+use std::str::FromStr;
+
+fn display_and_parse<T>(value: T, text: &str)
+where
+    T: fmt::Display + FromStr + PartialEq + fmt::Debug,
+    T::Err: fmt::Debug,
+{
+    assert_eq!(value.to_string(), text);
+    assert_eq!(text.parse::<T>().unwrap(), value);
+}
+
+#[test]
+fn file_handle() {
+    display_and_parse(FileHandle(vec![0x01, 0x00, 0xab, 0xff].into()), "0100abff");
+    assert_eq!(
+        "0100ABFF".parse::<FileHandle>().unwrap(),
+        FileHandle(vec![0x01, 0x00, 0xab, 0xff].into())
+    );
+    for bad in ["010", "0g", "é0"] {
+        let error = bad.parse::<FileHandle>().unwrap_err();
+        assert_eq!(error.to_string(), format!("invalid file handle `{bad}`"));
+    }
+}
+
+#[test]
+fn state_id() {
+    let state_id = StateId {
+        sequence_id: 3,
+        other: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+    };
+    display_and_parse(state_id, "3:000102030405060708090a0b");
+    for bad in [
+        "000102030405060708090a0b",
+        "3:0001",
+        "x:000102030405060708090a0b",
+    ] {
+        assert!(bad.parse::<StateId>().is_err());
+    }
+}
+
+#[test]
+fn mode() {
+    display_and_parse(Mode(0o755), "rwxr-xr-x");
+    display_and_parse(Mode(0o640), "rw-r-----");
+    display_and_parse(Mode(0o4755), "rwsr-xr-x");
+    display_and_parse(Mode(0o2644), "rw-r-Sr--");
+    display_and_parse(Mode(0o1777), "rwxrwxrwt");
+    display_and_parse(Mode(0o1776), "rwxrwxrwT");
+
+    assert_eq!("755".parse::<Mode>().unwrap(), Mode(0o755));
+    assert_eq!("04755".parse::<Mode>().unwrap(), Mode(0o4755));
+    for bad in ["", "789", "17777", "rwxr-xr-", "rwxr-xr-s", "xwrr-xr-x"] {
+        assert!(bad.parse::<Mode>().is_err(), "{bad}");
+    }
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn time() {
+    display_and_parse(
+        Time {
+            seconds: 1_680_352_200,
+            nseconds: 500_000_000,
+        },
+        "2023-04-01T12:30:00.500Z",
+    );
+    display_and_parse(Time::default(), "1970-01-01T00:00:00Z");
+    // Too far off for a date, but still shown as something which parses back
+    display_and_parse(
+        Time {
+            seconds: i64::MAX,
+            nseconds: 1,
+        },
+        "9223372036854775807.000000001",
+    );
+
+    assert_eq!(
+        "2023-04-01T14:30:00+02:00".parse::<Time>().unwrap(),
+        Time {
+            seconds: 1_680_352_200,
+            nseconds: 0
+        }
+    );
+    assert!("yesterday".parse::<Time>().is_err());
+}
@@ -1,5 +1,11 @@
 // copyright 2023 Remi Bernotavicius
 
+#![allow(
+    clippy::op_ref,
+    clippy::needless_borrows_for_generic_args,
+    clippy::clone_on_copy
+)]
+
 use nfs4::{
     Change, Cookie, DeviceData, DirectoryEntry, DirectoryList, EnumMap, EnumSet, FileAttribute,
     FileAttributeId, FileHandle, FileId, FileType, FsId, Mode, StatusResult, Time,
@@ -118,12 +124,15 @@ fn directory_list_serialization_round_trip() {
                         minor: 0,
                     }),
                     FileAttribute::ReadDirAttrError(StatusResult::Ok(())),
-                    FileAttribute::FileHandle(FileHandle(vec![
-                        0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
-                        0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x52, 0x02, 0x34, 0x0f, 0x6a, 0xd7, 0x7a, 0xae,
-                        0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
-                    ])),
+                    FileAttribute::FileHandle(FileHandle(
+                        vec![
+                            0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
+                            0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x52, 0x02, 0x34, 0x0f, 0x6a, 0xd7, 0x7a, 0xae,
+                            0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
+                        ]
+                        .into(),
+                    )),
                     FileAttribute::FileId(FileId(255066706)),
                     FileAttribute::Mode(Mode(0o0664)),
                     FileAttribute::NumLinks(1),
@@ -160,12 +169,15 @@ fn directory_list_serialization_round_trip() {
                         minor: 0,
                     }),
                     FileAttribute::ReadDirAttrError(StatusResult::Ok(())),
-                    FileAttribute::FileHandle(FileHandle(vec![
-                        0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
-                        0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x53, 0x02, 0x34, 0x0f, 0x6b, 0xd7, 0x7a, 0xae,
-                        0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
-                    ])),
+                    FileAttribute::FileHandle(FileHandle(
+                        vec![
+                            0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
+                            0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x53, 0x02, 0x34, 0x0f, 0x6b, 0xd7, 0x7a, 0xae,
+                            0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
+                        ]
+                        .into(),
+                    )),
                     FileAttribute::FileId(FileId(255066707)),
                     FileAttribute::Mode(Mode(0o0664)),
                     FileAttribute::NumLinks(1),
@@ -202,12 +214,15 @@ fn directory_list_serialization_round_trip() {
                         minor: 0,
                     }),
                     FileAttribute::ReadDirAttrError(StatusResult::Ok(())),
-                    FileAttribute::FileHandle(FileHandle(vec![
-                        0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
-                        0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
-                        0x00, 0x00, 0x00, 0x00, 0x51, 0x02, 0x34, 0x0f, 0x69, 0xd7, 0x7a, 0xae,
-                        0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
-                    ])),
+                    FileAttribute::FileHandle(FileHandle(
+                        vec![
+                            0x01, 0x00, 0x07, 0x02, 0x01, 0x00, 0xee, 0x0e, 0x00, 0x00, 0x00, 0x00,
+                            0xab, 0x2a, 0x5a, 0x2f, 0xfe, 0xce, 0x9f, 0x3c, 0x00, 0x00, 0x00, 0x00,
+                            0x00, 0x00, 0x00, 0x00, 0x51, 0x02, 0x34, 0x0f, 0x69, 0xd7, 0x7a, 0xae,
+                            0x50, 0x02, 0x34, 0x0f, 0x68, 0xd7, 0x7a, 0xae,
+                        ]
+                        .into(),
+                    )),
                     FileAttribute::FileId(FileId(255066705)),
                     FileAttribute::Mode(Mode(0o0664)),
                     FileAttribute::NumLinks(1),
@@ -0,0 +1,121 @@
+// Copyright 2023 Remi Bernotavicius
+
+#![cfg(feature = "serde")]
+
+use nfs4::{
+    Ace, AceFlags, AceMask, AceType, Acl, Cookie, DirectoryEntry, FileAttribute, FileAttributeId,
+    FileAttributes, FileHandle, FileType, FsId, Identity, Mode, Time,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Listing {
+    #[serde(with = "nfs4::readable")]
+    entries: Vec<DirectoryEntry>,
+    #[serde(with = "nfs4::readable")]
+    handle: Option<FileHandle>,
+}
+
+fn attributes() -> FileAttributes {
+    [
+        FileAttribute::Type(FileType::Directory),
+        FileAttribute::Size(4096),
+        FileAttribute::Mode(Mode(0o755)),
+        FileAttribute::Owner("alice".into()),
+        FileAttribute::FsId(FsId { major: 1, minor: 2 }),
+        FileAttribute::FileHandle(FileHandle(vec![0xab, 0x01].into())),
+        FileAttribute::TimeModify(Time {
+            seconds: 1_680_352_200,
+            nseconds: 5,
+        }),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn directory_entries() {
+    let listing = Listing {
+        entries: vec![DirectoryEntry {
+            cookie: Cookie(3),
+            name: "src".into(),
+            attrs: attributes(),
+        }],
+        handle: None,
+    };
+    let value = serde_json::to_value(&listing).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "entries": [{
+                "cookie": 3,
+                "name": "src",
+                "attrs": {
+                    "type": "directory",
+                    "size": 4096,
+                    "fs_id": { "major": 1, "minor": 2 },
+                    "file_handle": "ab01",
+                    "mode": 493,
+                    "owner": "alice",
+                    "time_modify": { "seconds": 1_680_352_200, "nseconds": 5 },
+                },
+            }],
+            "handle": null,
+        })
+    );
+    assert_eq!(serde_json::from_value::<Listing>(value).unwrap(), listing);
+}
+
+#[test]
+fn attributes_given_in_xdr() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Attributes(#[serde(with = "nfs4::readable")] FileAttributes);
+
+    let acl = Acl {
+        aces: vec![Ace {
+            type_: AceType::AccessAllowed,
+            flags: AceFlags::empty(),
+            access_mask: AceMask::READ_DATA,
+            who: Identity("EVERYONE@".into()),
+        }],
+    };
+    let attrs = Attributes([FileAttribute::Acl(acl)].into_iter().collect());
+    let text = serde_json::to_string(&attrs).unwrap();
+    assert_eq!(
+        text,
+        r#"{"acl":"000000010000000000000000000000010000000945564552594f4e4540000000"}"#
+    );
+    assert_eq!(serde_json::from_str::<Attributes>(&text).unwrap(), attrs);
+
+    let error = serde_json::from_str::<Attributes>(r#"{"acl":"00"}"#).unwrap_err();
+    assert!(error.to_string().starts_with("invalid value for acl: `00`"));
+    let error = serde_json::from_str::<Attributes>(r#"{"colour":"red"}"#).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("unknown file attribute `colour`"));
+}
+
+#[test]
+fn supported_attributes() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Attributes(#[serde(with = "nfs4::readable")] FileAttributes);
+
+    let supported = [FileAttributeId::Type, FileAttributeId::TimeModify]
+        .into_iter()
+        .collect();
+    let attrs = Attributes(
+        [FileAttribute::SupportedAttrs(supported)]
+            .into_iter()
+            .collect(),
+    );
+    let value = serde_json::to_value(&attrs).unwrap();
+    assert_eq!(value, json!({ "supported_attrs": ["type", "time_modify"] }));
+    assert_eq!(serde_json::from_value::<Attributes>(value).unwrap(), attrs);
+
+    let error =
+        serde_json::from_str::<Attributes>(r#"{"supported_attrs":["colour"]}"#).unwrap_err();
+    assert!(error
+        .to_string()
+        .starts_with("unknown file attribute `colour`"));
+}
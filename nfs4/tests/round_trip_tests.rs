@@ -0,0 +1,1157 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Property tests of every type, checking that any value of it decodes to what was encoded and
+//! encodes the same way again. proptest shrinks a failing value down to a small one, and keeps the
+//! seeds of failures in `proptest-regressions` so that they are tried again first. That what is
+//! seen on the wire encodes to the same bytes again is checked against the captures in
+//! `captures`, by `captures_decode` in `capture_tests.rs`.
+
+use nfs4::*;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::strategy::{LazyJust, Union, ValueTree as _};
+use proptest::test_runner::TestRunner;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use sun_rpc::{AuthSysParameters, Gid, Uid};
+
+/// How many values of each type are checked.
+const CASES: u32 = 2_000;
+
+/// Short, so that values with lots of them in them don't get too big.
+const MAX_LEN: usize = 5;
+
+/// Types there is a strategy for any value of. It can't be `Arbitrary`, as neither the trait nor
+/// the types are ours.
+trait Arb: fmt::Debug + Sized + 'static {
+    fn arb() -> BoxedStrategy<Self>;
+}
+
+impl Arb for () {
+    fn arb() -> BoxedStrategy<Self> {
+        Just(()).boxed()
+    }
+}
+
+macro_rules! arb_primitives {
+    ($($name:ty)*) => {$(
+        impl Arb for $name {
+            fn arb() -> BoxedStrategy<Self> {
+                any::<$name>().boxed()
+            }
+        }
+    )*};
+}
+
+arb_primitives!(bool i32 i64 u8 u32 u64);
+
+/// Any characters, not just ASCII ones, `'\0'` included.
+impl Arb for String {
+    fn arb() -> BoxedStrategy<Self> {
+        vec(any::<char>(), 0..MAX_LEN)
+            .prop_map(String::from_iter)
+            .boxed()
+    }
+}
+
+impl<const N: usize> Arb for [u8; N] {
+    fn arb() -> BoxedStrategy<Self> {
+        proptest::array::uniform(any::<u8>()).boxed()
+    }
+}
+
+impl Arb for Bytes {
+    fn arb() -> BoxedStrategy<Self> {
+        Vec::<u8>::arb().prop_map(Bytes::from).boxed()
+    }
+}
+
+impl<T: Arb> Arb for Vec<T> {
+    fn arb() -> BoxedStrategy<Self> {
+        vec(T::arb(), 0..MAX_LEN).boxed()
+    }
+}
+
+impl<T: Arb> Arb for Option<T> {
+    fn arb() -> BoxedStrategy<Self> {
+        proptest::option::of(T::arb()).boxed()
+    }
+}
+
+impl<K: Arb + Ord> Arb for EnumSet<K> {
+    fn arb() -> BoxedStrategy<Self> {
+        Vec::<K>::arb()
+            .prop_map(|ids| ids.into_iter().collect())
+            .boxed()
+    }
+}
+
+impl<K: Ord + fmt::Debug + 'static, V: Arb + ToId<K>> Arb for EnumMap<K, V> {
+    fn arb() -> BoxedStrategy<Self> {
+        Vec::<V>::arb()
+            .prop_map(|values| values.into_iter().collect())
+            .boxed()
+    }
+}
+
+impl<T: Arb> Arb for StatusResult<T> {
+    fn arb() -> BoxedStrategy<Self> {
+        prop_oneof![
+            T::arb().prop_map(Self::Ok),
+            StatusError::arb().prop_map(Self::Err),
+        ]
+        .boxed()
+    }
+}
+
+impl<T: Arb> Arb for LockStatusResult<T> {
+    fn arb() -> BoxedStrategy<Self> {
+        // What the lock is held by is only sent along with the error saying it is
+        let error = (StatusError::arb(), LockDenied::arb()).prop_map(|(error, denied)| {
+            let denied = (error == StatusError::Denied).then_some(denied);
+            Self::Err(LockStatusError { error, denied })
+        });
+        prop_oneof![T::arb().prop_map(Self::Ok), error].boxed()
+    }
+}
+
+macro_rules! arb_bitflags {
+    ($($name:ident),* $(,)?) => {$(
+        impl Arb for $name {
+            fn arb() -> BoxedStrategy<Self> {
+                any::<u32>().prop_map(Self::from_bits_retain).boxed()
+            }
+        }
+    )*};
+}
+
+macro_rules! arb_newtypes {
+    ($($name:ident),* $(,)?) => {$(
+        impl Arb for $name {
+            fn arb() -> BoxedStrategy<Self> {
+                Arb::arb().prop_map(Self).boxed()
+            }
+        }
+    )*};
+}
+
+/// The strategies of the given fields nested in pairs, as tuples of strategies only go up to
+/// twelve.
+macro_rules! arb_fields {
+    () => {
+        Just(())
+    };
+    ($field:ident $(, $rest:ident)*) => {
+        (Arb::arb(), arb_fields!($($rest),*))
+    };
+}
+
+/// The pattern taking apart what `arb_fields!` gives.
+macro_rules! fields_pattern {
+    () => {
+        ()
+    };
+    ($field:ident $(, $rest:ident)*) => {
+        ($field, fields_pattern!($($rest),*))
+    };
+}
+
+macro_rules! arb_structs {
+    ($($name:ident { $($field:ident),* $(,)? })*) => {$(
+        impl Arb for $name {
+            fn arb() -> BoxedStrategy<Self> {
+                arb_fields!($($field),*)
+                    .prop_map(|fields_pattern!($($field),*)| Self { $($field),* })
+                    .boxed()
+            }
+        }
+    )*};
+}
+
+macro_rules! arb_variant {
+    ($name:ident::$variant:ident) => {
+        LazyJust::new(|| $name::$variant).boxed()
+    };
+    ($name:ident::$variant:ident(_)) => {
+        Arb::arb().prop_map($name::$variant).boxed()
+    };
+    ($name:ident::$variant:ident { $($field:ident),* }) => {
+        arb_fields!($($field),*)
+            .prop_map(|fields_pattern!($($field),*)| $name::$variant { $($field),* })
+            .boxed()
+    };
+}
+
+macro_rules! arb_enums {
+    ($($name:ident {
+        $($variant:ident $(($payload:tt))? $({ $($field:ident),* })?),* $(,)?
+    })*) => {$(
+        impl Arb for $name {
+            fn arb() -> BoxedStrategy<Self> {
+                Union::new([$(
+                    arb_variant!($name::$variant $(($payload))? $({ $($field),* })?)
+                ),*])
+                .boxed()
+            }
+        }
+    )*};
+}
+
+arb_structs! {
+    AuthSysParameters { stamp, machine_name, uid, gid, gids }
+}
+
+arb_newtypes!(Gid, Uid);
+
+arb_bitflags! {
+    Access, AceFlags, AceMask, AclFlags, AclSupport, FhExpireType, FsCharsetCap,
+    FsLocationsInfoFlags, ShareAccess, ShareDeny, ExchangeIdFlags, CreateSessionFlags, OpenResult,
+    FlexFilesFlags, SequenceStatusFlags
+}
+
+arb_newtypes! {
+    SequenceId, FileHandle, Identity, Component, PathName, Mode, Lease, Change, FileId, ClientId,
+    Cookie, Verifier, GssHandle, SessionId, SecOid, DeviceId, SlotId, ChangeId, Qop, ServerScope,
+    Util
+}
+
+arb_structs! {
+    CompoundArgs { tag, minor_version, arg_array }
+    CompoundRes { status, tag, res_array }
+    AccessArgs { access }
+    StateId { sequence_id, other }
+    CloseArgs { sequence_id, open_stateid }
+    CommitArgs { offset, count }
+    DeviceData { major, minor }
+    RetentionGet { duration, begin_time }
+    RetentionSet { enable, duration }
+    FsId { major, minor }
+    Ace { type_, flags, access_mask, who }
+    Acl { aces }
+    AclWithFlags { flags, aces }
+    ChangePolicy { major, minor }
+    LabelFormat { format, policy }
+    SecLabel { format, data }
+    Time { seconds, nseconds }
+    LockDenied { offset, length, lock_type, owner }
+    FsLocation { server, root_path }
+    FsLocations { fs_root, locations }
+    FsLocationsServer { currency, info, server }
+    FsLocationsItem { entries, root_path }
+    FsLocationsInfo { flags, valid_for, fs_root, items }
+    FsStatus { absent, type_, source, current, age, version }
+    LayoutHint { type_, body }
+    ThresholdItem { layout_type, hintset }
+    MdsThreshold { hints }
+    ModeMasked { value, mask }
+    ModeUmask { mode, umask }
+    CreateArgs { object_type, object_name, create_attrs }
+    DelegPurgeArgs { client_id }
+    DelegReturnArgs { state_id }
+    GetAttrArgs { attr_request }
+    LinkArgs { new_name }
+    OpenToLockOwner { open_sequence_id, open_state_id, lock_sequence_id, lock_owner }
+    ExistingLockOwner { lock_state_id, lock_sequence_id }
+    LockArgs { lock_type, reclaim, offset, length, locker }
+    StateOwner { client_id, opaque }
+    LockTArgs { lock_type, offset, length, owner }
+    LockUArgs { lock_type, sequence_id, lock_state_id, offset, length }
+    LookUpArgs { object_name }
+    NVerifyArgs { object_attributes }
+    OpenClaimDelegateCurrent { delegate_stateid, file }
+    OpenArgs { sequence_id, share_access, share_deny, owner, open_how, claim }
+    OpenAttrArgs { create_dir }
+    OpenConfirmArgs { open_state_id, sequence_id }
+    OpenDowngradeArgs { open_state_id, sequence_id, share_access, share_deny }
+    PutFhArgs { object }
+    ReadArgs { state_id, offset, count }
+    ReadDirArgs { cookie, cookie_verifier, directory_count, max_count, attr_request }
+    RemoveArgs { target }
+    RenameArgs { old_name, new_name }
+    RenewArgs { client_id }
+    SecInfoArgs { name }
+    SetAttrArgs { state_id, object_attributes }
+    CallbackClient { program, location }
+    SetClientIdArgs { client, callback, callback_ident }
+    SetClientIdConfirmArgs { client_id, confirm }
+    ReleaseLockOwnerArgs { lock_owner }
+    VerifyArgs { object_attributes }
+    WriteArgs { state_id, offset, stable, data }
+    GssCallbackHandles { service, handle_from_server, handle_from_client }
+    BackchannelCtlArgs { cp_program, security_parameters }
+    BindConnToSessionArgs { session_id, direction, use_connection_in_rdma_mode }
+    ClientOwner { verifier, owner_id }
+    StateProtectOps { must_enforce, must_allow }
+    SsvProtInfo { ops, hash_algorithm, encryption_algorithm, ssv_length, window, handles }
+    SsvStateProtectParams { ops, hash_algorithms, encryption_algorithms, window, num_gss_handles }
+    ImplId { domain, name, date }
+    ExchangeIdArgs { client_owner, flags, state_protect, client_impl_id }
+    ChannelAttrs {
+        header_pad_size,
+        max_request_size,
+        max_response_size,
+        max_response_size_cached,
+        max_operations,
+        max_requests,
+        rdma_ird,
+    }
+    CreateSessionArgs {
+        client_id,
+        sequence_id,
+        flags,
+        fore_channel_attrs,
+        back_channel_attrs,
+        program,
+        security_parameters,
+    }
+    DestroySessionArgs { session_id }
+    FreeStateidArgs { state_id }
+    GetDirDelegationArgs {
+        signal_delegation_available,
+        notification_types,
+        child_attr_delay,
+        dir_attr_delay,
+        child_attributes,
+        dir_attributes,
+    }
+    GetDeviceInfoArgs { device_id, layout_type, max_count, notify_types }
+    GetDeviceListArgs { layout_type, max_devices, cookie, cookie_verifier }
+    LayoutUpdate { type_, body }
+    LayoutCommitArgs {
+        offset,
+        length,
+        reclaim,
+        state_id,
+        last_write_offset,
+        time_modify,
+        layout_update,
+    }
+    LayoutGetArgs {
+        signal_layout_available,
+        layout_type,
+        io_mode,
+        offset,
+        length,
+        min_length,
+        state_id,
+        max_count,
+    }
+    LayoutReturnFile { offset, length, state_id, body }
+    LayoutReturnArgs { reclaim, layout_type, io_mode, layout_return }
+    DeviceError { device_id, status, op }
+    LayoutErrorArgs { offset, length, state_id, errors }
+    IoInfo { count, bytes }
+    LayoutStatsArgs { offset, length, state_id, read, write, device_id, layout_update }
+    CopyArgs {
+        src_state_id,
+        dst_state_id,
+        src_offset,
+        dst_offset,
+        count,
+        consecutive,
+        synchronous,
+        source_server,
+    }
+    CopyNotifyArgs { src_state_id, destination_server }
+    OffloadCancelArgs { state_id }
+    OffloadStatusArgs { state_id }
+    SecInfoNoNameArgs { style }
+    SequenceArgs { session_id, sequence_id, slot_id, highest_slot_id, cache_this }
+    SetSsvArgs { ssv, digest }
+    TestStateIdArgs { state_ids }
+    WantDelegationArgs { want, claim }
+    DestroyClientIdArgs { client_id }
+    ReclaimCompleteArgs { one_fs }
+    DirectoryEntry { cookie, name, attrs }
+    DirectoryList { entries, eof }
+    AccessRes { supported, access }
+    CloseRes { open_state_id }
+    CommitRes { write_verifier }
+    ChangeInfo { atomic, before, after }
+    CreateRes { change_info, attribute_set }
+    GetAttrRes { object_attributes }
+    GetFhRes { object }
+    LinkRes { change_info }
+    LockRes { lock_state_id }
+    LockURes { lock_state_id }
+    OpenReadDelegation { state_id, recall, permissions }
+    ModifiedLimit { num_blocks, bytes_per_block }
+    OpenWriteDelegation { state_id, recall, space_limit, permissions }
+    OpenRes { state_id, change_info, result_flags, attribute_set, delegation }
+    OpenConfirmRes { open_state_id }
+    OpenDowngradeRes { open_state_id }
+    ReadRes { eof, data }
+    ReadDirRes { cookie_verifier, reply }
+    ReadLinkRes { link }
+    RemoveRes { change_info }
+    RenameRes { source_change_info, target_change_info }
+    RpcSecGssInfo { oid, qop, service }
+    SecInfoRes { body }
+    SetAttrRes { attr_set }
+    SetClientIdRes { client_id, confirm }
+    SetAttrStatusResult { status, res }
+    WriteRes { count, committed, write_veritifer }
+    BindConnToSessionRes { session_id, direction, use_connection_in_rdma_mode }
+    ServerOwner { minor_id, major_id }
+    ExchangeIdRes {
+        client_id,
+        sequence_id,
+        flags,
+        state_protect,
+        server_owner,
+        server_scope,
+        server_impl_id,
+    }
+    CreateSessionRes { session_id, sequence_id, flags, fore_channel_attrs, back_channel_attrs }
+    GetDirDelegationResOk {
+        cookie_verifier,
+        state_id,
+        notification,
+        child_attributes,
+        dir_attributes,
+    }
+    DeviceAddr { layout_type, body }
+    NetAddr { netid, addr }
+    FilesDeviceAddr { stripe_indices, multipath_ds_list }
+    GetDeviceInfoRes { device_addr, notification }
+    GetDeviceListRes { cookie, cookie_verifier, device_id_list, eof }
+    LayoutCommitRes { new_size }
+    LayoutContent { type_, body }
+    Layout { offset, length, io_mode, content }
+    FilesLayout { device_id, util, first_stripe_index, pattern_offset, fh_list }
+    FlexFilesDataServer { device_id, efficiency, state_id, fh_versions, user, group }
+    FlexFilesMirror { data_servers }
+    FlexFilesLayout { stripe_unit, mirrors, flags, stats_collect_hint }
+    FlexFilesDeviceVersion { version, minor_version, read_size, write_size, tightly_coupled }
+    FlexFilesDeviceAddr { netaddrs, versions }
+    FlexFilesIoLatency {
+        ops_requested,
+        bytes_requested,
+        ops_completed,
+        bytes_completed,
+        bytes_not_delivered,
+        total_busy_time,
+        aggregate_completion_time,
+    }
+    FlexFilesLayoutUpdate { addr, fh, read, write, duration, local }
+    FlexFilesIoError { offset, length, state_id, errors }
+    FlexFilesIoStats { offset, length, state_id, read, write, device_id, layout_update }
+    FlexFilesLayoutReturn { io_errors, io_stats }
+    WriteResponse { callback_id, count, committed, write_verifier }
+    CopyRequirements { consecutive, synchronous }
+    CopyRes { response, requirements }
+    CopyNotifyRes { lease_time, state_id, source_server }
+    OffloadStatusRes { count, complete }
+    LayoutGetRes { return_on_close, state_id, layout }
+    LayoutReturnRes { state_id }
+    SequenceRes {
+        session_id,
+        sequence_id,
+        slot_id,
+        highest_slot_id,
+        target_highest_slot_id,
+        status_flags,
+    }
+    SetSsvRes { digest }
+    TestStateIdRes { status_codes }
+    WantDelegationRes { delegation }
+    CbCompoundArgs { tag, minor_version, callback_ident, arg_array }
+    CbCompoundRes { status, tag, res_array }
+    ReferringCall { sequence_id, slot_id }
+    ReferringCallList { session_id, referring_calls }
+    CbSequenceArgs {
+        session_id,
+        sequence_id,
+        slot_id,
+        highest_slot_id,
+        cache_this,
+        referring_call_lists,
+    }
+    CbRecallArgs { state_id, truncate, fh }
+    Notify { mask, values }
+    NotifyEntry { file, attrs }
+    PrevEntry { prev_entry, prev_entry_cookie }
+    NotifyRemove { old_entry, old_entry_cookie }
+    NotifyAdd { old_entry, new_entry, new_entry_cookie, prev_entry, last_entry }
+    NotifyAttr { changed_entry }
+    NotifyRename { old_entry, new_entry }
+    NotifyVerifier { old_cookie_verifier, new_cookie_verifier }
+    CbNotifyArgs { state_id, fh, changes }
+    CbRecallAnyArgs { objects_to_keep, type_mask }
+    CbRecallSlotArgs { target_highest_slot_id }
+    CbOffloadArgs { fh, state_id, info }
+    CbSequenceRes { session_id, sequence_id, slot_id, highest_slot_id, target_highest_slot_id }
+}
+
+arb_enums! {
+    SetTime { SetToClientTime(_), SetToServerTime }
+    FileType { Regular, Directory, Block, Character, Link, Socket, Fifo, AttrDir, NamedAttr }
+    CreateType { Directory, Block, Character(_), Link(_), Socket, Fifo }
+    FileAttributeId {
+        SupportedAttrs,
+        Type,
+        FhExpireType,
+        Change,
+        Size,
+        LinkSupport,
+        SymlinkSupport,
+        NamedAttr,
+        FsId,
+        UniqueHandles,
+        LeaseTime,
+        ReadDirAttrError,
+        Acl,
+        AclSupport,
+        Archive,
+        CanSetTime,
+        CaseInsensitive,
+        CasePreserving,
+        ChownRestricted,
+        FileHandle,
+        FileId,
+        FilesAvail,
+        FilesFree,
+        FilesTotal,
+        FsLocations,
+        Hidden,
+        Homogeneous,
+        MaxFileSize,
+        MaxLink,
+        MaxName,
+        MaxRead,
+        MaxWrite,
+        MimeType,
+        Mode,
+        NoTrunc,
+        NumLinks,
+        Owner,
+        OwnerGroup,
+        QuotaAvailHard,
+        QuotaAvailSoft,
+        QuotaUsed,
+        RawDev,
+        SpaceAvail,
+        SpaceFree,
+        SpaceTotal,
+        SpaceUsed,
+        System,
+        TimeAccess,
+        TimeAccessSet,
+        TimeBackup,
+        TimeCreate,
+        TimeDelta,
+        TimeMetadata,
+        TimeModify,
+        TimeModifySet,
+        MountedOnFileid,
+        DirNotifDelay,
+        DirentNotifDelay,
+        Dacl,
+        Sacl,
+        ChangePolicy,
+        FsStatus,
+        FsLayoutType,
+        LayoutHint,
+        LayoutType,
+        LayoutBlksize,
+        LayoutAlignment,
+        FsLocationsInfo,
+        MdsThreshold,
+        RetentionGet,
+        RetentionSet,
+        RetentevtGet,
+        RetentevtSet,
+        RetentionHold,
+        ModeSetMasked,
+        SupportedAttrsExclusiveCreate,
+        FsCharsetCap,
+        CloneBlksize,
+        SpaceFreed,
+        ChangeAttrType,
+        SecLabel,
+        ModeUmask,
+        XattrSupport,
+    }
+    AceType { AccessAllowed, AccessDenied, SystemAudit, SystemAlarm }
+    ChangeAttrType {
+        MonotonicIncrement,
+        VersionCounter,
+        VersionCounterNoPnfs,
+        TimeMetadata,
+        Undefined,
+    }
+    LayoutType { NfsV41Files, Osd2Objects, BlockVolume, FlexFiles }
+    StatusError {
+        Perm,
+        NoEnt,
+        Io,
+        NxIo,
+        Access,
+        Exist,
+        XDev,
+        NotDir,
+        Isdir,
+        Inval,
+        FBig,
+        NoSpc,
+        RoFs,
+        MLink,
+        NameTooLong,
+        NotEmpty,
+        DQuot,
+        Stale,
+        BadHandle,
+        BadCookie,
+        NotSupported,
+        TooSmall,
+        ServerFault,
+        BadType,
+        Delay,
+        Same,
+        Denied,
+        Expired,
+        Locked,
+        Grace,
+        FhExpired,
+        ShareDenied,
+        WrongSec,
+        ClidInUse,
+        Moved,
+        NoFileHandle,
+        MinorVersMismatch,
+        StaleClientId,
+        StaleStateId,
+        OldStateId,
+        BadStateId,
+        BadSeqId,
+        NotSame,
+        LockRange,
+        Symlink,
+        RestoreFh,
+        LeaseMoved,
+        AttrNotSupported,
+        NoGrace,
+        ReclaimBad,
+        ReclaimConflict,
+        BadXdr,
+        LocksHeld,
+        OpenMode,
+        BadOwner,
+        BadChar,
+        BadName,
+        BadRange,
+        LockNotSupported,
+        OpIllegal,
+        Deadlock,
+        FileOpen,
+        AdminRevoked,
+        CbPathDown,
+        BadIoMode,
+        BadLayout,
+        BadSessionDigest,
+        BadSession,
+        BadSlot,
+        CompleteAlready,
+        ConnNotBoundToSession,
+        DelegAlreadyWanted,
+        BackChanBusy,
+        LayoutTryLater,
+        LayoutUnavailable,
+        NoMatchingLayout,
+        RecallConflict,
+        UnknownLayoutType,
+        SeqMisordered,
+        SequencePos,
+        ReqTooBig,
+        RepTooBig,
+        RepTooBigToCache,
+        RetryUncachedRep,
+        UnsafeCompound,
+        TooManyOps,
+        OpNotInSession,
+        HashAlgUnsupported,
+        ClientIdBusy,
+        PnfsIoHole,
+        SeqFalseRetry,
+        BadHighSlot,
+        DeadSession,
+        EncrAlgUnsupported,
+        PnfsNoLayout,
+        NotOnlyOp,
+        WrongCred,
+        WrongType,
+        DirDelegUnavail,
+        RejectDeleg,
+        ReturnConflict,
+        DelegRevoked,
+        PartnerNotSupp,
+        PartnerNoAuth,
+        UnionNotSupp,
+        OffloadDenied,
+        WrongLfs,
+        BadLabel,
+        OffloadNoReqs,
+    }
+    FsStatusType { Fixed, Updated, Versioned, Writable, Referral }
+    ThresholdAttributeId { ReadSize, WriteSize, ReadIoSize, WriteIoSize }
+    ThresholdAttribute { ReadSize(_), WriteSize(_), ReadIoSize(_), WriteIoSize(_) }
+    FileAttribute {
+        SupportedAttrs(_),
+        Type(_),
+        FhExpireType(_),
+        Change(_),
+        Size(_),
+        LinkSupport(_),
+        SymlinkSupport(_),
+        NamedAttr(_),
+        FsId(_),
+        UniqueHandles(_),
+        LeaseTime(_),
+        ReadDirAttrError(_),
+        Acl(_),
+        AclSupport(_),
+        Archive(_),
+        CanSetTime(_),
+        CaseInsensitive(_),
+        CasePreserving(_),
+        ChownRestricted(_),
+        FileHandle(_),
+        FileId(_),
+        FilesAvail(_),
+        FilesFree(_),
+        FilesTotal(_),
+        FsLocations(_),
+        Hidden(_),
+        Homogeneous(_),
+        MaxFileSize(_),
+        MaxLink(_),
+        MaxName(_),
+        MaxRead(_),
+        MaxWrite(_),
+        MimeType(_),
+        Mode(_),
+        NoTrunc(_),
+        NumLinks(_),
+        Owner(_),
+        OwnerGroup(_),
+        QuotaAvailHard(_),
+        QuotaAvailSoft(_),
+        QuotaUsed(_),
+        RawDev(_),
+        SpaceAvail(_),
+        SpaceFree(_),
+        SpaceTotal(_),
+        SpaceUsed(_),
+        System(_),
+        TimeAccess(_),
+        TimeAccessSet(_),
+        TimeBackup(_),
+        TimeCreate(_),
+        TimeDelta(_),
+        TimeMetadata(_),
+        TimeModify(_),
+        TimeModifySet(_),
+        MountedOnFileid(_),
+        DirNotifDelay(_),
+        DirentNotifDelay(_),
+        Dacl(_),
+        Sacl(_),
+        ChangePolicy(_),
+        FsStatus(_),
+        FsLayoutType(_),
+        LayoutHint(_),
+        LayoutType(_),
+        LayoutBlksize(_),
+        LayoutAlignment(_),
+        FsLocationsInfo(_),
+        MdsThreshold(_),
+        RetentionGet(_),
+        RetentionSet(_),
+        RetentevtGet(_),
+        RetentevtSet(_),
+        RetentionHold(_),
+        ModeSetMasked(_),
+        SupportedAttrsExclusiveCreate(_),
+        FsCharsetCap(_),
+        CloneBlksize(_),
+        SpaceFreed(_),
+        ChangeAttrType(_),
+        SecLabel(_),
+        ModeUmask(_),
+        XattrSupport(_),
+    }
+    LockType { Read, Write, BlockingRead, BlockingWrite }
+    Locker { NewLockOwner(_), ExistingLockOwner(_) }
+    OpenFlag { OpenNoCreate, OpenCreate(_) }
+    OpenDelegationType { None, Read, Write, NoneExt }
+    CreateHow {
+        Unchecked { create_attrs },
+        Guarded { create_attrs },
+        Exclusive { create_verifier },
+        ExclusiveBoth { create_verifier, create_attrs },
+    }
+    OpenClaim {
+        Null { file },
+        Previous { delegate_type },
+        DelegateCurrent { delegate_current_info },
+        DelegatePrevious { file_delegate_previous },
+        Fh,
+        DelegateCurrentFh { oc_delegate_state_id },
+        PreviousFh,
+    }
+    StableHow { Unstable, DataSync, FileSync }
+    RpcGssService { None, Integrity, Privacy }
+    CallbackSecurityParameters { None, Sys(_), RpcSecGss(_) }
+    ChannelDirectionFromServer { Fore, Back, Both }
+    StateProtect { None, MachCred(_), Ssv(_) }
+    NotifyType {
+        ChangeChildAttrs,
+        ChangeDirAttrs,
+        RemoveEntry,
+        AddEntry,
+        RenameEntry,
+        ChangeCookieVerifier,
+    }
+    LayoutIoMode { Read, ReadWrite, Any }
+    LayoutReturnType { File, FsId, All }
+    LayoutReturn { File(_), FsId, All }
+    NetLoc { Name(_), Url(_), NetAddr(_) }
+    SecInfoStyle { CurrentFh, Parent }
+    DelegationClaim {
+        Null,
+        Previous { delegate_type },
+        DelegationCurrent,
+        DelegationPrevious,
+        Fh,
+        DelegationCurrentFh,
+        DelefationPreviousFh,
+    }
+    OperationId {
+        Access,
+        Close,
+        Commit,
+        Create,
+        DelegPurge,
+        DelegReturn,
+        GetAttr,
+        GetFh,
+        Link,
+        Lock,
+        LockT,
+        LockU,
+        LookUp,
+        LookUpP,
+        NVerify,
+        Open,
+        OpenAttr,
+        OpenConfirm,
+        OpenDowngrade,
+        PutFh,
+        PutPubFh,
+        PutRootFh,
+        Read,
+        ReadDir,
+        ReadLink,
+        Remove,
+        Rename,
+        Renew,
+        RestoreFh,
+        SaveFh,
+        SecInfo,
+        SetAttr,
+        SetClientId,
+        SetClientIdConfirm,
+        Verify,
+        Write,
+        ReleaseLockOwner,
+        BackchannelCtl,
+        BindConnToSession,
+        ExchangeId,
+        CreateSession,
+        DestroySession,
+        FreeStateid,
+        GetDirDelegation,
+        GetDeviceInfo,
+        GetDeviceList,
+        LayoutCommit,
+        LayoutGet,
+        LayoutReturn,
+        SecInfoNoName,
+        Sequence,
+        SetSsv,
+        TestStateId,
+        WantDelegation,
+        DestroyClientId,
+        ReclaimComplete,
+        Copy,
+        CopyNotify,
+        LayoutError,
+        LayoutStats,
+        OffloadCancel,
+        OffloadStatus,
+    }
+    ArgOp {
+        Access(_),
+        Close(_),
+        Commit(_),
+        Create(_),
+        DelegPurge(_),
+        DelegReturn(_),
+        GetAttr(_),
+        GetFh,
+        Link(_),
+        Lock(_),
+        LockT(_),
+        LockU(_),
+        LookUp(_),
+        LookUpP,
+        NVerify(_),
+        Open(_),
+        OpenAttr(_),
+        OpenConfirm(_),
+        OpenDowngrade(_),
+        PutFh(_),
+        PutPubFh,
+        PutRootFh,
+        Read(_),
+        ReadDir(_),
+        ReadLink,
+        Remove(_),
+        Rename(_),
+        Renew(_),
+        RestoreFh,
+        SaveFh,
+        SecInfo(_),
+        SetAttr(_),
+        SetClientId(_),
+        SetClientIdConfirm(_),
+        Verify(_),
+        Write(_),
+        ReleaseLockOwner(_),
+        BackchannelCtl(_),
+        BindConnToSession(_),
+        ExchangeId(_),
+        CreateSession(_),
+        DestroySession(_),
+        FreeStateid(_),
+        GetDirDelegation(_),
+        GetDeviceInfo(_),
+        GetDeviceList(_),
+        LayoutCommit(_),
+        LayoutGet(_),
+        LayoutReturn(_),
+        SecInfoNoName(_),
+        Sequence(_),
+        SetSsv(_),
+        TestStateId(_),
+        WantDelegation(_),
+        DestroyClientId(_),
+        ReclaimComplete(_),
+        Copy(_),
+        CopyNotify(_),
+        LayoutError(_),
+        LayoutStats(_),
+        OffloadCancel(_),
+        OffloadStatus(_),
+    }
+    SpaceLimit { Size { file_size }, Blocks { modified_blocks } }
+    OpenNoneDelegation {
+        NotWanted,
+        Contention { server_will_push_delegation },
+        Resource { server_will_signal_available },
+        NotSupportedFileType,
+        WriteDelegationNotSupportedFileType,
+        NotSupportedUpgrade,
+        NotSupportedDowngrade,
+        Cancelled,
+        IsDir,
+    }
+    OpenDelegation { None, Read { read }, Write { write }, NoneExt { why_none } }
+    SecurityInfo { None, Sys, RpcSecGss { flavor_info } }
+    GetDirDelegationRes { Ok(_), Unavailable { will_signal_delegation_available } }
+    NotifyDeviceIdType { Change, Delete }
+    ResOp {
+        Access(_),
+        Close(_),
+        Commit(_),
+        Create(_),
+        DelegPurge(_),
+        DelegReturn(_),
+        GetAttr(_),
+        GetFh(_),
+        Link(_),
+        Lock(_),
+        LockT(_),
+        LockU(_),
+        LookUp(_),
+        LookUpP(_),
+        NVerify(_),
+        Open(_),
+        OpenAttr(_),
+        OpenConfirm(_),
+        OpenDowngrade(_),
+        PutFh(_),
+        PutPubFh(_),
+        PutRootFh(_),
+        Read(_),
+        ReadDir(_),
+        ReadLink(_),
+        Remove(_),
+        Rename(_),
+        Renew(_),
+        RestoreFh(_),
+        SaveFh(_),
+        SecInfo(_),
+        SetAttr(_),
+        SetClientId(_),
+        SetClientIdConfirm(_),
+        Verify(_),
+        Write(_),
+        ReleaseLockOwner(_),
+        BackchannelCtl(_),
+        BindConnToSession(_),
+        ExchangeId(_),
+        CreateSession(_),
+        DestroySession(_),
+        FreeStateid(_),
+        GetDirDelegation(_),
+        GetDeviceInfo(_),
+        GetDeviceList(_),
+        LayoutCommit(_),
+        LayoutGet(_),
+        LayoutReturn(_),
+        SecInfoNoName(_),
+        Sequence(_),
+        SetSsv(_),
+        TestStateId(_),
+        WantDelegation(_),
+        DestroyClientId(_),
+        ReclaimComplete(_),
+        Copy(_),
+        CopyNotify(_),
+        LayoutError(_),
+        LayoutStats(_),
+        OffloadCancel(_),
+        OffloadStatus(_),
+    }
+    OffloadInfo { Ok(_), Err { status, bytes_copied } }
+    CbOperationId {
+        GetAttr,
+        Recall,
+        LayoutRecall,
+        Notify,
+        PushDeleg,
+        RecallAny,
+        RecallableObjAvail,
+        RecallSlot,
+        Sequence,
+        WantsCancelled,
+        NotifyLock,
+        NotifyDeviceId,
+        Offload,
+        Illegal,
+    }
+    CbArgOp { Recall(_), Notify(_), RecallAny(_), RecallSlot(_), Sequence(_), Offload(_) }
+    CbResOp {
+        Recall(_),
+        Notify(_),
+        RecallAny(_),
+        RecallSlot(_),
+        Sequence(_),
+        Offload(_),
+        Illegal(_),
+    }
+}
+
+/// Checks the value decodes to itself, and that decoding and encoding it again gives the same
+/// bytes.
+fn round_trip<T>(value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+{
+    let bytes = xdr_extras::to_bytes(value).unwrap();
+    assert_eq!(bytes.len() % 4, 0, "{value:?}");
+    let decoded: T = xdr_extras::from_bytes(&bytes)
+        .unwrap_or_else(|error| panic!("{value:?} failed to decode: {error:?}"));
+    assert_eq!(&decoded, value);
+    assert_eq!(xdr_extras::to_bytes(&decoded).unwrap(), bytes, "{value:?}");
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn attributes(
+        attr in FileAttribute::arb(),
+        attrs in FileAttributes::arb(),
+        ids in EnumSet::<FileAttributeId>::arb(),
+    ) {
+        round_trip(&attr);
+        round_trip(&attrs);
+        round_trip(&ids);
+    }
+
+    #[test]
+    fn operations(arg in ArgOp::arb(), res in ResOp::arb()) {
+        round_trip(&arg);
+        round_trip(&res);
+    }
+
+    #[test]
+    fn callbacks(arg in CbArgOp::arb(), res in CbResOp::arb()) {
+        round_trip(&arg);
+        round_trip(&res);
+    }
+
+    #[test]
+    fn compounds(args in CompoundArgs::arb(), res in CompoundRes::arb()) {
+        round_trip(&args);
+        round_trip(&res);
+    }
+}
+
+/// The given number of values of the type, the same ones every time.
+fn sample<T: Arb>(count: u32) -> Vec<T> {
+    let mut runner = TestRunner::deterministic();
+    let strategy = T::arb();
+    (0..count)
+        .map(|_| strategy.new_tree(&mut runner).unwrap().current())
+        .collect()
+}
+
+/// The strategies give every kind of attribute and operation.
+#[test]
+fn coverage() {
+    let seen: BTreeSet<FileAttributeId> = sample::<FileAttribute>(CASES)
+        .iter()
+        .map(|a| a.to_id())
+        .collect();
+    let all: BTreeSet<FileAttributeId> = (0..=FileAttributeId::XattrSupport as u32)
+        .map(|id| FileAttributeId::try_from(id).unwrap())
+        .collect();
+    assert_eq!(seen, all);
+
+    let seen: BTreeSet<OperationId> = sample::<ArgOp>(CASES).iter().map(|a| a.to_id()).collect();
+    let all: BTreeSet<OperationId> = (0..=OperationId::OffloadStatus as u32)
+        .filter_map(|id| OperationId::try_from(id).ok())
+        .collect();
+    assert_eq!(seen, all);
+}
+
+#[test]
+fn shared_data() {
+    let res = ReadRes {
+        eof: true,
+        data: vec![0xab; 1000].into(),
+    };
+    let bytes = Bytes::from(xdr_extras::to_bytes(&res).unwrap());
+
+    let shared: ReadRes = xdr_extras::from_shared_bytes(&bytes, Default::default()).unwrap();
+    assert_eq!(shared, res);
+    assert!(bytes.as_ptr_range().contains(&shared.data.as_ptr()));
+
+    let copied: ReadRes = xdr_extras::from_bytes(&bytes).unwrap();
+    assert_eq!(copied, res);
+    assert!(!bytes.as_ptr_range().contains(&copied.data.as_ptr()));
+}
@@ -0,0 +1,41 @@
+// Copyright 2023 Remi Bernotavicius
+
+#![cfg(feature = "std")]
+
+use nfs4::Time;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn time(seconds: i64, nseconds: u32) -> Time {
+    Time { seconds, nseconds }
+}
+
+#[test]
+fn duration() {
+    let duration = Duration::new(1_680_352_200, 5);
+    assert_eq!(Time::from(duration), time(1_680_352_200, 5));
+    assert_eq!(time(1_680_352_200, 5).to_duration(), Some(duration));
+    assert_eq!(time(-1, 0).to_duration(), None);
+    assert_eq!(Time::from(Duration::from_secs(u64::MAX)), time(i64::MAX, 0));
+}
+
+#[test]
+fn system_time() {
+    let after = UNIX_EPOCH + Duration::new(1_680_352_200, 5);
+    assert_eq!(Time::from(after), time(1_680_352_200, 5));
+    assert_eq!(time(1_680_352_200, 5).to_system_time(), Some(after));
+
+    let before = UNIX_EPOCH - Duration::new(1, 250_000_000);
+    assert_eq!(Time::from(before), time(-2, 750_000_000));
+    assert_eq!(time(-2, 750_000_000).to_system_time(), Some(before));
+
+    let before = UNIX_EPOCH - Duration::from_secs(3);
+    assert_eq!(Time::from(before), time(-3, 0));
+    assert_eq!(time(-3, 0).to_system_time(), Some(before));
+}
+
+#[test]
+fn now() {
+    let earlier = SystemTime::now();
+    let now = Time::now().to_system_time().unwrap();
+    assert!(now >= earlier);
+}
@@ -0,0 +1,271 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Remembering attributes, look-ups and directory listings for a while, so that walking the same
+//! files over and over, like `sync` and a FUSE mount do, doesn't cost a round trip every time.
+//! What is older than the TTL is fetched again, except for listings, which are kept as long as
+//! the change attribute of the directory says it is the same. Anything the client changes itself
+//! empties the cache, what other clients change is only seen once it expires.
+
+use super::RemotePath;
+use nfs4::{
+    ArgOp, Bytes, Change, DirectoryEntry, EnumSet, FileAttributeId, FileHandle, GetAttrRes,
+};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How many of each kind of thing are kept. Expired ones are dropped first when there are too
+/// many, and everything if that isn't enough.
+const MAX_ENTRIES: usize = 10_000;
+
+struct Cached<T> {
+    value: T,
+    fetched: Instant,
+}
+
+impl<T> Cached<T> {
+    fn new(value: T) -> Self {
+        Self {
+            value,
+            fetched: Instant::now(),
+        }
+    }
+
+    fn fresh(&self, ttl: Duration) -> bool {
+        self.fetched.elapsed() < ttl
+    }
+}
+
+struct Listing {
+    attr_request: EnumSet<FileAttributeId>,
+    change: Change,
+    entries: Vec<DirectoryEntry>,
+}
+
+/// What the cache has of a directory's listing.
+pub(crate) enum CachedListing {
+    /// Young enough to use as it is
+    Fresh(Vec<DirectoryEntry>),
+    /// Usable if the directory still has this change attribute
+    Stale(Change),
+    Missing,
+}
+
+fn insert<K: Ord, T>(map: &mut BTreeMap<K, Cached<T>>, ttl: Duration, key: K, value: T) {
+    if map.len() >= MAX_ENTRIES {
+        map.retain(|_, cached| cached.fresh(ttl));
+    }
+    if map.len() >= MAX_ENTRIES {
+        map.clear();
+    }
+    map.insert(key, Cached::new(value));
+}
+
+pub(crate) struct AttrCache {
+    ttl: Duration,
+    attrs: BTreeMap<Bytes, Cached<GetAttrRes>>,
+    // By the handle of the directory the path starts from, which is empty for the root
+    look_ups: BTreeMap<(Bytes, String), Cached<FileHandle>>,
+    listings: BTreeMap<Bytes, Cached<Listing>>,
+}
+
+impl AttrCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            attrs: BTreeMap::new(),
+            look_ups: BTreeMap::new(),
+            listings: BTreeMap::new(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn attrs(&self, handle: &FileHandle) -> Option<GetAttrRes> {
+        let cached = self.attrs.get(&handle.0)?;
+        cached.fresh(self.ttl).then(|| cached.value.clone())
+    }
+
+    /// Also drops the listing of the object if it is a directory that changed since.
+    pub fn insert_attrs(&mut self, handle: &FileHandle, res: GetAttrRes) {
+        let change = res
+            .object_attributes
+            .get_as::<Change>(FileAttributeId::Change);
+        if let Some(listing) = self.listings.get(&handle.0) {
+            if change.is_some_and(|c| *c != listing.value.change) {
+                self.listings.remove(&handle.0);
+            }
+        }
+        insert(&mut self.attrs, self.ttl, handle.0.clone(), res);
+    }
+
+    pub fn look_up(&self, dir: Option<&FileHandle>, path: &RemotePath) -> Option<FileHandle> {
+        let key = (
+            dir.map(|d| d.0.clone()).unwrap_or_default(),
+            path.as_str().to_owned(),
+        );
+        let cached = self.look_ups.get(&key)?;
+        cached.fresh(self.ttl).then(|| cached.value.clone())
+    }
+
+    pub fn insert_look_up(
+        &mut self,
+        dir: Option<&FileHandle>,
+        path: &RemotePath,
+        object: FileHandle,
+    ) {
+        let key = (
+            dir.map(|d| d.0.clone()).unwrap_or_default(),
+            path.as_str().to_owned(),
+        );
+        insert(&mut self.look_ups, self.ttl, key, object);
+    }
+
+    /// Only listings with at least the given attributes of each entry are any use.
+    pub fn listing(
+        &self,
+        dir: &FileHandle,
+        attr_request: &EnumSet<FileAttributeId>,
+    ) -> CachedListing {
+        let Some(cached) = self.listings.get(&dir.0) else {
+            return CachedListing::Missing;
+        };
+        let listing = &cached.value;
+        if !attr_request
+            .iter()
+            .all(|a| listing.attr_request.contains(a))
+        {
+            CachedListing::Missing
+        } else if cached.fresh(self.ttl) {
+            CachedListing::Fresh(listing.entries.clone())
+        } else {
+            CachedListing::Stale(listing.change)
+        }
+    }
+
+    /// The directory was found not to have changed, so its listing is good for another TTL.
+    pub fn renew_listing(&mut self, dir: &FileHandle) -> Vec<DirectoryEntry> {
+        let cached = self.listings.get_mut(&dir.0).unwrap();
+        cached.fetched = Instant::now();
+        cached.value.entries.clone()
+    }
+
+    pub fn insert_listing(
+        &mut self,
+        dir: &FileHandle,
+        attr_request: EnumSet<FileAttributeId>,
+        change: Change,
+        entries: Vec<DirectoryEntry>,
+    ) {
+        let listing = Listing {
+            attr_request,
+            change,
+            entries,
+        };
+        insert(&mut self.listings, self.ttl, dir.0.clone(), listing);
+    }
+
+    pub fn clear(&mut self) {
+        self.attrs.clear();
+        self.look_ups.clear();
+        self.listings.clear();
+    }
+}
+
+/// Whether the operation can change the attributes of something, or what is in a directory.
+pub(crate) fn modifies(op: &ArgOp) -> bool {
+    matches!(
+        op,
+        ArgOp::Copy(_)
+            | ArgOp::Create(_)
+            | ArgOp::LayoutCommit(_)
+            | ArgOp::Link(_)
+            | ArgOp::Open(_)
+            | ArgOp::OpenAttr(_)
+            | ArgOp::Remove(_)
+            | ArgOp::Rename(_)
+            | ArgOp::SetAttr(_)
+            | ArgOp::Write(_)
+    )
+}
+
+#[test]
+fn attributes_remembered_until_changed() {
+    use super::Client;
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = super::in_memory_client(files);
+    client.set_attr_cache(Some(Duration::from_secs(600)));
+
+    let handle = client.look_up("/a_file").unwrap();
+    let size = |client: &mut Client<_>| -> u64 {
+        let res = client.get_attr(handle.clone()).unwrap();
+        *res.object_attributes.get_as(FileAttributeId::Size).unwrap()
+    };
+    assert_eq!(size(&mut client), 5);
+    let compounds = client.stats().compounds;
+
+    // What others change isn't seen while what we have is fresh
+    server
+        .update_file_system(|files| files.write_file("a_file", "hello there"))
+        .unwrap();
+    assert_eq!(client.look_up("/a_file").unwrap(), handle);
+    assert_eq!(size(&mut client), 5);
+    assert_eq!(client.stats().compounds, compounds);
+
+    // But what we change ourselves is
+    client.write(handle.clone(), 11, b"!".to_vec()).unwrap();
+    assert_eq!(size(&mut client), 12);
+
+    client.set_attr_cache(Some(Duration::ZERO));
+    server
+        .update_file_system(|files| files.write_file("a_file", "hello there, again"))
+        .unwrap();
+    assert_eq!(size(&mut client), 18);
+}
+
+#[test]
+fn listings_revalidated_by_change_attribute() {
+    use super::{Client, Result};
+    use nfs4::OperationId;
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.make_dir("dir").unwrap();
+    files.write_file("dir/a_file", "hello").unwrap();
+    let (server, mut client) = super::in_memory_client(files);
+    // Everything is stale straight away, so listings are only kept for being unchanged
+    client.set_attr_cache(Some(Duration::ZERO));
+
+    let dir = client.look_up("/dir").unwrap();
+    let list = |client: &mut Client<_>| -> Vec<String> {
+        client
+            .read_dir(dir.clone(), Default::default())
+            .map(|entry| Ok(entry?.name))
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+    let read_dirs = |client: &Client<_>| client.stats().ops[&OperationId::ReadDir];
+
+    assert_eq!(list(&mut client), ["a_file"]);
+    assert_eq!(read_dirs(&client), 1);
+    assert_eq!(list(&mut client), ["a_file"]);
+    assert_eq!(read_dirs(&client), 1);
+
+    server
+        .update_file_system(|files| files.write_file("dir/b_file", "hi"))
+        .unwrap();
+    assert_eq!(list(&mut client), ["a_file", "b_file"]);
+    assert_eq!(read_dirs(&client), 2);
+
+    // Stopping part way through doesn't leave half a listing behind
+    server
+        .update_file_system(|files| files.write_file("dir/c_file", "hey"))
+        .unwrap();
+    client.read_dir(dir.clone(), Default::default()).next();
+    assert_eq!(list(&mut client), ["a_file", "b_file", "c_file"]);
+    assert_eq!(read_dirs(&client), 4);
+}
@@ -0,0 +1,267 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Configuring a `Client` before it connects.
+
+use super::{
+    random_client_owner, AttrCache, Client, ClientMetrics, Connection, IdMapper, NameCache,
+    NumericIds, RateLimiter, Result, Stats, DEFAULT_READ_PIPELINE_DEPTH, DEFAULT_RETRY_DEADLINE,
+    DEFAULT_WRITE_PIPELINE_DEPTH, MAX_MINOR_VERSION,
+};
+use nfs4::{FhExpireType, StableHow};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sun_rpc_client::{OpaqueAuth, Transport};
+
+/// How `ClientBuilder::connect` sets up a client. Everything can be left alone, in which case the
+/// client is the same as one from `Client::new`.
+#[derive(Clone)]
+pub struct ClientBuilder {
+    credential: OpaqueAuth,
+    minor_versions: RangeInclusive<u32>,
+    protect_state: bool,
+    timeout: Option<Duration>,
+    retry_deadline: Duration,
+    read_chunk_size: Option<u32>,
+    write_chunk_size: Option<u32>,
+    read_pipeline_depth: usize,
+    write_pipeline_depth: usize,
+    write_stability: StableHow,
+    bandwidth_limit: Option<RateLimiter>,
+    attr_cache: Option<Duration>,
+    name_cache: Option<Duration>,
+    lease_renewal: bool,
+    id_mapper: Arc<dyn IdMapper>,
+    metrics: Option<Arc<dyn ClientMetrics>>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            credential: sun_rpc_client::default_credential(),
+            minor_versions: 0..=MAX_MINOR_VERSION,
+            protect_state: false,
+            timeout: None,
+            retry_deadline: DEFAULT_RETRY_DEADLINE,
+            read_chunk_size: None,
+            write_chunk_size: None,
+            read_pipeline_depth: DEFAULT_READ_PIPELINE_DEPTH,
+            write_pipeline_depth: DEFAULT_WRITE_PIPELINE_DEPTH,
+            write_stability: StableHow::Unstable,
+            bandwidth_limit: None,
+            attr_cache: None,
+            name_cache: None,
+            lease_renewal: false,
+            id_mapper: Arc::new(NumericIds),
+            metrics: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The credential requests are sent with, AUTH_SYS by default. The server can still make us
+    /// switch to another flavor it prefers with SECINFO.
+    pub fn credential(mut self, credential: OpaqueAuth) -> Self {
+        self.credential = credential;
+        self
+    }
+
+    /// The newest minor version of NFSv4 to speak, up to 2. An older one is settled on if the
+    /// server doesn't speak it, down to 0. Minor version 0 has no sessions, which are made up for
+    /// as far as they can be, but there is no pNFS, trunking, or delegations with it.
+    pub fn max_minor_version(mut self, minor_version: u32) -> Self {
+        let max = minor_version.min(MAX_MINOR_VERSION);
+        self.minor_versions = (*self.minor_versions.start()).min(max)..=max;
+        self
+    }
+
+    /// Speaks only the given minor version of NFSv4, failing to connect if the server doesn't
+    /// speak it. See `max_minor_version`.
+    pub fn minor_version(mut self, minor_version: u32) -> Self {
+        let minor_version = minor_version.min(MAX_MINOR_VERSION);
+        self.minor_versions = minor_version..=minor_version;
+        self
+    }
+
+    /// Asks the server for SP4_MACH_CRED state protection, so it only takes operations which
+    /// change or do away with our state, like CLOSE or DESTROY_SESSION, from the credential the
+    /// client was made with. Some servers insist on it. Others refuse it with AUTH_SYS, or answer
+    /// that they protect nothing, which `Client::protected_ops` tells.
+    pub fn protect_state(mut self, protect: bool) -> Self {
+        self.protect_state = protect;
+        self
+    }
+
+    /// How long `connect_tcp` waits for the server to accept the connection and to answer each
+    /// request after, see `Client::set_timeout`. There is no timeout by default. Clients made with
+    /// `connect` can be given one with `Client::set_timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// See `Client::set_retry_deadline`.
+    pub fn retry_deadline(mut self, deadline: Duration) -> Self {
+        self.retry_deadline = deadline;
+        self
+    }
+
+    /// See `Client::set_read_chunk_size`.
+    pub fn read_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.read_chunk_size = Some(chunk_size.max(1));
+        self
+    }
+
+    /// See `Client::set_write_chunk_size`.
+    pub fn write_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.write_chunk_size = Some(chunk_size.max(1));
+        self
+    }
+
+    /// See `Client::set_read_pipeline_depth`.
+    pub fn read_pipeline_depth(mut self, depth: usize) -> Self {
+        self.read_pipeline_depth = depth.max(1);
+        self
+    }
+
+    /// See `Client::set_write_pipeline_depth`.
+    pub fn write_pipeline_depth(mut self, depth: usize) -> Self {
+        self.write_pipeline_depth = depth.max(1);
+        self
+    }
+
+    /// See `Client::set_write_stability`.
+    pub fn write_stability(mut self, stable: StableHow) -> Self {
+        self.write_stability = stable;
+        self
+    }
+
+    /// See `Client::set_bandwidth_limit`.
+    pub fn bandwidth_limit(mut self, limiter: RateLimiter) -> Self {
+        self.bandwidth_limit = Some(limiter);
+        self
+    }
+
+    /// See `Client::set_attr_cache`.
+    pub fn attr_cache(mut self, ttl: Duration) -> Self {
+        self.attr_cache = Some(ttl);
+        self
+    }
+
+    /// See `Client::set_name_cache`.
+    pub fn name_cache(mut self, ttl: Duration) -> Self {
+        self.name_cache = Some(ttl);
+        self
+    }
+
+    /// Whether to keep the lease from expiring while the client is idle, like
+    /// `Client::start_default_lease_renewal`. Off by default.
+    pub fn lease_renewal(mut self, lease_renewal: bool) -> Self {
+        self.lease_renewal = lease_renewal;
+        self
+    }
+
+    /// See `Client::set_id_mapper`.
+    pub fn id_mapper(mut self, id_mapper: Arc<dyn IdMapper>) -> Self {
+        self.id_mapper = id_mapper;
+        self
+    }
+
+    /// See `Client::set_metrics`.
+    pub fn metrics(mut self, metrics: Arc<dyn ClientMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Establishes a session with the server on the other end of the transport.
+    pub fn connect<TransportT>(&self, transport: TransportT) -> Result<Client<TransportT>>
+    where
+        TransportT: Transport + Send + 'static,
+    {
+        let mut client = self.build(transport)?;
+        if self.lease_renewal {
+            client.start_default_lease_renewal();
+        }
+        Ok(client)
+    }
+
+    /// Connects to the first of the addresses the server answers on, with the timeout if one was
+    /// given.
+    pub fn connect_tcp(&self, addr: impl ToSocketAddrs) -> Result<Client<TcpStream>> {
+        let mut error = None;
+        for addr in addr.to_socket_addrs()? {
+            let stream = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    let mut client = self.connect(stream)?;
+                    client.set_timeout(self.timeout)?;
+                    return Ok(client);
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error
+            .unwrap_or_else(|| io::Error::other("no addresses to connect to"))
+            .into())
+    }
+
+    /// Everything `connect` does except for what needs a transport we can send to another thread.
+    pub(crate) fn build<TransportT: Transport>(
+        &self,
+        transport: TransportT,
+    ) -> Result<Client<TransportT>> {
+        let client_owner = random_client_owner();
+        let (mut connection, client_id, session) = Connection::establish(
+            transport,
+            &client_owner,
+            self.credential.clone(),
+            self.minor_versions.clone(),
+            self.protect_state,
+        )?;
+        let stats = Arc::new(Stats::new(self.metrics.clone()));
+        connection.raw_client.tracer.metrics = Some(stats.clone());
+        let mut client = Client {
+            connection: Arc::new(Mutex::new(connection)),
+            session,
+            lease_time: Duration::ZERO,
+            lease_renewal: None,
+            client_id,
+            client_owner,
+            max_read: 0,
+            max_write: 0,
+            supported_attrs: Default::default(),
+            read_pipeline_depth: self.read_pipeline_depth,
+            read_chunk_size: self.read_chunk_size,
+            write_pipeline_depth: self.write_pipeline_depth,
+            write_chunk_size: self.write_chunk_size,
+            write_stability: self.write_stability,
+            unstable_writes: Default::default(),
+            bandwidth_limit: self.bandwidth_limit.clone(),
+            attr_cache: self.attr_cache.map(AttrCache::new),
+            name_cache: self.name_cache.map(NameCache::new),
+            fh_expire_type: FhExpireType::empty(),
+            volatile_handles: None,
+            retry_deadline: self.retry_deadline,
+            pnfs: None,
+            referrals: None,
+            reconnect: None,
+            gss: None,
+            stats,
+            id_mapper: self.id_mapper.clone(),
+            minor_versions: self.minor_versions.clone(),
+        };
+        client.read_root_attrs()?;
+        Ok(client)
+    }
+}
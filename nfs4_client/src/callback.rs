@@ -0,0 +1,105 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! The callback program, which the server calls over the backchannel of our session to recall
+//! delegations, notify us of changes to directories and tell us when copies are done.
+
+use super::{MAX_MINOR_VERSION, NFS_CB};
+use nfs4::{
+    CbArgOp, CbCompoundArgs, CbCompoundRes, CbNotifyArgs, CbOffloadArgs, CbResOp, CbSequenceRes,
+    FileHandle, StatusError, StatusResult,
+};
+use std::sync::mpsc;
+use sun_rpc_client::{AcceptedReplyBody, Program, NULL_PROCEDURE};
+
+const NFS_CB_VERSION: u32 = 1;
+const CB_COMPOUND_PROCEDURE: u32 = 1;
+
+/// What the server asked of us. Calls only arrive while we are waiting for the reply to a request
+/// of our own, so rather than acting on them right away they are queued up for once it is done.
+#[derive(Debug)]
+pub(crate) enum Callback {
+    Recall(FileHandle),
+    RecallAny,
+    Notify(CbNotifyArgs),
+    Offload(CbOffloadArgs),
+}
+
+pub(crate) struct CallbackProgram {
+    callbacks: mpsc::Sender<Callback>,
+}
+
+impl CallbackProgram {
+    pub(crate) fn new(callbacks: mpsc::Sender<Callback>) -> Self {
+        Self { callbacks }
+    }
+
+    fn compound(&mut self, args: CbCompoundArgs) -> CbCompoundRes {
+        let mut res = CbCompoundRes {
+            status: StatusResult::Ok(()),
+            tag: args.tag,
+            res_array: vec![],
+        };
+        if !(1..=MAX_MINOR_VERSION).contains(&args.minor_version) {
+            res.status = StatusResult::Err(StatusError::MinorVersMismatch);
+            return res;
+        }
+
+        for op in args.arg_array {
+            // Queueing only fails once the client is gone, which leaves nothing to do
+            let res_op = match op {
+                CbArgOp::Sequence(args) => CbResOp::Sequence(StatusResult::Ok(CbSequenceRes {
+                    session_id: args.session_id,
+                    sequence_id: args.sequence_id,
+                    slot_id: args.slot_id,
+                    highest_slot_id: args.highest_slot_id,
+                    target_highest_slot_id: args.highest_slot_id,
+                })),
+                CbArgOp::Recall(args) => {
+                    let _ = self.callbacks.send(Callback::Recall(args.fh));
+                    CbResOp::Recall(StatusResult::Ok(()))
+                }
+                CbArgOp::RecallAny(_) => {
+                    let _ = self.callbacks.send(Callback::RecallAny);
+                    CbResOp::RecallAny(StatusResult::Ok(()))
+                }
+                CbArgOp::Notify(args) => {
+                    let _ = self.callbacks.send(Callback::Notify(args));
+                    CbResOp::Notify(StatusResult::Ok(()))
+                }
+                CbArgOp::Offload(args) => {
+                    let _ = self.callbacks.send(Callback::Offload(args));
+                    CbResOp::Offload(StatusResult::Ok(()))
+                }
+                CbArgOp::RecallSlot(_) => CbResOp::RecallSlot(StatusResult::Ok(())),
+            };
+            res.res_array.push(res_op);
+        }
+        res
+    }
+}
+
+impl Program for CallbackProgram {
+    fn program(&self) -> u32 {
+        NFS_CB
+    }
+
+    fn versions(&self) -> (u32, u32) {
+        (NFS_CB_VERSION, NFS_CB_VERSION)
+    }
+
+    fn call(&mut self, _version: u32, procedure: u32, args: &[u8]) -> AcceptedReplyBody<Vec<u8>> {
+        match procedure {
+            NULL_PROCEDURE => AcceptedReplyBody::Success(vec![]),
+            CB_COMPOUND_PROCEDURE => {
+                let Ok(args) = xdr_extras::from_bytes::<CbCompoundArgs>(args) else {
+                    return AcceptedReplyBody::GarbageArguments;
+                };
+                match xdr_extras::to_bytes(&self.compound(args)) {
+                    Ok(res) => AcceptedReplyBody::Success(res),
+                    Err(_) => AcceptedReplyBody::SystemError,
+                }
+            }
+            _ => AcceptedReplyBody::ProcedureUnavailable,
+        }
+    }
+}
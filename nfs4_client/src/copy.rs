@@ -0,0 +1,182 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Server-side copy, see RFC 7862. The server copies the file itself without the data going
+//! through us. For copies between servers, the source is first told with COPY_NOTIFY to let the
+//! destination read the file, then the destination pulls it from there. Copies the server carries
+//! on with in the background end with a CB_OFFLOAD callback, and we also ask with OFFLOAD_STATUS.
+
+use super::{lock, Client, OpenOptions, Result, ReturnSecond, SaveFh};
+use nfs4::{
+    CommitArgs, CopyArgs, CopyNotifyArgs, FileAttributeId, FileHandle, NetLoc, OffloadCancelArgs,
+    OffloadInfo, OffloadStatusArgs, OpenClaim, PutFhArgs, ShareAccess, StableHow, StateId,
+    StatusResult,
+};
+use std::thread;
+use std::time::Duration;
+use sun_rpc_client::Transport;
+
+// How often we ask about a copy going on in the background
+const OFFLOAD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Copies the whole of the source file over the start of the destination file, both on this
+    /// server, without reading or writing the data ourselves. Returns how many bytes were copied.
+    pub fn copy(&mut self, source: FileHandle, destination: FileHandle) -> Result<u64> {
+        let options = OpenOptions::new().access(ShareAccess::READ);
+        let source_file = self.open_with_claim(source.clone(), OpenClaim::Fh, &options)?;
+        let size = self.file_size(&source)?;
+        self.copy_to(&source, source_file.state_id, vec![], &destination, size)
+    }
+
+    /// Copies the whole of the source file on another server over the start of the destination
+    /// file on this one. This server reads the file straight from the other one, which is told to
+    /// let it with COPY_NOTIFY. `this_server` is how the other server sees this one. Returns how
+    /// many bytes were copied.
+    pub fn copy_from_remote<SourceTransportT: Transport>(
+        &mut self,
+        source: &mut Client<SourceTransportT>,
+        source_file: FileHandle,
+        this_server: NetLoc,
+        destination: FileHandle,
+    ) -> Result<u64> {
+        let options = OpenOptions::new().access(ShareAccess::READ);
+        let open = source.open_with_claim(source_file.clone(), OpenClaim::Fh, &options)?;
+        let size = source.file_size(&source_file)?;
+        let (_, notify) = source.do_compound((
+            PutFhArgs {
+                object: source_file.clone(),
+            },
+            CopyNotifyArgs {
+                src_state_id: open.state_id,
+                destination_server: this_server,
+            },
+        ))?;
+
+        let res = self.copy_to(
+            &source_file,
+            notify.state_id,
+            notify.source_server,
+            &destination,
+            size,
+        );
+
+        // The source forgets about the copy after its lease anyway
+        let _ = source.do_compound((
+            PutFhArgs {
+                object: source_file,
+            },
+            OffloadCancelArgs {
+                state_id: notify.state_id,
+            },
+        ));
+        res
+    }
+
+    fn file_size(&mut self, handle: &FileHandle) -> Result<u64> {
+        let attrs = self.get_attr(handle.clone())?.object_attributes;
+        Ok(*attrs.get_as(FileAttributeId::Size).unwrap())
+    }
+
+    /// Copies `size` bytes from the source to the destination with COPY, as many times as it
+    /// takes, then commits them.
+    fn copy_to(
+        &mut self,
+        source: &FileHandle,
+        src_state_id: StateId,
+        source_server: Vec<NetLoc>,
+        destination: &FileHandle,
+        size: u64,
+    ) -> Result<u64> {
+        self.return_delegation(destination)?;
+        let options = OpenOptions::new().access(ShareAccess::WRITE);
+        let destination_file =
+            self.open_with_claim(destination.clone(), OpenClaim::Fh, &options)?;
+
+        let mut copied = 0;
+        let mut committed = true;
+        while copied < size {
+            let (_, _, _, res) = self.do_compound((
+                PutFhArgs {
+                    object: source.clone(),
+                },
+                SaveFh,
+                PutFhArgs {
+                    object: destination.clone(),
+                },
+                CopyArgs {
+                    src_state_id,
+                    dst_state_id: destination_file.state_id,
+                    src_offset: copied,
+                    dst_offset: copied,
+                    count: size - copied,
+                    consecutive: false,
+                    synchronous: false,
+                    source_server: source_server.clone(),
+                },
+            ))?;
+            let response = res.response;
+            // Whether copies done in the background are on stable storage isn't always told
+            let count = match response.callback_id {
+                Some(state_id) => {
+                    committed = false;
+                    self.wait_for_offload(destination, state_id)?
+                }
+                None => {
+                    committed &= response.committed == StableHow::FileSync;
+                    response.count
+                }
+            };
+            if count == 0 {
+                break;
+            }
+            copied += count;
+        }
+
+        if !committed {
+            self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: destination.clone(),
+                },
+                CommitArgs {
+                    offset: 0,
+                    count: 0,
+                },
+            ))?;
+        }
+        Ok(copied)
+    }
+
+    /// Waits for a copy the server carries on with in the background to finish, returning how
+    /// many bytes it copied.
+    fn wait_for_offload(&mut self, destination: &FileHandle, state_id: StateId) -> Result<u64> {
+        loop {
+            if let Some(info) = lock(&self.connection).offloads.remove(&state_id.other) {
+                return match info {
+                    OffloadInfo::Ok(response) => Ok(response.count),
+                    OffloadInfo::Err { status, .. } => Err(status.into()),
+                };
+            }
+
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: destination.clone(),
+                },
+                OffloadStatusArgs { state_id },
+            ));
+            match res {
+                Ok(res) => match res.complete {
+                    Some(StatusResult::Ok(())) => return Ok(res.count),
+                    Some(StatusResult::Err(e)) => return Err(e.into()),
+                    None => thread::sleep(OFFLOAD_POLL_INTERVAL),
+                },
+                // Once the server calls us back it may forget about the copy, the callback is
+                // looked for again above
+                Err(_)
+                    if lock(&self.connection)
+                        .offloads
+                        .contains_key(&state_id.other) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Directory entries with the attributes most listings want as typed fields, rather than in a
+//! `FileAttributes` to be picked apart one at a time.
+
+use super::{Client, Result};
+use nfs4::{DirectoryEntry, EnumSet, FileAttributeId, FileHandle, FileType, Mode, Time};
+use sun_rpc_client::Transport;
+
+/// An entry of a directory, as returned by `Client::read_dir_plus`. What the server doesn't
+/// support, or wasn't asked for, is `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirEntryInfo {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(with = "nfs4::readable"))]
+    pub handle: Option<FileHandle>,
+    #[cfg_attr(feature = "serde", serde(with = "nfs4::readable"))]
+    pub file_type: Option<FileType>,
+    /// The permission bits, along with set-user-ID, set-group-ID and sticky.
+    pub mode: Option<u32>,
+    pub size: Option<u64>,
+    pub num_links: Option<u32>,
+    pub owner: Option<String>,
+    pub owner_group: Option<String>,
+    pub access_time: Option<Time>,
+    pub modify_time: Option<Time>,
+    /// When the attributes last changed.
+    pub metadata_time: Option<Time>,
+}
+
+impl DirEntryInfo {
+    /// The attributes `Client::read_dir_plus` asks for, all the fields have.
+    pub fn attr_request() -> EnumSet<FileAttributeId> {
+        [
+            FileAttributeId::FileHandle,
+            FileAttributeId::Type,
+            FileAttributeId::Mode,
+            FileAttributeId::Size,
+            FileAttributeId::NumLinks,
+            FileAttributeId::Owner,
+            FileAttributeId::OwnerGroup,
+            FileAttributeId::TimeAccess,
+            FileAttributeId::TimeModify,
+            FileAttributeId::TimeMetadata,
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == Some(FileType::Directory)
+    }
+}
+
+impl From<DirectoryEntry> for DirEntryInfo {
+    /// Takes whichever of the attributes the entry has, so it works for entries listed with
+    /// fewer attributes than `attr_request` too.
+    fn from(entry: DirectoryEntry) -> Self {
+        let mut attrs = entry.attrs;
+        Self {
+            name: entry.name,
+            handle: attrs.remove_as(FileAttributeId::FileHandle),
+            file_type: attrs.remove_as(FileAttributeId::Type),
+            mode: attrs.remove_as(FileAttributeId::Mode).map(|m: Mode| m.0),
+            size: attrs.remove_as(FileAttributeId::Size),
+            num_links: attrs.remove_as(FileAttributeId::NumLinks),
+            owner: attrs.remove_as(FileAttributeId::Owner),
+            owner_group: attrs.remove_as(FileAttributeId::OwnerGroup),
+            access_time: attrs.remove_as(FileAttributeId::TimeAccess),
+            modify_time: attrs.remove_as(FileAttributeId::TimeModify),
+            metadata_time: attrs.remove_as(FileAttributeId::TimeMetadata),
+        }
+    }
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Like `read_dir`, asking for the attributes of `DirEntryInfo`.
+    pub fn read_dir_plus(
+        &mut self,
+        handle: FileHandle,
+    ) -> impl Iterator<Item = Result<DirEntryInfo>> + '_ {
+        self.read_dir(handle, DirEntryInfo::attr_request())
+            .map(|entry| entry.map(DirEntryInfo::from))
+    }
+}
+
+#[test]
+fn read_dir_plus() {
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    files.make_dir("a_dir").unwrap();
+    let (_server, mut client) = super::in_memory_client(files);
+    let root = client.look_up("/").unwrap();
+
+    let mut entries = client
+        .read_dir_plus(root)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    let [dir, file] = &entries[..] else {
+        panic!("{entries:?}");
+    };
+    assert_eq!(dir.name, "a_dir");
+    assert!(dir.is_dir());
+    assert_eq!(file.name, "a_file");
+    assert_eq!(file.file_type, Some(FileType::Regular));
+    assert_eq!(file.size, Some(5));
+    assert_eq!(
+        file.handle.as_ref(),
+        Some(&client.look_up("/a_file").unwrap())
+    );
+    assert!(file.modify_time.is_some());
+}
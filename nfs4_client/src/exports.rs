@@ -0,0 +1,79 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Finding what the server exports over NFSv4 itself, see RFC 7530 section 7. The exports are
+//! joined together by a pseudo file system of directories leading from the root to each of them,
+//! which has an fsid of its own, so an export shows up as a directory whose fsid isn't that of the
+//! one it is in.
+
+use super::{Client, RemotePathBuf, Result};
+use nfs4::{EnumSet, FileAttributeId, FileType, FsId};
+use sun_rpc_client::Transport;
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// The paths the server exports, found by walking the pseudo file system from the root. Only
+    /// directories make up the pseudo file system, so one holding anything else is taken to be an
+    /// export too, like the root of a server which exports a single file system as `/`.
+    pub fn list_exports(&mut self) -> Result<Vec<RemotePathBuf>> {
+        let attr_request = [FileAttributeId::FsId].into_iter().collect();
+        let (root, mut root_attrs) = self.look_up_with_attrs("/", attr_request)?;
+        let root_fs_id = root_attrs.remove_as(FileAttributeId::FsId);
+
+        let attr_request: EnumSet<_> = [
+            FileAttributeId::Type,
+            FileAttributeId::FsId,
+            FileAttributeId::FileHandle,
+        ]
+        .into_iter()
+        .collect();
+        let mut exports = vec![];
+        let mut dirs = vec![(RemotePathBuf::from("/"), root, root_fs_id)];
+        while let Some((path, handle, fs_id)) = dirs.pop() {
+            let entries = self
+                .read_dir(handle.clone(), attr_request.clone())
+                .collect::<Result<Vec<_>>>()?;
+            if entries
+                .iter()
+                .any(|e| e.attrs.get_as(FileAttributeId::Type) != Some(&FileType::Directory))
+            {
+                exports.push(path);
+                continue;
+            }
+            for mut entry in entries {
+                let entry_path = path.join(&entry.name);
+                let entry_fs_id: Option<FsId> = entry.attrs.remove_as(FileAttributeId::FsId);
+                if entry_fs_id != fs_id {
+                    exports.push(entry_path);
+                    continue;
+                }
+                let entry_handle = match entry.attrs.remove_as(FileAttributeId::FileHandle) {
+                    Some(entry_handle) => entry_handle,
+                    None => self.look_up_from(handle.clone(), &entry.name)?,
+                };
+                dirs.push((entry_path, entry_handle, entry_fs_id));
+            }
+        }
+        exports.sort();
+        Ok(exports)
+    }
+}
+
+#[test]
+fn list_exports() {
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.make_dir("srv").unwrap();
+    files.make_file_system("srv/data").unwrap();
+    files.make_dir("srv/data/dir").unwrap();
+    files.make_file_system("home").unwrap();
+    files.write_file("home/a_file", "hello").unwrap();
+    let (_server, mut client) = super::in_memory_client(files);
+    let exports = client.list_exports().unwrap();
+    assert_eq!(exports, [RemotePathBuf::from("/home"), "/srv/data".into()]);
+
+    let mut files = MemoryFs::new();
+    files.make_dir("dir").unwrap();
+    files.write_file("a_file", "hello").unwrap();
+    let (_server, mut client) = super::in_memory_client(files);
+    assert_eq!(client.list_exports().unwrap(), [RemotePathBuf::from("/")]);
+}
@@ -0,0 +1,243 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! An open remote file which can be used wherever `std::io` is expected.
+
+use super::{Client, OpenFile, Result};
+use nfs4::{Bytes, FileAttributeId, StateId};
+use std::io::{self, SeekFrom};
+use sun_rpc_client::Transport;
+
+/// Reads and writes an open file from a current position, like `std::fs::File`. Reads fetch a
+/// whole chunk and keep what wasn't asked for yet around, and writes are collected until a chunk
+/// has been written, the position moves or the file is flushed. Dropping it flushes what is left,
+/// ignoring any error, and then closes the file.
+pub struct File<'a, TransportT: Transport> {
+    client: &'a mut Client<TransportT>,
+    open_file: OpenFile<TransportT>,
+    position: u64,
+    // What the last READ returned, and where in the file it starts
+    read_buffer: Bytes,
+    read_buffer_offset: u64,
+    // What hasn't been sent yet, it ends at `position`
+    write_buffer: Vec<u8>,
+}
+
+impl<'a, TransportT: Transport> File<'a, TransportT> {
+    pub fn new(client: &'a mut Client<TransportT>, open_file: OpenFile<TransportT>) -> Self {
+        Self {
+            client,
+            open_file,
+            position: 0,
+            read_buffer: Bytes::new(),
+            read_buffer_offset: 0,
+            write_buffer: vec![],
+        }
+    }
+
+    pub fn open_file(&self) -> &OpenFile<TransportT> {
+        &self.open_file
+    }
+
+    // A sequence ID of zero means whatever the current state of the open is, which keeps us from
+    // using an old one if the file gets opened again in the meantime.
+    fn state_id(&self) -> StateId {
+        StateId {
+            sequence_id: 0,
+            other: self.open_file.state_id.other,
+        }
+    }
+
+    fn buffered(&self) -> &[u8] {
+        let start = self.position.saturating_sub(self.read_buffer_offset) as usize;
+        if self.position < self.read_buffer_offset || start >= self.read_buffer.len() {
+            return &[];
+        }
+        &self.read_buffer[start..]
+    }
+
+    fn fill_read_buffer(&mut self) -> Result<()> {
+        let res = self.client.read_with_state(
+            self.open_file.handle.clone(),
+            self.state_id(),
+            self.position,
+            self.client.read_chunk_size(),
+        )?;
+        self.read_buffer = res.data;
+        self.read_buffer_offset = self.position;
+        Ok(())
+    }
+
+    fn flush_writes(&mut self) -> Result<()> {
+        let mut offset = self.position - self.write_buffer.len() as u64;
+        while !self.write_buffer.is_empty() {
+            let res = self.client.write_with_state(
+                self.open_file.handle.clone(),
+                self.state_id(),
+                offset,
+                self.write_buffer.clone().into(),
+            )?;
+            if res.count == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            let count = (res.count as usize).min(self.write_buffer.len());
+            self.write_buffer.drain(..count);
+            offset += count as u64;
+        }
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<u64> {
+        let attrs = self
+            .client
+            .get_attr(self.open_file.handle.clone())?
+            .object_attributes;
+        Ok(*attrs.get_as(FileAttributeId::Size).unwrap())
+    }
+}
+
+impl<TransportT: Transport> io::Read for File<'_, TransportT> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush_writes()?;
+        if self.buffered().is_empty() {
+            self.fill_read_buffer()?;
+        }
+
+        let buffered = self.buffered();
+        let len = buffered.len().min(buf.len());
+        buf[..len].copy_from_slice(&buffered[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<TransportT: Transport> io::Write for File<'_, TransportT> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk_size = self.client.write_chunk_size() as usize;
+        if self.write_buffer.len() >= chunk_size {
+            self.flush_writes()?;
+        }
+        self.read_buffer.clear();
+
+        let len = buf.len().min(chunk_size - self.write_buffer.len());
+        self.write_buffer.extend_from_slice(&buf[..len]);
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(self.flush_writes()?)
+    }
+}
+
+impl<TransportT: Transport> io::Seek for File<'_, TransportT> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_writes()?;
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.size()?.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl<TransportT: Transport> Drop for File<'_, TransportT> {
+    fn drop(&mut self) {
+        let _ = self.flush_writes();
+    }
+}
+
+#[cfg(test)]
+fn open_in_memory(
+    files: nfs4_server::memory::MemoryFs,
+) -> (
+    nfs4_server::Server<nfs4_server::memory::MemoryFs>,
+    Client<nfs4_server::pipe::Pipe>,
+    OpenFile<nfs4_server::pipe::Pipe>,
+) {
+    use super::OpenOptions;
+    use nfs4::ShareAccess;
+
+    let (server, mut client) = super::in_memory_client(files);
+    let root = client.look_up("/").unwrap();
+    let options = OpenOptions::new().access(ShareAccess::BOTH).create();
+    let open_file = client.open(root, "a_file", &options).unwrap();
+    (server, client, open_file)
+}
+
+#[test]
+fn write_then_read_back() {
+    use io::{Read as _, Seek as _, Write as _};
+
+    let (server, mut client, open_file) = open_in_memory(Default::default());
+    let mut file = File::new(&mut client, open_file);
+    file.write_all(b"hello world").unwrap();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    file.write_all(b"there").unwrap();
+
+    // Reading sends what was written first
+    file.rewind().unwrap();
+    let mut data = String::new();
+    file.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "hello there");
+
+    file.write_all(b"!").unwrap();
+    drop(file);
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"hello there!");
+}
+
+#[test]
+fn seek_past_end() {
+    use io::{Read as _, Seek as _, Write as _};
+
+    let mut files = nfs4_server::memory::MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client, open_file) = open_in_memory(files);
+    let mut file = File::new(&mut client, open_file);
+
+    assert_eq!(file.seek(SeekFrom::End(3)).unwrap(), 8);
+    assert_eq!(file.read(&mut [0; 4]).unwrap(), 0);
+
+    // Writing there leaves a hole of zeros
+    file.write_all(b"!").unwrap();
+    file.flush().unwrap();
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"hello\0\0\0!");
+
+    let error = file.seek(SeekFrom::Current(-10)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(file.stream_position().unwrap(), 9);
+}
+
+#[test]
+fn short_reads_and_writes() {
+    use io::{Read as _, Seek as _, Write as _};
+
+    let mut files = nfs4_server::memory::MemoryFs::new();
+    files.write_file("a_file", "hello world").unwrap();
+    let (server, mut client, open_file) = open_in_memory(files);
+    server.limit_io(Some(3));
+    let mut file = File::new(&mut client, open_file);
+
+    // Each read returns no more than the server did
+    let mut buf = [0; 8];
+    assert_eq!(file.read(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"hel");
+    let mut data = vec![];
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"lo world");
+
+    // Writes keep going until the server took all of it
+    file.rewind().unwrap();
+    file.write_all(b"goodbye world").unwrap();
+    file.flush().unwrap();
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"goodbye world");
+}
@@ -0,0 +1,267 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! The flexible files layout type, see RFC 8435. Each mirror of the file is striped across data
+//! servers which can speak NFSv3 or NFSv4.1, and which we may have to access with credentials the
+//! layout gives us. How the data servers did is reported back to the server with LAYOUTERROR and
+//! LAYOUTSTATS, or when giving the layout back to servers which don't take those.
+
+use super::pnfs::{
+    decode, DataFile, FileLayout, IoCounts, IoStats, Stripes, DEVICE_INFO_MAX_COUNT,
+};
+use super::{lock, Client, Result};
+use nfs4::{
+    DeviceId, FileHandle, FlexFilesDeviceAddr, FlexFilesDeviceVersion, FlexFilesFlags,
+    FlexFilesIoError, FlexFilesIoLatency, FlexFilesIoStats, FlexFilesLayout, FlexFilesLayoutReturn,
+    FlexFilesLayoutUpdate, GetDeviceInfoArgs, IoInfo, Layout, LayoutErrorArgs, LayoutStatsArgs,
+    LayoutType, LayoutUpdate, PutFhArgs, StateId, Time,
+};
+use std::cmp::Reverse;
+use sun_rpc_client::{AuthSysParameters, Gid, OpaqueAuth, Transport, Uid};
+
+/// Picks which of the versions of NFS a data server speaks we use with it, returning its index.
+fn choose_version(versions: &[FlexFilesDeviceVersion]) -> Option<usize> {
+    let position = |f: fn(&FlexFilesDeviceVersion) -> bool| versions.iter().position(f);
+    position(|v| v.version == 4 && v.minor_version >= 1).or(position(|v| v.version == 3))
+}
+
+/// The AUTH_SYS credential for the uid and gid a layout tells us to use with a data server.
+fn auth_sys_credential(user: &str, group: &str) -> Option<OpaqueAuth> {
+    let uid = Uid(user.parse().ok()?);
+    let gid = Gid(group.parse().ok()?);
+    Some(OpaqueAuth::auth_sys(AuthSysParameters {
+        stamp: 0,
+        machine_name: "test-machine".into(),
+        uid,
+        gid,
+        gids: vec![gid],
+    }))
+}
+
+fn io_info(counts: &IoCounts) -> IoInfo {
+    IoInfo {
+        count: counts.ops_completed,
+        bytes: counts.bytes_completed,
+    }
+}
+
+fn io_latency(counts: &IoCounts) -> FlexFilesIoLatency {
+    FlexFilesIoLatency {
+        ops_requested: counts.ops_requested,
+        bytes_requested: counts.bytes_requested,
+        ops_completed: counts.ops_completed,
+        bytes_completed: counts.bytes_completed,
+        bytes_not_delivered: counts.bytes_requested
+            - counts.bytes_completed.min(counts.bytes_requested),
+        total_busy_time: Time::from(counts.busy),
+        aggregate_completion_time: Time::from(counts.busy),
+    }
+}
+
+fn io_stats(state_id: StateId, file: &DataFile) -> Option<FlexFilesIoStats> {
+    let IoStats { read, write, since } = &file.stats;
+    Some(FlexFilesIoStats {
+        offset: 0,
+        length: u64::MAX,
+        state_id,
+        read: io_info(read),
+        write: io_info(write),
+        device_id: file.device_id,
+        layout_update: FlexFilesLayoutUpdate {
+            addr: file.addr.clone(),
+            fh: file.fh.clone(),
+            read: io_latency(read),
+            write: io_latency(write),
+            duration: Time::from(since.as_ref()?.elapsed()),
+            local: false,
+        },
+    })
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Finds the data servers of the given flexible files layout and connects to them. Returns
+    /// `None` if they can't be used.
+    pub(crate) fn flex_files_layout(
+        &mut self,
+        layout: &Layout,
+        state_id: StateId,
+    ) -> Result<Option<FileLayout>> {
+        let flex_files_layout: FlexFilesLayout = decode(&layout.content.body)?;
+        let mut devices: Vec<(DeviceId, FlexFilesDeviceAddr)> = vec![];
+        let mut mirrors = vec![];
+        for mirror in &flex_files_layout.mirrors {
+            let num_stripes = mirror.data_servers.len();
+            if num_stripes == 0 || (flex_files_layout.stripe_unit == 0 && num_stripes > 1) {
+                return Ok(None);
+            }
+
+            let mut files = vec![];
+            for data_server in &mirror.data_servers {
+                let device_id = data_server.device_id;
+                if !devices.iter().any(|(id, _)| *id == device_id) {
+                    let device = self.do_compound(GetDeviceInfoArgs {
+                        device_id,
+                        layout_type: LayoutType::FlexFiles,
+                        max_count: DEVICE_INFO_MAX_COUNT,
+                        notify_types: Default::default(),
+                    })?;
+                    devices.push((device_id, decode(&device.device_addr.body)?));
+                }
+                let (_, device) = devices.iter().find(|(id, _)| *id == device_id).unwrap();
+
+                let Some(index) = choose_version(&device.versions) else {
+                    return Ok(None);
+                };
+                let version = &device.versions[index];
+                let fh_versions = &data_server.fh_versions;
+                let Some(fh) = fh_versions.get(index).or(fh_versions.first()) else {
+                    return Ok(None);
+                };
+                // Loosely coupled data servers don't know about our opens, so access to the file
+                // is controlled by its owner and group
+                let credential = if version.tightly_coupled {
+                    Some(self.credential())
+                } else {
+                    auth_sys_credential(&data_server.user, &data_server.group)
+                };
+                let Some(credential) = credential else {
+                    return Ok(None);
+                };
+                let Some((data_server_id, addr)) =
+                    self.connect_data_server(&device.netaddrs, version.version, fh)
+                else {
+                    return Ok(None);
+                };
+                files.push(DataFile {
+                    data_server: data_server_id,
+                    addr,
+                    device_id,
+                    fh: fh.clone(),
+                    state_id: Some(data_server.state_id),
+                    credential,
+                    stats: Default::default(),
+                });
+            }
+
+            let efficiency = mirror.data_servers.iter().map(|d| d.efficiency).min();
+            let unit_size = match flex_files_layout.stripe_unit {
+                0 => u64::MAX,
+                unit_size => unit_size,
+            };
+            // Files are always striped sparsely
+            let stripes = Stripes {
+                unit_size,
+                first_stripe_index: 0,
+                pattern_offset: 0,
+                dense: false,
+                files,
+            };
+            mirrors.push((efficiency, stripes));
+        }
+        if mirrors.is_empty() {
+            return Ok(None);
+        }
+        // Reads go to the first mirror, which should be the one the server says is best to use
+        mirrors.sort_by_key(|(efficiency, _)| Reverse(*efficiency));
+
+        let mirrors = mirrors.into_iter().map(|(_, stripes)| stripes).collect();
+        let mut file_layout = FileLayout::new(LayoutType::FlexFiles, state_id, layout, mirrors);
+        let flags = flex_files_layout.flags;
+        file_layout.io_through_mds = !flags.contains(FlexFilesFlags::NO_IO_THRU_MDS);
+        file_layout.read_io = !flags.contains(FlexFilesFlags::NO_READ_IO);
+        file_layout.write_one_mirror = flags.contains(FlexFilesFlags::WRITE_ONE_MIRROR);
+        file_layout.layout_commit = !flags.contains(FlexFilesFlags::NO_LAYOUTCOMMIT);
+        Ok(Some(file_layout))
+    }
+
+    /// Tells the server about the errors and I/O with the data servers of the given layout,
+    /// returning the body to give it back with. Servers which take LAYOUTERROR and LAYOUTSTATS
+    /// get them that way, which is done on a best effort basis.
+    pub(crate) fn flex_files_report(
+        &mut self,
+        handle: &FileHandle,
+        layout: &FileLayout,
+    ) -> Vec<u8> {
+        let io_errors: Vec<_> = layout
+            .errors
+            .iter()
+            .map(|(offset, length, error)| FlexFilesIoError {
+                offset: *offset,
+                length: *length,
+                state_id: layout.state_id,
+                errors: vec![error.clone()],
+            })
+            .collect();
+        let io_stats: Vec<_> = layout
+            .mirrors
+            .iter()
+            .flat_map(|m| &m.files)
+            .filter_map(|f| io_stats(layout.state_id, f))
+            .collect();
+
+        let report = if lock(&self.connection).raw_client.minor_version < 2 {
+            FlexFilesLayoutReturn {
+                io_errors,
+                io_stats,
+            }
+        } else {
+            for io_error in io_errors {
+                let _ = self.do_compound((
+                    PutFhArgs {
+                        object: handle.clone(),
+                    },
+                    LayoutErrorArgs {
+                        offset: io_error.offset,
+                        length: io_error.length,
+                        state_id: io_error.state_id,
+                        errors: io_error.errors,
+                    },
+                ));
+            }
+            for stats in io_stats {
+                let body = xdr_extras::to_bytes(&stats.layout_update).unwrap_or_default();
+                let _ = self.do_compound((
+                    PutFhArgs {
+                        object: handle.clone(),
+                    },
+                    LayoutStatsArgs {
+                        offset: stats.offset,
+                        length: stats.length,
+                        state_id: stats.state_id,
+                        read: stats.read,
+                        write: stats.write,
+                        device_id: stats.device_id,
+                        layout_update: LayoutUpdate {
+                            type_: LayoutType::FlexFiles,
+                            body,
+                        },
+                    },
+                ));
+            }
+            FlexFilesLayoutReturn::default()
+        };
+        xdr_extras::to_bytes(&report).unwrap_or_default()
+    }
+}
+
+#[test]
+fn choose_data_server_version() {
+    let version = |version, minor_version| FlexFilesDeviceVersion {
+        version,
+        minor_version,
+        read_size: 1048576,
+        write_size: 1048576,
+        tightly_coupled: false,
+    };
+    assert_eq!(choose_version(&[version(3, 0), version(4, 1)]), Some(1));
+    assert_eq!(choose_version(&[version(4, 0), version(3, 0)]), Some(1));
+    assert_eq!(choose_version(&[version(4, 2)]), Some(0));
+    assert_eq!(choose_version(&[version(4, 0)]), None);
+}
+
+#[test]
+fn data_server_credential() {
+    let credential = auth_sys_credential("1001", "100").unwrap();
+    let params: AuthSysParameters = xdr_extras::from_bytes(&credential.body).unwrap();
+    assert_eq!((params.uid, params.gid), (Uid(1001), Gid(100)));
+    assert_eq!(auth_sys_credential("alice", "100"), None);
+}
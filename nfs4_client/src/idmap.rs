@@ -0,0 +1,201 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Mapping between the strings NFSv4 names owners and groups with, like `alice@example.com`, and
+//! local user and group IDs. Servers also send and accept IDs written out as numbers when they
+//! have no name for them, and so do we.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Converts the `Owner` and `OwnerGroup` attributes to local IDs and back. Set it with
+/// `Client::set_id_mapper`.
+pub trait IdMapper: Send + Sync {
+    /// The local user named by an `Owner` attribute, `None` if we have no user for it.
+    fn uid(&self, owner: &str) -> Option<u32>;
+
+    /// The local group named by an `OwnerGroup` attribute, `None` if we have no group for it.
+    fn gid(&self, group: &str) -> Option<u32>;
+
+    /// What to set the `Owner` attribute to for the local user.
+    fn owner(&self, uid: u32) -> String;
+
+    /// What to set the `OwnerGroup` attribute to for the local group.
+    fn group(&self, gid: u32) -> String;
+}
+
+/// Only maps IDs written out as numbers, the way servers without ID mapping (and AUTH_SYS with
+/// `nfs4_disable_idmapping` on Linux) do it. This is what a `Client` uses until told otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NumericIds;
+
+impl IdMapper for NumericIds {
+    fn uid(&self, owner: &str) -> Option<u32> {
+        owner.parse().ok()
+    }
+
+    fn gid(&self, group: &str) -> Option<u32> {
+        group.parse().ok()
+    }
+
+    fn owner(&self, uid: u32) -> String {
+        uid.to_string()
+    }
+
+    fn group(&self, gid: u32) -> String {
+        gid.to_string()
+    }
+}
+
+/// Maps `name@domain` to and from the IDs of users and groups it was given, either one by one or
+/// from files in the format of `/etc/passwd` and `/etc/group`. Names in other domains aren't
+/// mapped, and IDs it has no name for are written out as numbers.
+#[derive(Clone, Debug, Default)]
+pub struct IdMap {
+    domain: String,
+    users: Names,
+    groups: Names,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Names {
+    ids: BTreeMap<String, u32>,
+    names: BTreeMap<u32, String>,
+}
+
+impl Names {
+    fn insert(&mut self, name: &str, id: u32) {
+        self.ids.insert(name.into(), id);
+        // The first name for an ID is the one it maps to, like `getpwuid` does
+        self.names.entry(id).or_insert_with(|| name.into());
+    }
+
+    /// Takes the name and ID from the first and third fields of each line, which is where both
+    /// `/etc/passwd` and `/etc/group` have them.
+    fn parse(&mut self, contents: &str) {
+        for line in contents.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split(':');
+            let (Some(name), Some(id)) = (fields.next(), fields.nth(1)) else {
+                continue;
+            };
+            if let Ok(id) = id.parse() {
+                self.insert(name, id);
+            }
+        }
+    }
+}
+
+impl IdMap {
+    /// An empty map for names in the given domain, the NFSv4 domain the server uses.
+    pub fn new(domain: impl Into<String>) -> Self {
+        Self {
+            domain: domain.into(),
+            ..Self::default()
+        }
+    }
+
+    /// The users and groups of this machine, from `/etc/passwd` and `/etc/group`.
+    pub fn from_system(domain: impl Into<String>) -> io::Result<Self> {
+        Self::from_files(domain, "/etc/passwd", "/etc/group")
+    }
+
+    pub fn from_files(
+        domain: impl Into<String>,
+        passwd: impl AsRef<Path>,
+        group: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        Ok(Self::new(domain)
+            .with_passwd(&std::fs::read_to_string(passwd)?)
+            .with_groups(&std::fs::read_to_string(group)?))
+    }
+
+    pub fn add_user(mut self, name: &str, uid: u32) -> Self {
+        self.users.insert(name, uid);
+        self
+    }
+
+    pub fn add_group(mut self, name: &str, gid: u32) -> Self {
+        self.groups.insert(name, gid);
+        self
+    }
+
+    /// Adds the users from the contents of a file in the format of `/etc/passwd`.
+    pub fn with_passwd(mut self, contents: &str) -> Self {
+        self.users.parse(contents);
+        self
+    }
+
+    /// Adds the groups from the contents of a file in the format of `/etc/group`.
+    pub fn with_groups(mut self, contents: &str) -> Self {
+        self.groups.parse(contents);
+        self
+    }
+
+    /// The local name in a `name@domain` string, if it is in our domain.
+    fn local_name<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match name.rsplit_once('@') {
+            Some((name, domain)) if domain.eq_ignore_ascii_case(&self.domain) => Some(name),
+            Some(_) => None,
+            None => Some(name),
+        }
+    }
+
+    fn id(&self, names: &Names, name: &str) -> Option<u32> {
+        if let Ok(id) = name.parse() {
+            return Some(id);
+        }
+        names.ids.get(self.local_name(name)?).copied()
+    }
+
+    fn name(&self, names: &Names, id: u32) -> String {
+        match names.names.get(&id) {
+            Some(name) => format!("{name}@{}", self.domain),
+            None => id.to_string(),
+        }
+    }
+}
+
+impl IdMapper for IdMap {
+    fn uid(&self, owner: &str) -> Option<u32> {
+        self.id(&self.users, owner)
+    }
+
+    fn gid(&self, group: &str) -> Option<u32> {
+        self.id(&self.groups, group)
+    }
+
+    fn owner(&self, uid: u32) -> String {
+        self.name(&self.users, uid)
+    }
+
+    fn group(&self, gid: u32) -> String {
+        self.name(&self.groups, gid)
+    }
+}
+
+#[test]
+fn map_ids() {
+    let map = IdMap::new("example.com")
+        .with_passwd(
+            "# comment\n\
+             root:x:0:0:root:/root:/bin/bash\n\
+             alice:x:1000:1000::/home/alice:/bin/sh\n\
+             broken\n",
+        )
+        .with_groups("root:x:0:\nstaff:x:50:alice\n");
+
+    assert_eq!(map.uid("alice@example.com"), Some(1000));
+    assert_eq!(map.uid("alice@EXAMPLE.COM"), Some(1000));
+    assert_eq!(map.uid("alice"), Some(1000));
+    assert_eq!(map.uid("alice@example.org"), None);
+    assert_eq!(map.uid("bob@example.com"), None);
+    assert_eq!(map.uid("1234"), Some(1234));
+    assert_eq!(map.gid("staff@example.com"), Some(50));
+
+    assert_eq!(map.owner(0), "root@example.com");
+    assert_eq!(map.owner(1234), "1234");
+    assert_eq!(map.group(50), "staff@example.com");
+}
@@ -1,13 +1,82 @@
 // Copyright 2023 Remi Bernotavicius
 
+use attr_cache::{AttrCache, CachedListing};
 use derive_more::From;
 use nfs4::*;
 use paste::paste;
 use rand::Rng as _;
-use std::collections::VecDeque;
-use std::io;
-use std::path::{Component, Path};
-use sun_rpc_client::{RpcClient, Transport};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::io::{self, Read as _, Write as _};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use sun_rpc_client::{
+    GssInitiator, GssService, GssSession, OpaqueAuth, ReadTimeout, RpcClient, Transport, Xid,
+};
+
+mod attr_cache;
+mod builder;
+mod callback;
+mod copy;
+mod dir_entry;
+mod exports;
+mod file;
+mod flex_files;
+mod idmap;
+mod metrics;
+mod name_cache;
+mod named_attr;
+mod path;
+mod pnfs;
+mod probe;
+mod rate_limit;
+mod reclaim;
+mod reconnect;
+mod referral;
+mod revoked;
+mod shared;
+mod shutdown;
+mod state_protect;
+mod stats;
+mod trace;
+mod trunking;
+mod url;
+mod v40;
+mod volatile;
+mod watch;
+
+pub use builder::ClientBuilder;
+use callback::{Callback, CallbackProgram};
+pub use dir_entry::DirEntryInfo;
+pub use file::File;
+pub use idmap::{IdMap, IdMapper, NumericIds};
+pub use metrics::ClientMetrics;
+use name_cache::NameCache;
+pub use named_attr::NamedAttrs;
+pub use path::{RemotePath, RemotePathBuf};
+use pnfs::{Connector, Pnfs};
+pub use probe::Probe;
+use rate_limit::RateLimited;
+pub use rate_limit::RateLimiter;
+use reconnect::{is_connection_broken, is_idempotent, Reconnector, MAX_RECONNECTIONS};
+pub use shared::SharedClient;
+pub use shutdown::ShutdownHandle;
+use state_protect::MachineCredential;
+pub use stats::ClientStats;
+use stats::Stats;
+use trace::Tracer;
+pub use trunking::Scheduling;
+pub use url::NfsUrl;
+use volatile::VolatileHandles;
+use watch::WatchState;
+pub use watch::{DirEvent, DirWatcher};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -40,20 +109,156 @@ impl<T> From<LockStatusResult<T>> for TempResult<T> {
     }
 }
 
+/// A status returned by the server, along with the operation which failed and the path it was
+/// used on when that is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NfsError {
+    pub status: StatusError,
+    pub op: Option<OperationId>,
+    pub path: Option<RemotePathBuf>,
+}
+
+impl fmt::Display for NfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.status)?;
+        if let Some(op) = &self.op {
+            write!(f, " from {op:?}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, " on {path}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<StatusError> for NfsError {
+    fn from(status: StatusError) -> Self {
+        Self {
+            status,
+            op: None,
+            path: None,
+        }
+    }
+}
+
 #[derive(Debug, From)]
 pub enum Error {
     SunRpc(sun_rpc_client::Error),
-    Protocol(StatusError),
+    Protocol(NfsError),
     Lock(LockStatusError),
     Io(std::io::Error),
     #[from(ignore)]
     CompoundResponseMismatch(String),
+    /// The connection to the server broke while we were sending it the given operation, which
+    /// isn't safe to send twice. We reconnected, but whether the server did it is unknown.
+    #[from(ignore)]
+    ConnectionLost(OperationId),
+}
+
+impl From<StatusError> for Error {
+    fn from(status: StatusError) -> Self {
+        Self::Protocol(status.into())
+    }
+}
+
+impl Error {
+    /// The status the server returned, if this error came from the server.
+    pub fn status(&self) -> Option<StatusError> {
+        match self {
+            Self::Protocol(e) => Some(e.status),
+            Self::Lock(e) => Some(e.error),
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status().is_some_and(|s| s.is_not_found())
+    }
+
+    pub fn is_permission_denied(&self) -> bool {
+        self.status().is_some_and(|s| s.is_permission_denied())
+    }
+
+    pub fn is_already_exists(&self) -> bool {
+        self.status().is_some_and(|s| s.is_already_exists())
+    }
+
+    pub fn is_not_a_directory(&self) -> bool {
+        self.status().is_some_and(|s| s.is_not_a_directory())
+    }
+
+    pub fn is_directory_not_empty(&self) -> bool {
+        self.status().is_some_and(|s| s.is_directory_not_empty())
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.status().is_some_and(|s| s.is_stale())
+    }
+
+    /// Whether the server didn't answer in time, see `Client::set_timeout`.
+    pub fn is_timed_out(&self) -> bool {
+        let io_error = match self {
+            Self::SunRpc(sun_rpc_client::Error::Io(e)) | Self::Io(e) => e,
+            _ => return false,
+        };
+        io_error.kind() == std::io::ErrorKind::TimedOut
+    }
+
+    fn with_op(mut self, op: OperationId) -> Self {
+        if let Self::Protocol(e) = &mut self {
+            e.op.get_or_insert(op);
+        }
+        self
+    }
+
+    fn with_path(mut self, path: impl AsRef<RemotePath>) -> Self {
+        if let Self::Protocol(e) = &mut self {
+            e.path.get_or_insert_with(|| path.as_ref().to_owned());
+        }
+        self
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = if error.is_not_found() {
+            io::ErrorKind::NotFound
+        } else if error.is_permission_denied() {
+            io::ErrorKind::PermissionDenied
+        } else if error.is_already_exists() {
+            io::ErrorKind::AlreadyExists
+        } else {
+            io::ErrorKind::Other
+        };
+        match error {
+            Error::Io(e) => e,
+            e => io::Error::new(kind, format!("{e:?}")),
+        }
+    }
 }
 
 const NFS: u32 = 100003;
 const NFS_CB: u32 = 0x40000000;
 pub const NFS_PORT: u16 = 2049;
 const COMPOUND_PROCEDURE: u32 = 1;
+const DEFAULT_READ_PIPELINE_DEPTH: usize = 8;
+const DEFAULT_WRITE_PIPELINE_DEPTH: usize = 8;
+
+// How long to keep retrying requests the server answers with DELAY or GRACE. Servers commonly use
+// a 90 second grace period after restarting.
+const DEFAULT_RETRY_DEADLINE: Duration = Duration::from_secs(120);
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// Room left in each request and response for the RPC header and the other operations when sizing
+// READs and WRITEs to fit within the session's limits
+const COMPOUND_OVERHEAD: u32 = 1024;
+
+// How many chunks of unstable writes to keep around for resending before forcing a COMMIT
+const MAX_UNCOMMITTED_WRITE_CHUNKS: usize = 64;
+
+// How many times `commit` sends unstable writes again after the server lost them before giving up
+const MAX_WRITE_RESENDS: usize = 3;
 
 macro_rules! compound_op_impl_ {
     ($name:ident, $args:ident, $res:ty) => {
@@ -74,7 +279,9 @@ macro_rules! compound_op_impl_ {
                     .ok_or(Error::CompoundResponseMismatch("too few replies".into()))?;
 
                 if let ResOp::$name(res) = op {
-                    TempResult::from(res).0
+                    TempResult::from(res)
+                        .0
+                        .map_err(|e| e.with_op(OperationId::$name))
                 } else {
                     Err(Error::CompoundResponseMismatch(format!(
                         "expected {}, got {op:?}",
@@ -83,6 +290,14 @@ macro_rules! compound_op_impl_ {
                 }
             }
         }
+
+        impl Operation for $args {
+            type Response = $res;
+
+            fn process_reply(res: ResOp) -> Result<Self::Response> {
+                <Self as CompoundRequest>::process_reply(&mut VecDeque::from([res]), ())
+            }
+        }
     };
 }
 
@@ -129,6 +344,7 @@ macro_rules! compound_op_impl_no_ret {
 }
 
 compound_op_impl! {
+    Access
     Close
     Commit
     Create
@@ -137,6 +353,7 @@ compound_op_impl! {
     Lock
     LockU
     Open
+    OpenConfirm
     OpenDowngrade
     Read
     ReadDir
@@ -144,6 +361,7 @@ compound_op_impl! {
     Rename
     SecInfo
     SetAttr
+    SetClientId
     Write
     BindConnToSession
     ExchangeId
@@ -159,6 +377,9 @@ compound_op_impl! {
     SetSsv
     TestStateId
     WantDelegation
+    Copy
+    CopyNotify
+    OffloadStatus
 }
 
 compound_op_impl_no_ret! {
@@ -169,12 +390,16 @@ compound_op_impl_no_ret! {
     NVerify
     OpenAttr
     PutFh
+    SetClientIdConfirm
     Verify
     BackchannelCtl
     DestroySession
     FreeStateid
     DestroyClientId
     ReclaimComplete
+    LayoutError
+    LayoutStats
+    OffloadCancel
 }
 
 compound_op_impl_no_args! {
@@ -310,365 +535,4271 @@ tuple_impls! {
     (0 A 1 B 2 C 3 D 4 E 5 F 6 G 7 H 8 I 9 J 10 K 11 L 12 M 13 N 14 O 15 P 16 Q)
 }
 
-struct ClientWithoutSession<TransportT> {
-    rpc_client: RpcClient<TransportT>,
+/// An operation which can be added to a `CompoundBuilder`.
+pub trait Operation: Into<ArgOp> {
+    type Response;
+
+    /// Extracts the result of this operation from its reply.
+    fn process_reply(res: ResOp) -> Result<Self::Response>;
 }
 
-impl<TransportT: Transport> ClientWithoutSession<TransportT> {
-    fn new(rpc_client: RpcClient<TransportT>) -> Self {
-        Self { rpc_client }
+/// Identifies an operation added to a `CompoundBuilder`, to get its result out of the reply.
+pub struct OpIndex<Op> {
+    index: usize,
+    op: PhantomData<Op>,
+}
+
+impl<Op> OpIndex<Op> {
+    pub fn index(&self) -> usize {
+        self.index
     }
+}
 
-    fn do_compound<Args>(&mut self, args: Args) -> Result<Args::Response>
-    where
-        Args: CompoundRequest,
-    {
-        let (arg_array, geometry) = args.into_arg_array();
-        let call_args = CompoundArgs {
-            tag: "Test Client".into(),
-            minor_version: 1,
-            arg_array,
-        };
+impl<Op> Clone for OpIndex<Op> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
 
-        self.rpc_client
-            .send_request(COMPOUND_PROCEDURE, call_args)?;
+impl<Op> Copy for OpIndex<Op> {}
 
-        let compound_reply: CompoundRes = self.rpc_client.receive_reply()?;
+/// Assembles a COMPOUND out of any sequence of operations, for when none of the `Client` methods
+/// do what is needed in a single round trip. Send it with `Client::send_compound`.
+#[derive(Clone, Debug, Default)]
+pub struct CompoundBuilder {
+    ops: Vec<ArgOp>,
+}
 
-        if let StatusResult::Err(e) = compound_reply.status {
-            return Err(e.into());
+impl CompoundBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the given operation, returning what to pass to `CompoundReply::get` for its result.
+    pub fn push<Op: Operation>(&mut self, op: Op) -> OpIndex<Op> {
+        self.ops.push(op.into());
+        OpIndex {
+            index: self.ops.len() - 1,
+            op: PhantomData,
         }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
 
-        let mut res_array = compound_reply.res_array.into_iter().collect();
-        let reply = Args::process_reply(&mut res_array, geometry)?;
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// The reply to a `CompoundBuilder`. The server stops at the first operation which fails, so
+/// there are results for it and the ones before it only.
+#[derive(Clone, Debug)]
+pub struct CompoundReply {
+    status: StatusResult<()>,
+    results: Vec<ResOp>,
+}
 
-        if !res_array.is_empty() {
-            return Err(Error::CompoundResponseMismatch(format!(
-                "trailing response: {res_array:?}"
-            )));
+impl CompoundReply {
+    /// The status of the operation which failed, if any did.
+    pub fn status(&self) -> Option<StatusError> {
+        match self.status {
+            StatusResult::Ok(()) => None,
+            StatusResult::Err(e) => Some(e),
         }
+    }
 
-        Ok(reply)
+    /// Which operation failed, if any did.
+    pub fn failed_index(&self) -> Option<usize> {
+        self.status().map(|_| self.results.len().saturating_sub(1))
     }
-}
 
-fn random_client_owner() -> ClientOwner {
-    let mut rng = rand::thread_rng();
-    ClientOwner {
-        verifier: Verifier(0x0),
-        owner_id: rng.gen::<u64>().to_be_bytes().into(),
+    /// The result of the given operation, or `None` if the server never got to it because an
+    /// earlier one failed.
+    pub fn get<Op: Operation>(&self, op: OpIndex<Op>) -> Option<Result<Op::Response>> {
+        let res = self.results.get(op.index)?;
+        Some(Op::process_reply(res.clone()))
+    }
+
+    /// The raw results of the operations which were executed.
+    pub fn results(&self) -> &[ResOp] {
+        &self.results
     }
 }
 
-pub struct Client<TransportT> {
-    raw_client: ClientWithoutSession<TransportT>,
-    session: CreateSessionRes,
-    sequence_id: SequenceId,
-    client_id: ClientId,
-    client_owner: ClientOwner,
-    max_read: u64,
-    max_write: u64,
-    supported_attrs: EnumSet<FileAttributeId>,
+// The newest minor version of NFSv4 we speak, we settle on an older one if the server doesn't
+const MAX_MINOR_VERSION: u32 = 2;
+
+struct ClientWithoutSession<TransportT> {
+    rpc_client: RpcClient<TransportT>,
+    minor_version: u32,
+    // The oldest minor version we settle on if the server doesn't speak the one we ask for
+    min_minor_version: u32,
+    tracer: Tracer,
+    // How requests are translated when we speak minor version 0
+    v40: Option<v40::Translator>,
+    // Whether to ask for SP4_MACH_CRED state protection with EXCHANGE_ID
+    protect_state: bool,
+    machine_credential: Option<MachineCredential>,
+    // Whether a reply told us some of our state may have been revoked
+    revoked: bool,
 }
 
-impl<TransportT: Transport> Client<TransportT> {
-    pub fn new(transport: TransportT) -> Result<Self> {
-        let mut raw_client = ClientWithoutSession::new(RpcClient::new(transport, NFS));
+impl<TransportT: Transport> ClientWithoutSession<TransportT> {
+    fn new(rpc_client: RpcClient<TransportT>) -> Self {
+        Self {
+            rpc_client,
+            minor_version: MAX_MINOR_VERSION,
+            min_minor_version: 0,
+            tracer: Tracer::default(),
+            v40: None,
+            protect_state: false,
+            machine_credential: None,
+            revoked: false,
+        }
+    }
+
+    fn send_compound<Args>(&mut self, args: Args) -> Result<(Xid, Args::Geometry)>
+    where
+        Args: CompoundRequest,
+    {
+        let (arg_array, geometry) = args.into_arg_array();
+        let xid = self.send_arg_array(arg_array)?;
+        Ok((xid, geometry))
+    }
 
-        let client_owner = random_client_owner();
-        let eid_res = raw_client.do_compound(ExchangeIdArgs {
+    /// The COMPOUND to send for the operations, failing if any of them is newer than the minor
+    /// version we speak.
+    fn compound_args(&mut self, arg_array: Vec<ArgOp>) -> Result<CompoundArgs> {
+        let arg_array = match &mut self.v40 {
+            Some(v40) => v40.translate(arg_array),
+            None => arg_array,
+        };
+        let newer = arg_array
+            .iter()
+            .map(|op| op.to_id())
+            .find(|id| id.minor_version() > self.minor_version);
+        if let Some(id) = newer {
+            return Err(Error::from(StatusError::NotSupported).with_op(id));
+        }
+        Ok(CompoundArgs {
+            tag: "Test Client".into(),
+            minor_version: self.minor_version,
+            arg_array,
+        })
+    }
+
+    fn exchange_id_args(&self, client_owner: &ClientOwner) -> ExchangeIdArgs {
+        ExchangeIdArgs {
             client_owner: client_owner.clone(),
             flags: ExchangeIdFlags::empty(),
-            state_protect: StateProtect::None,
-            client_impl_id: None,
-        })?;
-
-        let client_id = eid_res.client_id;
-        let session = raw_client.do_compound(CreateSessionArgs {
-            client_id,
-            sequence_id: SequenceId(1),
-            flags: CreateSessionFlags::empty(),
-            fore_channel_attrs: ChannelAttrs {
-                header_pad_size: 0,
-                max_request_size: 1049620,
-                max_response_size: 1049480,
-                max_response_size_cached: 7584,
-                max_operations: 16,
-                max_requests: 64,
-                rdma_ird: None,
-            },
-            back_channel_attrs: ChannelAttrs {
-                header_pad_size: 0,
-                max_request_size: 4096,
-                max_response_size: 4096,
-                max_response_size_cached: 0,
-                max_operations: 16,
-                max_requests: 16,
-                rdma_ird: None,
+            state_protect: match self.protect_state {
+                true => state_protect::requested(),
+                false => StateProtect::None,
             },
-            program: NFS_CB,
-            security_parameters: vec![],
-        })?;
+            client_impl_id: None,
+        }
+    }
 
-        let mut client = Self {
-            raw_client,
-            session,
-            sequence_id: SequenceId(1),
-            client_id,
-            client_owner,
-            max_read: 0,
-            max_write: 0,
-            supported_attrs: Default::default(),
+    /// Sends the request with the machine credential if the server only takes it from that,
+    /// otherwise with whatever credential we use.
+    fn send_protected(
+        &mut self,
+        call_args: &CompoundArgs,
+        send: impl FnOnce(&mut RpcClient<TransportT>) -> sun_rpc_client::Result<Xid>,
+    ) -> Result<Xid> {
+        let machine = self
+            .machine_credential
+            .as_ref()
+            .and_then(|m| m.for_request(&call_args.arg_array))
+            .filter(|credential| {
+                self.rpc_client.gss().is_some() || *credential != self.rpc_client.credential()
+            })
+            .cloned();
+        let Some(machine) = machine else {
+            return Ok(send(&mut self.rpc_client)?);
         };
+        let credential = self.rpc_client.credential().clone();
+        let gss = self.rpc_client.gss().cloned();
+        self.rpc_client.set_credential(machine);
+        self.rpc_client.set_gss(None);
+        let xid = send(&mut self.rpc_client);
+        self.rpc_client.set_credential(credential);
+        self.rpc_client.set_gss(gss);
+        Ok(xid?)
+    }
 
-        let mut root_attrs = client
-            .do_compound(ReturnSecond(
-                (ReclaimCompleteArgs { one_fs: false }, PutRootFh),
-                GetAttrArgs {
-                    attr_request: [
-                        FileAttributeId::SupportedAttrs,
-                        FileAttributeId::MaxRead,
-                        FileAttributeId::MaxWrite,
-                    ]
-                    .into_iter()
-                    .collect(),
-                },
-            ))?
-            .object_attributes;
+    fn sent(&mut self, xid: &Xid, call_args: &CompoundArgs) {
+        self.tracer.sent(xid, call_args);
+        if let Some(v40) = &mut self.v40 {
+            v40.sent(xid);
+        }
+    }
 
-        client.supported_attrs = root_attrs
-            .remove_as(FileAttributeId::SupportedAttrs)
-            .unwrap();
-        client.max_read = *root_attrs.get_as(FileAttributeId::MaxRead).unwrap();
-        client.max_write = *root_attrs.get_as(FileAttributeId::MaxWrite).unwrap();
+    fn send_arg_array(&mut self, arg_array: Vec<ArgOp>) -> Result<Xid> {
+        let call_args = self.compound_args(arg_array)?;
+        let xid = self.send_protected(&call_args, |rpc_client| {
+            rpc_client.send_request(COMPOUND_PROCEDURE, &call_args)
+        })?;
+        self.sent(&xid, &call_args);
+        Ok(xid)
+    }
+
+    /// Like `send_arg_array`, with the data of the WRITE at the end streamed from the source.
+    fn send_arg_array_streaming(
+        &mut self,
+        arg_array: Vec<ArgOp>,
+        source: impl io::Read,
+        len: u32,
+    ) -> Result<Xid> {
+        let call_args = self.compound_args(arg_array)?;
+        let xid = self.send_protected(&call_args, |rpc_client| {
+            rpc_client.send_request_streaming(COMPOUND_PROCEDURE, &call_args, source, len)
+        })?;
+        self.sent(&xid, &call_args);
+        Ok(xid)
+    }
 
-        Ok(client)
+    fn receive_compound(&mut self) -> Result<(Xid, CompoundRes)> {
+        let (xid, reply) = self.rpc_client.receive_reply_with_xid()?;
+        self.tracer.received(&xid, &reply);
+        self.revoked |= revoked::reports_revoked(&reply);
+        // Anything the translation sends of its own goes untranslated
+        let Some(mut v40) = self.v40.take() else {
+            return Ok((xid, reply));
+        };
+        let reply = v40.translate_reply(self, &xid, reply);
+        self.v40 = Some(v40);
+        Ok((xid, reply?))
     }
 
     fn do_compound<Args>(&mut self, args: Args) -> Result<Args::Response>
     where
         Args: CompoundRequest,
     {
-        let sequence = SequenceArgs {
-            session_id: self.session.session_id,
-            sequence_id: self.sequence_id,
-            slot_id: SlotId(0),
-            highest_slot_id: SlotId(0),
-            cache_this: false,
+        let (_, geometry) = self.send_compound(args)?;
+        let (_, compound_reply) = self.receive_compound()?;
+        process_compound_reply::<Args>(compound_reply, geometry)
+    }
+}
+
+fn process_compound_reply<Args>(
+    compound_reply: CompoundRes,
+    geometry: Args::Geometry,
+) -> Result<Args::Response>
+where
+    Args: CompoundRequest,
+{
+    let mut res_array = compound_reply.res_array.into_iter().collect();
+    if let StatusResult::Err(e) = compound_reply.status {
+        // Processing the replies stops at the failing operation, which tells us which one it was
+        return match Args::process_reply(&mut res_array, geometry) {
+            Err(e @ (Error::Protocol(_) | Error::Lock(_))) => Err(e),
+            _ => Err(e.into()),
         };
+    }
 
-        self.sequence_id.incr();
+    let reply = Args::process_reply(&mut res_array, geometry)?;
 
-        self.raw_client.do_compound(ReturnSecond(sequence, args))
+    if !res_array.is_empty() {
+        return Err(Error::CompoundResponseMismatch(format!(
+            "trailing response: {res_array:?}"
+        )));
     }
 
-    pub fn get_attr(&mut self, handle: FileHandle) -> Result<GetAttrRes> {
-        let mut supported_attrs = self.supported_attrs.clone();
+    Ok(reply)
+}
 
-        supported_attrs.remove(FileAttributeId::TimeAccessSet);
-        supported_attrs.remove(FileAttributeId::TimeModifySet);
+// How many times in a row we re-establish the session before giving up on a request
+const MAX_SESSION_RECOVERIES: usize = 3;
 
-        self.do_compound(ReturnSecond(
-            PutFhArgs { object: handle },
-            GetAttrArgs {
-                attr_request: supported_attrs,
-            },
-        ))
+/// Makes the security context for an RPCSEC_GSS flavor the server lists, if we can, see
+/// `Client::set_gss`.
+pub(crate) type GssFactory = Box<dyn FnMut(&RpcSecGssInfo) -> Option<Box<dyn GssInitiator>> + Send>;
+
+/// The credential to use for the given security flavor, if it needs no security context. The
+/// RPCSEC_GSS ones do, see `Client::set_gss`.
+fn credential_for(flavor: &SecurityInfo) -> Option<OpaqueAuth> {
+    match flavor {
+        SecurityInfo::None => Some(OpaqueAuth::none()),
+        SecurityInfo::Sys => Some(sun_rpc_client::default_credential()),
+        SecurityInfo::RpcSecGss { .. } => None,
     }
+}
 
-    pub fn look_up(&mut self, path: impl AsRef<Path>) -> Result<FileHandle> {
-        Ok(self
-            .do_compound(ReturnSecond(
-                (
-                    PutRootFh,
-                    Vec::from_iter(path.as_ref().components().filter_map(|c| match c {
-                        Component::Normal(p) => Some(LookUpArgs {
-                            object_name: p.to_str().unwrap().into(),
-                        }),
-                        _ => None,
-                    })),
-                ),
-                GetFh,
-            ))?
-            .object)
+fn gss_service(service: &RpcGssService) -> GssService {
+    match service {
+        RpcGssService::None => GssService::None,
+        RpcGssService::Integrity => GssService::Integrity,
+        RpcGssService::Privacy => GssService::Privacy,
     }
+}
 
-    pub fn read(&mut self, handle: FileHandle, offset: u64, count: u32) -> Result<ReadRes> {
-        self.do_compound(ReturnSecond(
-            PutFhArgs { object: handle },
+/// The request asking the server for the security flavors of the object the operation with the
+/// given index failed on with WRONGSEC, going to it the same way. `None` if we can't get there
+/// without making or opening something.
+fn sec_info_request(arg_array: &[ArgOp], failed: usize) -> Option<Vec<ArgOp>> {
+    let failing = arg_array.get(failed)?;
+    let mut request = vec![];
+    for op in &arg_array[..failed] {
+        match op {
+            ArgOp::PutFh(_)
+            | ArgOp::PutRootFh
+            | ArgOp::PutPubFh
+            | ArgOp::LookUp(_)
+            | ArgOp::LookUpP
+            | ArgOp::SaveFh
+            | ArgOp::RestoreFh => request.push(op.clone()),
+            ArgOp::Create(_) | ArgOp::Open(_) | ArgOp::OpenAttr(_) => return None,
+            _ => {}
+        }
+    }
+    let current_fh = SecInfoNoNameArgs {
+        style: SecInfoStyle::CurrentFh,
+    };
+    let sec_info = match failing {
+        ArgOp::LookUp(args) => ArgOp::SecInfo(SecInfoArgs {
+            name: args.object_name.clone(),
+        }),
+        ArgOp::Open(OpenArgs {
+            claim: OpenClaim::Null { file },
+            ..
+        }) => ArgOp::SecInfo(SecInfoArgs { name: file.clone() }),
+        ArgOp::LookUpP => ArgOp::SecInfoNoName(SecInfoNoNameArgs {
+            style: SecInfoStyle::Parent,
+        }),
+        ArgOp::PutFh(_) | ArgOp::PutRootFh | ArgOp::PutPubFh | ArgOp::RestoreFh => {
+            request.push(failing.clone());
+            ArgOp::SecInfoNoName(current_fh)
+        }
+        _ => ArgOp::SecInfoNoName(current_fh),
+    };
+    // Without a current file handle there is nothing to ask about
+    if request.is_empty() {
+        return None;
+    }
+    request.push(sec_info);
+    Some(request)
+}
+
+/// Whether the server no longer knows our session or client ID, which happens when it restarts.
+fn is_session_lost(error: &Error) -> bool {
+    matches!(
+        error.status(),
+        Some(StatusError::BadSession | StatusError::DeadSession | StatusError::StaleClientId)
+    )
+}
+
+/// Whether the server asked us to try the request again later.
+fn is_transient(error: &Error) -> bool {
+    matches!(
+        error.status(),
+        Some(StatusError::Delay | StatusError::Grace)
+    )
+}
+
+/// Exponential backoff between retries of a request, up to a deadline.
+struct Backoff {
+    delay: Duration,
+    deadline: Instant,
+}
+
+impl Backoff {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            delay: INITIAL_RETRY_DELAY,
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    /// Sleeps before the next attempt. Returns false without sleeping if the attempt would start
+    /// after the deadline.
+    fn wait(&mut self) -> bool {
+        if Instant::now() + self.delay > self.deadline {
+            return false;
+        }
+        thread::sleep(self.delay);
+        self.delay = (self.delay * 2).min(MAX_RETRY_DELAY);
+        true
+    }
+}
+
+/// A stream of similar requests which `Client::run_pipeline` keeps several of in flight at once.
+trait Pipeline {
+    type Request: CompoundRequest;
+    type Tag;
+
+    fn next_request(&mut self) -> Result<Option<(Self::Request, Self::Tag)>>;
+
+    /// The file the requests are for, which is swapped for a new one if the server says it
+    /// expired.
+    fn handle_mut(&mut self) -> &mut FileHandle;
+
+    /// Called for a request which was lost along with the session or which the server asked us to
+    /// try again later, it should be returned again from `next_request`.
+    fn retry(&mut self, tag: Self::Tag);
+
+    fn handle_reply(
+        &mut self,
+        tag: Self::Tag,
+        reply: <Self::Request as CompoundRequest>::Response,
+    ) -> Result<()>;
+}
+
+struct ReadPipeline<SinkT> {
+    handle: FileHandle,
+    chunk_size: u32,
+    next_offset: u64,
+    retries: Vec<(u64, u32)>,
+    end: Option<u64>,
+    written: u64,
+    completed: BTreeMap<u64, Bytes>,
+    sink: SinkT,
+}
+
+impl<SinkT: io::Write> ReadPipeline<SinkT> {
+    fn new(handle: FileHandle, chunk_size: u32, sink: SinkT) -> Self {
+        Self {
+            handle,
+            chunk_size,
+            next_offset: 0,
+            retries: vec![],
+            end: None,
+            written: 0,
+            completed: BTreeMap::new(),
+            sink,
+        }
+    }
+}
+
+impl<SinkT: io::Write> Pipeline for ReadPipeline<SinkT> {
+    type Request = ReturnSecond<PutFhArgs, ReadArgs>;
+    type Tag = (u64, u32);
+
+    fn next_request(&mut self) -> Result<Option<(Self::Request, Self::Tag)>> {
+        let (offset, count) = if let Some(retry) = self.retries.pop() {
+            retry
+        } else if self.end.is_some_and(|end| self.next_offset >= end) {
+            return Ok(None);
+        } else {
+            let offset = self.next_offset;
+            self.next_offset += self.chunk_size as u64;
+            (offset, self.chunk_size)
+        };
+
+        let request = ReturnSecond(
+            PutFhArgs {
+                object: self.handle.clone(),
+            },
             ReadArgs {
                 state_id: StateId::anonymous(),
                 offset,
                 count,
             },
-        ))
+        );
+        Ok(Some((request, (offset, count))))
     }
 
-    pub fn read_all(&mut self, handle: FileHandle, mut sink: impl io::Write) -> Result<()> {
-        let mut offset = 0;
-        loop {
-            let read_res = self.read(handle.clone(), offset, self.max_read.try_into().unwrap())?;
-            offset += read_res.data.len() as u64;
-            sink.write_all(&read_res.data)?;
-            if read_res.eof {
+    fn handle_mut(&mut self) -> &mut FileHandle {
+        &mut self.handle
+    }
+
+    fn retry(&mut self, tag: Self::Tag) {
+        self.retries.push(tag);
+    }
+
+    fn handle_reply(&mut self, (offset, count): Self::Tag, mut reply: ReadRes) -> Result<()> {
+        // More than asked for would overlap the next chunk
+        reply.data.truncate(count as usize);
+        let len = reply.data.len() as u64;
+        if reply.eof || len == 0 {
+            let end = offset + len;
+            self.end = Some(self.end.map_or(end, |e| e.min(end)));
+        } else if len < count as u64 {
+            // short read, ask for the rest of the chunk again
+            self.retries.push((offset + len, count - len as u32));
+        }
+
+        self.completed.insert(offset, reply.data);
+        while let Some(data) = self.completed.remove(&self.written) {
+            if data.is_empty() {
                 break;
             }
+            self.sink.write_all(&data)?;
+            self.written += data.len() as u64;
         }
         Ok(())
     }
+}
 
-    pub fn write(&mut self, handle: FileHandle, offset: u64, data: Vec<u8>) -> Result<WriteRes> {
-        self.do_compound(ReturnSecond(
-            PutFhArgs { object: handle },
+struct UncommittedWrite {
+    data: Bytes,
+    verifier: Option<Verifier>,
+}
+
+struct WritePipeline<SourceT> {
+    handle: FileHandle,
+    chunk_size: usize,
+    stable: StableHow,
+    source: SourceT,
+    source_done: bool,
+    next_offset: u64,
+    uncommitted: BTreeMap<u64, UncommittedWrite>,
+    uncommitted_bytes: usize,
+    max_uncommitted_bytes: usize,
+    retries: Vec<u64>,
+    verifier: Option<Verifier>,
+}
+
+impl<SourceT: io::Read> WritePipeline<SourceT> {
+    fn new(handle: FileHandle, chunk_size: usize, source: SourceT) -> Self {
+        Self {
+            handle,
+            chunk_size,
+            stable: StableHow::Unstable,
+            source,
+            source_done: false,
+            next_offset: 0,
+            uncommitted: BTreeMap::new(),
+            uncommitted_bytes: 0,
+            max_uncommitted_bytes: chunk_size.saturating_mul(MAX_UNCOMMITTED_WRITE_CHUNKS),
+            retries: vec![],
+            verifier: None,
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.source_done && self.uncommitted.is_empty()
+    }
+
+    fn write_request(&self, offset: u64) -> ReturnSecond<PutFhArgs, WriteArgs> {
+        ReturnSecond(
+            PutFhArgs {
+                object: self.handle.clone(),
+            },
             WriteArgs {
                 state_id: StateId::anonymous(),
                 offset,
-                stable: StableHow::FileSync,
-                data,
+                stable: self.stable,
+                data: self.uncommitted[&offset].data.clone(),
             },
-        ))
+        )
     }
 
-    pub fn write_all(&mut self, handle: FileHandle, mut source: impl io::Read) -> Result<()> {
-        let mut offset = 0;
-        loop {
-            let mut buf = vec![0; self.max_write as usize];
-            let amount_read = source.read(&mut buf[..])?;
-            if amount_read == 0 {
-                break;
+    fn remove_uncommitted(&mut self, offset: u64) {
+        if let Some(write) = self.uncommitted.remove(&offset) {
+            self.uncommitted_bytes -= write.data.len();
+        }
+    }
+
+    /// Records the verifier the server returned. If it differs from the one we saw before the
+    /// server lost its unstable data (probably by rebooting) and everything not yet committed is
+    /// sent again.
+    fn update_verifier(&mut self, verifier: &Verifier) {
+        if self.verifier.as_ref() == Some(verifier) {
+            return;
+        }
+
+        for (offset, write) in &mut self.uncommitted {
+            if write.verifier.as_ref().is_some_and(|v| v != verifier) {
+                write.verifier = None;
+                self.retries.push(*offset);
+            }
+        }
+        self.verifier = Some(verifier.clone());
+    }
+
+    fn handle_commit(&mut self, commit_res: CommitRes) {
+        self.update_verifier(&commit_res.write_verifier);
+        let committed: Vec<u64> = self
+            .uncommitted
+            .iter()
+            .filter(|(_, w)| w.verifier.as_ref() == Some(&commit_res.write_verifier))
+            .map(|(offset, _)| *offset)
+            .collect();
+        for offset in committed {
+            self.remove_uncommitted(offset);
+        }
+    }
+}
+
+impl<SourceT: io::Read> Pipeline for WritePipeline<SourceT> {
+    type Request = ReturnSecond<PutFhArgs, WriteArgs>;
+    type Tag = u64;
+
+    fn next_request(&mut self) -> Result<Option<(Self::Request, Self::Tag)>> {
+        if let Some(offset) = self.retries.pop() {
+            return Ok(Some((self.write_request(offset), offset)));
+        }
+
+        if self.source_done || self.uncommitted_bytes >= self.max_uncommitted_bytes {
+            return Ok(None);
+        }
+
+        let mut data = Vec::with_capacity(self.chunk_size);
+        (&mut self.source)
+            .take(self.chunk_size as u64)
+            .read_to_end(&mut data)?;
+        if data.len() < self.chunk_size {
+            self.source_done = true;
+        }
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let offset = self.next_offset;
+        self.next_offset += data.len() as u64;
+        self.uncommitted_bytes += data.len();
+        self.uncommitted.insert(
+            offset,
+            UncommittedWrite {
+                data: data.into(),
+                verifier: None,
+            },
+        );
+
+        Ok(Some((self.write_request(offset), offset)))
+    }
+
+    fn handle_mut(&mut self) -> &mut FileHandle {
+        &mut self.handle
+    }
+
+    fn retry(&mut self, offset: u64) {
+        self.retries.push(offset);
+    }
+
+    fn handle_reply(&mut self, offset: u64, reply: WriteRes) -> Result<()> {
+        let write = self.uncommitted.get_mut(&offset).unwrap();
+        let count = reply.count as usize;
+        if count == 0 && !write.data.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+
+        if count < write.data.len() {
+            // short write, send the rest again as its own chunk
+            let rest = write.data.split_off(count);
+            let rest_offset = offset + count as u64;
+            self.uncommitted.insert(
+                rest_offset,
+                UncommittedWrite {
+                    data: rest,
+                    verifier: None,
+                },
+            );
+            self.retries.push(rest_offset);
+        }
+
+        if reply.committed == StableHow::Unstable {
+            self.uncommitted.get_mut(&offset).unwrap().verifier =
+                Some(reply.write_veritifer.clone());
+            self.update_verifier(&reply.write_veritifer);
+        } else {
+            self.remove_uncommitted(offset);
+        }
+        Ok(())
+    }
+}
+
+/// Data written with `Client::write_at_with_stability` which the server hasn't committed yet,
+/// kept for sending again if it loses it.
+struct UnstableWrite {
+    offset: u64,
+    data: Bytes,
+    verifier: Verifier,
+}
+
+impl UnstableWrite {
+    fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+fn look_up_args(path: impl AsRef<RemotePath>) -> Vec<LookUpArgs> {
+    path.as_ref()
+        .components()
+        .map(|c| LookUpArgs {
+            object_name: c.into(),
+        })
+        .collect()
+}
+
+fn random_client_owner() -> ClientOwner {
+    let mut rng = rand::thread_rng();
+    ClientOwner {
+        verifier: Verifier(0x0),
+        owner_id: rng.gen::<u64>().to_be_bytes().into(),
+    }
+}
+
+fn fore_channel_attrs() -> ChannelAttrs {
+    ChannelAttrs {
+        header_pad_size: 0,
+        max_request_size: 1049620,
+        max_response_size: 1049480,
+        max_response_size_cached: 7584,
+        max_operations: 16,
+        max_requests: 64,
+        rdma_ird: None,
+    }
+}
+
+fn back_channel_attrs() -> ChannelAttrs {
+    ChannelAttrs {
+        header_pad_size: 0,
+        max_request_size: 4096,
+        max_response_size: 4096,
+        max_response_size_cached: 0,
+        max_operations: 16,
+        max_requests: 16,
+        rdma_ird: None,
+    }
+}
+
+fn establish_session<TransportT: Transport>(
+    raw_client: &mut ClientWithoutSession<TransportT>,
+    client_owner: &ClientOwner,
+) -> Result<(ClientId, ServerOwner, CreateSessionRes)> {
+    let eid_res = loop {
+        if raw_client.minor_version == 0 {
+            return v40::establish_client_id(raw_client, client_owner);
+        }
+        let res = raw_client.do_compound(raw_client.exchange_id_args(client_owner));
+        match res {
+            Err(e)
+                if e.status() == Some(StatusError::MinorVersMismatch)
+                    && raw_client.minor_version > raw_client.min_minor_version =>
+            {
+                raw_client.minor_version -= 1;
             }
+            res => break res?,
+        }
+    };
+
+    // Once there is a machine credential, EXCHANGE_ID went with it
+    let credential = match &raw_client.machine_credential {
+        Some(machine) => machine.credential(),
+        None => raw_client.rpc_client.credential(),
+    };
+    raw_client.machine_credential =
+        MachineCredential::new(&eid_res.state_protect, credential.clone());
+
+    let client_id = eid_res.client_id;
+    let session = raw_client.do_compound(CreateSessionArgs {
+        client_id,
+        sequence_id: eid_res.sequence_id,
+        flags: CreateSessionFlags::CONN_BACK_CHAN,
+        fore_channel_attrs: fore_channel_attrs(),
+        back_channel_attrs: back_channel_attrs(),
+        program: NFS_CB,
+        security_parameters: vec![CallbackSecurityParameters::None],
+    })?;
+
+    Ok((client_id, eid_res.server_owner, session))
+}
 
-            buf.resize(amount_read, 0);
+fn session_slots(session: &CreateSessionRes) -> Vec<SequenceId> {
+    let num_slots = session.fore_channel_attrs.max_requests.max(1) as usize;
+    vec![SequenceId(1); num_slots]
+}
+
+// The server keeps a single open per file for each open-owner, each OPEN of a file we already
+// have open upgrades it and bumps the sequence ID of its state ID. We only CLOSE it once every
+// `OpenFile` for it is gone, using the newest state ID. The modes are what it is reclaimed with
+// if the server restarts.
+struct OpenState {
+    state_id: StateId,
+    count: usize,
+    access: ShareAccess,
+    deny: ShareDeny,
+}
+
+// Files larger than this aren't cached while we hold a delegation for them
+const MAX_CACHED_FILE_SIZE: usize = 1024 * 1024;
+
+// What we keep about a file while we hold a read delegation for it, which means nobody else can
+// change it until we give the delegation back.
+struct Delegation {
+    state_id: StateId,
+    attrs: Option<GetAttrRes>,
+    contents: Option<Bytes>,
+}
+
+// Passes what is written on to `sink`, keeping a copy unless it gets too large to cache
+struct Tee<SinkT> {
+    sink: SinkT,
+    copy: Option<Vec<u8>>,
+}
 
-            while !buf.is_empty() {
-                let write_res = self.write(handle.clone(), offset, buf.clone())?;
-                buf = buf[write_res.count as usize..].to_owned();
+impl<SinkT: io::Write> io::Write for Tee<SinkT> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            if copy.len() + written > MAX_CACHED_FILE_SIZE {
+                self.copy = None;
+            } else {
+                copy.extend_from_slice(&buf[..written]);
             }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+// The part of the client which is shared with the lease renewal thread and open files
+struct Connection<TransportT> {
+    raw_client: ClientWithoutSession<TransportT>,
+    client_id: ClientId,
+    session_id: SessionId,
+    slots: Vec<SequenceId>,
+    last_sequence: Instant,
+    opens: BTreeMap<Bytes, OpenState>,
+    delegations: BTreeMap<Bytes, Delegation>,
+    callbacks: mpsc::Receiver<Callback>,
+    watches: BTreeMap<Bytes, WatchState>,
+    server_owner: ServerOwner,
+    // More connections the session is trunked over, only used for pipelined requests
+    trunks: Vec<ClientWithoutSession<TransportT>>,
+    scheduling: Scheduling,
+    next_connection: usize,
+    // How copies the server did in the background turned out, by the state ID of the copy
+    offloads: BTreeMap<[u8; 12], OffloadInfo>,
+    // The opens we reclaimed after the server restarted, by the state ID they had before
+    reclaimed: BTreeMap<[u8; 12], StateId>,
+}
+
+impl<TransportT: Transport> Connection<TransportT> {
+    /// Creates a client ID and session with the server on the other end of the transport, serving
+    /// the callback program on its backchannel. Requests are sent with the given credential, in
+    /// the newest of the given minor versions the server speaks. The state made with it can be
+    /// protected with SP4_MACH_CRED.
+    fn establish(
+        transport: TransportT,
+        client_owner: &ClientOwner,
+        credential: OpaqueAuth,
+        minor_versions: RangeInclusive<u32>,
+        protect_state: bool,
+    ) -> Result<(Self, ClientId, CreateSessionRes)> {
+        let (callbacks_sender, callbacks) = mpsc::channel();
+        let mut rpc_client = RpcClient::new(transport, NFS);
+        rpc_client.set_credential(credential);
+        rpc_client.serve(CallbackProgram::new(callbacks_sender));
+        let mut raw_client = ClientWithoutSession::new(rpc_client);
+        raw_client.minor_version = *minor_versions.end();
+        raw_client.min_minor_version = *minor_versions.start();
+        raw_client.protect_state = protect_state;
+
+        let (client_id, server_owner, session) = establish_session(&mut raw_client, client_owner)?;
+        let connection = Self {
+            raw_client,
+            client_id,
+            session_id: session.session_id,
+            slots: session_slots(&session),
+            last_sequence: Instant::now(),
+            opens: BTreeMap::new(),
+            delegations: BTreeMap::new(),
+            callbacks,
+            watches: BTreeMap::new(),
+            server_owner,
+            trunks: vec![],
+            scheduling: Scheduling::default(),
+            next_connection: 0,
+            offloads: BTreeMap::new(),
+            reclaimed: BTreeMap::new(),
+        };
+        Ok((connection, client_id, session))
+    }
+
+    /// Sends requests with the given credential, or RPCSEC_GSS context, over every connection
+    /// from now on.
+    fn set_security(&mut self, credential: OpaqueAuth, gss: Option<Arc<GssSession>>) {
+        for raw_client in std::iter::once(&mut self.raw_client).chain(&mut self.trunks) {
+            raw_client.rpc_client.set_credential(credential.clone());
+            raw_client.rpc_client.set_gss(gss.clone());
+        }
+    }
 
-            offset += amount_read as u64;
+    /// The SEQUENCE for the next request on the slot. The slot only moves on to the request after
+    /// that once this one has been sent, see `sent`.
+    fn sequence_args(&self, slot_id: SlotId) -> SequenceArgs {
+        SequenceArgs {
+            session_id: self.session_id,
+            sequence_id: self.slots[slot_id.0 as usize],
+            slot_id,
+            highest_slot_id: SlotId(self.slots.len() as u32 - 1),
+            cache_this: false,
         }
+    }
+
+    /// Moves the slot on to its next request once the one from `sequence_args` has been sent. A
+    /// request which couldn't be sent never reached the server, so sending another one with the
+    /// same sequence ID in its place isn't taken as a retry of it.
+    fn sent(&mut self, slot_id: SlotId) {
+        self.slots[slot_id.0 as usize].incr();
+        self.last_sequence = Instant::now();
+    }
+
+    fn do_compound<Args>(&mut self, args: Args) -> Result<Args::Response>
+    where
+        Args: CompoundRequest,
+    {
+        let sequence = self.sequence_args(SlotId(0));
+        let (_, geometry) = self
+            .raw_client
+            .send_compound(ReturnSecond(sequence, args))?;
+        self.sent(SlotId(0));
+        let (_, compound_reply) = self.raw_client.receive_compound()?;
+        process_compound_reply::<ReturnSecond<SequenceArgs, Args>>(compound_reply, geometry)
+    }
+
+    /// Like `do_compound`, but leaves processing the reply to the caller.
+    fn do_arg_array(&mut self, arg_array: Vec<ArgOp>) -> Result<CompoundRes> {
+        let (mut sequenced, ()) = self.sequence_args(SlotId(0)).into_arg_array();
+        sequenced.extend(arg_array);
+        self.raw_client.send_arg_array(sequenced)?;
+        self.sent(SlotId(0));
+        let (_, compound_reply) = self.raw_client.receive_compound()?;
+        self.handle_callbacks();
+        Ok(compound_reply)
+    }
+
+    /// Like `do_arg_array`, for a request ending in a WRITE whose data comes from the source.
+    fn do_arg_array_streaming(
+        &mut self,
+        arg_array: Vec<ArgOp>,
+        source: impl io::Read,
+        len: u32,
+    ) -> Result<CompoundRes> {
+        let (mut sequenced, ()) = self.sequence_args(SlotId(0)).into_arg_array();
+        sequenced.extend(arg_array);
+        self.raw_client
+            .send_arg_array_streaming(sequenced, source, len)?;
+        self.sent(SlotId(0));
+        let (_, compound_reply) = self.raw_client.receive_compound()?;
+        self.handle_callbacks();
+        Ok(compound_reply)
+    }
+
+    fn renew_lease(&mut self) -> Result<()> {
+        let sequence = self.sequence_args(SlotId(0));
+        let (_, geometry) = self.raw_client.send_compound(sequence)?;
+        self.sent(SlotId(0));
+        let (_, compound_reply) = self.raw_client.receive_compound()?;
+        process_compound_reply::<SequenceArgs>(compound_reply, geometry)?;
+        self.handle_callbacks();
         Ok(())
     }
 
-    pub fn create_file(&mut self, parent: FileHandle, name: &str) -> Result<FileHandle> {
-        Ok(self
-            .do_compound(ReturnSecond(
-                (
-                    PutFhArgs { object: parent },
-                    OpenArgs {
-                        sequence_id: SequenceId(0),
-                        share_access: ShareAccess::WRITE,
-                        share_deny: ShareDeny::NONE,
-                        owner: StateOwner {
-                            client_id: self.client_id,
-                            opaque: self.client_owner.owner_id.clone(),
-                        },
-                        open_how: OpenFlag::OpenCreate(CreateHow::Exclusive {
-                            create_verifier: Verifier(0),
-                        }),
-                        claim: OpenClaim::Null { file: name.into() },
-                    },
-                ),
-                GetFh,
-            ))?
-            .object)
+    /// Deals with what the server asked of us while we were waiting for replies. Recalled
+    /// delegations are given back on a best effort basis, if that fails the server revokes them
+    /// after a while anyway.
+    fn handle_callbacks(&mut self) {
+        while let Ok(callback) = self.callbacks.try_recv() {
+            let recalled = match callback {
+                Callback::Recall(handle) => vec![handle],
+                Callback::RecallAny => self
+                    .delegations
+                    .keys()
+                    .chain(self.watches.keys())
+                    .map(|h| FileHandle(h.clone()))
+                    .collect(),
+                Callback::Notify(args) => {
+                    self.notified(args);
+                    vec![]
+                }
+                Callback::Offload(args) => {
+                    self.offloads.insert(args.state_id.other, args.info);
+                    vec![]
+                }
+            };
+            for handle in recalled {
+                let _ = self.return_delegation(&handle);
+                let _ = self.return_dir_delegation(&handle);
+            }
+        }
     }
 
-    pub fn read_dir(
+    fn opened(
         &mut self,
-        handle: FileHandle,
-        attr_request: EnumSet<FileAttributeId>,
-    ) -> Result<Vec<DirectoryEntry>> {
-        let mut entries = vec![];
-        let attr_request: EnumSet<_> = attr_request
-            .into_iter()
-            .filter(|a| self.supported_attrs.contains(*a))
-            .collect();
+        handle: &FileHandle,
+        state_id: StateId,
+        access: ShareAccess,
+        deny: ShareDeny,
+    ) {
+        let open = self.opens.entry(handle.0.clone()).or_insert(OpenState {
+            state_id,
+            count: 0,
+            access: ShareAccess::empty(),
+            deny: ShareDeny::NONE,
+        });
+        open.count += 1;
+        // Opening a file we already have open upgrades the open
+        open.access |= access & ShareAccess::BOTH;
+        open.deny |= deny;
+        self.update_open(handle, state_id);
+    }
 
-        let mut cookie = Cookie::initial();
-        loop {
-            let res = self.do_compound(ReturnSecond(
+    fn update_open(&mut self, handle: &FileHandle, state_id: StateId) {
+        if let Some(open) = self.opens.get_mut(&handle.0) {
+            if open.state_id.other != state_id.other
+                || state_id.sequence_id > open.state_id.sequence_id
+            {
+                open.state_id = state_id;
+            }
+        }
+    }
+
+    /// Forgets one `OpenFile` for the given file. If it was the last one, returns the request
+    /// which closes the file, giving back its delegation along with it.
+    fn release_open(&mut self, handle: &FileHandle) -> Option<CloseRequest> {
+        let open = self.opens.get_mut(&handle.0)?;
+        open.count -= 1;
+        if open.count > 0 {
+            return None;
+        }
+        let open = self.opens.remove(&handle.0)?;
+        let delegation = self.delegations.remove(&handle.0);
+        Some(ReturnSecond(
+            (
                 PutFhArgs {
                     object: handle.clone(),
                 },
-                ReadDirArgs {
-                    cookie,
-                    cookie_verifier: Verifier(0),
-                    directory_count: 1000,
-                    max_count: 1000,
-                    attr_request: attr_request.clone(),
-                },
-            ))?;
-
-            entries.extend(res.reply.entries);
+                delegation
+                    .map(|d| DelegReturnArgs {
+                        state_id: d.state_id,
+                    })
+                    .into_iter()
+                    .collect(),
+            ),
+            CloseArgs {
+                sequence_id: SequenceId(0),
+                open_stateid: open.state_id,
+            },
+        ))
+    }
 
-            if res.reply.eof {
-                break Ok(entries);
-            }
-            cookie = entries.last().unwrap().cookie;
+    fn close(&mut self, handle: &FileHandle) -> Result<()> {
+        if let Some(request) = self.release_open(handle) {
+            self.do_compound(request)?;
         }
+        Ok(())
     }
 
-    pub fn set_attr(&mut self, handle: FileHandle, attrs: FileAttributes) -> Result<()> {
-        self.do_compound(ReturnSecond(
-            PutFhArgs { object: handle },
-            SetAttrArgs {
-                state_id: StateId::anonymous(),
-                object_attributes: attrs,
+    fn return_delegation(&mut self, handle: &FileHandle) -> Result<()> {
+        let Some(delegation) = self.delegations.remove(&handle.0) else {
+            return Ok(());
+        };
+        self.do_compound((
+            PutFhArgs {
+                object: handle.clone(),
+            },
+            DelegReturnArgs {
+                state_id: delegation.state_id,
             },
         ))?;
         Ok(())
     }
 
-    pub fn remove(&mut self, handle: FileHandle, entry_name: &str) -> Result<ChangeInfo> {
-        Ok(self
+    fn cached_read(&self, handle: &FileHandle, offset: u64, count: u32) -> Option<ReadRes> {
+        let contents = self.delegations.get(&handle.0)?.contents.as_ref()?;
+        let start = usize::try_from(offset).map_or(contents.len(), |o| o.min(contents.len()));
+        let end = start.saturating_add(count as usize).min(contents.len());
+        Some(ReadRes {
+            eof: end == contents.len(),
+            data: contents.slice(start..end),
+        })
+    }
+}
+
+type CloseRequest = ReturnSecond<(PutFhArgs, Vec<DelegReturnArgs>), CloseArgs>;
+
+fn lock<TransportT>(
+    connection: &Mutex<Connection<TransportT>>,
+) -> MutexGuard<'_, Connection<TransportT>> {
+    connection.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+struct LeaseRenewal {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for LeaseRenewal {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Sends a SEQUENCE whenever the connection has been idle for `interval`, until told to stop.
+/// Errors are ignored here, whatever caused them will be reported by the next real request.
+fn renew_lease_periodically<TransportT: Transport>(
+    connection: Arc<Mutex<Connection<TransportT>>>,
+    interval: Duration,
+    stop: mpsc::Receiver<()>,
+) {
+    loop {
+        let wait = interval.saturating_sub(lock(&connection).last_sequence.elapsed());
+        if stop.recv_timeout(wait) != Err(mpsc::RecvTimeoutError::Timeout) {
+            break;
+        }
+
+        let mut connection = lock(&connection);
+        if connection.last_sequence.elapsed() >= interval {
+            let _ = connection.renew_lease();
+        }
+    }
+}
+
+pub struct Client<TransportT> {
+    connection: Arc<Mutex<Connection<TransportT>>>,
+    session: CreateSessionRes,
+    lease_time: Duration,
+    lease_renewal: Option<LeaseRenewal>,
+    client_id: ClientId,
+    client_owner: ClientOwner,
+    max_read: u64,
+    max_write: u64,
+    supported_attrs: EnumSet<FileAttributeId>,
+    read_pipeline_depth: usize,
+    read_chunk_size: Option<u32>,
+    write_pipeline_depth: usize,
+    write_chunk_size: Option<u32>,
+    write_stability: StableHow,
+    // By file handle, in the order they were written
+    unstable_writes: BTreeMap<Bytes, Vec<UnstableWrite>>,
+    bandwidth_limit: Option<RateLimiter>,
+    attr_cache: Option<AttrCache>,
+    name_cache: Option<NameCache>,
+    fh_expire_type: FhExpireType,
+    volatile_handles: Option<VolatileHandles>,
+    retry_deadline: Duration,
+    pnfs: Option<Pnfs<TransportT>>,
+    referrals: Option<Connector<TransportT>>,
+    reconnect: Option<Reconnector<TransportT>>,
+    gss: Option<GssFactory>,
+    stats: Arc<Stats>,
+    id_mapper: Arc<dyn IdMapper>,
+    minor_versions: RangeInclusive<u32>,
+}
+
+#[cfg(unix)]
+impl Client<UnixStream> {
+    /// Connects to a server listening on the given Unix domain socket, like a user-space server
+    /// running on the same machine.
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(UnixStream::connect(path)?)
+    }
+
+    /// Like `connect_unix`, but for a socket in the abstract namespace, which is known by a name
+    /// rather than a path in the file system.
+    #[cfg(target_os = "linux")]
+    pub fn connect_abstract(name: impl AsRef<[u8]>) -> Result<Self> {
+        use std::os::linux::net::SocketAddrExt as _;
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        Self::new(UnixStream::connect_addr(&addr)?)
+    }
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Connects with the default configuration, use `ClientBuilder` to configure the client.
+    pub fn new(transport: TransportT) -> Result<Self> {
+        ClientBuilder::new().build(transport)
+    }
+
+    /// The minor version of NFSv4 we settled on with the server.
+    pub fn minor_version(&self) -> u32 {
+        lock(&self.connection).raw_client.minor_version
+    }
+
+    /// The operations the server only takes from the credential the client ID was made with, none
+    /// without state protection. See `ClientBuilder::protect_state`.
+    pub fn protected_ops(&self) -> EnumSet<OperationId> {
+        let connection = lock(&self.connection);
+        match &connection.raw_client.machine_credential {
+            Some(machine) => machine.enforced().clone(),
+            None => EnumSet::default(),
+        }
+    }
+
+    /// The client ID the server gave us, which the owners of opens and locks are made of.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Gets what we need to know about the server from the attributes of its root, telling it
+    /// that we have nothing to reclaim along the way.
+    fn read_root_attrs(&mut self) -> Result<()> {
+        let mut root_attrs = self
             .do_compound(ReturnSecond(
-                PutFhArgs { object: handle },
-                RemoveArgs {
-                    target: entry_name.into(),
+                (ReclaimCompleteArgs { one_fs: false }, PutRootFh),
+                GetAttrArgs {
+                    attr_request: [
+                        FileAttributeId::SupportedAttrs,
+                        FileAttributeId::MaxRead,
+                        FileAttributeId::MaxWrite,
+                        FileAttributeId::LeaseTime,
+                        FileAttributeId::FhExpireType,
+                    ]
+                    .into_iter()
+                    .collect(),
                 },
             ))?
-            .change_info)
+            .object_attributes;
+
+        self.supported_attrs = root_attrs
+            .remove_as(FileAttributeId::SupportedAttrs)
+            .unwrap();
+        self.max_read = *root_attrs.get_as(FileAttributeId::MaxRead).unwrap();
+        self.max_write = *root_attrs.get_as(FileAttributeId::MaxWrite).unwrap();
+        let lease: &Lease = root_attrs.get_as(FileAttributeId::LeaseTime).unwrap();
+        self.lease_time = Duration::from_secs(lease.0.into());
+        self.fh_expire_type = root_attrs
+            .get_as(FileAttributeId::FhExpireType)
+            .copied()
+            .unwrap_or(FhExpireType::empty());
+        self.volatile_handles =
+            volatile::is_volatile(self.fh_expire_type).then(VolatileHandles::default);
+        Ok(())
     }
 
-    pub fn rename(
+    /// Makes `read_all` and `write_all` go straight to the data servers for files the server gives
+    /// us a files or flexible files layout for, using the given function to connect to them. Files
+    /// without one are still read and written through the server.
+    pub fn enable_pnfs(
         &mut self,
-        src_dir: FileHandle,
-        target_dir: FileHandle,
-        src_entry: &str,
-        target_entry: &str,
-    ) -> Result<RenameRes> {
-        self.do_compound(ReturnSecond(
-            (
-                PutFhArgs { object: src_dir },
-                SaveFh,
-                PutFhArgs { object: target_dir },
-            ),
-            RenameArgs {
-                old_name: src_entry.to_owned(),
-                new_name: target_entry.to_owned(),
-            },
-        ))
+        connect: impl FnMut(std::net::SocketAddr) -> io::Result<TransportT> + Send + 'static,
+    ) {
+        self.pnfs = Some(Pnfs::new(Box::new(connect)));
     }
 
-    pub fn create_directory(
+    /// Makes looking up paths follow the server when it refers us to another one, for parts of
+    /// the namespace kept elsewhere or file systems which moved, using the given function to
+    /// connect to it. The client then talks to the other server from then on, so file handles and
+    /// open files from before can't be used with it anymore.
+    pub fn follow_referrals(
         &mut self,
-        parent_dir: FileHandle,
-        name: &str,
-        attrs: FileAttributes,
-    ) -> Result<FileHandle> {
-        Ok(self
-            .do_compound(ReturnSecond(
-                (
-                    PutFhArgs { object: parent_dir },
-                    CreateArgs {
-                        object_type: CreateType::Directory,
-                        object_name: name.to_owned(),
+        connect: impl FnMut(std::net::SocketAddr) -> io::Result<TransportT> + Send + 'static,
+    ) {
+        self.referrals = Some(Box::new(connect));
+    }
+
+    /// Sets how many READs `read_all` keeps outstanding at once. It is limited by the number of
+    /// slots the server granted the session.
+    pub fn set_read_pipeline_depth(&mut self, depth: usize) {
+        self.read_pipeline_depth = depth.max(1);
+    }
+
+    /// The most data a single READ can return, from the server's maxread attribute and the
+    /// session's maximum response size.
+    pub fn max_read(&self) -> u32 {
+        let session_max = self
+            .session
+            .fore_channel_attrs
+            .max_response_size
+            .saturating_sub(COMPOUND_OVERHEAD);
+        (self.max_read.min(session_max as u64) as u32).max(1)
+    }
+
+    /// The most data a single WRITE can send, from the server's maxwrite attribute and the
+    /// session's maximum request size.
+    pub fn max_write(&self) -> u32 {
+        let session_max = self
+            .session
+            .fore_channel_attrs
+            .max_request_size
+            .saturating_sub(COMPOUND_OVERHEAD);
+        (self.max_write.min(session_max as u64) as u32).max(1)
+    }
+
+    /// Sets the amount of data `read_all` asks for in each READ, instead of `max_read`. It can't
+    /// be made larger than `max_read`. With `None` it goes back to asking for `max_read`.
+    pub fn set_read_chunk_size(&mut self, chunk_size: Option<u32>) {
+        self.read_chunk_size = chunk_size.map(|size| size.max(1));
+    }
+
+    pub fn read_chunk_size(&self) -> u32 {
+        self.read_chunk_size
+            .map_or(self.max_read(), |size| size.min(self.max_read()))
+    }
+
+    /// Sets the amount of data `write_all` sends in each WRITE, instead of `max_write`. It can't
+    /// be made larger than `max_write`. With `None` it goes back to sending `max_write`.
+    pub fn set_write_chunk_size(&mut self, chunk_size: Option<u32>) {
+        self.write_chunk_size = chunk_size.map(|size| size.max(1));
+    }
+
+    pub fn write_chunk_size(&self) -> u32 {
+        self.write_chunk_size
+            .map_or(self.max_write(), |size| size.min(self.max_write()))
+    }
+
+    /// Sets how many WRITEs `write_all` keeps outstanding at once. It is limited by the number of
+    /// slots the server granted the session.
+    pub fn set_write_pipeline_depth(&mut self, depth: usize) {
+        self.write_pipeline_depth = depth.max(1);
+    }
+
+    /// Sets how `write_all` and `write_all_at` write. With `StableHow::Unstable`, the default, the
+    /// server can keep the data in memory and they finish with COMMITs, which is faster. Otherwise
+    /// each WRITE asks for the data to be on stable storage before the server replies, and no
+    /// COMMIT is needed.
+    pub fn set_write_stability(&mut self, stable: StableHow) {
+        self.write_stability = stable;
+    }
+
+    pub fn write_stability(&self) -> StableHow {
+        self.write_stability
+    }
+
+    /// Limits how fast `read_all` and `write_all` move data, or lifts the limit with `None`. The
+    /// same limiter can be given to several clients for them to share the limit.
+    pub fn set_bandwidth_limit(&mut self, limiter: Option<RateLimiter>) {
+        self.bandwidth_limit = limiter;
+    }
+
+    pub fn bandwidth_limit(&self) -> Option<&RateLimiter> {
+        self.bandwidth_limit.as_ref()
+    }
+
+    /// Makes `get_attr`, `look_up`, `look_up_from` and `read_dir` remember what they got for the
+    /// given time, or turns that off with `None`, which is the default. A listing older than that
+    /// is still used if the directory's change attribute is the same, which takes a GETATTR
+    /// instead of however many READDIRs. Everything is forgotten whenever the client changes
+    /// anything, but changes made by others aren't seen until what it remembers expires.
+    pub fn set_attr_cache(&mut self, ttl: Option<Duration>) {
+        self.attr_cache = ttl.map(AttrCache::new);
+    }
+
+    pub fn attr_cache(&self) -> Option<Duration> {
+        self.attr_cache.as_ref().map(AttrCache::ttl)
+    }
+
+    fn clear_attr_cache(&mut self) {
+        if let Some(cache) = &mut self.attr_cache {
+            cache.clear();
+        }
+    }
+
+    /// Makes `look_up` and `look_up_from` remember the handle each name along the path leads to
+    /// for the given time, or turns that off with `None`, which is the default. Looking up a path
+    /// then only sends LOOKUPs for the components after the deepest directory along it we know.
+    /// Names are forgotten when this client removes or renames them, but ones removed or renamed
+    /// by others can still be found until they expire.
+    pub fn set_name_cache(&mut self, ttl: Option<Duration>) {
+        self.name_cache = ttl.map(NameCache::new);
+    }
+
+    pub fn name_cache(&self) -> Option<Duration> {
+        self.name_cache.as_ref().map(NameCache::ttl)
+    }
+
+    /// How long the server keeps our state around without hearing from us.
+    pub fn lease_time(&self) -> Duration {
+        self.lease_time
+    }
+
+    /// Starts a thread which keeps the lease from expiring while the client is idle, by sending a
+    /// SEQUENCE when nothing else has been sent for the given interval. Without this, state like
+    /// open files is lost if the client goes quiet for longer than `lease_time`.
+    pub fn start_lease_renewal(&mut self, interval: Duration)
+    where
+        TransportT: Send + 'static,
+    {
+        self.stop_lease_renewal();
+
+        let (stop, stop_receiver) = mpsc::channel();
+        let connection = self.connection.clone();
+        let interval = interval.max(Duration::from_secs(1));
+        let thread =
+            thread::spawn(move || renew_lease_periodically(connection, interval, stop_receiver));
+        self.lease_renewal = Some(LeaseRenewal {
+            stop: Some(stop),
+            thread: Some(thread),
+        });
+    }
+
+    /// Like `start_lease_renewal`, renewing at half the lease time.
+    pub fn start_default_lease_renewal(&mut self)
+    where
+        TransportT: Send + 'static,
+    {
+        self.start_lease_renewal(self.lease_time / 2);
+    }
+
+    pub fn stop_lease_renewal(&mut self) {
+        self.lease_renewal.take();
+    }
+
+    /// Sets how long a request the server answers with DELAY or GRACE keeps being retried before
+    /// the error is returned. A deadline of zero disables retrying.
+    pub fn set_retry_deadline(&mut self, deadline: Duration) {
+        self.retry_deadline = deadline;
+    }
+
+    pub fn retry_deadline(&self) -> Duration {
+        self.retry_deadline
+    }
+
+    /// Makes requests fail with `TimedOut` once the server has sent nothing back for the given
+    /// time, rather than waiting forever on a server which is gone while the connection to it
+    /// isn't. Replies which come later are dropped. There is no timeout by default. NFSv3 data
+    /// servers used with pNFS don't time out.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()>
+    where
+        TransportT: ReadTimeout,
+    {
+        let mut connection = lock(&self.connection);
+        for index in 0..connection.num_connections() {
+            connection
+                .connection_at(index)
+                .rpc_client
+                .set_reply_timeout(timeout)?;
+        }
+        drop(connection);
+        if let Some(pnfs) = &mut self.pnfs {
+            pnfs.set_timeout(timeout)?;
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        lock(&self.connection).raw_client.rpc_client.reply_timeout()
+    }
+
+    /// Makes replies larger than the given size fail with `MessageTooLarge` before they are read,
+    /// instead of a broken or malicious server being able to make us allocate as much as it likes.
+    /// The connection is made again if it can be. The default is
+    /// `sun_rpc_client::DEFAULT_MAX_MESSAGE_SIZE`, which is plenty for the largest READs. Data
+    /// servers used with pNFS keep the default.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        let mut connection = lock(&self.connection);
+        for index in 0..connection.num_connections() {
+            connection
+                .connection_at(index)
+                .rpc_client
+                .set_max_message_size(size);
+        }
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        lock(&self.connection)
+            .raw_client
+            .rpc_client
+            .max_message_size()
+    }
+
+    /// Makes replies with longer opaque data or arrays in them than the given limits fail with
+    /// `MessageTooLarge`, see `sun_rpc_client::RpcClient::set_limits`. There are none by default
+    /// besides the message size.
+    pub fn set_limits(&mut self, limits: sun_rpc_client::Limits) {
+        let mut connection = lock(&self.connection);
+        for index in 0..connection.num_connections() {
+            connection
+                .connection_at(index)
+                .rpc_client
+                .set_limits(limits);
+        }
+    }
+
+    pub fn limits(&self) -> sun_rpc_client::Limits {
+        lock(&self.connection).raw_client.rpc_client.limits()
+    }
+
+    /// Calls the given function with a timeout for the requests it makes, like `set_timeout`,
+    /// and then puts the timeout back to what it was.
+    pub fn with_timeout<R>(
+        &mut self,
+        timeout: Duration,
+        f: impl FnOnce(&mut Self) -> Result<R>,
+    ) -> Result<R>
+    where
+        TransportT: ReadTimeout,
+    {
+        let previous = self.timeout();
+        self.set_timeout(Some(timeout))?;
+        let res = f(self);
+        self.set_timeout(previous)?;
+        res
+    }
+
+    /// Tells the given metrics about every request made from now on, over any of the connections
+    /// the client has.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn ClientMetrics>) {
+        self.stats = Arc::new(self.stats.with_metrics(metrics));
+        let metrics = self.metrics();
+        let mut connection = lock(&self.connection);
+        for index in 0..connection.num_connections() {
+            connection.connection_at(index).tracer.metrics = metrics.clone();
+        }
+        drop(connection);
+        if let (Some(pnfs), Some(metrics)) = (&mut self.pnfs, &metrics) {
+            pnfs.set_metrics(metrics);
+        }
+    }
+
+    /// What the client did since it was made: the operations it sent, the bytes it read and
+    /// wrote, the errors it got back and how long the server took to reply.
+    pub fn stats(&self) -> ClientStats {
+        self.stats.get()
+    }
+
+    /// What each connection counts what it sends with.
+    fn metrics(&self) -> Option<Arc<dyn ClientMetrics>> {
+        Some(self.stats.clone())
+    }
+
+    /// Sets how owners and groups are mapped to local IDs and back, by default only IDs written
+    /// out as numbers are.
+    pub fn set_id_mapper(&mut self, id_mapper: Arc<dyn IdMapper>) {
+        self.id_mapper = id_mapper;
+    }
+
+    pub fn id_mapper(&self) -> &dyn IdMapper {
+        &*self.id_mapper
+    }
+
+    fn retrying(&self, error: &Error) {
+        if let Some(status) = error.status() {
+            self.stats.retry(status);
+        }
+    }
+
+    /// Sends the given request, re-establishing the session and sending it again if the server
+    /// lost our session in the meantime, and backing off and sending it again while the server
+    /// asks us to.
+    fn do_compound<Args>(&mut self, args: Args) -> Result<Args::Response>
+    where
+        Args: CompoundRequest,
+    {
+        let (arg_array, geometry) = args.into_arg_array();
+        let compound_reply = self.do_arg_array(arg_array)?;
+        process_compound_reply::<ReturnSecond<SequenceArgs, Args>>(compound_reply, ((), geometry))
+    }
+
+    fn do_arg_array(&mut self, mut arg_array: Vec<ArgOp>) -> Result<CompoundRes> {
+        let mut recoveries = 0;
+        let mut reconnections = 0;
+        let mut backoff = Backoff::new(self.retry_deadline);
+        let mut negotiated = false;
+        let mut renewed = false;
+        self.use_renewed_handles(&mut arg_array);
+        if arg_array.iter().any(attr_cache::modifies) {
+            self.clear_attr_cache();
+        }
+        if let Some(cache) = &mut self.name_cache {
+            cache.forget_changed(&arg_array);
+        }
+        loop {
+            let mut connection = lock(&self.connection);
+            connection.use_reclaimed_state_ids(&mut arg_array);
+            let res = connection.do_arg_array(arg_array.clone());
+            drop(connection);
+            let compound_reply = match res {
+                Err(e)
+                    if is_connection_broken(&e)
+                        && self.can_reconnect()
+                        && reconnections < MAX_RECONNECTIONS =>
+                {
+                    reconnections += 1;
+                    self.reconnect()?;
+                    if let Some(op) = arg_array.iter().find(|op| !is_idempotent(op)) {
+                        return Err(Error::ConnectionLost(op.to_id()));
+                    }
+                    continue;
+                }
+                res => res?,
+            };
+            if let StatusResult::Err(e) = &compound_reply.status {
+                let error = Error::from(*e);
+                if is_session_lost(&error) && recoveries < MAX_SESSION_RECOVERIES {
+                    recoveries += 1;
+                    self.retrying(&error);
+                    self.recover_session()?;
+                    continue;
+                }
+                if is_transient(&error) && backoff.wait() {
+                    self.retrying(&error);
+                    continue;
+                }
+                if *e == StatusError::WrongSec && !negotiated {
+                    negotiated = true;
+                    // The results start with the one of the SEQUENCE, and end with the failing one
+                    let failed = compound_reply.res_array.len().saturating_sub(2);
+                    if self.negotiate_security(&arg_array, failed)? {
+                        continue;
+                    }
+                }
+                if let (true, Some(cache)) = (e.is_stale(), &mut self.name_cache) {
+                    cache.forget_stale(&arg_array);
+                }
+                if *e == StatusError::FhExpired && !renewed {
+                    renewed = true;
+                    if self.renew_expired_handles(&mut arg_array) {
+                        continue;
+                    }
+                }
+            }
+            self.free_revoked_state();
+            return Ok(compound_reply);
+        }
+    }
+
+    /// Switches to the security flavor the server prefers for the object the operation with the
+    /// given index in the request failed on, after it rejected the one we used. Returns whether we
+    /// switched.
+    fn negotiate_security(&mut self, arg_array: &[ArgOp], failed: usize) -> Result<bool> {
+        let mut connection = lock(&self.connection);
+        let Some(request) = sec_info_request(arg_array, failed) else {
+            return Ok(false);
+        };
+        // Minor version 0 can only ask about the entries of directories
+        let no_name = request
+            .iter()
+            .any(|op| matches!(op, ArgOp::SecInfoNoName(_)));
+        if no_name && connection.raw_client.minor_version == 0 {
+            return Ok(false);
+        }
+
+        // SECINFO and SECINFO_NO_NAME are allowed with any flavor
+        let compound_reply = connection.do_arg_array(request)?;
+        if let StatusResult::Err(e) = compound_reply.status {
+            return Err(e.into());
+        }
+        let flavors = match compound_reply.res_array.last() {
+            Some(
+                ResOp::SecInfo(StatusResult::Ok(res)) | ResOp::SecInfoNoName(StatusResult::Ok(res)),
+            ) => res.body.clone(),
+            res => {
+                return Err(Error::CompoundResponseMismatch(format!(
+                    "expected SECINFO result, got {res:?}"
+                )))
+            }
+        };
+
+        let rpc_client = &connection.raw_client.rpc_client;
+        let using_gss = rpc_client.gss().map(|gss| gss.service());
+        for flavor in &flavors {
+            if let SecurityInfo::RpcSecGss { flavor_info } = flavor {
+                let service = gss_service(&flavor_info.service);
+                if using_gss == Some(service) {
+                    return Ok(false);
+                }
+                let Some(initiator) = self.gss.as_mut().and_then(|gss| gss(flavor_info)) else {
+                    continue;
+                };
+                let credential = connection.raw_client.rpc_client.credential().clone();
+                let session = connection
+                    .raw_client
+                    .rpc_client
+                    .establish_gss(initiator, service)?;
+                connection.set_security(credential, Some(session));
+                return Ok(true);
+            }
+            let Some(credential) = credential_for(flavor) else {
+                continue;
+            };
+            if using_gss.is_none() && rpc_client.credential().flavor == credential.flavor {
+                return Ok(false);
+            }
+            connection.set_security(credential, None);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Lets the client switch to RPCSEC_GSS when the server only takes that for an object, with
+    /// the security context the given function makes for the mechanism, QOP and service the
+    /// server lists. It returns `None` for the ones it can't do, and the next flavor the server
+    /// lists is tried instead.
+    pub fn set_gss(
+        &mut self,
+        initiator: impl FnMut(&RpcSecGssInfo) -> Option<Box<dyn GssInitiator>> + Send + 'static,
+    ) {
+        self.gss = Some(Box::new(initiator));
+    }
+
+    /// Lists the security flavors the server allows for the given entry of the given directory,
+    /// in its order of preference.
+    pub fn sec_info(&mut self, dir: FileHandle, name: &str) -> Result<Vec<SecurityInfo>> {
+        Ok(self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: dir },
+                SecInfoArgs { name: name.into() },
+            ))
+            .map_err(|e| e.with_path(name))?
+            .body)
+    }
+
+    /// Like `sec_info`, but for the given object itself.
+    pub fn sec_info_no_name(&mut self, handle: FileHandle) -> Result<Vec<SecurityInfo>> {
+        Ok(self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: handle },
+                SecInfoNoNameArgs {
+                    style: SecInfoStyle::CurrentFh,
+                },
+            ))?
+            .body)
+    }
+
+    /// Sends the operations of the given builder in a single COMPOUND, retrying like any other
+    /// request. Only failing to send it or to get a reply is an error here, how the operations
+    /// themselves went is in the reply.
+    pub fn send_compound(&mut self, builder: CompoundBuilder) -> Result<CompoundReply> {
+        let compound_reply = self.do_arg_array(builder.ops)?;
+        let mut results: VecDeque<_> = compound_reply.res_array.into();
+        <SequenceArgs as CompoundRequest>::process_reply(&mut results, ())?;
+        Ok(CompoundReply {
+            status: compound_reply.status,
+            results: results.into(),
+        })
+    }
+
+    /// Creates a new client ID and session after the server lost ours, most likely because it
+    /// restarted. If it lost our client ID along with the session, the files we had open are
+    /// reclaimed during its grace period before we tell it we are done reclaiming, see
+    /// `reclaim_opens`.
+    fn recover_session(&mut self) -> Result<()> {
+        let mut connection = lock(&self.connection);
+        let (client_id, _, session) =
+            establish_session(&mut connection.raw_client, &self.client_owner)?;
+        connection.session_id = session.session_id;
+        connection.slots = session_slots(&session);
+        connection.rebind_trunks();
+        // Someone else may have changed the files while we didn't hold delegations for them
+        connection.delegations.clear();
+        for watch in connection.watches.values_mut() {
+            watch.lost_delegation();
+        }
+
+        // If the server still knew our client ID only the session was lost, along with nothing
+        // to reclaim, and we already sent RECLAIM_COMPLETE for it
+        if client_id != self.client_id {
+            let owner = StateOwner {
+                client_id,
+                opaque: self.client_owner.owner_id.clone(),
+            };
+            connection.reclaim_opens(&owner)?;
+        }
+        match connection.do_compound(ReclaimCompleteArgs { one_fs: false }) {
+            Err(e) if e.status() != Some(StatusError::CompleteAlready) => return Err(e),
+            _ => {}
+        }
+        drop(connection);
+
+        self.client_id = client_id;
+        self.session = session;
+        Ok(())
+    }
+
+    /// Sends the requests of the given pipeline using up to `depth` session slots at once. When a
+    /// request fails, no more are sent but the replies to the ones already in flight are still
+    /// received so the connection stays usable. Requests lost along with the session are sent
+    /// again once it has been re-established, and ones the server asked us to try again later
+    /// after backing off.
+    fn run_pipeline<P: Pipeline>(&mut self, depth: usize, pipeline: &mut P) -> Result<()> {
+        let mut recoveries = 0;
+        let mut reconnections = 0;
+        let mut backoff = None;
+        let mut renewed = false;
+        loop {
+            let error = match self.run_pipeline_once(depth, pipeline) {
+                Ok(()) => {
+                    self.free_revoked_state();
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            if error.status() == Some(StatusError::FhExpired) && !renewed {
+                renewed = true;
+                if let Some(handle) = self.renew_expired_handle(pipeline.handle_mut()) {
+                    *pipeline.handle_mut() = handle;
+                    continue;
+                }
+            }
+            if is_session_lost(&error) && recoveries < MAX_SESSION_RECOVERIES {
+                recoveries += 1;
+                self.retrying(&error);
+                self.recover_session()?;
+                continue;
+            }
+            // READs and WRITEs are all safe to send again
+            if is_connection_broken(&error)
+                && self.can_reconnect()
+                && reconnections < MAX_RECONNECTIONS
+            {
+                reconnections += 1;
+                self.reconnect()?;
+                continue;
+            }
+
+            // The deadline counts from the first time the server asks us to wait
+            let backoff = backoff.get_or_insert_with(|| Backoff::new(self.retry_deadline));
+            if !(is_transient(&error) && backoff.wait()) {
+                self.free_revoked_state();
+                return Err(error);
+            }
+            self.retrying(&error);
+        }
+    }
+
+    fn run_pipeline_once<P: Pipeline>(&mut self, depth: usize, pipeline: &mut P) -> Result<()> {
+        let mut connection = lock(&self.connection);
+        let depth = depth.clamp(1, connection.slots.len());
+        let mut free_slots: Vec<SlotId> = (0..depth as u32).rev().map(SlotId).collect();
+        // Requests are told apart by the connection they were sent on along with their XID
+        let mut in_flight = BTreeMap::new();
+        let mut loads = vec![0; connection.num_connections()];
+        let mut result = Ok(());
+        let mut done = false;
+
+        loop {
+            while result.is_ok() && !done && !free_slots.is_empty() {
+                match pipeline.next_request() {
+                    Ok(Some((request, tag))) => {
+                        let slot_id = free_slots.pop().unwrap();
+                        let sequence = connection.sequence_args(slot_id);
+                        let index = connection.pick_connection(&loads);
+                        match connection
+                            .connection_at(index)
+                            .send_compound(ReturnSecond(sequence, request))
+                        {
+                            Ok((xid, geometry)) => {
+                                connection.sent(slot_id);
+                                in_flight.insert((index, xid), (slot_id, geometry, tag));
+                                loads[index] += 1;
+                            }
+                            Err(e) => {
+                                free_slots.push(slot_id);
+                                if is_connection_broken(&e) {
+                                    pipeline.retry(tag);
+                                }
+                                result = Err(e);
+                            }
+                        }
+                    }
+                    Ok(None) => done = true,
+                    Err(e) => result = Err(e),
+                }
+            }
+
+            // Any connection with requests in flight has replies coming
+            let Some(&(index, _)) = in_flight.keys().next() else {
+                break result;
+            };
+
+            let (xid, compound_reply) = match connection.connection_at(index).receive_compound() {
+                Ok(reply) => reply,
+                Err(e) => {
+                    // What was in flight is sent again if we reconnect
+                    if is_connection_broken(&e) {
+                        for (.., tag) in in_flight.into_values() {
+                            pipeline.retry(tag);
+                        }
+                    }
+                    return Err(e);
+                }
+            };
+            let key = (index, xid);
+            let (slot_id, geometry, tag) = in_flight.remove(&key).ok_or_else(|| {
+                Error::CompoundResponseMismatch(format!("unexpected reply {:?}", key.1))
+            })?;
+            free_slots.push(slot_id);
+            loads[index] -= 1;
+
+            let reply = process_compound_reply::<ReturnSecond<SequenceArgs, P::Request>>(
+                compound_reply,
+                geometry,
+            );
+            let can_resend = |e: &Error| {
+                is_session_lost(e) || is_transient(e) || e.status() == Some(StatusError::FhExpired)
+            };
+            let resending = result.as_ref().err().is_some_and(can_resend);
+            match reply {
+                Err(e) if can_resend(&e) => {
+                    pipeline.retry(tag);
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+                reply if result.is_ok() || resending => {
+                    match reply.and_then(|r| pipeline.handle_reply(tag, r)) {
+                        // It may have left more to send, like the rest of a short READ
+                        Ok(()) => done = false,
+                        Err(e) => result = Err(e),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Every attribute the server supports, except the write-only ones
+    fn readable_attrs(&self) -> EnumSet<FileAttributeId> {
+        let mut supported_attrs = self.supported_attrs.clone();
+
+        supported_attrs.remove(FileAttributeId::TimeAccessSet);
+        supported_attrs.remove(FileAttributeId::TimeModifySet);
+
+        supported_attrs
+    }
+
+    /// Gets every attribute of the given object. They are cached while we hold a delegation for
+    /// it, and for a while if the attribute cache is on, see `set_attr_cache`.
+    pub fn get_attr(&mut self, handle: FileHandle) -> Result<GetAttrRes> {
+        if let Some(delegation) = lock(&self.connection).delegations.get(&handle.0) {
+            if let Some(attrs) = &delegation.attrs {
+                return Ok(attrs.clone());
+            }
+        }
+        if let Some(res) = self.attr_cache.as_ref().and_then(|c| c.attrs(&handle)) {
+            return Ok(res);
+        }
+
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: handle.clone(),
+            },
+            GetAttrArgs {
+                attr_request: self.readable_attrs(),
+            },
+        ))?;
+        if let Some(delegation) = lock(&self.connection).delegations.get_mut(&handle.0) {
+            delegation.attrs = Some(res.clone());
+        }
+        if let Some(cache) = &mut self.attr_cache {
+            cache.insert_attrs(&handle, res.clone());
+        }
+        Ok(res)
+    }
+
+    /// Asks the server which of the given kinds of access it would grant us to the given object.
+    /// It leaves out of `supported` the ones it can't tell, which may or may not be allowed.
+    pub fn access(&mut self, handle: FileHandle, mask: Access) -> Result<AccessRes> {
+        self.do_compound(ReturnSecond(
+            PutFhArgs { object: handle },
+            AccessArgs { access: mask },
+        ))
+    }
+
+    /// Gets how much space and how many files the file system the given object is on has, and how
+    /// much of each is left. Anything the server doesn't support is reported as zero.
+    pub fn statfs(&mut self, handle: FileHandle) -> Result<FsStat> {
+        let attr_request = [
+            FileAttributeId::SpaceTotal,
+            FileAttributeId::SpaceFree,
+            FileAttributeId::SpaceAvail,
+            FileAttributeId::FilesTotal,
+            FileAttributeId::FilesFree,
+            FileAttributeId::FilesAvail,
+        ]
+        .into_iter()
+        .filter(|a| self.supported_attrs.contains(*a))
+        .collect();
+        let attrs = self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: handle },
+                GetAttrArgs { attr_request },
+            ))?
+            .object_attributes;
+
+        let get = |id| attrs.get_as::<u64>(id).copied().unwrap_or_default();
+        Ok(FsStat {
+            space_total: get(FileAttributeId::SpaceTotal),
+            space_free: get(FileAttributeId::SpaceFree),
+            space_avail: get(FileAttributeId::SpaceAvail),
+            files_total: get(FileAttributeId::FilesTotal),
+            files_free: get(FileAttributeId::FilesFree),
+            files_avail: get(FileAttributeId::FilesAvail),
+        })
+    }
+
+    /// Gets the quota of the user we send requests as on the file system the given object is on.
+    pub fn quota(&mut self, handle: FileHandle) -> Result<Quota> {
+        let attr_request = [
+            FileAttributeId::QuotaAvailHard,
+            FileAttributeId::QuotaAvailSoft,
+            FileAttributeId::QuotaUsed,
+        ]
+        .into_iter()
+        .filter(|a| self.supported_attrs.contains(*a))
+        .collect();
+        let attrs = self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: handle },
+                GetAttrArgs { attr_request },
+            ))?
+            .object_attributes;
+
+        let get = |id| attrs.get_as::<u64>(id).copied();
+        Ok(Quota {
+            avail_hard: get(FileAttributeId::QuotaAvailHard),
+            avail_soft: get(FileAttributeId::QuotaAvailSoft),
+            used: get(FileAttributeId::QuotaUsed),
+        })
+    }
+
+    /// Asks the server what it supports, from the attributes of its root. Unlike the limits the
+    /// client keeps from when it connected, like `max_read`, these are fetched anew.
+    pub fn server_capabilities(&mut self) -> Result<ServerCapabilities> {
+        let attr_request = [
+            FileAttributeId::SupportedAttrs,
+            FileAttributeId::SupportedAttrsExclusiveCreate,
+            FileAttributeId::MaxRead,
+            FileAttributeId::MaxWrite,
+            FileAttributeId::LeaseTime,
+            FileAttributeId::FhExpireType,
+            FileAttributeId::AclSupport,
+        ]
+        .into_iter()
+        .filter(|a| *a == FileAttributeId::SupportedAttrs || self.supported_attrs.contains(*a))
+        .collect();
+        let mut attrs = self
+            .do_compound(ReturnSecond(PutRootFh, GetAttrArgs { attr_request }))?
+            .object_attributes;
+
+        let get = |id| attrs.get_as::<u64>(id).copied().unwrap_or_default();
+        Ok(ServerCapabilities {
+            max_read: get(FileAttributeId::MaxRead),
+            max_write: get(FileAttributeId::MaxWrite),
+            lease_time: attrs
+                .get_as::<Lease>(FileAttributeId::LeaseTime)
+                .map_or(Duration::ZERO, |lease| Duration::from_secs(lease.0.into())),
+            fh_expire_type: attrs
+                .get_as(FileAttributeId::FhExpireType)
+                .copied()
+                .unwrap_or(FhExpireType::empty()),
+            acl_support: attrs.get_as(FileAttributeId::AclSupport).copied(),
+            supported_attrs: attrs
+                .remove_as(FileAttributeId::SupportedAttrs)
+                .unwrap_or_default(),
+            supported_attrs_exclusive_create: attrs
+                .remove_as(FileAttributeId::SupportedAttrsExclusiveCreate),
+        })
+    }
+
+    /// Gets every attribute of what is at the given path, like `get_attr` of what `look_up`
+    /// returns but in a single round trip.
+    pub fn stat(&mut self, path: impl AsRef<RemotePath>) -> Result<GetAttrRes> {
+        let attr_request = self.readable_attrs();
+        self.do_path_compound(path.as_ref(), |path| {
+            ReturnSecond(
+                (PutRootFh, look_up_args(path)),
+                GetAttrArgs {
+                    attr_request: attr_request.clone(),
+                },
+            )
+        })
+    }
+
+    pub fn look_up(&mut self, path: impl AsRef<RemotePath>) -> Result<FileHandle> {
+        let path = path.as_ref();
+        if let Some(handle) = self.attr_cache.as_ref().and_then(|c| c.look_up(None, path)) {
+            return Ok(handle);
+        }
+        let handle = match self.name_cache {
+            Some(_) => self.look_up_by_name(None, path)?,
+            None => {
+                self.do_path_compound(path, |path| {
+                    ReturnSecond((PutRootFh, look_up_args(path)), GetFh)
+                })?
+                .object
+            }
+        };
+        if let Some(cache) = &mut self.attr_cache {
+            cache.insert_look_up(None, path, handle.clone());
+        }
+        self.remember_path(&handle, path);
+        Ok(handle)
+    }
+
+    /// Like `look_up`, but also gets the given attributes of what it finds in the same round trip.
+    /// Ones the server doesn't support are left out.
+    pub fn look_up_with_attrs(
+        &mut self,
+        path: impl AsRef<RemotePath>,
+        attr_request: EnumSet<FileAttributeId>,
+    ) -> Result<(FileHandle, FileAttributes)> {
+        let attr_request: EnumSet<_> = attr_request
+            .into_iter()
+            .filter(|a| self.supported_attrs.contains(*a))
+            .collect();
+        let (get_fh_res, get_attr_res) = self.do_path_compound(path.as_ref(), |path| {
+            ReturnSecond(
+                (PutRootFh, look_up_args(path)),
+                (
+                    GetFh,
+                    GetAttrArgs {
+                        attr_request: attr_request.clone(),
+                    },
+                ),
+            )
+        })?;
+        self.remember_path(&get_fh_res.object, path.as_ref());
+        Ok((get_fh_res.object, get_attr_res.object_attributes))
+    }
+
+    /// Like `look_up`, but with the path relative to the given directory.
+    pub fn look_up_from(
+        &mut self,
+        dir: FileHandle,
+        path: impl AsRef<RemotePath>,
+    ) -> Result<FileHandle> {
+        let path = path.as_ref();
+        let cache = self.attr_cache.as_ref();
+        if let Some(handle) = cache.and_then(|c| c.look_up(Some(&dir), path)) {
+            return Ok(handle);
+        }
+        let handle = match self.name_cache {
+            Some(_) => self.look_up_by_name(Some(&dir), path)?,
+            None => {
+                self.do_compound(ReturnSecond(
+                    (
+                        PutFhArgs {
+                            object: dir.clone(),
+                        },
+                        look_up_args(path),
+                    ),
+                    GetFh,
+                ))
+                .map_err(|e| e.with_path(path))?
+                .object
+            }
+        };
+        if let Some(cache) = &mut self.attr_cache {
+            cache.insert_look_up(Some(&dir), path, handle.clone());
+        }
+        self.remember_entry_path(&dir, path, &handle);
+        Ok(handle)
+    }
+
+    /// Looks up the path from the given directory, or the root if `None`, with LOOKUPs for only
+    /// the components after the deepest directory along it the name cache knows, remembering the
+    /// handle of each one it looks up.
+    fn look_up_by_name(
+        &mut self,
+        start: Option<&FileHandle>,
+        path: &RemotePath,
+    ) -> Result<FileHandle> {
+        let components: Vec<_> = path.components().collect();
+        let cache = self.name_cache.as_ref().unwrap();
+        let (known, dir) = cache.known_prefix(start, &components);
+        let from_cache = known > 0 || (start.is_none() && dir.is_some());
+        let rest = &components[known..];
+        let look_ups: Vec<_> = rest
+            .iter()
+            .map(|name| {
+                let object_name = name.to_string();
+                (LookUpArgs { object_name }, GetFh)
+            })
+            .collect();
+
+        let res = match &dir {
+            Some(dir) => self
+                .do_compound(ReturnSecond(
+                    PutFhArgs {
+                        object: dir.clone(),
+                    },
+                    look_ups,
+                ))
+                .map(|handles| (dir.clone(), handles)),
+            None => self
+                .do_compound(ReturnSecond(PutRootFh, (GetFh, look_ups)))
+                .map(|(root, handles)| (root.object, handles)),
+        };
+        let (mut handle, handles) = match res {
+            Ok(res) => res,
+            // Only looking up the whole path from the root follows referrals
+            Err(e) if e.status() == Some(StatusError::Moved) && start.is_none() => {
+                return Ok(self
+                    .do_path_compound(path, |path| {
+                        ReturnSecond((PutRootFh, look_up_args(path)), GetFh)
+                    })?
+                    .object);
+            }
+            // What we remembered is gone, and forgotten by `do_arg_array`, so this time we start
+            // from further up
+            Err(e) if e.is_stale() && from_cache => return self.look_up_by_name(start, path),
+            Err(e) => return Err(e.with_path(path)),
+        };
+
+        let cache = self.name_cache.as_mut().unwrap();
+        if dir.is_none() {
+            cache.insert_root(handle.clone());
+        }
+        for (name, (_, res)) in rest.iter().zip(handles) {
+            cache.insert(&handle, name, res.object.clone());
+            handle = res.object;
+        }
+        Ok(handle)
+    }
+
+    pub fn read(&mut self, handle: FileHandle, offset: u64, count: u32) -> Result<ReadRes> {
+        if let Some(res) = lock(&self.connection).cached_read(&handle, offset, count) {
+            return Ok(res);
+        }
+        self.read_with_state(handle, StateId::anonymous(), offset, count)
+    }
+
+    fn read_with_state(
+        &mut self,
+        handle: FileHandle,
+        state_id: StateId,
+        offset: u64,
+        count: u32,
+    ) -> Result<ReadRes> {
+        self.do_compound(ReturnSecond(
+            PutFhArgs { object: handle },
+            ReadArgs {
+                state_id,
+                offset,
+                count,
+            },
+        ))
+    }
+
+    /// Reads the whole file into the given sink. While we hold a delegation for the file its
+    /// contents are cached, unless it is large.
+    pub fn read_all(&mut self, handle: FileHandle, sink: impl io::Write) -> Result<()> {
+        let mut sink = RateLimited::new(sink, self.bandwidth_limit.clone());
+        let delegated = match lock(&self.connection).delegations.get(&handle.0) {
+            Some(Delegation {
+                contents: Some(contents),
+                ..
+            }) => {
+                sink.write_all(contents)?;
+                return Ok(());
+            }
+            delegation => delegation.is_some(),
+        };
+        if !delegated {
+            if self.pnfs.is_some() && self.pnfs_read_all(&handle, &mut sink)? {
+                return Ok(());
+            }
+            let mut pipeline = ReadPipeline::new(handle, self.read_chunk_size(), sink);
+            return self.run_pipeline(self.read_pipeline_depth, &mut pipeline);
+        }
+
+        let tee = Tee {
+            sink,
+            copy: Some(vec![]),
+        };
+        let mut pipeline = ReadPipeline::new(handle.clone(), self.read_chunk_size(), tee);
+        self.run_pipeline(self.read_pipeline_depth, &mut pipeline)?;
+        if let Some(delegation) = lock(&self.connection).delegations.get_mut(&handle.0) {
+            delegation.contents = pipeline.sink.copy.map(Bytes::from);
+        }
+        Ok(())
+    }
+
+    /// Like `read_all`, but only what comes after the given offset, for picking up where an
+    /// earlier read left off. It always reads from the server itself, never the data servers.
+    pub fn read_all_from(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        sink: impl io::Write,
+    ) -> Result<()> {
+        if offset == 0 {
+            return self.read_all(handle, sink);
+        }
+        let sink = RateLimited::new(sink, self.bandwidth_limit.clone());
+        let mut pipeline = ReadPipeline::new(handle, self.read_chunk_size(), sink);
+        pipeline.next_offset = offset;
+        pipeline.written = offset;
+        self.run_pipeline(self.read_pipeline_depth, &mut pipeline)
+    }
+
+    /// Gives back the delegation we hold for the given file, if any, which is needed before we
+    /// change it ourselves.
+    fn return_delegation(&mut self, handle: &FileHandle) -> Result<()> {
+        lock(&self.connection).return_delegation(handle)
+    }
+
+    pub fn write(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        data: impl Into<Bytes>,
+    ) -> Result<WriteRes> {
+        self.write_with_state(handle, StateId::anonymous(), offset, data.into())
+    }
+
+    fn write_with_state(
+        &mut self,
+        handle: FileHandle,
+        state_id: StateId,
+        offset: u64,
+        data: Bytes,
+    ) -> Result<WriteRes> {
+        self.return_delegation(&handle)?;
+        self.do_compound(ReturnSecond(
+            PutFhArgs { object: handle },
+            WriteArgs {
+                state_id,
+                offset,
+                stable: StableHow::FileSync,
+                data,
+            },
+        ))
+    }
+
+    /// Like `write`, but with the data read from the source while the request is being sent
+    /// instead of all of it being in memory first, so that writing takes little memory however
+    /// large the WRITE is. `len` bytes are read, or `max_write` if that is less, and the server
+    /// may take less of them than that. Since the data is gone once sent, the request isn't sent
+    /// again when it fails, and if the source fails or runs out early the connection is broken.
+    pub fn write_from(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        source: impl io::Read,
+        len: u32,
+    ) -> Result<WriteRes> {
+        let len = len.min(self.max_write());
+        self.return_delegation(&handle)?;
+        self.clear_attr_cache();
+        let request = ReturnSecond(
+            PutFhArgs { object: handle },
+            WriteArgs {
+                state_id: StateId::anonymous(),
+                offset,
+                stable: StableHow::FileSync,
+                data: Bytes::new(),
+            },
+        );
+        let (mut arg_array, geometry) = request.into_arg_array();
+        self.use_renewed_handles(&mut arg_array);
+        let compound_reply =
+            lock(&self.connection).do_arg_array_streaming(arg_array, source, len)?;
+        process_compound_reply::<ReturnSecond<SequenceArgs, ReturnSecond<PutFhArgs, WriteArgs>>>(
+            compound_reply,
+            ((), geometry),
+        )
+    }
+
+    /// Reads up to `len` bytes at the given offset with a single READ, like `pread`. Less than
+    /// asked for can come back before the end of the file, for instance when `len` is more than
+    /// `max_read`, the reply says whether the end was reached.
+    pub fn read_at(&mut self, handle: FileHandle, offset: u64, len: usize) -> Result<ReadRes> {
+        let count = len.min(self.max_read() as usize) as u32;
+        self.read(handle, offset, count)
+    }
+
+    /// Writes as much of the data as the server takes in a single WRITE at the given offset, like
+    /// `pwrite`, and returns how much that was. It is on stable storage once this returns.
+    pub fn write_at(&mut self, handle: FileHandle, offset: u64, data: &[u8]) -> Result<usize> {
+        self.write_at_with_stability(handle, offset, data, StableHow::FileSync)
+    }
+
+    /// Like `write_at`, but asking for the given stability. What UNSTABLE writes send is kept
+    /// until a `commit` covering it succeeds, unless the server puts it on stable storage anyway,
+    /// so that it can be sent again if the server loses it before then.
+    pub fn write_at_with_stability(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        data: &[u8],
+        stable: StableHow,
+    ) -> Result<usize> {
+        let len = data.len().min(self.max_write() as usize);
+        let data = Bytes::copy_from_slice(&data[..len]);
+        self.return_delegation(&handle)?;
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: handle.clone(),
+            },
+            WriteArgs {
+                state_id: StateId::anonymous(),
+                offset,
+                stable,
+                data: data.clone(),
+            },
+        ))?;
+        let count = (res.count as usize).min(len);
+        if res.committed == StableHow::Unstable && count > 0 {
+            self.unstable_writes
+                .entry(handle.0)
+                .or_default()
+                .push(UnstableWrite {
+                    offset,
+                    data: data.slice(..count),
+                    verifier: res.write_veritifer,
+                });
+        }
+        Ok(count)
+    }
+
+    /// Checks whether the given attributes of the given object still have the given values.
+    pub fn verify(&mut self, handle: FileHandle, attrs: FileAttributes) -> Result<bool> {
+        let res = self.do_compound((
+            PutFhArgs { object: handle },
+            VerifyArgs {
+                object_attributes: attrs,
+            },
+        ));
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.status() == Some(StatusError::NotSame) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `write`, but only if the given attributes of the file still have the given values,
+    /// checked in the same COMPOUND so nothing can change in between. For instance, appending only
+    /// if the size is still what we think it is. Fails with `NotSame` if they don't match.
+    pub fn write_if_unchanged(
+        &mut self,
+        handle: FileHandle,
+        expected: FileAttributes,
+        offset: u64,
+        data: impl Into<Bytes>,
+    ) -> Result<WriteRes> {
+        self.return_delegation(&handle)?;
+        self.do_compound(ReturnSecond(
+            (
+                PutFhArgs { object: handle },
+                VerifyArgs {
+                    object_attributes: expected,
+                },
+            ),
+            WriteArgs {
+                state_id: StateId::anonymous(),
+                offset,
+                stable: StableHow::FileSync,
+                data: data.into(),
+            },
+        ))
+    }
+
+    /// Asks the server to put what was written to the given range of the file with UNSTABLE
+    /// writes on stable storage, a `count` of 0 meaning to the end of the file. If the verifier it
+    /// returns isn't the one the writes of `write_at_with_stability` in the range got, it lost
+    /// them, most likely by restarting, and they are sent again before committing once more.
+    pub fn commit(&mut self, handle: FileHandle, offset: u64, count: u32) -> Result<CommitRes> {
+        let end = match count {
+            0 => u64::MAX,
+            count => offset.saturating_add(count.into()),
+        };
+        let covered = |write: &UnstableWrite| write.offset >= offset && write.end() <= end;
+        let mut resends = 0;
+        loop {
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: handle.clone(),
+                },
+                CommitArgs { offset, count },
+            ))?;
+            let Some(mut writes) = self.unstable_writes.remove(&handle.0) else {
+                return Ok(res);
+            };
+            let lost = writes
+                .iter()
+                .position(|w| covered(w) && w.verifier != res.write_verifier);
+            let Some(first_lost) = lost else {
+                writes.retain(|w| !covered(w));
+                if !writes.is_empty() {
+                    self.unstable_writes.insert(handle.0.clone(), writes);
+                }
+                return Ok(res);
+            };
+            if resends == MAX_WRITE_RESENDS {
+                self.unstable_writes.insert(handle.0.clone(), writes);
+                let message = "the server keeps losing unstable writes before committing them";
+                return Err(io::Error::other(message).into());
+            }
+            resends += 1;
+
+            // Everything after the first lost write goes again too, in the order it was written,
+            // so that where writes overlap the later ones still end up on top
+            let mut sent = Ok(());
+            for write in writes[first_lost..].iter_mut().filter(|w| covered(w)) {
+                sent = self.resend_write(&handle, write);
+                if sent.is_err() {
+                    break;
+                }
+            }
+            self.unstable_writes.insert(handle.0.clone(), writes);
+            sent?;
+        }
+    }
+
+    fn resend_write(&mut self, handle: &FileHandle, write: &mut UnstableWrite) -> Result<()> {
+        let mut sent = 0;
+        while sent < write.data.len() {
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: handle.clone(),
+                },
+                WriteArgs {
+                    state_id: StateId::anonymous(),
+                    offset: write.offset + sent as u64,
+                    stable: StableHow::Unstable,
+                    data: write.data.slice(sent..),
+                },
+            ))?;
+            if res.count == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            sent += res.count as usize;
+            write.verifier = res.write_veritifer;
+        }
+        Ok(())
+    }
+
+    /// Writes everything from the source to the file using UNSTABLE writes, with several in
+    /// flight at once, followed by COMMITs. Data is resent if the server loses it before it is
+    /// committed. See `set_write_stability` for writing stable data instead.
+    pub fn write_all(&mut self, handle: FileHandle, source: impl io::Read) -> Result<()> {
+        // Pipelined WRITEs, and ones to data servers, don't go through `do_arg_array`
+        self.clear_attr_cache();
+        let mut source = RateLimited::new(source, self.bandwidth_limit.clone());
+        self.return_delegation(&handle)?;
+        if self.pnfs.is_some() && self.pnfs_write_all(&handle, &mut source)? {
+            return Ok(());
+        }
+        self.write_pipeline(handle, 0, source)
+    }
+
+    /// Like `write_all`, but writing the source to the file from the given offset on, for picking
+    /// up where an earlier write left off. It always writes to the server itself, never the data
+    /// servers.
+    pub fn write_all_at(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        source: impl io::Read,
+    ) -> Result<()> {
+        self.clear_attr_cache();
+        self.return_delegation(&handle)?;
+        let source = RateLimited::new(source, self.bandwidth_limit.clone());
+        self.write_pipeline(handle, offset, source)
+    }
+
+    fn write_pipeline(
+        &mut self,
+        handle: FileHandle,
+        offset: u64,
+        source: impl io::Read,
+    ) -> Result<()> {
+        let chunk_size = self.write_chunk_size() as usize;
+        let mut pipeline = WritePipeline::new(handle.clone(), chunk_size, source);
+        pipeline.next_offset = offset;
+        pipeline.stable = self.write_stability;
+        while !pipeline.finished() {
+            self.run_pipeline(self.write_pipeline_depth, &mut pipeline)?;
+            if !pipeline.uncommitted.is_empty() {
+                let commit_res = self.commit(handle.clone(), 0, 0)?;
+                pipeline.handle_commit(commit_res);
+            }
+        }
+        Ok(())
+    }
+
+    // Everything we open is opened by the same open-owner
+    fn open_owner(&self) -> StateOwner {
+        StateOwner {
+            client_id: self.client_id,
+            opaque: self.client_owner.owner_id.clone(),
+        }
+    }
+
+    /// Creates the given file in the given directory and returns it opened for writing.
+    pub fn create_file(&mut self, parent: FileHandle, name: &str) -> Result<OpenFile<TransportT>> {
+        let options = OpenOptions {
+            access: ShareAccess::WRITE,
+            deny: ShareDeny::NONE,
+            create: Some(CreateHow::Exclusive {
+                create_verifier: Verifier(0),
+            }),
+            read_delegation: false,
+        };
+        self.open(parent, name, &options)
+    }
+
+    /// Opens the given file in the given directory, taking a share reservation on it as described
+    /// by the options. The reservation is held until the file is closed with `close` or dropped.
+    pub fn open(
+        &mut self,
+        parent: FileHandle,
+        name: &str,
+        options: &OpenOptions,
+    ) -> Result<OpenFile<TransportT>> {
+        let claim = OpenClaim::Null { file: name.into() };
+        let file = self
+            .open_with_claim(parent.clone(), claim, options)
+            .map_err(|e| e.with_path(name))?;
+        self.remember_entry_path(&parent, name, &file.handle);
+        Ok(file)
+    }
+
+    /// Opens a file, which is either `object` itself or an entry of it depending on the claim.
+    fn open_with_claim(
+        &mut self,
+        object: FileHandle,
+        claim: OpenClaim,
+        options: &OpenOptions,
+    ) -> Result<OpenFile<TransportT>> {
+        let open_how = match &options.create {
+            Some(how) => OpenFlag::OpenCreate(how.clone()),
+            None => OpenFlag::OpenNoCreate,
+        };
+        let mut share_access = options.access;
+        if options.read_delegation {
+            share_access |= ShareAccess::WANT_READ_DELEG;
+        }
+        let owner = self.open_owner();
+        let (_, open_res, get_fh_res) = self.do_compound((
+            PutFhArgs { object },
+            OpenArgs {
+                sequence_id: SequenceId(0),
+                share_access,
+                share_deny: options.deny,
+                owner,
+                open_how,
+                claim,
+            },
+            GetFh,
+        ))?;
+
+        let handle = get_fh_res.object;
+        let mut connection = lock(&self.connection);
+        connection.opened(&handle, open_res.state_id, options.access, options.deny);
+        if let OpenDelegation::Read { read } = open_res.delegation {
+            connection
+                .delegations
+                .entry(handle.0.clone())
+                .or_insert(Delegation {
+                    state_id: read.state_id,
+                    attrs: None,
+                    contents: None,
+                });
+        }
+        drop(connection);
+        Ok(OpenFile {
+            handle,
+            state_id: open_res.state_id,
+            access: options.access,
+            deny: options.deny,
+            connection: Arc::downgrade(&self.connection),
+        })
+    }
+
+    /// Gives up some of the access or deny modes of an open file. The new modes must be a subset
+    /// of the ones the file was opened with. Since the server keeps one open per file for us, this
+    /// also applies to any other `OpenFile` we have for the same file.
+    pub fn open_downgrade(
+        &mut self,
+        file: &mut OpenFile<TransportT>,
+        access: ShareAccess,
+        deny: ShareDeny,
+    ) -> Result<()> {
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: file.handle.clone(),
+            },
+            OpenDowngradeArgs {
+                open_state_id: file.state_id,
+                sequence_id: SequenceId(0),
+                share_access: access,
+                share_deny: deny,
+            },
+        ))?;
+        file.state_id = res.open_state_id;
+        file.access = access;
+        file.deny = deny;
+        let mut connection = lock(&self.connection);
+        connection.update_open(&file.handle, res.open_state_id);
+        if let Some(open) = connection.opens.get_mut(&file.handle.0) {
+            open.access = access;
+            open.deny = deny;
+        }
+        Ok(())
+    }
+
+    /// Closes an open file, releasing its share reservation once no other `OpenFile` for the same
+    /// file is left. Unlike dropping it, this reports whether the server accepted the CLOSE.
+    pub fn close(&mut self, mut file: OpenFile<TransportT>) -> Result<()> {
+        file.connection = Weak::new();
+        let Some(request) = lock(&self.connection).release_open(&file.handle) else {
+            return Ok(());
+        };
+        self.do_compound(request)?;
+        Ok(())
+    }
+
+    /// Watches the given directory for entries being created, removed or renamed until the
+    /// returned `DirWatcher` is dropped. If the server grants us a directory delegation it
+    /// notifies us of changes, otherwise the directory is polled for them. Either way a thread
+    /// keeps talking to the server, which is when we hear about them.
+    pub fn watch_dir(&mut self, dir: FileHandle) -> Result<DirWatcher<TransportT>>
+    where
+        TransportT: Send + 'static,
+    {
+        let no_delay = Time {
+            seconds: 0,
+            nseconds: 0,
+        };
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: dir.clone(),
+            },
+            GetDirDelegationArgs {
+                signal_delegation_available: false,
+                notification_types: [
+                    NotifyType::AddEntry,
+                    NotifyType::RemoveEntry,
+                    NotifyType::RenameEntry,
+                ]
+                .into_iter()
+                .collect(),
+                child_attr_delay: no_delay,
+                dir_attr_delay: no_delay,
+                child_attributes: Default::default(),
+                dir_attributes: Default::default(),
+            },
+        ));
+        let delegation = match res {
+            Ok(GetDirDelegationRes::Ok(ok)) => Some(ok.state_id),
+            Ok(GetDirDelegationRes::Unavailable { .. }) => None,
+            // Plenty of servers don't support directory delegations at all
+            Err(e) if e.status().is_some() => None,
+            Err(e) => return Err(e),
+        };
+
+        // Listing after getting the delegation means we don't miss anything in between
+        let change = self
+            .get_attr(dir.clone())?
+            .object_attributes
+            .get_as::<Change>(FileAttributeId::Change)
+            .copied();
+        let names = self
+            .read_dir(dir.clone(), Default::default())
+            .map(|e| e.map(|e| e.name))
+            .collect::<Result<_>>()?;
+
+        let (events_sender, events) = mpsc::channel();
+        let watch = WatchState::new(events_sender, delegation, change, names);
+        lock(&self.connection).watches.insert(dir.0.clone(), watch);
+        Ok(DirWatcher::new(dir, events, &self.connection))
+    }
+
+    /// Lists the entries of the given directory, fetching them from the server a page at a time
+    /// as the iterator is advanced. With the attribute cache on, a listing gone through to the end
+    /// is remembered, see `set_attr_cache`.
+    pub fn read_dir(
+        &mut self,
+        handle: FileHandle,
+        attr_request: EnumSet<FileAttributeId>,
+    ) -> ReadDirIter<'_, TransportT> {
+        let attr_request: EnumSet<_> = attr_request
+            .into_iter()
+            .filter(|a| self.supported_attrs.contains(*a))
+            .collect();
+        let mut state = ReadDirState::new(handle.clone(), attr_request.clone());
+        let Some(cache) = &self.attr_cache else {
+            return ReadDirIter {
+                client: self,
+                state,
+                listing: None,
+            };
+        };
+
+        let cached_change = match cache.listing(&handle, &attr_request) {
+            CachedListing::Fresh(entries) => {
+                state.cached(entries);
+                return ReadDirIter {
+                    client: self,
+                    state,
+                    listing: None,
+                };
+            }
+            CachedListing::Stale(change) => Some(change),
+            CachedListing::Missing => None,
+        };
+        // Getting the change attribute before listing means a change made in between makes what
+        // we remember stale, rather than it being missed
+        let listing = match self.change_attr(&handle) {
+            Ok(Some(change)) if Some(change) == cached_change => {
+                let cache = self.attr_cache.as_mut().unwrap();
+                state.cached(cache.renew_listing(&handle));
+                None
+            }
+            Ok(Some(change)) => Some((change, vec![])),
+            // Then the listing isn't remembered, and if the directory is gone listing it fails too
+            Ok(None) | Err(_) => None,
+        };
+        ReadDirIter {
+            client: self,
+            state,
+            listing,
+        }
+    }
+
+    fn change_attr(&mut self, handle: &FileHandle) -> Result<Option<Change>> {
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: handle.clone(),
+            },
+            GetAttrArgs {
+                attr_request: [FileAttributeId::Change].into_iter().collect(),
+            },
+        ))?;
+        Ok(res
+            .object_attributes
+            .get_as::<Change>(FileAttributeId::Change)
+            .copied())
+    }
+
+    /// Walks the directory hierarchy under the given directory depth-first, returning each entry
+    /// before its contents. Symbolic links are returned but not followed. An error reading one
+    /// directory is returned in place of its contents and the walk carries on with the rest.
+    pub fn walk(
+        &mut self,
+        root: FileHandle,
+        attr_request: EnumSet<FileAttributeId>,
+    ) -> Walk<'_, TransportT> {
+        let walk_attrs = [
+            FileAttributeId::Type,
+            FileAttributeId::FileHandle,
+            FileAttributeId::FsId,
+            FileAttributeId::FileId,
+        ];
+        let attr_request = attr_request
+            .into_iter()
+            .chain(walk_attrs)
+            .filter(|a| self.supported_attrs.contains(*a))
+            .collect();
+        Walk {
+            client: self,
+            root: Some(root),
+            attr_request,
+            stack: vec![],
+        }
+    }
+
+    pub fn read_link(&mut self, handle: FileHandle) -> Result<String> {
+        Ok(self
+            .do_compound(ReturnSecond(PutFhArgs { object: handle }, ReadLink))?
+            .link)
+    }
+
+    /// Changes the owner or group of the object to the given local user or group, mapped with
+    /// the client's `IdMapper`.
+    pub fn set_owner(
+        &mut self,
+        handle: FileHandle,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<()> {
+        let attrs = [
+            uid.map(|uid| FileAttribute::Owner(self.id_mapper.owner(uid))),
+            gid.map(|gid| FileAttribute::OwnerGroup(self.id_mapper.group(gid))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        self.set_attr(handle, attrs)?;
+        Ok(())
+    }
+
+    /// Sets the given attributes of the object, see `FileAttributesBuilder`, returning which ones
+    /// the server set. Read-only attributes can't be set, asking to is `StatusError::Inval`
+    /// without asking the server.
+    pub fn set_attr(
+        &mut self,
+        handle: FileHandle,
+        attrs: FileAttributes,
+    ) -> Result<EnumSet<FileAttributeId>> {
+        if attrs.iter().any(|attr| !attr.to_id().is_settable()) {
+            return Err(StatusError::Inval.into());
+        }
+        self.return_delegation(&handle)?;
+        Ok(self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: handle },
+                SetAttrArgs {
+                    state_id: StateId::anonymous(),
+                    object_attributes: attrs,
+                },
+            ))?
+            .attr_set)
+    }
+
+    /// Gets the security label of the given object, for labeled NFS, see RFC 7204. Only NFSv4.2
+    /// servers exporting with security labels have them.
+    pub fn get_label(&mut self, handle: FileHandle) -> Result<SecLabel> {
+        if !self.supported_attrs.contains(FileAttributeId::SecLabel) {
+            return Err(StatusError::AttrNotSupported.into());
+        }
+        self.do_compound(ReturnSecond(
+            PutFhArgs { object: handle },
+            GetAttrArgs {
+                attr_request: [FileAttributeId::SecLabel].into_iter().collect(),
+            },
+        ))?
+        .object_attributes
+        .remove_as(FileAttributeId::SecLabel)
+        .ok_or_else(|| StatusError::AttrNotSupported.into())
+    }
+
+    pub fn set_label(&mut self, handle: FileHandle, label: SecLabel) -> Result<()> {
+        self.set_attr(
+            handle,
+            [FileAttribute::SecLabel(label)].into_iter().collect(),
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, handle: FileHandle, entry_name: &str) -> Result<ChangeInfo> {
+        Ok(self
+            .do_compound(ReturnSecond(
+                PutFhArgs { object: handle },
+                RemoveArgs {
+                    target: entry_name.into(),
+                },
+            ))
+            .map_err(|e| e.with_path(entry_name))?
+            .change_info)
+    }
+
+    pub fn rename(
+        &mut self,
+        src_dir: FileHandle,
+        target_dir: FileHandle,
+        src_entry: &str,
+        target_entry: &str,
+    ) -> Result<RenameRes> {
+        self.do_compound(ReturnSecond(
+            (
+                PutFhArgs { object: src_dir },
+                SaveFh,
+                PutFhArgs { object: target_dir },
+            ),
+            RenameArgs {
+                old_name: src_entry.to_owned(),
+                new_name: target_entry.to_owned(),
+            },
+        ))
+        .map_err(|e| e.with_path(src_entry))
+    }
+
+    pub fn create_symlink(
+        &mut self,
+        parent_dir: FileHandle,
+        name: &str,
+        target: &str,
+        attrs: FileAttributes,
+    ) -> Result<FileHandle> {
+        Ok(self
+            .do_compound(ReturnSecond(
+                (
+                    PutFhArgs { object: parent_dir },
+                    CreateArgs {
+                        object_type: CreateType::Link(target.to_owned()),
+                        object_name: name.to_owned(),
+                        create_attrs: attrs,
+                    },
+                ),
+                GetFh,
+            ))
+            .map_err(|e| e.with_path(name))?
+            .object)
+    }
+
+    pub fn create_directory(
+        &mut self,
+        parent_dir: FileHandle,
+        name: &str,
+        attrs: FileAttributes,
+    ) -> Result<FileHandle> {
+        let handle = self
+            .do_compound(ReturnSecond(
+                (
+                    PutFhArgs {
+                        object: parent_dir.clone(),
+                    },
+                    CreateArgs {
+                        object_type: CreateType::Directory,
+                        object_name: name.to_owned(),
                         create_attrs: attrs,
                     },
                 ),
                 GetFh,
+            ))
+            .map_err(|e| e.with_path(name))?
+            .object;
+        self.remember_entry_path(&parent_dir, name, &handle);
+        Ok(handle)
+    }
+}
+
+/// How `Client::open` opens a file. By default it is opened for reading without denying anyone
+/// else access, and it must already exist.
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    access: ShareAccess,
+    deny: ShareDeny,
+    create: Option<CreateHow>,
+    read_delegation: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            access: ShareAccess::READ,
+            deny: ShareDeny::NONE,
+            create: None,
+            read_delegation: false,
+        }
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What we want to do with the file: `READ`, `WRITE` or `BOTH`.
+    pub fn access(mut self, access: ShareAccess) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// What others are kept from doing with the file while we have it open: `NONE`, `READ`,
+    /// `WRITE` or `BOTH`. Opening fails with `ShareDenied` if this conflicts with someone else's
+    /// open.
+    pub fn deny(mut self, deny: ShareDeny) -> Self {
+        self.deny = deny;
+        self
+    }
+
+    /// Creates the file if it doesn't exist.
+    pub fn create(mut self) -> Self {
+        self.create = Some(CreateHow::Unchecked {
+            create_attrs: Default::default(),
+        });
+        self
+    }
+
+    /// Creates the file, failing with `Exist` if it already exists.
+    pub fn create_new(mut self) -> Self {
+        self.create = Some(CreateHow::Guarded {
+            create_attrs: Default::default(),
+        });
+        self
+    }
+
+    /// Asks for a read delegation, a promise that nobody else will change the file, which lets
+    /// its attributes and contents be cached until it is closed. The server decides whether to
+    /// grant one.
+    pub fn read_delegation(mut self) -> Self {
+        self.read_delegation = true;
+        self
+    }
+}
+
+/// A file opened by `Client::open`, holding the state the server gave us for the open. It is
+/// closed when dropped, ignoring any error, use `Client::close` to find out about them instead.
+#[derive(Debug)]
+pub struct OpenFile<TransportT: Transport> {
+    pub handle: FileHandle,
+    pub state_id: StateId,
+    pub access: ShareAccess,
+    pub deny: ShareDeny,
+    connection: Weak<Mutex<Connection<TransportT>>>,
+}
+
+impl<TransportT: Transport> Drop for OpenFile<TransportT> {
+    fn drop(&mut self) {
+        // If the client is already gone, so is our session and the server will clean up
+        if let Some(connection) = self.connection.upgrade() {
+            let _ = lock(&connection).close(&self.handle);
+        }
+    }
+}
+
+/// The sizes of a file system, as returned by `Client::statfs`. Space is in bytes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FsStat {
+    pub space_total: u64,
+    pub space_free: u64,
+    /// How much of the free space we may use.
+    pub space_avail: u64,
+    pub files_total: u64,
+    pub files_free: u64,
+    /// How many of the free files we may create.
+    pub files_avail: u64,
+}
+
+/// A user's quota, as returned by `Client::quota`, in bytes. What the server doesn't support, or
+/// doesn't limit, is `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quota {
+    /// How much more may be used before running into the hard limit.
+    pub avail_hard: Option<u64>,
+    /// How much more may be used before running into the soft limit.
+    pub avail_soft: Option<u64>,
+    pub used: Option<u64>,
+}
+
+/// What the server supports, as returned by `Client::server_capabilities`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub supported_attrs: EnumSet<FileAttributeId>,
+    /// The attributes an exclusive create can set along with the verifier, or `None` for
+    /// servers of minor version 0, which can't set any.
+    pub supported_attrs_exclusive_create: Option<EnumSet<FileAttributeId>>,
+    /// The most a READ returns, before any limit of the session.
+    pub max_read: u64,
+    /// The most a WRITE takes, before any limit of the session.
+    pub max_write: u64,
+    pub lease_time: Duration,
+    /// When file handles may stop working, empty if they are persistent.
+    pub fh_expire_type: FhExpireType,
+    /// Which kinds of ACE the server supports, or `None` if it has no ACLs.
+    pub acl_support: Option<AclSupport>,
+}
+
+// How many times to start a listing over when the server says our cookie verifier is stale
+const MAX_READ_DIR_RESTARTS: usize = 3;
+
+// Where we are in listing a directory, kept separate from the client so that the walker can hold
+// one for each directory it is in the middle of.
+struct ReadDirState {
+    handle: FileHandle,
+    attr_request: EnumSet<FileAttributeId>,
+    cookie: Cookie,
+    cookie_verifier: Verifier,
+    page: VecDeque<DirectoryEntry>,
+    eof: bool,
+    restarts: usize,
+    // The names of the entries returned so far, so that starting over doesn't return them again
+    returned: BTreeSet<String>,
+}
+
+impl ReadDirState {
+    fn new(handle: FileHandle, attr_request: EnumSet<FileAttributeId>) -> Self {
+        Self {
+            handle,
+            attr_request,
+            cookie: Cookie::initial(),
+            cookie_verifier: Verifier(0),
+            page: VecDeque::new(),
+            eof: false,
+            restarts: 0,
+            returned: BTreeSet::new(),
+        }
+    }
+
+    fn fetch_page<TransportT: Transport>(&mut self, client: &mut Client<TransportT>) -> Result<()> {
+        let max_count = client.max_read();
+        let res = client.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: self.handle.clone(),
+            },
+            ReadDirArgs {
+                cookie: self.cookie,
+                cookie_verifier: self.cookie_verifier.clone(),
+                directory_count: max_count,
+                max_count,
+                attr_request: self.attr_request.clone(),
+            },
+        ));
+
+        let res = match res {
+            Err(e)
+                if matches!(
+                    e.status(),
+                    Some(StatusError::NotSame | StatusError::BadCookie)
+                ) && self.restarts < MAX_READ_DIR_RESTARTS =>
+            {
+                self.restart();
+                return Ok(());
+            }
+            res => res?,
+        };
+
+        self.cookie_verifier = res.cookie_verifier;
+        self.eof = res.reply.eof;
+        if let Some(last) = res.reply.entries.last() {
+            self.cookie = last.cookie;
+        } else if !self.eof {
+            return Err(Error::CompoundResponseMismatch(
+                "READDIR returned no entries before the end of the directory".into(),
+            ));
+        }
+        self.page.extend(res.reply.entries);
+        Ok(())
+    }
+
+    /// Returns the given entries instead of listing the directory.
+    fn cached(&mut self, entries: Vec<DirectoryEntry>) {
+        self.page = entries.into();
+        self.eof = true;
+    }
+
+    /// The directory changed in a way that invalidated our cookie, so start over from the
+    /// beginning. Entries with names already returned are skipped, so entries added or removed
+    /// before where we were don't make us return others twice or not at all.
+    fn restart(&mut self) {
+        self.page.clear();
+        self.cookie = Cookie::initial();
+        self.cookie_verifier = Verifier(0);
+        self.restarts += 1;
+    }
+
+    fn next_entry<TransportT: Transport>(
+        &mut self,
+        client: &mut Client<TransportT>,
+    ) -> Result<Option<DirectoryEntry>> {
+        loop {
+            while let Some(entry) = self.page.pop_front() {
+                if self.returned.insert(entry.name.clone()) {
+                    return Ok(Some(entry));
+                }
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            self.fetch_page(client)?;
+        }
+    }
+}
+
+pub struct ReadDirIter<'a, TransportT> {
+    client: &'a mut Client<TransportT>,
+    state: ReadDirState,
+    // The change attribute the directory had before we started and the entries so far, for the
+    // attribute cache to remember if we get to the end
+    listing: Option<(Change, Vec<DirectoryEntry>)>,
+}
+
+impl<TransportT: Transport> Iterator for ReadDirIter<'_, TransportT> {
+    type Item = Result<DirectoryEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.state.next_entry(self.client).transpose();
+        match &res {
+            Some(Ok(entry)) => {
+                if let Some((_, entries)) = &mut self.listing {
+                    entries.push(entry.clone());
+                }
+            }
+            Some(Err(_)) => {
+                self.state.eof = true;
+                self.state.page.clear();
+                self.listing = None;
+            }
+            // Having to start over means the directory changed while we were listing it
+            None if self.state.restarts > 0 => self.listing = None,
+            None => {
+                let cache = self.client.attr_cache.as_mut();
+                if let (Some(cache), Some((change, entries))) = (cache, self.listing.take()) {
+                    let attr_request = self.state.attr_request.clone();
+                    cache.insert_listing(&self.state.handle, attr_request, change, entries);
+                }
+            }
+        }
+        res
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WalkEntry {
+    /// The path of the entry relative to the root of the walk
+    pub path: RemotePathBuf,
+    /// How many directories down from the root the entry is, starting at 1
+    pub depth: usize,
+    pub handle: FileHandle,
+    pub attrs: FileAttributes,
+}
+
+impl WalkEntry {
+    pub fn file_type(&self) -> Option<&FileType> {
+        self.attrs.get_as(FileAttributeId::Type)
+    }
+}
+
+/// An error encountered while walking, along with the path of the entry it happened on.
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: RemotePathBuf,
+    pub error: Error,
+}
+
+struct WalkFrame {
+    path: RemotePathBuf,
+    id: Option<(FsId, FileId)>,
+    listing: ReadDirState,
+}
+
+fn walk_id(attrs: &FileAttributes) -> Option<(FsId, FileId)> {
+    Some((
+        *attrs.get_as(FileAttributeId::FsId)?,
+        *attrs.get_as(FileAttributeId::FileId)?,
+    ))
+}
+
+pub struct Walk<'a, TransportT> {
+    client: &'a mut Client<TransportT>,
+    root: Option<FileHandle>,
+    attr_request: EnumSet<FileAttributeId>,
+    stack: Vec<WalkFrame>,
+}
+
+impl<TransportT: Transport> Walk<'_, TransportT> {
+    fn start(&mut self, root: FileHandle) -> Result<()> {
+        let attrs = self
+            .client
+            .do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: root.clone(),
+                },
+                GetAttrArgs {
+                    attr_request: [FileAttributeId::FsId, FileAttributeId::FileId]
+                        .into_iter()
+                        .collect(),
+                },
+            ))?
+            .object_attributes;
+        self.stack.push(WalkFrame {
+            path: RemotePathBuf::new(),
+            id: walk_id(&attrs),
+            listing: ReadDirState::new(root, self.attr_request.clone()),
+        });
+        Ok(())
+    }
+
+    fn entry_handle(
+        &mut self,
+        parent: &FileHandle,
+        entry: &mut DirectoryEntry,
+    ) -> Result<FileHandle> {
+        if let Some(handle) = entry.attrs.remove_as(FileAttributeId::FileHandle) {
+            return Ok(handle);
+        }
+        Ok(self
+            .client
+            .do_compound(ReturnSecond(
+                (
+                    PutFhArgs {
+                        object: parent.clone(),
+                    },
+                    LookUpArgs {
+                        object_name: entry.name.clone(),
+                    },
+                ),
+                GetFh,
             ))?
             .object)
     }
+
+    fn next_entry(&mut self) -> Option<std::result::Result<WalkEntry, WalkError>> {
+        if let Some(root) = self.root.take() {
+            if let Err(error) = self.start(root) {
+                return Some(Err(WalkError {
+                    path: RemotePathBuf::new(),
+                    error,
+                }));
+            }
+        }
+
+        loop {
+            let frame = self.stack.last_mut()?;
+            let mut entry = match frame.listing.next_entry(self.client) {
+                Ok(Some(entry)) => entry,
+                Ok(None) => {
+                    self.stack.pop();
+                    continue;
+                }
+                Err(error) => {
+                    let frame = self.stack.pop().unwrap();
+                    return Some(Err(WalkError {
+                        path: frame.path,
+                        error,
+                    }));
+                }
+            };
+
+            let path = frame.path.join(&entry.name);
+            let parent = frame.listing.handle.clone();
+            let handle = match self.entry_handle(&parent, &mut entry) {
+                Ok(handle) => handle,
+                Err(error) => return Some(Err(WalkError { path, error })),
+            };
+
+            // Directories are only descended into if they aren't one of the directories we are
+            // already in the middle of, so that loops in the hierarchy don't make us walk forever.
+            let is_dir = entry.attrs.get_as(FileAttributeId::Type) == Some(&FileType::Directory);
+            let id = walk_id(&entry.attrs);
+            let is_loop = id.is_some() && self.stack.iter().any(|f| f.id == id);
+
+            let depth = self.stack.len();
+            if is_dir && !is_loop {
+                self.stack.push(WalkFrame {
+                    path: path.clone(),
+                    id,
+                    listing: ReadDirState::new(handle.clone(), self.attr_request.clone()),
+                });
+            }
+
+            return Some(Ok(WalkEntry {
+                path,
+                depth,
+                handle,
+                attrs: entry.attrs,
+            }));
+        }
+    }
+}
+
+impl<TransportT: Transport> Iterator for Walk<'_, TransportT> {
+    type Item = std::result::Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry()
+    }
+}
+
+#[cfg(test)]
+use nfs4_server::{memory::MemoryFs, pipe::Pipe, Server};
+
+#[cfg(test)]
+fn in_memory_client(files: MemoryFs) -> (Server<MemoryFs>, Client<Pipe>) {
+    let server = Server::with_file_system(files);
+    let client = Client::new(server.connect_in_process()).unwrap();
+    (server, client)
+}
+
+#[test]
+fn negotiates_rpcsec_gss() {
+    use sun_rpc::gss::TestContext;
+
+    let mut files = MemoryFs::new();
+    files.make_dir("secure").unwrap();
+    files.write_file("secure/a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let gss = SecurityInfo::RpcSecGss {
+        flavor_info: RpcSecGssInfo {
+            oid: SecOid(vec![1, 2, 3]),
+            qop: Qop(0),
+            service: RpcGssService::Integrity,
+        },
+    };
+    server.set_security("/secure", vec![gss.clone()]).unwrap();
+    server.accept_gss(|| Box::new(TestContext::new(7)));
+
+    // The root is still fine, the LOOKUP of what is in it isn't
+    let error = client.look_up("/secure/a_file").unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::WrongSec));
+    let root = client.look_up("/").unwrap();
+    assert_eq!(client.sec_info(root, "secure").unwrap(), [gss]);
+
+    client.set_gss(|info| {
+        assert_eq!(info.service, RpcGssService::Integrity);
+        Some(Box::new(TestContext::new(7)))
+    });
+    let handle = client.look_up("/secure/a_file").unwrap();
+    assert_eq!(client.read(handle, 0, 5).unwrap().data, b"hello"[..]);
+}
+
+#[test]
+fn stale_handle() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+
+    let handle = client.look_up("/a_file").unwrap();
+    server
+        .update_file_system(|files| files.delete("a_file"))
+        .unwrap();
+    assert!(client.get_attr(handle.clone()).unwrap_err().is_stale());
+    assert!(client.read(handle, 0, 5).unwrap_err().is_stale());
+}
+
+#[test]
+fn server_capabilities() {
+    let mut files = MemoryFs::new();
+    files.set_fh_expire_type(FhExpireType::VOLATILE_ANY);
+    let (_server, mut client) = in_memory_client(files);
+
+    let capabilities = client.server_capabilities().unwrap();
+    assert!(capabilities
+        .supported_attrs
+        .contains(FileAttributeId::TimeModifySet));
+    assert_eq!(capabilities.supported_attrs_exclusive_create, None);
+    assert_eq!(capabilities.max_read, 1024 * 1024);
+    assert_eq!(capabilities.max_write, 1024 * 1024);
+    assert_eq!(capabilities.lease_time, Duration::from_secs(90));
+    assert_eq!(capabilities.fh_expire_type, FhExpireType::VOLATILE_ANY);
+    assert_eq!(capabilities.acl_support, None);
+}
+
+#[test]
+fn retries_while_server_delays() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 2);
+    assert_eq!(
+        &client.read(handle.clone(), 0, 5).unwrap().data[..],
+        b"hello"
+    );
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 1);
+    let mut data = vec![];
+    client.read_all(handle, &mut data).unwrap();
+    assert_eq!(data, b"hello");
+}
+
+#[test]
+fn read_and_write_from_offset() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello world").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    client.set_read_chunk_size(Some(4));
+    let handle = client.look_up("/a_file").unwrap();
+
+    let mut data = vec![];
+    client.read_all_from(handle.clone(), 6, &mut data).unwrap();
+    assert_eq!(data, b"world");
+
+    let mut data = vec![];
+    client.read_all_from(handle.clone(), 11, &mut data).unwrap();
+    assert_eq!(data, b"");
+
+    client
+        .write_all_at(handle.clone(), 6, &b"there, world"[..])
+        .unwrap();
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"hello there, world");
+}
+
+#[test]
+fn short_reads_and_writes() {
+    let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", data.clone()).unwrap();
+    let (server, mut client) = in_memory_client(files);
+    client.set_read_chunk_size(Some(100));
+    client.set_write_chunk_size(Some(100));
+    server.limit_io(Some(30));
+
+    let handle = client.look_up("/a_file").unwrap();
+    let mut read = vec![];
+    client.read_all(handle.clone(), &mut read).unwrap();
+    assert_eq!(read, data);
+    let res = client.read_at(handle, 990, 100).unwrap();
+    assert_eq!((res.data.len(), res.eof), (10, true));
+
+    let root = client.look_up("/").unwrap();
+    let copy = client.create_file(root, "a_copy").unwrap().handle.clone();
+    client.write_all(copy.clone(), &data[..]).unwrap();
+    let contents = server.update_file_system(|files| files.read_file("a_copy"));
+    assert_eq!(contents.unwrap(), data);
+    assert_eq!(client.write_at(copy, 0, &data).unwrap(), 30);
+}
+
+#[test]
+fn unstable_writes_sent_again_when_lost() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+
+    let stable = StableHow::Unstable;
+    let written = client.write_at_with_stability(handle.clone(), 0, b"hello", stable);
+    assert_eq!(written.unwrap(), 5);
+    client
+        .write_at_with_stability(handle.clone(), 2, b"y there", stable)
+        .unwrap();
+    client.commit(handle.clone(), 0, 0).unwrap();
+    assert!(client.unstable_writes.is_empty());
+
+    // As if the server restarted before committing the writes, losing them
+    client
+        .write_at_with_stability(handle.clone(), 0, b"bye", stable)
+        .unwrap();
+    client
+        .write_at_with_stability(handle.clone(), 2, b"e now", stable)
+        .unwrap();
+    server
+        .update_file_system(|files| files.write_file("a_file", "hey there"))
+        .unwrap();
+    for write in client.unstable_writes.get_mut(&handle.0).unwrap() {
+        write.verifier = Verifier(0);
+    }
+    client.commit(handle.clone(), 0, 0).unwrap();
+    assert!(client.unstable_writes.is_empty());
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"bye nowre");
+
+    // Stable writes have nothing to commit
+    client.write_at(handle.clone(), 0, b"hi").unwrap();
+    assert!(client.unstable_writes.is_empty());
+}
+
+#[test]
+fn write_all_stable() {
+    let (server, mut client) = in_memory_client(MemoryFs::new());
+    let root = client.look_up("/").unwrap();
+    client.set_write_stability(StableHow::FileSync);
+    let handle = client.create_file(root, "a_file").unwrap().handle.clone();
+    client.write_all(handle, &b"hello"[..]).unwrap();
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"hello");
+}
+
+#[test]
+fn retries_open_during_grace_period() {
+    let (server, mut client) = in_memory_client(MemoryFs::new());
+    let root = client.look_up("/").unwrap();
+
+    server.inject_error(OperationId::Open, StatusError::Grace, 1);
+    let file = client.create_file(root, "a_file").unwrap();
+    client
+        .write_all(file.handle.clone(), &b"hello"[..])
+        .unwrap();
+    client.close(file).unwrap();
+
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), b"hello");
+}
+
+#[test]
+fn gives_up_after_retry_deadline() {
+    let (server, mut client) = in_memory_client(MemoryFs::new());
+    let root = client.look_up("/").unwrap();
+    client.set_retry_deadline(Duration::from_millis(250));
+
+    server.inject_error(OperationId::Open, StatusError::Grace, usize::MAX);
+    let error = client.create_file(root.clone(), "a_file").unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::Grace));
+
+    server.inject_error(OperationId::Open, StatusError::Grace, 0);
+    client.create_file(root, "a_file").unwrap();
+}
+
+#[test]
+fn retries_delay_until_deadline() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+    // Waiting 100ms and then 200ms fits, waiting another 400ms doesn't
+    client.set_retry_deadline(Duration::from_millis(500));
+    let delays = |client: &Client<Pipe>| client.stats().errors.get(&StatusError::Delay).copied();
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 2);
+    assert_eq!(
+        &client.read(handle.clone(), 0, 5).unwrap().data[..],
+        b"hello"
+    );
+    assert_eq!(delays(&client), Some(2));
+
+    server.inject_error(OperationId::Read, StatusError::Delay, usize::MAX);
+    let start = Instant::now();
+    let error = client.read(handle.clone(), 0, 5).unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::Delay));
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert_eq!(delays(&client), Some(5));
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 0);
+    client.read(handle, 0, 5).unwrap();
+}
+
+#[test]
+fn quota_not_supported() {
+    let (_server, mut client) = in_memory_client(MemoryFs::new());
+    let root = client.look_up("/").unwrap();
+    assert_eq!(client.quota(root).unwrap(), Quota::default());
+}
+
+#[test]
+fn label_not_supported() {
+    let (_server, mut client) = in_memory_client(MemoryFs::new());
+    let root = client.look_up("/").unwrap();
+    let error = client.get_label(root).unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::AttrNotSupported));
+}
+
+#[test]
+fn named_attributes() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (_server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+
+    let mut attrs = client.named_attrs(handle.clone()).unwrap();
+    assert_eq!(attrs.dir(), None);
+    assert_eq!(attrs.list().unwrap(), Vec::<String>::new());
+    attrs.write("user.a", &b"first"[..]).unwrap();
+    attrs.write("user.b", &b"second"[..]).unwrap();
+    attrs.write("user.a", &b"one"[..]).unwrap();
+    assert_eq!(attrs.list().unwrap(), ["user.a", "user.b"]);
+
+    let mut attrs = client.named_attrs(handle.clone()).unwrap();
+    let mut data = vec![];
+    attrs.read("user.a", &mut data).unwrap();
+    assert_eq!(data, b"one");
+    attrs.remove("user.b").unwrap();
+    let error = attrs.read("user.b", vec![]).unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::NoEnt));
+    assert_eq!(attrs.list().unwrap(), ["user.a"]);
+
+    // The contents of the file are left alone
+    let mut data = vec![];
+    client.read_all(handle, &mut data).unwrap();
+    assert_eq!(data, b"hello");
+}
+
+#[test]
+fn build_client() {
+    let server = Server::with_file_system(MemoryFs::new());
+    let client = ClientBuilder::new()
+        .max_minor_version(1)
+        .retry_deadline(Duration::ZERO)
+        .read_chunk_size(100)
+        .write_pipeline_depth(2)
+        .lease_renewal(true)
+        .connect(server.connect_in_process())
+        .unwrap();
+    assert_eq!(client.minor_version(), 1);
+    assert_eq!(client.retry_deadline(), Duration::ZERO);
+    assert_eq!(client.read_chunk_size(), 100);
+    assert_eq!(client.write_pipeline_depth, 2);
+    assert!(client.lease_renewal.is_some());
+
+    // The server only speaks up to minor version 1
+    let client = Client::new(server.connect_in_process()).unwrap();
+    assert_eq!(client.minor_version(), 1);
+}
+
+#[test]
+fn state_protection_refused() {
+    // The server answers SP4_NONE, so nothing is protected and requests go as they would anyway
+    let server = Server::with_file_system(MemoryFs::new());
+    let mut client = ClientBuilder::new()
+        .protect_state(true)
+        .connect(server.connect_in_process())
+        .unwrap();
+    assert_eq!(client.protected_ops(), EnumSet::default());
+    let root = client.look_up("/").unwrap();
+    let file = client.create_file(root, "a_file").unwrap();
+    client.close(file).unwrap();
+}
+
+#[test]
+fn minor_version_zero() {
+    use std::io::{Read as _, Seek as _, Write as _};
+
+    let server = Server::with_file_system(MemoryFs::new());
+    let mut client = ClientBuilder::new()
+        .minor_version(0)
+        .connect(server.connect_in_process())
+        .unwrap();
+    assert_eq!(client.minor_version(), 0);
+    let root = client.look_up("/").unwrap();
+
+    // The open is confirmed, and the open owner's sequence IDs kept up with
+    let options = OpenOptions::new().access(ShareAccess::BOTH).create_new();
+    let open_file = client.open(root.clone(), "a_file", &options).unwrap();
+    let mut file = File::new(&mut client, open_file);
+    file.write_all(b"hello").unwrap();
+    file.rewind().unwrap();
+    let mut data = vec![];
+    file.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"hello");
+    drop(file);
+
+    let options = OpenOptions::new().access(ShareAccess::BOTH);
+    let mut open_file = client.open(root.clone(), "a_file", &options).unwrap();
+    client
+        .open_downgrade(&mut open_file, ShareAccess::READ, ShareDeny::NONE)
+        .unwrap();
+    client.close(open_file).unwrap();
+    lock(&client.connection).renew_lease().unwrap();
+
+    // What minor version 0 doesn't have isn't sent at all
+    let mut compound = CompoundBuilder::new();
+    compound.push(PutFhArgs { object: root });
+    compound.push(SecInfoNoNameArgs {
+        style: SecInfoStyle::CurrentFh,
+    });
+    let error = client.send_compound(compound).unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::NotSupported));
+}
+
+#[test]
+fn times_out_while_server_is_stuck() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+
+    // The server can't answer while something else has hold of its file system
+    let (held, hold) = std::sync::mpsc::channel();
+    let stuck = server.clone();
+    let holder = std::thread::spawn(move || {
+        stuck.update_file_system(|_| {
+            held.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(500));
+        })
+    });
+    hold.recv().unwrap();
+    let error = client
+        .with_timeout(Duration::from_millis(100), |client| {
+            client.read(handle.clone(), 0, 5)
+        })
+        .unwrap_err();
+    assert!(error.is_timed_out(), "{error:?}");
+    assert_eq!(client.timeout(), None);
+    holder.join().unwrap();
+
+    // The late reply to the abandoned read isn't mistaken for the reply to this one
+    assert_eq!(&client.read(handle, 1, 4).unwrap().data[..], b"ello");
+}
+
+// A connection to the in-process server which breaks for good once `cut` is set
+#[cfg(test)]
+struct Flaky {
+    pipe: Pipe,
+    cut: Arc<std::sync::atomic::AtomicBool>,
+    broken: bool,
+}
+
+#[cfg(test)]
+impl Flaky {
+    fn check(&mut self) -> io::Result<()> {
+        self.broken |= self.cut.swap(false, std::sync::atomic::Ordering::SeqCst);
+        if self.broken {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl io::Read for Flaky {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        self.pipe.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl io::Write for Flaky {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        self.pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+#[test]
+fn reconnects_after_connection_breaks() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let server = Server::with_file_system(files);
+    let cut = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let connect = {
+        let server = server.clone();
+        let cut = cut.clone();
+        move || Flaky {
+            pipe: server.connect_in_process(),
+            cut: cut.clone(),
+            broken: false,
+        }
+    };
+    let mut client = Client::new(connect.clone()()).unwrap();
+    client.reconnect_with(move || Ok(connect()));
+    let root = client.look_up("/").unwrap();
+    let handle = client.look_up("/a_file").unwrap();
+    let file = client
+        .open(root.clone(), "a_file", &OpenOptions::new())
+        .unwrap();
+
+    cut.store(true, std::sync::atomic::Ordering::SeqCst);
+    assert_eq!(
+        &client.read(handle.clone(), 0, 5).unwrap().data[..],
+        b"hello"
+    );
+
+    cut.store(true, std::sync::atomic::Ordering::SeqCst);
+    let mut data = vec![];
+    client.read_all(handle.clone(), &mut data).unwrap();
+    assert_eq!(data, b"hello");
+
+    // The server may or may not have renamed it
+    cut.store(true, std::sync::atomic::Ordering::SeqCst);
+    let error = client
+        .rename(root.clone(), root, "a_file", "b_file")
+        .unwrap_err();
+    assert!(
+        matches!(error, Error::ConnectionLost(OperationId::Rename)),
+        "{error:?}"
+    );
+
+    // What we had open is still open with the new session
+    client.close(file).unwrap();
+}
+
+// A connection to the in-process server where the next write fails once `fail` is set
+#[cfg(test)]
+struct FailsOnce {
+    pipe: Pipe,
+    fail: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+impl io::Read for FailsOnce {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+#[cfg(test)]
+impl io::Write for FailsOnce {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.fail.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.pipe.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+#[test]
+fn sequence_kept_when_send_fails() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let server = Server::with_file_system(files);
+    let fail = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let mut client = Client::new(FailsOnce {
+        pipe: server.connect_in_process(),
+        fail: fail.clone(),
+    })
+    .unwrap();
+    let handle = client.look_up("/a_file").unwrap();
+
+    // Had the slots moved on, the server would take the next requests as misordered
+    for _ in 0..2 {
+        fail.store(true, std::sync::atomic::Ordering::SeqCst);
+        let mut data = vec![];
+        client.read_all(handle.clone(), &mut data).unwrap_err();
+        fail.store(true, std::sync::atomic::Ordering::SeqCst);
+        client.read(handle.clone(), 0, 5).unwrap_err();
+    }
+    let mut data = vec![];
+    client.read_all(handle.clone(), &mut data).unwrap();
+    assert_eq!(data, b"hello");
+    assert_eq!(&client.read(handle, 0, 5).unwrap().data[..], b"hello");
+}
+
+#[test]
+fn shared_between_threads() {
+    let (server, client) = in_memory_client(MemoryFs::new());
+    let client = SharedClient::new(client);
+    let root = client.look_up("/").unwrap();
+    // Small enough for the threads to take turns
+    client.lock().set_read_chunk_size(Some(1000));
+    client.lock().set_write_chunk_size(Some(1000));
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let client = client.clone();
+            let root = root.clone();
+            std::thread::spawn(move || {
+                let name = format!("file_{i}");
+                let contents = name.repeat(1000);
+                let file = client.lock().create_file(root, &name).unwrap();
+                client
+                    .write_all(file.handle.clone(), contents.as_bytes())
+                    .unwrap();
+                let mut data = vec![];
+                client.read_all(file.handle.clone(), &mut data).unwrap();
+                assert_eq!(data, contents.as_bytes());
+                client.lock().close(file).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let client = client.into_inner().ok().unwrap();
+    assert_eq!(client.num_connections(), 1);
+    let contents = server.update_file_system(|files| files.read_file("file_3"));
+    assert_eq!(contents.unwrap(), "file_3".repeat(1000).as_bytes());
+}
+
+#[test]
+fn set_owner() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (_server, mut client) = in_memory_client(files);
+    client.set_id_mapper(Arc::new(IdMap::new("example.com").add_user("alice", 1000)));
+    let handle = client.look_up("/a_file").unwrap();
+
+    client
+        .set_owner(handle.clone(), Some(1000), Some(50))
+        .unwrap();
+    let attrs = client.get_attr(handle).unwrap().object_attributes;
+    let owner: &String = attrs.get_as(FileAttributeId::Owner).unwrap();
+    let group: &String = attrs.get_as(FileAttributeId::OwnerGroup).unwrap();
+    assert_eq!(owner, "alice@example.com");
+    assert_eq!(group, "50");
+    assert_eq!(client.id_mapper().uid(owner), Some(1000));
+    assert_eq!(client.id_mapper().gid(group), Some(50));
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct Counts {
+    ops: Vec<(OperationId, Option<StatusError>)>,
+    read: u64,
+    written: u64,
+    retries: Vec<StatusError>,
+}
+
+#[cfg(test)]
+impl ClientMetrics for Mutex<Counts> {
+    fn operation(&self, op: OperationId, status: Option<StatusError>, _latency: Duration) {
+        self.lock().unwrap().ops.push((op, status));
+    }
+
+    fn bytes_read(&self, bytes: u64) {
+        self.lock().unwrap().read += bytes;
+    }
+
+    fn bytes_written(&self, bytes: u64) {
+        self.lock().unwrap().written += bytes;
+    }
+
+    fn retry(&self, error: StatusError) {
+        self.lock().unwrap().retries.push(error);
+    }
+}
+
+#[test]
+fn metrics() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    client.set_metrics(counts.clone());
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 1);
+    client.read(handle.clone(), 0, 5).unwrap();
+    client.write(handle, 5, b" there".to_vec()).unwrap();
+
+    let counts = counts.lock().unwrap();
+    let reads: Vec<_> = counts
+        .ops
+        .iter()
+        .filter(|(op, _)| *op == OperationId::Read)
+        .collect();
+    assert_eq!(
+        reads,
+        [
+            &(OperationId::Read, Some(StatusError::Delay)),
+            &(OperationId::Read, None)
+        ]
+    );
+    assert_eq!((counts.read, counts.written), (5, 6));
+    assert_eq!(counts.retries, [StatusError::Delay]);
+}
+
+#[test]
+fn stats() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let root = client.look_up("/").unwrap();
+    let handle = client.look_up("/a_file").unwrap();
+    let before = client.stats();
+
+    server.inject_error(OperationId::Read, StatusError::Delay, 1);
+    client.read(handle.clone(), 0, 5).unwrap();
+    // Counting carries on when other metrics are given
+    client.set_metrics(Arc::new(Mutex::new(Counts::default())));
+    client.write(handle.clone(), 5, b" there".to_vec()).unwrap();
+    client.remove(root, "nothing").unwrap_err();
+
+    let stats = client.stats();
+    let ops = |stats: &ClientStats, op| stats.ops.get(&op).copied().unwrap_or(0);
+    assert_eq!(
+        ops(&stats, OperationId::Read) - ops(&before, OperationId::Read),
+        2
+    );
+    assert_eq!(ops(&stats, OperationId::Write), 1);
+    assert_eq!((stats.bytes_read, stats.bytes_written), (5, 6));
+    assert_eq!(stats.retransmits, 1);
+    assert_eq!(stats.errors[&StatusError::Delay], 1);
+    assert_eq!(stats.errors[&StatusError::NoEnt], 1);
+    assert!(stats.compounds > before.compounds);
+    assert!(stats.average_latency().is_some());
+}
+
+#[test]
+fn write_streamed_from_source() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "").unwrap();
+    let (server, mut client) = in_memory_client(files);
+    let handle = client.look_up("/a_file").unwrap();
+
+    let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    let res = client
+        .write_from(handle.clone(), 0, &data[..], 9_999)
+        .unwrap();
+    assert_eq!(res.count, 9_999);
+    let res = client
+        .write_from(handle.clone(), 9_999, &data[9_999..], 1)
+        .unwrap();
+    assert_eq!(res.count, 1);
+    let contents = server.update_file_system(|files| files.read_file("a_file"));
+    assert_eq!(contents.unwrap(), data);
+
+    // The connection can't be used once the source runs out part way through
+    let error = client.write_from(handle, 0, &b"hi"[..], 3).unwrap_err();
+    assert!(is_connection_broken(&error), "{error:?}");
+}
+
+#[test]
+fn replay_recorded_session() {
+    use sun_rpc_client::{Recording, Replay};
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let server = Server::with_file_system(files);
+
+    fn read(client: &mut Client<impl Transport>) -> Vec<u8> {
+        let handle = client.look_up("/a_file").unwrap();
+        client.read(handle, 0, 5).unwrap().data.into()
+    }
+
+    let mut recording = vec![];
+    {
+        let transport = Recording::new(server.connect_in_process(), &mut recording);
+        let mut client = Client::new(transport).unwrap();
+        assert_eq!(read(&mut client), b"hello");
+    }
+
+    // The server is no longer needed to do it all again
+    drop(server);
+    let mut client = Client::new(Replay::new(&recording[..]).unwrap()).unwrap();
+    assert_eq!(read(&mut client), b"hello");
+}
+
+#[test]
+fn read_dir_starts_over_when_directory_changes() {
+    let mut files = MemoryFs::new();
+    for name in ["a", "b", "c", "d", "e", "f"] {
+        files.write_file(name, "").unwrap();
+    }
+    let (server, mut client) = in_memory_client(files);
+    // Small enough for one entry to a page
+    client.max_read = 50;
+    let root = client.look_up("/").unwrap();
+
+    let mut entries = client.read_dir(root, Default::default());
+    let mut names: Vec<_> = (&mut entries).take(4).map(|e| e.unwrap().name).collect();
+
+    // Entries go away and come along before where we are, which invalidates our cookie
+    server.update_file_system(|files| {
+        files.delete("a").unwrap();
+        files.write_file("0", "").unwrap();
+        files.write_file("z", "").unwrap();
+    });
+    server.inject_error(OperationId::ReadDir, StatusError::NotSame, 1);
+    names.extend(entries.map(|e| e.unwrap().name));
+
+    names.sort();
+    assert_eq!(names, ["0", "a", "b", "c", "d", "e", "f", "z"]);
+}
+
+#[test]
+fn status_errors_say_what_failed() {
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = in_memory_client(files);
+
+    fn protocol_error(error: &Error) -> String {
+        let Error::Protocol(e) = error else {
+            panic!("{error:?}");
+        };
+        e.to_string()
+    }
+
+    let error = client.look_up("/missing").unwrap_err();
+    assert!(error.is_not_found(), "{error:?}");
+    assert!(!error.is_permission_denied());
+    assert_eq!(protocol_error(&error), "NoEnt from LookUp on /missing");
+
+    let error = client.look_up("/a_file/missing").unwrap_err();
+    assert!(error.is_not_a_directory(), "{error:?}");
+    assert!(!error.is_not_found());
+    assert_eq!(
+        protocol_error(&error),
+        "NotDir from LookUp on /a_file/missing"
+    );
+
+    server.inject_error(OperationId::LookUp, StatusError::Access, 1);
+    let error = client.look_up("/a_file").unwrap_err();
+    assert!(error.is_permission_denied(), "{error:?}");
+    assert!(!error.is_not_found());
+    assert_eq!(protocol_error(&error), "Access from LookUp on /a_file");
+
+    let handle = client.look_up("/a_file").unwrap();
+    server
+        .update_file_system(|files| files.delete("a_file"))
+        .unwrap();
+    let error = client.get_attr(handle).unwrap_err();
+    assert!(error.is_stale(), "{error:?}");
+    assert_eq!(protocol_error(&error), "Stale from PutFh");
+}
+
+#[test]
+fn lease_renewal_keeps_state_while_idle() {
+    let server = Server::with_file_system(MemoryFs::new());
+    server.set_lease_time(Duration::from_secs(2));
+    let mut client = Client::new(server.connect_in_process()).unwrap();
+    assert_eq!(client.lease_time(), Duration::from_secs(2));
+    let root = client.look_up("/").unwrap();
+    let options = OpenOptions::new().access(ShareAccess::BOTH).create();
+    let file = client.open(root, "a_file", &options).unwrap();
+
+    client.start_default_lease_renewal();
+    thread::sleep(Duration::from_secs(3));
+    client
+        .write_with_state(file.handle.clone(), file.state_id, 0, "hello".into())
+        .unwrap();
+
+    // Without renewing, the server forgets about us and what we had open
+    client.stop_lease_renewal();
+    thread::sleep(Duration::from_secs(3));
+    let error = client
+        .write_with_state(file.handle.clone(), file.state_id, 5, "bye".into())
+        .unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::BadStateId), "{error:?}");
 }
@@ -0,0 +1,32 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Hooks for keeping count of what the client does, for services which run for a long time and
+//! export their metrics to a monitoring system.
+
+use nfs4::{OperationId, StatusError};
+use std::time::Duration;
+
+/// Is told about the requests the client makes as their replies come in. Set it with
+/// `Client::set_metrics`. Every method does nothing by default, so only the ones of interest need
+/// implementing. They are called while a connection is locked, so they should be quick, like
+/// bumping a counter or observing a histogram.
+///
+/// Only NFSv4 requests are counted, not what goes to NFSv3 data servers with pNFS.
+pub trait ClientMetrics: Send + Sync {
+    /// A COMPOUND got its reply `latency` after it was sent, with the given status.
+    fn compound(&self, _latency: Duration, _status: Option<StatusError>) {}
+
+    /// An operation of a COMPOUND was carried out by the server, which took `latency` to reply to
+    /// the whole COMPOUND. Operations after one which failed aren't carried out.
+    fn operation(&self, _op: OperationId, _status: Option<StatusError>, _latency: Duration) {}
+
+    /// A READ returned this many bytes.
+    fn bytes_read(&self, _bytes: u64) {}
+
+    /// A WRITE wrote this many bytes.
+    fn bytes_written(&self, _bytes: u64) {}
+
+    /// A request is sent again because it failed with the given error, after backing off when the
+    /// server asked us to wait or after re-establishing a session the server lost.
+    fn retry(&self, _error: StatusError) {}
+}
@@ -0,0 +1,188 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Remembering the handle each name in a directory leads to, so that looking up paths deep in the
+//! tree only looks up the components we haven't seen, starting from the deepest directory we have.
+//! Names are forgotten when they are removed or renamed, handles when the server says they are
+//! stale, and everything after the TTL, which is when renames by other clients are seen.
+
+use nfs4::{ArgOp, Bytes, FileHandle};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How many names are kept. Expired ones are dropped first when there are too many, and
+/// everything if that isn't enough.
+const MAX_NAMES: usize = 10_000;
+
+struct Cached {
+    handle: FileHandle,
+    fetched: Instant,
+}
+
+impl Cached {
+    fn new(handle: FileHandle) -> Self {
+        Self {
+            handle,
+            fetched: Instant::now(),
+        }
+    }
+}
+
+pub(crate) struct NameCache {
+    ttl: Duration,
+    root: Option<Cached>,
+    // By the handle of the directory and the name in it
+    names: BTreeMap<(Bytes, String), Cached>,
+}
+
+impl NameCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            root: None,
+            names: BTreeMap::new(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn fresh(&self, cached: &Cached) -> bool {
+        cached.fetched.elapsed() < self.ttl
+    }
+
+    /// Follows the given components from the directory, or the root if `None`, for as long as we
+    /// know where they lead. Returns how many it got through and the handle it got to, or `None`
+    /// if we don't know the root either.
+    pub fn known_prefix(
+        &self,
+        start: Option<&FileHandle>,
+        components: &[&str],
+    ) -> (usize, Option<FileHandle>) {
+        let mut handle = match start {
+            Some(start) => start.clone(),
+            None => match self.root.as_ref().filter(|r| self.fresh(r)) {
+                Some(root) => root.handle.clone(),
+                None => return (0, None),
+            },
+        };
+        let mut known = 0;
+        for name in components {
+            let key = (handle.0, name.to_string());
+            match self.names.get(&key).filter(|c| self.fresh(c)) {
+                Some(cached) => handle = cached.handle.clone(),
+                None => return (known, Some(FileHandle(key.0))),
+            }
+            known += 1;
+        }
+        (known, Some(handle))
+    }
+
+    pub fn insert_root(&mut self, root: FileHandle) {
+        self.root = Some(Cached::new(root));
+    }
+
+    pub fn insert(&mut self, dir: &FileHandle, name: &str, handle: FileHandle) {
+        if self.names.len() >= MAX_NAMES {
+            let ttl = self.ttl;
+            self.names
+                .retain(|_, cached| cached.fetched.elapsed() < ttl);
+        }
+        if self.names.len() >= MAX_NAMES {
+            self.names.clear();
+        }
+        self.names
+            .insert((dir.0.clone(), name.into()), Cached::new(handle));
+    }
+
+    /// Forgets the name in whichever directory it is in, for when we don't know which one that
+    /// is.
+    fn forget_name(&mut self, name: &str) {
+        self.names.retain(|(_, n), _| n != name);
+    }
+
+    /// Forgets the names in the directory with the given handle, and the names leading to it.
+    fn forget_handle(&mut self, handle: &FileHandle) {
+        if self.root.as_ref().is_some_and(|r| r.handle == *handle) {
+            self.root = None;
+        }
+        self.names
+            .retain(|(dir, _), cached| *dir != handle.0 && cached.handle != *handle);
+    }
+
+    /// Forgets what the given COMPOUND may make out of date, before it is sent.
+    pub fn forget_changed(&mut self, arg_array: &[ArgOp]) {
+        for op in arg_array {
+            match op {
+                ArgOp::Remove(args) => self.forget_name(&args.target),
+                ArgOp::Rename(args) => {
+                    self.forget_name(&args.old_name);
+                    self.forget_name(&args.new_name);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Forgets the handles the given COMPOUND used, after the server said one of them was stale.
+    pub fn forget_stale(&mut self, arg_array: &[ArgOp]) {
+        for op in arg_array {
+            if let ArgOp::PutFh(args) = op {
+                self.forget_handle(&args.object);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.root = None;
+        self.names.clear();
+    }
+}
+
+#[test]
+fn look_ups_start_from_deepest_known_directory() {
+    use nfs4::OperationId;
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.make_dir("a").unwrap();
+    files.make_dir("a/b").unwrap();
+    files.write_file("a/b/one", "1").unwrap();
+    files.write_file("a/b/two", "2").unwrap();
+    let (server, mut client) = super::in_memory_client(files);
+    client.set_name_cache(Some(Duration::from_secs(600)));
+    let look_ups = |client: &super::Client<_>| client.stats().ops[&OperationId::LookUp];
+
+    let one = client.look_up("/a/b/one").unwrap();
+    assert_eq!(look_ups(&client), 3);
+    assert_eq!(client.look_up("/a//b/./one/").unwrap(), one);
+    assert_eq!(look_ups(&client), 3);
+    client.look_up("/a/b/two").unwrap();
+    assert_eq!(look_ups(&client), 4);
+    let b = client.look_up("/a/b").unwrap();
+    assert_eq!(
+        client.look_up_from(b.clone(), "two").unwrap(),
+        client.look_up("/a/b/two").unwrap()
+    );
+    assert_eq!(look_ups(&client), 4);
+
+    // Renamed and removed names are forgotten
+    client.rename(b.clone(), b.clone(), "one", "three").unwrap();
+    assert!(client.look_up("/a/b/one").unwrap_err().is_not_found());
+    assert_eq!(client.look_up("/a/b/three").unwrap(), one);
+    client.remove(b.clone(), "two").unwrap();
+    assert!(client.look_up("/a/b/two").unwrap_err().is_not_found());
+
+    // As are directories which the server says are stale
+    server
+        .update_file_system(|files| {
+            files.delete("a/b/three")?;
+            files.delete("a/b")?;
+            files.make_dir("a/b")?;
+            files.write_file("a/b/one", "1")
+        })
+        .unwrap();
+    let one = client.look_up("/a/b/one").unwrap();
+    assert_ne!(client.look_up("/a/b").unwrap(), b);
+    assert_eq!(client.look_up("/a/b/one").unwrap(), one);
+}
@@ -0,0 +1,103 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Named attributes, streams of data attached to a file apart from its contents. OPENATTR gets
+//! the directory they are kept in, which is then listed, read and written like any other, except
+//! that it can only have regular files in it.
+
+use super::{Client, GetFh, OpenOptions, Result, ReturnSecond};
+use nfs4::{FileAttribute, FileHandle, OpenAttrArgs, PutFhArgs, ShareAccess, StatusError};
+use std::io;
+use sun_rpc_client::Transport;
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// The named attributes of the given object. The object need not have any yet, its named
+    /// attribute directory is made when the first one is written.
+    pub fn named_attrs(&mut self, handle: FileHandle) -> Result<NamedAttrs<'_, TransportT>> {
+        let dir = match self.open_attr(handle.clone(), false) {
+            Ok(dir) => Some(dir),
+            Err(e) if e.status() == Some(StatusError::NoEnt) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(NamedAttrs {
+            client: self,
+            object: handle,
+            dir,
+        })
+    }
+
+    fn open_attr(&mut self, object: FileHandle, create_dir: bool) -> Result<FileHandle> {
+        Ok(self
+            .do_compound(ReturnSecond(
+                (PutFhArgs { object }, OpenAttrArgs { create_dir }),
+                GetFh,
+            ))?
+            .object)
+    }
+}
+
+/// The named attributes of one object, as returned by `Client::named_attrs`.
+pub struct NamedAttrs<'a, TransportT> {
+    client: &'a mut Client<TransportT>,
+    object: FileHandle,
+    dir: Option<FileHandle>,
+}
+
+impl<TransportT: Transport> NamedAttrs<'_, TransportT> {
+    /// The named attribute directory, if the object has one. It can be used with the other
+    /// methods of `Client` too.
+    pub fn dir(&self) -> Option<&FileHandle> {
+        self.dir.as_ref()
+    }
+
+    /// The names of the named attributes, sorted.
+    pub fn list(&mut self) -> Result<Vec<String>> {
+        let Some(dir) = self.dir.clone() else {
+            return Ok(vec![]);
+        };
+        let mut names = self
+            .client
+            .read_dir(dir, Default::default())
+            .map(|e| e.map(|e| e.name))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn look_up(&mut self, name: &str) -> Result<FileHandle> {
+        let Some(dir) = self.dir.clone() else {
+            return Err(StatusError::NoEnt.into());
+        };
+        self.client.look_up_from(dir, name)
+    }
+
+    /// Reads the whole of the named attribute into the given sink.
+    pub fn read(&mut self, name: &str, sink: impl io::Write) -> Result<()> {
+        let handle = self.look_up(name)?;
+        self.client.read_all(handle, sink)
+    }
+
+    /// Replaces the named attribute with everything from the source, creating it if need be.
+    pub fn write(&mut self, name: &str, source: impl io::Read) -> Result<()> {
+        let dir = match self.dir.clone() {
+            Some(dir) => dir,
+            None => {
+                let dir = self.client.open_attr(self.object.clone(), true)?;
+                self.dir.insert(dir).clone()
+            }
+        };
+        let options = OpenOptions::new().access(ShareAccess::WRITE).create();
+        let file = self.client.open(dir, name, &options)?;
+        let truncate = [FileAttribute::Size(0)].into_iter().collect();
+        self.client.set_attr(file.handle.clone(), truncate)?;
+        self.client.write_all(file.handle.clone(), source)?;
+        self.client.close(file)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let Some(dir) = self.dir.clone() else {
+            return Err(StatusError::NoEnt.into());
+        };
+        self.client.remove(dir, name)?;
+        Ok(())
+    }
+}
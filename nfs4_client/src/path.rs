@@ -0,0 +1,267 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Paths on the server. They aren't `std::path::Path`s since those follow the rules of the
+//! platform we run on, which on Windows would split names at backslashes and take `C:` for a
+//! prefix. Here `/` is the only separator wherever we are, and the components are the UTF-8
+//! strings NFSv4 names things with.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// A slice of a path on the server, like `str` is to `String`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct RemotePath(str);
+
+/// An owned path on the server, built up with `push` and `join`.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RemotePathBuf(String);
+
+/// Takes trailing slashes and `.`s off the path, which aren't components of their own.
+fn trim_end(mut path: &str) -> &str {
+    loop {
+        let trimmed = path.trim_end_matches('/');
+        match trimmed.strip_suffix('.') {
+            Some(rest) if rest.is_empty() || rest.ends_with('/') => path = rest,
+            _ => return trimmed,
+        }
+    }
+}
+
+impl RemotePath {
+    pub fn new<S: AsRef<str> + ?Sized>(path: &S) -> &Self {
+        // SAFETY: `RemotePath` is `repr(transparent)` over `str`, so the two have the same layout
+        unsafe { &*(path.as_ref() as *const str as *const Self) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the path starts from the root rather than some directory.
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with('/')
+    }
+
+    /// The names the path is made of, leaving out empty ones and `.`. `..` is kept, since only
+    /// the server knows what it leads to.
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.0.split('/').filter(|c| !c.is_empty() && *c != ".")
+    }
+
+    /// The path without its last component, `None` for the root or an empty path.
+    pub fn parent(&self) -> Option<&Self> {
+        let trimmed = trim_end(&self.0);
+        let last = trimmed.rsplit('/').next().unwrap();
+        if last.is_empty() {
+            return None;
+        }
+        match trim_end(&trimmed[..trimmed.len() - last.len()]) {
+            "" if self.is_absolute() => Some(Self::new("/")),
+            parent => Some(Self::new(parent)),
+        }
+    }
+
+    /// The last component of the path, `None` for the root or an empty path.
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().next_back()
+    }
+
+    /// The path with the given one added onto the end. An absolute path replaces it instead.
+    pub fn join(&self, path: impl AsRef<RemotePath>) -> RemotePathBuf {
+        let mut joined = self.to_owned();
+        joined.push(path);
+        joined
+    }
+
+    /// The path as a local one, with the separators of the platform we run on. Meant for paths
+    /// relative to some directory, like the ones of `Walk`, to be joined onto a local directory.
+    pub fn to_local(&self) -> PathBuf {
+        let mut local = PathBuf::new();
+        if self.is_absolute() {
+            local.push(std::path::MAIN_SEPARATOR_STR);
+        }
+        local.extend(self.components());
+        local
+    }
+}
+
+impl RemotePathBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given path onto the end with a `/` between, or replaces this one with it if it is
+    /// absolute. A trailing `/` on the given path is kept.
+    pub fn push(&mut self, path: impl AsRef<RemotePath>) {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            self.0.clear();
+        } else if !self.0.is_empty() && !self.0.ends_with('/') {
+            self.0.push('/');
+        }
+        self.0.push_str(path.as_str());
+    }
+
+    /// Takes off the last component, returning false if there was none.
+    pub fn pop(&mut self) -> bool {
+        let Some(len) = self.parent().map(|p| p.0.len()) else {
+            return false;
+        };
+        self.0.truncate(len);
+        true
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl Deref for RemotePathBuf {
+    type Target = RemotePath;
+
+    fn deref(&self) -> &RemotePath {
+        RemotePath::new(&self.0)
+    }
+}
+
+impl Borrow<RemotePath> for RemotePathBuf {
+    fn borrow(&self) -> &RemotePath {
+        self
+    }
+}
+
+impl ToOwned for RemotePath {
+    type Owned = RemotePathBuf;
+
+    fn to_owned(&self) -> RemotePathBuf {
+        RemotePathBuf(self.0.to_owned())
+    }
+}
+
+impl AsRef<RemotePath> for RemotePath {
+    fn as_ref(&self) -> &RemotePath {
+        self
+    }
+}
+
+impl AsRef<RemotePath> for RemotePathBuf {
+    fn as_ref(&self) -> &RemotePath {
+        self
+    }
+}
+
+impl AsRef<RemotePath> for str {
+    fn as_ref(&self) -> &RemotePath {
+        RemotePath::new(self)
+    }
+}
+
+impl AsRef<RemotePath> for String {
+    fn as_ref(&self) -> &RemotePath {
+        RemotePath::new(self)
+    }
+}
+
+impl From<String> for RemotePathBuf {
+    fn from(path: String) -> Self {
+        Self(path)
+    }
+}
+
+impl From<&str> for RemotePathBuf {
+    fn from(path: &str) -> Self {
+        Self(path.into())
+    }
+}
+
+impl From<&RemotePath> for RemotePathBuf {
+    fn from(path: &RemotePath) -> Self {
+        path.to_owned()
+    }
+}
+
+impl FromStr for RemotePathBuf {
+    type Err = std::convert::Infallible;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        Ok(path.into())
+    }
+}
+
+impl<P: AsRef<RemotePath>> FromIterator<P> for RemotePathBuf {
+    fn from_iter<I: IntoIterator<Item = P>>(iter: I) -> Self {
+        let mut path = Self::new();
+        for p in iter {
+            path.push(p);
+        }
+        path
+    }
+}
+
+impl<P: AsRef<RemotePath>> Extend<P> for RemotePathBuf {
+    fn extend<I: IntoIterator<Item = P>>(&mut self, iter: I) {
+        for p in iter {
+            self.push(p);
+        }
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for RemotePathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl fmt::Debug for RemotePathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn remote_paths() {
+    let path = RemotePath::new("/export//dir/./a\\file");
+    assert!(path.is_absolute());
+    assert_eq!(
+        path.components().collect::<Vec<_>>(),
+        ["export", "dir", "a\\file"]
+    );
+    assert_eq!(path.file_name(), Some("a\\file"));
+    assert_eq!(path.parent(), Some(RemotePath::new("/export//dir")));
+    assert_eq!(RemotePath::new("a/.").parent(), Some(RemotePath::new("")));
+    assert_eq!(RemotePath::new("/a").parent(), Some(RemotePath::new("/")));
+    assert_eq!(RemotePath::new("a/").parent(), Some(RemotePath::new("")));
+    assert_eq!(RemotePath::new("/").parent(), None);
+
+    let mut path = RemotePath::new("/export").join("dir/");
+    assert_eq!(path.as_str(), "/export/dir/");
+    path.push("file");
+    assert_eq!(path.as_str(), "/export/dir/file");
+    assert!(path.pop());
+    assert_eq!(path.as_str(), "/export/dir");
+    path.push("/other");
+    assert_eq!(path.as_str(), "/other");
+
+    let walked: RemotePathBuf = ["dir", "sub", "file"].into_iter().collect();
+    assert_eq!(walked.as_str(), "dir/sub/file");
+    assert_eq!(
+        walked.to_local(),
+        ["dir", "sub", "file"].iter().collect::<PathBuf>()
+    );
+}
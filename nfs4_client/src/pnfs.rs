@@ -0,0 +1,1088 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Parallel NFS. The server hands out layouts which describe where the contents of a file are kept
+//! on data servers, which we then read from and write to directly, sending to all of them at once.
+//! Both the files and the flexible files layout types are supported.
+
+use super::{
+    lock, process_compound_reply, Client, ClientMetrics, ClientOwner, CompoundRequest, Connection,
+    Error, OpenFile, OpenOptions, Result, ReturnSecond,
+};
+use nfs4::{
+    Bytes, DeviceError, DeviceId, FileAttributeId, FileHandle, FilesDeviceAddr, FilesLayout,
+    GetDeviceInfoArgs, Layout, LayoutCommitArgs, LayoutGetArgs, LayoutGetRes, LayoutIoMode,
+    LayoutReturn, LayoutReturnArgs, LayoutReturnFile, LayoutType, LayoutUpdate, NetAddr, OpenClaim,
+    OperationId, PutFhArgs, ReadArgs, ReadRes, SequenceArgs, ShareAccess, SlotId, StableHow,
+    StateId, StatusError, WriteArgs,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read as _};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sun_rpc_client::portmap::universal_address_port;
+use sun_rpc_client::{OpaqueAuth, ReadTimeout, Transport};
+
+// How large a layout and a device's addresses we accept
+const LAYOUT_MAX_COUNT: u32 = 4096;
+pub(crate) const DEVICE_INFO_MAX_COUNT: u32 = 65536;
+
+pub(crate) type Connector<TransportT> = Box<dyn FnMut(SocketAddr) -> io::Result<TransportT> + Send>;
+
+// A data server is told apart by its address and the version of NFS we speak to it
+pub(crate) type DataServerId = (SocketAddr, u32);
+
+pub(crate) enum DataServer<TransportT> {
+    V3(Box<nfs3_client::Client<TransportT>>),
+    V4(Box<Connection<TransportT>>),
+}
+
+pub(crate) struct Pnfs<TransportT> {
+    connect: Connector<TransportT>,
+    data_servers: BTreeMap<DataServerId, DataServer<TransportT>>,
+    // The layout types we ask for, in order. Ones the server turns down are removed.
+    layout_types: Vec<LayoutType>,
+}
+
+impl<TransportT> Pnfs<TransportT> {
+    pub(crate) fn new(connect: Connector<TransportT>) -> Self {
+        Self {
+            connect,
+            data_servers: BTreeMap::new(),
+            layout_types: vec![LayoutType::NfsV41Files, LayoutType::FlexFiles],
+        }
+    }
+
+    pub(crate) fn set_metrics(&mut self, metrics: &Arc<dyn ClientMetrics>) {
+        for data_server in self.data_servers.values_mut() {
+            if let DataServer::V4(connection) = data_server {
+                connection.raw_client.tracer.metrics = Some(metrics.clone());
+            }
+        }
+    }
+
+    /// Destroys our sessions and client IDs with the NFSv4.1 data servers, returning the first
+    /// error.
+    pub(crate) fn shut_down(&mut self) -> Result<()>
+    where
+        TransportT: Transport,
+    {
+        let mut result = Ok(());
+        for data_server in self.data_servers.values_mut() {
+            if let DataServer::V4(connection) = data_server {
+                result = result.and(connection.shut_down());
+            }
+        }
+        result
+    }
+
+    pub(crate) fn set_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>
+    where
+        TransportT: Transport + ReadTimeout,
+    {
+        for data_server in self.data_servers.values_mut() {
+            if let DataServer::V4(connection) = data_server {
+                connection
+                    .raw_client
+                    .rpc_client
+                    .set_reply_timeout(timeout)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The address to connect to for the given data server address, if it is one we can use.
+fn socket_addr(addr: &NetAddr) -> Option<SocketAddr> {
+    if addr.netid != "tcp" && addr.netid != "tcp6" {
+        return None;
+    }
+    let host: IpAddr = addr.addr.rsplitn(3, '.').nth(2)?.parse().ok()?;
+    Some(SocketAddr::new(host, universal_address_port(&addr.addr)?))
+}
+
+#[derive(Default)]
+pub(crate) struct IoCounts {
+    pub(crate) ops_requested: u64,
+    pub(crate) bytes_requested: u64,
+    pub(crate) ops_completed: u64,
+    pub(crate) bytes_completed: u64,
+    // We only have one request in flight for a data file at a time, so this is both how long it
+    // was busy and how long its requests took to complete altogether
+    pub(crate) busy: Duration,
+}
+
+impl IoCounts {
+    fn requested(&mut self, bytes: usize) {
+        self.ops_requested += 1;
+        self.bytes_requested += bytes as u64;
+    }
+
+    fn completed(&mut self, bytes: usize, started: Instant) {
+        self.ops_completed += 1;
+        self.bytes_completed += bytes as u64;
+        self.busy += started.elapsed();
+    }
+}
+
+// The I/O done with a data file, which the server wants to hear about for flexible files layouts
+#[derive(Default)]
+pub(crate) struct IoStats {
+    pub(crate) read: IoCounts,
+    pub(crate) write: IoCounts,
+    pub(crate) since: Option<Instant>,
+}
+
+// Where some of a file is kept on a data server
+pub(crate) struct DataFile {
+    pub(crate) data_server: DataServerId,
+    pub(crate) addr: NetAddr,
+    pub(crate) device_id: DeviceId,
+    pub(crate) fh: FileHandle,
+    // The state ID to use with it, if not the one of our open
+    pub(crate) state_id: Option<StateId>,
+    pub(crate) credential: OpaqueAuth,
+    pub(crate) stats: IoStats,
+}
+
+// Where each stripe unit of a file lives, see section 13.4 of RFC 8881
+pub(crate) struct Stripes {
+    pub(crate) unit_size: u64,
+    pub(crate) first_stripe_index: u64,
+    pub(crate) pattern_offset: u64,
+    pub(crate) dense: bool,
+    pub(crate) files: Vec<DataFile>,
+}
+
+impl Stripes {
+    /// Finds which stripe the given offset of the file is in, the offset to use for it on the data
+    /// server, and how much of its stripe unit is left from there.
+    fn locate(&self, offset: u64) -> (usize, u64, u64) {
+        let relative = offset.saturating_sub(self.pattern_offset);
+        let unit_number = relative / self.unit_size;
+        let num_stripes = self.files.len() as u64;
+        let stripe = (unit_number + self.first_stripe_index) % num_stripes;
+        let data_server_offset = if self.dense {
+            unit_number / num_stripes * self.unit_size + relative % self.unit_size
+        } else {
+            offset
+        };
+        let left = self.unit_size - relative % self.unit_size;
+        (stripe as usize, data_server_offset, left)
+    }
+}
+
+pub(crate) struct FileLayout {
+    pub(crate) layout_type: LayoutType,
+    pub(crate) state_id: StateId,
+    // The part of the file the layout is for
+    start: u64,
+    end: u64,
+    // Copies of the file, reads use the first one and writes go to all of them
+    pub(crate) mirrors: Vec<Stripes>,
+    // Whether I/O may go through the server instead when a data server fails us
+    pub(crate) io_through_mds: bool,
+    pub(crate) read_io: bool,
+    pub(crate) write_one_mirror: bool,
+    pub(crate) layout_commit: bool,
+    // What went wrong with the data servers, for the range of the file it happened with
+    pub(crate) errors: Vec<(u64, u64, DeviceError)>,
+}
+
+impl FileLayout {
+    pub(crate) fn new(
+        layout_type: LayoutType,
+        state_id: StateId,
+        layout: &Layout,
+        mirrors: Vec<Stripes>,
+    ) -> Self {
+        Self {
+            layout_type,
+            state_id,
+            start: layout.offset,
+            end: layout.offset.saturating_add(layout.length),
+            mirrors,
+            io_through_mds: true,
+            read_io: true,
+            write_one_mirror: false,
+            layout_commit: true,
+            errors: vec![],
+        }
+    }
+
+    fn covers(&self, offset: u64) -> bool {
+        (self.start..self.end).contains(&offset)
+    }
+
+    fn data_file(&mut self, (mirror, stripe): (usize, usize)) -> &mut DataFile {
+        &mut self.mirrors[mirror].files[stripe]
+    }
+
+    fn failed(
+        &mut self,
+        target: (usize, usize),
+        range: (u64, u64),
+        op: OperationId,
+        status: StatusError,
+    ) {
+        let (offset, length) = range;
+        let device_id = self.data_file(target).device_id;
+        self.errors.push((
+            offset,
+            length,
+            DeviceError {
+                device_id,
+                status,
+                op,
+            },
+        ));
+    }
+}
+
+pub(crate) fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(xdr_extras::from_bytes(bytes).map_err(sun_rpc_client::Error::from)?)
+}
+
+/// Whether the server won't give us layouts for this file system at all.
+fn is_layout_unavailable(error: &Error) -> bool {
+    matches!(
+        error.status(),
+        Some(StatusError::LayoutUnavailable | StatusError::NotSupported)
+    )
+}
+
+impl<TransportT: Transport> Connection<TransportT> {
+    fn send_request<Args: CompoundRequest>(&mut self, args: Args) -> Result<Args::Geometry> {
+        let sequence = self.sequence_args(SlotId(0));
+        let (_, ((), geometry)) = self
+            .raw_client
+            .send_compound(ReturnSecond(sequence, args))?;
+        self.sent(SlotId(0));
+        Ok(geometry)
+    }
+
+    fn receive_reply<Args: CompoundRequest>(
+        &mut self,
+        geometry: Args::Geometry,
+    ) -> Result<Args::Response> {
+        let (_, compound_reply) = self.raw_client.receive_compound()?;
+        process_compound_reply::<ReturnSecond<SequenceArgs, Args>>(compound_reply, ((), geometry))
+    }
+}
+
+type DataServerRead = ReturnSecond<PutFhArgs, ReadArgs>;
+type DataServerWrite = ReturnSecond<PutFhArgs, WriteArgs>;
+
+// A request sent to a data server. The NFSv3 client doesn't let us wait for replies separately,
+// so requests to those are done right away.
+enum Pending<Geometry, Res> {
+    Sent(Geometry),
+    Done(Res),
+}
+
+type PendingRead = Pending<<DataServerRead as CompoundRequest>::Geometry, ReadRes>;
+type PendingWrite = Pending<<DataServerWrite as CompoundRequest>::Geometry, u32>;
+
+/// The status to report to the server for a failed request to a data server. Ones which didn't
+/// get as far as the data server are reported as it being unreachable.
+fn v4_status(error: Error) -> StatusError {
+    error.status().unwrap_or(StatusError::NxIo)
+}
+
+fn v3_status(error: nfs3_client::Error) -> StatusError {
+    // The statuses NFSv3 and NFSv4 have in common have the same values
+    let status = error.status().map(|s| s as u32);
+    status
+        .and_then(|s| StatusError::try_from(s).ok())
+        .unwrap_or(StatusError::NxIo)
+}
+
+impl<TransportT: Transport> DataServer<TransportT> {
+    fn send_read(
+        &mut self,
+        file: &DataFile,
+        state_id: StateId,
+        offset: u64,
+        count: u32,
+    ) -> std::result::Result<PendingRead, StatusError> {
+        match self {
+            Self::V3(client) => {
+                client.set_credential(file.credential.clone());
+                let res = client.read(nfs3::FileHandle(file.fh.0.to_vec()), offset, count);
+                res.map(|r| {
+                    Pending::Done(ReadRes {
+                        eof: r.eof,
+                        data: r.data.into(),
+                    })
+                })
+                .map_err(v3_status)
+            }
+            Self::V4(connection) => {
+                connection
+                    .raw_client
+                    .rpc_client
+                    .set_credential(file.credential.clone());
+                let request = ReturnSecond(
+                    PutFhArgs {
+                        object: file.fh.clone(),
+                    },
+                    ReadArgs {
+                        state_id,
+                        offset,
+                        count,
+                    },
+                );
+                let res = connection.send_request(request);
+                res.map(Pending::Sent).map_err(v4_status)
+            }
+        }
+    }
+
+    fn receive_read(&mut self, pending: PendingRead) -> std::result::Result<ReadRes, StatusError> {
+        match (self, pending) {
+            (_, Pending::Done(res)) => Ok(res),
+            (Self::V4(connection), Pending::Sent(geometry)) => {
+                let res = connection.receive_reply::<DataServerRead>(geometry);
+                res.map_err(v4_status)
+            }
+            (Self::V3(_), Pending::Sent(_)) => Err(StatusError::ServerFault),
+        }
+    }
+
+    fn send_write(
+        &mut self,
+        file: &DataFile,
+        state_id: StateId,
+        offset: u64,
+        data: Bytes,
+    ) -> std::result::Result<PendingWrite, StatusError> {
+        match self {
+            Self::V3(client) => {
+                client.set_credential(file.credential.clone());
+                let res = client.write(nfs3::FileHandle(file.fh.0.to_vec()), offset, data.into());
+                res.map(|r| Pending::Done(r.count)).map_err(v3_status)
+            }
+            Self::V4(connection) => {
+                connection
+                    .raw_client
+                    .rpc_client
+                    .set_credential(file.credential.clone());
+                let request = ReturnSecond(
+                    PutFhArgs {
+                        object: file.fh.clone(),
+                    },
+                    WriteArgs {
+                        state_id,
+                        offset,
+                        stable: StableHow::FileSync,
+                        data,
+                    },
+                );
+                let res = connection.send_request(request);
+                res.map(Pending::Sent).map_err(v4_status)
+            }
+        }
+    }
+
+    fn receive_write(&mut self, pending: PendingWrite) -> std::result::Result<u32, StatusError> {
+        match (self, pending) {
+            (_, Pending::Done(count)) => Ok(count),
+            (Self::V4(connection), Pending::Sent(geometry)) => {
+                let res = connection.receive_reply::<DataServerWrite>(geometry);
+                res.map(|r| r.count).map_err(v4_status)
+            }
+            (Self::V3(_), Pending::Sent(_)) => Err(StatusError::ServerFault),
+        }
+    }
+}
+
+// A request to a data file sent as part of a round
+struct InFlight<PendingT> {
+    target: (usize, usize),
+    sent: std::result::Result<PendingT, StatusError>,
+    started: Instant,
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Connects to the first of the given addresses of a data server we can reach, unless we
+    /// already have a session with it. NFSv3 data servers need a file handle on them to start
+    /// with.
+    pub(crate) fn connect_data_server(
+        &mut self,
+        addrs: &[NetAddr],
+        version: u32,
+        fh: &FileHandle,
+    ) -> Option<(DataServerId, NetAddr)> {
+        let client_owner: ClientOwner = self.client_owner.clone();
+        let credential = self.credential();
+        let metrics = self.metrics();
+        let pnfs = self.pnfs.as_mut().unwrap();
+        for addr in addrs {
+            let Some(socket_addr) = socket_addr(addr) else {
+                continue;
+            };
+            let id = (socket_addr, version);
+            if pnfs.data_servers.contains_key(&id) {
+                return Some((id, addr.clone()));
+            }
+            let Ok(transport) = (pnfs.connect)(socket_addr) else {
+                continue;
+            };
+            let data_server = if version == 3 {
+                let root = nfs3::FileHandle(fh.0.to_vec());
+                nfs3_client::Client::new(transport, root)
+                    .ok()
+                    .map(|c| DataServer::V3(Box::new(c)))
+            } else {
+                Connection::establish(
+                    transport,
+                    &client_owner,
+                    credential.clone(),
+                    self.minor_versions.clone(),
+                    false,
+                )
+                .ok()
+                .and_then(|(mut c, ..)| {
+                    c.raw_client.tracer.metrics = metrics.clone();
+                    let main = &lock(&self.connection).raw_client.rpc_client;
+                    c.raw_client.rpc_client.copy_reply_timeout(main).ok()?;
+                    Some(DataServer::V4(Box::new(c)))
+                })
+            };
+            if let Some(data_server) = data_server {
+                pnfs.data_servers.insert(id, data_server);
+                return Some((id, addr.clone()));
+            }
+        }
+        None
+    }
+
+    /// The credential we send requests to the server with.
+    pub(crate) fn credential(&self) -> OpaqueAuth {
+        lock(&self.connection)
+            .raw_client
+            .rpc_client
+            .credential()
+            .clone()
+    }
+
+    /// Gets a layout for the whole of the given open file and connects to its data servers.
+    /// Returns `None` when the server doesn't give us one we can use, and I/O should go through it
+    /// instead.
+    fn layout_get(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        io_mode: LayoutIoMode,
+    ) -> Result<Option<FileLayout>> {
+        let layout_types = self.pnfs.as_ref().unwrap().layout_types.clone();
+        for layout_type in layout_types {
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: file.handle.clone(),
+                },
+                LayoutGetArgs {
+                    signal_layout_available: false,
+                    layout_type,
+                    io_mode: io_mode.clone(),
+                    offset: 0,
+                    length: u64::MAX,
+                    min_length: 0,
+                    state_id: file.state_id,
+                    max_count: LAYOUT_MAX_COUNT,
+                },
+            ));
+            let pnfs = self.pnfs.as_mut().unwrap();
+            match res {
+                Ok(res) => return self.use_layout(file, layout_type, res),
+                Err(e) if e.status() == Some(StatusError::UnknownLayoutType) => {
+                    pnfs.layout_types.retain(|t| *t != layout_type);
+                }
+                Err(e) if is_layout_unavailable(&e) => {
+                    pnfs.layout_types.clear();
+                    return Ok(None);
+                }
+                Err(e) if e.status().is_some() => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    fn use_layout(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        layout_type: LayoutType,
+        res: LayoutGetRes,
+    ) -> Result<Option<FileLayout>> {
+        let layout = res
+            .layout
+            .iter()
+            .find(|l| l.offset == 0 && l.content.type_ == layout_type);
+        let decoded = match (layout, layout_type) {
+            (Some(l), LayoutType::NfsV41Files) => self.files_layout(l, res.state_id),
+            (Some(l), LayoutType::FlexFiles) => self.flex_files_layout(l, res.state_id),
+            _ => Ok(None),
+        };
+        match decoded {
+            Ok(Some(layout)) => Ok(Some(layout)),
+            // We can't use what we got, so give it straight back
+            decoded => {
+                self.layout_return(&file.handle, layout_type, res.state_id, vec![])?;
+                match decoded {
+                    Err(e) if e.status().is_none() => Err(e),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Finds the data servers of the given files layout and connects to them. Returns `None` if
+    /// they can't be used.
+    fn files_layout(&mut self, layout: &Layout, state_id: StateId) -> Result<Option<FileLayout>> {
+        let files_layout: FilesLayout = decode(&layout.content.body)?;
+        let device = self.do_compound(GetDeviceInfoArgs {
+            device_id: files_layout.device_id,
+            layout_type: LayoutType::NfsV41Files,
+            max_count: DEVICE_INFO_MAX_COUNT,
+            notify_types: Default::default(),
+        })?;
+        let device: FilesDeviceAddr = decode(&device.device_addr.body)?;
+
+        let num_stripes = device.stripe_indices.len();
+        let unit_size = files_layout.util.stripe_unit_size();
+        let num_fhs = files_layout.fh_list.len();
+        if num_stripes == 0 || unit_size == 0 || (num_fhs != 1 && num_fhs != num_stripes) {
+            return Ok(None);
+        }
+
+        let credential = self.credential();
+        let mut files = vec![];
+        for (stripe, index) in device.stripe_indices.iter().enumerate() {
+            let fh = &files_layout.fh_list[if num_fhs == 1 { 0 } else { stripe }];
+            let Some(addrs) = device.multipath_ds_list.get(*index as usize) else {
+                return Ok(None);
+            };
+            let Some((data_server, addr)) = self.connect_data_server(addrs, 4, fh) else {
+                return Ok(None);
+            };
+            files.push(DataFile {
+                data_server,
+                addr,
+                device_id: files_layout.device_id,
+                fh: fh.clone(),
+                state_id: None,
+                credential: credential.clone(),
+                stats: Default::default(),
+            });
+        }
+        let stripes = Stripes {
+            unit_size: unit_size.into(),
+            first_stripe_index: files_layout.first_stripe_index.into(),
+            pattern_offset: files_layout.pattern_offset,
+            dense: files_layout.util.is_dense(),
+            files,
+        };
+        Ok(Some(FileLayout::new(
+            LayoutType::NfsV41Files,
+            state_id,
+            layout,
+            vec![stripes],
+        )))
+    }
+
+    fn layout_return(
+        &mut self,
+        handle: &FileHandle,
+        layout_type: LayoutType,
+        state_id: StateId,
+        body: Vec<u8>,
+    ) -> Result<()> {
+        self.do_compound((
+            PutFhArgs {
+                object: handle.clone(),
+            },
+            LayoutReturnArgs {
+                reclaim: false,
+                layout_type,
+                io_mode: LayoutIoMode::Any,
+                layout_return: LayoutReturn::File(LayoutReturnFile {
+                    offset: 0,
+                    length: u64::MAX,
+                    state_id,
+                    body,
+                }),
+            },
+        ))?;
+        Ok(())
+    }
+
+    /// Gives back the layout, telling the server how the data servers did if it wants to know.
+    fn finish_layout(&mut self, handle: &FileHandle, layout: FileLayout) -> Result<()> {
+        let body = match layout.layout_type {
+            LayoutType::FlexFiles => self.flex_files_report(handle, &layout),
+            _ => vec![],
+        };
+        self.layout_return(handle, layout.layout_type, layout.state_id, body)
+    }
+
+    /// Opens the file for pNFS I/O and gets a layout for it, unless the server won't give us one.
+    fn open_for_pnfs(
+        &mut self,
+        handle: &FileHandle,
+        access: ShareAccess,
+    ) -> Result<Option<(OpenFile<TransportT>, FileLayout)>> {
+        if self.pnfs.as_ref().is_none_or(|p| p.layout_types.is_empty()) {
+            return Ok(None);
+        }
+        let options = OpenOptions::new().access(access);
+        let file = self.open_with_claim(handle.clone(), OpenClaim::Fh, &options)?;
+        let io_mode = if access.contains(ShareAccess::WRITE) {
+            LayoutIoMode::ReadWrite
+        } else {
+            LayoutIoMode::Read
+        };
+        Ok(self
+            .layout_get(&file, io_mode)?
+            .map(|layout| (file, layout)))
+    }
+
+    /// Like `read_all`, but reading from the data servers. Returns false without having read
+    /// anything when the server doesn't give us a layout for the file.
+    pub(crate) fn pnfs_read_all(
+        &mut self,
+        handle: &FileHandle,
+        sink: &mut impl io::Write,
+    ) -> Result<bool> {
+        let Some((file, mut layout)) = self.open_for_pnfs(handle, ShareAccess::READ)? else {
+            return Ok(false);
+        };
+        let res = if layout.read_io {
+            self.read_striped(&file, &mut layout, sink).map(|()| true)
+        } else {
+            Ok(false)
+        };
+        let _ = self.finish_layout(handle, layout);
+        res
+    }
+
+    fn read_striped(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        layout: &mut FileLayout,
+        sink: &mut impl io::Write,
+    ) -> Result<()> {
+        let size = *self
+            .get_attr(file.handle.clone())?
+            .object_attributes
+            .get_as::<u64>(FileAttributeId::Size)
+            .ok_or(Error::CompoundResponseMismatch("missing size".into()))?;
+        let chunk_size = u64::from(self.read_chunk_size());
+
+        let mut offset = 0;
+        while offset < size {
+            // Each data server gets one READ per round, so that we get the replies in order
+            let mut round: Vec<(InFlight<PendingRead>, u64, u32)> = vec![];
+            let mut busy = BTreeSet::new();
+            let mut next = offset;
+            while next < size && layout.covers(next) {
+                let (stripe, data_server_offset, left) = layout.mirrors[0].locate(next);
+                let target = (0, stripe);
+                let data_file = layout.data_file(target);
+                if !busy.insert(data_file.data_server) {
+                    break;
+                }
+                let count = left.min(size - next).min(chunk_size) as u32;
+                let state_id = data_file.state_id.unwrap_or(file.state_id);
+                let pnfs = self.pnfs.as_mut().unwrap();
+                let sent = match pnfs.data_servers.get_mut(&data_file.data_server) {
+                    Some(ds) => ds.send_read(data_file, state_id, data_server_offset, count),
+                    None => Err(StatusError::NxIo),
+                };
+                data_file.stats.since.get_or_insert_with(Instant::now);
+                data_file.stats.read.requested(count as usize);
+                let in_flight = InFlight {
+                    target,
+                    sent,
+                    started: Instant::now(),
+                };
+                round.push((in_flight, next, count));
+                next += u64::from(count);
+            }
+
+            if round.is_empty() {
+                // The layout doesn't cover this part of the file
+                let count = chunk_size.min(size - offset) as u32;
+                let res =
+                    self.read_with_state(file.handle.clone(), file.state_id, offset, count)?;
+                sink.write_all(&res.data)?;
+                if res.data.is_empty() {
+                    break;
+                }
+                offset += res.data.len() as u64;
+                continue;
+            }
+
+            let mut failed = vec![];
+            let mut replies = vec![];
+            for (in_flight, chunk_offset, count) in round {
+                let pnfs = self.pnfs.as_mut().unwrap();
+                let data_file = layout.data_file(in_flight.target);
+                let reply = in_flight.sent.and_then(|pending| {
+                    match pnfs.data_servers.get_mut(&data_file.data_server) {
+                        Some(ds) => ds.receive_read(pending),
+                        None => Err(StatusError::NxIo),
+                    }
+                });
+                match &reply {
+                    Ok(res) => data_file
+                        .stats
+                        .read
+                        .completed(res.data.len(), in_flight.started),
+                    Err(status) => {
+                        failed.push(data_file.data_server);
+                        let range = (chunk_offset, u64::from(count));
+                        layout.failed(in_flight.target, range, OperationId::Read, *status);
+                    }
+                }
+                replies.push((chunk_offset, count, reply));
+            }
+            // We can't tell what state the connections to the ones which failed are in
+            for data_server in failed {
+                self.pnfs
+                    .as_mut()
+                    .unwrap()
+                    .data_servers
+                    .remove(&data_server);
+            }
+
+            for (chunk_offset, count, reply) in replies {
+                let mut data = match reply {
+                    Ok(res) if res.eof || res.data.len() >= count as usize => res.data.into(),
+                    Ok(res) => {
+                        let mut data = Vec::from(res.data);
+                        self.finish_short_read(file, layout, chunk_offset, count, &mut data)?;
+                        data
+                    }
+                    Err(status) if !layout.io_through_mds => return Err(status.into()),
+                    Err(_) => self.read_range(file, chunk_offset, count)?,
+                };
+                // Data servers don't know how large the file is, so parts of it they don't have
+                // are holes
+                data.resize(count as usize, 0);
+                sink.write_all(&data)?;
+            }
+            offset = next;
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of a chunk a data server returned less of than asked for without having
+    /// got to the end of what it has, from the data servers again.
+    fn finish_short_read(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        layout: &mut FileLayout,
+        chunk_offset: u64,
+        count: u32,
+        data: &mut Vec<u8>,
+    ) -> Result<()> {
+        while data.len() < count as usize {
+            let offset = chunk_offset + data.len() as u64;
+            let left = count - data.len() as u32;
+            let (stripe, data_server_offset, _) = layout.mirrors[0].locate(offset);
+            let data_file = layout.data_file((0, stripe));
+            let state_id = data_file.state_id.unwrap_or(file.state_id);
+            let pnfs = self.pnfs.as_mut().unwrap();
+            let res = match pnfs.data_servers.get_mut(&data_file.data_server) {
+                Some(ds) => ds
+                    .send_read(data_file, state_id, data_server_offset, left)
+                    .and_then(|pending| ds.receive_read(pending)),
+                None => Err(StatusError::NxIo),
+            };
+            match res {
+                Ok(res) => {
+                    let eof = res.eof || res.data.is_empty();
+                    data.extend(res.data);
+                    if eof {
+                        break;
+                    }
+                }
+                Err(status) if !layout.io_through_mds => return Err(status.into()),
+                Err(_) => {
+                    data.extend(self.read_range(file, offset, left)?);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the given range through the server rather than a data server.
+    fn read_range(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        offset: u64,
+        count: u32,
+    ) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        while data.len() < count as usize {
+            let res = self.read_with_state(
+                file.handle.clone(),
+                file.state_id,
+                offset + data.len() as u64,
+                count - data.len() as u32,
+            )?;
+            data.extend(&res.data);
+            if res.eof || res.data.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Like `write_all`, but writing to the data servers. Returns false without having read from
+    /// the source when the server doesn't give us a layout for the file.
+    pub(crate) fn pnfs_write_all(
+        &mut self,
+        handle: &FileHandle,
+        source: &mut impl io::Read,
+    ) -> Result<bool> {
+        let Some((file, mut layout)) = self.open_for_pnfs(handle, ShareAccess::WRITE)? else {
+            return Ok(false);
+        };
+        let res = self.write_striped(&file, &mut layout, source);
+        let _ = self.finish_layout(handle, layout);
+        res.map(|()| true)
+    }
+
+    fn write_striped(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        layout: &mut FileLayout,
+        source: &mut impl io::Read,
+    ) -> Result<()> {
+        let chunk_size = u64::from(self.write_chunk_size());
+        let num_mirrors = if layout.write_one_mirror {
+            1
+        } else {
+            layout.mirrors.len()
+        };
+
+        let mut offset = 0;
+        let mut done = false;
+        while !done {
+            // Like when reading, each data server gets one WRITE per round. Each chunk goes to
+            // every mirror.
+            let mut round: Vec<(Vec<InFlight<PendingWrite>>, u64, Bytes)> = vec![];
+            let mut busy = BTreeSet::new();
+            let mut next = offset;
+            while layout.covers(next) {
+                let targets: Vec<_> = (0..num_mirrors)
+                    .map(|m| (m, layout.mirrors[m].locate(next)))
+                    .collect();
+                let data_servers: BTreeSet<_> = targets
+                    .iter()
+                    .map(|&(m, (stripe, ..))| layout.data_file((m, stripe)).data_server)
+                    .collect();
+                if data_servers.len() < targets.len() || !busy.is_disjoint(&data_servers) {
+                    break;
+                }
+                busy.extend(data_servers);
+
+                let left = targets.iter().map(|(_, (_, _, left))| *left).min();
+                let mut data = vec![];
+                source
+                    .take(left.unwrap_or(0).min(chunk_size))
+                    .read_to_end(&mut data)?;
+                if data.is_empty() {
+                    done = true;
+                    break;
+                }
+                let data = Bytes::from(data);
+
+                let mut in_flight = vec![];
+                for (mirror, (stripe, data_server_offset, _)) in targets {
+                    let target = (mirror, stripe);
+                    let data_file = layout.data_file(target);
+                    let state_id = data_file.state_id.unwrap_or(file.state_id);
+                    let pnfs = self.pnfs.as_mut().unwrap();
+                    let sent = match pnfs.data_servers.get_mut(&data_file.data_server) {
+                        Some(ds) => {
+                            ds.send_write(data_file, state_id, data_server_offset, data.clone())
+                        }
+                        None => Err(StatusError::NxIo),
+                    };
+                    data_file.stats.since.get_or_insert_with(Instant::now);
+                    data_file.stats.write.requested(data.len());
+                    in_flight.push(InFlight {
+                        target,
+                        sent,
+                        started: Instant::now(),
+                    });
+                }
+                let len = data.len() as u64;
+                round.push((in_flight, next, data));
+                next += len;
+            }
+
+            if round.is_empty() && !done {
+                // The layout doesn't cover this part of the file
+                let mut data = vec![];
+                source.take(chunk_size).read_to_end(&mut data)?;
+                if data.is_empty() {
+                    break;
+                }
+                let len = data.len() as u64;
+                self.write_range(file, offset, data.into())?;
+                offset += len;
+                continue;
+            }
+
+            let mut failed = vec![];
+            let mut replies = vec![];
+            for (in_flight, chunk_offset, data) in round {
+                let mut status = None;
+                for request in in_flight {
+                    let pnfs = self.pnfs.as_mut().unwrap();
+                    let data_file = layout.data_file(request.target);
+                    let reply = request.sent.and_then(|pending| {
+                        match pnfs.data_servers.get_mut(&data_file.data_server) {
+                            Some(ds) => ds.receive_write(pending),
+                            None => Err(StatusError::NxIo),
+                        }
+                    });
+                    match reply {
+                        Ok(count) => {
+                            data_file
+                                .stats
+                                .write
+                                .completed(count as usize, request.started);
+                            // A short write leaves the mirrors out of step with each other, so
+                            // the chunk is written through the server instead
+                            if (count as usize) < data.len() {
+                                status.get_or_insert(StatusError::Io);
+                            }
+                        }
+                        Err(s) => {
+                            failed.push(data_file.data_server);
+                            let range = (chunk_offset, data.len() as u64);
+                            layout.failed(request.target, range, OperationId::Write, s);
+                            status = Some(s);
+                        }
+                    }
+                }
+                replies.push((chunk_offset, data, status));
+            }
+            for data_server in failed {
+                self.pnfs
+                    .as_mut()
+                    .unwrap()
+                    .data_servers
+                    .remove(&data_server);
+            }
+
+            for (chunk_offset, data, status) in replies {
+                match status {
+                    None => {}
+                    Some(status) if !layout.io_through_mds => return Err(status.into()),
+                    Some(_) => self.write_range(file, chunk_offset, data)?,
+                }
+            }
+            offset = next;
+        }
+
+        if offset > 0 && layout.layout_commit {
+            self.do_compound((
+                PutFhArgs {
+                    object: file.handle.clone(),
+                },
+                LayoutCommitArgs {
+                    offset: 0,
+                    length: offset,
+                    reclaim: false,
+                    state_id: layout.state_id,
+                    last_write_offset: Some(offset - 1),
+                    time_modify: None,
+                    layout_update: LayoutUpdate {
+                        type_: layout.layout_type,
+                        body: vec![],
+                    },
+                },
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the given data through the server rather than a data server.
+    fn write_range(
+        &mut self,
+        file: &OpenFile<TransportT>,
+        mut offset: u64,
+        mut data: Bytes,
+    ) -> Result<()> {
+        while !data.is_empty() {
+            let res =
+                self.write_with_state(file.handle.clone(), file.state_id, offset, data.clone())?;
+            if res.count == 0 {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+            }
+            let count = (res.count as usize).min(data.len());
+            data = data.slice(count..);
+            offset += count as u64;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_data_server_address() {
+    let addr = |netid: &str, addr: &str| {
+        socket_addr(&NetAddr {
+            netid: netid.into(),
+            addr: addr.into(),
+        })
+    };
+    assert_eq!(
+        addr("tcp", "192.168.0.1.8.1"),
+        Some("192.168.0.1:2049".parse().unwrap())
+    );
+    assert_eq!(addr("tcp6", "::1.8.1"), Some("[::1]:2049".parse().unwrap()));
+    assert_eq!(addr("rdma", "192.168.0.1.8.1"), None);
+    assert_eq!(addr("tcp", "host.8.1"), None);
+}
+
+#[test]
+fn locate_stripes() {
+    let stripes = |dense| Stripes {
+        unit_size: 100,
+        first_stripe_index: 1,
+        pattern_offset: 0,
+        dense,
+        files: (0..3)
+            .map(|i| DataFile {
+                data_server: ("10.0.0.1:2049".parse().unwrap(), 4),
+                addr: NetAddr {
+                    netid: "tcp".into(),
+                    addr: "10.0.0.1.8.1".into(),
+                },
+                device_id: DeviceId([0; 16]),
+                fh: FileHandle(vec![i].into()),
+                state_id: None,
+                credential: OpaqueAuth::none(),
+                stats: Default::default(),
+            })
+            .collect(),
+    };
+
+    let sparse = stripes(false);
+    assert_eq!(sparse.locate(0), (1, 0, 100));
+    assert_eq!(sparse.locate(150), (2, 150, 50));
+    assert_eq!(sparse.locate(250), (0, 250, 50));
+    assert_eq!(sparse.locate(310), (1, 310, 90));
+
+    let dense = stripes(true);
+    assert_eq!(dense.locate(0), (1, 0, 100));
+    assert_eq!(dense.locate(150), (2, 50, 50));
+    assert_eq!(dense.locate(250), (0, 50, 50));
+    assert_eq!(dense.locate(310), (1, 110, 90));
+}
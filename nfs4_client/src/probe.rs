@@ -0,0 +1,54 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Talking to a server without a session, for finding out whether it is there and what it
+//! supports before, or instead of, connecting properly.
+
+use super::{Result, COMPOUND_PROCEDURE, NFS};
+use nfs4::{CompoundArgs, CompoundRes, StatusError, StatusResult};
+use sun_rpc_client::{RpcClient, Transport, NULL_PROCEDURE};
+
+pub struct Probe<TransportT> {
+    rpc_client: RpcClient<TransportT>,
+}
+
+impl<TransportT: Transport> Probe<TransportT> {
+    pub fn new(transport: TransportT) -> Self {
+        Self {
+            rpc_client: RpcClient::with_version(transport, NFS, 4),
+        }
+    }
+
+    /// Calls the NULL procedure, which the server answers without doing anything, so how long it
+    /// takes is how long a round trip to it takes.
+    pub fn null(&mut self) -> Result<()> {
+        Ok(self.rpc_client.call(NULL_PROCEDURE, ())?)
+    }
+
+    /// Whether the server speaks the given minor version of NFSv4. A COMPOUND with no operations
+    /// in it succeeds unless the server doesn't take its minor version, RFC 8881 section 16.2.3.
+    pub fn supports_minor_version(&mut self, minor_version: u32) -> Result<bool> {
+        let args = CompoundArgs {
+            tag: "probe".into(),
+            minor_version,
+            arg_array: vec![],
+        };
+        let res: CompoundRes = self.rpc_client.call(COMPOUND_PROCEDURE, args)?;
+        match res.status {
+            StatusResult::Ok(()) => Ok(true),
+            StatusResult::Err(StatusError::MinorVersMismatch) => Ok(false),
+            StatusResult::Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[test]
+fn probe() {
+    use nfs4_server::{memory::MemoryFs, Server};
+
+    let server = Server::with_file_system(MemoryFs::new());
+    let mut probe = Probe::new(server.connect_in_process());
+    probe.null().unwrap();
+    assert!(probe.supports_minor_version(0).unwrap());
+    assert!(probe.supports_minor_version(1).unwrap());
+    assert!(!probe.supports_minor_version(2).unwrap());
+}
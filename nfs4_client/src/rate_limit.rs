@@ -0,0 +1,105 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Limiting how fast `read_all` and `write_all` move data, so that a large transfer doesn't take
+//! all of a link shared with others.
+
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    bytes_per_second: u64,
+    // Goes below zero when more is taken than there is, which is then waited off
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket which fills at the given rate and holds up to a second's worth. Clones share the
+/// same bucket, so one limit can be set on several clients transferring at once.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                bytes_per_second,
+                tokens: bytes_per_second as f64,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bucket
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .bytes_per_second
+    }
+
+    /// Takes the given number of bytes from the bucket, waiting for as long as it takes to fill
+    /// back up if there weren't enough.
+    pub fn take(&self, len: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+            let rate = bucket.bytes_per_second as f64;
+            let now = Instant::now();
+            let filled = now.duration_since(bucket.updated).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + filled).min(rate) - len as f64;
+            bucket.updated = now;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / rate)
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// A sink or source which is only read from or written to as fast as the limiter allows, or as
+/// fast as it goes when there is none.
+pub(crate) struct RateLimited<T> {
+    inner: T,
+    limiter: Option<RateLimiter>,
+}
+
+impl<T> RateLimited<T> {
+    pub(crate) fn new(inner: T, limiter: Option<RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<T: io::Read> io::Read for RateLimited<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(limiter) = &self.limiter {
+            limiter.take(len);
+        }
+        Ok(len)
+    }
+}
+
+impl<T: io::Write> io::Write for RateLimited<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if let Some(limiter) = &self.limiter {
+            limiter.take(len);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn waits_once_bucket_is_empty() {
+    let limiter = RateLimiter::new(100_000);
+    let start = Instant::now();
+    limiter.clone().take(100_000);
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    limiter.take(50_000);
+    assert!(start.elapsed() >= Duration::from_millis(450));
+}
@@ -0,0 +1,126 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Reclaiming what we had open after the server restarts, see RFC 8881 section 8.4.2.1. Once we
+//! have a new client ID and session, each file we have open is opened again with CLAIM_PREVIOUS
+//! during the server's grace period. The state IDs of the old opens are swapped for the new ones
+//! in the requests using them, so the `OpenFile`s callers have keep working, and a request cut
+//! off by the restart can be sent again. We don't take byte-range locks, so there are none to
+//! reclaim.
+
+use super::{Connection, Result, ReturnSecond};
+use nfs4::{
+    ArgOp, FileHandle, OpenArgs, OpenClaim, OpenDelegationType, OpenFlag, PutFhArgs, SequenceId,
+    StateId, StateOwner,
+};
+use sun_rpc_client::Transport;
+
+impl<TransportT: Transport> Connection<TransportT> {
+    /// Opens each file we have open again as the given open-owner, with the modes it was open
+    /// with. Opens the server won't let us reclaim are forgotten about, requests using them fail
+    /// like they would have anyway.
+    pub(crate) fn reclaim_opens(&mut self, owner: &StateOwner) -> Result<()> {
+        let handles: Vec<_> = self.opens.keys().cloned().collect();
+        for handle in handles {
+            let open = &self.opens[&handle];
+            let old = open.state_id;
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: FileHandle(handle.clone()),
+                },
+                OpenArgs {
+                    sequence_id: SequenceId(0),
+                    share_access: open.access,
+                    share_deny: open.deny,
+                    owner: owner.clone(),
+                    open_how: OpenFlag::OpenNoCreate,
+                    claim: OpenClaim::Previous {
+                        delegate_type: OpenDelegationType::None,
+                    },
+                },
+            ));
+            match res {
+                Ok(res) => {
+                    self.opens.get_mut(&handle).unwrap().state_id = res.state_id;
+                    self.reclaimed(old, res.state_id);
+                }
+                Err(e) if e.status().is_some() => {
+                    self.opens.remove(&handle);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn reclaimed(&mut self, old: StateId, new: StateId) {
+        for replacement in self.reclaimed.values_mut() {
+            if replacement.other == old.other {
+                *replacement = new;
+            }
+        }
+        self.reclaimed.insert(old.other, new);
+    }
+
+    /// Swaps the state IDs of opens from before the server restarted in the request for the ones
+    /// they were reclaimed as.
+    pub(crate) fn use_reclaimed_state_ids(&self, arg_array: &mut [ArgOp]) {
+        if self.reclaimed.is_empty() {
+            return;
+        }
+        for op in arg_array {
+            let state_id = match op {
+                ArgOp::Read(args) => &mut args.state_id,
+                ArgOp::Write(args) => &mut args.state_id,
+                ArgOp::SetAttr(args) => &mut args.state_id,
+                ArgOp::Close(args) => &mut args.open_stateid,
+                ArgOp::OpenDowngrade(args) => &mut args.open_state_id,
+                _ => continue,
+            };
+            if let Some(reclaimed) = self.reclaimed.get(&state_id.other) {
+                // The open may have been upgraded or downgraded since, so whatever it is now
+                *state_id = StateId {
+                    sequence_id: 0,
+                    other: reclaimed.other,
+                };
+            }
+        }
+    }
+}
+
+#[test]
+fn reclaims_opens_after_server_restarts() {
+    use super::{lock, OpenOptions};
+    use nfs4::{ShareAccess, StatusError};
+    use nfs4_server::memory::MemoryFs;
+
+    let (server, mut client) = super::in_memory_client(MemoryFs::new());
+    let mut other_client = super::Client::new(server.connect_in_process()).unwrap();
+    other_client.set_retry_deadline(std::time::Duration::ZERO);
+    let root = client.look_up("/").unwrap();
+    let options = OpenOptions::new().access(ShareAccess::BOTH).create();
+    let file = client.open(root.clone(), "file", &options).unwrap();
+    let handle = file.handle.clone();
+    client
+        .write_with_state(handle.clone(), file.state_id, 0, "before".into())
+        .unwrap();
+
+    server.restart();
+    // Until we are done reclaiming, nobody else can open anything
+    let error = other_client
+        .open(root, "file", &OpenOptions::new())
+        .unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::Grace));
+
+    // The WRITE is cut off along with the session, and sent again with the reclaimed open
+    client
+        .write_with_state(handle.clone(), file.state_id, 6, " and after".into())
+        .unwrap();
+    assert_ne!(
+        lock(&client.connection).opens[&handle.0].state_id.other,
+        file.state_id.other
+    );
+    let mut contents = vec![];
+    client.read_all(handle, &mut contents).unwrap();
+    assert_eq!(contents, b"before and after");
+    client.close(file).unwrap();
+}
@@ -0,0 +1,121 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Reconnecting when the connection to the server breaks. The old session could be kept by binding
+//! the new connection to it, but requests lost along with the connection leave its slots out of
+//! step with the server. Instead a new session is made for our client ID, which keeps everything we
+//! have open and locked as long as our lease didn't run out in the meantime.
+
+use super::{lock, CallbackProgram, Client, ClientWithoutSession, Error, Result, NFS};
+use nfs4::ArgOp;
+use std::io;
+use std::sync::mpsc;
+use sun_rpc_client::{RpcClient, Transport};
+
+pub(crate) type Reconnector<TransportT> = Box<dyn FnMut() -> io::Result<TransportT> + Send>;
+
+// How many times in a row we reconnect before giving up on a request
+pub(crate) const MAX_RECONNECTIONS: usize = 3;
+
+/// Whether the error means the connection to the server is gone.
+pub(crate) fn is_connection_broken(error: &Error) -> bool {
+    let io_error = match error {
+        Error::SunRpc(sun_rpc_client::Error::Io(e)) | Error::Io(e) => e,
+        // Whatever is left of the message is still to come
+        Error::SunRpc(sun_rpc_client::Error::MessageTooLarge(_)) => return true,
+        _ => return false,
+    };
+    matches!(
+        io_error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Whether doing the operation twice leaves things the same as doing it once, so it can be sent
+/// again when we can't tell if the server got it before the connection broke. Ones which create
+/// or remove something, or which change state IDs, aren't.
+pub(crate) fn is_idempotent(op: &ArgOp) -> bool {
+    !matches!(
+        op,
+        ArgOp::Close(_)
+            | ArgOp::Copy(_)
+            | ArgOp::Create(_)
+            | ArgOp::DelegReturn(_)
+            | ArgOp::FreeStateid(_)
+            | ArgOp::LayoutReturn(_)
+            | ArgOp::Link(_)
+            | ArgOp::Lock(_)
+            | ArgOp::LockU(_)
+            | ArgOp::OffloadCancel(_)
+            | ArgOp::Open(_)
+            | ArgOp::OpenDowngrade(_)
+            | ArgOp::ReleaseLockOwner(_)
+            | ArgOp::Remove(_)
+            | ArgOp::Rename(_)
+    )
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Makes the client connect to the server again with the given function when the connection
+    /// to it breaks, rather than failing every request from then on. Requests which were cut off
+    /// are sent again, except for ones which aren't safe to send twice, like a RENAME the server
+    /// may already have done. Those fail with `Error::ConnectionLost` instead.
+    pub fn reconnect_with(
+        &mut self,
+        connect: impl FnMut() -> io::Result<TransportT> + Send + 'static,
+    ) {
+        self.reconnect = Some(Box::new(connect));
+    }
+
+    pub(crate) fn can_reconnect(&self) -> bool {
+        self.reconnect.is_some()
+    }
+
+    /// Replaces the broken connection with a new one and makes a new session on it. The
+    /// connections the session is trunked over are bound to the new one if they still work.
+    pub(crate) fn reconnect(&mut self) -> Result<()> {
+        let connect = self.reconnect.as_mut().unwrap();
+        let transport = connect()?;
+
+        // Keep using the same connection so the lease renewal thread follows along
+        let mut connection = lock(&self.connection);
+        let old = &connection.raw_client;
+        let (callbacks_sender, callbacks) = mpsc::channel();
+        let mut rpc_client = RpcClient::new(transport, NFS);
+        rpc_client.set_credential(old.rpc_client.credential().clone());
+        rpc_client.set_gss(old.rpc_client.gss().cloned());
+        rpc_client.copy_reply_timeout(&old.rpc_client)?;
+        rpc_client.set_max_message_size(old.rpc_client.max_message_size());
+        rpc_client.set_limits(old.rpc_client.limits());
+        rpc_client.serve(CallbackProgram::new(callbacks_sender));
+        let mut raw_client = ClientWithoutSession::new(rpc_client);
+        raw_client.minor_version = old.minor_version;
+        raw_client.protect_state = old.protect_state;
+        raw_client.machine_credential = old.machine_credential.clone();
+        raw_client.tracer.metrics = self.metrics();
+        connection.raw_client = raw_client;
+        connection.callbacks = callbacks;
+        drop(connection);
+
+        self.recover_session()
+    }
+}
+
+#[test]
+fn broken_connection() {
+    let reset = Error::Io(io::ErrorKind::ConnectionReset.into());
+    assert!(is_connection_broken(&reset));
+    let eof = Error::SunRpc(sun_rpc_client::Error::Io(
+        io::ErrorKind::UnexpectedEof.into(),
+    ));
+    assert!(is_connection_broken(&eof));
+    let too_large = Error::SunRpc(sun_rpc_client::Error::MessageTooLarge(100));
+    assert!(is_connection_broken(&too_large));
+    assert!(!is_connection_broken(&Error::Io(
+        io::ErrorKind::TimedOut.into()
+    )));
+    assert!(!is_connection_broken(&nfs4::StatusError::Io.into()));
+}
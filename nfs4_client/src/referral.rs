@@ -0,0 +1,249 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Referrals and migration, see RFC 5661 section 11. Part of the namespace, or all of it, can live
+//! on another server, which the server tells us about with the fs_locations and
+//! fs_locations_info attributes once we get MOVED walking into it. We then move over to that
+//! server and walk the rest of the path from where the file system is kept there.
+
+use super::{
+    lock, look_up_args, Client, CompoundRequest, Connection, GetFh, PutRootFh, RemotePath,
+    RemotePathBuf, Result, ReturnSecond, NFS_PORT,
+};
+use nfs4::{
+    FileAttributeId, FileAttributes, FsLocations, FsLocationsInfo, GetAttrArgs, PathName,
+    StatusError,
+};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs as _};
+use sun_rpc_client::Transport;
+
+// How many referrals we follow for one path before giving up, in case they go around in circles
+const MAX_REFERRALS: usize = 8;
+
+/// The addresses to try for the given server from a location, which is a host name or address.
+fn server_addrs(server: &str) -> Vec<SocketAddr> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return vec![addr];
+    }
+    if let Ok(ip) = server.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return vec![SocketAddr::new(ip, NFS_PORT)];
+    }
+    (server, NFS_PORT)
+        .to_socket_addrs()
+        .map(|addrs| addrs.collect())
+        .unwrap_or_default()
+}
+
+fn path_name(path: &PathName) -> RemotePathBuf {
+    let mut path_buf = RemotePathBuf::from("/");
+    path_buf.extend(path.0.iter().map(|c| &c.0));
+    path_buf
+}
+
+/// The servers a file system can be found on along with where it is kept on each, in the order
+/// they should be tried. fs_locations_info is preferred since it has them in order of preference.
+fn locations(attrs: &FileAttributes) -> Vec<(String, RemotePathBuf)> {
+    if let Some(info) = attrs.get_as::<FsLocationsInfo>(FileAttributeId::FsLocationsInfo) {
+        return info
+            .items
+            .iter()
+            .flat_map(|item| {
+                let root = path_name(&item.root_path);
+                item.entries
+                    .iter()
+                    .map(move |e| (e.server.clone(), root.clone()))
+            })
+            .collect();
+    }
+    let Some(fs_locations) = attrs.get_as::<FsLocations>(FileAttributeId::FsLocations) else {
+        return vec![];
+    };
+    fs_locations
+        .locations
+        .iter()
+        .flat_map(|location| {
+            let root = path_name(&location.root_path);
+            location
+                .server
+                .iter()
+                .map(move |s| (s.clone(), root.clone()))
+        })
+        .collect()
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Sends the request built for the given path, which starts from the root. If it gets MOVED
+    /// and referrals are being followed, it is sent again to where the path leads.
+    pub(crate) fn do_path_compound<Args: CompoundRequest>(
+        &mut self,
+        path: &RemotePath,
+        args: impl Fn(&RemotePath) -> Args,
+    ) -> Result<Args::Response> {
+        let mut referred_path = path.to_owned();
+        let mut referrals = 0;
+        loop {
+            let error = match self.do_compound(args(&referred_path)) {
+                Err(e) if e.status() == Some(StatusError::Moved) => e,
+                res => return res.map_err(|e| e.with_path(path)),
+            };
+            if self.referrals.is_none() || referrals == MAX_REFERRALS {
+                return Err(error.with_path(path));
+            }
+            referrals += 1;
+            match self.follow_referral(&referred_path)? {
+                Some(new_path) => referred_path = new_path,
+                None => return Err(error.with_path(path)),
+            }
+        }
+    }
+
+    /// Finds the file system along the given path which isn't on this server anymore and moves
+    /// over to a server it is on, returning the path to use there. Returns `None` if there is no
+    /// such file system or none of its servers can be reached.
+    fn follow_referral(&mut self, path: &RemotePath) -> Result<Option<RemotePathBuf>> {
+        let components = look_up_args(path);
+        for n in 0..=components.len() {
+            let prefix = components[..n].to_vec();
+            match self.do_compound(ReturnSecond((PutRootFh, prefix.clone()), GetFh)) {
+                Ok(_) => continue,
+                Err(e) if e.status() == Some(StatusError::Moved) => {}
+                Err(e) => return Err(e),
+            }
+
+            // Only a few attributes, among them the locations, can be gotten for an absent file
+            // system
+            let attr_request = [
+                FileAttributeId::FsLocations,
+                FileAttributeId::FsLocationsInfo,
+            ]
+            .into_iter()
+            .filter(|a| self.supported_attrs.contains(*a))
+            .collect();
+            let attrs = self
+                .do_compound(ReturnSecond(
+                    (PutRootFh, prefix),
+                    GetAttrArgs { attr_request },
+                ))?
+                .object_attributes;
+            let rest: RemotePathBuf = components[n..].iter().map(|c| &c.object_name).collect();
+            for (server, root) in locations(&attrs) {
+                if self.move_to(&server)? {
+                    return Ok(Some(root.join(&rest)));
+                }
+            }
+            return Ok(None);
+        }
+        Ok(None)
+    }
+
+    /// Connects to the given server and carries on with it instead of the one we were talking
+    /// to. Anything we had open there is lost. Returns false if it couldn't be reached.
+    fn move_to(&mut self, server: &str) -> Result<bool> {
+        let protect_state = lock(&self.connection).raw_client.protect_state;
+        for addr in server_addrs(server) {
+            let connect = self.referrals.as_mut().unwrap();
+            let Ok(transport) = connect(addr) else {
+                continue;
+            };
+            let Ok((mut connection, client_id, session)) = Connection::establish(
+                transport,
+                &self.client_owner,
+                self.credential(),
+                self.minor_versions.clone(),
+                protect_state,
+            ) else {
+                continue;
+            };
+            connection.raw_client.tracer.metrics = self.metrics();
+
+            // Keep using the same connection so the lease renewal thread follows along
+            let mut old = lock(&self.connection);
+            connection
+                .raw_client
+                .rpc_client
+                .copy_reply_timeout(&old.raw_client.rpc_client)?;
+            let max_message_size = old.raw_client.rpc_client.max_message_size();
+            connection
+                .raw_client
+                .rpc_client
+                .set_max_message_size(max_message_size);
+            let limits = old.raw_client.rpc_client.limits();
+            connection.raw_client.rpc_client.set_limits(limits);
+            *old = connection;
+            drop(old);
+            self.client_id = client_id;
+            self.session = session;
+            self.read_root_attrs()?;
+            // The handles we remember are the old server's
+            self.clear_attr_cache();
+            if let Some(cache) = &mut self.name_cache {
+                cache.clear();
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+#[test]
+fn parse_server_addrs() {
+    assert_eq!(
+        server_addrs("10.0.0.1"),
+        vec![SocketAddr::from(([10, 0, 0, 1], NFS_PORT))]
+    );
+    assert_eq!(
+        server_addrs("10.0.0.1:2050"),
+        vec![SocketAddr::from(([10, 0, 0, 1], 2050))]
+    );
+    assert_eq!(
+        server_addrs("[::1]"),
+        vec![SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], NFS_PORT))]
+    );
+}
+
+#[test]
+fn locations_prefer_info() {
+    use nfs4::{
+        Component, FileAttribute, FsLocation, FsLocationsInfoFlags, FsLocationsItem,
+        FsLocationsServer,
+    };
+
+    let path = |components: &[&str]| {
+        PathName(
+            components
+                .iter()
+                .map(|c| Component(c.to_string()))
+                .collect(),
+        )
+    };
+    let fs_locations = FileAttribute::FsLocations(FsLocations {
+        fs_root: path(&["shared"]),
+        locations: vec![FsLocation {
+            server: vec!["a".into(), "b".into()],
+            root_path: path(&["export", "shared"]),
+        }],
+    });
+    let attrs: FileAttributes = [fs_locations.clone()].into_iter().collect();
+    assert_eq!(
+        locations(&attrs),
+        vec![
+            ("a".into(), "/export/shared".into()),
+            ("b".into(), "/export/shared".into())
+        ]
+    );
+
+    let info = FileAttribute::FsLocationsInfo(FsLocationsInfo {
+        flags: FsLocationsInfoFlags::empty(),
+        valid_for: 0,
+        fs_root: path(&["shared"]),
+        items: vec![FsLocationsItem {
+            entries: vec![FsLocationsServer {
+                currency: 0,
+                info: vec![],
+                server: "c".into(),
+            }],
+            root_path: path(&[]),
+        }],
+    });
+    let attrs: FileAttributes = [fs_locations, info].into_iter().collect();
+    assert_eq!(locations(&attrs), vec![("c".into(), "/".into())]);
+}
@@ -0,0 +1,154 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Cleaning up state the server revoked, see RFC 8881 section 8.4.2.1. After a network partition
+//! or an administrator's doing, some of our opens and delegations may be no good anymore. SEQUENCE
+//! tells us so with its status flags, and requests using them fail. We then ask the server with
+//! TEST_STATEID which of the state IDs we hold it still takes, let go of the rest with
+//! FREE_STATEID and forget about them, rather than keep on using them and failing with
+//! BAD_STATEID.
+
+use super::{lock, Client, Connection, Result};
+use nfs4::{
+    CompoundRes, FreeStateidArgs, ResOp, SequenceStatusFlags, StateId, StatusError, StatusResult,
+    TestStateIdArgs,
+};
+use sun_rpc_client::Transport;
+
+/// Whether the reply tells us some of our state may have been revoked.
+pub(crate) fn reports_revoked(reply: &CompoundRes) -> bool {
+    let revoked = SequenceStatusFlags::EXPIRED_ALL_STATE_REVOKED
+        | SequenceStatusFlags::EXPIRED_SOME_STATE_REVOKED
+        | SequenceStatusFlags::ADMIN_STATE_REVOKED
+        | SequenceStatusFlags::RECALLABLE_STATE_REVOKED;
+    let flagged = match reply.res_array.first() {
+        Some(ResOp::Sequence(StatusResult::Ok(res))) => res.status_flags.intersects(revoked),
+        _ => false,
+    };
+    flagged
+        || matches!(
+            reply.status,
+            StatusResult::Err(
+                StatusError::BadStateId
+                    | StatusError::Expired
+                    | StatusError::AdminRevoked
+                    | StatusError::DelegRevoked
+            )
+        )
+}
+
+impl<TransportT: Transport> Connection<TransportT> {
+    /// Whether any connection was told some of our state may have been revoked since we last
+    /// asked.
+    fn take_revoked(&mut self) -> bool {
+        let mut revoked = false;
+        for index in 0..self.num_connections() {
+            revoked |= std::mem::take(&mut self.connection_at(index).revoked);
+        }
+        revoked
+    }
+
+    fn held_state_ids(&self) -> Vec<StateId> {
+        let opens = self.opens.values().map(|o| o.state_id);
+        let delegations = self.delegations.values().map(|d| d.state_id);
+        let dir_delegations = self.watches.values().filter_map(|w| w.delegation());
+        opens.chain(delegations).chain(dir_delegations).collect()
+    }
+
+    fn forget_state_id(&mut self, state_id: &StateId) {
+        self.opens.retain(|_, o| o.state_id.other != state_id.other);
+        self.delegations
+            .retain(|_, d| d.state_id.other != state_id.other);
+        for watch in self.watches.values_mut() {
+            if watch
+                .delegation()
+                .is_some_and(|d| d.other == state_id.other)
+            {
+                watch.lost_delegation();
+            }
+        }
+    }
+
+    /// Frees the state IDs we hold which the server no longer takes, and forgets about them.
+    /// Returns how many there were.
+    pub(crate) fn free_revoked_state(&mut self) -> Result<usize> {
+        let state_ids = self.held_state_ids();
+        if state_ids.is_empty() {
+            return Ok(0);
+        }
+        let res = self.do_compound(TestStateIdArgs {
+            state_ids: state_ids.clone(),
+        })?;
+
+        let mut freed = 0;
+        for (state_id, status) in state_ids.iter().zip(res.status_codes) {
+            match status {
+                StatusResult::Ok(()) | StatusResult::Err(StatusError::OldStateId) => continue,
+                // The server doesn't know it at all, so there is nothing to free
+                StatusResult::Err(StatusError::BadStateId) => {}
+                StatusResult::Err(_) => {
+                    self.do_compound(FreeStateidArgs {
+                        state_id: *state_id,
+                    })?;
+                }
+            }
+            self.forget_state_id(state_id);
+            freed += 1;
+        }
+        // What the replies to these told us is about what we just freed
+        self.take_revoked();
+        Ok(freed)
+    }
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Asks the server whether it still takes each of the given state IDs, which it could have
+    /// revoked.
+    pub fn test_state_ids(&mut self, state_ids: Vec<StateId>) -> Result<Vec<StatusResult<()>>> {
+        Ok(self
+            .do_compound(TestStateIdArgs { state_ids })?
+            .status_codes)
+    }
+
+    /// Lets go of a state ID the server revoked, so it can forget about it.
+    pub fn free_state_id(&mut self, state_id: StateId) -> Result<()> {
+        self.do_compound(FreeStateidArgs { state_id })
+    }
+
+    /// If the server told us some of our state may have been revoked, frees what it no longer
+    /// takes. This is best effort, whatever is left over fails again and is tried again after.
+    pub(crate) fn free_revoked_state(&mut self) {
+        let mut connection = lock(&self.connection);
+        if connection.take_revoked() {
+            let _ = connection.free_revoked_state();
+        }
+    }
+}
+
+#[test]
+fn revoked_opens_freed() {
+    use super::OpenOptions;
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = super::in_memory_client(files);
+    let root = client.look_up("/").unwrap();
+    let file = client
+        .open(root.clone(), "a_file", &OpenOptions::new())
+        .unwrap();
+    server.revoke_opens();
+
+    // The next request hears of it, and the open is freed and forgotten
+    client.look_up("/a_file").unwrap();
+    assert!(lock(&client.connection).opens.is_empty());
+    assert!(!lock(&client.connection).take_revoked());
+
+    // Closing it has nothing left to do, and the file can be opened again
+    client.close(file).unwrap();
+    let file = client.open(root, "a_file", &OpenOptions::new()).unwrap();
+    assert_eq!(
+        &client.read(file.handle.clone(), 0, 5).unwrap().data[..],
+        b"hello"
+    );
+    client.close(file).unwrap();
+}
@@ -0,0 +1,115 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Sharing one client, and with it one session and connection, between threads.
+
+use super::{Client, RateLimited, RemotePath, Result};
+use nfs4::{FileHandle, GetAttrRes};
+use std::io::{self, Read as _, Write as _};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use sun_rpc_client::Transport;
+
+/// A `Client` which can be cloned and used from many threads at once, every clone using the same
+/// session and connection. Each call has the client to itself while it runs, so the calls of
+/// different threads take turns rather than going to the server together. `read_all` and
+/// `write_all` take turns a chunk at a time, so that a thread copying a large file doesn't hold up
+/// the others until it is done.
+pub struct SharedClient<TransportT> {
+    client: Arc<Mutex<Client<TransportT>>>,
+}
+
+impl<TransportT> Clone for SharedClient<TransportT> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+        }
+    }
+}
+
+impl<TransportT> From<Client<TransportT>> for SharedClient<TransportT> {
+    fn from(client: Client<TransportT>) -> Self {
+        Self::new(client)
+    }
+}
+
+impl<TransportT> SharedClient<TransportT> {
+    pub fn new(client: Client<TransportT>) -> Self {
+        Self {
+            client: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    /// The client, for the calls `SharedClient` doesn't have, or for a series of calls which no
+    /// other thread's may come between, like creating, writing and closing a file.
+    pub fn lock(&self) -> MutexGuard<'_, Client<TransportT>> {
+        self.client.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Gives the client back, unless there are other clones of it left.
+    pub fn into_inner(self) -> std::result::Result<Client<TransportT>, Self> {
+        match Arc::try_unwrap(self.client) {
+            Ok(client) => Ok(client.into_inner().unwrap_or_else(PoisonError::into_inner)),
+            Err(client) => Err(Self { client }),
+        }
+    }
+}
+
+impl<TransportT: Transport> SharedClient<TransportT> {
+    pub fn look_up(&self, path: impl AsRef<RemotePath>) -> Result<FileHandle> {
+        self.lock().look_up(path)
+    }
+
+    pub fn get_attr(&self, handle: FileHandle) -> Result<GetAttrRes> {
+        self.lock().get_attr(handle)
+    }
+
+    /// Like `Client::read_all`, except that other threads get to use the client between chunks.
+    pub fn read_all(&self, handle: FileHandle, sink: impl io::Write) -> Result<()> {
+        let mut sink = RateLimited::new(sink, self.lock().bandwidth_limit().cloned());
+        let mut offset = 0;
+        loop {
+            let res = {
+                let mut client = self.lock();
+                let len = client.read_chunk_size() as usize;
+                client.read_at(handle.clone(), offset, len)?
+            };
+            sink.write_all(&res.data)?;
+            offset += res.data.len() as u64;
+            if res.eof || res.data.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Like `Client::write_all`, except that other threads get to use the client between chunks.
+    pub fn write_all(&self, handle: FileHandle, source: impl io::Read) -> Result<()> {
+        let mut source = RateLimited::new(source, self.lock().bandwidth_limit().cloned());
+        let chunk_size = self.lock().write_chunk_size() as usize;
+        let mut chunk = vec![0; chunk_size];
+        let mut offset = 0;
+        loop {
+            let mut len = 0;
+            while len < chunk_size {
+                match source.read(&mut chunk[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if len == 0 {
+                return Ok(());
+            }
+            let mut written = 0;
+            while written < len {
+                let n = self
+                    .lock()
+                    .write_at(handle.clone(), offset, &chunk[written..len])?;
+                if n == 0 {
+                    return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+                }
+                written += n;
+                offset += n as u64;
+            }
+        }
+    }
+}
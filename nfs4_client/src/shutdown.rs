@@ -0,0 +1,110 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Shutting down gracefully. A client which just goes away leaves the server holding its opens,
+//! delegations and session until its lease runs out, keeping others from what it had open with a
+//! deny in the meantime. Instead we give it all back and destroy the session and client ID. We never
+//! take byte-range locks, so there are none to release.
+
+use super::{lock, Client, Connection, Result};
+use nfs4::{DestroyClientIdArgs, DestroySessionArgs, FileHandle};
+use std::sync::{Arc, Mutex, Weak};
+use sun_rpc_client::Transport;
+
+impl<TransportT: Transport> Connection<TransportT> {
+    /// Closes every file we have open, gives back every delegation, and destroys the session and
+    /// client ID. It carries on after an error, returning the first one.
+    pub(crate) fn shut_down(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for handle in self.opens.keys().cloned().collect::<Vec<_>>() {
+            // However many `OpenFile`s are still around for it
+            if let Some(open) = self.opens.get_mut(&handle) {
+                open.count = 1;
+            }
+            result = result.and(self.close(&FileHandle(handle)));
+        }
+        for handle in self.delegations.keys().cloned().collect::<Vec<_>>() {
+            result = result.and(self.return_delegation(&FileHandle(handle)));
+        }
+        for handle in self.watches.keys().cloned().collect::<Vec<_>>() {
+            result = result.and(self.return_dir_delegation(&FileHandle(handle)));
+        }
+
+        // Minor version 0 has neither, the client ID only goes away with its lease
+        if self.raw_client.v40.is_none() {
+            result = result.and(self.raw_client.do_compound(DestroySessionArgs {
+                session_id: self.session_id,
+            }));
+            result = result.and(self.raw_client.do_compound(DestroyClientIdArgs {
+                client_id: self.client_id,
+            }));
+        }
+        result
+    }
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Commits what was written unstably, closes every file, gives back every delegation, and
+    /// destroys the session and client ID, on the server and any pNFS data servers. The server
+    /// otherwise holds on to all of it until our lease runs out. `OpenFile`s and `DirWatcher`s
+    /// still around are of no use afterwards. It carries on after an error, returning the first
+    /// one.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.lease_renewal.take();
+        let mut result = Ok(());
+        for handle in self.unstable_writes.keys().cloned().collect::<Vec<_>>() {
+            result = result.and(self.commit(FileHandle(handle), 0, 0).map(|_| ()));
+        }
+        if let Some(pnfs) = &mut self.pnfs {
+            result = result.and(pnfs.shut_down());
+        }
+        result.and(lock(&self.connection).shut_down())
+    }
+
+    /// Something to shut the client down with from another thread, like one handling Ctrl-C,
+    /// while this one may be in the middle of a request.
+    pub fn shutdown_handle(&self) -> ShutdownHandle<TransportT> {
+        ShutdownHandle {
+            connection: Arc::downgrade(&self.connection),
+        }
+    }
+}
+
+/// Shuts down a `Client` from another thread, see `Client::shutdown_handle`.
+pub struct ShutdownHandle<TransportT> {
+    connection: Weak<Mutex<Connection<TransportT>>>,
+}
+
+impl<TransportT: Transport> ShutdownHandle<TransportT> {
+    /// Like `Client::shutdown`, once the request the client is in the middle of is done, but
+    /// without committing unstable writes or going to pNFS data servers, which hold no opens of
+    /// ours. The client can't be used for anything afterwards. Does nothing if it is already gone.
+    pub fn shutdown(&self) -> Result<()> {
+        match self.connection.upgrade() {
+            Some(connection) => lock(&connection).shut_down(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn shutdown_closes_files() {
+    use super::OpenOptions;
+    use nfs4::{ShareAccess, ShareDeny};
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let (server, mut client) = super::in_memory_client(files);
+    let root = client.look_up("/").unwrap();
+    let options = OpenOptions::new().deny(ShareDeny::WRITE);
+    let file = client.open(root.clone(), "a_file", &options).unwrap();
+    client.shutdown().unwrap();
+
+    // Nobody else could write it if the server still had it open
+    let mut other = Client::new(server.connect_in_process()).unwrap();
+    let options = OpenOptions::new().access(ShareAccess::WRITE);
+    other.open(root, "a_file", &options).unwrap();
+
+    // The client is gone, so dropping what it opened does nothing
+    drop(file);
+}
@@ -0,0 +1,101 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! SP4_MACH_CRED state protection, see RFC 8881 section 2.10.8.3. When it is agreed on with
+//! EXCHANGE_ID, the server only takes the operations it enforces from the machine credential, the
+//! one the client ID was made with, so nobody with another credential can tear down our session or
+//! close our files. Those keep being sent with it even after we switch to another flavor an export
+//! prefers.
+
+use nfs4::{ArgOp, EnumSet, OperationId, StateProtect, StateProtectOps, ToId as _};
+use sun_rpc_client::OpaqueAuth;
+
+/// What we ask the server to protect, the operations which change or do away with our state. It
+/// may still allow the others with the machine credential when they are about state made with
+/// another.
+pub(crate) fn requested() -> StateProtect {
+    StateProtect::MachCred(StateProtectOps {
+        must_enforce: [
+            OperationId::BindConnToSession,
+            OperationId::ExchangeId,
+            OperationId::CreateSession,
+            OperationId::DestroySession,
+            OperationId::DestroyClientId,
+            OperationId::Close,
+            OperationId::OpenDowngrade,
+            OperationId::LockU,
+            OperationId::DelegReturn,
+            OperationId::LayoutReturn,
+            OperationId::FreeStateid,
+        ]
+        .into_iter()
+        .collect(),
+        must_allow: [
+            OperationId::Commit,
+            OperationId::Write,
+            OperationId::SecInfo,
+            OperationId::SecInfoNoName,
+            OperationId::TestStateId,
+        ]
+        .into_iter()
+        .collect(),
+    })
+}
+
+/// The machine credential and the operations the server only takes from it.
+#[derive(Clone)]
+pub(crate) struct MachineCredential {
+    credential: OpaqueAuth,
+    enforced: EnumSet<OperationId>,
+}
+
+impl MachineCredential {
+    /// From what the server answered an EXCHANGE_ID sent with the given credential, if it agreed
+    /// to protect anything.
+    pub(crate) fn new(answer: &StateProtect, credential: OpaqueAuth) -> Option<Self> {
+        match answer {
+            StateProtect::MachCred(ops) => Some(Self {
+                credential,
+                enforced: ops.must_enforce.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn credential(&self) -> &OpaqueAuth {
+        &self.credential
+    }
+
+    pub(crate) fn enforced(&self) -> &EnumSet<OperationId> {
+        &self.enforced
+    }
+
+    /// The credential a request with the given operations has to be sent with, if it matters.
+    pub(crate) fn for_request(&self, arg_array: &[ArgOp]) -> Option<&OpaqueAuth> {
+        arg_array
+            .iter()
+            .any(|op| self.enforced.contains(op.to_id()))
+            .then_some(&self.credential)
+    }
+}
+
+#[test]
+fn machine_credential_for_request() {
+    use nfs4::{CloseArgs, SequenceId, StateId};
+
+    let credential = OpaqueAuth::none();
+    let machine = MachineCredential::new(&requested(), credential.clone()).unwrap();
+
+    let close = ArgOp::Close(CloseArgs {
+        sequence_id: SequenceId(0),
+        open_stateid: StateId::anonymous(),
+    });
+    assert_eq!(
+        machine.for_request(&[ArgOp::PutRootFh, close]),
+        Some(&credential)
+    );
+    assert_eq!(machine.for_request(&[ArgOp::PutRootFh, ArgOp::GetFh]), None);
+    // What we only allow isn't enforced
+    assert!(!machine.enforced().contains(OperationId::Write));
+
+    assert!(MachineCredential::new(&StateProtect::None, credential).is_none());
+}
@@ -0,0 +1,119 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Counts of what the client did since it was made, kept by the client itself so that they are
+//! there without implementing `ClientMetrics`.
+
+use super::ClientMetrics;
+use nfs4::{OperationId, StatusError};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// What the client did since it was made, as returned by `Client::stats`. Like `ClientMetrics`,
+/// only NFSv4 requests are counted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    /// How many COMPOUNDs got a reply.
+    pub compounds: u64,
+    /// How many times the server carried out each operation, whether it succeeded or not.
+    pub ops: BTreeMap<OperationId, u64>,
+    /// How many COMPOUNDs failed with each status.
+    pub errors: BTreeMap<StatusError, u64>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// How many requests were sent again, see `ClientMetrics::retry`.
+    pub retransmits: u64,
+    /// How long the replies to all the COMPOUNDs took, added up.
+    pub total_latency: Duration,
+}
+
+impl ClientStats {
+    pub fn average_latency(&self) -> Option<Duration> {
+        let compounds = u32::try_from(self.compounds).ok().filter(|c| *c > 0)?;
+        Some(self.total_latency / compounds)
+    }
+}
+
+/// The metrics every connection of a client is given. It keeps the counts for `Client::stats` and
+/// passes everything on to the metrics set with `Client::set_metrics`.
+pub(crate) struct Stats {
+    counts: Mutex<ClientStats>,
+    metrics: Option<Arc<dyn ClientMetrics>>,
+}
+
+impl Stats {
+    pub(crate) fn new(metrics: Option<Arc<dyn ClientMetrics>>) -> Self {
+        Self {
+            counts: Mutex::new(ClientStats::default()),
+            metrics,
+        }
+    }
+
+    /// Carries on counting from these stats, passing everything on to other metrics.
+    pub(crate) fn with_metrics(&self, metrics: Arc<dyn ClientMetrics>) -> Self {
+        Self {
+            counts: Mutex::new(self.get()),
+            metrics: Some(metrics),
+        }
+    }
+
+    pub(crate) fn get(&self) -> ClientStats {
+        self.counts().clone()
+    }
+
+    fn counts(&self) -> MutexGuard<'_, ClientStats> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ClientMetrics for Stats {
+    fn compound(&self, latency: Duration, status: Option<StatusError>) {
+        let mut counts = self.counts();
+        counts.compounds += 1;
+        counts.total_latency += latency;
+        if let Some(status) = status {
+            *counts.errors.entry(status).or_default() += 1;
+        }
+        drop(counts);
+        if let Some(metrics) = &self.metrics {
+            metrics.compound(latency, status);
+        }
+    }
+
+    fn operation(&self, op: OperationId, status: Option<StatusError>, latency: Duration) {
+        *self.counts().ops.entry(op).or_default() += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.operation(op, status, latency);
+        }
+    }
+
+    fn bytes_read(&self, bytes: u64) {
+        self.counts().bytes_read += bytes;
+        if let Some(metrics) = &self.metrics {
+            metrics.bytes_read(bytes);
+        }
+    }
+
+    fn bytes_written(&self, bytes: u64) {
+        self.counts().bytes_written += bytes;
+        if let Some(metrics) = &self.metrics {
+            metrics.bytes_written(bytes);
+        }
+    }
+
+    fn retry(&self, error: StatusError) {
+        self.counts().retransmits += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.retry(error);
+        }
+    }
+}
+
+#[test]
+fn average_latency() {
+    let mut stats = ClientStats::default();
+    assert_eq!(stats.average_latency(), None);
+    stats.compounds = 4;
+    stats.total_latency = Duration::from_millis(10);
+    assert_eq!(stats.average_latency(), Some(Duration::from_micros(2500)));
+}
@@ -0,0 +1,163 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Tracing of the COMPOUNDs going over a connection, for debugging what we and the server say to
+//! each other without capturing packets. Nothing is logged unless a `tracing` subscriber asks for
+//! it. Each reply gives a DEBUG event with the operations sent, how each of them went, the sizes of
+//! the request and reply and how long the reply took. At TRACE level the whole request and reply
+//! are logged too. The same goes to the `ClientMetrics` the client was given, if any.
+
+use super::ClientMetrics;
+use nfs4::{CompoundArgs, CompoundRes, OperationId, ResOp, StatusError, StatusResult, ToId as _};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Instant;
+use sun_rpc_client::Xid;
+use tracing::Level;
+
+struct Sent {
+    ops: Vec<OperationId>,
+    // Only worked out when tracing
+    size: Option<usize>,
+    at: Instant,
+}
+
+/// The requests sent over one connection which are still waiting for a reply.
+#[derive(Default)]
+pub(crate) struct Tracer {
+    in_flight: HashMap<u32, Sent>,
+    pub(crate) metrics: Option<Arc<dyn ClientMetrics>>,
+}
+
+fn size(message: &impl Serialize) -> usize {
+    xdr_extras::to_bytes(message).map_or(0, |b| b.len())
+}
+
+/// How the operation at the given index went, `None` if it wasn't carried out. The server stops
+/// at the first one which fails, it is the last one it has a result for and gives the COMPOUND its
+/// status.
+fn op_status(index: usize, reply: &CompoundRes) -> Option<Option<StatusError>> {
+    let done = reply.res_array.len();
+    match reply.status {
+        _ if index >= done => None,
+        StatusResult::Err(e) if index + 1 == done => Some(Some(e)),
+        _ => Some(None),
+    }
+}
+
+fn op_statuses(ops: &[OperationId], reply: &CompoundRes) -> String {
+    let mut statuses = String::new();
+    for (i, op) in ops.iter().enumerate() {
+        if i > 0 {
+            statuses.push_str(", ");
+        }
+        match op_status(i, reply) {
+            None => write!(statuses, "{op:?} skipped"),
+            Some(Some(e)) => write!(statuses, "{op:?} {e:?}"),
+            Some(None) => write!(statuses, "{op:?} ok"),
+        }
+        .unwrap();
+    }
+    statuses
+}
+
+fn count(metrics: &dyn ClientMetrics, sent: &Sent, reply: &CompoundRes) {
+    let latency = sent.at.elapsed();
+    let status = match reply.status {
+        StatusResult::Ok(()) => None,
+        StatusResult::Err(e) => Some(e),
+    };
+    metrics.compound(latency, status);
+    for (i, &op) in sent.ops.iter().enumerate() {
+        if let Some(status) = op_status(i, reply) {
+            metrics.operation(op, status, latency);
+        }
+    }
+    for res in &reply.res_array {
+        match res {
+            ResOp::Read(StatusResult::Ok(res)) => metrics.bytes_read(res.data.len() as u64),
+            ResOp::Write(StatusResult::Ok(res)) => metrics.bytes_written(res.count.into()),
+            _ => {}
+        }
+    }
+}
+
+impl Tracer {
+    pub(crate) fn sent(&mut self, xid: &Xid, args: &CompoundArgs) {
+        let tracing = tracing::enabled!(Level::DEBUG);
+        if !tracing && self.metrics.is_none() {
+            return;
+        }
+        tracing::trace!(xid = xid.0, "request {args:#?}");
+        self.in_flight.insert(
+            xid.0,
+            Sent {
+                ops: args.arg_array.iter().map(|op| op.to_id()).collect(),
+                size: tracing.then(|| size(args)),
+                at: Instant::now(),
+            },
+        );
+    }
+
+    pub(crate) fn received(&mut self, xid: &Xid, reply: &CompoundRes) {
+        let Some(sent) = self.in_flight.remove(&xid.0) else {
+            return;
+        };
+        if let Some(metrics) = &self.metrics {
+            count(&**metrics, &sent, reply);
+        }
+        let Some(request_size) = sent.size else {
+            return;
+        };
+        let latency = sent.at.elapsed();
+        tracing::trace!(xid = xid.0, "reply {reply:#?}");
+        tracing::debug!(
+            xid = xid.0,
+            status = ?reply.status,
+            request_size,
+            reply_size = size(reply),
+            ?latency,
+            "COMPOUND {}",
+            op_statuses(&sent.ops, reply)
+        );
+    }
+}
+
+#[test]
+fn statuses_of_ops() {
+    let ops = [
+        OperationId::PutRootFh,
+        OperationId::LookUp,
+        OperationId::GetFh,
+    ];
+    let reply = |status, res_array| CompoundRes {
+        status,
+        tag: String::new(),
+        res_array,
+    };
+
+    let ok = reply(
+        StatusResult::Ok(()),
+        vec![
+            ResOp::PutRootFh(StatusResult::Ok(())),
+            ResOp::LookUp(StatusResult::Ok(())),
+            ResOp::GetFh(StatusResult::Ok(nfs4::GetFhRes {
+                object: nfs4::FileHandle(vec![1].into()),
+            })),
+        ],
+    );
+    assert_eq!(op_statuses(&ops, &ok), "PutRootFh ok, LookUp ok, GetFh ok");
+
+    let failed = reply(
+        StatusResult::Err(StatusError::NoEnt),
+        vec![
+            ResOp::PutRootFh(StatusResult::Ok(())),
+            ResOp::LookUp(StatusResult::Err(StatusError::NoEnt)),
+        ],
+    );
+    assert_eq!(
+        op_statuses(&ops, &failed),
+        "PutRootFh ok, LookUp NoEnt, GetFh skipped"
+    );
+}
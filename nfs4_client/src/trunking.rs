@@ -0,0 +1,132 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Session trunking, see RFC 5661 section 2.10.5. More connections, possibly to other addresses of
+//! the same server, are bound to our session with BIND_CONN_TO_SESSION so that the requests of
+//! `read_all` and `write_all` can be spread over all of them instead of being limited by what a
+//! single TCP stream manages. Everything else, including the backchannel, stays on the connection
+//! the client was made with.
+
+use super::{lock, Client, ClientWithoutSession, Connection, Error, Result, NFS};
+use nfs4::{BindConnToSessionArgs, ChannelDirectionFromServer, SessionId, StatusError};
+use std::io;
+use sun_rpc_client::{RpcClient, Transport};
+
+/// How requests are spread over the connections of a trunked session.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Scheduling {
+    /// Each connection in turn
+    #[default]
+    RoundRobin,
+    /// The connection with the fewest requests in flight
+    LeastLoaded,
+}
+
+/// Picks the connection to send the next request on, given how many requests each one has in
+/// flight.
+fn pick(scheduling: Scheduling, next: &mut usize, loads: &[usize]) -> usize {
+    match scheduling {
+        Scheduling::RoundRobin => {
+            let index = *next % loads.len();
+            *next = index + 1;
+            index
+        }
+        Scheduling::LeastLoaded => (0..loads.len()).min_by_key(|i| loads[*i]).unwrap(),
+    }
+}
+
+impl<TransportT: Transport> Connection<TransportT> {
+    /// How many connections the session is trunked over, including the main one.
+    pub(crate) fn num_connections(&self) -> usize {
+        self.trunks.len() + 1
+    }
+
+    /// The connection with the given index, the main one being 0.
+    pub(crate) fn connection_at(&mut self, index: usize) -> &mut ClientWithoutSession<TransportT> {
+        match index {
+            0 => &mut self.raw_client,
+            _ => &mut self.trunks[index - 1],
+        }
+    }
+
+    pub(crate) fn pick_connection(&mut self, loads: &[usize]) -> usize {
+        pick(self.scheduling, &mut self.next_connection, loads)
+    }
+
+    /// Binds the other connections to the session again after it was re-established. Ones the
+    /// server doesn't take anymore are dropped.
+    pub(crate) fn rebind_trunks(&mut self) {
+        let session_id = self.session_id;
+        self.trunks
+            .retain_mut(|trunk| bind(trunk, session_id).is_ok());
+    }
+}
+
+fn bind<TransportT: Transport>(
+    raw_client: &mut ClientWithoutSession<TransportT>,
+    session_id: SessionId,
+) -> Result<()> {
+    // BIND_CONN_TO_SESSION goes on its own, without a SEQUENCE
+    raw_client.do_compound(BindConnToSessionArgs {
+        session_id,
+        direction: ChannelDirectionFromServer::Fore,
+        use_connection_in_rdma_mode: false,
+    })?;
+    Ok(())
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// Trunks the session over another connection to the server, which can be to any of its
+    /// addresses. Fails if the other end turns out to be a different server.
+    pub fn add_connection(&mut self, transport: TransportT) -> Result<()> {
+        let mut connection = lock(&self.connection);
+        let mut rpc_client = RpcClient::new(transport, NFS);
+        rpc_client.set_credential(connection.raw_client.rpc_client.credential().clone());
+        rpc_client.set_gss(connection.raw_client.rpc_client.gss().cloned());
+        rpc_client.copy_reply_timeout(&connection.raw_client.rpc_client)?;
+        rpc_client.set_max_message_size(connection.raw_client.rpc_client.max_message_size());
+        rpc_client.set_limits(connection.raw_client.rpc_client.limits());
+        let mut raw_client = ClientWithoutSession::new(rpc_client);
+        raw_client.minor_version = connection.raw_client.minor_version;
+        raw_client.protect_state = connection.raw_client.protect_state;
+        raw_client.machine_credential = connection.raw_client.machine_credential.clone();
+        raw_client.tracer.metrics = self.metrics();
+
+        // The server tells us who it is, and that we are who it thinks, with EXCHANGE_ID
+        let eid_res = raw_client.do_compound(raw_client.exchange_id_args(&self.client_owner))?;
+        if eid_res.server_owner != connection.server_owner {
+            return Err(Error::Io(io::Error::other(
+                "can't trunk the session with a connection to a different server",
+            )));
+        }
+        if eid_res.client_id != self.client_id {
+            return Err(StatusError::StaleClientId.into());
+        }
+
+        bind(&mut raw_client, connection.session_id)?;
+        connection.trunks.push(raw_client);
+        Ok(())
+    }
+
+    /// How many connections the session is trunked over, including the one the client was made
+    /// with.
+    pub fn num_connections(&self) -> usize {
+        lock(&self.connection).num_connections()
+    }
+
+    /// Sets how the requests of `read_all` and `write_all` are spread over the connections.
+    pub fn set_scheduling(&mut self, scheduling: Scheduling) {
+        lock(&self.connection).scheduling = scheduling;
+    }
+}
+
+#[test]
+fn pick_connection() {
+    let mut next = 0;
+    let picked: Vec<_> = (0..4)
+        .map(|_| pick(Scheduling::RoundRobin, &mut next, &[5, 0, 0]))
+        .collect();
+    assert_eq!(picked, [0, 1, 2, 0]);
+
+    assert_eq!(pick(Scheduling::LeastLoaded, &mut next, &[2, 1, 1]), 1);
+    assert_eq!(pick(Scheduling::LeastLoaded, &mut next, &[0, 1, 1]), 0);
+}
@@ -0,0 +1,204 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! NFS URLs, see RFC 7532 section 4, like `nfs://server:2049/export/file?minorversion=1`. Besides
+//! the host, port and path, the query can ask for a minor version of NFSv4 with `minorversion`
+//! and a security flavor with `sec`, which is `sys` or `none`.
+
+use super::{
+    credential_for, Client, ClientBuilder, RemotePathBuf, Result, MAX_MINOR_VERSION, NFS_PORT,
+};
+use nfs4::{FileHandle, SecurityInfo};
+use std::io;
+use std::net::TcpStream;
+use std::str::FromStr;
+
+/// A parsed `nfs://` URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NfsUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: RemotePathBuf,
+    pub minor_version: Option<u32>,
+    pub security: Option<SecurityInfo>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn percent_decode(s: &str) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()];
+        let digits = hex.map(|d| d.and_then(|d| (d as char).to_digit(16)));
+        let [Some(high), Some(low)] = digits else {
+            return Err(invalid(format!("invalid percent-encoding in `{s}`")));
+        };
+        decoded.push((high * 16 + low) as u8);
+    }
+    Ok(decoded)
+}
+
+impl NfsUrl {
+    /// The root of the given server.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            path: "/".into(),
+            minor_version: None,
+            security: None,
+        }
+    }
+
+    /// A builder for a client which speaks the minor version and uses the security flavor the
+    /// URL asks for, if it does.
+    pub fn client_builder(&self) -> ClientBuilder {
+        let mut builder = ClientBuilder::new();
+        if let Some(minor_version) = self.minor_version {
+            builder = builder.minor_version(minor_version);
+        }
+        if let Some(credential) = self.security.as_ref().and_then(credential_for) {
+            builder = builder.credential(credential);
+        }
+        builder
+    }
+
+    pub fn connect(&self) -> Result<Client<TcpStream>> {
+        self.client_builder()
+            .connect_tcp((self.host.as_str(), self.port))
+    }
+
+    fn set_query(&mut self, query: &str) -> io::Result<()> {
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("missing `=` in `{pair}`")))?;
+            match key {
+                "minorversion" => {
+                    let minor_version = value
+                        .parse()
+                        .ok()
+                        .filter(|v| *v <= MAX_MINOR_VERSION)
+                        .ok_or_else(|| invalid(format!("unsupported minor version `{value}`")))?;
+                    self.minor_version = Some(minor_version);
+                }
+                "sec" => {
+                    self.security = Some(match value {
+                        "sys" => SecurityInfo::Sys,
+                        "none" => SecurityInfo::None,
+                        _ => return Err(invalid(format!("unsupported security flavor `{value}`"))),
+                    });
+                }
+                _ => return Err(invalid(format!("unknown URL parameter `{key}`"))),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for NfsUrl {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let rest = s
+            .strip_prefix("nfs://")
+            .ok_or_else(|| invalid(format!("`{s}` isn't an nfs:// URL")))?;
+        let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+        // IPv6 addresses are in brackets so that their colons aren't taken for the port's
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("missing `]` in URL"))?;
+                (host, rest.strip_prefix(':'))
+            }
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host in URL"));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| invalid(format!("invalid port `{port}`")))?,
+            None => NFS_PORT,
+        };
+
+        let mut url = Self::new(host, port);
+        if !path.is_empty() {
+            let path = String::from_utf8(percent_decode(path)?)
+                .map_err(|_| invalid(format!("`{path}` isn't UTF-8 once decoded")))?;
+            url.path = path.into();
+        }
+        url.set_query(query)?;
+        Ok(url)
+    }
+}
+
+impl Client<TcpStream> {
+    /// Connects to the server the given `nfs://` URL names, the way it asks to, and looks up the
+    /// path in it.
+    pub fn from_url(url: &str) -> Result<(Self, FileHandle)> {
+        let url: NfsUrl = url.parse()?;
+        let mut client = url.connect()?;
+        let handle = client.look_up(&url.path)?;
+        Ok((client, handle))
+    }
+}
+
+#[test]
+fn parse_urls() {
+    let url: NfsUrl = "nfs://server/export/a%20file".parse().unwrap();
+    assert_eq!(url.host, "server");
+    assert_eq!(url.port, NFS_PORT);
+    assert_eq!(url.path, RemotePathBuf::from("/export/a file"));
+    assert_eq!(url.minor_version, None);
+
+    let url: NfsUrl = "nfs://[::1]:1234?minorversion=1&sec=none".parse().unwrap();
+    assert_eq!(url.host, "::1");
+    assert_eq!(url.port, 1234);
+    assert_eq!(url.path, RemotePathBuf::from("/"));
+    assert_eq!(url.minor_version, Some(1));
+    assert_eq!(url.security, Some(SecurityInfo::None));
+
+    for bad in [
+        "http://server/",
+        "nfs:///export",
+        "nfs://server:port/",
+        "nfs://server/?minorversion=3",
+        "nfs://server/?sec=krb5",
+        "nfs://server/?vers=4",
+        "nfs://server/%2",
+        "nfs://server/%ff",
+    ] {
+        assert!(bad.parse::<NfsUrl>().is_err(), "{bad}");
+    }
+}
+
+#[test]
+fn connect_with_url() {
+    use nfs4_server::{memory::MemoryFs, Server};
+
+    let mut files = MemoryFs::new();
+    files.write_file("a_file", "hello").unwrap();
+    let server = Server::with_file_system(files);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || server.serve(listener));
+
+    let url = format!("nfs://127.0.0.1:{port}/a_file?minorversion=1");
+    let (mut client, handle) = Client::from_url(&url).unwrap();
+    assert_eq!(client.minor_version(), 1);
+    assert_eq!(&client.read(handle, 0, 5).unwrap().data[..], b"hello");
+}
@@ -0,0 +1,323 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Speaking NFSv4.0 (RFC 7530) with servers which don't do sessions. Rather than everything
+//! knowing about both, requests are made the 4.1 way and translated on their way out. The SEQUENCE
+//! they start with is left out, or sent as RENEW when there is nothing else, RECLAIM_COMPLETE is
+//! left out, and open owners get the sequence IDs 4.0 wants of them. Replies are translated back
+//! with results made up for what was left out, after confirming the OPENs which need it. We give
+//! the server no address to call us back at, so it doesn't give us delegations.
+
+use super::{
+    back_channel_attrs, fore_channel_attrs, ClientWithoutSession, Error, Result, ReturnSecond,
+    NFS_CB,
+};
+use nfs4::{
+    ArgOp, CallbackClient, ClientId, ClientOwner, CloseArgs, CloseRes, CompoundRes,
+    CreateSessionFlags, CreateSessionRes, FileHandle, GetFhRes, NetAddr, OpenConfirmArgs,
+    OpenDowngradeArgs, OpenDowngradeRes, OpenResult, OperationId, PutFhArgs, RenewArgs, ResOp,
+    SequenceId, SequenceRes, SequenceStatusFlags, ServerOwner, SessionId, SetClientIdArgs,
+    SetClientIdConfirmArgs, SlotId, StateId, StatusError, StatusResult, ToId as _,
+};
+use std::collections::{BTreeMap, VecDeque};
+use sun_rpc_client::{Transport, Xid};
+
+/// Gets a client ID with SETCLIENTID, returning it along with stand-ins for the server owner and
+/// session minor version 0 doesn't have.
+pub(crate) fn establish_client_id<TransportT: Transport>(
+    raw_client: &mut ClientWithoutSession<TransportT>,
+    client_owner: &ClientOwner,
+) -> Result<(ClientId, ServerOwner, CreateSessionRes)> {
+    let res = raw_client.do_compound(SetClientIdArgs {
+        client: client_owner.clone(),
+        callback: CallbackClient {
+            program: NFS_CB,
+            location: NetAddr {
+                netid: "tcp".into(),
+                addr: "0.0.0.0.0.0".into(),
+            },
+        },
+        callback_ident: 0,
+    })?;
+    raw_client.do_compound(SetClientIdConfirmArgs {
+        client_id: res.client_id,
+        confirm: res.confirm,
+    })?;
+    raw_client.v40 = Some(Translator::new(res.client_id));
+
+    let server_owner = ServerOwner {
+        minor_id: 0,
+        major_id: vec![],
+    };
+    let session = CreateSessionRes {
+        session_id: SessionId([0; 16]),
+        sequence_id: SequenceId(0),
+        flags: CreateSessionFlags::empty(),
+        fore_channel_attrs: fore_channel_attrs(),
+        back_channel_attrs: back_channel_attrs(),
+    };
+    Ok((res.client_id, server_owner, session))
+}
+
+/// Whether an open owner's sequence ID is used up by an operation which failed this way, see RFC
+/// 7530 section 9.1.7.
+fn uses_sequence_id(status: StatusError) -> bool {
+    !matches!(
+        status,
+        StatusError::StaleClientId
+            | StatusError::StaleStateId
+            | StatusError::BadStateId
+            | StatusError::BadSeqId
+            | StatusError::BadXdr
+            | StatusError::NoFileHandle
+            | StatusError::Moved
+    )
+}
+
+fn sequence_res() -> SequenceRes {
+    SequenceRes {
+        session_id: SessionId([0; 16]),
+        sequence_id: SequenceId(0),
+        slot_id: SlotId(0),
+        highest_slot_id: SlotId(0),
+        target_highest_slot_id: SlotId(0),
+        status_flags: SequenceStatusFlags::empty(),
+    }
+}
+
+// What became of an operation of the request we were given
+enum Sent {
+    AsIs,
+    // SEQUENCE or RECLAIM_COMPLETE, which aren't sent at all
+    LeftOut(OperationId),
+    // A SEQUENCE which was all there was
+    Renew,
+    // Followed by a GETFH we added, to confirm the open with
+    Open { owner: Vec<u8> },
+    // CLOSE or OPEN_DOWNGRADE, which use up a sequence ID of the open owner
+    OpenOwner { owner: Vec<u8> },
+}
+
+pub(crate) struct Translator {
+    client_id: ClientId,
+    // The sequence ID each open owner is to use next
+    owners: BTreeMap<Vec<u8>, SequenceId>,
+    // The open owner and newest sequence ID of our open state IDs, by the other part of them
+    opens: BTreeMap<[u8; 12], (Vec<u8>, u32)>,
+    // How the last request was translated, until it is sent
+    unsent: Vec<Sent>,
+    // How the requests in flight were translated, by XID
+    in_flight: BTreeMap<u32, Vec<Sent>>,
+}
+
+impl Translator {
+    fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            owners: BTreeMap::new(),
+            opens: BTreeMap::new(),
+            unsent: vec![],
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    // A sequence ID of 0 in a state ID means the current one in 4.1, but is just an old one in 4.0
+    fn current_state_id(&self, state_id: &mut StateId) {
+        if let (0, Some((_, sequence_id))) = (state_id.sequence_id, self.opens.get(&state_id.other))
+        {
+            state_id.sequence_id = *sequence_id;
+        }
+    }
+
+    fn owner_sequence_id(&mut self, owner: &[u8]) -> SequenceId {
+        *self.owners.entry(owner.to_owned()).or_insert(SequenceId(0))
+    }
+
+    fn used(&mut self, owner: &[u8], status: Option<StatusError>) {
+        if status.is_none_or(uses_sequence_id) {
+            if let Some(sequence_id) = self.owners.get_mut(owner) {
+                sequence_id.incr();
+            }
+        }
+    }
+
+    fn opened(&mut self, owner: Vec<u8>, state_id: StateId) {
+        let open = self.opens.entry(state_id.other).or_insert((owner, 0));
+        open.1 = open.1.max(state_id.sequence_id);
+    }
+
+    /// Translates the operations of a request, it is remembered how once it is sent.
+    pub(crate) fn translate(&mut self, arg_array: Vec<ArgOp>) -> Vec<ArgOp> {
+        let renewing = matches!(&arg_array[..], [ArgOp::Sequence(_)]);
+        self.unsent.clear();
+        let mut translated = vec![];
+        for mut op in arg_array {
+            let sent = match &mut op {
+                ArgOp::Sequence(_) if renewing => {
+                    op = ArgOp::Renew(RenewArgs {
+                        client_id: self.client_id,
+                    });
+                    Sent::Renew
+                }
+                ArgOp::Sequence(_) | ArgOp::ReclaimComplete(_) => {
+                    self.unsent.push(Sent::LeftOut(op.to_id()));
+                    continue;
+                }
+                ArgOp::Open(args) => {
+                    args.sequence_id = self.owner_sequence_id(&args.owner.opaque);
+                    Sent::Open {
+                        owner: args.owner.opaque.clone(),
+                    }
+                }
+                ArgOp::OpenDowngrade(OpenDowngradeArgs {
+                    open_state_id,
+                    sequence_id,
+                    ..
+                })
+                | ArgOp::Close(CloseArgs {
+                    open_stateid: open_state_id,
+                    sequence_id,
+                }) => {
+                    self.current_state_id(open_state_id);
+                    match self.opens.get(&open_state_id.other) {
+                        Some((owner, _)) => {
+                            let owner = owner.clone();
+                            *sequence_id = self.owner_sequence_id(&owner);
+                            Sent::OpenOwner { owner }
+                        }
+                        None => Sent::AsIs,
+                    }
+                }
+                ArgOp::Read(args) => {
+                    self.current_state_id(&mut args.state_id);
+                    Sent::AsIs
+                }
+                ArgOp::Write(args) => {
+                    self.current_state_id(&mut args.state_id);
+                    Sent::AsIs
+                }
+                ArgOp::SetAttr(args) => {
+                    self.current_state_id(&mut args.state_id);
+                    Sent::AsIs
+                }
+                _ => Sent::AsIs,
+            };
+            let open = matches!(sent, Sent::Open { .. });
+            translated.push(op);
+            self.unsent.push(sent);
+            if open {
+                translated.push(ArgOp::GetFh);
+            }
+        }
+        translated
+    }
+
+    pub(crate) fn sent(&mut self, xid: &Xid) {
+        self.in_flight
+            .insert(xid.0, std::mem::take(&mut self.unsent));
+    }
+
+    /// Translates the reply to a request back, confirming the OPENs in it which need it first.
+    /// This sends a request of its own, so it mustn't be done with other requests in flight, which
+    /// OPENs never are.
+    pub(crate) fn translate_reply<TransportT: Transport>(
+        &mut self,
+        raw_client: &mut ClientWithoutSession<TransportT>,
+        xid: &Xid,
+        reply: CompoundRes,
+    ) -> Result<CompoundRes> {
+        let Some(sent) = self.in_flight.remove(&xid.0) else {
+            return Ok(reply);
+        };
+        let failed = match &reply.status {
+            StatusResult::Ok(()) => None,
+            StatusResult::Err(e) => Some(*e),
+        };
+        let mut results = VecDeque::from(reply.res_array);
+        let mut res_array = vec![];
+        for sent in sent {
+            let res = match sent {
+                Sent::LeftOut(OperationId::Sequence) => {
+                    res_array.push(ResOp::Sequence(StatusResult::Ok(sequence_res())));
+                    continue;
+                }
+                Sent::LeftOut(_) => {
+                    res_array.push(ResOp::ReclaimComplete(StatusResult::Ok(())));
+                    continue;
+                }
+                _ => match results.pop_front() {
+                    Some(res) => res,
+                    // The COMPOUND stopped before it
+                    None => break,
+                },
+            };
+            let res = match (sent, res) {
+                (Sent::Renew, ResOp::Renew(StatusResult::Ok(()))) => {
+                    ResOp::Sequence(StatusResult::Ok(sequence_res()))
+                }
+                (Sent::Renew, ResOp::Renew(StatusResult::Err(e))) => {
+                    ResOp::Sequence(StatusResult::Err(e))
+                }
+                (Sent::Open { owner }, ResOp::Open(StatusResult::Ok(mut open))) => {
+                    self.used(&owner, None);
+                    let Some(ResOp::GetFh(StatusResult::Ok(GetFhRes { object }))) =
+                        results.pop_front()
+                    else {
+                        return Err(Error::CompoundResponseMismatch(
+                            "no GETFH after OPEN".into(),
+                        ));
+                    };
+                    if open.result_flags.contains(OpenResult::CONFIRM) {
+                        open.state_id = self.confirm(raw_client, object, &owner, open.state_id)?;
+                        open.result_flags.remove(OpenResult::CONFIRM);
+                    }
+                    self.opened(owner, open.state_id);
+                    ResOp::Open(StatusResult::Ok(open))
+                }
+                (Sent::Open { owner }, res @ ResOp::Open(StatusResult::Err(e))) => {
+                    self.used(&owner, Some(e));
+                    res
+                }
+                (Sent::OpenOwner { owner }, res) => {
+                    match &res {
+                        ResOp::Close(StatusResult::Ok(CloseRes { open_state_id })) => {
+                            self.opens.remove(&open_state_id.other);
+                        }
+                        ResOp::OpenDowngrade(StatusResult::Ok(OpenDowngradeRes {
+                            open_state_id,
+                        })) => self.opened(owner.clone(), *open_state_id),
+                        _ => {}
+                    }
+                    let status = failed.filter(|_| results.is_empty());
+                    self.used(&owner, status);
+                    res
+                }
+                (_, res) => res,
+            };
+            res_array.push(res);
+        }
+
+        Ok(CompoundRes {
+            status: reply.status,
+            tag: reply.tag,
+            res_array,
+        })
+    }
+
+    fn confirm<TransportT: Transport>(
+        &mut self,
+        raw_client: &mut ClientWithoutSession<TransportT>,
+        handle: FileHandle,
+        owner: &[u8],
+        state_id: StateId,
+    ) -> Result<StateId> {
+        let res = raw_client.do_compound(ReturnSecond(
+            PutFhArgs { object: handle },
+            OpenConfirmArgs {
+                open_state_id: state_id,
+                sequence_id: self.owner_sequence_id(owner),
+            },
+        ));
+        self.used(owner, res.as_ref().err().and_then(Error::status));
+        Ok(res?.open_state_id)
+    }
+}
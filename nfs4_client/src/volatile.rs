@@ -0,0 +1,152 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Servers whose file handles can expire, RFC 8881 section 4.2.3. For those we remember the path
+//! each handle was found at, so that when the server says one expired we can look the path up
+//! again and send the request again with the handle we get. The handle callers have keeps being
+//! swapped for the new one from then on.
+
+use super::{look_up_args, Client, GetFh, PutRootFh, RemotePath, RemotePathBuf, ReturnSecond};
+use nfs4::{ArgOp, Bytes, FhExpireType, FileHandle};
+use std::collections::BTreeMap;
+use sun_rpc_client::Transport;
+
+/// How many paths are remembered. When there are more, the oldest handles can't be renewed.
+const MAX_PATHS: usize = 100_000;
+
+#[derive(Default)]
+pub(crate) struct VolatileHandles {
+    // From the root, by handle
+    paths: BTreeMap<Bytes, RemotePathBuf>,
+    // The handles which replaced ones that expired
+    renewed: BTreeMap<Bytes, FileHandle>,
+}
+
+/// Whether the server's handles can expire while we are using them.
+pub(crate) fn is_volatile(fh_expire_type: FhExpireType) -> bool {
+    fh_expire_type.intersects(
+        FhExpireType::VOLATILE_ANY | FhExpireType::VOL_MIGRATION | FhExpireType::VOL_RENAME,
+    )
+}
+
+impl<TransportT: Transport> Client<TransportT> {
+    /// What the server said about when its file handles can stop working, empty if they never
+    /// do. Unless it is empty, handles which expire are looked up again by the path they were
+    /// found at, so callers can keep using the ones they have.
+    pub fn fh_expire_type(&self) -> FhExpireType {
+        self.fh_expire_type
+    }
+
+    pub(crate) fn remember_path(&mut self, handle: &FileHandle, path: &RemotePath) {
+        let Some(volatile) = &mut self.volatile_handles else {
+            return;
+        };
+        if volatile.paths.len() >= MAX_PATHS {
+            volatile.paths.clear();
+        }
+        let path = RemotePath::new("/").join(path);
+        volatile.paths.insert(handle.0.clone(), path);
+    }
+
+    /// Remembers the path of something found from a directory whose path we know.
+    pub(crate) fn remember_entry_path(
+        &mut self,
+        dir: &FileHandle,
+        path: impl AsRef<RemotePath>,
+        handle: &FileHandle,
+    ) {
+        let Some(volatile) = &self.volatile_handles else {
+            return;
+        };
+        if let Some(dir_path) = volatile.paths.get(&dir.0) {
+            let path = dir_path.join(path);
+            self.remember_path(handle, &path);
+        }
+    }
+
+    /// Swaps the handles in the request for the ones which replaced them, if any expired.
+    pub(crate) fn use_renewed_handles(&self, arg_array: &mut [ArgOp]) {
+        let Some(volatile) = self.volatile_handles.as_ref() else {
+            return;
+        };
+        if volatile.renewed.is_empty() {
+            return;
+        }
+        for op in arg_array {
+            if let ArgOp::PutFh(args) = op {
+                if let Some(renewed) = volatile.renewed.get(&args.object.0) {
+                    args.object = renewed.clone();
+                }
+            }
+        }
+    }
+
+    /// Looks up the path the given handle was found at again, after the server said it expired.
+    /// Returns the handle it has now, or `None` if we don't know the path or it isn't there
+    /// anymore.
+    pub(crate) fn renew_expired_handle(&mut self, expired: &FileHandle) -> Option<FileHandle> {
+        let volatile = self.volatile_handles.as_ref()?;
+        let path = volatile.paths.get(&expired.0)?.clone();
+        let handle = self
+            .do_path_compound(&path, |path| {
+                ReturnSecond((PutRootFh, look_up_args(path)), GetFh)
+            })
+            .ok()?
+            .object;
+
+        let volatile = self.volatile_handles.as_mut().unwrap();
+        for replacement in volatile.renewed.values_mut() {
+            if replacement == expired {
+                *replacement = handle.clone();
+            }
+        }
+        volatile.renewed.insert(expired.0.clone(), handle.clone());
+        volatile.paths.insert(handle.0.clone(), path);
+        Some(handle)
+    }
+
+    /// Renews the handles of the request, returning whether any of them could be, in which case
+    /// it can be sent again.
+    pub(crate) fn renew_expired_handles(&mut self, arg_array: &mut [ArgOp]) -> bool {
+        let mut renewed_any = false;
+        for op in arg_array {
+            if let ArgOp::PutFh(args) = op {
+                if let Some(handle) = self.renew_expired_handle(&args.object) {
+                    args.object = handle;
+                    renewed_any = true;
+                }
+            }
+        }
+        renewed_any
+    }
+}
+
+#[test]
+fn expired_handles_looked_up_again() {
+    use nfs4::{OperationId, StatusError};
+    use nfs4_server::memory::MemoryFs;
+
+    let mut files = MemoryFs::new();
+    files.make_dir("dir").unwrap();
+    files.write_file("dir/a_file", "hello").unwrap();
+    files.set_fh_expire_type(FhExpireType::VOLATILE_ANY);
+    let (server, mut client) = super::in_memory_client(files);
+    assert_eq!(client.fh_expire_type(), FhExpireType::VOLATILE_ANY);
+
+    let dir = client.look_up("/dir").unwrap();
+    let handle = client.look_up_from(dir, "a_file").unwrap();
+    server.inject_error(OperationId::PutFh, StatusError::FhExpired, 1);
+    assert_eq!(
+        &client.read(handle.clone(), 0, 5).unwrap().data[..],
+        b"hello"
+    );
+
+    server.inject_error(OperationId::PutFh, StatusError::FhExpired, 1);
+    let mut contents = vec![];
+    client.read_all(handle.clone(), &mut contents).unwrap();
+    assert_eq!(contents, b"hello");
+
+    // It is only looked up again once for each request
+    server.inject_error(OperationId::PutFh, StatusError::FhExpired, 2);
+    let error = client.read(handle, 0, 5).unwrap_err();
+    assert_eq!(error.status(), Some(StatusError::FhExpired));
+}
@@ -0,0 +1,319 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Watching directories for entries being created, removed or renamed.
+
+use super::{lock, Connection, Result, ReturnSecond};
+use nfs4::{
+    CbNotifyArgs, Change, Cookie, DelegReturnArgs, FileAttributeId, FileHandle, GetAttrArgs,
+    Notify, NotifyAdd, NotifyAttr, NotifyRemove, NotifyRename, NotifyType, NotifyVerifier,
+    PutFhArgs, ReadDirArgs, StateId, Verifier,
+};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeSet;
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+use sun_rpc_client::Transport;
+
+// How often a watched directory is checked for changes when the server doesn't notify us of them.
+// When it does, this is how often we give it the chance to.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// How much of a directory listing to ask for at once when looking for changes
+const WATCH_READ_DIR_COUNT: u32 = 32768;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DirEvent {
+    Created(String),
+    Removed(String),
+    Renamed { from: String, to: String },
+}
+
+// What we know about a watched directory
+pub(crate) struct WatchState {
+    events: mpsc::Sender<DirEvent>,
+    delegation: Option<StateId>,
+    // The change attribute when we last listed the directory, `None` when it needs listing again
+    change: Option<Change>,
+    names: BTreeSet<String>,
+}
+
+impl WatchState {
+    pub(crate) fn new(
+        events: mpsc::Sender<DirEvent>,
+        delegation: Option<StateId>,
+        change: Option<Change>,
+        names: BTreeSet<String>,
+    ) -> Self {
+        Self {
+            events,
+            delegation,
+            change,
+            names,
+        }
+    }
+
+    pub(crate) fn delegation(&self) -> Option<StateId> {
+        self.delegation
+    }
+
+    /// The server forgot the delegation along with our session, we could have missed anything
+    /// since.
+    pub(crate) fn lost_delegation(&mut self) {
+        self.delegation = None;
+        self.change = None;
+    }
+
+    fn send(&mut self, event: DirEvent) {
+        match &event {
+            DirEvent::Created(name) => {
+                self.names.insert(name.clone());
+            }
+            DirEvent::Removed(name) => {
+                self.names.remove(name);
+            }
+            DirEvent::Renamed { from, to } => {
+                self.names.remove(from);
+                self.names.insert(to.clone());
+            }
+        }
+        let _ = self.events.send(event);
+    }
+}
+
+/// What changed between two listings of a directory. Renames show up as one entry being removed
+/// and another created.
+fn diff_listings(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Vec<DirEvent> {
+    let removed = old.difference(new).cloned().map(DirEvent::Removed);
+    let created = new.difference(old).cloned().map(DirEvent::Created);
+    removed.chain(created).collect()
+}
+
+fn next_value<T: DeserializeOwned>(values: &mut &[u8]) -> Result<T> {
+    let mut deserializer = xdr_extras::Deserializer::new(values);
+    let value = T::deserialize(&mut deserializer).map_err(sun_rpc_client::Error::from)?;
+    *values = deserializer.remaining();
+    Ok(value)
+}
+
+/// Decodes a notification from the server, which can describe several types of change at once.
+fn notify_events(notify: &Notify) -> Result<Vec<DirEvent>> {
+    let mut values = &notify.values[..];
+    let mut events = vec![];
+    for notify_type in notify.mask.clone() {
+        match notify_type {
+            NotifyType::AddEntry => {
+                let add: NotifyAdd = next_value(&mut values)?;
+                events.push(DirEvent::Created(add.new_entry.file));
+            }
+            NotifyType::RemoveEntry => {
+                let remove: NotifyRemove = next_value(&mut values)?;
+                events.push(DirEvent::Removed(remove.old_entry.file));
+            }
+            NotifyType::RenameEntry => {
+                let rename: NotifyRename = next_value(&mut values)?;
+                events.push(DirEvent::Renamed {
+                    from: rename.old_entry.old_entry.file,
+                    to: rename.new_entry.new_entry.file,
+                });
+            }
+            NotifyType::ChangeChildAttrs | NotifyType::ChangeDirAttrs => {
+                next_value::<NotifyAttr>(&mut values)?;
+            }
+            NotifyType::ChangeCookieVerifier => {
+                next_value::<NotifyVerifier>(&mut values)?;
+            }
+        }
+    }
+    Ok(events)
+}
+
+impl<TransportT: Transport> Connection<TransportT> {
+    fn dir_change(&mut self, dir: &FileHandle) -> Result<Option<Change>> {
+        let res = self.do_compound(ReturnSecond(
+            PutFhArgs {
+                object: dir.clone(),
+            },
+            GetAttrArgs {
+                attr_request: [FileAttributeId::Change].into_iter().collect(),
+            },
+        ))?;
+        Ok(res
+            .object_attributes
+            .get_as::<Change>(FileAttributeId::Change)
+            .copied())
+    }
+
+    fn list_names(&mut self, dir: &FileHandle) -> Result<BTreeSet<String>> {
+        let mut names = BTreeSet::new();
+        let mut cookie = Cookie::initial();
+        let mut cookie_verifier = Verifier(0);
+        loop {
+            let res = self.do_compound(ReturnSecond(
+                PutFhArgs {
+                    object: dir.clone(),
+                },
+                ReadDirArgs {
+                    cookie,
+                    cookie_verifier,
+                    directory_count: WATCH_READ_DIR_COUNT,
+                    max_count: WATCH_READ_DIR_COUNT,
+                    attr_request: Default::default(),
+                },
+            ))?;
+            names.extend(res.reply.entries.iter().map(|e| e.name.clone()));
+            match res.reply.entries.last() {
+                Some(last) if !res.reply.eof => {
+                    cookie = last.cookie;
+                    cookie_verifier = res.cookie_verifier;
+                }
+                _ => return Ok(names),
+            }
+        }
+    }
+
+    /// Looks for changes to the given watched directory if the server isn't notifying us of them,
+    /// otherwise just gives it the chance to.
+    fn poll_watch(&mut self, dir: &FileHandle) -> Result<()> {
+        let Some(watch) = self.watches.get(&dir.0) else {
+            return Ok(());
+        };
+        let last_change = watch.change;
+        if watch.delegation.is_some() && last_change.is_some() {
+            return self.renew_lease();
+        }
+
+        let change = self.dir_change(dir)?;
+        if change.is_some() && change == last_change {
+            return Ok(());
+        }
+        let names = self.list_names(dir)?;
+
+        let Some(watch) = self.watches.get_mut(&dir.0) else {
+            return Ok(());
+        };
+        for event in diff_listings(&watch.names, &names) {
+            watch.send(event);
+        }
+        watch.change = change;
+        Ok(())
+    }
+
+    pub(crate) fn notified(&mut self, args: CbNotifyArgs) {
+        let Some(watch) = self.watches.get_mut(&args.fh.0) else {
+            return;
+        };
+        for notify in &args.changes {
+            match notify_events(notify) {
+                Ok(events) => events.into_iter().for_each(|e| watch.send(e)),
+                // Not knowing what changed, we have to go and look
+                Err(_) => watch.change = None,
+            }
+        }
+    }
+
+    /// Gives back the delegation for the given watched directory if we have one, after which it
+    /// is polled instead.
+    pub(crate) fn return_dir_delegation(&mut self, dir: &FileHandle) -> Result<()> {
+        let Some(watch) = self.watches.get_mut(&dir.0) else {
+            return Ok(());
+        };
+        let Some(state_id) = watch.delegation.take() else {
+            return Ok(());
+        };
+        watch.change = None;
+        self.do_compound((
+            PutFhArgs {
+                object: dir.clone(),
+            },
+            DelegReturnArgs { state_id },
+        ))?;
+        Ok(())
+    }
+}
+
+/// Delivers changes to a directory until dropped, see `Client::watch_dir`.
+pub struct DirWatcher<TransportT: Transport> {
+    dir: FileHandle,
+    events: mpsc::Receiver<DirEvent>,
+    connection: Weak<Mutex<Connection<TransportT>>>,
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<TransportT: Transport + Send + 'static> DirWatcher<TransportT> {
+    pub(crate) fn new(
+        dir: FileHandle,
+        events: mpsc::Receiver<DirEvent>,
+        connection: &Arc<Mutex<Connection<TransportT>>>,
+    ) -> Self {
+        let (stop, stop_receiver) = mpsc::channel();
+        let thread = {
+            let connection = Arc::downgrade(connection);
+            let dir = dir.clone();
+            thread::spawn(move || poll_watch_periodically(connection, dir, stop_receiver))
+        };
+        Self {
+            dir,
+            events,
+            connection: Arc::downgrade(connection),
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<TransportT: Transport> DirWatcher<TransportT> {
+    pub fn events(&self) -> &mpsc::Receiver<DirEvent> {
+        &self.events
+    }
+
+    /// Whether the server notifies us of changes, rather than us polling for them.
+    pub fn is_notified(&self) -> bool {
+        self.connection.upgrade().is_some_and(|c| {
+            lock(&c)
+                .watches
+                .get(&self.dir.0)
+                .is_some_and(|w| w.delegation.is_some())
+        })
+    }
+}
+
+impl<TransportT: Transport> Drop for DirWatcher<TransportT> {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(connection) = self.connection.upgrade() {
+            let mut connection = lock(&connection);
+            let _ = connection.return_dir_delegation(&self.dir);
+            connection.watches.remove(&self.dir.0);
+        }
+    }
+}
+
+/// Errors are ignored here, they are most likely to do with the connection and will be reported
+/// by the next real request.
+fn poll_watch_periodically<TransportT: Transport>(
+    connection: Weak<Mutex<Connection<TransportT>>>,
+    dir: FileHandle,
+    stop: mpsc::Receiver<()>,
+) {
+    while stop.recv_timeout(WATCH_INTERVAL) == Err(mpsc::RecvTimeoutError::Timeout) {
+        let Some(connection) = connection.upgrade() else {
+            break;
+        };
+        let _ = lock(&connection).poll_watch(&dir);
+    }
+}
+
+#[test]
+fn diff() {
+    let old: BTreeSet<String> = ["a", "b", "c"].into_iter().map(Into::into).collect();
+    let new: BTreeSet<String> = ["a", "c", "d"].into_iter().map(Into::into).collect();
+    assert_eq!(
+        diff_listings(&old, &new),
+        vec![DirEvent::Removed("b".into()), DirEvent::Created("d".into())]
+    );
+}
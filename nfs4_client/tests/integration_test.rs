@@ -1,11 +1,46 @@
 // Copyright Remi Bernotavicius
 
-use nfs4::{FileAttribute, FileAttributeId, FileHandle};
-use nfs4_client::Client;
+use nfs4::{
+    Access, FileAttribute, FileAttributeId, FileAttributesBuilder, FileHandle, GetAttrArgs,
+    LookUpArgs, SecurityInfo, ShareAccess, ShareDeny, StatusError,
+};
 use nfs4_client::NFS_PORT;
+use nfs4_client::{
+    Client, ClientBuilder, CompoundBuilder, DirEvent, File, GetFh, NfsUrl, OpenOptions, PutRootFh,
+    RemotePath, RemotePathBuf, Scheduling,
+};
 use std::collections::BTreeSet;
-use std::net::TcpStream;
-use std::path::Path;
+use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::net::{Ipv4Addr, TcpStream};
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+/// The server the tests run against, either the one in the VM or one given with
+/// `NFS4_TEST_SERVER`.
+enum Server<'machine> {
+    Vm(&'machine mut vm_runner::Machine),
+    External(NfsUrl),
+}
+
+impl Server<'_> {
+    fn connect_transport(&self) -> TcpStream {
+        match self {
+            Self::Vm(machine) => {
+                let port = machine
+                    .forwarded_ports()
+                    .iter()
+                    .find(|p| p.guest == NFS_PORT)
+                    .unwrap();
+                TcpStream::connect(("127.0.0.1", port.host)).unwrap()
+            }
+            Self::External(url) => TcpStream::connect((url.host.as_str(), url.port)).unwrap(),
+        }
+    }
+
+    fn connect(&self) -> Client<TcpStream> {
+        Client::new(self.connect_transport()).unwrap()
+    }
+}
 
 macro_rules! test {
     ($test_name:ident) => {
@@ -14,38 +49,90 @@ macro_rules! test {
 }
 
 struct Fixture<'machine> {
-    machine: &'machine mut vm_runner::Machine,
+    server: Server<'machine>,
     client: Client<TcpStream>,
+    // The directory the tests make their files in, emptied after each one
+    files: RemotePathBuf,
 }
 
 impl<'machine> Fixture<'machine> {
-    fn new(machine: &'machine mut vm_runner::Machine) -> Self {
-        let port = machine
-            .forwarded_ports()
-            .iter()
-            .find(|p| p.guest == NFS_PORT)
-            .unwrap();
-        let transport = TcpStream::connect(("127.0.0.1", port.host)).unwrap();
-        let client = Client::new(transport).unwrap();
-
-        Self { machine, client }
+    fn new(server: Server<'machine>) -> Self {
+        let mut client = server.connect();
+        let files = match &server {
+            Server::Vm(_) => "/files".into(),
+            // Someone else's server may have anything in it, so we keep to a directory of our own
+            Server::External(url) => {
+                let name = format!("nfs4-test-{:016x}", rand::random::<u64>());
+                let parent = client.look_up(&url.path).unwrap();
+                client
+                    .create_directory(parent, &name, Default::default())
+                    .unwrap();
+                url.path.join(name)
+            }
+        };
+        Self {
+            server,
+            client,
+            files,
+        }
     }
 
     fn run(&mut self) {
         let tests = [
+            test!(access_test),
+            test!(compound_builder_test),
+            test!(copy_test),
             test!(create_directory_test),
             test!(create_file_test),
+            test!(delegation_test),
+            test!(file_test),
+            test!(open_test),
+            test!(pnfs_test),
             test!(read_dir_test),
             test!(read_write_test),
+            test!(read_pipelined_test),
+            test!(read_write_at_test),
+            test!(recall_test),
             test!(remove_test),
             test!(rename_test),
+            test!(sec_info_test),
             test!(set_attr_test),
+            test!(stat_test),
+            test!(statfs_test),
+            test!(trunking_test),
+            test!(verify_test),
+            test!(walk_test),
+            test!(watch_dir_test),
+            test!(write_pipelined_test),
         ];
 
+        // Servers other than ours may not do everything, so we keep going to find out all they
+        // don't do
+        let mut failed = vec![];
         for (test, test_name) in tests {
             log::info!("running test {}:Fixture::{}", file!(), test_name);
-            test(self);
-            self.machine.run_command("rm -rf /files/*");
+            if std::panic::catch_unwind(AssertUnwindSafe(|| test(self))).is_err() {
+                failed.push(test_name);
+            }
+            self.clean();
+        }
+
+        if let Server::External(_) = self.server {
+            let parent = self.client.look_up(self.files.parent().unwrap()).unwrap();
+            let name = self.files.file_name().unwrap().to_owned();
+            self.client.remove(parent, &name).unwrap();
+        }
+        assert!(failed.is_empty(), "failed tests: {failed:?}");
+    }
+
+    /// Removes everything the last test left in the directory.
+    fn clean(&mut self) {
+        match &mut self.server {
+            Server::Vm(machine) => machine.run_command("rm -rf /files/*"),
+            Server::External(_) => {
+                let files = self.client.look_up(&self.files).unwrap();
+                remove_entries(&mut self.client, files);
+            }
         }
     }
 
@@ -56,7 +143,11 @@ impl<'machine> Fixture<'machine> {
     // |_| |_|\___|_| .__/ \___|_|  |___/
     //              |_|
 
-    fn get_file_size(&mut self, path: &str) -> u64 {
+    fn path(&self, name: &str) -> RemotePathBuf {
+        self.files.join(name)
+    }
+
+    fn get_file_size(&mut self, path: impl AsRef<RemotePath>) -> u64 {
         let handle = self.client.look_up(path).unwrap();
         let reply = self.client.get_attr(handle).unwrap();
         *reply
@@ -65,13 +156,15 @@ impl<'machine> Fixture<'machine> {
             .unwrap()
     }
 
-    fn create_file(&mut self, path: impl AsRef<Path>) -> FileHandle {
+    fn create_file(&mut self, path: impl AsRef<RemotePath>) -> FileHandle {
         let path = path.as_ref();
 
         let parent = self.client.look_up(path.parent().unwrap()).unwrap();
         self.client
-            .create_file(parent.clone(), path.file_name().unwrap().to_str().unwrap())
+            .create_file(parent.clone(), path.file_name().unwrap())
             .unwrap()
+            .handle
+            .clone()
     }
 
     //  _            _
@@ -81,13 +174,170 @@ impl<'machine> Fixture<'machine> {
     //  \__\___||___/\__|___/
     //
 
+    fn access_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        let res = self
+            .client
+            .access(handle, Access::READ | Access::MODIFY)
+            .unwrap();
+        assert_eq!(res.supported, Access::READ | Access::MODIFY);
+        assert!(res.access.contains(Access::MODIFY));
+    }
+
+    fn compound_builder_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        let mut compound = CompoundBuilder::new();
+        compound.push(PutRootFh);
+        for component in self.files.components() {
+            compound.push(LookUpArgs {
+                object_name: component.into(),
+            });
+        }
+        compound.push(LookUpArgs {
+            object_name: "a_file".into(),
+        });
+        let get_fh = compound.push(GetFh);
+        let get_attr = compound.push(GetAttrArgs {
+            attr_request: [FileAttributeId::Size].into_iter().collect(),
+        });
+        let reply = self.client.send_compound(compound).unwrap();
+        assert_eq!(reply.status(), None);
+        assert_eq!(reply.get(get_fh).unwrap().unwrap().object, handle);
+        let attrs = reply.get(get_attr).unwrap().unwrap().object_attributes;
+        assert_eq!(*attrs.get_as::<u64>(FileAttributeId::Size).unwrap(), 0);
+
+        let mut compound = CompoundBuilder::new();
+        compound.push(PutRootFh);
+        let look_up = compound.push(LookUpArgs {
+            object_name: "not_there".into(),
+        });
+        let get_fh = compound.push(GetFh);
+        let reply = self.client.send_compound(compound).unwrap();
+        assert_eq!(reply.status(), Some(StatusError::NoEnt));
+        assert_eq!(reply.failed_index(), Some(look_up.index()));
+        assert!(reply.get(look_up).unwrap().unwrap_err().is_not_found());
+        assert!(reply.get(get_fh).is_none());
+    }
+
+    fn copy_test(&mut self) {
+        let source = self.create_file(self.path("a_file"));
+        let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 247) as u8).collect();
+        self.client
+            .write_all(source.clone(), &test_contents[..])
+            .unwrap();
+
+        let destination = self.create_file(self.path("b_file"));
+        let copied = self.client.copy(source, destination.clone()).unwrap();
+        assert_eq!(copied, test_contents.len() as u64);
+
+        let mut read_data = vec![];
+        self.client.read_all(destination, &mut read_data).unwrap();
+        assert_eq!(read_data, test_contents);
+    }
+
     fn create_file_test(&mut self) {
-        self.create_file("/files/a_file");
-        self.client.look_up("/files/a_file").unwrap();
+        self.create_file(self.path("a_file"));
+        self.client.look_up(self.path("a_file")).unwrap();
+    }
+
+    fn delegation_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+        self.client
+            .write_all(handle.clone(), &b"some config"[..])
+            .unwrap();
+
+        let parent = self.client.look_up(&self.files).unwrap();
+        let options = OpenOptions::new().read_delegation();
+        let file = self.client.open(parent, "a_file", &options).unwrap();
+
+        for _ in 0..2 {
+            let mut read_data = vec![];
+            self.client
+                .read_all(file.handle.clone(), &mut read_data)
+                .unwrap();
+            assert_eq!(read_data, b"some config");
+
+            let reply = self.client.read(file.handle.clone(), 5, 100).unwrap();
+            assert_eq!(&reply.data[..], b"config");
+            assert!(reply.eof);
+        }
+
+        // Writing gives back any delegation, so we don't read what we had cached
+        self.client
+            .write(file.handle.clone(), 0, b"other".to_vec())
+            .unwrap();
+        let mut read_data = vec![];
+        self.client
+            .read_all(file.handle.clone(), &mut read_data)
+            .unwrap();
+        assert_eq!(read_data, b"other config");
+
+        self.client.close(file).unwrap();
+    }
+
+    fn file_test(&mut self) {
+        let parent = self.client.look_up(&self.files).unwrap();
+        let options = OpenOptions::new().access(ShareAccess::BOTH).create();
+        let open_file = self.client.open(parent, "a_file", &options).unwrap();
+
+        let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 251) as u8).collect();
+        let mut file = File::new(&mut self.client, open_file);
+        file.write_all(&test_contents).unwrap();
+        assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), 100_000);
+
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        let mut read_data = vec![0; 10];
+        file.read_exact(&mut read_data).unwrap();
+        assert_eq!(read_data, test_contents[1000..1010]);
+
+        file.seek(SeekFrom::Current(-10)).unwrap();
+        file.write_all(b"hello").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut read_data = vec![];
+        file.read_to_end(&mut read_data).unwrap();
+        drop(file);
+
+        let mut expected = test_contents;
+        expected[1000..1005].copy_from_slice(b"hello");
+        assert_eq!(read_data, expected);
+    }
+
+    fn open_test(&mut self) {
+        let parent = self.client.look_up(&self.files).unwrap();
+
+        let options = OpenOptions::new()
+            .access(ShareAccess::BOTH)
+            .deny(ShareDeny::WRITE)
+            .create_new();
+        let mut file = self
+            .client
+            .open(parent.clone(), "a_file", &options)
+            .unwrap();
+        assert_eq!(
+            file.handle,
+            self.client.look_up(self.path("a_file")).unwrap()
+        );
+
+        self.client
+            .open(parent.clone(), "a_file", &options)
+            .unwrap_err();
+
+        self.client
+            .open_downgrade(&mut file, ShareAccess::READ, ShareDeny::NONE)
+            .unwrap();
+        self.client.close(file).unwrap();
+
+        let file = self
+            .client
+            .open(parent, "a_file", &OpenOptions::new())
+            .unwrap();
+        self.client.close(file).unwrap();
     }
 
     fn read_write_test(&mut self) {
-        let handle = self.create_file("/files/a_file");
+        let handle = self.create_file(self.path("a_file"));
 
         let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 255) as u8).collect();
         self.client
@@ -100,11 +350,197 @@ impl<'machine> Fixture<'machine> {
             .unwrap();
         assert_eq!(read_data, test_contents);
 
-        assert_eq!(self.get_file_size("/files/a_file"), read_data.len() as u64);
+        assert_eq!(
+            self.get_file_size(self.path("a_file")),
+            read_data.len() as u64
+        );
+    }
+
+    fn pnfs_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        // Without a files layout from the server this all goes through it instead
+        let mut client = self.server.connect();
+        client.enable_pnfs(TcpStream::connect);
+
+        let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 253) as u8).collect();
+        client
+            .write_all(handle.clone(), &test_contents[..])
+            .unwrap();
+
+        let mut read_data = vec![];
+        client.read_all(handle.clone(), &mut read_data).unwrap();
+        assert_eq!(read_data, test_contents);
+
+        assert_eq!(
+            self.get_file_size(self.path("a_file")),
+            read_data.len() as u64
+        );
+    }
+
+    fn read_pipelined_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 251) as u8).collect();
+        self.client
+            .write_all(handle.clone(), &test_contents[..])
+            .unwrap();
+
+        self.client.set_read_chunk_size(Some(999));
+        self.client.set_read_pipeline_depth(16);
+
+        let mut read_data = vec![];
+        self.client
+            .read_all(handle.clone(), &mut read_data)
+            .unwrap();
+        assert_eq!(read_data, test_contents);
+
+        self.client.set_read_chunk_size(None);
+    }
+
+    fn read_write_at_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        assert_eq!(
+            self.client.write_at(handle.clone(), 10, b"hello").unwrap(),
+            5
+        );
+        assert_eq!(self.client.write_at(handle.clone(), 0, b"abc").unwrap(), 3);
+
+        let reply = self.client.read_at(handle.clone(), 8, 4).unwrap();
+        assert_eq!(&reply.data[..], b"\0\0he");
+        assert!(!reply.eof);
+
+        let reply = self.client.read_at(handle.clone(), 12, 100).unwrap();
+        assert_eq!(&reply.data[..], b"llo");
+        assert!(reply.eof);
+
+        let reply = self.client.read_at(handle, 0, 3).unwrap();
+        assert_eq!(&reply.data[..], b"abc");
+    }
+
+    fn trunking_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        let mut client = self.server.connect();
+        client
+            .add_connection(self.server.connect_transport())
+            .unwrap();
+        client
+            .add_connection(self.server.connect_transport())
+            .unwrap();
+        assert_eq!(client.num_connections(), 3);
+
+        let test_contents: Vec<u8> = (0..100_000).map(|v| (v % 241) as u8).collect();
+        client.set_write_chunk_size(Some(1000));
+        client.set_write_pipeline_depth(8);
+        client
+            .write_all(handle.clone(), &test_contents[..])
+            .unwrap();
+
+        client.set_scheduling(Scheduling::LeastLoaded);
+        client.set_read_chunk_size(Some(1000));
+        client.set_read_pipeline_depth(8);
+        let mut read_data = vec![];
+        client.read_all(handle, &mut read_data).unwrap();
+        assert_eq!(read_data, test_contents);
+    }
+
+    fn verify_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+        let size = |size| [FileAttribute::Size(size)].into_iter().collect();
+
+        assert!(self.client.verify(handle.clone(), size(0)).unwrap());
+        assert!(!self.client.verify(handle.clone(), size(5)).unwrap());
+
+        self.client
+            .write_if_unchanged(handle.clone(), size(0), 0, b"hello".to_vec())
+            .unwrap();
+        let error = self
+            .client
+            .write_if_unchanged(handle.clone(), size(0), 5, b"hello".to_vec())
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusError::NotSame));
+
+        assert_eq!(self.get_file_size(self.path("a_file")), 5);
+    }
+
+    fn walk_test(&mut self) {
+        let root = self.client.look_up(&self.files).unwrap();
+        let a = self
+            .client
+            .create_directory(root.clone(), "a", Default::default())
+            .unwrap();
+        let b = self
+            .client
+            .create_directory(a, "b", Default::default())
+            .unwrap();
+        self.client.create_file(b.clone(), "c").unwrap();
+        self.client.create_file(root.clone(), "d").unwrap();
+        let target = self.path("a").into_string();
+        self.client
+            .create_symlink(b, "link", &target, Default::default())
+            .unwrap();
+
+        let entries: Vec<_> = self
+            .client
+            .walk(root, Default::default())
+            .map(|e| e.unwrap())
+            .collect();
+        let actual: BTreeSet<_> = entries
+            .iter()
+            .map(|e| (e.path.to_string(), e.depth))
+            .collect();
+        let expected: BTreeSet<_> = [
+            ("a", 1),
+            ("a/b", 2),
+            ("a/b/c", 3),
+            ("a/b/link", 3),
+            ("d", 1),
+        ]
+        .into_iter()
+        .map(|(p, d)| (p.to_owned(), d))
+        .collect();
+        assert_eq!(actual, expected);
+
+        let position = |path: &str| entries.iter().position(|e| e.path.as_str() == path);
+        assert!(position("a") < position("a/b"));
+        assert!(position("a/b") < position("a/b/c"));
+    }
+
+    fn write_pipelined_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+
+        let test_contents: Vec<u8> = (0..5_000_000).map(|v| (v % 251) as u8).collect();
+        self.client.set_write_pipeline_depth(16);
+        assert!(self.client.write_chunk_size() <= self.client.max_write());
+        self.client
+            .write_all(handle.clone(), &test_contents[..])
+            .unwrap();
+
+        assert_eq!(
+            self.get_file_size(self.path("a_file")),
+            test_contents.len() as u64
+        );
+
+        let mut read_data = vec![];
+        self.client.read_all(handle, &mut read_data).unwrap();
+        assert_eq!(read_data, test_contents);
+    }
+
+    fn sec_info_test(&mut self) {
+        let parent = self.client.look_up(self.files.parent().unwrap()).unwrap();
+        let name = self.files.file_name().unwrap();
+        let flavors = self.client.sec_info(parent, name).unwrap();
+        assert!(flavors.contains(&SecurityInfo::Sys));
+
+        let files = self.client.look_up(&self.files).unwrap();
+        let flavors = self.client.sec_info_no_name(files).unwrap();
+        assert!(flavors.contains(&SecurityInfo::Sys));
     }
 
     fn set_attr_test(&mut self) {
-        let handle = self.create_file("/files/a_file");
+        let handle = self.create_file(self.path("a_file"));
 
         self.client
             .set_attr(
@@ -123,8 +559,50 @@ impl<'machine> Fixture<'machine> {
         );
     }
 
+    fn stat_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+        self.client
+            .write_all(handle.clone(), &b"hello"[..])
+            .unwrap();
+
+        let reply = self.client.stat(self.path("a_file")).unwrap();
+        assert_eq!(
+            *reply
+                .object_attributes
+                .get_as::<u64>(FileAttributeId::Size)
+                .unwrap(),
+            5
+        );
+
+        let (actual_handle, attrs) = self
+            .client
+            .look_up_with_attrs(
+                self.path("a_file"),
+                [FileAttributeId::Size].into_iter().collect(),
+            )
+            .unwrap();
+        assert_eq!(actual_handle, handle);
+        assert_eq!(*attrs.get_as::<u64>(FileAttributeId::Size).unwrap(), 5);
+
+        assert!(self
+            .client
+            .stat(self.path("not_there"))
+            .unwrap_err()
+            .is_not_found());
+    }
+
+    fn statfs_test(&mut self) {
+        let root = self.client.look_up(&self.files).unwrap();
+        let stat = self.client.statfs(root).unwrap();
+        assert!(stat.space_total > 0);
+        assert!(stat.space_free <= stat.space_total);
+        assert!(stat.space_avail <= stat.space_free);
+        assert!(stat.files_total > 0);
+        assert!(stat.files_free <= stat.files_total);
+    }
+
     fn read_dir_test(&mut self) {
-        let parent = self.client.look_up("/files").unwrap();
+        let parent = self.client.look_up(&self.files).unwrap();
 
         let mut expected = BTreeSet::new();
 
@@ -134,48 +612,257 @@ impl<'machine> Fixture<'machine> {
             expected.insert(name);
         }
 
-        let entries = self
+        let actual: BTreeSet<String> = self
             .client
             .read_dir(parent.clone(), Default::default())
-            .unwrap();
-        let actual: BTreeSet<String> = entries.into_iter().map(|e| e.name).collect();
+            .map(|e| e.unwrap().name)
+            .collect();
         assert_eq!(actual, expected);
     }
 
+    fn recall_test(&mut self) {
+        let handle = self.create_file(self.path("a_file"));
+        self.client
+            .write_all(handle.clone(), &b"some config"[..])
+            .unwrap();
+
+        let parent = self.client.look_up(&self.files).unwrap();
+        let options = OpenOptions::new().read_delegation();
+        let file = self.client.open(parent, "a_file", &options).unwrap();
+        let mut read_data = vec![];
+        self.client
+            .read_all(file.handle.clone(), &mut read_data)
+            .unwrap();
+
+        // Another client writing makes the server recall our delegation, which we only hear about
+        // and give back while talking to it
+        let mut other_client = self.server.connect();
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                other_client
+                    .write(handle.clone(), 0, b"other".to_vec())
+                    .unwrap()
+            });
+            while !writer.is_finished() {
+                self.client.look_up(&self.files).unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            writer.join().unwrap();
+        });
+
+        let mut read_data = vec![];
+        self.client
+            .read_all(file.handle.clone(), &mut read_data)
+            .unwrap();
+        assert_eq!(read_data, b"other config");
+
+        self.client.close(file).unwrap();
+    }
+
     fn remove_test(&mut self) {
-        self.create_file("/files/a_file");
-        let parent = self.client.look_up("/files").unwrap();
+        self.create_file(self.path("a_file"));
+        let parent = self.client.look_up(&self.files).unwrap();
 
         self.client.remove(parent, "a_file").unwrap();
-        self.client.look_up("/files/a_file").unwrap_err();
+        self.client.look_up(self.path("a_file")).unwrap_err();
     }
 
     fn rename_test(&mut self) {
-        self.create_file("/files/a_file");
-        let parent = self.client.look_up("/files").unwrap();
+        self.create_file(self.path("a_file"));
+        let parent = self.client.look_up(&self.files).unwrap();
         self.client
             .rename(parent.clone(), parent, "a_file", "b_file")
             .unwrap();
 
-        self.client.look_up("/files/a_file").unwrap_err();
-        self.client.look_up("/files/b_file").unwrap();
+        self.client.look_up(self.path("a_file")).unwrap_err();
+        self.client.look_up(self.path("b_file")).unwrap();
     }
 
     fn create_directory_test(&mut self) {
-        let parent = self.client.look_up("/files").unwrap();
+        let parent = self.client.look_up(&self.files).unwrap();
         let new_dir = self
             .client
             .create_directory(parent, "foobar", Default::default())
             .unwrap();
         self.client.create_file(new_dir, "a_file").unwrap();
-        self.client.look_up("/files/foobar/a_file").unwrap();
+        self.client.look_up(self.path("foobar/a_file")).unwrap();
+    }
+
+    fn watch_dir_test(&mut self) {
+        let parent = self.client.look_up(&self.files).unwrap();
+        let watcher = self.client.watch_dir(parent.clone()).unwrap();
+
+        self.create_file(self.path("a_file"));
+        let event = watcher.events().recv_timeout(Duration::from_secs(10));
+        assert_eq!(event, Ok(DirEvent::Created("a_file".into())));
+
+        self.client.remove(parent, "a_file").unwrap();
+        let event = watcher.events().recv_timeout(Duration::from_secs(10));
+        assert_eq!(event, Ok(DirEvent::Removed("a_file".into())));
+    }
+}
+
+/// Removes everything in the directory, whatever is in it.
+fn remove_entries(client: &mut Client<TcpStream>, dir: FileHandle) {
+    let entries: Vec<_> = client
+        .read_dir(dir.clone(), Default::default())
+        .map(|e| e.unwrap().name)
+        .collect();
+    for entry in entries {
+        if let Err(e) = client.remove(dir.clone(), &entry) {
+            assert!(e.is_directory_not_empty(), "{e:?}");
+            let subdir = client.look_up_from(dir.clone(), &entry).unwrap();
+            remove_entries(client, subdir);
+            client.remove(dir.clone(), &entry).unwrap();
+        }
     }
 }
 
 #[test]
 fn linux_server() {
     vm_test_fixture::fixture(&[NFS_PORT], |m| {
-        let mut fix = Fixture::new(m);
+        let mut fix = Fixture::new(Server::Vm(m));
         fix.run();
     });
 }
+
+/// Runs the same tests against the server given as an `nfs://` URL with `NFS4_TEST_SERVER`, for
+/// when there is no VM to run them in. They make their files in a directory of their own in the
+/// path of the URL. Without it set there is nothing to do.
+#[test]
+fn external_server() {
+    let Ok(url) = std::env::var("NFS4_TEST_SERVER") else {
+        eprintln!("NFS4_TEST_SERVER isn't set, skipping");
+        return;
+    };
+    let mut fix = Fixture::new(Server::External(url.parse().unwrap()));
+    fix.run();
+}
+
+/// A transport which also writes what it carries to a pcap file, as the segments of a TCP
+/// connection between made up addresses. The traffic of the server in the VM goes through QEMU's
+/// port forwarding, so it can't be captured with tcpdump on this end.
+struct CaptureTransport {
+    stream: TcpStream,
+    pcap: std::fs::File,
+    /// The next sequence number of what we send and of what we receive
+    sequences: [u32; 2],
+}
+
+impl CaptureTransport {
+    const CLIENT: (Ipv4Addr, u16) = (Ipv4Addr::new(10, 0, 2, 2), 740);
+    const SERVER: (Ipv4Addr, u16) = (Ipv4Addr::new(10, 0, 2, 15), NFS_PORT);
+
+    fn new(stream: TcpStream, path: &std::path::Path) -> io::Result<Self> {
+        let mut pcap = std::fs::File::create(path)?;
+        let mut header = vec![];
+        header.extend(0xa1b2c3d4u32.to_le_bytes());
+        header.extend([2, 0, 4, 0]);
+        header.extend([0; 8]);
+        header.extend(65535u32.to_le_bytes());
+        // Raw IPv4
+        header.extend(228u32.to_le_bytes());
+        pcap.write_all(&header)?;
+        let mut capture = Self {
+            stream,
+            pcap,
+            sequences: [1000, 5000],
+        };
+        capture.segment(false, true, &[])?;
+        capture.segment(true, true, &[])?;
+        Ok(capture)
+    }
+
+    fn segment(&mut self, received: bool, syn: bool, data: &[u8]) -> io::Result<()> {
+        let ((source, source_port), (destination, destination_port)) = match received {
+            false => (Self::CLIENT, Self::SERVER),
+            true => (Self::SERVER, Self::CLIENT),
+        };
+        for data in data.chunks(65000).chain(syn.then_some(&[][..])) {
+            let sequence = &mut self.sequences[received as usize];
+            let mut packet = vec![0x45, 0];
+            packet.extend((40 + data.len() as u16).to_be_bytes());
+            packet.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+            packet.extend(source.octets());
+            packet.extend(destination.octets());
+            packet.extend(source_port.to_be_bytes());
+            packet.extend(destination_port.to_be_bytes());
+            packet.extend(sequence.to_be_bytes());
+            packet.extend([0; 4]);
+            packet.extend([5 << 4, if syn { 0x02 } else { 0x18 }]);
+            packet.extend([0; 6]);
+            packet.extend(data);
+            // The SYN takes up a sequence number of its own
+            *sequence = sequence.wrapping_add(data.len() as u32 + syn as u32);
+
+            let mut record = vec![0; 8];
+            record.extend((packet.len() as u32).to_le_bytes());
+            record.extend((packet.len() as u32).to_le_bytes());
+            record.extend(packet);
+            self.pcap.write_all(&record)?;
+        }
+        Ok(())
+    }
+}
+
+impl io::Read for CaptureTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        self.segment(true, false, &buf[..read])?;
+        Ok(read)
+    }
+}
+
+impl io::Write for CaptureTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        self.segment(false, false, &buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Records captures of a session with each minor version of the server in the VM to
+/// `nfs4/tests/captures`, for `captures_decode` there, when `NFS4_RECORD_CAPTURES` is set.
+#[test]
+fn record_linux_captures() {
+    if std::env::var_os("NFS4_RECORD_CAPTURES").is_none() {
+        eprintln!("NFS4_RECORD_CAPTURES isn't set, skipping");
+        return;
+    }
+    let captures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../nfs4/tests/captures");
+    vm_test_fixture::fixture(&[NFS_PORT], |m| {
+        let server = Server::Vm(m);
+        for minor_version in [0, 1] {
+            let name = format!("linux-alpine-v4.{minor_version}-copy-and-attributes.pcap");
+            let transport = CaptureTransport::new(server.connect_transport(), &captures.join(name));
+            let mut client = ClientBuilder::new()
+                .minor_version(minor_version)
+                .connect(transport.unwrap())
+                .unwrap();
+
+            let dir = client.look_up("/files").unwrap();
+            let name = format!("café naïve {minor_version}");
+            let file = client.create_file(dir.clone(), &name).unwrap();
+            let handle = file.handle.clone();
+            let contents: Vec<u8> = (0..3000).map(|v| (v % 253) as u8).collect();
+            client.write_all(handle.clone(), &contents[..]).unwrap();
+            client.close(file).unwrap();
+
+            let mut read = vec![];
+            client.read_all(handle.clone(), &mut read).unwrap();
+            assert_eq!(read, contents);
+            let attrs = FileAttributesBuilder::new().mode(0o600).mtime_now().build();
+            client.set_attr(handle.clone(), attrs).unwrap();
+            client.get_attr(handle).unwrap();
+            let attrs = [FileAttributeId::Type, FileAttributeId::Size];
+            for entry in client.read_dir(dir.clone(), attrs.into_iter().collect()) {
+                entry.unwrap();
+            }
+            client.remove(dir, &name).unwrap();
+        }
+    });
+}
@@ -0,0 +1,1182 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Carrying out COMPOUND requests, one operation after the other until one fails.
+
+use super::fs::{check_name, check_settable, FileSystem, MAX_IO_SIZE};
+use super::state::{Sequenced, State};
+use super::{Exported, Fault, MAX_MINOR_VERSION};
+use nfs4::{
+    Access, AccessArgs, AccessRes, ArgOp, BindConnToSessionArgs, BindConnToSessionRes, ChangeId,
+    ChangeInfo, ChannelDirectionFromServer, ClientId, CloseArgs, CloseRes, CommitArgs, CommitRes,
+    CompoundArgs, CompoundRes, Cookie, CreateArgs, CreateHow, CreateRes, CreateType,
+    DirectoryEntry, DirectoryList, EnumSet, ExchangeIdArgs, ExchangeIdFlags, ExchangeIdRes,
+    FileAttribute, FileAttributeId, FileAttributes, FileHandle, FileType, GetAttrArgs, GetAttrRes,
+    GetFhRes, Lease, LinkArgs, LinkRes, LockStatusError, LockStatusResult, LookUpArgs, Mode,
+    OpenArgs, OpenAttrArgs, OpenClaim, OpenConfirmArgs, OpenConfirmRes, OpenDelegation,
+    OpenDowngradeArgs, OpenDowngradeRes, OpenFlag, OpenRes, OpenResult, OperationId, PutFhArgs,
+    ReadArgs, ReadDirArgs, ReadDirRes, ReadLinkRes, ReadRes, RemoveArgs, RemoveRes, RenameArgs,
+    RenameRes, RenewArgs, ResOp, SecInfoArgs, SecInfoNoNameArgs, SecInfoRes, SecInfoStyle,
+    SecurityInfo, ServerOwner, ServerScope, SetAttrArgs, SetAttrRes, SetAttrStatusResult,
+    SetClientIdArgs, SetClientIdConfirmArgs, SetClientIdRes, ShareAccess, StableHow, StateProtect,
+    StatusError, StatusResult, TestStateIdArgs, TestStateIdRes, ToId as _, Verifier, WriteArgs,
+    WriteRes,
+};
+use std::collections::BTreeMap;
+use sun_rpc::server::Caller;
+use sun_rpc::AuthFlavor;
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+fn status_result<T>(result: Result<T>) -> StatusResult<T> {
+    match result {
+        Ok(v) => StatusResult::Ok(v),
+        Err(e) => StatusResult::Err(e),
+    }
+}
+
+struct Compound<'a, FileSystemT> {
+    files: &'a mut FileSystemT,
+    state: &'a mut State,
+    faults: &'a mut BTreeMap<OperationId, Fault>,
+    io_limit: Option<u32>,
+    minor_version: u32,
+    /// For minor version 1, the client the session belongs to.
+    client_id: Option<ClientId>,
+    current: Option<FileHandle>,
+    saved: Option<FileHandle>,
+    caller: &'a Caller,
+    /// The security flavors of the objects which don't take the default ones
+    security: &'a mut BTreeMap<FileHandle, Vec<SecurityInfo>>,
+}
+
+/// The security flavors of the objects `Server::set_security` wasn't used for.
+fn default_security() -> Vec<SecurityInfo> {
+    vec![SecurityInfo::Sys, SecurityInfo::None]
+}
+
+fn allows(flavors: &[SecurityInfo], caller: &Caller) -> bool {
+    flavors.iter().any(|flavor| match flavor {
+        SecurityInfo::None => caller.flavor == AuthFlavor::None,
+        SecurityInfo::Sys => caller.flavor == AuthFlavor::Sys,
+        SecurityInfo::RpcSecGss { flavor_info } => {
+            caller.flavor == AuthFlavor::RpcSecGss
+                && caller.service.map(|service| service as u32)
+                    == Some(flavor_info.service.clone() as u32)
+        }
+    })
+}
+
+pub(crate) fn compound<FileSystemT: FileSystem>(
+    exported: &mut Exported<FileSystemT>,
+    args: CompoundArgs,
+    caller: &Caller,
+) -> CompoundRes {
+    let mut res = CompoundRes {
+        status: StatusResult::Ok(()),
+        tag: args.tag,
+        res_array: vec![],
+    };
+    if args.minor_version > MAX_MINOR_VERSION {
+        res.status = StatusResult::Err(StatusError::MinorVersMismatch);
+        return res;
+    }
+
+    exported.state.expire_leases();
+    let mut compound = Compound {
+        files: &mut exported.files,
+        state: &mut exported.state,
+        faults: &mut exported.faults,
+        io_limit: exported.io_limit,
+        minor_version: args.minor_version,
+        client_id: None,
+        current: None,
+        saved: None,
+        caller,
+        security: &mut exported.security,
+    };
+    let mut sequence = None;
+    let mut arg_array = args.arg_array.into_iter().enumerate().peekable();
+    while let Some((position, op)) = arg_array.next() {
+        let next = arg_array.peek().map(|(_, op)| op.to_id());
+        let (res_op, error) = match op {
+            ArgOp::Sequence(args) if compound.minor_version > 0 && position == 0 => {
+                match compound.state.sequence(&args) {
+                    Ok(Sequenced::Replay(reply)) => return reply,
+                    Ok(Sequenced::New(sequence_res, client_id)) => {
+                        compound.client_id = Some(client_id);
+                        sequence = Some(args);
+                        (ResOp::Sequence(StatusResult::Ok(sequence_res)), None)
+                    }
+                    Err(e) => (ResOp::Sequence(StatusResult::Err(e)), Some(e)),
+                }
+            }
+            op => {
+                // What is found in an object has the same security flavors
+                let inherits = matches!(op, ArgOp::LookUp(_) | ArgOp::Open(_) | ArgOp::Create(_));
+                let dir = compound.current.clone();
+                let (res_op, error) = compound.op(position, op, next);
+                if inherits && error.is_none() {
+                    compound.inherit_security(dir);
+                }
+                (res_op, error)
+            }
+        };
+        res.res_array.push(res_op);
+        if let Some(error) = error {
+            res.status = StatusResult::Err(error);
+            break;
+        }
+    }
+
+    if let Some(args) = sequence.filter(|s| s.cache_this) {
+        compound.state.cache_reply(&args, &res);
+    }
+    res
+}
+
+// Makes the result of an operation from the outcome of `check` and then the given result, also
+// returning the error which ends the COMPOUND if there is one. Without a result the operation
+// isn't supported.
+macro_rules! res {
+    ($variant:ident, $check:expr) => {{
+        let error = $check.err().unwrap_or(StatusError::NotSupported);
+        (ResOp::$variant(StatusResult::Err(error)), Some(error))
+    }};
+    ($variant:ident, $check:expr, $result:expr) => {{
+        let result = $check.and_then(|()| $result);
+        let error = result.as_ref().err().copied();
+        (ResOp::$variant(status_result(result)), error)
+    }};
+}
+
+impl<FileSystemT: FileSystem> Compound<'_, FileSystemT> {
+    /// Whether the operation can be done in this COMPOUND at all.
+    fn check(&self, position: usize, op: &ArgOp) -> Result<()> {
+        let minor_version_0 = matches!(
+            op,
+            ArgOp::OpenConfirm(_)
+                | ArgOp::Renew(_)
+                | ArgOp::SetClientId(_)
+                | ArgOp::SetClientIdConfirm(_)
+                | ArgOp::ReleaseLockOwner(_)
+        );
+        let without_session = matches!(
+            op,
+            ArgOp::ExchangeId(_)
+                | ArgOp::CreateSession(_)
+                | ArgOp::DestroySession(_)
+                | ArgOp::BindConnToSession(_)
+                | ArgOp::DestroyClientId(_)
+        );
+        let minor_version_1 = without_session
+            || matches!(
+                op,
+                ArgOp::Sequence(_)
+                    | ArgOp::ReclaimComplete(_)
+                    | ArgOp::SecInfoNoName(_)
+                    | ArgOp::TestStateId(_)
+                    | ArgOp::FreeStateid(_)
+            );
+
+        match self.minor_version {
+            0 if minor_version_1 => Err(StatusError::NotSupported),
+            0 => Ok(()),
+            _ if minor_version_0 => Err(StatusError::NotSupported),
+            _ if matches!(op, ArgOp::Sequence(_)) => Err(StatusError::SequencePos),
+            _ if position == 0 && !without_session => Err(StatusError::OpNotInSession),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails the operation if an error was injected for it.
+    fn injected(&mut self, op: &ArgOp) -> Result<()> {
+        let id = op.to_id();
+        let Some(fault) = self.faults.get_mut(&id) else {
+            return Ok(());
+        };
+        fault.times -= 1;
+        let error = fault.error;
+        if fault.times == 0 {
+            self.faults.remove(&id);
+        }
+        Err(error)
+    }
+
+    /// Fails operations which make an object the current one with `StatusError::WrongSec` if the
+    /// caller didn't authenticate with one of the security flavors the object takes. Objects are
+    /// put only to find something in them, or what flavors they take, aren't checked.
+    fn check_security(&mut self, op: &ArgOp, next: Option<OperationId>) -> Result<()> {
+        let sec_info_next = matches!(
+            next,
+            Some(OperationId::SecInfo | OperationId::SecInfoNoName)
+        );
+        let finding_next = matches!(
+            next,
+            Some(OperationId::LookUp | OperationId::LookUpP | OperationId::Open)
+        );
+        let put = matches!(
+            op,
+            ArgOp::PutFh(_) | ArgOp::PutRootFh | ArgOp::PutPubFh | ArgOp::RestoreFh
+        );
+        if sec_info_next || (put && finding_next) {
+            return Ok(());
+        }
+        let flavors = match op {
+            ArgOp::PutFh(args) => Some(self.security_of(&args.object)),
+            ArgOp::PutRootFh | ArgOp::PutPubFh => Some(self.security_of(&self.files.root())),
+            ArgOp::RestoreFh => self.saved.as_ref().map(|saved| self.security_of(saved)),
+            ArgOp::LookUp(args) => self.current_dir().ok().and_then(|dir| {
+                let object = self.files.look_up(&dir, &args.object_name).ok()?;
+                Some(self.entry_security(&dir, &object))
+            }),
+            ArgOp::LookUpP => self
+                .current_dir()
+                .and_then(|dir| self.files.parent(&dir))
+                .ok()
+                .map(|parent| self.security_of(&parent)),
+            // Whether the file is there or not, it takes what the directory takes
+            ArgOp::Open(_) => self.current.as_ref().map(|dir| self.security_of(dir)),
+            _ => None,
+        };
+        // Operations which fail anyway fail for that reason
+        match flavors {
+            Some(flavors) if !allows(&flavors, self.caller) => Err(StatusError::WrongSec),
+            _ => Ok(()),
+        }
+    }
+
+    fn security_of(&self, object: &FileHandle) -> Vec<SecurityInfo> {
+        self.security
+            .get(object)
+            .cloned()
+            .unwrap_or_else(default_security)
+    }
+
+    /// The security flavors of an entry of the given directory, which are those of the directory
+    /// unless it has its own.
+    fn entry_security(&self, dir: &FileHandle, object: &FileHandle) -> Vec<SecurityInfo> {
+        match self.security.get(object) {
+            Some(flavors) => flavors.clone(),
+            None => self.security_of(dir),
+        }
+    }
+
+    /// Gives the current object the security flavors of the directory it was found in, unless it
+    /// has its own.
+    fn inherit_security(&mut self, dir: Option<FileHandle>) {
+        let (Some(dir), Some(current)) = (dir, &self.current) else {
+            return;
+        };
+        if let Some(flavors) = self.security.get(&dir).cloned() {
+            self.security.entry(current.clone()).or_insert(flavors);
+        }
+    }
+
+    fn op(
+        &mut self,
+        position: usize,
+        op: ArgOp,
+        next: Option<OperationId>,
+    ) -> (ResOp, Option<StatusError>) {
+        let check = self
+            .check(position, &op)
+            .and_then(|()| self.injected(&op))
+            .and_then(|()| self.check_security(&op, next));
+        match op {
+            ArgOp::Access(args) => res!(Access, check, self.access(args)),
+            ArgOp::Close(args) => res!(Close, check, self.close(args)),
+            ArgOp::Commit(args) => res!(Commit, check, self.commit(args)),
+            ArgOp::Create(args) => res!(Create, check, self.create(args)),
+            ArgOp::GetAttr(args) => res!(GetAttr, check, self.get_attr(args)),
+            ArgOp::GetFh => res!(GetFh, check, self.get_fh()),
+            ArgOp::Link(args) => {
+                let result = check.and_then(|()| self.link(args));
+                let error = result.as_ref().err().copied();
+                let res = match result {
+                    Ok(res) => LockStatusResult::Ok(res),
+                    Err(error) => LockStatusResult::Err(LockStatusError {
+                        error,
+                        denied: None,
+                    }),
+                };
+                (ResOp::Link(res), error)
+            }
+            ArgOp::LookUp(args) => res!(LookUp, check, self.look_up(args)),
+            ArgOp::LookUpP => res!(LookUpP, check, self.look_up_p()),
+            ArgOp::NVerify(args) => res!(NVerify, check, {
+                match self.same_attrs(&args.object_attributes)? {
+                    true => Err(StatusError::Same),
+                    false => Ok(()),
+                }
+            }),
+            ArgOp::Open(args) => res!(Open, check, self.open(args)),
+            ArgOp::OpenConfirm(args) => res!(OpenConfirm, check, self.open_confirm(args)),
+            ArgOp::OpenDowngrade(args) => res!(OpenDowngrade, check, self.open_downgrade(args)),
+            ArgOp::PutFh(args) => res!(PutFh, check, self.put_fh(args)),
+            ArgOp::PutPubFh => res!(PutPubFh, check, self.put_root_fh()),
+            ArgOp::PutRootFh => res!(PutRootFh, check, self.put_root_fh()),
+            ArgOp::Read(args) => res!(Read, check, self.read(args)),
+            ArgOp::ReadDir(args) => res!(ReadDir, check, self.read_dir(args)),
+            ArgOp::ReadLink => res!(ReadLink, check, self.read_link()),
+            ArgOp::Remove(args) => res!(Remove, check, self.remove(args)),
+            ArgOp::Rename(args) => res!(Rename, check, self.rename(args)),
+            ArgOp::Renew(args) => res!(Renew, check, self.renew(args)),
+            ArgOp::RestoreFh => res!(RestoreFh, check, self.restore_fh()),
+            ArgOp::SaveFh => res!(SaveFh, check, self.save_fh()),
+            ArgOp::SecInfo(args) => res!(SecInfo, check, self.sec_info(args)),
+            ArgOp::SetAttr(args) => {
+                let result = check.and_then(|()| self.set_attr(args));
+                let error = result.as_ref().err().copied();
+                let res = SetAttrStatusResult {
+                    status: status_result(result.clone().map(|_| ())),
+                    res: SetAttrRes {
+                        attr_set: result.unwrap_or_default(),
+                    },
+                };
+                (ResOp::SetAttr(res), error)
+            }
+            ArgOp::SetClientId(args) => res!(SetClientId, check, self.set_client_id(args)),
+            ArgOp::SetClientIdConfirm(args) => {
+                res!(SetClientIdConfirm, check, self.set_client_id_confirm(args))
+            }
+            ArgOp::Verify(args) => res!(Verify, check, {
+                match self.same_attrs(&args.object_attributes)? {
+                    true => Ok(()),
+                    false => Err(StatusError::NotSame),
+                }
+            }),
+            ArgOp::Write(args) => res!(Write, check, self.write(args)),
+            // We have no locks, so the owner can't be holding any
+            ArgOp::ReleaseLockOwner(_) => res!(ReleaseLockOwner, check, Ok(())),
+            ArgOp::BindConnToSession(args) => {
+                res!(BindConnToSession, check, self.bind_conn_to_session(args))
+            }
+            ArgOp::ExchangeId(args) => res!(ExchangeId, check, self.exchange_id(args)),
+            ArgOp::CreateSession(args) => {
+                res!(CreateSession, check, self.state.create_session(&args))
+            }
+            ArgOp::DestroySession(args) => {
+                res!(
+                    DestroySession,
+                    check,
+                    self.state.destroy_session(args.session_id)
+                )
+            }
+            ArgOp::FreeStateid(args) => {
+                res!(FreeStateid, check, self.state.free_state_id(&args.state_id))
+            }
+            ArgOp::SecInfoNoName(args) => res!(SecInfoNoName, check, self.sec_info_no_name(args)),
+            ArgOp::Sequence(_) => res!(Sequence, check, Err(StatusError::SequencePos)),
+            ArgOp::TestStateId(args) => res!(TestStateId, check, self.test_state_id(args)),
+            ArgOp::DestroyClientId(args) => {
+                res!(
+                    DestroyClientId,
+                    check,
+                    self.state.destroy_client_id(args.client_id)
+                )
+            }
+            ArgOp::ReclaimComplete(_) => {
+                res!(ReclaimComplete, check, self.reclaim_complete())
+            }
+            ArgOp::Lock(_) => {
+                let error = check.err().unwrap_or(StatusError::NotSupported);
+                let res = LockStatusResult::Err(LockStatusError {
+                    error,
+                    denied: None,
+                });
+                (ResOp::Lock(res), Some(error))
+            }
+            ArgOp::DelegPurge(_) => res!(DelegPurge, check),
+            ArgOp::DelegReturn(_) => res!(DelegReturn, check),
+            ArgOp::LockT(_) => res!(LockT, check),
+            ArgOp::LockU(_) => res!(LockU, check),
+            ArgOp::OpenAttr(args) => res!(OpenAttr, check, self.open_attr(args)),
+            ArgOp::BackchannelCtl(_) => res!(BackchannelCtl, check),
+            ArgOp::GetDirDelegation(_) => res!(GetDirDelegation, check),
+            ArgOp::GetDeviceInfo(_) => res!(GetDeviceInfo, check),
+            ArgOp::GetDeviceList(_) => res!(GetDeviceList, check),
+            ArgOp::LayoutCommit(_) => res!(LayoutCommit, check),
+            ArgOp::LayoutGet(_) => res!(LayoutGet, check),
+            ArgOp::LayoutReturn(_) => res!(LayoutReturn, check),
+            ArgOp::SetSsv(_) => res!(SetSsv, check),
+            ArgOp::WantDelegation(_) => res!(WantDelegation, check),
+            ArgOp::Copy(_) => res!(Copy, check),
+            ArgOp::CopyNotify(_) => res!(CopyNotify, check),
+            ArgOp::LayoutError(_) => res!(LayoutError, check),
+            ArgOp::LayoutStats(_) => res!(LayoutStats, check),
+            ArgOp::OffloadCancel(_) => res!(OffloadCancel, check),
+            ArgOp::OffloadStatus(_) => res!(OffloadStatus, check),
+        }
+    }
+
+    fn current(&self) -> Result<&FileHandle> {
+        self.current.as_ref().ok_or(StatusError::NoFileHandle)
+    }
+
+    fn attribute(&mut self, handle: &FileHandle, id: FileAttributeId) -> Result<FileAttribute> {
+        let request = [id].into_iter().collect();
+        let mut attrs = self.files.get_attributes(handle, &request)?;
+        attrs.remove(id).ok_or(StatusError::ServerFault)
+    }
+
+    fn file_type(&mut self, handle: &FileHandle) -> Result<FileType> {
+        match self.attribute(handle, FileAttributeId::Type)? {
+            FileAttribute::Type(file_type) => Ok(file_type),
+            _ => Err(StatusError::ServerFault),
+        }
+    }
+
+    fn change(&mut self, handle: &FileHandle) -> Result<ChangeId> {
+        match self.attribute(handle, FileAttributeId::Change)? {
+            FileAttribute::Change(change) => Ok(ChangeId(change.0)),
+            _ => Err(StatusError::ServerFault),
+        }
+    }
+
+    fn change_info(&mut self, before: ChangeId, dir: &FileHandle) -> Result<ChangeInfo> {
+        Ok(ChangeInfo {
+            atomic: false,
+            before,
+            after: self.change(dir)?,
+        })
+    }
+
+    fn dir(&mut self, handle: &FileHandle) -> Result<FileHandle> {
+        match self.file_type(handle)? {
+            FileType::Directory | FileType::AttrDir => Ok(handle.clone()),
+            FileType::Link => Err(StatusError::Symlink),
+            _ => Err(StatusError::NotDir),
+        }
+    }
+
+    fn current_dir(&mut self) -> Result<FileHandle> {
+        let current = self.current()?.clone();
+        self.dir(&current)
+    }
+
+    fn put_fh(&mut self, args: PutFhArgs) -> Result<()> {
+        self.file_type(&args.object)?;
+        self.current = Some(args.object);
+        Ok(())
+    }
+
+    fn put_root_fh(&mut self) -> Result<()> {
+        self.current = Some(self.files.root());
+        Ok(())
+    }
+
+    fn get_fh(&mut self) -> Result<GetFhRes> {
+        Ok(GetFhRes {
+            object: self.current()?.clone(),
+        })
+    }
+
+    fn save_fh(&mut self) -> Result<()> {
+        self.saved = Some(self.current()?.clone());
+        Ok(())
+    }
+
+    fn restore_fh(&mut self) -> Result<()> {
+        self.current = Some(self.saved.clone().ok_or(StatusError::RestoreFh)?);
+        Ok(())
+    }
+
+    fn look_up(&mut self, args: LookUpArgs) -> Result<()> {
+        let dir = self.current_dir()?;
+        check_name(&args.object_name)?;
+        self.current = Some(self.files.look_up(&dir, &args.object_name)?);
+        Ok(())
+    }
+
+    fn look_up_p(&mut self) -> Result<()> {
+        let dir = self.current_dir()?;
+        self.current = Some(self.files.parent(&dir)?);
+        Ok(())
+    }
+
+    fn open_attr(&mut self, args: OpenAttrArgs) -> Result<()> {
+        let current = self.current()?.clone();
+        // Named attributes don't have named attributes of their own
+        if matches!(
+            self.file_type(&current)?,
+            FileType::AttrDir | FileType::NamedAttr
+        ) {
+            return Err(StatusError::Inval);
+        }
+        self.current = Some(self.files.named_attr_dir(&current, args.create_dir)?);
+        Ok(())
+    }
+
+    fn get_attr(&mut self, args: GetAttrArgs) -> Result<GetAttrRes> {
+        let current = self.current()?.clone();
+        let mut object_attributes = self.files.get_attributes(&current, &args.attr_request)?;
+        // Which is the file system's unless the server was given another
+        if let Some(lease_time) = self.state.lease_time() {
+            if object_attributes.get(FileAttributeId::LeaseTime).is_some() {
+                let lease_time = Lease(lease_time.as_secs() as u32);
+                object_attributes.insert(FileAttribute::LeaseTime(lease_time));
+            }
+        }
+        Ok(GetAttrRes { object_attributes })
+    }
+
+    fn same_attrs(&mut self, attrs: &FileAttributes) -> Result<bool> {
+        let request: EnumSet<_> = attrs.iter().map(|a| a.to_id()).collect();
+        let supported = self.files.supported_attrs();
+        if request.iter().any(|id| !supported.contains(id)) || attrs.unknown().next().is_some() {
+            return Err(StatusError::AttrNotSupported);
+        }
+        let current = self.current()?.clone();
+        Ok(&self.files.get_attributes(&current, &request)? == attrs)
+    }
+
+    /// What can be done with the object going by its mode, without regard to who is asking.
+    fn access(&mut self, args: AccessArgs) -> Result<AccessRes> {
+        let current = self.current()?.clone();
+        let FileAttribute::Mode(Mode(mode)) = self.attribute(&current, FileAttributeId::Mode)?
+        else {
+            return Err(StatusError::ServerFault);
+        };
+        let mut allowed = Access::empty();
+        if mode & 0o444 != 0 {
+            allowed |= Access::READ;
+        }
+        if mode & 0o222 != 0 {
+            allowed |= Access::MODIFY | Access::EXTEND | Access::DELETE;
+        }
+        if mode & 0o111 != 0 {
+            allowed |= Access::LOOKUP | Access::EXECUTE;
+        }
+        let supported = args.access & Access::all();
+        Ok(AccessRes {
+            supported,
+            access: supported & allowed,
+        })
+    }
+
+    fn open(&mut self, args: OpenArgs) -> Result<OpenRes> {
+        let client_id = match self.client_id {
+            Some(client_id) => client_id,
+            None => {
+                self.state.check_client(args.owner.client_id)?;
+                args.owner.client_id
+            }
+        };
+        if (args.share_access & ShareAccess::BOTH).is_empty() {
+            return Err(StatusError::Inval);
+        }
+        // Until the grace period is over, what was open before we restarted can only be reclaimed
+        let reclaim = matches!(args.claim, OpenClaim::Previous { .. });
+        if reclaim {
+            self.state.check_reclaim(client_id)?;
+        } else if self.state.in_grace() {
+            return Err(StatusError::Grace);
+        }
+
+        let (handle, change_info, attribute_set) = match (args.claim, args.open_how) {
+            (OpenClaim::Null { file }, open_how) => {
+                let dir = self.current_dir()?;
+                check_name(&file)?;
+                let before = self.change(&dir)?;
+                let (handle, attribute_set) = match open_how {
+                    OpenFlag::OpenNoCreate => {
+                        (self.files.look_up(&dir, &file)?, EnumSet::default())
+                    }
+                    OpenFlag::OpenCreate(how) => {
+                        if let CreateHow::Unchecked { create_attrs }
+                        | CreateHow::Guarded { create_attrs }
+                        | CreateHow::ExclusiveBoth { create_attrs, .. } = &how
+                        {
+                            check_settable(create_attrs)?;
+                        }
+                        self.files.create_file(&dir, &file, how)?
+                    }
+                };
+                (handle, self.change_info(before, &dir)?, attribute_set)
+            }
+            (OpenClaim::Fh | OpenClaim::Previous { .. }, OpenFlag::OpenNoCreate)
+                if self.minor_version > 0 || reclaim =>
+            {
+                let handle = self.current()?.clone();
+                let dir = match self.files.parent(&handle) {
+                    Err(StatusError::NoEnt) => handle.clone(),
+                    dir => dir?,
+                };
+                let before = self.change(&dir)?;
+                let change_info = self.change_info(before, &dir)?;
+                (handle, change_info, EnumSet::default())
+            }
+            (OpenClaim::Fh | OpenClaim::Previous { .. }, _) => return Err(StatusError::Inval),
+            _ => return Err(StatusError::NotSupported),
+        };
+
+        match self.file_type(&handle)? {
+            FileType::Regular | FileType::NamedAttr => {}
+            FileType::Directory => return Err(StatusError::Isdir),
+            FileType::Link => return Err(StatusError::Symlink),
+            _ => return Err(StatusError::Inval),
+        }
+        // Make sure we can open it the way the client wants to
+        self.files.check_open(&handle, args.share_access)?;
+
+        let open = self.state.open(
+            &handle,
+            client_id,
+            &args.owner.opaque,
+            args.share_access,
+            args.share_deny,
+            self.minor_version == 0,
+        )?;
+        let result_flags = if open.confirmed {
+            OpenResult::empty()
+        } else {
+            OpenResult::CONFIRM
+        };
+        let state_id = open.state_id;
+        self.current = Some(handle);
+        Ok(OpenRes {
+            state_id,
+            change_info,
+            result_flags,
+            attribute_set,
+            delegation: OpenDelegation::None,
+        })
+    }
+
+    fn reclaim_complete(&mut self) -> Result<()> {
+        let client_id = self.client_id.ok_or(StatusError::OpNotInSession)?;
+        self.state.reclaim_complete(client_id)
+    }
+
+    fn open_confirm(&mut self, args: OpenConfirmArgs) -> Result<OpenConfirmRes> {
+        let handle = self.current()?.clone();
+        Ok(OpenConfirmRes {
+            open_state_id: self.state.open_confirm(&handle, &args.open_state_id)?,
+        })
+    }
+
+    fn open_downgrade(&mut self, args: OpenDowngradeArgs) -> Result<OpenDowngradeRes> {
+        let handle = self.current()?.clone();
+        Ok(OpenDowngradeRes {
+            open_state_id: self.state.open_downgrade(
+                &handle,
+                &args.open_state_id,
+                args.share_access,
+                args.share_deny,
+            )?,
+        })
+    }
+
+    fn close(&mut self, args: CloseArgs) -> Result<CloseRes> {
+        let handle = self.current()?.clone();
+        Ok(CloseRes {
+            open_state_id: self.state.close(&handle, &args.open_stateid)?,
+        })
+    }
+
+    fn regular_file(&mut self) -> Result<FileHandle> {
+        let handle = self.current()?.clone();
+        match self.file_type(&handle)? {
+            FileType::Regular | FileType::NamedAttr => Ok(handle),
+            FileType::Directory => Err(StatusError::Isdir),
+            FileType::Link => Err(StatusError::Symlink),
+            _ => Err(StatusError::Inval),
+        }
+    }
+
+    fn read(&mut self, args: ReadArgs) -> Result<ReadRes> {
+        let handle = self.regular_file()?;
+        // Files opened only for writing can be read too, like most servers allow
+        self.state
+            .check_state_id(&handle, &args.state_id, ShareAccess::empty())?;
+        let count = u64::from(args.count).min(MAX_IO_SIZE) as u32;
+        let count = self.io_limit.map_or(count, |limit| count.min(limit));
+        self.files.read(&handle, args.offset, count)
+    }
+
+    fn write(&mut self, args: WriteArgs) -> Result<WriteRes> {
+        let handle = self.regular_file()?;
+        self.state
+            .check_state_id(&handle, &args.state_id, ShareAccess::WRITE)?;
+
+        let committed = match args.stable {
+            StableHow::Unstable => StableHow::Unstable,
+            _ => StableHow::FileSync,
+        };
+        let sync = committed == StableHow::FileSync;
+        let len = self
+            .io_limit
+            .map_or(args.data.len(), |limit| args.data.len().min(limit as usize));
+        self.files
+            .write(&handle, args.offset, &args.data[..len], sync)?;
+        Ok(WriteRes {
+            count: len as u32,
+            committed,
+            write_veritifer: self.state.write_verifier(),
+        })
+    }
+
+    fn commit(&mut self, _args: CommitArgs) -> Result<CommitRes> {
+        let handle = self.regular_file()?;
+        self.files.commit(&handle)?;
+        Ok(CommitRes {
+            write_verifier: self.state.write_verifier(),
+        })
+    }
+
+    fn read_dir(&mut self, args: ReadDirArgs) -> Result<ReadDirRes> {
+        let dir = self.current_dir()?;
+        let names = self.files.read_dir(&dir)?;
+
+        // The cookie of each entry is where it is in the sorted listing, after the 1 and 2 which
+        // are kept for "." and ".."
+        let mut entries = vec![];
+        let mut size = 16;
+        let mut eof = true;
+        let start = args.cookie.0.saturating_sub(2) as usize;
+        for (index, name) in names.into_iter().enumerate().skip(start) {
+            // It may have gone away in the meantime
+            let Ok(handle) = self.files.look_up(&dir, &name) else {
+                continue;
+            };
+            let Ok(attrs) = self.files.get_attributes(&handle, &args.attr_request) else {
+                continue;
+            };
+            let entry = DirectoryEntry {
+                cookie: Cookie(index as u64 + 3),
+                name,
+                attrs,
+            };
+            let entry_size = xdr_extras::to_bytes(&entry)
+                .map_err(|_| StatusError::ServerFault)?
+                .len()
+                + 4;
+            if size + entry_size > args.max_count as usize {
+                eof = false;
+                break;
+            }
+            size += entry_size;
+            entries.push(entry);
+        }
+        if entries.is_empty() && !eof {
+            return Err(StatusError::TooSmall);
+        }
+
+        Ok(ReadDirRes {
+            cookie_verifier: Verifier(0),
+            reply: DirectoryList { entries, eof },
+        })
+    }
+
+    fn read_link(&mut self) -> Result<ReadLinkRes> {
+        let current = self.current()?.clone();
+        if self.file_type(&current)? != FileType::Link {
+            return Err(StatusError::Inval);
+        }
+        Ok(ReadLinkRes {
+            link: self.files.read_link(&current)?,
+        })
+    }
+
+    fn set_attr(&mut self, args: SetAttrArgs) -> Result<EnumSet<FileAttributeId>> {
+        let handle = self.current()?.clone();
+        check_settable(&args.object_attributes)?;
+        if args.object_attributes.get(FileAttributeId::Size).is_some() {
+            self.regular_file()?;
+            self.state
+                .check_state_id(&handle, &args.state_id, ShareAccess::WRITE)?;
+        }
+        self.files.set_attributes(&handle, &args.object_attributes)
+    }
+
+    fn create(&mut self, args: CreateArgs) -> Result<CreateRes> {
+        let dir = self.current_dir()?;
+        check_name(&args.object_name)?;
+        check_settable(&args.create_attrs)?;
+        let before = self.change(&dir)?;
+        let handle = match args.object_type {
+            CreateType::Directory => self.files.create_dir(&dir, &args.object_name),
+            CreateType::Link(target) => self.files.create_symlink(&dir, &args.object_name, &target),
+            _ => return Err(StatusError::BadType),
+        }?;
+
+        let attribute_set = self.files.set_attributes(&handle, &args.create_attrs)?;
+        let change_info = self.change_info(before, &dir)?;
+        self.current = Some(handle);
+        Ok(CreateRes {
+            change_info,
+            attribute_set,
+        })
+    }
+
+    fn link(&mut self, args: LinkArgs) -> Result<LinkRes> {
+        let source = self.saved.clone().ok_or(StatusError::NoFileHandle)?;
+        let dir = self.current_dir()?;
+        check_name(&args.new_name)?;
+        let before = self.change(&dir)?;
+        self.files.link(&source, &dir, &args.new_name)?;
+        Ok(LinkRes {
+            change_info: self.change_info(before, &dir)?,
+        })
+    }
+
+    fn remove(&mut self, args: RemoveArgs) -> Result<RemoveRes> {
+        let dir = self.current_dir()?;
+        check_name(&args.target)?;
+        let before = self.change(&dir)?;
+        self.files.remove(&dir, &args.target)?;
+        Ok(RemoveRes {
+            change_info: self.change_info(before, &dir)?,
+        })
+    }
+
+    fn rename(&mut self, args: RenameArgs) -> Result<RenameRes> {
+        let saved = self.saved.clone().ok_or(StatusError::NoFileHandle)?;
+        let source_dir = self.dir(&saved)?;
+        let target_dir = self.current_dir()?;
+        check_name(&args.old_name)?;
+        check_name(&args.new_name)?;
+        let source_before = self.change(&source_dir)?;
+        let target_before = self.change(&target_dir)?;
+
+        self.files
+            .rename(&source_dir, &args.old_name, &target_dir, &args.new_name)?;
+        Ok(RenameRes {
+            source_change_info: self.change_info(source_before, &source_dir)?,
+            target_change_info: self.change_info(target_before, &target_dir)?,
+        })
+    }
+
+    /// The security flavors the entry of the current directory takes, which are those of the
+    /// directory unless it has its own. SECINFO leaves no current file handle behind.
+    fn sec_info(&mut self, args: SecInfoArgs) -> Result<SecInfoRes> {
+        let dir = self.current_dir()?;
+        check_name(&args.name)?;
+        let object = self.files.look_up(&dir, &args.name)?;
+        let body = self.entry_security(&dir, &object);
+        self.current = None;
+        Ok(SecInfoRes { body })
+    }
+
+    fn sec_info_no_name(&mut self, args: SecInfoNoNameArgs) -> Result<SecInfoRes> {
+        let object = match args.style {
+            SecInfoStyle::CurrentFh => self.current()?.clone(),
+            SecInfoStyle::Parent => {
+                let dir = self.current_dir()?;
+                self.files.parent(&dir)?
+            }
+        };
+        self.current = None;
+        Ok(SecInfoRes {
+            body: self.security_of(&object),
+        })
+    }
+
+    fn set_client_id(&mut self, args: SetClientIdArgs) -> Result<SetClientIdRes> {
+        let (client_id, confirm) = self.state.set_client_id(&args.client);
+        Ok(SetClientIdRes { client_id, confirm })
+    }
+
+    fn set_client_id_confirm(&mut self, args: SetClientIdConfirmArgs) -> Result<()> {
+        self.state
+            .set_client_id_confirm(args.client_id, args.confirm)
+    }
+
+    fn renew(&mut self, args: RenewArgs) -> Result<()> {
+        self.state.renew(args.client_id)
+    }
+
+    fn exchange_id(&mut self, args: ExchangeIdArgs) -> Result<ExchangeIdRes> {
+        let (client_id, sequence_id, confirmed) = self.state.exchange_id(&args.client_owner);
+        let mut flags = ExchangeIdFlags::USE_NON_PNFS;
+        if confirmed {
+            flags |= ExchangeIdFlags::CONFIRMED_R;
+        }
+        let server_owner = self.state.server_owner();
+        Ok(ExchangeIdRes {
+            client_id,
+            sequence_id,
+            flags,
+            state_protect: StateProtect::None,
+            server_scope: ServerScope(server_owner.clone()),
+            server_owner: ServerOwner {
+                minor_id: 0,
+                major_id: server_owner,
+            },
+            server_impl_id: None,
+        })
+    }
+
+    fn bind_conn_to_session(
+        &mut self,
+        args: BindConnToSessionArgs,
+    ) -> Result<BindConnToSessionRes> {
+        self.state.check_session(args.session_id)?;
+        // Only the fore channel, since there is no backchannel
+        Ok(BindConnToSessionRes {
+            session_id: args.session_id,
+            direction: ChannelDirectionFromServer::Fore,
+            use_connection_in_rdma_mode: false,
+        })
+    }
+
+    fn test_state_id(&mut self, args: TestStateIdArgs) -> Result<TestStateIdRes> {
+        Ok(TestStateIdRes {
+            status_codes: args
+                .state_ids
+                .iter()
+                .map(|s| status_result(self.state.test_state_id(s)))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+use super::LocalFs;
+
+#[cfg(test)]
+const SYS_CALLER: Caller = Caller {
+    flavor: AuthFlavor::Sys,
+    service: None,
+};
+
+#[cfg(test)]
+fn send(exported: &mut Exported<LocalFs>, minor_version: u32, arg_array: Vec<ArgOp>) -> Vec<ResOp> {
+    let res = compound(
+        exported,
+        CompoundArgs {
+            tag: String::new(),
+            minor_version,
+            arg_array,
+        },
+        &SYS_CALLER,
+    );
+    assert_eq!(res.status, StatusResult::Ok(()), "{:?}", res.res_array);
+    res.res_array
+}
+
+#[test]
+fn minor_version_zero_open_write_read() {
+    use nfs4::{
+        Bytes, CallbackClient, ClientOwner, CreateHow, NetAddr, SequenceId, ShareDeny, StateId,
+        StateOwner,
+    };
+
+    let root = tempdir::TempDir::new("nfs4_server").unwrap();
+    let mut exported = Exported::new(LocalFs::new(root.path().to_owned()).unwrap(), 1);
+
+    let res = send(
+        &mut exported,
+        0,
+        vec![ArgOp::SetClientId(SetClientIdArgs {
+            client: ClientOwner {
+                verifier: Verifier(1),
+                owner_id: b"test".to_vec(),
+            },
+            callback: CallbackClient {
+                program: 0,
+                location: NetAddr {
+                    netid: "tcp".into(),
+                    addr: "127.0.0.1.0.0".into(),
+                },
+            },
+            callback_ident: 0,
+        })],
+    );
+    let [ResOp::SetClientId(StatusResult::Ok(SetClientIdRes { client_id, confirm }))] = &res[..]
+    else {
+        panic!("{res:?}")
+    };
+    let client_id = *client_id;
+    send(
+        &mut exported,
+        0,
+        vec![ArgOp::SetClientIdConfirm(SetClientIdConfirmArgs {
+            client_id,
+            confirm: confirm.clone(),
+        })],
+    );
+
+    let res = send(
+        &mut exported,
+        0,
+        vec![
+            ArgOp::PutRootFh,
+            ArgOp::Open(OpenArgs {
+                sequence_id: SequenceId(0),
+                share_access: ShareAccess::BOTH,
+                share_deny: ShareDeny::NONE,
+                owner: StateOwner {
+                    client_id,
+                    opaque: b"owner".to_vec(),
+                },
+                open_how: OpenFlag::OpenCreate(CreateHow::Unchecked {
+                    create_attrs: FileAttributes::default(),
+                }),
+                claim: OpenClaim::Null {
+                    file: "a_file".into(),
+                },
+            }),
+            ArgOp::GetFh,
+        ],
+    );
+    let [_, ResOp::Open(StatusResult::Ok(open)), ResOp::GetFh(StatusResult::Ok(fh))] = &res[..]
+    else {
+        panic!("{res:?}")
+    };
+    assert!(open.result_flags.contains(OpenResult::CONFIRM));
+    let handle = fh.object.clone();
+
+    // Until the open is confirmed, its state ID can't be used
+    let write = |state_id: StateId| WriteArgs {
+        state_id,
+        offset: 0,
+        stable: StableHow::FileSync,
+        data: Bytes::from_static(b"hello"),
+    };
+    let res = compound(
+        &mut exported,
+        CompoundArgs {
+            tag: String::new(),
+            minor_version: 0,
+            arg_array: vec![
+                ArgOp::PutFh(PutFhArgs {
+                    object: handle.clone(),
+                }),
+                ArgOp::Write(write(open.state_id)),
+            ],
+        },
+        &SYS_CALLER,
+    );
+    assert_eq!(res.status, StatusResult::Err(StatusError::BadStateId));
+
+    let res = send(
+        &mut exported,
+        0,
+        vec![
+            ArgOp::PutFh(PutFhArgs {
+                object: handle.clone(),
+            }),
+            ArgOp::OpenConfirm(OpenConfirmArgs {
+                open_state_id: open.state_id,
+                sequence_id: SequenceId(1),
+            }),
+        ],
+    );
+    let [_, ResOp::OpenConfirm(StatusResult::Ok(confirmed))] = &res[..] else {
+        panic!("{res:?}")
+    };
+    let state_id = confirmed.open_state_id;
+
+    let res = send(
+        &mut exported,
+        0,
+        vec![
+            ArgOp::PutFh(PutFhArgs {
+                object: handle.clone(),
+            }),
+            ArgOp::Write(write(state_id)),
+            ArgOp::Read(ReadArgs {
+                state_id,
+                offset: 0,
+                count: 100,
+            }),
+            ArgOp::Close(CloseArgs {
+                sequence_id: SequenceId(2),
+                open_stateid: state_id,
+            }),
+        ],
+    );
+    let [_, _, ResOp::Read(StatusResult::Ok(read)), _] = &res[..] else {
+        panic!("{res:?}")
+    };
+    assert_eq!(&read.data[..], b"hello");
+    assert!(read.eof);
+    assert_eq!(std::fs::read(root.path().join("a_file")).unwrap(), b"hello");
+}
+
+#[test]
+fn sessions_not_in_minor_version_zero() {
+    let root = tempdir::TempDir::new("nfs4_server").unwrap();
+    let mut exported = Exported::new(LocalFs::new(root.path().to_owned()).unwrap(), 1);
+
+    let res = compound(
+        &mut exported,
+        CompoundArgs {
+            tag: String::new(),
+            minor_version: 1,
+            arg_array: vec![ArgOp::PutRootFh],
+        },
+        &SYS_CALLER,
+    );
+    assert_eq!(res.status, StatusResult::Err(StatusError::OpNotInSession));
+
+    let res = compound(
+        &mut exported,
+        CompoundArgs {
+            tag: String::new(),
+            minor_version: 2,
+            arg_array: vec![ArgOp::PutRootFh],
+        },
+        &SYS_CALLER,
+    );
+    assert_eq!(
+        res.status,
+        StatusResult::Err(StatusError::MinorVersMismatch)
+    );
+}
+
+#[test]
+fn wrong_security_flavor() {
+    let root = tempdir::TempDir::new("nfs4_server").unwrap();
+    std::fs::create_dir(root.path().join("a_dir")).unwrap();
+    let mut exported = Exported::new(LocalFs::new(root.path().to_owned()).unwrap(), 1);
+    let dir = exported
+        .files
+        .look_up(&exported.files.root(), "a_dir")
+        .unwrap();
+    exported.security.insert(dir, vec![SecurityInfo::None]);
+    let args = |arg_array| CompoundArgs {
+        tag: String::new(),
+        minor_version: 0,
+        arg_array,
+    };
+    let look_up = || {
+        ArgOp::LookUp(nfs4::LookUpArgs {
+            object_name: "a_dir".into(),
+        })
+    };
+
+    // The root is only put to look something up in it, it is what is looked up which is checked
+    let res = compound(
+        &mut exported,
+        args(vec![ArgOp::PutRootFh, look_up()]),
+        &SYS_CALLER,
+    );
+    assert_eq!(res.status, StatusResult::Err(StatusError::WrongSec));
+    assert_eq!(res.res_array.len(), 2);
+
+    let sec_info = ArgOp::SecInfo(SecInfoArgs {
+        name: "a_dir".into(),
+    });
+    let res = compound(
+        &mut exported,
+        args(vec![ArgOp::PutRootFh, sec_info]),
+        &SYS_CALLER,
+    );
+    let [_, ResOp::SecInfo(StatusResult::Ok(sec_info))] = &res.res_array[..] else {
+        panic!("{res:?}")
+    };
+    assert_eq!(sec_info.body, [SecurityInfo::None]);
+
+    let caller = Caller {
+        flavor: AuthFlavor::None,
+        service: None,
+    };
+    let res = compound(
+        &mut exported,
+        args(vec![ArgOp::PutRootFh, look_up(), ArgOp::GetFh]),
+        &caller,
+    );
+    assert_eq!(res.status, StatusResult::Ok(()), "{:?}", res.res_array);
+}
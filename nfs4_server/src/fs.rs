@@ -0,0 +1,173 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! What the server exports. The operations of a COMPOUND are carried out on a `FileSystem`, which
+//! names the objects in it with file handles of its own making.
+
+use nfs4::{
+    CreateHow, EnumSet, FileAttributeId, FileAttributes, FileHandle, Lease, ReadRes, ShareAccess,
+    StatusError, ToId as _,
+};
+
+/// The most READ returns and WRITE takes at once.
+pub(crate) const MAX_IO_SIZE: u64 = 1024 * 1024;
+
+/// How long clients may go without talking to us before we would forget about them.
+pub(crate) const LEASE_TIME: Lease = Lease(90);
+
+pub(crate) const MAX_NAME: u32 = 255;
+
+/// A hierarchy of files the server can export.
+///
+/// Names passed in have been through `check_name` already, and the directories passed in are
+/// directories. Objects which are gone give `StatusError::Stale`. The attributes the server
+/// itself relies on, `Type`, `Change` and `Mode`, have to be supported.
+pub trait FileSystem {
+    fn root(&self) -> FileHandle;
+
+    fn supported_attrs(&self) -> EnumSet<FileAttributeId>;
+
+    /// The requested attributes of the object, leaving out the ones we don't have.
+    fn get_attributes(
+        &mut self,
+        handle: &FileHandle,
+        request: &EnumSet<FileAttributeId>,
+    ) -> Result<FileAttributes, StatusError>;
+
+    /// Changes the given attributes of the object, returning which ones were set. Nothing is
+    /// changed if any of them is one that can't be set.
+    fn set_attributes(
+        &mut self,
+        handle: &FileHandle,
+        attrs: &FileAttributes,
+    ) -> Result<EnumSet<FileAttributeId>, StatusError>;
+
+    fn look_up(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle, StatusError>;
+
+    /// The directory the given one is in, or `StatusError::NoEnt` for the root. The object a named
+    /// attribute directory belongs to counts as the directory it is in.
+    fn parent(&mut self, dir: &FileHandle) -> Result<FileHandle, StatusError>;
+
+    /// The names of the entries of the directory, sorted.
+    fn read_dir(&mut self, dir: &FileHandle) -> Result<Vec<String>, StatusError>;
+
+    fn read_link(&mut self, handle: &FileHandle) -> Result<String, StatusError>;
+
+    /// Makes sure the regular file can be opened with the given access.
+    fn check_open(&mut self, handle: &FileHandle, access: ShareAccess) -> Result<(), StatusError>;
+
+    fn read(
+        &mut self,
+        handle: &FileHandle,
+        offset: u64,
+        count: u32,
+    ) -> Result<ReadRes, StatusError>;
+
+    fn write(
+        &mut self,
+        handle: &FileHandle,
+        offset: u64,
+        data: &[u8],
+        sync: bool,
+    ) -> Result<(), StatusError>;
+
+    fn commit(&mut self, handle: &FileHandle) -> Result<(), StatusError>;
+
+    /// Creates the regular file for OPEN, returning it along with which attributes were set.
+    fn create_file(
+        &mut self,
+        dir: &FileHandle,
+        name: &str,
+        how: CreateHow,
+    ) -> Result<(FileHandle, EnumSet<FileAttributeId>), StatusError>;
+
+    fn create_dir(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle, StatusError>;
+
+    fn create_symlink(
+        &mut self,
+        dir: &FileHandle,
+        name: &str,
+        target: &str,
+    ) -> Result<FileHandle, StatusError>;
+
+    fn link(
+        &mut self,
+        source: &FileHandle,
+        dir: &FileHandle,
+        name: &str,
+    ) -> Result<(), StatusError>;
+
+    /// Removes the entry, which can be an empty directory.
+    fn remove(&mut self, dir: &FileHandle, name: &str) -> Result<(), StatusError>;
+
+    /// Moves the entry, replacing whatever the target entry was.
+    fn rename(
+        &mut self,
+        from_dir: &FileHandle,
+        from_name: &str,
+        to_dir: &FileHandle,
+        to_name: &str,
+    ) -> Result<(), StatusError>;
+
+    /// The directory of named attributes of the object, of type `AttrDir`, making it if `create`
+    /// is set and the object doesn't have one yet. The named attributes in it are regular files of
+    /// type `NamedAttr`. Named attributes aren't supported unless this is implemented.
+    fn named_attr_dir(
+        &mut self,
+        _handle: &FileHandle,
+        _create: bool,
+    ) -> Result<FileHandle, StatusError> {
+        Err(StatusError::NotSupported)
+    }
+}
+
+/// Checks a name given for an object in a directory, which can't lead anywhere else.
+pub(crate) fn check_name(name: &str) -> Result<(), StatusError> {
+    if name.is_empty() {
+        Err(StatusError::Inval)
+    } else if name.len() > MAX_NAME as usize {
+        Err(StatusError::NameTooLong)
+    } else if name == "." || name == ".." || name.contains(['/', '\0']) {
+        Err(StatusError::BadName)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks attributes given to set on an object: ones unknown to us aren't supported, and the
+/// read-only ones can't be set at all.
+pub(crate) fn check_settable(attrs: &FileAttributes) -> Result<(), StatusError> {
+    if attrs.unknown().next().is_some() {
+        Err(StatusError::AttrNotSupported)
+    } else if attrs.iter().any(|attr| !attr.to_id().is_settable()) {
+        Err(StatusError::Inval)
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn check_names() {
+    check_name("a_file").unwrap();
+    assert_eq!(check_name(""), Err(StatusError::Inval));
+    assert_eq!(check_name(".."), Err(StatusError::BadName));
+    assert_eq!(check_name("a/b"), Err(StatusError::BadName));
+    assert_eq!(
+        check_name(&"a".repeat(MAX_NAME as usize + 1)),
+        Err(StatusError::NameTooLong)
+    );
+}
+
+#[test]
+fn check_settable_attributes() {
+    use nfs4::{FileAttribute, FileType, Mode};
+
+    let attrs = |attrs: Vec<FileAttribute>| attrs.into_iter().collect::<FileAttributes>();
+    check_settable(&attrs(vec![FileAttribute::Mode(Mode(0o644))])).unwrap();
+    assert_eq!(
+        check_settable(&attrs(vec![
+            FileAttribute::Mode(Mode(0o644)),
+            FileAttribute::Type(FileType::Regular)
+        ])),
+        Err(StatusError::Inval)
+    );
+}
@@ -0,0 +1,269 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! An NFSv4 server which exports a directory of the local file system, for reading and writing.
+//! Clients can use minor version 0 (RFC 7530), or minor version 1 (RFC 5661) with sessions. It is
+//! kept simple: everything is done with the permissions of the server process whoever asks, and
+//! there are no locks, delegations, callbacks or pNFS.
+//!
+//! What is exported doesn't have to be a local directory, any `FileSystem` will do. A
+//! `memory::MemoryFs` served over an in-process `pipe::Pipe` makes a quick server to test clients
+//! against, which can also be told to fail operations with `Server::inject_error`.
+
+use nfs4::{FileHandle, OperationId, SecurityInfo, StatusError};
+use std::collections::BTreeMap;
+use std::io::{self, Read as _};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sun_rpc::gss::GssAcceptor;
+use sun_rpc::server::{serve_call, serve_call_with_gss, Caller, GssContexts};
+use sun_rpc::AuthFlavor;
+use sun_rpc_client::{
+    write_record, AcceptedReplyBody, Program, RecordReader, DEFAULT_FRAGMENT_SIZE, NULL_PROCEDURE,
+};
+
+mod compound;
+mod fs;
+mod local;
+pub mod memory;
+pub mod pipe;
+mod state;
+
+pub use fs::FileSystem;
+pub use local::LocalFs;
+
+pub const NFS_PORT: u16 = 2049;
+
+const NFS: u32 = 100003;
+const NFS_VERSION: u32 = 4;
+const COMPOUND_PROCEDURE: u32 = 1;
+const MAX_MINOR_VERSION: u32 = 1;
+
+/// An error to fail an operation with the next few times it is done.
+struct Fault {
+    error: StatusError,
+    times: usize,
+}
+
+struct Exported<FileSystemT> {
+    files: FileSystemT,
+    state: state::State,
+    faults: BTreeMap<OperationId, Fault>,
+    /// The most a READ or WRITE does, if less than `MAX_IO_SIZE`
+    io_limit: Option<u32>,
+    /// The security flavors of the objects which don't take the default ones
+    security: BTreeMap<FileHandle, Vec<SecurityInfo>>,
+}
+
+impl<FileSystemT> Exported<FileSystemT> {
+    fn new(files: FileSystemT, boot: u32) -> Self {
+        Self {
+            files,
+            state: state::State::new(boot),
+            faults: BTreeMap::new(),
+            io_limit: None,
+            security: BTreeMap::new(),
+        }
+    }
+}
+
+/// Serves a file system to any number of connections, which all see the same clients and opens.
+pub struct Server<FileSystemT = LocalFs> {
+    exported: Arc<Mutex<Exported<FileSystemT>>>,
+    /// The RPCSEC_GSS contexts clients established, if we take RPCSEC_GSS
+    gss: Arc<Mutex<Option<GssContexts>>>,
+}
+
+impl<FileSystemT> Clone for Server<FileSystemT> {
+    fn clone(&self) -> Self {
+        Self {
+            exported: self.exported.clone(),
+            gss: self.gss.clone(),
+        }
+    }
+}
+
+impl<FileSystemT> Server<FileSystemT> {
+    /// A call which panicked part way through doesn't stop the others from being served.
+    fn exported(&self) -> MutexGuard<'_, Exported<FileSystemT>> {
+        self.exported.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Server {
+    /// Exports the given directory, which has to exist.
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(Self::with_file_system(LocalFs::new(root.into())?))
+    }
+}
+
+impl<FileSystemT: FileSystem + Send + 'static> Server<FileSystemT> {
+    pub fn with_file_system(files: FileSystemT) -> Self {
+        let boot = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        Self {
+            exported: Arc::new(Mutex::new(Exported::new(files, boot))),
+            gss: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Changes what is exported behind the backs of the clients.
+    pub fn update_file_system<R>(&self, f: impl FnOnce(&mut FileSystemT) -> R) -> R {
+        f(&mut self.exported().files)
+    }
+
+    /// Fails the given operation with the given error the next `times` times any client does it,
+    /// instead of doing it. This is for testing how clients deal with errors which are hard to
+    /// bring about, like `StatusError::Delay` or `StatusError::Grace`.
+    pub fn inject_error(&self, op: OperationId, error: StatusError, times: usize) {
+        let mut exported = self.exported();
+        if times == 0 {
+            exported.faults.remove(&op);
+        } else {
+            exported.faults.insert(op, Fault { error, times });
+        }
+    }
+
+    /// Revokes every open of every client, like an administrator can. Clients are told with the
+    /// next SEQUENCE, and the state IDs of the opens fail with `StatusError::AdminRevoked` until
+    /// they are freed.
+    pub fn revoke_opens(&self) {
+        self.exported().state.revoke_opens();
+    }
+
+    /// Makes clients which send nothing for longer than the given time lose their client IDs,
+    /// sessions and opens, and has GETATTR say so with the lease_time attribute. Leases never
+    /// expire otherwise.
+    pub fn set_lease_time(&self, lease_time: Duration) {
+        self.exported().state.set_lease_time(Some(lease_time));
+    }
+
+    /// Forgets every client, session and open, like restarting does, but keeps what is exported
+    /// and connections open. The clients which had sessions can then reclaim their opens with
+    /// CLAIM_PREVIOUS until they send RECLAIM_COMPLETE, and other OPENs fail with
+    /// `StatusError::Grace` until they all have.
+    pub fn restart(&self) {
+        self.exported().state.restart();
+    }
+
+    /// Makes each READ and WRITE do at most the given amount, without changing the maximum the
+    /// server says it takes, like servers are allowed to. This is for testing how clients deal
+    /// with short reads and writes.
+    pub fn limit_io(&self, limit: Option<u32>) {
+        self.exported().io_limit = limit;
+    }
+
+    /// Makes the object at the given path, and what is looked up, opened or created in it from
+    /// then on, take only the given security flavors, listed in order of preference. Using it any
+    /// other way fails with `StatusError::WrongSec`, and SECINFO tells clients which flavors to
+    /// use. Everything else takes AUTH_SYS or no credentials at all.
+    pub fn set_security(&self, path: &str, flavors: Vec<SecurityInfo>) -> Result<(), StatusError> {
+        let mut exported = self.exported();
+        let mut object = exported.files.root();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            object = exported.files.look_up(&object, name)?;
+        }
+        exported.security.insert(object, flavors);
+        Ok(())
+    }
+
+    /// Takes calls with RPCSEC_GSS, accepting contexts with what the given function returns for
+    /// each. A context can be used on any connection to the server once it is established.
+    pub fn accept_gss(&self, accept: impl FnMut() -> Box<dyn GssAcceptor> + Send + 'static) {
+        *self.gss.lock().unwrap_or_else(PoisonError::into_inner) = Some(GssContexts::new(accept));
+    }
+
+    /// Connects to the server without going through the network, serving the connection on a
+    /// thread of its own until the returned end of it is dropped.
+    pub fn connect_in_process(&self) -> pipe::Pipe {
+        let (ours, theirs) = pipe::duplex();
+        let mut server = self.clone();
+        thread::spawn(move || {
+            let _ = server.serve_connection(ours);
+        });
+        theirs
+    }
+
+    /// Serves each connection made to the listener on a thread of its own. Only returns if
+    /// accepting a connection fails.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let mut server = self.clone();
+            let stream = stream?;
+            thread::spawn(move || {
+                // The connection is done with either way
+                let _ = server.serve_connection(stream);
+            });
+        }
+        Ok(())
+    }
+
+    /// Serves the calls arriving on the given transport until the other end goes away.
+    pub fn serve_connection(&mut self, mut transport: impl io::Read + io::Write) -> io::Result<()> {
+        loop {
+            let mut message = vec![];
+            match RecordReader::new(&mut transport).read_to_end(&mut message) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && message.is_empty() => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            }
+            let gss = self.gss.clone();
+            let mut gss = gss.lock().unwrap_or_else(PoisonError::into_inner);
+            let reply = match gss.as_mut() {
+                Some(contexts) => serve_call_with_gss(self, contexts, &message),
+                None => serve_call(self, &message),
+            };
+            drop(gss);
+            if let Some(reply) = reply {
+                write_record(&mut transport, &reply, DEFAULT_FRAGMENT_SIZE)?;
+            }
+        }
+    }
+}
+
+impl<FileSystemT: FileSystem> Program for Server<FileSystemT> {
+    fn program(&self) -> u32 {
+        NFS
+    }
+
+    fn versions(&self) -> (u32, u32) {
+        (NFS_VERSION, NFS_VERSION)
+    }
+
+    fn call(&mut self, version: u32, procedure: u32, args: &[u8]) -> AcceptedReplyBody<Vec<u8>> {
+        let caller = Caller {
+            flavor: AuthFlavor::None,
+            service: None,
+        };
+        self.call_from(&caller, version, procedure, args)
+    }
+
+    fn call_from(
+        &mut self,
+        caller: &Caller,
+        _version: u32,
+        procedure: u32,
+        args: &[u8],
+    ) -> AcceptedReplyBody<Vec<u8>> {
+        match procedure {
+            NULL_PROCEDURE => AcceptedReplyBody::Success(vec![]),
+            COMPOUND_PROCEDURE => {
+                let Ok(args) = xdr_extras::from_bytes(args) else {
+                    return AcceptedReplyBody::GarbageArguments;
+                };
+                let res = compound::compound(&mut self.exported(), args, caller);
+                match xdr_extras::to_bytes(&res) {
+                    Ok(res) => AcceptedReplyBody::Success(res),
+                    Err(_) => AcceptedReplyBody::SystemError,
+                }
+            }
+            _ => AcceptedReplyBody::ProcedureUnavailable,
+        }
+    }
+}
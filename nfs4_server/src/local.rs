@@ -0,0 +1,540 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Exporting a directory of the local file system. File handles are made from the device and
+//! inode numbers of what they refer to, and we remember the path we found each one at so we can get
+//! back to it. Handles we haven't given out, like ones from before the server restarted, are stale.
+
+use super::fs::{FileSystem, LEASE_TIME, MAX_IO_SIZE, MAX_NAME};
+use nfs4::{
+    Change, CreateHow, EnumSet, FhExpireType, FileAttribute, FileAttributeId, FileAttributes,
+    FileHandle, FileId, FileType, FsId, Mode, ReadRes, SetTime, ShareAccess, StatusError, Time,
+    ToId as _, Verifier,
+};
+use std::collections::BTreeMap;
+use std::fs::{self, File, FileTimes, Metadata, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt as _, FileTypeExt as _, MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct FileKey {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileKey {
+    fn of(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+
+    fn to_handle(self) -> FileHandle {
+        let mut handle = self.dev.to_be_bytes().to_vec();
+        handle.extend(self.ino.to_be_bytes());
+        FileHandle(handle.into())
+    }
+
+    fn from_handle(handle: &FileHandle) -> Result<Self> {
+        let bytes: &[u8; 16] = handle
+            .0
+            .as_ref()
+            .try_into()
+            .map_err(|_| StatusError::BadHandle)?;
+        Ok(Self {
+            dev: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            ino: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+}
+
+/// A directory of the local file system, everything in it done with the permissions of the server
+/// process.
+pub struct LocalFs {
+    root: PathBuf,
+    root_key: FileKey,
+    paths: BTreeMap<FileKey, PathBuf>,
+}
+
+impl LocalFs {
+    /// Exports the given directory, which has to exist.
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        let metadata = fs::metadata(&root)?;
+        if !metadata.is_dir() {
+            return Err(io::ErrorKind::NotADirectory.into());
+        }
+        let root_key = FileKey::of(&metadata);
+        Ok(Self {
+            paths: BTreeMap::from([(root_key, root.clone())]),
+            root,
+            root_key,
+        })
+    }
+
+    /// Where the object the handle refers to is, as long as it is still there.
+    fn path(&self, handle: &FileHandle) -> Result<PathBuf> {
+        let key = FileKey::from_handle(handle)?;
+        let path = self.paths.get(&key).ok_or(StatusError::Stale)?;
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if FileKey::of(&metadata) == key => Ok(path.clone()),
+            _ => Err(StatusError::Stale),
+        }
+    }
+
+    /// Gives out a handle for the object at the given path.
+    fn handle(&mut self, path: PathBuf) -> Result<FileHandle> {
+        let metadata = fs::symlink_metadata(&path).map_err(status)?;
+        let key = FileKey::of(&metadata);
+        self.paths.insert(key, path);
+        Ok(key.to_handle())
+    }
+
+    /// Follows an object, and everything under it, from one path to another.
+    fn renamed(&mut self, from: &Path, to: &Path) {
+        for path in self.paths.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = to.join(rest);
+            }
+        }
+    }
+
+    /// Forgets about the object at the given path, making its handle stale.
+    fn removed(&mut self, path: &Path) {
+        self.paths.retain(|_, p| p != path);
+    }
+}
+
+fn status(error: io::Error) -> StatusError {
+    match error.kind() {
+        io::ErrorKind::NotFound => StatusError::NoEnt,
+        io::ErrorKind::PermissionDenied => StatusError::Access,
+        io::ErrorKind::AlreadyExists => StatusError::Exist,
+        io::ErrorKind::NotADirectory => StatusError::NotDir,
+        io::ErrorKind::IsADirectory => StatusError::Isdir,
+        io::ErrorKind::DirectoryNotEmpty => StatusError::NotEmpty,
+        io::ErrorKind::ReadOnlyFilesystem => StatusError::RoFs,
+        io::ErrorKind::StorageFull => StatusError::NoSpc,
+        io::ErrorKind::QuotaExceeded => StatusError::DQuot,
+        io::ErrorKind::FileTooLarge => StatusError::FBig,
+        io::ErrorKind::CrossesDevices => StatusError::XDev,
+        io::ErrorKind::InvalidFilename => StatusError::NameTooLong,
+        io::ErrorKind::TooManyLinks => StatusError::MLink,
+        io::ErrorKind::InvalidInput => StatusError::Inval,
+        _ => StatusError::Io,
+    }
+}
+
+fn file_type(metadata: &Metadata) -> FileType {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::Link
+    } else if file_type.is_block_device() {
+        FileType::Block
+    } else if file_type.is_char_device() {
+        FileType::Character
+    } else if file_type.is_socket() {
+        FileType::Socket
+    } else if file_type.is_fifo() {
+        FileType::Fifo
+    } else {
+        FileType::Regular
+    }
+}
+
+fn time(seconds: i64, nseconds: i64) -> Time {
+    Time {
+        seconds,
+        nseconds: nseconds as u32,
+    }
+}
+
+fn change(metadata: &Metadata) -> u64 {
+    (metadata.ctime() as u64)
+        .wrapping_mul(1_000_000_000)
+        .wrapping_add(metadata.ctime_nsec() as u64)
+}
+
+fn supported_attrs() -> EnumSet<FileAttributeId> {
+    [
+        FileAttributeId::SupportedAttrs,
+        FileAttributeId::Type,
+        FileAttributeId::FhExpireType,
+        FileAttributeId::Change,
+        FileAttributeId::Size,
+        FileAttributeId::LinkSupport,
+        FileAttributeId::SymlinkSupport,
+        FileAttributeId::NamedAttr,
+        FileAttributeId::FsId,
+        FileAttributeId::UniqueHandles,
+        FileAttributeId::LeaseTime,
+        FileAttributeId::CanSetTime,
+        FileAttributeId::FileHandle,
+        FileAttributeId::FileId,
+        FileAttributeId::MaxFileSize,
+        FileAttributeId::MaxName,
+        FileAttributeId::MaxRead,
+        FileAttributeId::MaxWrite,
+        FileAttributeId::Mode,
+        FileAttributeId::NoTrunc,
+        FileAttributeId::NumLinks,
+        FileAttributeId::Owner,
+        FileAttributeId::OwnerGroup,
+        FileAttributeId::SpaceUsed,
+        FileAttributeId::TimeAccess,
+        FileAttributeId::TimeAccessSet,
+        FileAttributeId::TimeMetadata,
+        FileAttributeId::TimeModify,
+        FileAttributeId::TimeModifySet,
+        FileAttributeId::MountedOnFileid,
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn attributes(metadata: &Metadata, request: &EnumSet<FileAttributeId>) -> FileAttributes {
+    let key = FileKey::of(metadata);
+    request
+        .iter()
+        .filter_map(|id| {
+            Some(match id {
+                FileAttributeId::SupportedAttrs => FileAttribute::SupportedAttrs(supported_attrs()),
+                FileAttributeId::Type => FileAttribute::Type(file_type(metadata)),
+                // Handles are persistent, as long as the server doesn't restart
+                FileAttributeId::FhExpireType => FileAttribute::FhExpireType(FhExpireType::empty()),
+                FileAttributeId::Change => FileAttribute::Change(Change(change(metadata))),
+                FileAttributeId::Size => FileAttribute::Size(metadata.size()),
+                FileAttributeId::LinkSupport => FileAttribute::LinkSupport(true),
+                FileAttributeId::SymlinkSupport => FileAttribute::SymlinkSupport(true),
+                FileAttributeId::NamedAttr => FileAttribute::NamedAttr(false),
+                FileAttributeId::FsId => FileAttribute::FsId(FsId {
+                    major: key.dev,
+                    minor: 0,
+                }),
+                FileAttributeId::UniqueHandles => FileAttribute::UniqueHandles(true),
+                FileAttributeId::LeaseTime => FileAttribute::LeaseTime(LEASE_TIME),
+                FileAttributeId::CanSetTime => FileAttribute::CanSetTime(true),
+                FileAttributeId::FileHandle => FileAttribute::FileHandle(key.to_handle()),
+                FileAttributeId::FileId => FileAttribute::FileId(FileId(key.ino)),
+                FileAttributeId::MaxFileSize => FileAttribute::MaxFileSize(i64::MAX as u64),
+                FileAttributeId::MaxName => FileAttribute::MaxName(MAX_NAME),
+                FileAttributeId::MaxRead => FileAttribute::MaxRead(MAX_IO_SIZE),
+                FileAttributeId::MaxWrite => FileAttribute::MaxWrite(MAX_IO_SIZE),
+                FileAttributeId::Mode => FileAttribute::Mode(Mode(metadata.mode() & 0o7777)),
+                FileAttributeId::NoTrunc => FileAttribute::NoTrunc(true),
+                FileAttributeId::NumLinks => FileAttribute::NumLinks(metadata.nlink() as u32),
+                FileAttributeId::Owner => FileAttribute::Owner(metadata.uid().to_string()),
+                FileAttributeId::OwnerGroup => {
+                    FileAttribute::OwnerGroup(metadata.gid().to_string())
+                }
+                FileAttributeId::SpaceUsed => FileAttribute::SpaceUsed(metadata.blocks() * 512),
+                FileAttributeId::TimeAccess => {
+                    FileAttribute::TimeAccess(time(metadata.atime(), metadata.atime_nsec()))
+                }
+                FileAttributeId::TimeMetadata => {
+                    FileAttribute::TimeMetadata(time(metadata.ctime(), metadata.ctime_nsec()))
+                }
+                FileAttributeId::TimeModify => {
+                    FileAttribute::TimeModify(time(metadata.mtime(), metadata.mtime_nsec()))
+                }
+                FileAttributeId::MountedOnFileid => FileAttribute::MountedOnFileid(FileId(key.ino)),
+                _ => return None,
+            })
+        })
+        .collect()
+}
+
+fn system_time(time: &SetTime) -> Result<SystemTime> {
+    match time {
+        SetTime::SetToClientTime(time) => time.to_system_time().ok_or(StatusError::Inval),
+        SetTime::SetToServerTime => Ok(SystemTime::now()),
+    }
+}
+
+fn id(owner: &str) -> Result<u32> {
+    owner.parse().map_err(|_| StatusError::BadOwner)
+}
+
+fn set_attributes(path: &Path, attrs: &FileAttributes) -> Result<EnumSet<FileAttributeId>> {
+    let mut owner = None;
+    let mut group = None;
+    for attr in attrs.iter() {
+        match attr {
+            FileAttribute::Owner(o) => owner = Some(id(o)?),
+            FileAttribute::OwnerGroup(g) => group = Some(id(g)?),
+            FileAttribute::Size(_)
+            | FileAttribute::Mode(_)
+            | FileAttribute::TimeAccessSet(_)
+            | FileAttribute::TimeModifySet(_) => {}
+            _ => return Err(StatusError::AttrNotSupported),
+        }
+    }
+
+    let mut times = None;
+    for attr in attrs.iter() {
+        match attr {
+            FileAttribute::Size(size) => {
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .map_err(status)?;
+                file.set_len(*size).map_err(status)?;
+            }
+            FileAttribute::Mode(mode) => {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode.0 & 0o7777))
+                    .map_err(status)?;
+            }
+            FileAttribute::TimeAccessSet(time) => {
+                times = Some(
+                    times
+                        .unwrap_or(FileTimes::new())
+                        .set_accessed(system_time(time)?),
+                );
+            }
+            FileAttribute::TimeModifySet(time) => {
+                times = Some(
+                    times
+                        .unwrap_or(FileTimes::new())
+                        .set_modified(system_time(time)?),
+                );
+            }
+            _ => {}
+        }
+    }
+    if owner.is_some() || group.is_some() {
+        std::os::unix::fs::lchown(path, owner, group).map_err(status)?;
+    }
+    if let Some(times) = times {
+        File::open(path)
+            .and_then(|f| f.set_times(times))
+            .map_err(status)?;
+    }
+    Ok(attrs.iter().map(|a| a.to_id()).collect())
+}
+
+fn metadata(path: &Path) -> Result<Metadata> {
+    fs::symlink_metadata(path).map_err(status)
+}
+
+/// The verifier of an exclusive create is kept in the access and modify times of the file, so
+/// when the client sends the OPEN again it finds it there and the OPEN succeeds.
+fn verifier_times(verifier: Verifier) -> FileTimes {
+    FileTimes::new()
+        .set_accessed(UNIX_EPOCH + Duration::from_secs(verifier.0 >> 32))
+        .set_modified(UNIX_EPOCH + Duration::from_secs(verifier.0 & 0xffff_ffff))
+}
+
+fn has_verifier(metadata: &Metadata, verifier: Verifier) -> bool {
+    metadata.atime() as u64 == verifier.0 >> 32
+        && metadata.mtime() as u64 == verifier.0 & 0xffff_ffff
+}
+
+fn create_new(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(status)
+}
+
+fn create_file(path: &Path, how: CreateHow) -> Result<EnumSet<FileAttributeId>> {
+    let (verifier, attrs) = match how {
+        CreateHow::Unchecked { create_attrs } => {
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(status)?;
+            return set_attributes(path, &create_attrs);
+        }
+        CreateHow::Guarded { create_attrs } => {
+            create_new(path)?;
+            return set_attributes(path, &create_attrs);
+        }
+        CreateHow::Exclusive { create_verifier } => (create_verifier, FileAttributes::default()),
+        CreateHow::ExclusiveBoth {
+            create_verifier,
+            create_attrs,
+        } => (create_verifier, create_attrs),
+    };
+
+    match create_new(path) {
+        Ok(file) => file.set_times(verifier_times(verifier)).map_err(status)?,
+        Err(StatusError::Exist) if has_verifier(&metadata(path)?, verifier) => {}
+        Err(e) => return Err(e),
+    }
+    let attribute_set = set_attributes(path, &attrs)?;
+    Ok([FileAttributeId::TimeAccess, FileAttributeId::TimeModify]
+        .into_iter()
+        .chain(attribute_set)
+        .collect())
+}
+
+impl FileSystem for LocalFs {
+    fn root(&self) -> FileHandle {
+        self.root_key.to_handle()
+    }
+
+    fn supported_attrs(&self) -> EnumSet<FileAttributeId> {
+        supported_attrs()
+    }
+
+    fn get_attributes(
+        &mut self,
+        handle: &FileHandle,
+        request: &EnumSet<FileAttributeId>,
+    ) -> Result<FileAttributes> {
+        Ok(attributes(&metadata(&self.path(handle)?)?, request))
+    }
+
+    fn set_attributes(
+        &mut self,
+        handle: &FileHandle,
+        attrs: &FileAttributes,
+    ) -> Result<EnumSet<FileAttributeId>> {
+        set_attributes(&self.path(handle)?, attrs)
+    }
+
+    fn look_up(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle> {
+        let path = self.path(dir)?.join(name);
+        self.handle(path)
+    }
+
+    fn parent(&mut self, dir: &FileHandle) -> Result<FileHandle> {
+        let path = self.path(dir)?;
+        if path == self.root {
+            return Err(StatusError::NoEnt);
+        }
+        self.handle(path.parent().unwrap().to_owned())
+    }
+
+    fn read_dir(&mut self, dir: &FileHandle) -> Result<Vec<String>> {
+        let mut names: Vec<_> = fs::read_dir(self.path(dir)?)
+            .and_then(|entries| {
+                entries
+                    .map(|e| Ok(e?.file_name()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(status)?
+            .into_iter()
+            // Names which aren't UTF-8 can't be sent
+            .filter_map(|name| name.into_string().ok())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn read_link(&mut self, handle: &FileHandle) -> Result<String> {
+        let link = fs::read_link(self.path(handle)?).map_err(status)?;
+        Ok(link.to_str().ok_or(StatusError::BadChar)?.into())
+    }
+
+    fn check_open(&mut self, handle: &FileHandle, access: ShareAccess) -> Result<()> {
+        OpenOptions::new()
+            .read(access.contains(ShareAccess::READ))
+            .write(access.contains(ShareAccess::WRITE))
+            .open(self.path(handle)?)
+            .map_err(status)?;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: &FileHandle, offset: u64, count: u32) -> Result<ReadRes> {
+        let file = File::open(self.path(handle)?).map_err(status)?;
+        let count = u64::from(count).min(MAX_IO_SIZE) as usize;
+        let mut data = vec![0; count];
+        let mut len = 0;
+        while len < count {
+            match file
+                .read_at(&mut data[len..], offset + len as u64)
+                .map_err(status)?
+            {
+                0 => break,
+                n => len += n,
+            }
+        }
+        data.truncate(len);
+        let size = file.metadata().map_err(status)?.len();
+        Ok(ReadRes {
+            eof: offset + len as u64 >= size,
+            data: data.into(),
+        })
+    }
+
+    fn write(&mut self, handle: &FileHandle, offset: u64, data: &[u8], sync: bool) -> Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(self.path(handle)?)
+            .map_err(status)?;
+        file.write_all_at(data, offset).map_err(status)?;
+        if sync {
+            file.sync_data().map_err(status)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, handle: &FileHandle) -> Result<()> {
+        File::open(self.path(handle)?)
+            .and_then(|f| f.sync_all())
+            .map_err(status)
+    }
+
+    fn create_file(
+        &mut self,
+        dir: &FileHandle,
+        name: &str,
+        how: CreateHow,
+    ) -> Result<(FileHandle, EnumSet<FileAttributeId>)> {
+        let path = self.path(dir)?.join(name);
+        let attribute_set = create_file(&path, how)?;
+        Ok((self.handle(path)?, attribute_set))
+    }
+
+    fn create_dir(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle> {
+        let path = self.path(dir)?.join(name);
+        fs::create_dir(&path).map_err(status)?;
+        self.handle(path)
+    }
+
+    fn create_symlink(&mut self, dir: &FileHandle, name: &str, target: &str) -> Result<FileHandle> {
+        let path = self.path(dir)?.join(name);
+        std::os::unix::fs::symlink(target, &path).map_err(status)?;
+        self.handle(path)
+    }
+
+    fn link(&mut self, source: &FileHandle, dir: &FileHandle, name: &str) -> Result<()> {
+        fs::hard_link(self.path(source)?, self.path(dir)?.join(name)).map_err(status)
+    }
+
+    fn remove(&mut self, dir: &FileHandle, name: &str) -> Result<()> {
+        let path = self.path(dir)?.join(name);
+        if metadata(&path)?.is_dir() {
+            fs::remove_dir(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(status)?;
+        self.removed(&path);
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        from_dir: &FileHandle,
+        from_name: &str,
+        to_dir: &FileHandle,
+        to_name: &str,
+    ) -> Result<()> {
+        let from = self.path(from_dir)?.join(from_name);
+        let to = self.path(to_dir)?.join(to_name);
+        fs::rename(&from, &to).map_err(status)?;
+        self.removed(&to);
+        self.renamed(&from, &to);
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright 2023 Remi Bernotavicius
+
+use clap::Parser;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, TcpListener};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+struct Options {
+    /// The directory to export
+    root: PathBuf,
+    #[clap(long, default_value_t = IpAddr::V4(Ipv4Addr::UNSPECIFIED))]
+    address: IpAddr,
+    #[clap(long, default_value_t = nfs4_server::NFS_PORT)]
+    port: u16,
+}
+
+fn main() -> io::Result<()> {
+    let opts = Options::parse();
+
+    let server = nfs4_server::Server::new(opts.root)?;
+    let listener = TcpListener::bind((opts.address, opts.port))?;
+    server.serve(listener)
+}
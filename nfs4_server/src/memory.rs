@@ -0,0 +1,725 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! A file system kept in memory, for exporting from tests. File handles are the numbers of the
+//! objects, which are never used again once an object is gone so its handle becomes stale.
+
+use super::fs::{FileSystem, LEASE_TIME, MAX_IO_SIZE, MAX_NAME};
+use nfs4::{
+    Bytes, Change, CreateHow, EnumSet, FhExpireType, FileAttribute, FileAttributeId,
+    FileAttributes, FileHandle, FileId, FileType, FsId, Mode, ReadRes, SetTime, ShareAccess,
+    StatusError, Time, ToId as _, Verifier,
+};
+use std::collections::BTreeMap;
+
+type Result<T> = std::result::Result<T, StatusError>;
+
+const ROOT: u64 = 1;
+
+enum Contents {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, u64>),
+    Link(String),
+}
+
+struct Node {
+    contents: Contents,
+    /// For directories, the one it is in. Only files can be in more than one.
+    parent: u64,
+    links: u32,
+    mode: u32,
+    owner: String,
+    group: String,
+    access: Time,
+    modify: Time,
+    metadata: Time,
+    change: u64,
+    /// The verifier of the exclusive create which made it.
+    verifier: Option<Verifier>,
+    /// Its named attribute directory, once it has one.
+    named_attrs: Option<u64>,
+    /// Whether it is a named attribute directory or a named attribute.
+    named: bool,
+    /// The file system it is in, the one of its parent unless made with `make_file_system`.
+    fs_id: u64,
+}
+
+fn supported_attrs() -> EnumSet<FileAttributeId> {
+    [
+        FileAttributeId::SupportedAttrs,
+        FileAttributeId::Type,
+        FileAttributeId::FhExpireType,
+        FileAttributeId::Change,
+        FileAttributeId::Size,
+        FileAttributeId::LinkSupport,
+        FileAttributeId::SymlinkSupport,
+        FileAttributeId::NamedAttr,
+        FileAttributeId::FsId,
+        FileAttributeId::UniqueHandles,
+        FileAttributeId::LeaseTime,
+        FileAttributeId::CanSetTime,
+        FileAttributeId::FileHandle,
+        FileAttributeId::FileId,
+        FileAttributeId::MaxFileSize,
+        FileAttributeId::MaxName,
+        FileAttributeId::MaxRead,
+        FileAttributeId::MaxWrite,
+        FileAttributeId::Mode,
+        FileAttributeId::NoTrunc,
+        FileAttributeId::NumLinks,
+        FileAttributeId::Owner,
+        FileAttributeId::OwnerGroup,
+        FileAttributeId::TimeAccess,
+        FileAttributeId::TimeAccessSet,
+        FileAttributeId::TimeMetadata,
+        FileAttributeId::TimeModify,
+        FileAttributeId::TimeModifySet,
+        FileAttributeId::MountedOnFileid,
+    ]
+    .into_iter()
+    .collect()
+}
+
+fn to_handle(id: u64) -> FileHandle {
+    FileHandle(id.to_be_bytes().to_vec().into())
+}
+
+fn set_time(time: &SetTime) -> Time {
+    match time {
+        SetTime::SetToClientTime(time) => *time,
+        SetTime::SetToServerTime => Time::now(),
+    }
+}
+
+/// Files, directories and symbolic links, all owned by root to begin with.
+pub struct MemoryFs {
+    nodes: BTreeMap<u64, Node>,
+    next_id: u64,
+    change: u64,
+    fh_expire_type: FhExpireType,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    /// Makes one with only an empty root directory.
+    pub fn new() -> Self {
+        let mut files = Self {
+            nodes: BTreeMap::new(),
+            next_id: ROOT,
+            change: 0,
+            fh_expire_type: FhExpireType::empty(),
+        };
+        files.new_node(Contents::Directory(BTreeMap::new()), ROOT, 0o755);
+        files
+    }
+
+    /// Creates the file at the given path, or replaces what is in it, from outside of the
+    /// server. The directory it goes in has to exist.
+    pub fn write_file(&mut self, path: &str, data: impl Into<Vec<u8>>) -> Result<()> {
+        let (dir, name) = self.resolve(path)?;
+        let how = CreateHow::Unchecked {
+            create_attrs: FileAttributes::default(),
+        };
+        let (handle, _) = self.create_file(&dir, &name, how)?;
+        let id = self.id(&handle)?;
+        self.node_mut(id)?.contents = Contents::File(data.into());
+        self.changed(id);
+        Ok(())
+    }
+
+    /// Creates the directory at the given path from outside of the server.
+    pub fn make_dir(&mut self, path: &str) -> Result<()> {
+        let (dir, name) = self.resolve(path)?;
+        self.create_dir(&dir, &name)?;
+        Ok(())
+    }
+
+    /// Creates the directory at the given path as the root of a file system of its own, like an
+    /// export under the pseudo file system. Everything made in it is in that file system too.
+    pub fn make_file_system(&mut self, path: &str) -> Result<()> {
+        let (dir, name) = self.resolve(path)?;
+        let handle = self.create_dir(&dir, &name)?;
+        let id = self.id(&handle)?;
+        self.node_mut(id)?.fs_id = id;
+        Ok(())
+    }
+
+    /// Says that handles can expire, which they don't, for testing how clients deal with servers
+    /// whose handles do along with `Server::inject_error`.
+    pub fn set_fh_expire_type(&mut self, fh_expire_type: FhExpireType) {
+        self.fh_expire_type = fh_expire_type;
+    }
+
+    /// Removes what is at the given path from outside of the server, making handles to it stale.
+    pub fn delete(&mut self, path: &str) -> Result<()> {
+        let (dir, name) = self.resolve(path)?;
+        self.remove(&dir, &name)
+    }
+
+    /// What is in the file at the given path.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let (dir, name) = self.resolve(path)?;
+        let id = self.entry(self.id(&dir)?, &name)?;
+        match &self.node(id)?.contents {
+            Contents::File(data) => Ok(data.clone()),
+            Contents::Directory(_) => Err(StatusError::Isdir),
+            Contents::Link(_) => Err(StatusError::Inval),
+        }
+    }
+
+    /// The directory the last part of the path is in, along with that last part.
+    fn resolve(&mut self, path: &str) -> Result<(FileHandle, String)> {
+        let mut names: Vec<_> = path.split('/').filter(|n| !n.is_empty()).collect();
+        let name = names.pop().ok_or(StatusError::Inval)?;
+        let mut dir = self.root();
+        for name in names {
+            dir = self.look_up(&dir, name)?;
+        }
+        Ok((dir, name.into()))
+    }
+
+    fn new_node(&mut self, contents: Contents, parent: u64, mode: u32) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.change += 1;
+        let time = Time::now();
+        let fs_id = self.nodes.get(&parent).map_or(0, |p| p.fs_id);
+        self.nodes.insert(
+            id,
+            Node {
+                contents,
+                parent,
+                links: 1,
+                mode,
+                owner: "0".into(),
+                group: "0".into(),
+                access: time,
+                modify: time,
+                metadata: time,
+                change: self.change,
+                verifier: None,
+                named_attrs: None,
+                named: false,
+                fs_id,
+            },
+        );
+        id
+    }
+
+    fn id(&self, handle: &FileHandle) -> Result<u64> {
+        let bytes: [u8; 8] = handle
+            .0
+            .as_ref()
+            .try_into()
+            .map_err(|_| StatusError::BadHandle)?;
+        let id = u64::from_be_bytes(bytes);
+        if !self.nodes.contains_key(&id) {
+            return Err(StatusError::Stale);
+        }
+        Ok(id)
+    }
+
+    fn node(&self, id: u64) -> Result<&Node> {
+        self.nodes.get(&id).ok_or(StatusError::Stale)
+    }
+
+    fn node_mut(&mut self, id: u64) -> Result<&mut Node> {
+        self.nodes.get_mut(&id).ok_or(StatusError::Stale)
+    }
+
+    fn entries(&self, dir: u64) -> Result<&BTreeMap<String, u64>> {
+        match &self.node(dir)?.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(StatusError::NotDir),
+        }
+    }
+
+    fn entries_mut(&mut self, dir: u64) -> Result<&mut BTreeMap<String, u64>> {
+        match &mut self.node_mut(dir)?.contents {
+            Contents::Directory(entries) => Ok(entries),
+            _ => Err(StatusError::NotDir),
+        }
+    }
+
+    fn entry(&self, dir: u64, name: &str) -> Result<u64> {
+        self.entries(dir)?
+            .get(name)
+            .copied()
+            .ok_or(StatusError::NoEnt)
+    }
+
+    /// Notes that the contents of the object changed.
+    fn changed(&mut self, id: u64) {
+        self.change += 1;
+        let change = self.change;
+        if let Some(node) = self.nodes.get_mut(&id) {
+            let time = Time::now();
+            node.modify = time;
+            node.metadata = time;
+            node.change = change;
+        }
+    }
+
+    /// Notes that the attributes of the object changed.
+    fn metadata_changed(&mut self, id: u64) {
+        self.change += 1;
+        let change = self.change;
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.metadata = Time::now();
+            node.change = change;
+        }
+    }
+
+    /// Adds a new object to the directory, which can't have anything by that name already.
+    fn add(&mut self, dir: u64, name: &str, contents: Contents, mode: u32) -> Result<u64> {
+        if self.entries(dir)?.contains_key(name) {
+            return Err(StatusError::Exist);
+        }
+        // Named attributes can only be regular files
+        let named = self.node(dir)?.named;
+        if named && !matches!(contents, Contents::File(_)) {
+            return Err(StatusError::Inval);
+        }
+        let id = self.new_node(contents, dir, mode);
+        self.node_mut(id)?.named = named;
+        self.entries_mut(dir)?.insert(name.into(), id);
+        self.changed(dir);
+        Ok(id)
+    }
+
+    /// Takes the entry out of the directory, getting rid of what it refers to if that was the last
+    /// entry for it.
+    fn unlink(&mut self, dir: u64, name: &str) -> Result<()> {
+        let id = self
+            .entries_mut(dir)?
+            .remove(name)
+            .ok_or(StatusError::NoEnt)?;
+        self.changed(dir);
+        let node = self.node_mut(id)?;
+        node.links -= 1;
+        if node.links == 0 {
+            self.forget(id);
+        } else {
+            self.metadata_changed(id);
+        }
+        Ok(())
+    }
+
+    /// Gets rid of the object along with its named attributes.
+    fn forget(&mut self, id: u64) {
+        let Some(node) = self.nodes.remove(&id) else {
+            return;
+        };
+        if let Some(named_attrs) = node.named_attrs {
+            if let Some(Node {
+                contents: Contents::Directory(entries),
+                ..
+            }) = self.nodes.remove(&named_attrs)
+            {
+                for attr in entries.into_values() {
+                    self.nodes.remove(&attr);
+                }
+            }
+        }
+    }
+
+    fn is_in(&self, mut id: u64, dir: u64) -> bool {
+        while id != ROOT {
+            if id == dir {
+                return true;
+            }
+            id = self.nodes[&id].parent;
+        }
+        dir == ROOT
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn root(&self) -> FileHandle {
+        to_handle(ROOT)
+    }
+
+    fn supported_attrs(&self) -> EnumSet<FileAttributeId> {
+        supported_attrs()
+    }
+
+    fn get_attributes(
+        &mut self,
+        handle: &FileHandle,
+        request: &EnumSet<FileAttributeId>,
+    ) -> Result<FileAttributes> {
+        let id = self.id(handle)?;
+        let node = self.node(id)?;
+        let (file_type, size) = match (&node.contents, node.named) {
+            (Contents::File(data), false) => (FileType::Regular, data.len() as u64),
+            (Contents::File(data), true) => (FileType::NamedAttr, data.len() as u64),
+            (Contents::Directory(entries), false) => (FileType::Directory, entries.len() as u64),
+            (Contents::Directory(entries), true) => (FileType::AttrDir, entries.len() as u64),
+            (Contents::Link(target), _) => (FileType::Link, target.len() as u64),
+        };
+        let has_named_attrs = match node.named_attrs {
+            Some(dir) => !self.entries(dir)?.is_empty(),
+            None => false,
+        };
+        Ok(request
+            .iter()
+            .filter_map(|attribute| {
+                Some(match attribute {
+                    FileAttributeId::SupportedAttrs => {
+                        FileAttribute::SupportedAttrs(supported_attrs())
+                    }
+                    FileAttributeId::Type => FileAttribute::Type(file_type.clone()),
+                    FileAttributeId::FhExpireType => {
+                        FileAttribute::FhExpireType(self.fh_expire_type)
+                    }
+                    FileAttributeId::Change => FileAttribute::Change(Change(node.change)),
+                    FileAttributeId::Size => FileAttribute::Size(size),
+                    FileAttributeId::LinkSupport => FileAttribute::LinkSupport(true),
+                    FileAttributeId::SymlinkSupport => FileAttribute::SymlinkSupport(true),
+                    FileAttributeId::NamedAttr => FileAttribute::NamedAttr(has_named_attrs),
+                    FileAttributeId::FsId => FileAttribute::FsId(FsId {
+                        major: node.fs_id,
+                        minor: 0,
+                    }),
+                    FileAttributeId::UniqueHandles => FileAttribute::UniqueHandles(true),
+                    FileAttributeId::LeaseTime => FileAttribute::LeaseTime(LEASE_TIME),
+                    FileAttributeId::CanSetTime => FileAttribute::CanSetTime(true),
+                    FileAttributeId::FileHandle => FileAttribute::FileHandle(to_handle(id)),
+                    FileAttributeId::FileId => FileAttribute::FileId(FileId(id)),
+                    FileAttributeId::MaxFileSize => FileAttribute::MaxFileSize(u32::MAX as u64),
+                    FileAttributeId::MaxName => FileAttribute::MaxName(MAX_NAME),
+                    FileAttributeId::MaxRead => FileAttribute::MaxRead(MAX_IO_SIZE),
+                    FileAttributeId::MaxWrite => FileAttribute::MaxWrite(MAX_IO_SIZE),
+                    FileAttributeId::Mode => FileAttribute::Mode(Mode(node.mode)),
+                    FileAttributeId::NoTrunc => FileAttribute::NoTrunc(true),
+                    FileAttributeId::NumLinks => FileAttribute::NumLinks(node.links),
+                    FileAttributeId::Owner => FileAttribute::Owner(node.owner.clone()),
+                    FileAttributeId::OwnerGroup => FileAttribute::OwnerGroup(node.group.clone()),
+                    FileAttributeId::TimeAccess => FileAttribute::TimeAccess(node.access),
+                    FileAttributeId::TimeMetadata => FileAttribute::TimeMetadata(node.metadata),
+                    FileAttributeId::TimeModify => FileAttribute::TimeModify(node.modify),
+                    FileAttributeId::MountedOnFileid => FileAttribute::MountedOnFileid(FileId(id)),
+                    _ => return None,
+                })
+            })
+            .collect())
+    }
+
+    fn set_attributes(
+        &mut self,
+        handle: &FileHandle,
+        attrs: &FileAttributes,
+    ) -> Result<EnumSet<FileAttributeId>> {
+        let id = self.id(handle)?;
+        for attr in attrs.iter() {
+            match attr {
+                FileAttribute::Size(_) => {
+                    if !matches!(self.node(id)?.contents, Contents::File(_)) {
+                        return Err(StatusError::Inval);
+                    }
+                }
+                FileAttribute::Mode(_)
+                | FileAttribute::Owner(_)
+                | FileAttribute::OwnerGroup(_)
+                | FileAttribute::TimeAccessSet(_)
+                | FileAttribute::TimeModifySet(_) => {}
+                _ => return Err(StatusError::AttrNotSupported),
+            }
+        }
+
+        let node = self.node_mut(id)?;
+        for attr in attrs.iter() {
+            match attr {
+                FileAttribute::Size(size) => {
+                    if let Contents::File(data) = &mut node.contents {
+                        data.resize(*size as usize, 0);
+                    }
+                }
+                FileAttribute::Mode(mode) => node.mode = mode.0 & 0o7777,
+                FileAttribute::Owner(owner) => node.owner = owner.clone(),
+                FileAttribute::OwnerGroup(group) => node.group = group.clone(),
+                FileAttribute::TimeAccessSet(time) => node.access = set_time(time),
+                FileAttribute::TimeModifySet(time) => node.modify = set_time(time),
+                _ => {}
+            }
+        }
+        self.metadata_changed(id);
+        Ok(attrs.iter().map(|a| a.to_id()).collect())
+    }
+
+    fn look_up(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle> {
+        Ok(to_handle(self.entry(self.id(dir)?, name)?))
+    }
+
+    fn parent(&mut self, dir: &FileHandle) -> Result<FileHandle> {
+        match self.id(dir)? {
+            ROOT => Err(StatusError::NoEnt),
+            id => Ok(to_handle(self.node(id)?.parent)),
+        }
+    }
+
+    fn read_dir(&mut self, dir: &FileHandle) -> Result<Vec<String>> {
+        Ok(self.entries(self.id(dir)?)?.keys().cloned().collect())
+    }
+
+    fn read_link(&mut self, handle: &FileHandle) -> Result<String> {
+        match &self.node(self.id(handle)?)?.contents {
+            Contents::Link(target) => Ok(target.clone()),
+            _ => Err(StatusError::Inval),
+        }
+    }
+
+    fn check_open(&mut self, handle: &FileHandle, _access: ShareAccess) -> Result<()> {
+        self.id(handle)?;
+        Ok(())
+    }
+
+    fn read(&mut self, handle: &FileHandle, offset: u64, count: u32) -> Result<ReadRes> {
+        let Contents::File(data) = &self.node(self.id(handle)?)?.contents else {
+            return Err(StatusError::Inval);
+        };
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(count as usize).min(data.len());
+        Ok(ReadRes {
+            eof: end == data.len(),
+            data: Bytes::copy_from_slice(&data[start..end]),
+        })
+    }
+
+    fn write(
+        &mut self,
+        handle: &FileHandle,
+        offset: u64,
+        new_data: &[u8],
+        _sync: bool,
+    ) -> Result<()> {
+        let id = self.id(handle)?;
+        let Contents::File(data) = &mut self.node_mut(id)?.contents else {
+            return Err(StatusError::Inval);
+        };
+        let end = offset as usize + new_data.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(new_data);
+        self.changed(id);
+        Ok(())
+    }
+
+    fn commit(&mut self, handle: &FileHandle) -> Result<()> {
+        self.id(handle)?;
+        Ok(())
+    }
+
+    fn create_file(
+        &mut self,
+        dir: &FileHandle,
+        name: &str,
+        how: CreateHow,
+    ) -> Result<(FileHandle, EnumSet<FileAttributeId>)> {
+        let dir = self.id(dir)?;
+        let (verifier, attrs) = match how {
+            CreateHow::Unchecked { create_attrs } => {
+                let id = match self.entry(dir, name) {
+                    Ok(id) => id,
+                    Err(_) => self.add(dir, name, Contents::File(vec![]), 0o644)?,
+                };
+                let handle = to_handle(id);
+                let attribute_set = self.set_attributes(&handle, &create_attrs)?;
+                return Ok((handle, attribute_set));
+            }
+            CreateHow::Guarded { create_attrs } => {
+                let handle = to_handle(self.add(dir, name, Contents::File(vec![]), 0o644)?);
+                let attribute_set = self.set_attributes(&handle, &create_attrs)?;
+                return Ok((handle, attribute_set));
+            }
+            CreateHow::Exclusive { create_verifier } => {
+                (create_verifier, FileAttributes::default())
+            }
+            CreateHow::ExclusiveBoth {
+                create_verifier,
+                create_attrs,
+            } => (create_verifier, create_attrs),
+        };
+
+        let id = match self.add(dir, name, Contents::File(vec![]), 0o644) {
+            Ok(id) => id,
+            Err(StatusError::Exist) => {
+                let id = self.entry(dir, name)?;
+                if self.node(id)?.verifier.as_ref() != Some(&verifier) {
+                    return Err(StatusError::Exist);
+                }
+                id
+            }
+            Err(e) => return Err(e),
+        };
+        self.node_mut(id)?.verifier = Some(verifier);
+        let handle = to_handle(id);
+        let attribute_set = self.set_attributes(&handle, &attrs)?;
+        Ok((handle, attribute_set))
+    }
+
+    fn create_dir(&mut self, dir: &FileHandle, name: &str) -> Result<FileHandle> {
+        let dir = self.id(dir)?;
+        let contents = Contents::Directory(BTreeMap::new());
+        Ok(to_handle(self.add(dir, name, contents, 0o755)?))
+    }
+
+    fn create_symlink(&mut self, dir: &FileHandle, name: &str, target: &str) -> Result<FileHandle> {
+        let dir = self.id(dir)?;
+        let contents = Contents::Link(target.into());
+        Ok(to_handle(self.add(dir, name, contents, 0o777)?))
+    }
+
+    fn link(&mut self, source: &FileHandle, dir: &FileHandle, name: &str) -> Result<()> {
+        let source = self.id(source)?;
+        let dir = self.id(dir)?;
+        if matches!(self.node(source)?.contents, Contents::Directory(_)) {
+            return Err(StatusError::Isdir);
+        }
+        let (source_node, dir_node) = (self.node(source)?, self.node(dir)?);
+        if source_node.named != dir_node.named || source_node.fs_id != dir_node.fs_id {
+            return Err(StatusError::XDev);
+        }
+        if self.entries(dir)?.contains_key(name) {
+            return Err(StatusError::Exist);
+        }
+        self.entries_mut(dir)?.insert(name.into(), source);
+        self.changed(dir);
+        self.node_mut(source)?.links += 1;
+        self.metadata_changed(source);
+        Ok(())
+    }
+
+    fn remove(&mut self, dir: &FileHandle, name: &str) -> Result<()> {
+        let dir = self.id(dir)?;
+        let id = self.entry(dir, name)?;
+        if let Contents::Directory(entries) = &self.node(id)?.contents {
+            if !entries.is_empty() {
+                return Err(StatusError::NotEmpty);
+            }
+        }
+        self.unlink(dir, name)
+    }
+
+    fn rename(
+        &mut self,
+        from_dir: &FileHandle,
+        from_name: &str,
+        to_dir: &FileHandle,
+        to_name: &str,
+    ) -> Result<()> {
+        let from_dir = self.id(from_dir)?;
+        let to_dir = self.id(to_dir)?;
+        let (from_node, to_node) = (self.node(from_dir)?, self.node(to_dir)?);
+        if from_node.named != to_node.named || from_node.fs_id != to_node.fs_id {
+            return Err(StatusError::XDev);
+        }
+        let id = self.entry(from_dir, from_name)?;
+        let is_dir = matches!(self.node(id)?.contents, Contents::Directory(_));
+        if is_dir && self.is_in(to_dir, id) {
+            return Err(StatusError::Inval);
+        }
+
+        match self.entry(to_dir, to_name) {
+            Ok(target) if target == id => return Ok(()),
+            Ok(target) => {
+                match (&self.node(target)?.contents, is_dir) {
+                    (Contents::Directory(entries), true) if entries.is_empty() => {}
+                    (Contents::Directory(_), true) => return Err(StatusError::NotEmpty),
+                    (Contents::Directory(_), false) | (_, true) => return Err(StatusError::Exist),
+                    _ => {}
+                }
+                self.unlink(to_dir, to_name)?;
+            }
+            Err(_) => {}
+        }
+
+        self.entries_mut(from_dir)?.remove(from_name);
+        self.changed(from_dir);
+        self.entries_mut(to_dir)?.insert(to_name.into(), id);
+        self.changed(to_dir);
+        if is_dir {
+            self.node_mut(id)?.parent = to_dir;
+        }
+        self.metadata_changed(id);
+        Ok(())
+    }
+
+    fn named_attr_dir(&mut self, handle: &FileHandle, create: bool) -> Result<FileHandle> {
+        let id = self.id(handle)?;
+        let node = self.node(id)?;
+        if node.named {
+            return Err(StatusError::Inval);
+        }
+        if let Some(dir) = node.named_attrs {
+            return Ok(to_handle(dir));
+        }
+        if !create {
+            return Err(StatusError::NoEnt);
+        }
+        let dir = self.new_node(Contents::Directory(BTreeMap::new()), id, 0o755);
+        self.node_mut(dir)?.named = true;
+        self.node_mut(id)?.named_attrs = Some(dir);
+        self.metadata_changed(id);
+        Ok(to_handle(dir))
+    }
+}
+
+#[test]
+fn handles_go_stale() {
+    let mut files = MemoryFs::new();
+    files.make_dir("a").unwrap();
+    files.write_file("a/b", b"hello").unwrap();
+
+    let root = files.root();
+    let a = files.look_up(&root, "a").unwrap();
+    let b = files.look_up(&a, "b").unwrap();
+    assert_eq!(&files.read(&b, 1, 3).unwrap().data[..], b"ell");
+
+    assert_eq!(files.remove(&root, "a"), Err(StatusError::NotEmpty));
+    files.rename(&a, "b", &root, "c").unwrap();
+    assert_eq!(files.read_dir(&root).unwrap(), ["a", "c"]);
+    assert_eq!(files.read_file("c").unwrap(), b"hello");
+
+    files.delete("c").unwrap();
+    assert_eq!(files.read(&b, 0, 5), Err(StatusError::Stale));
+    assert_eq!(files.look_up(&root, "c"), Err(StatusError::NoEnt));
+}
+
+#[test]
+fn named_attributes() {
+    let mut files = MemoryFs::new();
+    files.write_file("a", b"hello").unwrap();
+    let root = files.root();
+    let a = files.look_up(&root, "a").unwrap();
+    assert_eq!(files.named_attr_dir(&a, false), Err(StatusError::NoEnt));
+
+    let dir = files.named_attr_dir(&a, true).unwrap();
+    assert_eq!(files.named_attr_dir(&a, false).unwrap(), dir);
+    assert_eq!(files.parent(&dir).unwrap(), a);
+    files
+        .create_file(
+            &dir,
+            "user.x",
+            CreateHow::Guarded {
+                create_attrs: Default::default(),
+            },
+        )
+        .unwrap();
+    assert_eq!(files.create_dir(&dir, "d"), Err(StatusError::Inval));
+    assert_eq!(
+        files.rename(&dir, "user.x", &root, "b"),
+        Err(StatusError::XDev)
+    );
+    let attr = files.look_up(&dir, "user.x").unwrap();
+    assert_eq!(files.named_attr_dir(&attr, true), Err(StatusError::Inval));
+
+    files.delete("a").unwrap();
+    assert_eq!(files.read_dir(&dir), Err(StatusError::Stale));
+    assert_eq!(files.read(&attr, 0, 1), Err(StatusError::Stale));
+}
@@ -0,0 +1,97 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! A connection which stays within the process, for running a client and a server side by side.
+
+use std::io;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use sun_rpc_client::ReadTimeout;
+
+/// One end of a connection made by `duplex`. What is written to it is read from the other end.
+/// Once the other end is dropped reading gives end-of-file and writing fails.
+#[derive(Debug)]
+pub struct Pipe {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    position: usize,
+    read_timeout: Option<Duration>,
+}
+
+/// Makes both ends of a connection.
+pub fn duplex() -> (Pipe, Pipe) {
+    let (a_sender, b_receiver) = channel();
+    let (b_sender, a_receiver) = channel();
+    let end = |sender, receiver| Pipe {
+        sender,
+        receiver,
+        buffer: vec![],
+        position: 0,
+        read_timeout: None,
+    };
+    (end(a_sender, a_receiver), end(b_sender, b_receiver))
+}
+
+impl io::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            let data = match self.read_timeout {
+                Some(timeout) => self.receiver.recv_timeout(timeout),
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let data = match data {
+                Ok(data) => data,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.buffer = data;
+            self.position = 0;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..][..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl ReadTimeout for Pipe {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+}
+
+impl io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::ErrorKind::BrokenPipe)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn both_ways() {
+    use std::io::{Read as _, Write as _};
+
+    let (mut a, mut b) = duplex();
+    a.write_all(b"hello").unwrap();
+    b.write_all(b"there").unwrap();
+
+    let mut data = [0; 5];
+    b.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"hello");
+    a.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"there");
+
+    drop(a);
+    assert_eq!(b.read(&mut data).unwrap(), 0);
+    b.write_all(b"gone").unwrap_err();
+}
@@ -0,0 +1,608 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! What we keep about clients: their client IDs, sessions and the files they have open. None of
+//! it is kept past a restart. Unless we are given a lease time, leases are never let expire, so
+//! neither is it forgotten about while the server runs unless the client gets rid of it itself.
+//! After a restart the clients which had sessions get a grace period to reclaim their opens in.
+
+use nfs4::{
+    ChannelAttrs, ClientId, ClientOwner, CompoundRes, CreateSessionArgs, CreateSessionFlags,
+    CreateSessionRes, FileHandle, SequenceArgs, SequenceId, SequenceRes, SequenceStatusFlags,
+    SessionId, ShareAccess, ShareDeny, SlotId, StateId, StatusError, Verifier,
+};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// The most slots a session gets, however many the client asks for
+const MAX_SLOTS: u32 = 64;
+
+// Enough for the largest READ or WRITE along with the rest of the COMPOUND
+const MAX_MESSAGE_SIZE: u32 = super::fs::MAX_IO_SIZE as u32 + 4096;
+
+struct Client {
+    owner: ClientOwner,
+    confirmed: bool,
+    /// For minor version 0, what SETCLIENTID_CONFIRM has to give back.
+    confirm: Verifier,
+    /// For minor version 1, the sequence ID CREATE_SESSION has to use next.
+    sequence_id: SequenceId,
+    /// Whether it sent RECLAIM_COMPLETE.
+    reclaim_complete: bool,
+    /// When its lease was last renewed.
+    renewed: Instant,
+}
+
+struct Slot {
+    sequence_id: SequenceId,
+    reply: Option<CompoundRes>,
+}
+
+struct Session {
+    client_id: ClientId,
+    slots: Vec<Slot>,
+}
+
+pub(crate) struct Open {
+    pub(crate) handle: FileHandle,
+    client_id: ClientId,
+    owner: Vec<u8>,
+    pub(crate) access: ShareAccess,
+    pub(crate) deny: ShareDeny,
+    pub(crate) state_id: StateId,
+    /// Opens of minor version 0 can't be used until they are confirmed with OPEN_CONFIRM, the
+    /// first time an open-owner is used.
+    pub(crate) confirmed: bool,
+}
+
+/// What SEQUENCE found.
+pub(crate) enum Sequenced {
+    /// A new request, which the rest of the COMPOUND is to be done for.
+    New(SequenceRes, ClientId),
+    /// A request we already did, with the reply we sent for it.
+    Replay(CompoundRes),
+}
+
+pub(crate) struct State {
+    /// Tells apart the state IDs, client IDs and sessions of this run of the server from earlier
+    /// ones, and is the write verifier.
+    boot: u32,
+    next_id: u64,
+    clients: BTreeMap<u64, Client>,
+    sessions: BTreeMap<[u8; 16], Session>,
+    opens: BTreeMap<[u8; 12], Open>,
+    /// The opens which were revoked, by whose they were, until they are freed with FREE_STATEID.
+    revoked: BTreeMap<[u8; 12], ClientId>,
+    /// The open-owners which have been confirmed, by client ID and owner.
+    confirmed_owners: Vec<(ClientId, Vec<u8>)>,
+    /// The owners of the clients which had sessions before the server restarted and haven't sent
+    /// RECLAIM_COMPLETE since. The grace period lasts until there are none left.
+    reclaiming: Vec<Vec<u8>>,
+    /// How long clients keep what they have without renewing their leases, forever if `None`.
+    lease_time: Option<Duration>,
+}
+
+fn is_special(state_id: &StateId) -> bool {
+    state_id.other == [0; 12] || state_id.other == [0xff; 12]
+}
+
+impl State {
+    pub(crate) fn new(boot: u32) -> Self {
+        Self {
+            boot,
+            next_id: 1,
+            clients: BTreeMap::new(),
+            sessions: BTreeMap::new(),
+            opens: BTreeMap::new(),
+            revoked: BTreeMap::new(),
+            confirmed_owners: vec![],
+            reclaiming: vec![],
+            lease_time: None,
+        }
+    }
+
+    /// Forgets everything, like restarting does, and starts the grace period for the clients
+    /// which had sessions. Those with minor version 0 have no way to say they are done
+    /// reclaiming, so they aren't waited for.
+    pub(crate) fn restart(&mut self) {
+        let reclaiming = self
+            .clients
+            .iter()
+            .filter(|(id, _)| self.sessions.values().any(|s| s.client_id.0 == **id))
+            .map(|(_, c)| c.owner.owner_id.clone())
+            .collect();
+        let lease_time = self.lease_time;
+        *self = Self::new(self.boot.wrapping_add(1));
+        self.reclaiming = reclaiming;
+        self.lease_time = lease_time;
+    }
+
+    pub(crate) fn set_lease_time(&mut self, lease_time: Option<Duration>) {
+        self.lease_time = lease_time;
+    }
+
+    pub(crate) fn lease_time(&self) -> Option<Duration> {
+        self.lease_time
+    }
+
+    /// Forgets about the clients which haven't renewed their leases within the lease time.
+    pub(crate) fn expire_leases(&mut self) {
+        let Some(lease_time) = self.lease_time else {
+            return;
+        };
+        let expired: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, c)| c.renewed.elapsed() > lease_time)
+            .map(|(id, _)| ClientId(*id))
+            .collect();
+        for client_id in expired {
+            self.remove_client(client_id);
+        }
+    }
+
+    fn renew_lease(&mut self, client_id: ClientId) {
+        if let Some(client) = self.clients.get_mut(&client_id.0) {
+            client.renewed = Instant::now();
+        }
+    }
+
+    /// Whether opens which aren't reclaims have to wait for the grace period to end.
+    pub(crate) fn in_grace(&self) -> bool {
+        !self.reclaiming.is_empty()
+    }
+
+    /// Who we are, for clients to tell whether two connections are to the same server.
+    pub(crate) fn server_owner(&self) -> Vec<u8> {
+        format!("nfs4_server-{:08x}", self.boot).into_bytes()
+    }
+
+    pub(crate) fn write_verifier(&self) -> Verifier {
+        Verifier(self.boot.into())
+    }
+
+    fn new_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        u64::from(self.boot) << 32 | id
+    }
+
+    fn new_other(&mut self) -> [u8; 12] {
+        let mut other = [0; 12];
+        other[..8].copy_from_slice(&self.new_id().to_be_bytes());
+        other[8..].copy_from_slice(&self.boot.to_be_bytes());
+        other
+    }
+
+    /// Forgets about the client and everything it had.
+    fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id.0);
+        self.sessions.retain(|_, s| s.client_id != client_id);
+        self.opens.retain(|_, o| o.client_id != client_id);
+        self.revoked.retain(|_, c| *c != client_id);
+        self.confirmed_owners.retain(|(c, _)| *c != client_id);
+    }
+
+    /// Once a client is confirmed, the client IDs it had from before it restarted are done with.
+    fn confirm_client(&mut self, client_id: ClientId) {
+        let client = self.clients.get_mut(&client_id.0).unwrap();
+        client.confirmed = true;
+        let owner_id = client.owner.owner_id.clone();
+        let previous: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(id, c)| **id != client_id.0 && c.owner.owner_id == owner_id)
+            .map(|(id, _)| ClientId(*id))
+            .collect();
+        for client_id in previous {
+            self.remove_client(client_id);
+        }
+    }
+
+    /// Finds the client ID for the given client, or makes a new one if we don't know it or it
+    /// restarted since we last saw it.
+    fn client_id_for(&mut self, owner: &ClientOwner) -> ClientId {
+        let existing = self.clients.iter().find(|(_, c)| &c.owner == owner);
+        if let Some((id, _)) = existing {
+            return ClientId(*id);
+        }
+        let id = self.new_id();
+        self.clients.insert(
+            id,
+            Client {
+                owner: owner.clone(),
+                confirmed: false,
+                confirm: Verifier(0),
+                sequence_id: SequenceId(1),
+                reclaim_complete: false,
+                renewed: Instant::now(),
+            },
+        );
+        ClientId(id)
+    }
+
+    pub(crate) fn check_client(&self, client_id: ClientId) -> Result<(), StatusError> {
+        match self.clients.get(&client_id.0) {
+            Some(client) if client.confirmed => Ok(()),
+            _ => Err(StatusError::StaleClientId),
+        }
+    }
+
+    /// RENEW, for minor version 0.
+    pub(crate) fn renew(&mut self, client_id: ClientId) -> Result<(), StatusError> {
+        self.check_client(client_id)?;
+        self.renew_lease(client_id);
+        Ok(())
+    }
+
+    /// Checks that the client can still reclaim what it had before the server restarted.
+    pub(crate) fn check_reclaim(&self, client_id: ClientId) -> Result<(), StatusError> {
+        let client = self
+            .clients
+            .get(&client_id.0)
+            .ok_or(StatusError::StaleClientId)?;
+        if client.reclaim_complete || !self.reclaiming.contains(&client.owner.owner_id) {
+            return Err(StatusError::NoGrace);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn reclaim_complete(&mut self, client_id: ClientId) -> Result<(), StatusError> {
+        let client = self
+            .clients
+            .get_mut(&client_id.0)
+            .ok_or(StatusError::StaleClientId)?;
+        if client.reclaim_complete {
+            return Err(StatusError::CompleteAlready);
+        }
+        client.reclaim_complete = true;
+        let owner_id = client.owner.owner_id.clone();
+        self.reclaiming.retain(|o| *o != owner_id);
+        Ok(())
+    }
+
+    // Minor version 0
+
+    pub(crate) fn set_client_id(&mut self, owner: &ClientOwner) -> (ClientId, Verifier) {
+        let client_id = self.client_id_for(owner);
+        let confirm = Verifier(self.new_id());
+        self.clients.get_mut(&client_id.0).unwrap().confirm = confirm.clone();
+        (client_id, confirm)
+    }
+
+    pub(crate) fn set_client_id_confirm(
+        &mut self,
+        client_id: ClientId,
+        confirm: Verifier,
+    ) -> Result<(), StatusError> {
+        match self.clients.get(&client_id.0) {
+            Some(client) if client.confirm == confirm => {
+                self.confirm_client(client_id);
+                Ok(())
+            }
+            _ => Err(StatusError::StaleClientId),
+        }
+    }
+
+    // Minor version 1
+
+    pub(crate) fn exchange_id(&mut self, owner: &ClientOwner) -> (ClientId, SequenceId, bool) {
+        let client_id = self.client_id_for(owner);
+        let client = &self.clients[&client_id.0];
+        (client_id, client.sequence_id, client.confirmed)
+    }
+
+    pub(crate) fn create_session(
+        &mut self,
+        args: &CreateSessionArgs,
+    ) -> Result<CreateSessionRes, StatusError> {
+        let client = self
+            .clients
+            .get_mut(&args.client_id.0)
+            .ok_or(StatusError::StaleClientId)?;
+        if args.sequence_id != client.sequence_id {
+            return Err(StatusError::SeqMisordered);
+        }
+        client.sequence_id.incr();
+        self.confirm_client(args.client_id);
+
+        let fore_channel_attrs = ChannelAttrs {
+            header_pad_size: 0,
+            max_request_size: args
+                .fore_channel_attrs
+                .max_request_size
+                .min(MAX_MESSAGE_SIZE),
+            max_response_size: args
+                .fore_channel_attrs
+                .max_response_size
+                .min(MAX_MESSAGE_SIZE),
+            max_response_size_cached: args
+                .fore_channel_attrs
+                .max_response_size_cached
+                .min(MAX_MESSAGE_SIZE),
+            max_operations: args.fore_channel_attrs.max_operations,
+            max_requests: args.fore_channel_attrs.max_requests.clamp(1, MAX_SLOTS),
+            rdma_ird: None,
+        };
+        let slots = (0..fore_channel_attrs.max_requests)
+            .map(|_| Slot {
+                sequence_id: SequenceId(0),
+                reply: None,
+            })
+            .collect();
+
+        let mut session_id = [0; 16];
+        session_id[..8].copy_from_slice(&args.client_id.0.to_be_bytes());
+        session_id[8..].copy_from_slice(&self.new_id().to_be_bytes());
+        self.sessions.insert(
+            session_id,
+            Session {
+                client_id: args.client_id,
+                slots,
+            },
+        );
+
+        // There is no backchannel, since we never call clients back
+        Ok(CreateSessionRes {
+            session_id: SessionId(session_id),
+            sequence_id: args.sequence_id,
+            flags: CreateSessionFlags::empty(),
+            fore_channel_attrs,
+            back_channel_attrs: args.back_channel_attrs.clone(),
+        })
+    }
+
+    pub(crate) fn check_session(&self, session_id: SessionId) -> Result<(), StatusError> {
+        if self.sessions.contains_key(&session_id.0) {
+            Ok(())
+        } else {
+            Err(StatusError::BadSession)
+        }
+    }
+
+    pub(crate) fn destroy_session(&mut self, session_id: SessionId) -> Result<(), StatusError> {
+        self.sessions
+            .remove(&session_id.0)
+            .map(|_| ())
+            .ok_or(StatusError::BadSession)
+    }
+
+    pub(crate) fn destroy_client_id(&mut self, client_id: ClientId) -> Result<(), StatusError> {
+        if !self.clients.contains_key(&client_id.0) {
+            return Err(StatusError::StaleClientId);
+        }
+        if self.sessions.values().any(|s| s.client_id == client_id) {
+            return Err(StatusError::ClientIdBusy);
+        }
+        self.remove_client(client_id);
+        Ok(())
+    }
+
+    pub(crate) fn sequence(&mut self, args: &SequenceArgs) -> Result<Sequenced, StatusError> {
+        let session = self
+            .sessions
+            .get_mut(&args.session_id.0)
+            .ok_or(StatusError::BadSession)?;
+        let highest_slot_id = SlotId(session.slots.len() as u32 - 1);
+        if args.highest_slot_id.0 > highest_slot_id.0 {
+            return Err(StatusError::BadHighSlot);
+        }
+        let slot = session
+            .slots
+            .get_mut(args.slot_id.0 as usize)
+            .ok_or(StatusError::BadSlot)?;
+
+        if args.sequence_id == slot.sequence_id {
+            return slot
+                .reply
+                .clone()
+                .map(Sequenced::Replay)
+                .ok_or(StatusError::RetryUncachedRep);
+        }
+        if args.sequence_id.0 != slot.sequence_id.0.wrapping_add(1) {
+            return Err(StatusError::SeqMisordered);
+        }
+        slot.sequence_id = args.sequence_id;
+        slot.reply = None;
+
+        let mut res = SequenceRes {
+            session_id: args.session_id,
+            sequence_id: args.sequence_id,
+            slot_id: args.slot_id,
+            highest_slot_id,
+            target_highest_slot_id: highest_slot_id,
+            status_flags: SequenceStatusFlags::empty(),
+        };
+        let client_id = session.client_id;
+        self.renew_lease(client_id);
+        if self.revoked.values().any(|c| *c == client_id) {
+            res.status_flags |= SequenceStatusFlags::ADMIN_STATE_REVOKED;
+        }
+        Ok(Sequenced::New(res, client_id))
+    }
+
+    /// Keeps the reply to the request in the given slot, in case the client sends it again.
+    pub(crate) fn cache_reply(&mut self, args: &SequenceArgs, reply: &CompoundRes) {
+        if let Some(slot) = self
+            .sessions
+            .get_mut(&args.session_id.0)
+            .and_then(|s| s.slots.get_mut(args.slot_id.0 as usize))
+        {
+            slot.reply = Some(reply.clone());
+        }
+    }
+
+    // Opens
+
+    /// Opens the file for the open-owner, or upgrades the open it already has of it. Fails if it
+    /// conflicts with the share reservations of other opens of the file.
+    pub(crate) fn open(
+        &mut self,
+        handle: &FileHandle,
+        client_id: ClientId,
+        owner: &[u8],
+        access: ShareAccess,
+        deny: ShareDeny,
+        needs_confirm: bool,
+    ) -> Result<&Open, StatusError> {
+        let access = access & ShareAccess::BOTH;
+        let mut existing = None;
+        for (other, open) in &self.opens {
+            if &open.handle != handle {
+                continue;
+            }
+            if open.client_id == client_id && open.owner == owner {
+                existing = Some(*other);
+            } else if access.bits() & open.deny.bits() != 0 || deny.bits() & open.access.bits() != 0
+            {
+                return Err(StatusError::ShareDenied);
+            }
+        }
+
+        let other = match existing {
+            Some(other) => {
+                let open = self.opens.get_mut(&other).unwrap();
+                open.access |= access;
+                open.deny |= deny;
+                open.state_id.sequence_id += 1;
+                other
+            }
+            None => {
+                let confirmed = !needs_confirm
+                    || self
+                        .confirmed_owners
+                        .iter()
+                        .any(|(c, o)| *c == client_id && o == owner);
+                let other = self.new_other();
+                self.opens.insert(
+                    other,
+                    Open {
+                        handle: handle.clone(),
+                        client_id,
+                        owner: owner.to_vec(),
+                        access,
+                        deny,
+                        state_id: StateId {
+                            sequence_id: 1,
+                            other,
+                        },
+                        confirmed,
+                    },
+                );
+                other
+            }
+        };
+        Ok(&self.opens[&other])
+    }
+
+    /// Finds the open the state ID is for, checking that it is the current one. A sequence ID of
+    /// 0 stands for whatever the current one is.
+    fn find_open(&mut self, state_id: &StateId) -> Result<&mut Open, StatusError> {
+        if self.revoked.contains_key(&state_id.other) {
+            return Err(StatusError::AdminRevoked);
+        }
+        let open = self
+            .opens
+            .get_mut(&state_id.other)
+            .ok_or(StatusError::BadStateId)?;
+        match state_id.sequence_id {
+            0 => Ok(open),
+            s if s == open.state_id.sequence_id => Ok(open),
+            s if s < open.state_id.sequence_id => Err(StatusError::OldStateId),
+            _ => Err(StatusError::BadStateId),
+        }
+    }
+
+    pub(crate) fn open_confirm(
+        &mut self,
+        handle: &FileHandle,
+        state_id: &StateId,
+    ) -> Result<StateId, StatusError> {
+        let open = self.find_open(state_id)?;
+        if &open.handle != handle || open.confirmed {
+            return Err(StatusError::BadStateId);
+        }
+        open.confirmed = true;
+        open.state_id.sequence_id += 1;
+        let state_id = open.state_id;
+        let owner = (open.client_id, open.owner.clone());
+        self.confirmed_owners.push(owner);
+        Ok(state_id)
+    }
+
+    pub(crate) fn open_downgrade(
+        &mut self,
+        handle: &FileHandle,
+        state_id: &StateId,
+        access: ShareAccess,
+        deny: ShareDeny,
+    ) -> Result<StateId, StatusError> {
+        let open = self.find_open(state_id)?;
+        let access = access & ShareAccess::BOTH;
+        if &open.handle != handle || !open.confirmed {
+            return Err(StatusError::BadStateId);
+        }
+        if !open.access.contains(access) || !open.deny.contains(deny) || access.is_empty() {
+            return Err(StatusError::Inval);
+        }
+        open.access = access;
+        open.deny = deny;
+        open.state_id.sequence_id += 1;
+        Ok(open.state_id)
+    }
+
+    pub(crate) fn close(
+        &mut self,
+        handle: &FileHandle,
+        state_id: &StateId,
+    ) -> Result<StateId, StatusError> {
+        let open = self.find_open(state_id)?;
+        if &open.handle != handle {
+            return Err(StatusError::BadStateId);
+        }
+        let mut closed = open.state_id;
+        closed.sequence_id += 1;
+        self.opens.remove(&state_id.other);
+        Ok(closed)
+    }
+
+    /// Revokes every open of every client, like an administrator can.
+    pub(crate) fn revoke_opens(&mut self) {
+        for (other, open) in std::mem::take(&mut self.opens) {
+            self.revoked.insert(other, open.client_id);
+        }
+    }
+
+    /// Checks that the state ID lets the file be read or written, for the given access. The
+    /// special anonymous and read bypass state IDs always do.
+    pub(crate) fn check_state_id(
+        &mut self,
+        handle: &FileHandle,
+        state_id: &StateId,
+        access: ShareAccess,
+    ) -> Result<(), StatusError> {
+        if is_special(state_id) {
+            return Ok(());
+        }
+        let open = self.find_open(state_id)?;
+        if &open.handle != handle || !open.confirmed {
+            return Err(StatusError::BadStateId);
+        }
+        if !open.access.contains(access) {
+            return Err(StatusError::OpenMode);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn test_state_id(&mut self, state_id: &StateId) -> Result<(), StatusError> {
+        self.find_open(state_id).map(|_| ())
+    }
+
+    pub(crate) fn free_state_id(&mut self, state_id: &StateId) -> Result<(), StatusError> {
+        if self.revoked.remove(&state_id.other).is_some() {
+            return Ok(());
+        }
+        // Opens are only let go of with CLOSE
+        self.find_open(state_id)?;
+        Err(StatusError::LocksHeld)
+    }
+}
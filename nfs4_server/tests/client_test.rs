@@ -0,0 +1,140 @@
+// Copyright 2023 Remi Bernotavicius
+
+use nfs4::{FileAttribute, FileAttributeId, FileAttributes, FileAttributesBuilder, StatusError};
+use nfs4_client::Client;
+use nfs4_server::Server;
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use tempdir::TempDir;
+
+fn start(root: &TempDir) -> Client<TcpStream> {
+    let server = Server::new(root.path()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || server.serve(listener));
+    Client::new(TcpStream::connect(address).unwrap()).unwrap()
+}
+
+#[test]
+fn write_and_read_back() {
+    let root = TempDir::new("nfs4_server").unwrap();
+    let mut client = start(&root);
+
+    let dir = client.look_up("/").unwrap();
+    let file = client.create_file(dir, "a_file").unwrap();
+    let handle = file.handle.clone();
+    client
+        .write_all(handle.clone(), &b"hello world"[..])
+        .unwrap();
+    client.close(file).unwrap();
+
+    assert_eq!(
+        fs::read(root.path().join("a_file")).unwrap(),
+        b"hello world"
+    );
+
+    let mut data = vec![];
+    client.read_all(handle, &mut data).unwrap();
+    assert_eq!(data, b"hello world");
+}
+
+#[test]
+fn directories() {
+    let root = TempDir::new("nfs4_server").unwrap();
+    fs::write(root.path().join("b"), b"").unwrap();
+    let mut client = start(&root);
+
+    let dir = client.look_up("/").unwrap();
+    client
+        .create_directory(dir.clone(), "a", FileAttributes::default())
+        .unwrap();
+    client
+        .create_symlink(dir.clone(), "c", "b", FileAttributes::default())
+        .unwrap();
+
+    let names: Vec<_> = client
+        .read_dir(dir.clone(), [FileAttributeId::Type].into_iter().collect())
+        .map(|e| e.unwrap().name)
+        .collect();
+    assert_eq!(names, ["a", "b", "c"]);
+
+    let link = client.look_up("/c").unwrap();
+    assert_eq!(client.read_link(link).unwrap(), "b");
+
+    client
+        .rename(dir.clone(), dir.clone(), "b", "a/d")
+        .unwrap_err();
+    let a = client.look_up("/a").unwrap();
+    client.rename(dir.clone(), a, "b", "d").unwrap();
+    assert!(root.path().join("a/d").exists());
+    client.look_up("/a/d").unwrap();
+
+    client.remove(dir.clone(), "a").unwrap_err();
+    assert!(client.look_up("/b").unwrap_err().is_not_found());
+}
+
+#[test]
+fn non_ascii_names() {
+    let root = TempDir::new("nfs4_server").unwrap();
+    fs::write(root.path().join("café"), b"").unwrap();
+    let mut client = start(&root);
+
+    let dir = client.look_up("/").unwrap();
+    client.look_up("/café").unwrap();
+    let file = client.create_file(dir.clone(), "naïve 日本 🦀").unwrap();
+    client.close(file).unwrap();
+    assert!(root.path().join("naïve 日本 🦀").exists());
+
+    let names: Vec<_> = client
+        .read_dir(dir, [FileAttributeId::Type].into_iter().collect())
+        .map(|e| e.unwrap().name)
+        .collect();
+    assert_eq!(names, ["café", "naïve 日本 🦀"]);
+
+    // The server is still there afterwards
+    client.look_up("/café").unwrap();
+}
+
+#[test]
+fn set_attributes() {
+    let root = TempDir::new("nfs4_server").unwrap();
+    fs::write(root.path().join("a_file"), b"some data").unwrap();
+    let mut client = start(&root);
+
+    let handle = client.look_up("/a_file").unwrap();
+    let attrs = FileAttributesBuilder::new().mode(0o600).size(4).build();
+    let set = client.set_attr(handle.clone(), attrs).unwrap();
+    assert_eq!(
+        set,
+        [FileAttributeId::Size, FileAttributeId::Mode]
+            .into_iter()
+            .collect()
+    );
+
+    let metadata = fs::metadata(root.path().join("a_file")).unwrap();
+    assert_eq!(metadata.len(), 4);
+    assert_eq!(
+        std::os::unix::fs::PermissionsExt::mode(&metadata.permissions()) & 0o777,
+        0o600
+    );
+
+    let read_only = FileAttributesBuilder::new()
+        .attr(FileAttribute::NumLinks(2))
+        .build();
+    assert_eq!(
+        client.set_attr(handle, read_only).unwrap_err().status(),
+        Some(StatusError::Inval)
+    );
+}
+
+#[test]
+fn stale_handle() {
+    let root = TempDir::new("nfs4_server").unwrap();
+    fs::write(root.path().join("a_file"), b"").unwrap();
+    let mut client = start(&root);
+
+    let handle = client.look_up("/a_file").unwrap();
+    fs::remove_file(root.path().join("a_file")).unwrap();
+    assert!(client.get_attr(handle).unwrap_err().is_stale());
+}
@@ -1,8 +1,16 @@
 // Copyright 2023 Remi Bernotavicius
 
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
 use serde::{Deserialize, Serialize};
 use xdr_extras::{DeserializeWithDiscriminant, SerializeWithDiscriminant};
 
+pub mod gss;
+pub mod server;
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Xid(pub u32);
 
@@ -101,7 +109,7 @@ impl OpaqueAuth {
     pub fn auth_sys(params: AuthSysParameters) -> Self {
         Self {
             flavor: AuthFlavor::Sys,
-            body: serde_xdr::to_bytes(&params).unwrap(),
+            body: xdr_extras::to_bytes(&params).unwrap(),
         }
     }
 }
@@ -132,6 +140,11 @@ pub enum AuthStat {
      */
     InvalidResp = 6, /* bogus response verifier        */
     Failed = 7,      /* reason unknown                 */
+    /*
+     * RPCSEC_GSS errors
+     */
+    GssCredProblem = 13,    /* no credentials for user        */
+    GssContextProblem = 14, /* problem with context        */
 }
 
 #[derive(
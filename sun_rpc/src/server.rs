@@ -0,0 +1,377 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Serving RPC programs. This only deals with the messages, receiving calls and sending back the
+//! replies is left to whatever transport they arrive on.
+
+use super::gss::{
+    self, GssAcceptor, GssCredential, GssInitRes, GssProcedure, GssService, GssStep,
+    GSS_S_COMPLETE, GSS_S_CONTINUE_NEEDED, MAX_SEQUENCE, RPCSEC_GSS_VERSION,
+};
+use super::{
+    AcceptedReply, AcceptedReplyBody, AuthFlavor, AuthStat, CallBody, Message, MessageBody,
+    OpaqueAuth, RejectedReply, ReplyBody, Xid,
+};
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use serde::Deserialize as _;
+use serde_bytes::Bytes;
+
+pub const RPC_VERSION: u32 = 2;
+
+/// How many calls we tell callers with RPCSEC_GSS they can have outstanding.
+const GSS_SEQUENCE_WINDOW: u32 = 128;
+
+/// How the caller of a call authenticated.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Caller {
+    pub flavor: AuthFlavor,
+    /// For RPCSEC_GSS, how the arguments and results are protected
+    pub service: Option<GssService>,
+}
+
+pub trait Program {
+    /// The program number calls have to be addressed to.
+    fn program(&self) -> u32;
+
+    /// The lowest and highest version supported.
+    fn versions(&self) -> (u32, u32);
+
+    /// Handles a call given its XDR encoded arguments. A successful reply carries the XDR encoded
+    /// results.
+    fn call(&mut self, version: u32, procedure: u32, args: &[u8]) -> AcceptedReplyBody<Vec<u8>>;
+
+    /// Like `call`, for programs which care how the caller authenticated.
+    fn call_from(
+        &mut self,
+        caller: &Caller,
+        version: u32,
+        procedure: u32,
+        args: &[u8],
+    ) -> AcceptedReplyBody<Vec<u8>> {
+        let _ = caller;
+        self.call(version, procedure, args)
+    }
+}
+
+struct AcceptedContext {
+    acceptor: Box<dyn GssAcceptor>,
+    established: bool,
+}
+
+/// The RPCSEC_GSS contexts callers established, see `serve_call_with_gss`. Calls are taken in
+/// any order, replays of them within the sequence window aren't noticed.
+pub struct GssContexts {
+    accept: Box<dyn FnMut() -> Box<dyn GssAcceptor> + Send>,
+    contexts: BTreeMap<Vec<u8>, AcceptedContext>,
+    next_handle: u64,
+}
+
+impl GssContexts {
+    /// Accepts contexts with what the given function returns, which is called for each one.
+    pub fn new(accept: impl FnMut() -> Box<dyn GssAcceptor> + Send + 'static) -> Self {
+        Self {
+            accept: Box::new(accept),
+            contexts: BTreeMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    /// Takes the next token of a context being established, returning the verifier and results
+    /// of the reply.
+    fn establish(
+        &mut self,
+        credential: &GssCredential,
+        token: &[u8],
+    ) -> Result<(OpaqueAuth, Vec<u8>), AuthStat> {
+        let handle = match credential.procedure {
+            GssProcedure::Init => {
+                let handle = self.next_handle.to_be_bytes().to_vec();
+                self.next_handle += 1;
+                let acceptor = (self.accept)();
+                self.contexts.insert(
+                    handle.clone(),
+                    AcceptedContext {
+                        acceptor,
+                        established: false,
+                    },
+                );
+                handle
+            }
+            _ => credential.handle.clone(),
+        };
+        let context = self
+            .contexts
+            .get_mut(&handle)
+            .filter(|context| !context.established)
+            .ok_or(AuthStat::GssCredProblem)?;
+
+        let mut verifier = OpaqueAuth::none();
+        let res = match context.acceptor.accept_sec_context(token) {
+            Ok(GssStep::Complete(token)) => {
+                context.established = true;
+                verifier = gss::verifier(&mut *context.acceptor, GSS_SEQUENCE_WINDOW)
+                    .map_err(|_| AuthStat::GssContextProblem)?;
+                GssInitRes {
+                    handle,
+                    major: GSS_S_COMPLETE,
+                    minor: 0,
+                    sequence_window: GSS_SEQUENCE_WINDOW,
+                    token,
+                }
+            }
+            Ok(GssStep::Continue(token)) => GssInitRes {
+                handle,
+                major: GSS_S_CONTINUE_NEEDED,
+                minor: 0,
+                sequence_window: 0,
+                token,
+            },
+            Err(error) => {
+                self.contexts.remove(&handle);
+                GssInitRes {
+                    handle: vec![],
+                    major: error.major,
+                    minor: error.minor,
+                    sequence_window: 0,
+                    token: vec![],
+                }
+            }
+        };
+        Ok((verifier, xdr_extras::to_bytes(&res).unwrap()))
+    }
+}
+
+fn accepted(verifier: OpaqueAuth, body: AcceptedReplyBody<()>) -> ReplyBody<()> {
+    ReplyBody::Accepted(AcceptedReply { verifier, body })
+}
+
+/// Splits the results off the reply body, they are appended to the reply as they are rather than
+/// going through serde.
+fn split_results(body: AcceptedReplyBody<Vec<u8>>) -> (AcceptedReplyBody<()>, Vec<u8>) {
+    let body = match body {
+        AcceptedReplyBody::Success(results) => return (AcceptedReplyBody::Success(()), results),
+        AcceptedReplyBody::ProgramUnavailable => AcceptedReplyBody::ProgramUnavailable,
+        AcceptedReplyBody::ProgramMismatch { low, high } => {
+            AcceptedReplyBody::ProgramMismatch { low, high }
+        }
+        AcceptedReplyBody::ProcedureUnavailable => AcceptedReplyBody::ProcedureUnavailable,
+        AcceptedReplyBody::GarbageArguments => AcceptedReplyBody::GarbageArguments,
+        AcceptedReplyBody::SystemError => AcceptedReplyBody::SystemError,
+    };
+    (body, vec![])
+}
+
+/// Handles the given call message, returning the reply message to send back. Returns `None` when
+/// the message isn't a call, or is too mangled to reply to. Calls with RPCSEC_GSS are rejected.
+pub fn serve_call<ProgramT: Program + ?Sized>(
+    program: &mut ProgramT,
+    message: &[u8],
+) -> Option<Vec<u8>> {
+    serve(program, None, message)
+}
+
+/// Like `serve_call`, also taking calls with RPCSEC_GSS using the given contexts.
+pub fn serve_call_with_gss<ProgramT: Program + ?Sized>(
+    program: &mut ProgramT,
+    gss: &mut GssContexts,
+    message: &[u8],
+) -> Option<Vec<u8>> {
+    serve(program, Some(gss), message)
+}
+
+fn serve<ProgramT: Program + ?Sized>(
+    program: &mut ProgramT,
+    gss: Option<&mut GssContexts>,
+    message: &[u8],
+) -> Option<Vec<u8>> {
+    let mut deserializer = xdr_extras::Deserializer::new(message);
+    let Message {
+        xid,
+        body: MessageBody::Call(call),
+    } = Message::<()>::deserialize(&mut deserializer).ok()?
+    else {
+        return None;
+    };
+    let args = deserializer.remaining();
+
+    let (low, high) = program.versions();
+    let mut results = vec![];
+    let body = if call.rpc_version != RPC_VERSION {
+        ReplyBody::Denied(RejectedReply::RpcMismatch {
+            low: RPC_VERSION,
+            high: RPC_VERSION,
+        })
+    } else if call.program != program.program() {
+        accepted(OpaqueAuth::none(), AcceptedReplyBody::ProgramUnavailable)
+    } else if !(low..=high).contains(&call.version) {
+        accepted(
+            OpaqueAuth::none(),
+            AcceptedReplyBody::ProgramMismatch { low, high },
+        )
+    } else if call.credential.flavor == AuthFlavor::RpcSecGss {
+        match serve_gss(program, gss, &xid, &call, args) {
+            Ok((verifier, body, gss_results)) => {
+                results = gss_results;
+                accepted(verifier, body)
+            }
+            Err(stat) => ReplyBody::Denied(RejectedReply::AuthError(stat)),
+        }
+    } else {
+        let caller = Caller {
+            flavor: call.credential.flavor.clone(),
+            service: None,
+        };
+        let (body, call_results) =
+            split_results(program.call_from(&caller, call.version, call.procedure, args));
+        results = call_results;
+        accepted(OpaqueAuth::none(), body)
+    };
+
+    let reply = Message {
+        xid,
+        body: MessageBody::Reply(body),
+    };
+    let mut serialized = xdr_extras::to_bytes(&reply).ok()?;
+    serialized.extend(results);
+    Some(serialized)
+}
+
+/// Handles a call with RPCSEC_GSS, returning the verifier, body and results of the reply, or why
+/// the credential isn't any good.
+fn serve_gss<ProgramT: Program + ?Sized>(
+    program: &mut ProgramT,
+    contexts: Option<&mut GssContexts>,
+    xid: &Xid,
+    call: &CallBody<()>,
+    args: &[u8],
+) -> Result<(OpaqueAuth, AcceptedReplyBody<()>, Vec<u8>), AuthStat> {
+    let credential: GssCredential =
+        xdr_extras::from_bytes(&call.credential.body).map_err(|_| AuthStat::BadCred)?;
+    let contexts = contexts.ok_or(AuthStat::BadCred)?;
+    if credential.version != RPCSEC_GSS_VERSION {
+        return Err(AuthStat::BadCred);
+    }
+
+    if let GssProcedure::Init | GssProcedure::ContinueInit = credential.procedure {
+        // Contexts are established with calls to the NULL procedure
+        if call.procedure != 0 {
+            return Err(AuthStat::BadCred);
+        }
+        let Ok(token) = xdr_extras::from_bytes::<&Bytes>(args) else {
+            let garbage = AcceptedReplyBody::GarbageArguments;
+            return Ok((OpaqueAuth::none(), garbage, vec![]));
+        };
+        let (verifier, results) = contexts.establish(&credential, token)?;
+        return Ok((verifier, AcceptedReplyBody::Success(()), results));
+    }
+
+    let context = contexts
+        .contexts
+        .get_mut(&credential.handle)
+        .filter(|context| context.established)
+        .ok_or(AuthStat::GssCredProblem)?;
+    let acceptor = &mut *context.acceptor;
+    if credential.sequence >= MAX_SEQUENCE {
+        return Err(AuthStat::GssContextProblem);
+    }
+    let header = gss::call_header(
+        xid,
+        call.program,
+        call.version,
+        call.procedure,
+        &call.credential,
+    );
+    acceptor
+        .verify_mic(&header, &call.verifier.body)
+        .map_err(|_| AuthStat::GssCredProblem)?;
+    let verifier =
+        gss::verifier(acceptor, credential.sequence).map_err(|_| AuthStat::GssContextProblem)?;
+
+    if credential.procedure == GssProcedure::Destroy {
+        contexts.contexts.remove(&credential.handle);
+        return Ok((verifier, AcceptedReplyBody::Success(()), vec![]));
+    }
+
+    let sequence = credential.sequence;
+    let Ok(args) = gss::unwrap_data(acceptor, credential.service, sequence, args) else {
+        return Ok((verifier, AcceptedReplyBody::GarbageArguments, vec![]));
+    };
+    let caller = Caller {
+        flavor: AuthFlavor::RpcSecGss,
+        service: Some(credential.service),
+    };
+    let (body, results) =
+        split_results(program.call_from(&caller, call.version, call.procedure, &args));
+    if body != AcceptedReplyBody::Success(()) {
+        return Ok((verifier, body, results));
+    }
+    match gss::wrap_data(acceptor, credential.service, sequence, &results) {
+        Ok(wrapped) => Ok((verifier, body, wrapped.into_owned())),
+        Err(_) => Ok((verifier, AcceptedReplyBody::SystemError, vec![])),
+    }
+}
+
+#[test]
+fn serve_calls() {
+    struct Echo;
+
+    impl Program for Echo {
+        fn program(&self) -> u32 {
+            7
+        }
+
+        fn versions(&self) -> (u32, u32) {
+            (1, 2)
+        }
+
+        fn call(
+            &mut self,
+            _version: u32,
+            procedure: u32,
+            args: &[u8],
+        ) -> AcceptedReplyBody<Vec<u8>> {
+            match procedure {
+                0 => AcceptedReplyBody::Success(args.to_vec()),
+                _ => AcceptedReplyBody::ProcedureUnavailable,
+            }
+        }
+    }
+
+    let call = |program, version, procedure| {
+        let message = Message {
+            xid: Xid(3),
+            body: MessageBody::Call(CallBody {
+                rpc_version: RPC_VERSION,
+                program,
+                version,
+                procedure,
+                credential: OpaqueAuth::none(),
+                verifier: OpaqueAuth::none(),
+                call_args: 42u32,
+            }),
+        };
+        let reply = serve_call(&mut Echo, &serde_xdr::to_bytes(&message).unwrap()).unwrap();
+        let reply: Message<u32> = serde_xdr::from_bytes(reply).unwrap();
+        assert_eq!(reply.xid, Xid(3));
+        let MessageBody::Reply(ReplyBody::Accepted(accepted)) = reply.body else {
+            panic!("unexpected reply {reply:?}");
+        };
+        accepted.body
+    };
+
+    assert_eq!(call(7, 2, 0), AcceptedReplyBody::Success(42));
+    assert_eq!(call(7, 1, 1), AcceptedReplyBody::ProcedureUnavailable);
+    assert_eq!(
+        call(7, 3, 0),
+        AcceptedReplyBody::ProgramMismatch { low: 1, high: 2 }
+    );
+    assert_eq!(call(8, 1, 0), AcceptedReplyBody::ProgramUnavailable);
+
+    let reply = Message::<()> {
+        xid: Xid(3),
+        body: MessageBody::Reply(accepted(OpaqueAuth::none(), AcceptedReplyBody::Success(()))),
+    };
+    assert_eq!(
+        serve_call(&mut Echo, &serde_xdr::to_bytes(&reply).unwrap()),
+        None
+    );
+}
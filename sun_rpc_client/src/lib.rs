@@ -1,19 +1,50 @@
 // Copyright 2023 Remi Bernotavicius
 
+use bytes::Bytes;
 use derive_more::From;
-use serde::{de::DeserializeOwned, Serialize};
+use record::RecordAssembler;
+use serde::{de::Deserialize as _, de::DeserializeOwned, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read as _, Write as _};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use std::{fmt, io};
-use sun_rpc::{
-    AcceptedReplyBody, AuthSysParameters, CallBody, Gid, Message, MessageBody, OpaqueAuth,
-    ReplyBody, Uid, Xid,
+use sun_rpc::gss::{
+    self, GssCredential, GssInitRes, GssProcedure, GSS_S_COMPLETE, GSS_S_CONTEXT_EXPIRED,
+    GSS_S_CONTINUE_NEEDED, GSS_S_DEFECTIVE_TOKEN, MAX_SEQUENCE, RPCSEC_GSS_VERSION,
 };
+use sun_rpc::{server::serve_call, AuthFlavor, CallBody, Message, MessageBody, ReplyBody};
+
+pub use sun_rpc::gss::{GssContext, GssError, GssInitiator, GssService, GssStep};
+pub use sun_rpc::{
+    server::Program, AcceptedReplyBody, AuthSysParameters, Gid, OpaqueAuth, Uid, Xid,
+};
+
+pub use record::{
+    encode_record, write_record, RecordReader, RecordWriter, DEFAULT_FRAGMENT_SIZE,
+    MAX_FRAGMENT_SIZE,
+};
+pub use replay::{Recording, Replay};
+pub use udp::{UdpTransport, DEFAULT_MAX_RETRANSMISSIONS, DEFAULT_RETRANSMIT_TIMEOUT};
+pub use xdr_extras::Limits;
+
+pub mod mount;
+pub mod portmap;
+mod record;
+mod replay;
+mod udp;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, From)]
 pub enum Error {
-    Deseralization(serde_xdr::CompatDeserializationError),
-    Serialization(serde_xdr::CompatSerializationError),
+    /// Encoding a call or decoding a reply failed.
+    Xdr(xdr_extras::Error),
     Io(io::Error),
     ProgramUnavailable,
     ProgramMismatch,
@@ -22,84 +53,607 @@ pub enum Error {
     SystemError,
     #[from(ignore)]
     UnexpectedReply(String),
+    /// What the other end sent was larger than the given maximum, see
+    /// `RpcClient::set_max_message_size`, or had a length in it over the `RpcClient::set_limits`
+    /// ones. For the former the transport is left part way through it, so it can't be used
+    /// anymore.
+    #[from(ignore)]
+    MessageTooLarge(usize),
+    /// The RPCSEC_GSS context failed, or the other end didn't protect a reply with it like it
+    /// should have.
+    Gss(GssError),
 }
 
+/// The largest message received by default, well over what NFS sends with the largest READs.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20;
+
 pub trait Transport: io::Read + io::Write {}
 
 impl<T> Transport for T where T: io::Read + io::Write {}
 
+/// Transports whose reads can be made to give up after a while, which is what
+/// `RpcClient::set_reply_timeout` needs. A read which times out fails with `TimedOut` or
+/// `WouldBlock`.
+pub trait ReadTimeout {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for UnixStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+type SetReadTimeout<TransportT> = fn(&mut TransportT, Option<Duration>) -> io::Result<()>;
+
 pub const PORT_MAPPER: u32 = 100000;
 pub const PORT_MAPPER_PORT: u16 = 111;
 pub const NULL_PROCEDURE: u32 = 0;
 
+/// The AUTH_SYS credential requests are sent with unless the client is told otherwise.
+pub fn default_credential() -> OpaqueAuth {
+    OpaqueAuth::auth_sys(AuthSysParameters {
+        stamp: 0,
+        machine_name: "test-machine".into(),
+        uid: Uid(1337),
+        gid: Gid(42),
+        gids: vec![Gid(1337)],
+    })
+}
+
+/// A context established with RPCSEC_GSS, see `RpcClient::establish_gss`. Any number of clients
+/// talking to the same server can use it.
+pub struct GssSession {
+    context: Mutex<Box<dyn GssInitiator>>,
+    handle: Vec<u8>,
+    service: GssService,
+    /// The sequence number of the next call
+    sequence: AtomicU32,
+    /// How many calls the server takes out of sequence order
+    window: u32,
+}
+
+impl fmt::Debug for GssSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GssSession")
+            .field("handle", &self.handle)
+            .field("service", &self.service)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GssSession {
+    /// How the arguments and results of calls are protected.
+    pub fn service(&self) -> GssService {
+        self.service
+    }
+
+    /// How many calls can be in flight at once, the server may drop any more than that.
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    fn context(&self) -> std::sync::MutexGuard<'_, Box<dyn GssInitiator>> {
+        self.context.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Checks the verifier and unwraps the results of the reply to the call with the given
+    /// sequence number. With integrity protection they are part of what was given.
+    fn unwrap_reply(&self, sequence: u32, verifier: &OpaqueAuth, results: &Bytes) -> Result<Bytes> {
+        let mut context = self.context();
+        gss::verify_verifier(&mut **context, sequence, verifier)?;
+        Ok(
+            match gss::unwrap_data(&mut **context, self.service, sequence, results)? {
+                Cow::Borrowed(data) => results.slice_ref(data),
+                Cow::Owned(data) => data.into(),
+            },
+        )
+    }
+}
+
 pub struct RpcClient<TransportT> {
     xid: Xid,
     program: u32,
+    version: u32,
     transport: TransportT,
+    max_fragment_size: usize,
+    max_message_size: usize,
+    limits: Limits,
+    credential: OpaqueAuth,
+    gss: Option<Arc<GssSession>>,
+    /// The RPCSEC_GSS context and sequence number of the calls in flight which were sent with one
+    gss_calls: BTreeMap<u32, (Arc<GssSession>, u32)>,
+    served_program: Option<Box<dyn Program + Send>>,
+    received: RecordAssembler,
+    /// What requests are serialized into, kept so that sending one doesn't allocate.
+    send_buffer: Vec<u8>,
+    /// The XIDs of the calls we are waiting for a reply to.
+    in_flight: BTreeSet<u32>,
+    /// The XIDs of the calls we gave up waiting for, whose replies are dropped if they still come.
+    abandoned: BTreeSet<u32>,
+    reply_timeout: Option<Duration>,
+    set_read_timeout: Option<SetReadTimeout<TransportT>>,
 }
 
 impl<TransportT: Transport> RpcClient<TransportT> {
     pub fn new(transport: TransportT, program: u32) -> Self {
+        Self::with_version(transport, program, 4)
+    }
+
+    pub fn with_version(transport: TransportT, program: u32, version: u32) -> Self {
         Self {
             xid: Xid(1),
             program,
+            version,
             transport,
+            max_fragment_size: DEFAULT_FRAGMENT_SIZE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            limits: Limits::default(),
+            credential: default_credential(),
+            gss: None,
+            gss_calls: BTreeMap::new(),
+            served_program: None,
+            received: RecordAssembler::default(),
+            send_buffer: vec![],
+            in_flight: BTreeSet::new(),
+            abandoned: BTreeSet::new(),
+            reply_timeout: None,
+            set_read_timeout: None,
         }
     }
 
-    pub fn send_request<T: Serialize>(&mut self, procedure: u32, call_args: T) -> Result<()> {
+    /// Sets the credential sent with every request from now on.
+    pub fn set_credential(&mut self, credential: OpaqueAuth) {
+        self.credential = credential;
+    }
+
+    pub fn credential(&self) -> &OpaqueAuth {
+        &self.credential
+    }
+
+    /// Establishes a context with the server with RPCSEC_GSS, using the given side of it, and
+    /// sends every request from now on with it instead of `credential`. Nothing can be in flight.
+    pub fn establish_gss(
+        &mut self,
+        mut initiator: Box<dyn GssInitiator>,
+        service: GssService,
+    ) -> Result<Arc<GssSession>> {
+        let mut reply: Option<(GssInitRes, OpaqueAuth)> = None;
+        let (res, verifier) = loop {
+            let input = reply.as_ref().map(|(res, _)| &res.token[..]);
+            let (token, complete) = match initiator.init_sec_context(input)? {
+                GssStep::Continue(token) => (token, false),
+                GssStep::Complete(token) => (token, true),
+            };
+            match reply {
+                // The server was done first, and only had to tell us something
+                Some((res, verifier)) if res.major == GSS_S_COMPLETE => {
+                    if !complete || !token.is_empty() {
+                        return Err(GssError::new(GSS_S_DEFECTIVE_TOKEN).into());
+                    }
+                    break (res, verifier);
+                }
+                _ => {}
+            }
+
+            let credential = GssCredential {
+                version: RPCSEC_GSS_VERSION,
+                procedure: match &reply {
+                    None => GssProcedure::Init,
+                    Some(_) => GssProcedure::ContinueInit,
+                },
+                sequence: 0,
+                service,
+                handle: reply.map(|(res, _)| res.handle).unwrap_or_default(),
+            };
+            self.serialize_message(
+                NULL_PROCEDURE,
+                credential.to_opaque_auth(),
+                OpaqueAuth::none(),
+                serde_bytes::Bytes::new(&token),
+            )?;
+            write_record(
+                &mut self.transport,
+                &self.send_buffer,
+                self.max_fragment_size,
+            )?;
+            self.sent();
+            let (_, verifier, res): (_, _, GssInitRes) = self.receive_reply_with_verifier()?;
+            match res.major {
+                GSS_S_COMPLETE if complete => break (res, verifier),
+                GSS_S_COMPLETE | GSS_S_CONTINUE_NEEDED if !complete => {
+                    reply = Some((res, verifier));
+                }
+                major => {
+                    let minor = res.minor;
+                    return Err(GssError { major, minor }.into());
+                }
+            }
+        };
+
+        gss::verify_verifier(&mut *initiator, res.sequence_window, &verifier)?;
+        let session = Arc::new(GssSession {
+            context: Mutex::new(initiator),
+            handle: res.handle,
+            service,
+            sequence: AtomicU32::new(0),
+            window: res.sequence_window,
+        });
+        self.gss = Some(session.clone());
+        Ok(session)
+    }
+
+    /// Sends every request from now on with the given RPCSEC_GSS context, or with `credential`
+    /// again given `None`.
+    pub fn set_gss(&mut self, gss: Option<Arc<GssSession>>) {
+        self.gss = gss;
+    }
+
+    pub fn gss(&self) -> Option<&Arc<GssSession>> {
+        self.gss.as_ref()
+    }
+
+    /// Serves calls to the given program which the other end sends over our transport while we
+    /// wait for replies, like NFSv4.1 callbacks arriving over the backchannel.
+    pub fn serve(&mut self, program: impl Program + Send + 'static) {
+        self.served_program = Some(Box::new(program));
+    }
+
+    /// Makes waiting for a reply fail with `TimedOut` once nothing has come for the given time,
+    /// instead of waiting forever on a server which stopped answering. Every call still waiting for
+    /// a reply then is given up on, and replies to them which come later are dropped.
+    pub fn set_reply_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>
+    where
+        TransportT: ReadTimeout,
+    {
+        self.transport.set_read_timeout(None)?;
+        self.reply_timeout = timeout;
+        self.set_read_timeout = Some(TransportT::set_read_timeout);
+        Ok(())
+    }
+
+    pub fn reply_timeout(&self) -> Option<Duration> {
+        self.reply_timeout
+    }
+
+    /// Gives this client the same reply timeout as the other one, for when both talk to the same
+    /// server.
+    pub fn copy_reply_timeout(&mut self, other: &Self) -> io::Result<()> {
+        if let Some(set_read_timeout) = other.set_read_timeout {
+            set_read_timeout(&mut self.transport, None)?;
+        }
+        self.reply_timeout = other.reply_timeout;
+        self.set_read_timeout = other.set_read_timeout;
+        Ok(())
+    }
+
+    pub fn set_max_fragment_size(&mut self, size: usize) {
+        assert!(size > 0 && size <= MAX_FRAGMENT_SIZE);
+        self.max_fragment_size = size;
+    }
+
+    /// Makes receiving a message larger than the given size fail with `Error::MessageTooLarge`
+    /// once its fragment headers say it will be, before any more of it is read, so that the other
+    /// end can't make us use up memory.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Makes replies with longer opaque data or arrays in them than the given limits fail with
+    /// `Error::MessageTooLarge`. Whatever the limits, lengths longer than the rest of the message
+    /// fail to decode.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Serializes a call with the given credential and verifier into `send_buffer`.
+    fn serialize_message<T: Serialize>(
+        &mut self,
+        procedure: u32,
+        credential: OpaqueAuth,
+        verifier: OpaqueAuth,
+        call_args: T,
+    ) -> Result<()> {
         let message = Message {
             xid: self.xid.clone(),
             body: MessageBody::Call(CallBody {
                 rpc_version: 2,
                 program: self.program,
-                version: 4,
+                version: self.version,
                 procedure,
-                credential: OpaqueAuth::auth_sys(AuthSysParameters {
-                    stamp: 0,
-                    machine_name: "test-machine".into(),
-                    uid: Uid(1337),
-                    gid: Gid(42),
-                    gids: vec![Gid(1337)],
-                }),
-                verifier: OpaqueAuth::none(),
+                credential,
+                verifier,
                 call_args,
             }),
         };
-        let mut serialized = vec![0; 4];
-        serde_xdr::to_writer(&mut serialized, &message)?;
-
-        let fragment_header = (serialized.len() - 4) as u32 | 0x1 << 31;
-        serde_xdr::to_writer(&mut &mut serialized[..4], &fragment_header)?;
+        self.send_buffer.clear();
+        xdr_extras::to_bytes_into(&message, &mut self.send_buffer)?;
+        Ok(())
+    }
 
-        self.transport.write_all(&serialized[..])?;
+    /// Serializes the call into `send_buffer`. Any data given goes in the empty variable-length
+    /// opaque the arguments end with, see `send_request_streaming`.
+    fn serialize_call<T: Serialize>(
+        &mut self,
+        procedure: u32,
+        call_args: T,
+        data: Option<&[u8]>,
+    ) -> Result<()> {
+        let Some(session) = self.gss.clone() else {
+            self.serialize_message(procedure, self.credential.clone(), OpaqueAuth::none(), ())?;
+            return self.serialize_args(call_args, data);
+        };
 
-        self.xid = Xid(self.xid.0 + 1);
+        let sequence = session.sequence.fetch_add(1, Ordering::Relaxed);
+        if sequence >= MAX_SEQUENCE {
+            return Err(GssError::new(GSS_S_CONTEXT_EXPIRED).into());
+        }
+        let credential = GssCredential {
+            version: RPCSEC_GSS_VERSION,
+            procedure: GssProcedure::Data,
+            sequence,
+            service: session.service,
+            handle: session.handle.clone(),
+        }
+        .to_opaque_auth();
+        let header = gss::call_header(
+            &self.xid,
+            self.program,
+            self.version,
+            procedure,
+            &credential,
+        );
+        let mut context = session.context();
+        let verifier = OpaqueAuth {
+            flavor: AuthFlavor::RpcSecGss,
+            body: context.get_mic(&header)?,
+        };
+        self.serialize_message(procedure, credential, verifier, ())?;
+        let start = self.send_buffer.len();
+        self.serialize_args(call_args, data)?;
+        if session.service != GssService::None {
+            let args = &self.send_buffer[start..];
+            let wrapped = gss::wrap_data(&mut **context, session.service, sequence, args)?;
+            let wrapped = wrapped.into_owned();
+            self.send_buffer.truncate(start);
+            self.send_buffer.extend(wrapped);
+        }
+        drop(context);
+        self.gss_calls
+            .insert(self.xid.0, (session.clone(), sequence));
+        Ok(())
+    }
 
+    /// Appends the arguments to `send_buffer`, with any data given in the empty variable-length
+    /// opaque they end with.
+    fn serialize_args<T: Serialize>(&mut self, call_args: T, data: Option<&[u8]>) -> Result<()> {
+        xdr_extras::to_bytes_into(&call_args, &mut self.send_buffer)?;
+        if let Some(data) = data {
+            assert!(
+                self.send_buffer.ends_with(&[0; 4]),
+                "the arguments must end with an empty opaque"
+            );
+            let length_at = self.send_buffer.len() - 4;
+            self.send_buffer.truncate(length_at);
+            xdr_extras::to_bytes_into(serde_bytes::Bytes::new(data), &mut self.send_buffer)?;
+        }
         Ok(())
     }
 
-    pub fn receive_reply<T: DeserializeOwned + fmt::Debug>(&mut self) -> Result<T> {
-        let fragment_header: u32 = serde_xdr::from_reader(&mut self.transport)?;
-        let length = fragment_header & !(0x1 << 31);
-        let reply: Message<T> =
-            serde_xdr::from_reader(&mut io::Read::take(&mut self.transport, length as u64))?;
-
-        if let Message {
-            body: MessageBody::Reply(ReplyBody::Accepted(accepted_reply)),
-            ..
-        } = reply
+    pub fn send_request<T: Serialize>(&mut self, procedure: u32, call_args: T) -> Result<Xid> {
+        self.serialize_call(procedure, call_args, None)?;
+        write_record(
+            &mut self.transport,
+            &self.send_buffer,
+            self.max_fragment_size,
+        )?;
+        Ok(self.sent())
+    }
+
+    /// Like `send_request`, but with `len` bytes of the arguments streamed from the reader as
+    /// they are sent, so that requests carrying lots of data don't need all of it in memory. The
+    /// arguments have to end with an empty variable-length opaque, which is where the data goes.
+    /// If the reader fails or has less than `len` bytes, the transport is left part way through
+    /// the request and can't be used anymore. Arguments protected with RPCSEC_GSS have to be all
+    /// there to be protected, so then the data is read before any of it is sent.
+    pub fn send_request_streaming<T: Serialize>(
+        &mut self,
+        procedure: u32,
+        call_args: T,
+        data: impl io::Read,
+        len: u32,
+    ) -> Result<Xid> {
+        if self
+            .gss
+            .as_ref()
+            .is_some_and(|session| session.service != GssService::None)
         {
-            match accepted_reply.body {
-                AcceptedReplyBody::Success(b) => Ok(b),
-                AcceptedReplyBody::ProgramUnavailable => Err(Error::ProgramUnavailable),
-                AcceptedReplyBody::ProgramMismatch { .. } => Err(Error::ProgramMismatch),
-                AcceptedReplyBody::ProcedureUnavailable => Err(Error::ProcedureUnavailable),
-                AcceptedReplyBody::GarbageArguments => Err(Error::GarbageArguments),
-                AcceptedReplyBody::SystemError => Err(Error::SystemError),
+            let mut buffer = Vec::with_capacity(len as usize);
+            data.take(len.into()).read_to_end(&mut buffer)?;
+            if buffer.len() < len as usize {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            self.serialize_call(procedure, call_args, Some(&buffer))?;
+            write_record(
+                &mut self.transport,
+                &self.send_buffer,
+                self.max_fragment_size,
+            )?;
+            return Ok(self.sent());
+        }
+
+        self.serialize_call(procedure, call_args, None)?;
+        assert!(
+            self.send_buffer.ends_with(&[0; 4]),
+            "the arguments must end with an empty opaque"
+        );
+        let length_at = self.send_buffer.len() - 4;
+        self.send_buffer[length_at..].copy_from_slice(&len.to_be_bytes());
+
+        let padding = (4 - len as usize % 4) % 4;
+        let record_len = (self.send_buffer.len() + padding) as u64 + u64::from(len);
+        let mut record = RecordWriter::new(&mut self.transport, record_len, self.max_fragment_size);
+        record.write_all(&self.send_buffer)?;
+        let streamed = io::copy(&mut data.take(len.into()), &mut record)?;
+        if streamed < len.into() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        record.write_all(&[0; 3][..padding])?;
+        record.finish()?;
+        Ok(self.sent())
+    }
+
+    /// Moves on to the next XID after a request went out, returning the one it had.
+    fn sent(&mut self) -> Xid {
+        let xid = self.xid.clone();
+        self.xid = Xid(self.xid.0 + 1);
+        self.in_flight.insert(xid.0);
+        xid
+    }
+
+    pub fn receive_reply<T: DeserializeOwned + fmt::Debug>(&mut self) -> Result<T> {
+        Ok(self.receive_reply_with_xid()?.1)
+    }
+
+    /// Sends a request and waits for its reply.
+    pub fn call<Args, Res>(&mut self, procedure: u32, args: Args) -> Result<Res>
+    where
+        Args: Serialize,
+        Res: DeserializeOwned + fmt::Debug,
+    {
+        self.send_request(procedure, args)?;
+        self.receive_reply()
+    }
+
+    /// Receives the next reply, which when multiple requests are outstanding may not be the reply
+    /// to the request sent first.
+    pub fn receive_reply_with_xid<T: DeserializeOwned + fmt::Debug>(&mut self) -> Result<(Xid, T)> {
+        let (xid, _, reply) = self.receive_reply_with_verifier()?;
+        Ok((xid, reply))
+    }
+
+    /// Like `receive_reply_with_xid`, along with the verifier of the reply.
+    fn receive_reply_with_verifier<T: DeserializeOwned + fmt::Debug>(
+        &mut self,
+    ) -> Result<(Xid, OpaqueAuth, T)> {
+        let deadline = self.reply_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let message = self.receive_message(deadline)?;
+
+            // The message type comes right after the XID
+            if message.get(4..8) != Some(&0u32.to_be_bytes()[..]) {
+                let xid = message
+                    .get(..4)
+                    .map_or(0, |xid| u32::from_be_bytes(xid.try_into().unwrap()));
+                if self.abandoned.remove(&xid) {
+                    self.gss_calls.remove(&xid);
+                    continue;
+                }
+                self.in_flight.remove(&xid);
+                // Opaque data in the reply, like what READ returns, refers to the message
+                // rather than being copied out of it, and then the message can't be recycled
+                let message = Bytes::from(message);
+                let reply = self.decode_reply(xid, &message);
+                if let Ok(message) = message.try_into_mut() {
+                    self.received.recycle(message.into());
+                }
+                return reply;
+            }
+
+            // Calls nobody is serving are dropped, the other end will eventually give up on them
+            let program = self.served_program.as_deref_mut();
+            let reply = program.and_then(|p| serve_call(p, &message));
+            self.received.recycle(message);
+            if let Some(reply) = reply {
+                write_record(&mut self.transport, &reply, self.max_fragment_size)?;
+            }
+        }
+    }
+
+    /// Decodes the reply with the given XID, unwrapping the results if the call was sent with
+    /// RPCSEC_GSS.
+    fn decode_reply<T: DeserializeOwned + fmt::Debug>(
+        &mut self,
+        xid: u32,
+        message: &Bytes,
+    ) -> Result<(Xid, OpaqueAuth, T)> {
+        let decode_error = |error| match error {
+            xdr_extras::Error::LimitExceeded { limit, .. } => Error::MessageTooLarge(limit),
+            error => Error::Xdr(error),
+        };
+        let gss_call = self.gss_calls.remove(&xid);
+        let mut deserializer = xdr_extras::Deserializer::with_limits(message, self.limits);
+        let header = Message::<()>::deserialize(&mut deserializer).map_err(decode_error)?;
+        let results = message.slice(message.len() - deserializer.remaining().len()..);
+
+        let (xid, accepted_reply) = match header {
+            Message {
+                xid,
+                body: MessageBody::Reply(ReplyBody::Accepted(accepted_reply)),
+            } => (xid, accepted_reply),
+            header => return Err(Error::UnexpectedReply(format!("{header:?}"))),
+        };
+        match accepted_reply.body {
+            AcceptedReplyBody::Success(()) => {}
+            AcceptedReplyBody::ProgramUnavailable => return Err(Error::ProgramUnavailable),
+            AcceptedReplyBody::ProgramMismatch { .. } => return Err(Error::ProgramMismatch),
+            AcceptedReplyBody::ProcedureUnavailable => return Err(Error::ProcedureUnavailable),
+            AcceptedReplyBody::GarbageArguments => return Err(Error::GarbageArguments),
+            AcceptedReplyBody::SystemError => return Err(Error::SystemError),
+        }
+        let results = match gss_call {
+            Some((session, sequence)) => {
+                session.unwrap_reply(sequence, &accepted_reply.verifier, &results)?
+            }
+            None => results,
+        };
+        let reply = xdr_extras::from_shared_bytes(&results, self.limits).map_err(decode_error)?;
+        Ok((xid, accepted_reply.verifier, reply))
+    }
+
+    /// Reads the next message, giving up on the calls in flight if it isn't all there by the
+    /// deadline.
+    fn receive_message(&mut self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        loop {
+            if let (Some(deadline), Some(set_read_timeout)) = (deadline, self.set_read_timeout) {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    self.abandoned.append(&mut self.in_flight);
+                    return Err(io::Error::from(io::ErrorKind::TimedOut).into());
+                }
+                set_read_timeout(&mut self.transport, Some(remaining))?;
+            }
+            match self.received.read_from(&mut self.transport) {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) if self.received.size() > self.max_message_size => {
+                    return Err(Error::MessageTooLarge(self.max_message_size));
+                }
+                Ok(None) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e)
+                    if deadline.is_some()
+                        && matches!(
+                            e.kind(),
+                            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                        ) => {}
+                Err(e) => return Err(e.into()),
             }
-        } else {
-            Err(Error::UnexpectedReply(format!("{reply:?}")))
         }
     }
 }
@@ -120,3 +674,237 @@ fn ping() {
         client.receive_reply::<()>().unwrap();
     });
 }
+
+#[test]
+fn unix_socket() {
+    use std::io::Read as _;
+
+    struct Echo;
+
+    impl Program for Echo {
+        fn program(&self) -> u32 {
+            7
+        }
+
+        fn versions(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn call(
+            &mut self,
+            _version: u32,
+            _procedure: u32,
+            args: &[u8],
+        ) -> AcceptedReplyBody<Vec<u8>> {
+            AcceptedReplyBody::Success(args.to_vec())
+        }
+    }
+
+    let (transport, mut server) = UnixStream::pair().unwrap();
+    let thread = std::thread::spawn(move || {
+        let mut message = vec![];
+        RecordReader::new(&mut server)
+            .read_to_end(&mut message)
+            .unwrap();
+        let reply = serve_call(&mut Echo, &message).unwrap();
+        std::io::Write::write_all(&mut server, &encode_record(&reply, DEFAULT_FRAGMENT_SIZE))
+            .unwrap();
+    });
+
+    let mut client = RpcClient::with_version(transport, 7, 1);
+    let reply: u32 = client.call(1, 42u32).unwrap();
+    assert_eq!(reply, 42);
+    thread.join().unwrap();
+}
+
+#[test]
+fn gss() {
+    use std::io::Read as _;
+    use sun_rpc::gss::TestContext;
+    use sun_rpc::server::{serve_call_with_gss, Caller, GssContexts};
+
+    // Echoes the arguments, if they came with RPCSEC_GSS
+    struct Echo;
+
+    impl Program for Echo {
+        fn program(&self) -> u32 {
+            7
+        }
+
+        fn versions(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn call(&mut self, _: u32, _: u32, _: &[u8]) -> AcceptedReplyBody<Vec<u8>> {
+            AcceptedReplyBody::SystemError
+        }
+
+        fn call_from(
+            &mut self,
+            caller: &sun_rpc::server::Caller,
+            _version: u32,
+            _procedure: u32,
+            args: &[u8],
+        ) -> AcceptedReplyBody<Vec<u8>> {
+            match caller {
+                Caller {
+                    flavor: AuthFlavor::RpcSecGss,
+                    service: Some(_),
+                } => AcceptedReplyBody::Success(args.to_vec()),
+                _ => AcceptedReplyBody::SystemError,
+            }
+        }
+    }
+
+    let serve = |key| {
+        let (transport, mut server) = UnixStream::pair().unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut contexts = GssContexts::new(move || Box::new(TestContext::new(key)));
+            loop {
+                let mut message = vec![];
+                if RecordReader::new(&mut server)
+                    .read_to_end(&mut message)
+                    .is_err()
+                {
+                    break;
+                }
+                let reply = serve_call_with_gss(&mut Echo, &mut contexts, &message).unwrap();
+                let record = encode_record(&reply, DEFAULT_FRAGMENT_SIZE);
+                std::io::Write::write_all(&mut server, &record).unwrap();
+            }
+        });
+        (RpcClient::with_version(transport, 7, 1), thread)
+    };
+
+    for service in [GssService::None, GssService::Integrity, GssService::Privacy] {
+        let (mut client, thread) = serve(3);
+        let session = client
+            .establish_gss(Box::new(TestContext::new(3)), service)
+            .unwrap();
+        assert_eq!(session.service(), service);
+        assert_eq!(client.call::<_, u32>(1, 42u32).unwrap(), 42);
+
+        let data = b"some data";
+        let args = (5u32, serde_bytes::ByteBuf::new());
+        client
+            .send_request_streaming(1, args, &data[..], data.len() as u32)
+            .unwrap();
+        let (number, echoed): (u32, serde_bytes::ByteBuf) = client.receive_reply().unwrap();
+        assert_eq!((number, &echoed[..]), (5, &data[..]));
+
+        // A server which didn't establish the context rejects it, and Echo wants one
+        let (mut other, other_thread) = serve(3);
+        other.set_gss(Some(session));
+        let error = other.call::<_, u32>(1, 42u32).unwrap_err();
+        assert!(matches!(error, Error::UnexpectedReply(_)), "{error:?}");
+        other.set_gss(None);
+        let error = other.call::<_, u32>(1, 42u32).unwrap_err();
+        assert!(matches!(error, Error::SystemError), "{error:?}");
+        drop((client, other));
+        thread.join().unwrap();
+        other_thread.join().unwrap();
+    }
+
+    // The server can't prove it has the same key
+    let (mut client, thread) = serve(4);
+    let error = client
+        .establish_gss(Box::new(TestContext::new(3)), GssService::Integrity)
+        .unwrap_err();
+    assert!(
+        matches!(error, Error::Gss(e) if e.major == gss::GSS_S_BAD_MIC),
+        "{error:?}"
+    );
+    drop(client);
+    thread.join().unwrap();
+}
+
+#[test]
+fn reply_too_large() {
+    // A fragment larger than allowed, and the start of endless empty ones
+    for stream in [&[0x80, 0, 0x10, 0][..], &[0; 4 * 30][..]] {
+        let (transport, mut server) = UnixStream::pair().unwrap();
+        server.write_all(stream).unwrap();
+        let mut client = RpcClient::with_version(transport, 7, 1);
+        client.set_max_message_size(100);
+        let error = client.call::<_, u32>(1, 1u32).unwrap_err();
+        assert!(matches!(error, Error::MessageTooLarge(100)), "{error:?}");
+    }
+}
+
+#[test]
+fn reply_lengths() {
+    // A reply to XID 1 whose result is an array said to be almost 4GiB long
+    let mut reply = vec![
+        0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    reply.extend([0xff, 0xff, 0xff, 0xf0]);
+    let (transport, mut server) = UnixStream::pair().unwrap();
+    server
+        .write_all(&encode_record(&reply, DEFAULT_FRAGMENT_SIZE))
+        .unwrap();
+    let mut client = RpcClient::with_version(transport, 7, 1);
+    let error = client.call::<_, Vec<u32>>(1, 1u32).unwrap_err();
+    assert!(
+        matches!(error, Error::Xdr(xdr_extras::Error::UnexpectedEnd)),
+        "{error:?}"
+    );
+
+    let mut reply = reply[..24].to_vec();
+    reply[3] = 2;
+    reply.extend([0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+    server
+        .write_all(&encode_record(&reply, DEFAULT_FRAGMENT_SIZE))
+        .unwrap();
+    client.set_limits(Limits {
+        max_array_len: 2,
+        ..Limits::default()
+    });
+    let error = client.call::<_, Vec<u32>>(1, 1u32).unwrap_err();
+    assert!(matches!(error, Error::MessageTooLarge(2)), "{error:?}");
+}
+
+#[test]
+fn reply_timeout() {
+    use std::io::Read as _;
+    use std::sync::mpsc;
+
+    let (transport, mut server) = UnixStream::pair().unwrap();
+    let (answer, answer_receiver) = mpsc::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        // Replies with the number sent plus one, once told to
+        for () in answer_receiver {
+            let mut message = vec![];
+            RecordReader::new(&mut server)
+                .read_to_end(&mut message)
+                .unwrap();
+            let call: Message<u32> = xdr_extras::from_bytes(&message).unwrap();
+            let MessageBody::Call(body) = call.body else {
+                panic!("expected a call");
+            };
+            let reply = Message {
+                xid: call.xid,
+                body: MessageBody::<u32>::Reply(ReplyBody::Accepted(sun_rpc::AcceptedReply {
+                    verifier: OpaqueAuth::none(),
+                    body: AcceptedReplyBody::Success(body.call_args + 1),
+                })),
+            };
+            let record = encode_record(&xdr_extras::to_bytes(&reply).unwrap(), 4);
+            std::io::Write::write_all(&mut server, &record).unwrap();
+        }
+    });
+
+    let mut client = RpcClient::with_version(transport, 7, 1);
+    client
+        .set_reply_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let error = client.call::<_, u32>(1, 1u32).unwrap_err();
+    assert!(matches!(error, Error::Io(e) if e.kind() == io::ErrorKind::TimedOut));
+
+    // The late reply to the call we gave up on isn't taken for the reply to the next one
+    answer.send(()).unwrap();
+    answer.send(()).unwrap();
+    let reply: u32 = client.call(1, 10u32).unwrap();
+    assert_eq!(reply, 11);
+    drop(answer);
+    thread.join().unwrap();
+}
@@ -0,0 +1,127 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Client for version 3 of the MOUNT protocol, which NFSv3 servers use to hand out the file handle
+//! of an export and to list what they export.
+
+use super::{Result, RpcClient, Transport};
+use serde::{Deserialize, Serialize};
+use xdr_extras::{DeserializeWithDiscriminant, SerializeWithDiscriminant};
+
+pub const MOUNT: u32 = 100005;
+pub const MOUNT_VERSION: u32 = 3;
+
+const MNT_PROCEDURE: u32 = 1;
+const DUMP_PROCEDURE: u32 = 2;
+const UMNT_PROCEDURE: u32 = 3;
+const EXPORT_PROCEDURE: u32 = 5;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MountOk {
+    #[serde(with = "serde_bytes")]
+    pub handle: Vec<u8>,
+    pub auth_flavors: Vec<u32>,
+}
+
+#[derive(SerializeWithDiscriminant, DeserializeWithDiscriminant, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum MountResult {
+    Ok(MountOk) = 0,
+    Perm = 1,
+    NoEnt = 2,
+    Io = 5,
+    Access = 13,
+    NotDir = 20,
+    Inval = 22,
+    NameTooLong = 63,
+    NotSupported = 10004,
+    ServerFault = 10006,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Export {
+    pub directory: String,
+    /// The hosts or netgroups allowed to mount it, everyone when empty.
+    #[serde(with = "xdr_extras::list")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+struct ExportList {
+    #[serde(with = "xdr_extras::list")]
+    exports: Vec<Export>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MountEntry {
+    pub host_name: String,
+    pub directory: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+struct MountList {
+    #[serde(with = "xdr_extras::list")]
+    mounts: Vec<MountEntry>,
+}
+
+pub struct MountClient<TransportT> {
+    client: RpcClient<TransportT>,
+}
+
+impl<TransportT: Transport> MountClient<TransportT> {
+    pub fn new(transport: TransportT) -> Self {
+        Self {
+            client: RpcClient::with_version(transport, MOUNT, MOUNT_VERSION),
+        }
+    }
+
+    /// Asks for the file handle of the given exported directory.
+    pub fn mount(&mut self, path: &str) -> Result<MountResult> {
+        self.client.call(MNT_PROCEDURE, path)
+    }
+
+    /// Tells the server we no longer have the given directory mounted.
+    pub fn unmount(&mut self, path: &str) -> Result<()> {
+        self.client.call(UMNT_PROCEDURE, path)
+    }
+
+    /// Lists which clients the server thinks have which directories mounted.
+    pub fn dump(&mut self) -> Result<Vec<MountEntry>> {
+        let list: MountList = self.client.call(DUMP_PROCEDURE, ())?;
+        Ok(list.mounts)
+    }
+
+    pub fn exports(&mut self) -> Result<Vec<Export>> {
+        let list: ExportList = self.client.call(EXPORT_PROCEDURE, ())?;
+        Ok(list.exports)
+    }
+}
+
+#[test]
+fn deserialize_exports() {
+    #[rustfmt::skip]
+    let bytes = [
+        0, 0, 0, 1,
+        0, 0, 0, 4, b'/', b's', b'r', b'v',
+        0, 0, 0, 1,
+        0, 0, 0, 1, b'*', 0, 0, 0,
+        0, 0, 0, 0,
+        0, 0, 0, 1,
+        0, 0, 0, 3, b'/', b'a', b'b', 0,
+        0, 0, 0, 0,
+        0, 0, 0, 0,
+    ];
+    let list: ExportList = xdr_extras::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        list.exports,
+        vec![
+            Export {
+                directory: "/srv".into(),
+                groups: vec!["*".into()],
+            },
+            Export {
+                directory: "/ab".into(),
+                groups: vec![],
+            },
+        ]
+    );
+}
@@ -0,0 +1,163 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Clients for the port mapper (version 2) and rpcbind (versions 3 and 4) protocols, which servers
+//! use to advertise which ports their RPC programs are listening on.
+
+use super::{Result, RpcClient, Transport, PORT_MAPPER};
+use serde::{Deserialize, Serialize};
+use xdr_extras::{DeserializeWithDiscriminant, SerializeWithDiscriminant};
+
+pub const PORT_MAPPER_VERSION: u32 = 2;
+pub const RPCBIND_VERSION_3: u32 = 3;
+pub const RPCBIND_VERSION_4: u32 = 4;
+
+// Both protocols use the same numbers, GETPORT is called GETADDR in rpcbind
+const GET_PORT_PROCEDURE: u32 = 3;
+const DUMP_PROCEDURE: u32 = 4;
+
+#[derive(
+    SerializeWithDiscriminant, DeserializeWithDiscriminant, Copy, Clone, PartialEq, Eq, Debug,
+)]
+#[repr(u32)]
+pub enum Protocol {
+    Tcp = 6,
+    Udp = 17,
+}
+
+impl Protocol {
+    /// The rpcbind network identifier for this protocol over IPv4.
+    pub fn netid(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Mapping {
+    pub program: u32,
+    pub version: u32,
+    pub protocol: Protocol,
+    pub port: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+struct MappingList {
+    #[serde(with = "xdr_extras::list")]
+    mappings: Vec<Mapping>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Binding {
+    pub program: u32,
+    pub version: u32,
+    pub netid: String,
+    /// The universal address, see `universal_address_port`.
+    pub address: String,
+    pub owner: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+struct BindingList {
+    #[serde(with = "xdr_extras::list")]
+    bindings: Vec<Binding>,
+}
+
+/// Extracts the port from a universal address like `192.168.0.1.8.1` or `::1.8.1`, where the last
+/// two numbers are the high and low bytes of the port.
+pub fn universal_address_port(address: &str) -> Option<u16> {
+    let mut parts = address.rsplitn(3, '.');
+    let low: u8 = parts.next()?.parse().ok()?;
+    let high: u8 = parts.next()?.parse().ok()?;
+    parts.next()?;
+    Some(u16::from_be_bytes([high, low]))
+}
+
+pub struct PortMapper<TransportT> {
+    client: RpcClient<TransportT>,
+}
+
+impl<TransportT: Transport> PortMapper<TransportT> {
+    pub fn new(transport: TransportT) -> Self {
+        Self {
+            client: RpcClient::with_version(transport, PORT_MAPPER, PORT_MAPPER_VERSION),
+        }
+    }
+
+    /// Returns the port the given program is listening on, or `None` if it isn't registered.
+    pub fn get_port(
+        &mut self,
+        program: u32,
+        version: u32,
+        protocol: Protocol,
+    ) -> Result<Option<u16>> {
+        let mapping = Mapping {
+            program,
+            version,
+            protocol,
+            port: 0,
+        };
+        let port: u32 = self.client.call(GET_PORT_PROCEDURE, mapping)?;
+        Ok((port != 0).then_some(port as u16))
+    }
+
+    /// Lists every registered program.
+    pub fn dump(&mut self) -> Result<Vec<Mapping>> {
+        let list: MappingList = self.client.call(DUMP_PROCEDURE, ())?;
+        Ok(list.mappings)
+    }
+}
+
+pub struct RpcBind<TransportT> {
+    client: RpcClient<TransportT>,
+}
+
+impl<TransportT: Transport> RpcBind<TransportT> {
+    /// Creates a client speaking the given version of rpcbind, either 3 or 4.
+    pub fn new(transport: TransportT, version: u32) -> Self {
+        assert!(version == RPCBIND_VERSION_3 || version == RPCBIND_VERSION_4);
+        Self {
+            client: RpcClient::with_version(transport, PORT_MAPPER, version),
+        }
+    }
+
+    /// Returns the universal address the given program is listening on, or `None` if it isn't
+    /// registered.
+    pub fn get_addr(&mut self, program: u32, version: u32, netid: &str) -> Result<Option<String>> {
+        let binding = Binding {
+            program,
+            version,
+            netid: netid.into(),
+            address: String::new(),
+            owner: String::new(),
+        };
+        let address: String = self.client.call(GET_PORT_PROCEDURE, binding)?;
+        Ok((!address.is_empty()).then_some(address))
+    }
+
+    pub fn get_port(
+        &mut self,
+        program: u32,
+        version: u32,
+        protocol: Protocol,
+    ) -> Result<Option<u16>> {
+        let address = self.get_addr(program, version, protocol.netid())?;
+        Ok(address.as_deref().and_then(universal_address_port))
+    }
+
+    /// Lists every registered program.
+    pub fn dump(&mut self) -> Result<Vec<Binding>> {
+        let list: BindingList = self.client.call(DUMP_PROCEDURE, ())?;
+        Ok(list.bindings)
+    }
+}
+
+#[test]
+fn parse_universal_address() {
+    assert_eq!(universal_address_port("192.168.0.1.8.1"), Some(2049));
+    assert_eq!(universal_address_port("0.0.0.0.0.111"), Some(111));
+    assert_eq!(universal_address_port("::1.8.1"), Some(2049));
+    assert_eq!(universal_address_port("8.1"), None);
+    assert_eq!(universal_address_port("/run/rpcbind.sock"), None);
+}
@@ -0,0 +1,441 @@
+// Copyright 2023 Remi Bernotavicius
+
+// Record marking for RPC over stream transports (RFC 5531 section 11)
+
+use std::io::{self, IoSlice};
+
+const LAST_FRAGMENT: u32 = 0x1 << 31;
+
+/// The largest fragment the header can describe.
+pub const MAX_FRAGMENT_SIZE: usize = (LAST_FRAGMENT - 1) as usize;
+
+/// Records larger than this are split into multiple fragments when sending.
+pub const DEFAULT_FRAGMENT_SIZE: usize = 1 << 20;
+
+/// Reads a single record from the underlying stream, reassembling its fragments.
+pub struct RecordReader<R> {
+    inner: R,
+    remaining: u32,
+    last: bool,
+}
+
+impl<R: io::Read> RecordReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            last: false,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 4];
+        self.inner.read_exact(&mut header)?;
+        let header = u32::from_be_bytes(header);
+        self.remaining = header & !LAST_FRAGMENT;
+        self.last = header & LAST_FRAGMENT != 0;
+        Ok(())
+    }
+
+    /// Skips over the rest of the record, leaving the stream at the start of the next one.
+    pub fn finish(mut self) -> io::Result<()> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(())
+    }
+}
+
+impl<R: io::Read> io::Read for RecordReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            if self.last {
+                return Ok(0);
+            }
+            self.read_header()?;
+        }
+
+        let len = std::cmp::min(buf.len(), self.remaining as usize);
+        let amount_read = self.inner.read(&mut buf[..len])?;
+        if amount_read == 0 && len > 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= amount_read as u32;
+        Ok(amount_read)
+    }
+}
+
+/// Returns the given record with fragment headers inserted so that no fragment is larger than
+/// `max_fragment_size`.
+pub fn encode_record(record: &[u8], max_fragment_size: usize) -> Vec<u8> {
+    assert!(max_fragment_size > 0 && max_fragment_size <= MAX_FRAGMENT_SIZE);
+
+    let num_fragments = std::cmp::max(record.len().div_ceil(max_fragment_size), 1);
+    let mut encoded = Vec::with_capacity(record.len() + num_fragments * 4);
+
+    let mut chunks = record.chunks(max_fragment_size).peekable();
+    if chunks.peek().is_none() {
+        encoded.extend(LAST_FRAGMENT.to_be_bytes());
+    }
+    while let Some(chunk) = chunks.next() {
+        let mut header = chunk.len() as u32;
+        if chunks.peek().is_none() {
+            header |= LAST_FRAGMENT;
+        }
+        encoded.extend(header.to_be_bytes());
+        encoded.extend(chunk);
+    }
+    encoded
+}
+
+/// Writes the record with fragment headers like `encode_record` does, but without copying it.
+/// Each fragment goes out along with its header in a single vectored write where the stream
+/// supports those.
+pub fn write_record(
+    writer: &mut impl io::Write,
+    record: &[u8],
+    max_fragment_size: usize,
+) -> io::Result<()> {
+    let mut record_writer = RecordWriter::new(writer, record.len() as u64, max_fragment_size);
+    io::Write::write_all(&mut record_writer, record)?;
+    record_writer.finish()?;
+    Ok(())
+}
+
+fn write_all_vectored(
+    writer: &mut impl io::Write,
+    mut slices: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Writes a record whose length is known up front a piece at a time, so that it never has to be
+/// in memory all at once. Each fragment header goes out in the same vectored write as the start
+/// of its fragment.
+pub struct RecordWriter<W> {
+    inner: W,
+    // What is left of the record after the current fragment
+    remaining: u64,
+    remaining_in_fragment: usize,
+    max_fragment_size: usize,
+    started: bool,
+}
+
+impl<W: io::Write> RecordWriter<W> {
+    pub fn new(inner: W, len: u64, max_fragment_size: usize) -> Self {
+        assert!(max_fragment_size > 0 && max_fragment_size <= MAX_FRAGMENT_SIZE);
+        Self {
+            inner,
+            remaining: len,
+            remaining_in_fragment: 0,
+            max_fragment_size,
+            started: false,
+        }
+    }
+
+    fn next_header(&mut self) -> [u8; 4] {
+        let size = self.remaining.min(self.max_fragment_size as u64);
+        self.remaining -= size;
+        self.remaining_in_fragment = size as usize;
+        self.started = true;
+        let mut header = size as u32;
+        if self.remaining == 0 {
+            header |= LAST_FRAGMENT;
+        }
+        header.to_be_bytes()
+    }
+
+    /// Fails if less than the length given was written, in which case the stream is left part
+    /// way through the record. Returns the underlying stream.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.started && self.remaining == 0 {
+            // An empty record is still a fragment
+            let header = self.next_header();
+            self.inner.write_all(&header)?;
+        }
+        if self.remaining > 0 || self.remaining_in_fragment > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "less written than the length of the record",
+            ));
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: io::Write> io::Write for RecordWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining_in_fragment > 0 {
+            let len = buf.len().min(self.remaining_in_fragment);
+            let written = self.inner.write(&buf[..len])?;
+            self.remaining_in_fragment -= written;
+            return Ok(written);
+        }
+        if self.remaining == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more written than the length of the record",
+            ));
+        }
+
+        let header = self.next_header();
+        let len = buf.len().min(self.remaining_in_fragment);
+        let slices = &mut [IoSlice::new(&header), IoSlice::new(&buf[..len])];
+        write_all_vectored(&mut self.inner, slices)?;
+        self.remaining_in_fragment -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The most read from the stream at once for a record being assembled
+const READ_SIZE: usize = 64 * 1024;
+
+/// Reassembles records from a stream one read at a time, so that a read which fails part way
+/// through a record, like when it times out, can be tried again without losing what came before.
+#[derive(Default)]
+pub(crate) struct RecordAssembler {
+    header: [u8; 4],
+    header_len: usize,
+    // Of all the fragments of the record so far
+    headers_read: usize,
+    remaining: usize,
+    last: bool,
+    record: Vec<u8>,
+}
+
+impl RecordAssembler {
+    /// Reads from the stream once, returning the record if that finished it.
+    pub(crate) fn read_from(&mut self, inner: &mut impl io::Read) -> io::Result<Option<Vec<u8>>> {
+        let reading_header = self.header_len < self.header.len();
+        let amount_read = if reading_header {
+            inner.read(&mut self.header[self.header_len..])?
+        } else {
+            let start = self.record.len();
+            self.record.resize(start + self.remaining.min(READ_SIZE), 0);
+            let res = inner.read(&mut self.record[start..]);
+            self.record.truncate(start + res.as_ref().map_or(0, |&n| n));
+            res?
+        };
+        if amount_read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if reading_header {
+            self.header_len += amount_read;
+            self.headers_read += amount_read;
+            if self.header_len < self.header.len() {
+                return Ok(None);
+            }
+            let header = u32::from_be_bytes(self.header);
+            self.remaining = (header & !LAST_FRAGMENT) as usize;
+            self.last = header & LAST_FRAGMENT != 0;
+        } else {
+            self.remaining -= amount_read;
+        }
+        if self.remaining > 0 {
+            return Ok(None);
+        }
+
+        // On to the next fragment
+        self.header_len = 0;
+        if !self.last {
+            return Ok(None);
+        }
+        self.headers_read = 0;
+        Ok(Some(std::mem::take(&mut self.record)))
+    }
+
+    /// How large the record being assembled is so far, counting what the header of the current
+    /// fragment says is still to come. Fragment headers count too, so that a stream of empty
+    /// fragments can't go on forever.
+    pub(crate) fn size(&self) -> usize {
+        self.headers_read + self.record.len() + self.remaining
+    }
+
+    /// Takes back a record it returned once we are done with it, so that the next one can be
+    /// read into the same memory instead of growing a new buffer to its size.
+    pub(crate) fn recycle(&mut self, mut record: Vec<u8>) {
+        if self.record.is_empty() && record.capacity() > self.record.capacity() {
+            record.clear();
+            self.record = record;
+        }
+    }
+}
+
+/// Takes the first record off the front of the given bytes, if all of it is there.
+pub(crate) fn take_record(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut record = vec![];
+    let mut position = 0;
+    loop {
+        let header = u32::from_be_bytes(buffer.get(position..position + 4)?.try_into().unwrap());
+        let start = position + 4;
+        let end = start + (header & !LAST_FRAGMENT) as usize;
+        record.extend(buffer.get(start..end)?);
+        position = end;
+        if header & LAST_FRAGMENT != 0 {
+            buffer.drain(..position);
+            return Some(record);
+        }
+    }
+}
+
+#[test]
+fn read_multi_fragment_record() {
+    use std::io::Read as _;
+
+    let mut input = vec![];
+    input.extend(3u32.to_be_bytes());
+    input.extend(b"abc");
+    input.extend(0u32.to_be_bytes());
+    input.extend((2u32 | LAST_FRAGMENT).to_be_bytes());
+    input.extend(b"de");
+    input.extend((1u32 | LAST_FRAGMENT).to_be_bytes());
+    input.extend(b"f");
+
+    let mut cursor = &input[..];
+
+    let mut record = vec![];
+    RecordReader::new(&mut cursor)
+        .read_to_end(&mut record)
+        .unwrap();
+    assert_eq!(record, b"abcde");
+
+    let mut record = RecordReader::new(&mut cursor);
+    let mut first = [0; 1];
+    record.read_exact(&mut first).unwrap();
+    assert_eq!(&first, b"f");
+    record.finish().unwrap();
+    assert!(cursor.is_empty());
+}
+
+#[test]
+fn encode_record_round_trip() {
+    use std::io::Read as _;
+
+    let data: Vec<u8> = (0..100u8).collect();
+    for fragment_size in [1, 7, 50, 100, 1000] {
+        let encoded = encode_record(&data, fragment_size);
+        assert_eq!(
+            encoded.len(),
+            data.len() + data.len().div_ceil(fragment_size) * 4
+        );
+
+        let mut decoded = vec![];
+        RecordReader::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    assert_eq!(encode_record(&[], 10), LAST_FRAGMENT.to_be_bytes());
+}
+
+#[test]
+fn write_record_a_few_bytes_at_a_time() {
+    use std::io::Read as _;
+
+    /// Takes at most three bytes of each write.
+    struct Trickle(Vec<u8>);
+
+    impl io::Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let data: Vec<u8> = (0..100u8).collect();
+    for fragment_size in [1, 7, 100, 1000] {
+        let mut writer = Trickle(vec![]);
+        write_record(&mut writer, &data, fragment_size).unwrap();
+        assert_eq!(writer.0, encode_record(&data, fragment_size));
+
+        let mut decoded = vec![];
+        RecordReader::new(&writer.0[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    let mut writer = Trickle(vec![]);
+    write_record(&mut writer, &[], 10).unwrap();
+    assert_eq!(writer.0, LAST_FRAGMENT.to_be_bytes());
+}
+
+#[test]
+fn record_written_in_pieces() {
+    use std::io::Write as _;
+
+    let data: Vec<u8> = (0..100u8).collect();
+    let mut writer = RecordWriter::new(vec![], data.len() as u64, 30);
+    for piece in data.chunks(7) {
+        writer.write_all(piece).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), encode_record(&data, 30));
+
+    // Writing more or less than the length given fails
+    let mut writer = RecordWriter::new(vec![], 5, 30);
+    writer.write_all(b"hello").unwrap();
+    assert!(writer.write_all(b"!").is_err());
+    let mut writer = RecordWriter::new(vec![], 5, 30);
+    writer.write_all(b"hell").unwrap();
+    assert!(writer.finish().is_err());
+}
+
+#[test]
+fn take_partial_record() {
+    let encoded = encode_record(b"abcdef", 4);
+    let mut buffer = encoded[..7].to_vec();
+    assert_eq!(take_record(&mut buffer), None);
+    buffer.extend(&encoded[7..]);
+    buffer.extend(encode_record(b"g", 4));
+    assert_eq!(take_record(&mut buffer).unwrap(), b"abcdef");
+    assert_eq!(take_record(&mut buffer).unwrap(), b"g");
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn assemble_record_a_byte_at_a_time() {
+    let mut encoded = encode_record(b"abcdef", 4);
+    encoded.extend(encode_record(b"", 4));
+    let mut assembler = RecordAssembler::default();
+    let mut records = vec![];
+    for byte in encoded.chunks(1) {
+        if let Some(record) = assembler.read_from(&mut &byte[..]).unwrap() {
+            records.push(record);
+        }
+        // Nothing more to read is like a read timing out, what came before is kept
+        let error = assembler.read_from(&mut &[][..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+    assert_eq!(records, [&b"abcdef"[..], &b""[..]]);
+
+    // A record given back is read into again
+    let record = records.swap_remove(0);
+    let capacity = record.capacity();
+    assembler.recycle(record);
+    let record = assembler
+        .read_from(&mut &encode_record(b"g", 4)[..])
+        .unwrap();
+    assert_eq!(record, None);
+    let record = assembler.read_from(&mut &b"g"[..]).unwrap().unwrap();
+    assert_eq!((&record[..], record.capacity()), (&b"g"[..], capacity));
+}
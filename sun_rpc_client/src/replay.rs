@@ -0,0 +1,314 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! Recording the messages a stream transport carries, and replaying them later without the other
+//! end, so that what happened with a real server can be turned into a test which runs anywhere.
+//!
+//! A recording is a sequence of messages, each a byte saying which way it went, 0 for sent and 1
+//! for received, then its length as a big-endian `u32` and then the message itself without record
+//! marking.
+
+use super::record::{encode_record, take_record, DEFAULT_FRAGMENT_SIZE};
+use super::ReadTimeout;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+const SENT: u8 = 0;
+const RECEIVED: u8 = 1;
+
+// The message type comes right after the XID, and then for calls the RPC version, program,
+// version and procedure
+const CALL: [u8; 4] = 0u32.to_be_bytes();
+const REPLY: [u8; 4] = 1u32.to_be_bytes();
+
+fn message_type(message: &[u8]) -> Option<&[u8]> {
+    message.get(4..8)
+}
+
+fn procedure(message: &[u8]) -> Option<&[u8]> {
+    message.get(12..24)
+}
+
+/// A transport which writes every message it carries, either way, to a log.
+pub struct Recording<TransportT, LogT> {
+    transport: TransportT,
+    log: LogT,
+    sent: Vec<u8>,
+    received: Vec<u8>,
+}
+
+impl<TransportT, LogT: io::Write> Recording<TransportT, LogT> {
+    pub fn new(transport: TransportT, log: LogT) -> Self {
+        Self {
+            transport,
+            log,
+            sent: vec![],
+            received: vec![],
+        }
+    }
+
+    pub fn into_inner(self) -> (TransportT, LogT) {
+        (self.transport, self.log)
+    }
+
+    fn log(log: &mut LogT, direction: u8, buffer: &mut Vec<u8>) -> io::Result<()> {
+        while let Some(message) = take_record(buffer) {
+            log.write_all(&[direction])?;
+            log.write_all(&(message.len() as u32).to_be_bytes())?;
+            log.write_all(&message)?;
+        }
+        Ok(())
+    }
+}
+
+impl<TransportT: io::Read, LogT: io::Write> io::Read for Recording<TransportT, LogT> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.transport.read(buf)?;
+        self.received.extend(&buf[..len]);
+        Self::log(&mut self.log, RECEIVED, &mut self.received)?;
+        Ok(len)
+    }
+}
+
+impl<TransportT: ReadTimeout, LogT> ReadTimeout for Recording<TransportT, LogT> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.transport.set_read_timeout(timeout)
+    }
+}
+
+impl<TransportT: io::Write, LogT: io::Write> io::Write for Recording<TransportT, LogT> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.transport.write(buf)?;
+        self.sent.extend(&buf[..len]);
+        Self::log(&mut self.log, SENT, &mut self.sent)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()?;
+        self.log.flush()
+    }
+}
+
+/// A transport which plays the other end of a recording back.
+///
+/// What is sent has to come in the order it was recorded, and calls have to be to the same
+/// procedures. Everything else about them can differ, so XIDs are allowed to differ too: replies
+/// are given the XID of the call they answer. The messages received after each one sent are
+/// readable once it has been sent, and reading past them gives end-of-file.
+pub struct Replay {
+    messages: VecDeque<(u8, Vec<u8>)>,
+    /// The XIDs of the calls sent, by the XIDs they had in the recording.
+    xids: BTreeMap<Vec<u8>, Vec<u8>>,
+    written: Vec<u8>,
+    readable: Vec<u8>,
+    position: usize,
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl Replay {
+    pub fn new(mut recording: impl io::Read) -> io::Result<Self> {
+        let mut messages = VecDeque::new();
+        loop {
+            let mut direction = [0; 1];
+            match recording.read_exact(&mut direction) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            if direction[0] != SENT && direction[0] != RECEIVED {
+                return Err(invalid_data("not a recording"));
+            }
+            let mut len = [0; 4];
+            recording.read_exact(&mut len)?;
+            let mut message = vec![0; u32::from_be_bytes(len) as usize];
+            recording.read_exact(&mut message)?;
+            messages.push_back((direction[0], message));
+        }
+
+        let mut replay = Self {
+            messages,
+            xids: BTreeMap::new(),
+            written: vec![],
+            readable: vec![],
+            position: 0,
+        };
+        replay.make_readable();
+        Ok(replay)
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(io::BufReader::new(File::open(path)?))
+    }
+
+    /// Whether everything in the recording was sent and read.
+    pub fn is_finished(&self) -> bool {
+        self.messages.is_empty() && self.position == self.readable.len()
+    }
+
+    /// Makes the messages received up to the next one sent readable.
+    fn make_readable(&mut self) {
+        while let Some((RECEIVED, _)) = self.messages.front() {
+            let (_, mut message) = self.messages.pop_front().unwrap();
+            if message_type(&message) == Some(&REPLY[..]) {
+                if let Some(xid) = self.xids.get(&message[..4]) {
+                    message[..4].copy_from_slice(xid);
+                }
+            }
+            self.readable.drain(..self.position);
+            self.position = 0;
+            self.readable
+                .extend(encode_record(&message, DEFAULT_FRAGMENT_SIZE));
+        }
+    }
+
+    fn sent(&mut self, message: Vec<u8>) -> io::Result<()> {
+        let Some((SENT, recorded)) = self.messages.pop_front() else {
+            return Err(invalid_data("sent more than the recording has"));
+        };
+        if message_type(&message) == Some(&CALL[..]) {
+            if message_type(&recorded) != Some(&CALL[..])
+                || procedure(&message) != procedure(&recorded)
+            {
+                return Err(invalid_data(
+                    "sent a call to a different procedure than the recording has",
+                ));
+            }
+            self.xids
+                .insert(recorded[..4].to_vec(), message[..4].to_vec());
+        }
+        self.make_readable();
+        Ok(())
+    }
+}
+
+impl io::Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.readable.len() - self.position);
+        buf[..len].copy_from_slice(&self.readable[self.position..][..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// Everything there is to read is there already
+impl ReadTimeout for Replay {
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.extend(buf);
+        while let Some(message) = take_record(&mut self.written) {
+            self.sent(message)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct Echo {
+    received: Vec<u8>,
+    replies: Vec<u8>,
+}
+
+#[cfg(test)]
+impl crate::Program for Echo {
+    fn program(&self) -> u32 {
+        7
+    }
+
+    fn versions(&self) -> (u32, u32) {
+        (1, 1)
+    }
+
+    fn call(
+        &mut self,
+        _version: u32,
+        _procedure: u32,
+        args: &[u8],
+    ) -> crate::AcceptedReplyBody<Vec<u8>> {
+        crate::AcceptedReplyBody::Success(args.to_vec())
+    }
+}
+
+#[cfg(test)]
+impl io::Read for Echo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.replies.len());
+        buf[..len].copy_from_slice(&self.replies[..len]);
+        self.replies.drain(..len);
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+impl io::Write for Echo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.received.extend(buf);
+        while let Some(message) = take_record(&mut self.received) {
+            let reply = sun_rpc::server::serve_call(self, &message).unwrap();
+            self.replies
+                .extend(encode_record(&reply, DEFAULT_FRAGMENT_SIZE));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn record_echo() -> Vec<u8> {
+    let echo = Echo {
+        received: vec![],
+        replies: vec![],
+    };
+    let mut client = crate::RpcClient::with_version(Recording::new(echo, vec![]), 7, 1);
+    assert_eq!(client.call::<_, u32>(1, 42u32).unwrap(), 42);
+    assert_eq!(client.call::<_, u32>(2, 43u32).unwrap(), 43);
+    client.transport.into_inner().1
+}
+
+#[test]
+fn replay_calls() {
+    let recording = record_echo();
+    let mut client = crate::RpcClient::with_version(Replay::new(&recording[..]).unwrap(), 7, 1);
+    assert_eq!(client.call::<_, u32>(1, 42u32).unwrap(), 42);
+    assert!(!client.transport.is_finished());
+    assert_eq!(client.call::<_, u32>(2, 43u32).unwrap(), 43);
+    assert!(client.transport.is_finished());
+}
+
+#[test]
+fn replay_with_other_xids() {
+    let recording = record_echo();
+    let mut client = crate::RpcClient::with_version(Replay::new(&recording[..]).unwrap(), 7, 1);
+    client.xid = crate::Xid(100);
+    let (xid, reply) = {
+        client.send_request(1, 0u32).unwrap();
+        client.receive_reply_with_xid::<u32>().unwrap()
+    };
+    // The reply is the recorded one, whatever was sent
+    assert_eq!((xid, reply), (crate::Xid(100), 42));
+}
+
+#[test]
+fn replay_other_procedure() {
+    let recording = record_echo();
+    let mut client = crate::RpcClient::with_version(Replay::new(&recording[..]).unwrap(), 7, 1);
+    let error = client.call::<_, u32>(2, 42u32).unwrap_err();
+    assert!(matches!(error, crate::Error::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+}
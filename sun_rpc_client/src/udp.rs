@@ -0,0 +1,243 @@
+// Copyright 2023 Remi Bernotavicius
+
+//! RPC over UDP (RFC 5531 section 10). Each message is a single datagram without record marking,
+//! and since datagrams can be lost, calls are sent again until their reply arrives.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+const LAST_FRAGMENT: u32 = 0x1 << 31;
+
+// The largest datagram UDP over IPv4 can carry
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// How long we wait for a reply before sending the call again at first, doubling each time.
+pub const DEFAULT_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many times a call is sent again before giving up on it.
+pub const DEFAULT_MAX_RETRANSMISSIONS: u32 = 6;
+
+/// A transport which sends messages as UDP datagrams. To `RpcClient` it looks like a stream
+/// transport: the record marking it writes is taken off before sending, and it is put back on
+/// datagrams received. Calls without a reply yet are remembered by XID and sent again with
+/// exponential backoff, and replies to calls which aren't outstanding, like a second reply to a
+/// call we sent twice, are dropped.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    to_send: Vec<u8>,
+    received: Vec<u8>,
+    position: usize,
+    outstanding: BTreeMap<[u8; 4], Vec<u8>>,
+    retransmit_timeout: Duration,
+    max_retransmissions: u32,
+}
+
+impl UdpTransport {
+    /// Creates a transport which talks to the given address from an unused local port.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut last_error = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        for addr in addr.to_socket_addrs()? {
+            let local = match addr {
+                SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            };
+            let socket = UdpSocket::bind(local)?;
+            match socket.connect(addr) {
+                Ok(()) => return Ok(Self::new(socket)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Uses the given socket, which must already be connected to the other end.
+    pub fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            to_send: vec![],
+            received: vec![],
+            position: 0,
+            outstanding: BTreeMap::new(),
+            retransmit_timeout: DEFAULT_RETRANSMIT_TIMEOUT,
+            max_retransmissions: DEFAULT_MAX_RETRANSMISSIONS,
+        }
+    }
+
+    /// Sets how long to wait for a reply before sending a call again the first time.
+    pub fn set_retransmit_timeout(&mut self, timeout: Duration) {
+        self.retransmit_timeout = timeout.max(Duration::from_millis(1));
+    }
+
+    /// Sets how many times a call is sent again before reading fails with `TimedOut`.
+    pub fn set_max_retransmissions(&mut self, max_retransmissions: u32) {
+        self.max_retransmissions = max_retransmissions;
+    }
+
+    /// Sends the records which have been written in full.
+    fn send_records(&mut self) -> io::Result<()> {
+        while let Some((message, len)) = take_record(&self.to_send) {
+            self.to_send.drain(..len);
+            if message.len() > MAX_DATAGRAM_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "message too large for a datagram",
+                ));
+            }
+            self.socket.send(&message)?;
+
+            // Only calls get replies, which we might have to wait for
+            if message_type(&message) == Some(CALL) {
+                let xid = message[..4].try_into().unwrap();
+                self.outstanding.insert(xid, message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the next datagram we want, sending outstanding calls again while none arrives.
+    fn receive(&mut self) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+        let mut timeout = self.retransmit_timeout;
+        let mut retransmissions = 0;
+        loop {
+            self.socket.set_read_timeout(Some(timeout))?;
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => {
+                    let message = &buffer[..len];
+                    let is_wanted = match message_type(message) {
+                        Some(CALL) => true,
+                        Some(_) => self.outstanding.remove(&message[..4]).is_some(),
+                        None => false,
+                    };
+                    if is_wanted {
+                        return Ok(message.to_vec());
+                    }
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if retransmissions == self.max_retransmissions || self.outstanding.is_empty() {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    retransmissions += 1;
+                    timeout = timeout.saturating_mul(2);
+                    for message in self.outstanding.values() {
+                        self.socket.send(message)?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+const CALL: u32 = 0;
+
+fn message_type(message: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(message.get(4..8)?.try_into().unwrap()))
+}
+
+/// Takes the record marking off the first record in the buffer if all of it is there, returning
+/// the record and how much of the buffer it took up.
+fn take_record(buffer: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut record = vec![];
+    let mut position = 0;
+    loop {
+        let header = u32::from_be_bytes(buffer.get(position..position + 4)?.try_into().unwrap());
+        let len = (header & !LAST_FRAGMENT) as usize;
+        let fragment = buffer.get(position + 4..position + 4 + len)?;
+        record.extend(fragment);
+        position += 4 + len;
+        if header & LAST_FRAGMENT != 0 {
+            return Some((record, position));
+        }
+    }
+}
+
+impl io::Write for UdpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.to_send.extend(buf);
+        self.send_records()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl io::Read for UdpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.received.len() {
+            let message = self.receive()?;
+            self.received.clear();
+            self.received
+                .extend((message.len() as u32 | LAST_FRAGMENT).to_be_bytes());
+            self.received.extend(message);
+            self.position = 0;
+        }
+
+        let len = buf.len().min(self.received.len() - self.position);
+        buf[..len].copy_from_slice(&self.received[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn take_record_from_fragments() {
+    let mut buffer = vec![];
+    buffer.extend(2u32.to_be_bytes());
+    buffer.extend(b"ab");
+    assert_eq!(take_record(&buffer), None);
+
+    buffer.extend((1u32 | LAST_FRAGMENT).to_be_bytes());
+    buffer.extend(b"c");
+    buffer.extend(b"next");
+    assert_eq!(take_record(&buffer), Some((b"abc".to_vec(), 11)));
+}
+
+#[test]
+fn retransmit_until_reply() {
+    use std::io::{Read as _, Write as _};
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut transport = UdpTransport::connect(server.local_addr().unwrap()).unwrap();
+    transport.set_retransmit_timeout(Duration::from_millis(10));
+
+    let call = [0, 0, 0, 7, 0, 0, 0, 0, 1, 2];
+    transport
+        .write_all(&crate::encode_record(&call, 4))
+        .unwrap();
+
+    let mut buffer = [0; 100];
+    let thread = std::thread::spawn(move || {
+        // Ignore the first one, as if it was lost
+        let (_, client) = server.recv_from(&mut buffer).unwrap();
+        let (len, _) = server.recv_from(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], call);
+
+        // A reply to something else is dropped, and so is the second reply to our call
+        let stray = [0, 0, 0, 8, 0, 0, 0, 1];
+        let reply = [0, 0, 0, 7, 0, 0, 0, 1, 3];
+        for message in [&stray[..], &reply, &reply] {
+            server.send_to(message, client).unwrap();
+        }
+    });
+
+    let mut record = vec![];
+    crate::RecordReader::new(&mut transport)
+        .read_to_end(&mut record)
+        .unwrap();
+    assert_eq!(record, [0, 0, 0, 7, 0, 0, 0, 1, 3]);
+    thread.join().unwrap();
+
+    transport.set_max_retransmissions(0);
+    let error = transport.read(&mut buffer).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,333 @@
+// copyright 2023 Remi Bernotavicius
+
+use super::error::{Error, Result};
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer as _, Visitor};
+
+/// Decodes a value from the start of the XDR data, the same way `serde-xdr` does, but without
+/// needing `std`. Anything after the value is ignored.
+pub fn from_bytes<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    T::deserialize(&mut Deserializer::new(bytes))
+}
+
+/// The longest opaque data, strings and arrays a `Deserializer` takes, so that a length which is
+/// garbage or malicious fails to decode rather than being acted on. Whatever the limits, a length
+/// can't be more than what is left of the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// In bytes, for opaque data and strings.
+    pub max_opaque_len: usize,
+    /// In elements, for variable-length arrays.
+    pub max_array_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_opaque_len: usize::MAX,
+            max_array_len: usize::MAX,
+        }
+    }
+}
+
+/// Decodes values one after the other from XDR data.
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    limits: Limits,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Self {
+        Self::with_limits(input, Limits::default())
+    }
+
+    pub fn with_limits(input: &'de [u8], limits: Limits) -> Self {
+        Self { input, limits }
+    }
+
+    /// What is left after the values decoded so far.
+    pub fn remaining(&self) -> &'de [u8] {
+        self.input
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn take_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take_array()?))
+    }
+
+    fn take_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take_array()?))
+    }
+
+    fn take_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take_array()?))
+    }
+
+    /// Opaque data, skipping over the padding after it.
+    fn take_opaque(&mut self) -> Result<&'de [u8]> {
+        let len = self.take_u32()? as usize;
+        if len > self.limits.max_opaque_len {
+            return Err(Error::LimitExceeded {
+                len,
+                limit: self.limits.max_opaque_len,
+            });
+        }
+        let data = self.take(len)?;
+        self.take((4 - len % 4) % 4)?;
+        Ok(data)
+    }
+
+    fn take_narrow<T: TryFrom<i64>>(&mut self, signed: bool) -> Result<T> {
+        let value = if signed {
+            self.take_i32()?.into()
+        } else {
+            self.take_u32()?.into()
+        };
+        T::try_from(value).map_err(|_| Error::IntegerOutOfRange(value))
+    }
+}
+
+/// The elements of something compound. Structs don't have a known number of elements, the
+/// `Deserialize` impls of enums with discriminants read more than the fields they give.
+struct Elements<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    remaining: Option<usize>,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match &mut self.remaining {
+            Some(0) => return Ok(None),
+            Some(remaining) => *remaining -= 1,
+            None => {}
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = self.take_u32()?;
+        let variant = seed.deserialize(index.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a value of unknown type"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_u32()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            v => Err(Error::InvalidBool(v)),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(self.take_narrow(true)?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(self.take_narrow(true)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.take_i32()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(i64::from_be_bytes(self.take_array()?))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.take_narrow(false)?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(self.take_narrow(false)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.take_u32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.take_u64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_bits(self.take_u32()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_bits(self.take_u64()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = self.take_u32()?;
+        visitor.visit_char(char::from_u32(v).ok_or(Error::InvalidChar(v))?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let s = core::str::from_utf8(self.take_opaque()?).map_err(|_| Error::InvalidUtf8)?;
+        visitor.visit_borrowed_str(s)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_bytes(self.take_opaque()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_u32()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            v => Err(Error::InvalidOption(v)),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.take_u32()? as usize;
+        if len > self.limits.max_array_len {
+            return Err(Error::LimitExceeded {
+                len,
+                limit: self.limits.max_array_len,
+            });
+        }
+        // Each element takes at least 4 bytes, unless it is of a type which takes none, which
+        // nothing sends arrays of
+        if len > self.input.len() / 4 {
+            return Err(Error::UnexpectedEnd);
+        }
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining: Some(len),
+        })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a map"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Elements {
+            de: self,
+            remaining: None,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("an identifier"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a value of unknown type"))
+    }
+}
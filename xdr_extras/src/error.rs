@@ -0,0 +1,65 @@
+// copyright 2023 Remi Bernotavicius
+
+use alloc::string::{String, ToString as _};
+use core::fmt;
+
+/// What went wrong encoding or decoding XDR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The data ended before the value did.
+    UnexpectedEnd,
+    InvalidBool(u32),
+    InvalidOption(u32),
+    InvalidChar(u32),
+    InvalidUtf8,
+    /// An integer didn't fit in the type it was decoded as.
+    IntegerOutOfRange(i64),
+    /// A length was over what the `Limits` of the `Deserializer` allow.
+    LimitExceeded {
+        len: usize,
+        limit: usize,
+    },
+    /// Lengths are encoded in 32 bits.
+    TooLong(usize),
+    /// Sequences need their length up front.
+    UnknownLength,
+    /// A kind of value XDR has no encoding for, like a map.
+    Unsupported(&'static str),
+    Custom(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Self::InvalidBool(v) => write!(f, "invalid bool {v}"),
+            Self::InvalidOption(v) => write!(f, "invalid option discriminant {v}"),
+            Self::InvalidChar(v) => write!(f, "invalid char {v:#x}"),
+            Self::InvalidUtf8 => write!(f, "string isn't valid UTF-8"),
+            Self::IntegerOutOfRange(v) => write!(f, "integer {v} out of range"),
+            Self::LimitExceeded { len, limit } => {
+                write!(f, "length {len} over the limit of {limit}")
+            }
+            Self::TooLong(len) => write!(f, "length {len} doesn't fit in 32 bits"),
+            Self::UnknownLength => write!(f, "sequence of unknown length"),
+            Self::Unsupported(what) => write!(f, "{what} can't be encoded in XDR"),
+            Self::Custom(message) => f.write_str(message),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Custom(message.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Custom(message.to_string())
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
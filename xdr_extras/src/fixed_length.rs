@@ -0,0 +1,51 @@
+// copyright 2023 Remi Bernotavicius
+
+//! Opaque data of a fixed length, like `[u8; 16]`, encoded the way `serde-xdr`'s
+//! `opaque_data::fixed_length` does, as four byte blocks without a length.
+
+use core::fmt;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeTuple as _, Serializer};
+
+pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]>,
+    S: Serializer,
+{
+    let bytes = bytes.as_ref();
+    let mut state = serializer.serialize_tuple(bytes.len().div_ceil(4))?;
+    for chunk in bytes.chunks(4) {
+        let mut block = [0; 4];
+        block[..chunk.len()].copy_from_slice(chunk);
+        state.serialize_element(&u32::from_be_bytes(block))?;
+    }
+    state.end()
+}
+
+pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct BlocksVisitor<const N: usize>;
+
+    impl<'de, const N: usize> Visitor<'de> for BlocksVisitor<N> {
+        type Value = [u8; N];
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "opaque data of {N} bytes")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; N], A::Error> {
+            let mut bytes = [0; N];
+            for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+                let block: u32 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(i * 4, &self))?;
+                chunk.copy_from_slice(&block.to_be_bytes()[..chunk.len()]);
+            }
+            Ok(bytes)
+        }
+    }
+
+    deserializer.deserialize_tuple(N.div_ceil(4), BlocksVisitor)
+}
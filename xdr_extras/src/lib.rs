@@ -1,6 +1,26 @@
 // copyright 2023 Remi Bernotavicius
 
-pub use serde_dis::*;
+//! Tools to use with `serde-xdr`, and an XDR encoding of our own which works without `std`, for
+//! the protocol crates which have to.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+extern crate alloc;
+
+pub use de::{from_bytes, Deserializer, Limits};
+pub use error::Error;
+pub use ser::{to_bytes, to_bytes_into};
+pub use shared_bytes::from_shared_bytes;
+pub use xdr_extras_derive::*;
+
+mod de;
+mod error;
+pub mod fixed_length;
+mod ser;
+pub mod shared_bytes;
 
 pub mod list {
     use serde::ser::SerializeStruct as _;
@@ -10,6 +30,8 @@ pub mod list {
         Deserialize, Serialize,
     };
 
+    use alloc::vec::Vec;
+
     pub fn serialize<T, S>(value: &Vec<T>, serializer: S) -> Result<S::Ok, S::Error>
     where
         T: Serialize,
@@ -29,7 +51,7 @@ pub mod list {
         T: DeserializeOwned,
         D: Deserializer<'de>,
     {
-        struct Visitor<T>(std::marker::PhantomData<T>);
+        struct Visitor<T>(core::marker::PhantomData<T>);
 
         impl<'de, T> serde::de::Visitor<'de> for Visitor<T>
         where
@@ -37,7 +59,7 @@ pub mod list {
         {
             type Value = Vec<T>;
 
-            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut ::core::fmt::Formatter) -> core::fmt::Result {
                 formatter.write_str("List")
             }
 
@@ -45,7 +67,7 @@ pub mod list {
             where
                 A: serde::de::SeqAccess<'de>,
             {
-                let mut res = vec![];
+                let mut res = Vec::new();
                 while let Some(v) = seq.next_element::<Option<T>>()?.flatten() {
                     res.push(v);
                 }
@@ -53,6 +75,6 @@ pub mod list {
             }
         }
 
-        deserializer.deserialize_struct("List", &[], Visitor(std::marker::PhantomData))
+        deserializer.deserialize_struct("List", &[], Visitor(core::marker::PhantomData))
     }
 }
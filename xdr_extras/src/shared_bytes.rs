@@ -0,0 +1,106 @@
+// copyright 2023 Remi Bernotavicius
+
+//! Opaque data held as `Bytes`, for `#[serde(with = "xdr_extras::shared_bytes")]`. Decoded with
+//! `from_shared_bytes` it refers to the `Bytes` it was decoded from rather than being copied out
+//! of it, so the data of a large READ or WRITE isn't copied once the record holding it is received.
+//! Decoded any other way it is copied, like `Bytes` itself is.
+
+use super::de::{Deserializer, Limits};
+use super::error;
+use alloc::vec::Vec;
+use bytes::Bytes;
+use core::fmt;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::Serializer;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    /// What `from_shared_bytes` on this thread is decoding.
+    static SOURCE: core::cell::RefCell<Option<Bytes>> = const { core::cell::RefCell::new(None) };
+}
+
+/// Decodes a value from the start of the XDR data, like `from_bytes` but with the given limits,
+/// and with the opaque data of `shared_bytes` fields in it referring to `bytes`.
+#[cfg(feature = "std")]
+pub fn from_shared_bytes<T: de::DeserializeOwned>(
+    bytes: &Bytes,
+    limits: Limits,
+) -> error::Result<T> {
+    /// Puts back what was being decoded before, even if decoding panics.
+    struct Restore(Option<Bytes>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SOURCE.with(|source| *source.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SOURCE.with(|source| source.borrow_mut().replace(bytes.clone())));
+    T::deserialize(&mut Deserializer::with_limits(bytes, limits))
+}
+
+/// Without `std` there is nowhere to keep what is being decoded, so this copies.
+#[cfg(not(feature = "std"))]
+pub fn from_shared_bytes<T: de::DeserializeOwned>(
+    bytes: &Bytes,
+    limits: Limits,
+) -> error::Result<T> {
+    T::deserialize(&mut Deserializer::with_limits(bytes, limits))
+}
+
+/// Refers to the data in what `from_shared_bytes` is decoding if it is in there.
+fn share(data: &[u8]) -> Bytes {
+    #[cfg(feature = "std")]
+    {
+        let shared = SOURCE.with(|source| {
+            let source = source.borrow();
+            let source = source.as_ref()?;
+            let within = source.as_ptr_range();
+            let range = data.as_ptr_range();
+            (within.start <= range.start && range.end <= within.end).then(|| source.slice_ref(data))
+        });
+        if let Some(shared) = shared {
+            return shared;
+        }
+    }
+    Bytes::copy_from_slice(data)
+}
+
+pub fn serialize<S: Serializer>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+pub fn deserialize<'de, D: de::Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("opaque data")
+        }
+
+        fn visit_borrowed_bytes<E: de::Error>(self, data: &'de [u8]) -> Result<Bytes, E> {
+            Ok(share(data))
+        }
+
+        fn visit_bytes<E: de::Error>(self, data: &[u8]) -> Result<Bytes, E> {
+            Ok(Bytes::copy_from_slice(data))
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, data: Vec<u8>) -> Result<Bytes, E> {
+            Ok(data.into())
+        }
+
+        // How formats without bytes of their own, like JSON, give them
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+            while let Some(byte) = seq.next_element::<u8>()? {
+                data.push(byte);
+            }
+            Ok(data.into())
+        }
+    }
+
+    deserializer.deserialize_bytes(BytesVisitor)
+}
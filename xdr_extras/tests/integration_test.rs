@@ -21,6 +21,11 @@ fn serialize_round_trip<
 
     let new_bytes = serde_xdr::to_bytes(&v).unwrap();
     assert_eq!(&new_bytes, bytes);
+
+    // Our own encoding has to agree with serde-xdr's
+    let new_v: V = xdr_extras::from_bytes(bytes).unwrap();
+    assert_eq!(&new_v, &v);
+    assert_eq!(&xdr_extras::to_bytes(&v).unwrap(), bytes);
 }
 
 #[test]
@@ -82,3 +87,117 @@ fn round_trip_generic_enum_xdr() {
 
     serialize_round_trip(Baz::<u32>::B, &[0x0, 0x0, 0x0, 0x9][..]);
 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+struct Qux {
+    flag: bool,
+    small: u8,
+    signed: i16,
+    name: Option<String>,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    #[serde(with = "xdr_extras::fixed_length")]
+    id: [u8; 6],
+    numbers: Vec<u32>,
+    #[serde(with = "xdr_extras::list")]
+    list: Vec<u32>,
+    pair: (i64, char),
+}
+
+#[test]
+fn round_trip_struct_xdr() {
+    serialize_round_trip(
+        Qux {
+            flag: true,
+            small: 7,
+            signed: -2,
+            name: Some("abcde".into()),
+            data: vec![1, 2],
+            id: [1, 2, 3, 4, 5, 6],
+            numbers: vec![3],
+            list: vec![4],
+            pair: (-1, 'a'),
+        },
+        &[
+            0, 0, 0, 1, // flag
+            0, 0, 0, 7, // small
+            0xff, 0xff, 0xff, 0xfe, // signed
+            0, 0, 0, 1, 0, 0, 0, 5, b'a', b'b', b'c', b'd', b'e', 0, 0, 0, // name
+            0, 0, 0, 2, 1, 2, 0, 0, // data
+            1, 2, 3, 4, 5, 6, 0, 0, // id
+            0, 0, 0, 1, 0, 0, 0, 3, // numbers
+            0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 0, // list
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0x61, // pair
+        ][..],
+    );
+}
+
+#[test]
+fn decode_errors_xdr() {
+    use xdr_extras::Error;
+
+    assert_eq!(
+        xdr_extras::from_bytes::<u64>(&[0, 0, 0, 1]),
+        Err(Error::UnexpectedEnd)
+    );
+    assert_eq!(
+        xdr_extras::from_bytes::<bool>(&[0, 0, 0, 2]),
+        Err(Error::InvalidBool(2))
+    );
+    assert_eq!(
+        xdr_extras::from_bytes::<u8>(&[0, 0, 1, 0]),
+        Err(Error::IntegerOutOfRange(256))
+    );
+    assert_eq!(
+        xdr_extras::from_bytes::<String>(&[0, 0, 0, 1, 0xff, 0, 0, 0]),
+        Err(Error::InvalidUtf8)
+    );
+    // The padding after opaque data has to be there too
+    assert_eq!(
+        xdr_extras::from_bytes::<String>(&[0, 0, 0, 1, b'a']),
+        Err(Error::UnexpectedEnd)
+    );
+
+    let mut de = xdr_extras::Deserializer::new(&[0, 0, 0, 1, 0, 0, 0, 2]);
+    let first: u32 = serde::Deserialize::deserialize(&mut de).unwrap();
+    assert_eq!(first, 1);
+    assert_eq!(de.remaining(), &[0, 0, 0, 2]);
+}
+
+#[test]
+fn length_limits_xdr() {
+    use serde::Deserialize as _;
+    use xdr_extras::{Deserializer, Error, Limits};
+
+    // Lengths longer than what is left fail before anything is made of them
+    let huge = [0xff, 0xff, 0xff, 0xf0];
+    assert_eq!(
+        xdr_extras::from_bytes::<Vec<u32>>(&huge),
+        Err(Error::UnexpectedEnd)
+    );
+    assert_eq!(
+        xdr_extras::from_bytes::<serde_bytes::ByteBuf>(&huge),
+        Err(Error::UnexpectedEnd)
+    );
+
+    let limits = Limits {
+        max_opaque_len: 3,
+        max_array_len: 1,
+    };
+    let decode_list =
+        |bytes| Vec::<u32>::deserialize(&mut Deserializer::with_limits(bytes, limits));
+    assert_eq!(decode_list(&[0, 0, 0, 1, 0, 0, 0, 7]), Ok(vec![7]));
+    assert_eq!(
+        decode_list(&[0, 0, 0, 2, 0, 0, 0, 7, 0, 0, 0, 8]),
+        Err(Error::LimitExceeded { len: 2, limit: 1 })
+    );
+    let decode_string = |bytes| String::deserialize(&mut Deserializer::with_limits(bytes, limits));
+    assert_eq!(
+        decode_string(&[0, 0, 0, 3, b'a', b'b', b'c', 0]),
+        Ok("abc".into())
+    );
+    assert_eq!(
+        decode_string(&[0, 0, 0, 4, b'a', b'b', b'c', b'd']),
+        Err(Error::LimitExceeded { len: 4, limit: 3 })
+    );
+}